@@ -113,6 +113,7 @@ fn bench_constraint_ir_serialization(c: &mut Criterion) {
                 rich_context: None,
                 feasibility_score: 0.0,
                 is_feasible: true,
+                schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
             },
             "medium" => ConstraintIR {
                 name: "medium".to_string(),
@@ -127,6 +128,7 @@ fn bench_constraint_ir_serialization(c: &mut Criterion) {
                 rich_context: None,
                 feasibility_score: 0.0,
                 is_feasible: true,
+                schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
             },
             "large" => ConstraintIR {
                 name: "complex".to_string(),
@@ -150,6 +152,7 @@ fn bench_constraint_ir_serialization(c: &mut Criterion) {
                 rich_context: None,
                 feasibility_score: 0.0,
                 is_feasible: true,
+                schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
             },
             _ => unreachable!(),
         };
@@ -189,6 +192,7 @@ fn bench_llguidance_conversion(c: &mut Criterion) {
                 rich_context: None,
                 feasibility_score: 0.0,
                 is_feasible: true,
+                schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
             }],
             "medium" => (0..5)
                 .map(|i| ConstraintIR {
@@ -201,6 +205,7 @@ fn bench_llguidance_conversion(c: &mut Criterion) {
                     rich_context: None,
                     feasibility_score: 0.0,
                     is_feasible: true,
+                    schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
                 })
                 .collect(),
             "large" => (0..10)
@@ -217,6 +222,7 @@ fn bench_llguidance_conversion(c: &mut Criterion) {
                     rich_context: None,
                     feasibility_score: 0.0,
                     is_feasible: true,
+                    schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
                 })
                 .collect(),
             _ => unreachable!(),
@@ -227,7 +233,7 @@ fn bench_llguidance_conversion(c: &mut Criterion) {
             &constraints,
             |b, constraints| {
                 b.iter(|| {
-                    let _ = orchestrator.compile_to_llguidance(black_box(constraints));
+                    let _ = orchestrator.compile_to_format(black_box(constraints));
                 });
             },
         );
@@ -36,10 +36,12 @@ fn create_test_constraint(id: usize) -> ConstraintIR {
             allowed_tokens: Some((0..100).collect()),
             forbidden_tokens: None,
         }),
+        type_inhabitation: None,
         priority: 1,
         rich_context: None,
         feasibility_score: 0.0,
         is_feasible: true,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }
 }
 
@@ -96,13 +98,14 @@ fn bench_vector_marshaling(c: &mut Criterion) {
 fn bench_string_copying(c: &mut Criterion) {
     let mut group = c.benchmark_group("string_copying");
 
+    let long = "x".repeat(1000);
     let test_strings = vec![
         ("short", "test"),
         (
             "medium",
             "This is a medium length string for testing FFI overhead",
         ),
-        ("long", &"x".repeat(1000)),
+        ("long", long.as_str()),
     ];
 
     for (name, test_str) in test_strings.iter() {
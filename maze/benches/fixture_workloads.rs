@@ -0,0 +1,256 @@
+//! Fixture Workload Benchmarks
+//! Sizes constraint sets from the Rust fixtures under test/fixtures and
+//! measures cache-key hashing, schema compilation and merging, and LRU
+//! hit/miss cost at realistic volumes
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use maze::ffi::{ConstraintIR, JsonSchema, RegexPattern};
+use maze::{MazeConfig, MazeOrchestrator, MockInferenceClient};
+use regex::Regex;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hasher};
+use std::path::Path;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Rust fixtures by size tier, smallest first
+const FIXTURES: [(&str, &str); 4] = [
+    ("small", "entity_service_100.rs"),
+    ("medium", "entity_service_500.rs"),
+    ("large", "entity_service_1000.rs"),
+    ("xlarge", "entity_service_5000.rs"),
+];
+
+fn read_fixture(tier: &str, file: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../test/fixtures/rust")
+        .join(tier)
+        .join(file);
+    std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Failed to read fixture {}: {}", path.display(), e))
+}
+
+/// JSON schema type for a Rust field type, and whether it is optional
+fn schema_type(rust_type: &str) -> (serde_json::Value, bool) {
+    let rust_type = rust_type.trim();
+    if let Some(inner) = rust_type
+        .strip_prefix("Option<")
+        .and_then(|t| t.strip_suffix('>'))
+    {
+        return (schema_type(inner).0, true);
+    }
+    let json_type = match rust_type {
+        "u8" | "u16" | "u32" | "u64" | "usize" | "i32" | "i64" => "integer",
+        "f32" | "f64" => "number",
+        "bool" => "boolean",
+        "String" | "&str" => "string",
+        _ => "object",
+    };
+    (serde_json::json!({ "type": json_type }), false)
+}
+
+/// A constraint whose schema has one property per `(name, type)` field
+fn schema_constraint<'a>(
+    name: &str,
+    fields: impl Iterator<Item = (&'a str, &'a str)>,
+    priority: u32,
+) -> ConstraintIR {
+    let mut properties = HashMap::new();
+    let mut required = Vec::new();
+    for (field, rust_type) in fields {
+        let (schema, optional) = schema_type(rust_type);
+        if !optional {
+            required.push(field.to_string());
+        }
+        properties.insert(field.to_string(), schema);
+    }
+    ConstraintIR {
+        name: name.to_string(),
+        json_schema: Some(JsonSchema {
+            schema_type: "object".to_string(),
+            properties,
+            required,
+            additional_properties: false,
+        }),
+        grammar: None,
+        regex_patterns: vec![],
+        token_masks: None,
+        type_inhabitation: None,
+        priority,
+        rich_context: None,
+        feasibility_score: 0.0,
+        is_feasible: true,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+    }
+}
+
+/// One constraint per struct (its fields) and per function (its
+/// parameters, and its name as a pattern)
+///
+/// The functions share parameter names, so merging the set combines many
+/// overlapping properties, as constraints extracted from one file do.
+fn fixture_constraints(source: &str) -> Vec<ConstraintIR> {
+    let structs = Regex::new(r"(?s)pub struct (\w+) \{(.*?)\n\}").unwrap();
+    let fields = Regex::new(r"(?:pub )?(\w+): ([^,\n]+),").unwrap();
+    let functions = Regex::new(r"pub (?:async )?fn (\w+)\(([^)]*)\)").unwrap();
+
+    let mut constraints: Vec<_> = structs
+        .captures_iter(source)
+        .map(|s| {
+            let body = s.get(2).unwrap().as_str();
+            schema_constraint(
+                &s[1],
+                fields
+                    .captures_iter(body)
+                    .map(|f| (f.get(1).unwrap().as_str(), f.get(2).unwrap().as_str())),
+                2,
+            )
+        })
+        .collect();
+
+    for f in functions.captures_iter(source) {
+        let params = f
+            .get(2)
+            .unwrap()
+            .as_str()
+            .split(',')
+            .filter_map(|param| param.split_once(':'))
+            .map(|(name, ty)| (name.trim(), ty.trim()));
+        let mut constraint = schema_constraint(&f[1], params, 1);
+        constraint.regex_patterns.push(RegexPattern {
+            pattern: format!("^{}$", &f[1]),
+            flags: String::new(),
+        });
+        constraints.push(constraint);
+    }
+    constraints
+}
+
+/// Each fixture's constraint set, labelled with the fixture tier
+fn workloads() -> Vec<(&'static str, Vec<ConstraintIR>)> {
+    FIXTURES
+        .iter()
+        .map(|&(tier, file)| (tier, fixture_constraints(&read_fixture(tier, file))))
+        .collect()
+}
+
+fn orchestrator(cache_size_limit: usize) -> MazeOrchestrator {
+    MazeOrchestrator::with_client(
+        MockInferenceClient::new("bench-model"),
+        MazeConfig {
+            cache_size_limit,
+            ..MazeConfig::default()
+        },
+    )
+}
+
+fn bench_cache_key_hashing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fixture_cache_key");
+    let orchestrator = orchestrator(0);
+
+    for (tier, constraints) in workloads() {
+        // The canonical form the cache key is computed from
+        let canonical = serde_json::to_value(&constraints).unwrap().to_string();
+        group.throughput(Throughput::Bytes(canonical.len() as u64));
+
+        // Hash function alone, on the same bytes
+        group.bench_with_input(BenchmarkId::new("xxh3", tier), &canonical, |b, bytes| {
+            b.iter(|| {
+                let mut hasher = Xxh3::new();
+                hasher.write(black_box(bytes.as_bytes()));
+                hasher.finish()
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("default_hasher", tier),
+            &canonical,
+            |b, bytes| {
+                b.iter(|| {
+                    let mut hasher = DefaultHasher::new();
+                    hasher.write(black_box(bytes.as_bytes()));
+                    hasher.finish()
+                })
+            },
+        );
+
+        // Serialization and hashing, as a cache lookup pays it
+        group.bench_with_input(
+            BenchmarkId::new("generate_cache_key", tier),
+            &constraints,
+            |b, constraints| {
+                b.iter(|| {
+                    orchestrator
+                        .generate_cache_key(black_box(constraints))
+                        .unwrap()
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_schema_compilation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fixture_compile");
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    // Caching disabled, so every call compiles and merges the whole set
+    let orchestrator = orchestrator(0);
+
+    for (tier, constraints) in workloads() {
+        group.throughput(Throughput::Elements(constraints.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new(tier, constraints.len()),
+            &constraints,
+            |b, constraints| {
+                b.iter(|| {
+                    rt.block_on(orchestrator.compile_constraints(black_box(constraints)))
+                        .unwrap()
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_lru_hit_miss(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fixture_lru");
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    for (tier, constraints) in workloads() {
+        // Hit: the set is already compiled
+        let cached = orchestrator(16);
+        rt.block_on(cached.compile_constraints(&constraints))
+            .unwrap();
+        group.bench_with_input(BenchmarkId::new("hit", tier), &constraints, |b, set| {
+            b.iter(|| {
+                rt.block_on(cached.compile_constraints(black_box(set)))
+                    .unwrap()
+            })
+        });
+
+        // Miss: two sets alternate through a one-entry cache, so every call
+        // compiles and evicts the other set
+        let mut other = constraints.clone();
+        other[0].priority += 1;
+        let sets = [constraints, other];
+        let churning = orchestrator(1);
+        let mut next = 0;
+        group.bench_function(BenchmarkId::new("miss_evict", tier), |b| {
+            b.iter(|| {
+                next ^= 1;
+                rt.block_on(churning.compile_constraints(black_box(&sets[next])))
+                    .unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_cache_key_hashing,
+    bench_schema_compilation,
+    bench_lru_hit_miss
+);
+criterion_main!(benches);
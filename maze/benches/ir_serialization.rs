@@ -0,0 +1,138 @@
+//! ConstraintIR Serialization Benchmarks
+//! Compares IR wire formats and the cache-key hashing path
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use maze::ffi::{ConstraintIR, JsonSchema, RegexPattern, TokenMaskRules};
+use maze::ir_codec::{self, IrFormat};
+use maze::{MazeOrchestrator, MockInferenceClient};
+use std::collections::HashMap;
+use std::hash::Hasher;
+use xxhash_rust::xxh3::Xxh3;
+
+fn create_constraint(id: usize) -> ConstraintIR {
+    ConstraintIR {
+        name: format!("constraint_{}", id),
+        json_schema: Some(JsonSchema {
+            schema_type: "object".to_string(),
+            properties: (0..8)
+                .map(|field| {
+                    (
+                        format!("field_{}", field),
+                        serde_json::json!({"type": "string", "maxLength": 64}),
+                    )
+                })
+                .collect::<HashMap<_, _>>(),
+            required: vec!["field_0".to_string()],
+            additional_properties: false,
+        }),
+        grammar: None,
+        regex_patterns: vec![RegexPattern {
+            pattern: r"^[a-z_][a-z0-9_]*$".to_string(),
+            flags: String::new(),
+        }],
+        token_masks: Some(TokenMaskRules {
+            allowed_tokens: None,
+            forbidden_tokens: Some((0..32).collect()),
+        }),
+        type_inhabitation: None,
+        priority: 1,
+        rich_context: Some(serde_json::json!({"source": "bench", "id": id})),
+        feasibility_score: 0.0,
+        is_feasible: true,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+    }
+}
+
+const SIZES: [usize; 3] = [10, 100, 1000];
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ir_encode");
+
+    for size in SIZES {
+        let constraints: Vec<_> = (0..size).map(create_constraint).collect();
+        for format in [IrFormat::Json, IrFormat::MessagePack] {
+            group.bench_with_input(
+                BenchmarkId::new(format.as_str(), size),
+                &constraints,
+                |b, constraints| b.iter(|| format.encode(black_box(constraints)).unwrap()),
+            );
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ir_decode");
+
+    for size in SIZES {
+        let constraints: Vec<_> = (0..size).map(create_constraint).collect();
+        for format in [IrFormat::Json, IrFormat::MessagePack] {
+            let bytes = format.encode(&constraints).unwrap();
+            group.bench_with_input(
+                BenchmarkId::new(format.as_str(), size),
+                &bytes,
+                |b, bytes| b.iter(|| format.decode(black_box(bytes)).unwrap()),
+            );
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_cache_key(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ir_cache_key");
+    let orchestrator = MazeOrchestrator::with_client(
+        MockInferenceClient::new("bench-model"),
+        maze::MazeConfig::default(),
+    );
+
+    for size in SIZES {
+        let constraints: Vec<_> = (0..size).map(create_constraint).collect();
+
+        // The previous path: canonical JSON built as a string, then hashed
+        group.bench_with_input(
+            BenchmarkId::new("json_string", size),
+            &constraints,
+            |b, constraints| {
+                b.iter(|| {
+                    let json = serde_json::to_value(black_box(constraints))
+                        .unwrap()
+                        .to_string();
+                    let mut hasher = Xxh3::new();
+                    hasher.write(json.as_bytes());
+                    hasher.finish()
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("canonical_stream", size),
+            &constraints,
+            |b, constraints| {
+                b.iter(|| {
+                    let mut hasher = Xxh3::new();
+                    ir_codec::canonical_hash(black_box(constraints), &mut hasher).unwrap();
+                    hasher.finish()
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("generate_cache_key", size),
+            &constraints,
+            |b, constraints| {
+                b.iter(|| {
+                    orchestrator
+                        .generate_cache_key(black_box(constraints))
+                        .unwrap()
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode, bench_decode, bench_cache_key);
+criterion_main!(benches);
@@ -22,6 +22,7 @@ fn create_test_constraint(name: &str) -> ConstraintIR {
         rich_context: None,
         feasibility_score: 0.0,
         is_feasible: true,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }
 }
 
@@ -150,7 +151,7 @@ fn bench_llguidance_schema_generation(c: &mut Criterion) {
             &constraints,
             |b, constraints| {
                 b.iter(|| {
-                    let _ = orchestrator.compile_to_llguidance(black_box(constraints));
+                    let _ = orchestrator.compile_to_format(black_box(constraints));
                 });
             },
         );
@@ -136,6 +136,7 @@ async fn main() -> anyhow::Result<()> {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }];
 
     let routing = ensemble
@@ -157,6 +158,11 @@ async fn main() -> anyhow::Result<()> {
         max_tokens: 256,
         temperature: 0.7,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     println!("Would generate with request:");
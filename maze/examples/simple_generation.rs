@@ -58,6 +58,9 @@ async fn main() -> Result<()> {
             project_root: Some("/Users/example/project".to_string()),
             metadata: HashMap::new(),
         }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     println!("Generation request:");
@@ -141,6 +144,7 @@ fn create_example_constraints() -> Vec<ConstraintIR> {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
         // Constraint 2: Security - forbid dangerous operations
         ConstraintIR {
@@ -161,6 +165,7 @@ fn create_example_constraints() -> Vec<ConstraintIR> {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
         // Constraint 3: Code style - require documentation
         ConstraintIR {
@@ -177,6 +182,7 @@ fn create_example_constraints() -> Vec<ConstraintIR> {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
         // Constraint 4: Async handling
         ConstraintIR {
@@ -193,6 +199,7 @@ fn create_example_constraints() -> Vec<ConstraintIR> {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
     ]
 }
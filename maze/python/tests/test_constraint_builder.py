@@ -0,0 +1,95 @@
+"""
+Tests for the Python ConstraintBuilder
+
+Constraints returned by ConstraintBuilder.build() can still be edited from
+Python; the edits must reach the compiled constraints.
+"""
+
+import pytest
+
+
+def _ananke():
+    from ananke import Ananke
+
+    return Ananke(
+        modal_endpoint="https://test.modal.run",
+        modal_api_key="test_key",
+        enable_cache=False,
+    )
+
+
+def test_builder_fills_fields():
+    """build() exposes what it validated through the plain fields"""
+    try:
+        from ananke import ConstraintBuilder
+
+        constraint = (
+            ConstraintBuilder("ids")
+            .with_regex("old_[a-z]+", "i")
+            .with_priority(7)
+            .build()
+        )
+
+        assert constraint.name == "ids"
+        assert constraint.regex_patterns == ["old_[a-z]+"]
+        assert constraint.json_schema is None
+    except ImportError:
+        pytest.skip("Ananke module not built yet")
+
+
+def test_builder_rejects_invalid_regex():
+    """Malformed input raises ValueError immediately"""
+    try:
+        from ananke import ConstraintBuilder
+
+        with pytest.raises(ValueError):
+            ConstraintBuilder("bad").with_regex("(unclosed")
+    except ImportError:
+        pytest.skip("Ananke module not built yet")
+
+
+@pytest.mark.asyncio
+async def test_edited_regex_reaches_compiled_constraint():
+    """Patterns set after build() replace the built ones"""
+    try:
+        from ananke import ConstraintBuilder
+
+        constraint = ConstraintBuilder("ids").with_regex("old_[a-z]+", "i").build()
+        ananke = _ananke()
+
+        before = await ananke.compile_constraints([constraint])
+        assert "old_[a-z]+" in before["schema"]
+        # Flags, which the fields cannot express, come from the builder
+        assert '"flags":"i"' in before["schema"]
+
+        constraint.regex_patterns = ["new_[0-9]+"]
+        after = await ananke.compile_constraints([constraint])
+        assert "new_[0-9]+" in after["schema"]
+        assert "old_[a-z]+" not in after["schema"]
+        assert after["hash"] != before["hash"]
+    except ImportError:
+        pytest.skip("Ananke module not built yet")
+
+
+@pytest.mark.asyncio
+async def test_cleared_schema_is_not_compiled():
+    """Clearing a field after build() removes it from the constraint"""
+    try:
+        from ananke import ConstraintBuilder
+
+        constraint = (
+            ConstraintBuilder("shape")
+            .with_json_schema({"type": "object", "properties": {"built_prop": {"type": "string"}}})
+            .with_regex("x+")
+            .build()
+        )
+        ananke = _ananke()
+
+        before = await ananke.compile_constraints([constraint])
+        assert "built_prop" in before["schema"]
+
+        constraint.json_schema = None
+        after = await ananke.compile_constraints([constraint])
+        assert "built_prop" not in after["schema"]
+    except ImportError:
+        pytest.skip("Ananke module not built yet")
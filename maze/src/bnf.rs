@@ -0,0 +1,767 @@
+//! BNF-style grammar text for backends without llguidance
+//!
+//! Translates grammar, JSON schema, and regex constraints into a small rule
+//! IR ([`RuleSet`]) and renders it as llama.cpp GBNF or W3C-style EBNF. Both
+//! dialects share the `name ::= expr` rule syntax; they differ in how
+//! characters, repetition counts, and the start rule are written.
+
+use anyhow::{anyhow, bail, Result};
+use regex_syntax::hir::{Class, Hir, HirKind, Look};
+use std::fmt::Write as _;
+
+use crate::ffi::{Grammar, JsonSchema, RegexPattern};
+
+/// Name of the start rule in rendered grammars
+pub const ROOT_RULE: &str = "root";
+
+/// Output dialect for [`RuleSet::render`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    /// llama.cpp GBNF
+    Gbnf,
+    /// W3C-style EBNF (as in the XML specification)
+    Ebnf,
+}
+
+/// A grammar expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// Matches the empty string
+    Empty,
+    /// A literal string
+    Literal(String),
+    /// A character class of inclusive ranges
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    /// Reference to another rule
+    Ref(String),
+    /// Expressions in sequence
+    Seq(Vec<Expr>),
+    /// Any one of the expressions
+    Alt(Vec<Expr>),
+    /// `min` to `max` (unbounded if `None`) repetitions
+    Repeat {
+        expr: Box<Expr>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+impl Expr {
+    fn lit(s: &str) -> Self {
+        Expr::Literal(s.to_string())
+    }
+
+    fn star(expr: Expr) -> Self {
+        Expr::Repeat {
+            expr: Box::new(expr),
+            min: 0,
+            max: None,
+        }
+    }
+
+    fn opt(expr: Expr) -> Self {
+        Expr::Repeat {
+            expr: Box::new(expr),
+            min: 0,
+            max: Some(1),
+        }
+    }
+
+    fn class(ranges: &[(char, char)]) -> Self {
+        Expr::Class {
+            ranges: ranges.to_vec(),
+            negated: false,
+        }
+    }
+}
+
+/// An ordered set of named grammar rules
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleSet {
+    rules: Vec<(String, Expr)>,
+}
+
+impl RuleSet {
+    /// Create an empty rule set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a rule; a rule with the same name is replaced
+    pub fn add(&mut self, name: impl Into<String>, expr: Expr) {
+        let name = name.into();
+        match self.rules.iter_mut().find(|(n, _)| *n == name) {
+            Some(rule) => rule.1 = expr,
+            None => self.rules.push((name, expr)),
+        }
+    }
+
+    /// Whether a rule with `name` exists
+    pub fn contains(&self, name: &str) -> bool {
+        self.rules.iter().any(|(n, _)| n == name)
+    }
+
+    /// Render all rules, one per line, in insertion order
+    pub fn render(&self, dialect: Dialect) -> String {
+        let mut out = String::new();
+        for (name, expr) in &self.rules {
+            let _ = writeln!(out, "{} ::= {}", name, render_expr(expr, dialect));
+        }
+        out
+    }
+}
+
+/// Make `name` a valid rule name in both dialects
+pub fn rule_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    if !out.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        out.insert_str(0, "r-");
+    }
+    out
+}
+
+/// Add the rules of a grammar constraint, prefixed with `prefix`
+///
+/// Rules sharing a left-hand side become alternatives. Right-hand side
+/// symbols that name a rule are references; all others are literals.
+/// Returns a reference to the start symbol.
+pub fn add_grammar(rules: &mut RuleSet, prefix: &str, grammar: &Grammar) -> Result<Expr> {
+    let name_of = |lhs: &str| format!("{}-{}", prefix, rule_name(lhs));
+    let is_rule = |symbol: &str| grammar.rules.iter().any(|r| r.lhs == symbol);
+
+    if !is_rule(&grammar.start_symbol) {
+        bail!("start symbol '{}' has no rule", grammar.start_symbol);
+    }
+
+    let mut seen: Vec<&str> = Vec::new();
+    for rule in &grammar.rules {
+        if seen.contains(&rule.lhs.as_str()) {
+            continue;
+        }
+        seen.push(&rule.lhs);
+
+        let alternatives: Vec<Expr> = grammar
+            .rules
+            .iter()
+            .filter(|r| r.lhs == rule.lhs)
+            .map(|r| {
+                let symbols: Vec<Expr> = r
+                    .rhs
+                    .iter()
+                    .map(|s| {
+                        if is_rule(s) {
+                            Expr::Ref(name_of(s))
+                        } else {
+                            Expr::lit(s)
+                        }
+                    })
+                    .collect();
+                match symbols.len() {
+                    0 => Expr::Empty,
+                    1 => symbols.into_iter().next().unwrap(),
+                    _ => Expr::Seq(symbols),
+                }
+            })
+            .collect();
+
+        let expr = if alternatives.len() == 1 {
+            alternatives.into_iter().next().unwrap()
+        } else {
+            Expr::Alt(alternatives)
+        };
+        rules.add(name_of(&rule.lhs), expr);
+    }
+
+    Ok(Expr::Ref(name_of(&grammar.start_symbol)))
+}
+
+/// Translate a regex constraint into a grammar expression
+///
+/// Anchors are dropped, since a grammar always matches the whole output.
+/// Word boundaries and other look-arounds cannot be expressed and fail.
+pub fn regex_expr(pattern: &RegexPattern) -> Result<Expr> {
+    let mut parser = regex_syntax::ParserBuilder::new();
+    for flag in pattern.flags.chars() {
+        match flag {
+            'i' => parser.case_insensitive(true),
+            'm' => parser.multi_line(true),
+            's' => parser.dot_matches_new_line(true),
+            'x' => parser.ignore_whitespace(true),
+            _ => &mut parser,
+        };
+    }
+    let hir = parser
+        .build()
+        .parse(&pattern.pattern)
+        .map_err(|e| anyhow!("invalid regex /{}/: {}", pattern.pattern, e))?;
+    hir_expr(&hir).map_err(|e| anyhow!("regex /{}/: {}", pattern.pattern, e))
+}
+
+fn hir_expr(hir: &Hir) -> Result<Expr> {
+    Ok(match hir.kind() {
+        HirKind::Empty => Expr::Empty,
+        HirKind::Literal(literal) => Expr::Literal(
+            String::from_utf8(literal.0.to_vec())
+                .map_err(|_| anyhow!("non-UTF-8 literal cannot be expressed"))?,
+        ),
+        HirKind::Class(Class::Unicode(class)) => Expr::Class {
+            ranges: class
+                .ranges()
+                .iter()
+                .map(|r| (r.start(), r.end()))
+                .collect(),
+            negated: false,
+        },
+        HirKind::Class(Class::Bytes(_)) => bail!("byte classes cannot be expressed"),
+        HirKind::Look(look) => match look {
+            Look::Start
+            | Look::End
+            | Look::StartLF
+            | Look::EndLF
+            | Look::StartCRLF
+            | Look::EndCRLF => Expr::Empty,
+            other => bail!("look-around {:?} cannot be expressed", other),
+        },
+        HirKind::Repetition(rep) => Expr::Repeat {
+            expr: Box::new(hir_expr(&rep.sub)?),
+            min: rep.min,
+            max: rep.max,
+        },
+        HirKind::Capture(capture) => hir_expr(&capture.sub)?,
+        HirKind::Concat(items) => {
+            // Dropped anchors leave empty items behind
+            let mut items = items
+                .iter()
+                .map(hir_expr)
+                .filter(|e| !matches!(e, Ok(Expr::Empty)))
+                .collect::<Result<Vec<_>>>()?;
+            match items.len() {
+                0 => Expr::Empty,
+                1 => items.pop().unwrap(),
+                _ => Expr::Seq(items),
+            }
+        }
+        HirKind::Alternation(items) => {
+            Expr::Alt(items.iter().map(hir_expr).collect::<Result<_>>()?)
+        }
+    })
+}
+
+/// Add the shared JSON value rules (`json-value`, `json-string`, ...)
+fn add_json_primitives(rules: &mut RuleSet) {
+    if rules.contains("json-value") {
+        return;
+    }
+    let ws = || Expr::Ref("json-ws".into());
+    let digits = || Expr::Repeat {
+        expr: Box::new(Expr::class(&[('0', '9')])),
+        min: 1,
+        max: None,
+    };
+    let comma = || Expr::Seq(vec![ws(), Expr::lit(","), ws()]);
+
+    rules.add(
+        "json-ws",
+        Expr::star(Expr::class(&[(' ', ' '), ('\t', '\t'), ('\n', '\n')])),
+    );
+    rules.add(
+        "json-string",
+        Expr::Seq(vec![
+            Expr::lit("\""),
+            Expr::star(Expr::Alt(vec![
+                Expr::Class {
+                    ranges: vec![('"', '"'), ('\\', '\\'), ('\0', '\x1f')],
+                    negated: true,
+                },
+                Expr::Seq(vec![
+                    Expr::lit("\\"),
+                    Expr::Class {
+                        ranges: "\"\\/bfnrt".chars().map(|c| (c, c)).collect(),
+                        negated: false,
+                    },
+                ]),
+            ])),
+            Expr::lit("\""),
+        ]),
+    );
+    rules.add(
+        "json-integer",
+        Expr::Seq(vec![Expr::opt(Expr::lit("-")), digits()]),
+    );
+    rules.add(
+        "json-number",
+        Expr::Seq(vec![
+            Expr::Ref("json-integer".into()),
+            Expr::opt(Expr::Seq(vec![Expr::lit("."), digits()])),
+            Expr::opt(Expr::Seq(vec![
+                Expr::class(&[('e', 'e'), ('E', 'E')]),
+                Expr::opt(Expr::class(&[('+', '+'), ('-', '-')])),
+                digits(),
+            ])),
+        ]),
+    );
+    rules.add(
+        "json-boolean",
+        Expr::Alt(vec![Expr::lit("true"), Expr::lit("false")]),
+    );
+    rules.add("json-null", Expr::lit("null"));
+    let member = Expr::Seq(vec![
+        Expr::Ref("json-string".into()),
+        ws(),
+        Expr::lit(":"),
+        ws(),
+        Expr::Ref("json-value".into()),
+    ]);
+    rules.add(
+        "json-object",
+        Expr::Seq(vec![
+            Expr::lit("{"),
+            ws(),
+            Expr::opt(Expr::Seq(vec![
+                member.clone(),
+                Expr::star(Expr::Seq(vec![comma(), member])),
+            ])),
+            ws(),
+            Expr::lit("}"),
+        ]),
+    );
+    rules.add(
+        "json-array",
+        Expr::Seq(vec![
+            Expr::lit("["),
+            ws(),
+            Expr::opt(Expr::Seq(vec![
+                Expr::Ref("json-value".into()),
+                Expr::star(Expr::Seq(vec![comma(), Expr::Ref("json-value".into())])),
+            ])),
+            ws(),
+            Expr::lit("]"),
+        ]),
+    );
+    rules.add(
+        "json-value",
+        Expr::Alt(
+            ["object", "array", "string", "number", "boolean", "null"]
+                .iter()
+                .map(|t| Expr::Ref(format!("json-{}", t)))
+                .collect(),
+        ),
+    );
+}
+
+/// Add rules for a JSON schema constraint under `prefix`
+///
+/// Required properties are emitted in `required` order, followed by the
+/// optional ones in name order, each of which may be omitted. Additional
+/// properties are never generated. Returns a reference to the object rule.
+pub fn add_json_schema(rules: &mut RuleSet, prefix: &str, schema: &JsonSchema) -> Result<Expr> {
+    add_json_primitives(rules);
+    let value = serde_json::json!({
+        "type": schema.schema_type,
+        "properties": schema.properties,
+        "required": schema.required,
+    });
+    json_value_expr(rules, prefix, &value)
+}
+
+fn json_value_expr(rules: &mut RuleSet, name: &str, schema: &serde_json::Value) -> Result<Expr> {
+    if let Some(value) = schema.get("const") {
+        return Ok(Expr::Literal(value.to_string()));
+    }
+    if let Some(values) = schema.get("enum").and_then(|v| v.as_array()) {
+        return Ok(Expr::Alt(
+            values
+                .iter()
+                .map(|v| Expr::Literal(v.to_string()))
+                .collect(),
+        ));
+    }
+
+    let primitive = |t: &str| Expr::Ref(format!("json-{}", t));
+    Ok(match schema.get("type").and_then(|t| t.as_str()) {
+        Some(t @ ("string" | "integer" | "number" | "boolean" | "null")) => primitive(t),
+        Some("array") => match schema.get("items") {
+            Some(items) => {
+                let item = json_value_expr(rules, &format!("{}-item", name), items)?;
+                let ws = || Expr::Ref("json-ws".into());
+                let rule = name.to_string();
+                rules.add(
+                    rule.clone(),
+                    Expr::Seq(vec![
+                        Expr::lit("["),
+                        ws(),
+                        Expr::opt(Expr::Seq(vec![
+                            item.clone(),
+                            Expr::star(Expr::Seq(vec![ws(), Expr::lit(","), ws(), item])),
+                        ])),
+                        ws(),
+                        Expr::lit("]"),
+                    ]),
+                );
+                Expr::Ref(rule)
+            }
+            None => primitive("array"),
+        },
+        Some("object") | None if schema.get("properties").is_some() => {
+            json_object_rule(rules, name, schema)?
+        }
+        Some("object") => primitive("object"),
+        _ => primitive("value"),
+    })
+}
+
+fn json_object_rule(rules: &mut RuleSet, name: &str, schema: &serde_json::Value) -> Result<Expr> {
+    let properties = schema
+        .get("properties")
+        .and_then(|p| p.as_object())
+        .ok_or_else(|| anyhow!("schema \"properties\" must be an object"))?;
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(|r| r.as_array())
+        .map(|r| r.iter().filter_map(|v| v.as_str()).collect())
+        .unwrap_or_default();
+
+    let mut optional: Vec<&str> = properties
+        .keys()
+        .map(String::as_str)
+        .filter(|k| !required.contains(k))
+        .collect();
+    optional.sort_unstable();
+
+    let ws = || Expr::Ref("json-ws".into());
+    let member = |rules: &mut RuleSet, key: &str| -> Result<Expr> {
+        let value_schema = properties
+            .get(key)
+            .ok_or_else(|| anyhow!("required property '{}' is not declared", key))?;
+        let value = json_value_expr(rules, &format!("{}-{}", name, rule_name(key)), value_schema)?;
+        Ok(Expr::Seq(vec![
+            Expr::Literal(serde_json::Value::from(key).to_string()),
+            ws(),
+            Expr::lit(":"),
+            ws(),
+            value,
+        ]))
+    };
+    let comma = || Expr::Seq(vec![ws(), Expr::lit(","), ws()]);
+
+    let mut body = Vec::new();
+    for (i, key) in required.iter().enumerate() {
+        if i > 0 {
+            body.push(comma());
+        }
+        body.push(member(rules, key)?);
+    }
+
+    let optional_members: Vec<Expr> = optional
+        .iter()
+        .map(|key| member(rules, key))
+        .collect::<Result<_>>()?;
+    if required.is_empty() {
+        // The first present optional property has no leading comma
+        let alternatives: Vec<Expr> = (0..optional_members.len())
+            .map(|first| {
+                let mut seq = vec![optional_members[first].clone()];
+                for later in &optional_members[first + 1..] {
+                    seq.push(Expr::opt(Expr::Seq(vec![comma(), later.clone()])));
+                }
+                Expr::Seq(seq)
+            })
+            .collect();
+        if !alternatives.is_empty() {
+            body.push(Expr::opt(Expr::Alt(alternatives)));
+        }
+    } else {
+        for member in optional_members {
+            body.push(Expr::opt(Expr::Seq(vec![comma(), member])));
+        }
+    }
+
+    let mut seq = vec![Expr::lit("{"), ws()];
+    seq.extend(body);
+    seq.extend([ws(), Expr::lit("}")]);
+    rules.add(name.to_string(), Expr::Seq(seq));
+    Ok(Expr::Ref(name.to_string()))
+}
+
+fn render_expr(expr: &Expr, dialect: Dialect) -> String {
+    match expr {
+        Expr::Empty => "\"\"".to_string(),
+        Expr::Literal(s) if s.is_empty() => "\"\"".to_string(),
+        Expr::Literal(s) => render_literal(s, dialect),
+        Expr::Class { ranges, negated } => render_class(ranges, *negated, dialect),
+        Expr::Ref(name) => name.clone(),
+        Expr::Seq(items) => match items.len() {
+            0 => "\"\"".to_string(),
+            1 => render_expr(&items[0], dialect),
+            _ => items
+                .iter()
+                .map(|e| render_grouped(e, dialect, false))
+                .collect::<Vec<_>>()
+                .join(" "),
+        },
+        Expr::Alt(items) => match items.len() {
+            0 => "\"\"".to_string(),
+            1 => render_expr(&items[0], dialect),
+            _ => items
+                .iter()
+                .map(|e| render_expr(e, dialect))
+                .collect::<Vec<_>>()
+                .join(" | "),
+        },
+        Expr::Repeat { expr, min, max } => render_repeat(expr, *min, *max, dialect),
+    }
+}
+
+/// Render `expr`, parenthesized if it would not bind as a single unit
+fn render_grouped(expr: &Expr, dialect: Dialect, for_suffix: bool) -> String {
+    let needs_group = match expr {
+        Expr::Alt(items) => items.len() > 1,
+        Expr::Seq(items) => for_suffix && items.len() > 1,
+        Expr::Literal(s) => for_suffix && dialect == Dialect::Ebnf && needs_ebnf_split(s),
+        Expr::Repeat { .. } => for_suffix,
+        _ => false,
+    };
+    let rendered = render_expr(expr, dialect);
+    if needs_group {
+        format!("({})", rendered)
+    } else {
+        rendered
+    }
+}
+
+fn render_repeat(expr: &Expr, min: u32, max: Option<u32>, dialect: Dialect) -> String {
+    let atom = render_grouped(expr, dialect, true);
+    match (min, max, dialect) {
+        (0, None, _) => format!("{}*", atom),
+        (1, None, _) => format!("{}+", atom),
+        (0, Some(1), _) => format!("{}?", atom),
+        (_, Some(0), _) => "\"\"".to_string(),
+        (1, Some(1), _) => atom,
+        (min, None, Dialect::Gbnf) => format!("{}{{{},}}", atom, min),
+        (min, Some(max), Dialect::Gbnf) if min == max => format!("{}{{{}}}", atom, min),
+        (min, Some(max), Dialect::Gbnf) => format!("{}{{{},{}}}", atom, min, max),
+        // EBNF has no counted repetition; expand it
+        (min, max, Dialect::Ebnf) => {
+            let mut parts = vec![atom.clone(); min as usize];
+            match max {
+                None => parts.push(format!("{}*", atom)),
+                Some(max) => {
+                    let optional = max.saturating_sub(min);
+                    if optional > 0 {
+                        let mut tail = format!("{}?", atom);
+                        for _ in 1..optional {
+                            tail = format!("({} {})?", atom, tail);
+                        }
+                        parts.push(tail);
+                    }
+                }
+            }
+            parts.join(" ")
+        }
+    }
+}
+
+fn needs_ebnf_split(s: &str) -> bool {
+    (s.contains('"') && s.contains('\'')) || s.chars().any(|c| c.is_control())
+}
+
+fn render_literal(s: &str, dialect: Dialect) -> String {
+    match dialect {
+        Dialect::Gbnf => {
+            let mut out = String::from("\"");
+            for c in s.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    '\t' => out.push_str("\\t"),
+                    c if c.is_control() => {
+                        let _ = write!(out, "\\x{:02X}", c as u32);
+                    }
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+            out
+        }
+        Dialect::Ebnf => {
+            // Quoted runs without control characters, joined with #xN references
+            let mut parts = Vec::new();
+            let mut run = String::new();
+            let flush = |run: &mut String, parts: &mut Vec<String>| {
+                if run.is_empty() {
+                    return;
+                }
+                let quote = if run.contains('"') { '\'' } else { '"' };
+                parts.push(format!("{}{}{}", quote, run, quote));
+                run.clear();
+            };
+            for c in s.chars() {
+                let quote_conflict =
+                    (c == '"' && run.contains('\'')) || (c == '\'' && run.contains('"'));
+                if c.is_control() || quote_conflict {
+                    flush(&mut run, &mut parts);
+                }
+                if c.is_control() {
+                    parts.push(format!("#x{:X}", c as u32));
+                } else {
+                    run.push(c);
+                }
+            }
+            flush(&mut run, &mut parts);
+            parts.join(" ")
+        }
+    }
+}
+
+fn render_class(ranges: &[(char, char)], negated: bool, dialect: Dialect) -> String {
+    let char_in_class = |c: char| -> String {
+        match dialect {
+            Dialect::Gbnf => match c {
+                '\\' | ']' | '-' | '^' | '[' => format!("\\{}", c),
+                '\n' => "\\n".to_string(),
+                '\r' => "\\r".to_string(),
+                '\t' => "\\t".to_string(),
+                c if c.is_control() => format!("\\x{:02X}", c as u32),
+                c if (c as u32) > 0xFFFF => format!("\\U{:08X}", c as u32),
+                c => c.to_string(),
+            },
+            Dialect::Ebnf => {
+                if c.is_control() || matches!(c, ']' | '-' | '^' | '[' | '#' | '\\') {
+                    format!("#x{:X}", c as u32)
+                } else {
+                    c.to_string()
+                }
+            }
+        }
+    };
+
+    let mut out = String::from("[");
+    if negated {
+        out.push('^');
+    }
+    for &(start, end) in ranges {
+        out.push_str(&char_in_class(start));
+        if end != start {
+            out.push('-');
+            out.push_str(&char_in_class(end));
+        }
+    }
+    out.push(']');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::GrammarRule;
+    use std::collections::HashMap;
+
+    fn regex(pattern: &str, flags: &str) -> Expr {
+        regex_expr(&RegexPattern {
+            pattern: pattern.to_string(),
+            flags: flags.to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_regex_renders_in_both_dialects() {
+        let expr = regex(r"^[a-z_][a-z0-9_]{0,2}$", "");
+        assert_eq!(render_expr(&expr, Dialect::Gbnf), "[_a-z] [0-9_a-z]{0,2}");
+        assert_eq!(
+            render_expr(&expr, Dialect::Ebnf),
+            "[_a-z] ([0-9_a-z] [0-9_a-z]?)?"
+        );
+
+        let alt = regex("(GET|POST) /", "");
+        assert_eq!(
+            render_expr(&alt, Dialect::Gbnf),
+            "(\"GET\" | \"POST\") \" /\""
+        );
+    }
+
+    #[test]
+    fn test_regex_rejects_word_boundaries() {
+        let err = regex_expr(&RegexPattern {
+            pattern: r"\bfoo\b".to_string(),
+            flags: String::new(),
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("cannot be expressed"));
+    }
+
+    #[test]
+    fn test_grammar_rules_are_prefixed_and_grouped() {
+        let grammar = Grammar {
+            rules: vec![
+                GrammarRule {
+                    lhs: "expr".into(),
+                    rhs: vec!["term".into(), "+".into(), "expr".into()],
+                },
+                GrammarRule {
+                    lhs: "expr".into(),
+                    rhs: vec!["term".into()],
+                },
+                GrammarRule {
+                    lhs: "term".into(),
+                    rhs: vec!["x".into()],
+                },
+            ],
+            start_symbol: "expr".into(),
+        };
+        let mut rules = RuleSet::new();
+        let start = add_grammar(&mut rules, "math", &grammar).unwrap();
+        rules.add(ROOT_RULE, start);
+
+        assert_eq!(
+            rules.render(Dialect::Gbnf),
+            "math-expr ::= math-term \"+\" math-expr | math-term\n\
+             math-term ::= \"x\"\n\
+             root ::= math-expr\n"
+        );
+    }
+
+    #[test]
+    fn test_json_schema_object_rule() {
+        let schema = JsonSchema {
+            schema_type: "object".into(),
+            properties: HashMap::from([
+                ("name".to_string(), serde_json::json!({"type": "string"})),
+                ("age".to_string(), serde_json::json!({"type": "integer"})),
+            ]),
+            required: vec!["name".into()],
+            additional_properties: false,
+        };
+        let mut rules = RuleSet::new();
+        add_json_schema(&mut rules, "user", &schema).unwrap();
+
+        let text = rules.render(Dialect::Gbnf);
+        assert!(text.contains(
+            "user ::= \"{\" json-ws \"\\\"name\\\"\" json-ws \":\" json-ws json-string \
+             (json-ws \",\" json-ws \"\\\"age\\\"\" json-ws \":\" json-ws json-integer)? \
+             json-ws \"}\""
+        ));
+        assert!(text.contains("json-value ::= json-object | json-array"));
+    }
+
+    #[test]
+    fn test_ebnf_literals_avoid_quote_conflicts_and_controls() {
+        assert_eq!(render_literal("say \"hi\"", Dialect::Ebnf), "'say \"hi\"'");
+        assert_eq!(render_literal("a\nb", Dialect::Ebnf), "\"a\" #xA \"b\"");
+        assert_eq!(render_literal("a\nb", Dialect::Gbnf), "\"a\\nb\"");
+    }
+}
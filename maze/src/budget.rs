@@ -0,0 +1,222 @@
+//! Token and cost budgets for refinement sessions
+//!
+//! Filling many holes can run up an unbounded bill. A [`TokenBudget`] caps a
+//! [`ProgressiveRefiner`](crate::ProgressiveRefiner) session by generated
+//! tokens, estimated cost, or both. Before each fill the refiner reserves
+//! the fill's `max_tokens` against the budget, so fills running in parallel
+//! can never overshoot it together; once the fill returns, the reservation
+//! is replaced by the tokens actually billed. Fills that error out bill
+//! nothing. When the next fill no longer fits, the session stops and the
+//! remaining holes are handled per [`BudgetExhaustedAction`].
+
+use serde::{Deserialize, Serialize};
+
+/// Spending limits for one refinement session
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenBudget {
+    /// Maximum generated tokens across all fills, including retries
+    #[serde(default)]
+    pub max_total_tokens: Option<usize>,
+
+    /// Maximum estimated cost across all fills
+    #[serde(default)]
+    pub max_cost: Option<f64>,
+
+    /// Cost per 1000 generated tokens, used when the serving model has no
+    /// `ModelEndpoint::cost_per_1k_tokens` of its own
+    #[serde(default)]
+    pub cost_per_1k_tokens: f64,
+
+    /// What happens to unfilled holes once the budget runs out
+    #[serde(default)]
+    pub on_exhausted: BudgetExhaustedAction,
+}
+
+impl TokenBudget {
+    /// Whether any limit is set
+    pub fn is_limited(&self) -> bool {
+        self.max_total_tokens.is_some() || self.max_cost.is_some()
+    }
+}
+
+/// Status given to holes left unfilled when a budget runs out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BudgetExhaustedAction {
+    /// Mark them `Skipped`
+    #[default]
+    Skip,
+
+    /// Mark them `NeedsHuman`, listing them for review
+    HumanReview,
+}
+
+/// Budget usage reported in
+/// [`RefinementMetadata::budget`](crate::progressive_refinement::RefinementMetadata::budget)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BudgetState {
+    /// Configured token limit
+    pub max_total_tokens: Option<usize>,
+
+    /// Configured cost limit
+    pub max_cost: Option<f64>,
+
+    /// Tokens billed by completed fills
+    pub tokens_used: usize,
+
+    /// Estimated cost of completed fills
+    pub cost_used: f64,
+
+    /// Whether the session stopped because the next fill did not fit
+    pub exhausted: bool,
+
+    /// Holes left unfilled because the budget ran out
+    pub unfilled_holes: Vec<u64>,
+}
+
+/// Budget held for one in-flight fill
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Reservation {
+    tokens: usize,
+    cost: f64,
+}
+
+/// Tracks spending against a [`TokenBudget`] during a session
+#[derive(Debug)]
+pub(crate) struct BudgetAccountant {
+    budget: TokenBudget,
+    state: BudgetState,
+    reserved_tokens: usize,
+    reserved_cost: f64,
+}
+
+impl BudgetAccountant {
+    pub(crate) fn new(budget: TokenBudget) -> Self {
+        let state = BudgetState {
+            max_total_tokens: budget.max_total_tokens,
+            max_cost: budget.max_cost,
+            ..Default::default()
+        };
+        Self {
+            budget,
+            state,
+            reserved_tokens: 0,
+            reserved_cost: 0.0,
+        }
+    }
+
+    /// Estimated cost of `tokens` at `cost_per_1k` (or the budget's default rate)
+    pub(crate) fn cost(&self, tokens: usize, cost_per_1k: Option<f64>) -> f64 {
+        tokens as f64 * cost_per_1k.unwrap_or(self.budget.cost_per_1k_tokens) / 1000.0
+    }
+
+    /// Hold up to `max_tokens` for a fill, or mark the budget exhausted if
+    /// that would exceed a limit
+    pub(crate) fn reserve(
+        &mut self,
+        max_tokens: usize,
+        cost_per_1k: Option<f64>,
+    ) -> Option<Reservation> {
+        if self.state.exhausted {
+            return None;
+        }
+        let reservation = Reservation {
+            tokens: max_tokens,
+            cost: self.cost(max_tokens, cost_per_1k),
+        };
+
+        let over_tokens = self.budget.max_total_tokens.is_some_and(|max| {
+            self.state.tokens_used + self.reserved_tokens + reservation.tokens > max
+        });
+        let over_cost = self
+            .budget
+            .max_cost
+            .is_some_and(|max| self.state.cost_used + self.reserved_cost + reservation.cost > max);
+        if over_tokens || over_cost {
+            tracing::info!(
+                tokens_used = self.state.tokens_used,
+                cost_used = self.state.cost_used,
+                "Refinement budget exhausted"
+            );
+            self.state.exhausted = true;
+            return None;
+        }
+
+        self.reserved_tokens += reservation.tokens;
+        self.reserved_cost += reservation.cost;
+        Some(reservation)
+    }
+
+    /// Release `reservation` and charge what the fill actually billed
+    pub(crate) fn settle(
+        &mut self,
+        reservation: Reservation,
+        billed_tokens: usize,
+        cost_per_1k: Option<f64>,
+    ) {
+        self.reserved_tokens -= reservation.tokens;
+        self.reserved_cost = (self.reserved_cost - reservation.cost).max(0.0);
+        self.state.tokens_used += billed_tokens;
+        self.state.cost_used += self.cost(billed_tokens, cost_per_1k);
+    }
+
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.state.exhausted
+    }
+
+    pub(crate) fn on_exhausted(&self) -> BudgetExhaustedAction {
+        self.budget.on_exhausted
+    }
+
+    /// Finish the session, recording holes left unfilled by exhaustion
+    pub(crate) fn into_state(mut self, mut unfilled_holes: Vec<u64>) -> BudgetState {
+        unfilled_holes.sort_unstable();
+        self.state.unfilled_holes = unfilled_holes;
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reservations_cap_parallel_spend() {
+        let mut accountant = BudgetAccountant::new(TokenBudget {
+            max_total_tokens: Some(300),
+            ..Default::default()
+        });
+
+        // Two 128-token fills fit; a third in the same batch would not
+        let a = accountant.reserve(128, None).unwrap();
+        let b = accountant.reserve(128, None).unwrap();
+        assert!(accountant.reserve(128, None).is_none());
+        assert!(accountant.is_exhausted());
+
+        accountant.settle(a, 40, None);
+        accountant.settle(b, 0, None);
+        let state = accountant.into_state(vec![3, 1]);
+        assert_eq!(state.tokens_used, 40);
+        assert_eq!(state.unfilled_holes, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_cost_limit_uses_model_rate() {
+        let mut accountant = BudgetAccountant::new(TokenBudget {
+            max_cost: Some(1.0),
+            cost_per_1k_tokens: 0.5,
+            ..Default::default()
+        });
+
+        // 1000 tokens at the default rate fit; at 2.0/1k they do not
+        assert!(accountant.reserve(1000, Some(2.0)).is_none());
+
+        let mut accountant = BudgetAccountant::new(TokenBudget {
+            max_cost: Some(1.0),
+            cost_per_1k_tokens: 0.5,
+            ..Default::default()
+        });
+        let r = accountant.reserve(1000, None).unwrap();
+        accountant.settle(r, 1000, None);
+        assert!((accountant.into_state(vec![]).cost_used - 0.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,115 @@
+//! Storage for compiled constraints
+//!
+//! Each [`MazeOrchestrator`](crate::MazeOrchestrator) compiles constraints
+//! into a [`CacheBackend`], by default an [`InMemoryCache`] of its own. In a
+//! multi-worker server, every worker would then compile the same constraints
+//! once. Handing the workers one backend through
+//! [`with_constraint_cache`](crate::MazeOrchestrator::with_constraint_cache)
+//! shares the compilations instead; backends over Redis or shared memory
+//! implement the same trait.
+
+use async_trait::async_trait;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use crate::{CacheStats, CompiledConstraint};
+
+/// Store of compiled constraints by cache key
+///
+/// Keys are [`CompiledConstraint::hash`]es. Entries may be evicted at any
+/// time; a miss just means compiling again.
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    /// The entry for `key`, counting as a use for eviction
+    async fn get(&self, key: &str) -> Option<CompiledConstraint>;
+
+    /// The entry for `key`, without counting as a use
+    async fn peek(&self, key: &str) -> Option<CompiledConstraint> {
+        self.get(key).await
+    }
+
+    /// Store `value` under `key`
+    async fn put(&self, key: String, value: CompiledConstraint);
+
+    /// Drop the entry for `key`
+    async fn remove(&self, key: &str);
+
+    /// Drop every entry
+    async fn clear(&self);
+
+    /// Number of entries and capacity
+    async fn stats(&self) -> CacheStats;
+
+    /// Change the capacity, evicting entries that no longer fit
+    ///
+    /// Backends whose capacity is managed elsewhere ignore this.
+    async fn resize(&self, capacity: usize) {
+        tracing::debug!(
+            capacity,
+            "Constraint cache backend does not support resizing"
+        );
+    }
+}
+
+/// In-process LRU cache, the default backend
+///
+/// A capacity of zero disables caching: nothing is stored.
+#[derive(Debug)]
+pub struct InMemoryCache {
+    lru: Mutex<Option<LruCache<String, CompiledConstraint>>>,
+}
+
+impl InMemoryCache {
+    /// A cache holding up to `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lru: Mutex::new(NonZeroUsize::new(capacity).map(LruCache::new)),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheBackend for InMemoryCache {
+    async fn get(&self, key: &str) -> Option<CompiledConstraint> {
+        self.lru.lock().unwrap().as_mut()?.get(key).cloned()
+    }
+
+    async fn peek(&self, key: &str) -> Option<CompiledConstraint> {
+        self.lru.lock().unwrap().as_ref()?.peek(key).cloned()
+    }
+
+    async fn put(&self, key: String, value: CompiledConstraint) {
+        if let Some(lru) = self.lru.lock().unwrap().as_mut() {
+            lru.put(key, value);
+        }
+    }
+
+    async fn remove(&self, key: &str) {
+        if let Some(lru) = self.lru.lock().unwrap().as_mut() {
+            lru.pop(key);
+        }
+    }
+
+    async fn clear(&self) {
+        if let Some(lru) = self.lru.lock().unwrap().as_mut() {
+            lru.clear();
+        }
+    }
+
+    async fn stats(&self) -> CacheStats {
+        let lru = self.lru.lock().unwrap();
+        CacheStats {
+            size: lru.as_ref().map_or(0, |c| c.len()),
+            limit: lru.as_ref().map_or(0, |c| c.cap().get()),
+        }
+    }
+
+    async fn resize(&self, capacity: usize) {
+        let mut lru = self.lru.lock().unwrap();
+        match (lru.as_mut(), NonZeroUsize::new(capacity)) {
+            (Some(cache), Some(capacity)) => cache.resize(capacity),
+            (_, capacity) => *lru = capacity.map(LruCache::new),
+        }
+    }
+}
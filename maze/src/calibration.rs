@@ -0,0 +1,362 @@
+//! Confidence calibration
+//!
+//! A model's raw confidence is rarely calibrated: fills scored 0.8 may pass
+//! validation far more or less than 80% of the time, and each model errs
+//! differently, so one `min_confidence` threshold does not suit them all. A
+//! [`CalibrationCurve`] maps raw confidence to the observed pass rate, and
+//! [`ConfidenceCalibration`] picks a curve per model. Curves can be fitted
+//! from labelled samples, such as those [`samples_from_attempts`] collects
+//! from past fill attempts.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::progressive_refinement::FillAttempt;
+
+/// Mapping from raw confidence to calibrated confidence
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CalibrationCurve {
+    /// Linear interpolation between `(raw, calibrated)` points sorted by
+    /// raw confidence; flat beyond the first and last point
+    PiecewiseLinear { points: Vec<(f32, f32)> },
+
+    /// Platt scaling: `1 / (1 + exp(a * raw + b))`
+    Platt { a: f32, b: f32 },
+}
+
+/// A raw confidence and whether the fill it scored passed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CalibrationSample {
+    /// Raw confidence reported for the fill
+    pub confidence: f32,
+
+    /// Whether the fill passed validation
+    pub passed: bool,
+}
+
+impl CalibrationCurve {
+    /// A piecewise-linear curve through `points`, in any order
+    ///
+    /// Fails on an empty or non-finite point list.
+    pub fn piecewise(mut points: Vec<(f32, f32)>) -> Result<Self> {
+        if points.is_empty() {
+            bail!("A calibration curve needs at least one point");
+        }
+        if points.iter().any(|(x, y)| !x.is_finite() || !y.is_finite()) {
+            bail!("Calibration points must be finite");
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self::PiecewiseLinear { points })
+    }
+
+    /// Calibrated confidence for `raw`, clamped to 0.0-1.0
+    ///
+    /// Non-finite input is returned unchanged.
+    pub fn apply(&self, raw: f32) -> f32 {
+        if !raw.is_finite() {
+            return raw;
+        }
+        let calibrated = match self {
+            Self::PiecewiseLinear { points } => interpolate(points, raw),
+            Self::Platt { a, b } => 1.0 / (1.0 + (a * raw + b).exp()),
+        };
+        calibrated.clamp(0.0, 1.0)
+    }
+
+    /// Fit Platt scaling parameters to `samples` by maximum likelihood
+    ///
+    /// Uses Newton's method with backtracking and Platt's smoothed targets,
+    /// which keep the fit finite when the samples are perfectly separable.
+    pub fn fit_platt(samples: &[CalibrationSample]) -> Result<Self> {
+        if samples.is_empty() {
+            bail!("Cannot fit a calibration curve without samples");
+        }
+        let positives = samples.iter().filter(|s| s.passed).count() as f64;
+        let negatives = samples.len() as f64 - positives;
+        let hi_target = (positives + 1.0) / (positives + 2.0);
+        let lo_target = 1.0 / (negatives + 2.0);
+        let data: Vec<(f64, f64)> = samples
+            .iter()
+            .map(|s| {
+                let target = if s.passed { hi_target } else { lo_target };
+                (s.confidence as f64, target)
+            })
+            .collect();
+
+        // Negative log-likelihood, arranged to avoid overflow in exp
+        let objective = |a: f64, b: f64| -> f64 {
+            data.iter()
+                .map(|&(f, t)| {
+                    let z = f * a + b;
+                    if z >= 0.0 {
+                        t * z + (-z).exp().ln_1p()
+                    } else {
+                        (t - 1.0) * z + z.exp().ln_1p()
+                    }
+                })
+                .sum()
+        };
+
+        let mut a = 0.0;
+        let mut b = ((negatives + 1.0) / (positives + 1.0)).ln();
+        let mut value = objective(a, b);
+        for _ in 0..100 {
+            // A small ridge keeps the Hessian invertible
+            let (mut h11, mut h22, mut h21) = (1e-12, 1e-12, 0.0);
+            let (mut g1, mut g2) = (0.0, 0.0);
+            for &(f, t) in &data {
+                let z = f * a + b;
+                let (p, q) = if z >= 0.0 {
+                    let e = (-z).exp();
+                    (e / (1.0 + e), 1.0 / (1.0 + e))
+                } else {
+                    let e = z.exp();
+                    (1.0 / (1.0 + e), e / (1.0 + e))
+                };
+                let d2 = p * q;
+                h11 += f * f * d2;
+                h22 += d2;
+                h21 += f * d2;
+                let d1 = t - p;
+                g1 += f * d1;
+                g2 += d1;
+            }
+            if g1.abs() < 1e-5 && g2.abs() < 1e-5 {
+                break;
+            }
+
+            let det = h11 * h22 - h21 * h21;
+            let da = -(h22 * g1 - h21 * g2) / det;
+            let db = -(-h21 * g1 + h11 * g2) / det;
+            let slope = g1 * da + g2 * db;
+            let mut step = 1.0;
+            loop {
+                let (next_a, next_b) = (a + step * da, b + step * db);
+                let next = objective(next_a, next_b);
+                if next < value + 1e-4 * step * slope {
+                    a = next_a;
+                    b = next_b;
+                    value = next;
+                    break;
+                }
+                step /= 2.0;
+                if step < 1e-10 {
+                    // No further progress along the Newton direction
+                    return Ok(Self::Platt {
+                        a: a as f32,
+                        b: b as f32,
+                    });
+                }
+            }
+        }
+
+        Ok(Self::Platt {
+            a: a as f32,
+            b: b as f32,
+        })
+    }
+
+    /// Fit a monotone piecewise-linear curve to `samples` by isotonic
+    /// regression
+    ///
+    /// Samples are pooled into blocks whose pass rates never decrease with
+    /// confidence; each block contributes one point at its mean raw
+    /// confidence and pass rate.
+    pub fn fit_isotonic(samples: &[CalibrationSample]) -> Result<Self> {
+        if samples.is_empty() {
+            bail!("Cannot fit a calibration curve without samples");
+        }
+        let mut sorted: Vec<CalibrationSample> = samples
+            .iter()
+            .copied()
+            .filter(|s| s.confidence.is_finite())
+            .collect();
+        if sorted.is_empty() {
+            bail!("Cannot fit a calibration curve without finite confidences");
+        }
+        sorted.sort_by(|a, b| a.confidence.total_cmp(&b.confidence));
+
+        // Samples at the same confidence start in one block
+        let mut groups: Vec<(f32, f64, f64)> = Vec::new();
+        for sample in sorted {
+            let passed = if sample.passed { 1.0 } else { 0.0 };
+            match groups.last_mut() {
+                Some(group) if group.0 == sample.confidence => {
+                    group.1 += passed;
+                    group.2 += 1.0;
+                }
+                _ => groups.push((sample.confidence, passed, 1.0)),
+            }
+        }
+
+        // Pool adjacent violators: (sum of raw, sum of passes, count)
+        let mut blocks: Vec<(f64, f64, f64)> = Vec::new();
+        for (confidence, passes, count) in groups {
+            let mut block = (confidence as f64 * count, passes, count);
+            while let Some(&last) = blocks.last() {
+                if last.1 / last.2 < block.1 / block.2 {
+                    break;
+                }
+                blocks.pop();
+                block = (last.0 + block.0, last.1 + block.1, last.2 + block.2);
+            }
+            blocks.push(block);
+        }
+
+        let points = blocks
+            .into_iter()
+            .map(|(raw, passes, count)| ((raw / count) as f32, (passes / count) as f32))
+            .collect();
+        Self::piecewise(points)
+    }
+}
+
+fn interpolate(points: &[(f32, f32)], raw: f32) -> f32 {
+    let Some(&(first_x, first_y)) = points.first() else {
+        return raw;
+    };
+    if raw <= first_x {
+        return first_y;
+    }
+    for pair in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        if raw <= x1 {
+            if x1 == x0 {
+                return y1;
+            }
+            return y0 + (y1 - y0) * (raw - x0) / (x1 - x0);
+        }
+    }
+    points[points.len() - 1].1
+}
+
+/// Calibration curves per model
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConfidenceCalibration {
+    /// Curves keyed by model name
+    pub models: HashMap<String, CalibrationCurve>,
+
+    /// Curve for models without their own; `None` leaves them raw
+    pub default: Option<CalibrationCurve>,
+}
+
+impl ConfidenceCalibration {
+    /// Curve applied to `model`'s confidence, if any
+    pub fn curve(&self, model: &str) -> Option<&CalibrationCurve> {
+        self.models.get(model).or(self.default.as_ref())
+    }
+
+    /// `raw` confidence from `model`, calibrated
+    pub fn calibrate(&self, model: &str, raw: f32) -> f32 {
+        match self.curve(model) {
+            Some(curve) => curve.apply(raw),
+            None => raw,
+        }
+    }
+
+    /// Calibrate `model` with `curve`
+    pub fn with_model(mut self, model: impl Into<String>, curve: CalibrationCurve) -> Self {
+        self.models.insert(model.into(), curve);
+        self
+    }
+}
+
+/// Labelled samples from `attempts` by `model`, or by every model if `None`
+///
+/// Uses each attempt's raw confidence and whether it passed validation;
+/// attempts without a scored confidence are left out.
+pub fn samples_from_attempts<'a>(
+    attempts: impl IntoIterator<Item = &'a FillAttempt>,
+    model: Option<&str>,
+) -> Vec<CalibrationSample> {
+    attempts
+        .into_iter()
+        .filter(|attempt| model.is_none_or(|model| attempt.model == model))
+        .filter_map(|attempt| {
+            attempt.raw_confidence.map(|confidence| CalibrationSample {
+                confidence,
+                passed: attempt.validation_passed,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(confidence: f32, passed: bool) -> CalibrationSample {
+        CalibrationSample { confidence, passed }
+    }
+
+    /// `count` samples at `confidence`, of which `rate` pass
+    fn bucket(confidence: f32, rate: f64, count: usize) -> Vec<CalibrationSample> {
+        let passes = (rate * count as f64).round() as usize;
+        (0..count).map(|i| sample(confidence, i < passes)).collect()
+    }
+
+    #[test]
+    fn test_piecewise_curve_interpolates_and_clamps() {
+        let curve = CalibrationCurve::piecewise(vec![(0.9, 0.6), (0.5, 0.2), (0.7, 0.5)]).unwrap();
+        assert_eq!(curve.apply(0.1), 0.2);
+        assert!((curve.apply(0.6) - 0.35).abs() < 1e-6);
+        assert!((curve.apply(0.8) - 0.55).abs() < 1e-6);
+        assert_eq!(curve.apply(1.0), 0.6);
+        assert!(CalibrationCurve::piecewise(vec![]).is_err());
+    }
+
+    #[test]
+    fn test_platt_fit_recovers_known_sigmoid() {
+        // Pass rate 1 / (1 + exp(-6x + 3)), so a = -6 and b = 3
+        let samples: Vec<_> = (0..=20)
+            .flat_map(|i| {
+                let x = i as f32 / 20.0;
+                let rate = 1.0 / (1.0 + (-6.0 * x as f64 + 3.0).exp());
+                bucket(x, rate, 400)
+            })
+            .collect();
+
+        let curve = CalibrationCurve::fit_platt(&samples).unwrap();
+        let CalibrationCurve::Platt { a, b } = curve else {
+            panic!("expected Platt parameters");
+        };
+        assert!((a + 6.0).abs() < 0.2, "a = {}", a);
+        assert!((b - 3.0).abs() < 0.1, "b = {}", b);
+        assert!((curve.apply(0.5) - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_isotonic_fit_corrects_overconfidence_and_pools_violators() {
+        let mut samples = bucket(0.2, 0.5, 10);
+        samples.extend(bucket(0.4, 0.3, 10));
+        samples.extend(bucket(0.9, 0.6, 10));
+
+        let curve = CalibrationCurve::fit_isotonic(&samples).unwrap();
+        let CalibrationCurve::PiecewiseLinear { ref points } = curve else {
+            panic!("expected a piecewise curve");
+        };
+        // 0.2 and 0.4 violate monotonicity and pool to a 40% pass rate
+        assert_eq!(points.len(), 2);
+        assert!((points[0].0 - 0.3).abs() < 1e-6 && (points[0].1 - 0.4).abs() < 1e-6);
+        assert!((curve.apply(0.9) - 0.6).abs() < 1e-6);
+        assert!((curve.apply(0.6) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_per_model_curve_falls_back_to_default() {
+        let calibration = ConfidenceCalibration {
+            default: Some(CalibrationCurve::Platt { a: 0.0, b: 0.0 }),
+            ..Default::default()
+        }
+        .with_model(
+            "overconfident",
+            CalibrationCurve::piecewise(vec![(0.0, 0.0), (1.0, 0.5)]).unwrap(),
+        );
+        assert_eq!(calibration.calibrate("overconfident", 0.8), 0.4);
+        assert_eq!(calibration.calibrate("other", 0.8), 0.5);
+        assert_eq!(ConfidenceCalibration::default().calibrate("any", 0.8), 0.8);
+    }
+}
@@ -0,0 +1,144 @@
+//! Ranking ensemble outputs
+//!
+//! When an [`EnsembleClient`](crate::EnsembleClient) has several answers to
+//! choose from ([`generate_ensemble`](crate::EnsembleClient::generate_ensemble)
+//! and [`generate_best_of_n`](crate::EnsembleClient::generate_best_of_n)), a
+//! [`CandidateScorer`] scores each and the highest wins. The default,
+//! [`ConfidenceScorer`], prefers the most confident answer;
+//! [`SyntaxScorer`] prefers answers that parse.
+
+use std::sync::Arc;
+
+use crate::syntax::SyntaxValidator;
+
+/// One model's answer, as seen by a [`CandidateScorer`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EnsembleCandidate<'a> {
+    /// Generated code
+    pub code: &'a str,
+
+    /// Confidence of the answer, if it is scored
+    pub confidence: Option<f32>,
+
+    /// Model that produced it
+    pub model: &'a str,
+}
+
+/// Scores ensemble answers; the highest score is selected
+///
+/// Ties go to the earlier candidate: the primary model before its
+/// fallbacks, and earlier samples before later ones.
+pub trait CandidateScorer: Send + Sync {
+    /// Score of `candidate`; higher is better
+    fn score(&self, candidate: &EnsembleCandidate<'_>) -> f64;
+}
+
+/// Scores by confidence, ranking unscored answers below scored ones
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConfidenceScorer;
+
+impl CandidateScorer for ConfidenceScorer {
+    fn score(&self, candidate: &EnsembleCandidate<'_>) -> f64 {
+        candidate
+            .confidence
+            .map(f64::from)
+            .unwrap_or(f64::NEG_INFINITY)
+    }
+}
+
+/// Scores by syntax errors, fewest first, then by confidence
+///
+/// Each error costs more than any difference in confidence, which is clamped
+/// to `0.0..=1.0`, so an answer that parses beats any that does not, even one
+/// with full confidence and an unscored rival.
+#[derive(Clone)]
+pub struct SyntaxScorer {
+    validator: Arc<dyn SyntaxValidator>,
+}
+
+impl SyntaxScorer {
+    /// Score with `validator`
+    pub fn new(validator: impl SyntaxValidator + 'static) -> Self {
+        Self {
+            validator: Arc::new(validator),
+        }
+    }
+}
+
+impl CandidateScorer for SyntaxScorer {
+    fn score(&self, candidate: &EnsembleCandidate<'_>) -> f64 {
+        let errors = self.validator.validate(candidate.code).len();
+        let confidence = f64::from(candidate.confidence.unwrap_or(0.0).clamp(0.0, 1.0));
+        -(errors as f64) * 2.0 + confidence
+    }
+}
+
+/// Index of the highest-scoring of `candidates`, the earliest on ties
+pub(crate) fn select(
+    scorer: &dyn CandidateScorer,
+    candidates: &[EnsembleCandidate<'_>],
+) -> Option<usize> {
+    let mut best: Option<(usize, f64)> = None;
+    for (index, candidate) in candidates.iter().enumerate() {
+        let score = scorer.score(candidate);
+        tracing::debug!(model = candidate.model, score, "Scored ensemble candidate");
+        if best.is_none_or(|(_, top)| score.total_cmp(&top).is_gt()) {
+            best = Some((index, score));
+        }
+    }
+    best.map(|(index, _)| index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::SyntaxError;
+
+    fn candidate<'a>(code: &'a str, confidence: Option<f32>) -> EnsembleCandidate<'a> {
+        EnsembleCandidate {
+            code,
+            confidence,
+            model: "mock-model",
+        }
+    }
+
+    struct UnbalancedParens;
+
+    impl SyntaxValidator for UnbalancedParens {
+        fn validate(&self, code: &str) -> Vec<SyntaxError> {
+            if code.matches('(').count() == code.matches(')').count() {
+                Vec::new()
+            } else {
+                vec![SyntaxError::at(code, 0, 1, "unbalanced parentheses")]
+            }
+        }
+    }
+
+    #[test]
+    fn test_confidence_scorer_prefers_scored_and_earlier() {
+        let candidates = [
+            candidate("a", None),
+            candidate("b", Some(0.4)),
+            candidate("c", Some(0.9)),
+            candidate("d", Some(0.9)),
+        ];
+        assert_eq!(select(&ConfidenceScorer, &candidates), Some(2));
+        assert_eq!(select(&ConfidenceScorer, &candidates[..1]), Some(0));
+        assert_eq!(select(&ConfidenceScorer, &[]), None);
+    }
+
+    #[test]
+    fn test_syntax_scorer_prefers_code_that_parses() {
+        let scorer = SyntaxScorer::new(UnbalancedParens);
+        let candidates = [
+            candidate("f(x", Some(0.99)),
+            candidate("f(x)", Some(0.2)),
+            candidate("g(x)", None),
+        ];
+        assert_eq!(select(&scorer, &candidates), Some(1));
+
+        // Full confidence with an error does not tie an unscored clean answer
+        let candidates = [candidate("f(x", Some(1.0)), candidate("f(x)", None)];
+        assert_eq!(select(&scorer, &candidates), Some(1));
+    }
+}
@@ -0,0 +1,135 @@
+//! Time source for retry backoff and cache expiry
+//!
+//! [`ModalClient`](crate::ModalClient) and
+//! [`MazeOrchestrator`](crate::MazeOrchestrator) read the time and sleep
+//! through a [`Clock`] rather than calling the system directly, so tests
+//! can substitute a [`MockClock`] and check backoff delays and TTLs without
+//! waiting for them. [`SystemClock`] is the default.
+
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of the current time and of delays
+#[async_trait]
+pub trait Clock: Send + Sync {
+    /// The current instant
+    fn now(&self) -> Instant;
+
+    /// Wait for `duration`
+    async fn sleep(&self, duration: Duration);
+}
+
+/// The system clock, sleeping on the tokio timer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+/// Clock that only moves when told to, for tests
+///
+/// [`sleep`](Clock::sleep) returns at once, advancing the clock by the
+/// requested duration and recording it, so a test can assert exact delays.
+/// [`advance`](Self::advance) moves time forward without a sleep, e.g. past
+/// a cache TTL. Clones share the same time.
+///
+/// ```
+/// use maze::{Clock, MockClock};
+/// use std::time::Duration;
+///
+/// # tokio_test::block_on(async {
+/// let clock = MockClock::new();
+/// let start = clock.now();
+/// clock.sleep(Duration::from_millis(100)).await;
+/// clock.advance(Duration::from_secs(5));
+///
+/// assert_eq!(clock.now() - start, Duration::from_millis(5100));
+/// assert_eq!(clock.sleeps(), vec![Duration::from_millis(100)]);
+/// # });
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    state: Arc<Mutex<MockClockState>>,
+}
+
+#[derive(Debug)]
+struct MockClockState {
+    now: Instant,
+    sleeps: Vec<Duration>,
+}
+
+impl MockClock {
+    /// A clock starting at the current system time
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockClockState {
+                now: Instant::now(),
+                sleeps: Vec::new(),
+            })),
+        }
+    }
+
+    /// Move time forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        self.state.lock().unwrap().now += duration;
+    }
+
+    /// Durations slept so far, in order
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.state.lock().unwrap().sleeps.clone()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.now += duration;
+            state.sleeps.push(duration);
+        }
+        // Still a suspension point, as a real sleep would be
+        tokio::task::yield_now().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_clock_sleeps_without_waiting() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let real_start = Instant::now();
+
+        clock.sleep(Duration::from_secs(60)).await;
+        clock.clone().sleep(Duration::from_secs(30)).await;
+
+        assert_eq!(clock.now() - start, Duration::from_secs(90));
+        assert_eq!(
+            clock.sleeps(),
+            vec![Duration::from_secs(60), Duration::from_secs(30)]
+        );
+        assert!(real_start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,598 @@
+//! Concurrency limiting for generation requests
+//!
+//! Without a limit, every concurrent `generate()` call becomes an in-flight
+//! request to the inference service, which can trip its rate limits. A
+//! [`ConcurrencyLimiter`] caps in-flight generations; excess callers wait
+//! for a permit, optionally failing with [`Busy`] after a queue timeout.
+//!
+//! A fixed limit is either too cautious or too aggressive as backend
+//! capacity varies. With an [`AdaptiveConcurrencyConfig`], the limit moves
+//! within its bounds by additive increase, multiplicative decrease (AIMD):
+//! it grows while generations complete at a steady latency and shrinks when
+//! the service sheds load or slows down.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::modal_client::{RequestTimedOut, ServiceError};
+
+/// A request waited longer than the queue timeout for a generation slot
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "orchestrator busy: no generation slot free within {waited_ms}ms ({max_concurrent} in flight)"
+)]
+pub struct Busy {
+    /// How long the request waited, in milliseconds
+    pub waited_ms: u64,
+
+    /// Concurrency limit that was saturated
+    pub max_concurrent: usize,
+}
+
+/// In-flight and queued generation counts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoadStats {
+    /// Generations currently holding a slot
+    pub in_flight: usize,
+
+    /// Callers waiting for a slot
+    pub queued: usize,
+
+    /// Maximum concurrent generations, if limited; the current limit under
+    /// adaptive concurrency
+    pub max_concurrent: Option<usize>,
+}
+
+/// Bounds and tuning for a concurrency limit that adapts to the backend
+///
+/// The limit grows by one once a limit's worth of generations in a row
+/// complete without overload. It is multiplied by `backoff_ratio` when a
+/// generation fails with a 429 or 503 [`ServiceError`] or
+/// [`RequestTimedOut`], or takes longer than `latency_spike_ratio` times the
+/// moving average. After backing off it ignores overload until as many
+/// generations have completed as the limit allowed in flight when it backed
+/// off, so a burst of failures from one overload counts once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveConcurrencyConfig {
+    /// Lowest the limit goes
+    #[serde(default = "default_min_limit")]
+    pub min_limit: usize,
+
+    /// Highest the limit goes
+    #[serde(default = "default_max_limit")]
+    pub max_limit: usize,
+
+    /// Factor the limit is multiplied by on overload, between 0 and 1
+    #[serde(default = "default_backoff_ratio")]
+    pub backoff_ratio: f64,
+
+    /// Latency, as a multiple of the moving average, that counts as overload
+    #[serde(default = "default_latency_spike_ratio")]
+    pub latency_spike_ratio: f64,
+}
+
+fn default_min_limit() -> usize {
+    1
+}
+
+fn default_max_limit() -> usize {
+    64
+}
+
+fn default_backoff_ratio() -> f64 {
+    0.5
+}
+
+fn default_latency_spike_ratio() -> f64 {
+    2.0
+}
+
+impl Default for AdaptiveConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            min_limit: default_min_limit(),
+            max_limit: default_max_limit(),
+            backoff_ratio: default_backoff_ratio(),
+            latency_spike_ratio: default_latency_spike_ratio(),
+        }
+    }
+}
+
+/// Weight of the latest generation in the latency moving average
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+/// Caps concurrent generations with a semaphore
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimiter {
+    semaphore: Arc<Semaphore>,
+    max_concurrent: Option<usize>,
+    queue_timeout: Option<Duration>,
+    in_flight: Arc<AtomicUsize>,
+    queued: Arc<AtomicUsize>,
+    adaptive: Option<Arc<AdaptiveLimit>>,
+}
+
+/// The moving limit of an adaptive [`ConcurrencyLimiter`]
+#[derive(Debug)]
+struct AdaptiveLimit {
+    config: AdaptiveConcurrencyConfig,
+    semaphore: Arc<Semaphore>,
+    state: std::sync::Mutex<AdaptiveState>,
+}
+
+#[derive(Debug)]
+struct AdaptiveState {
+    limit: usize,
+
+    /// Generations completed without overload since the limit last changed
+    successes: usize,
+
+    /// Generations completed since the last backoff
+    since_backoff: usize,
+
+    /// Limit at the last backoff: the most generations that can have been
+    /// in flight then, and so fail from the same overload
+    backoff_window: usize,
+
+    /// Moving average of generation latency, in milliseconds
+    avg_latency_ms: Option<f64>,
+
+    /// Permits to retire as they are released, after the limit dropped
+    /// below the number in use
+    excess: usize,
+}
+
+impl AdaptiveLimit {
+    fn limit(&self) -> usize {
+        self.lock().limit
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, AdaptiveState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adjust the limit for a generation that took `latency` and ended with
+    /// `outcome`
+    fn record(&self, latency: Duration, outcome: Outcome) {
+        let mut state = self.lock();
+        state.since_backoff = state.since_backoff.saturating_add(1);
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        let overloaded = match outcome {
+            Outcome::Success => {
+                let spike = state
+                    .avg_latency_ms
+                    .is_some_and(|avg| latency_ms > avg * self.config.latency_spike_ratio);
+                state.avg_latency_ms = Some(match state.avg_latency_ms {
+                    Some(avg) => avg + LATENCY_SMOOTHING * (latency_ms - avg),
+                    None => latency_ms,
+                });
+                spike
+            }
+            Outcome::Overloaded => true,
+            Outcome::Failed => return,
+        };
+
+        if overloaded {
+            state.successes = 0;
+            if state.since_backoff < state.backoff_window {
+                return;
+            }
+            let lowered = ((state.limit as f64 * self.config.backoff_ratio) as usize)
+                .max(self.config.min_limit);
+            if lowered < state.limit {
+                let mut retire = state.limit - lowered;
+                while retire > 0 {
+                    match self.semaphore.try_acquire() {
+                        Ok(permit) => permit.forget(),
+                        Err(_) => break,
+                    }
+                    retire -= 1;
+                }
+                state.excess += retire;
+                tracing::info!(limit = lowered, "Lowered adaptive concurrency limit");
+            }
+            state.backoff_window = state.limit;
+            state.limit = lowered;
+            state.since_backoff = 0;
+        } else {
+            state.successes += 1;
+            if state.successes >= state.limit && state.limit < self.config.max_limit {
+                state.successes = 0;
+                state.limit += 1;
+                if state.excess > 0 {
+                    state.excess -= 1;
+                } else {
+                    self.semaphore.add_permits(1);
+                }
+                tracing::debug!(limit = state.limit, "Raised adaptive concurrency limit");
+            }
+        }
+    }
+
+    /// Whether a released permit should be retired instead of returned
+    fn take_excess(&self) -> bool {
+        let mut state = self.lock();
+        let retire = state.excess > 0;
+        if retire {
+            state.excess -= 1;
+        }
+        retire
+    }
+}
+
+/// How a generation ended, as far as the adaptive limit is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Success,
+    Overloaded,
+    Failed,
+}
+
+impl Outcome {
+    fn of<T>(result: &anyhow::Result<T>) -> Self {
+        match result {
+            Ok(_) => Self::Success,
+            Err(e)
+                if e.downcast_ref::<ServiceError>()
+                    .is_some_and(ServiceError::is_overload)
+                    || e.downcast_ref::<RequestTimedOut>().is_some() =>
+            {
+                Self::Overloaded
+            }
+            Err(_) => Self::Failed,
+        }
+    }
+}
+
+impl ConcurrencyLimiter {
+    /// Allow at most `max_concurrent` generations at once; zero means unlimited
+    ///
+    /// Waiting callers fail with [`Busy`] after `queue_timeout`, or wait
+    /// indefinitely if it is `None`.
+    pub fn new(max_concurrent: usize, queue_timeout: Option<Duration>) -> Self {
+        let max_concurrent = (max_concurrent > 0).then_some(max_concurrent);
+        Self {
+            semaphore: Arc::new(Semaphore::new(
+                max_concurrent.unwrap_or(Semaphore::MAX_PERMITS),
+            )),
+            max_concurrent,
+            queue_timeout,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            queued: Arc::new(AtomicUsize::new(0)),
+            adaptive: None,
+        }
+    }
+
+    /// Start at `initial` concurrent generations, clamped to `config`'s
+    /// bounds, and adapt the limit to how generations reported through
+    /// [`GenerationPermit::record`] fare
+    pub fn adaptive(
+        initial: usize,
+        config: AdaptiveConcurrencyConfig,
+        queue_timeout: Option<Duration>,
+    ) -> Self {
+        let max_limit = config.max_limit.max(1);
+        let min_limit = config.min_limit.clamp(1, max_limit);
+        let config = AdaptiveConcurrencyConfig {
+            min_limit,
+            max_limit,
+            ..config
+        };
+        let limit = initial.clamp(min_limit, max_limit);
+        let semaphore = Arc::new(Semaphore::new(limit));
+        Self {
+            semaphore: semaphore.clone(),
+            max_concurrent: Some(limit),
+            queue_timeout,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            queued: Arc::new(AtomicUsize::new(0)),
+            adaptive: Some(Arc::new(AdaptiveLimit {
+                config,
+                semaphore,
+                state: std::sync::Mutex::new(AdaptiveState {
+                    limit,
+                    successes: 0,
+                    since_backoff: 0,
+                    backoff_window: 0,
+                    avg_latency_ms: None,
+                    excess: 0,
+                }),
+            })),
+        }
+    }
+
+    /// Current concurrency limit, if limited
+    fn limit(&self) -> Option<usize> {
+        match self.adaptive {
+            Some(ref adaptive) => Some(adaptive.limit()),
+            None => self.max_concurrent,
+        }
+    }
+
+    /// Wait for a generation slot; the slot is released when the permit drops
+    pub async fn acquire(&self) -> Result<GenerationPermit, Busy> {
+        let start = std::time::Instant::now();
+        let _queued = CountGuard::increment(&self.queued);
+
+        let acquire = self.semaphore.clone().acquire_owned();
+        let permit = match self.queue_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, acquire).await.ok(),
+            None => Some(acquire.await),
+        };
+
+        match permit {
+            // The semaphore is never closed
+            Some(Ok(permit)) => Ok(GenerationPermit {
+                permit: Some(permit),
+                _in_flight: CountGuard::increment(&self.in_flight),
+                adaptive: self.adaptive.clone(),
+                started: Instant::now(),
+            }),
+            Some(Err(_)) | None => Err(Busy {
+                waited_ms: start.elapsed().as_millis() as u64,
+                max_concurrent: self.limit().unwrap_or(0),
+            }),
+        }
+    }
+
+    /// Current in-flight and queued counts
+    pub fn stats(&self) -> LoadStats {
+        LoadStats {
+            in_flight: self.in_flight.load(Ordering::SeqCst),
+            queued: self.queued.load(Ordering::SeqCst),
+            max_concurrent: self.limit(),
+        }
+    }
+}
+
+/// A held generation slot
+#[derive(Debug)]
+pub struct GenerationPermit {
+    permit: Option<OwnedSemaphorePermit>,
+    _in_flight: CountGuard,
+    adaptive: Option<Arc<AdaptiveLimit>>,
+    started: Instant,
+}
+
+impl GenerationPermit {
+    /// Report how the generation holding this slot ended, so an adaptive
+    /// limiter can adjust its limit; a no-op for fixed limits
+    pub fn record<T>(&self, result: &anyhow::Result<T>) {
+        if let Some(ref adaptive) = self.adaptive {
+            adaptive.record(self.started.elapsed(), Outcome::of(result));
+        }
+    }
+}
+
+/// A stream holding a generation slot until dropped, reporting to the
+/// permit how the stream ended: in error at its first error, or in success
+/// once exhausted
+pub(crate) struct PermitStream<S> {
+    stream: S,
+    permit: GenerationPermit,
+    recorded: bool,
+}
+
+impl<S> PermitStream<S> {
+    pub(crate) fn new(stream: S, permit: GenerationPermit) -> Self {
+        Self {
+            stream,
+            permit,
+            recorded: false,
+        }
+    }
+}
+
+impl<S, T> futures::Stream for PermitStream<S>
+where
+    S: futures::Stream<Item = anyhow::Result<T>> + Unpin,
+{
+    type Item = anyhow::Result<T>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        use futures::StreamExt;
+
+        let item = futures::ready!(self.stream.poll_next_unpin(cx));
+        if !self.recorded {
+            match item {
+                Some(Ok(_)) => {}
+                Some(ref result) => {
+                    self.permit.record(result);
+                    self.recorded = true;
+                }
+                None => {
+                    self.permit.record(&Ok(()));
+                    self.recorded = true;
+                }
+            }
+        }
+        std::task::Poll::Ready(item)
+    }
+}
+
+impl Drop for GenerationPermit {
+    fn drop(&mut self) {
+        if let (Some(adaptive), Some(permit)) = (&self.adaptive, self.permit.take()) {
+            if adaptive.take_excess() {
+                permit.forget();
+            }
+        }
+    }
+}
+
+/// Increments a counter for as long as it is alive, including across
+/// cancellation of the owning future
+#[derive(Debug)]
+struct CountGuard(Arc<AtomicUsize>);
+
+impl CountGuard {
+    fn increment(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Self(counter.clone())
+    }
+}
+
+impl Drop for CountGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limiter_caps_in_flight_and_counts_queue() {
+        let limiter = ConcurrencyLimiter::new(1, None);
+
+        let permit = limiter.acquire().await.unwrap();
+        assert_eq!(limiter.stats().in_flight, 1);
+
+        let waiter = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move { limiter.acquire().await.map(|_| ()) })
+        };
+        while limiter.stats().queued == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        drop(permit);
+        waiter.await.unwrap().unwrap();
+        assert_eq!(
+            limiter.stats(),
+            LoadStats {
+                in_flight: 0,
+                queued: 0,
+                max_concurrent: Some(1),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limiter_queue_timeout_returns_busy() {
+        let limiter = ConcurrencyLimiter::new(1, Some(Duration::from_millis(20)));
+        let _permit = limiter.acquire().await.unwrap();
+
+        let busy = limiter.acquire().await.unwrap_err();
+        assert_eq!(busy.max_concurrent, 1);
+        assert!(busy.waited_ms >= 20);
+        assert_eq!(limiter.stats().queued, 0);
+    }
+
+    fn overloaded() -> anyhow::Result<()> {
+        Err(ServiceError {
+            status: 503,
+            message: "overloaded".to_string(),
+        }
+        .into())
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_limit_grows_and_backs_off() {
+        let limiter = ConcurrencyLimiter::adaptive(8, AdaptiveConcurrencyConfig::default(), None);
+        assert_eq!(limiter.stats().max_concurrent, Some(8));
+
+        // A limit's worth of successes raises it by one
+        for _ in 0..8 {
+            limiter.acquire().await.unwrap().record(&Ok(()));
+        }
+        assert_eq!(limiter.stats().max_concurrent, Some(9));
+
+        // Overload halves it, once per limit's worth of completions
+        limiter.acquire().await.unwrap().record(&overloaded());
+        limiter.acquire().await.unwrap().record(&overloaded());
+        assert_eq!(limiter.stats().max_concurrent, Some(4));
+
+        // Other failures leave it alone
+        let failed: anyhow::Result<()> = Err(anyhow::anyhow!("bad request"));
+        for _ in 0..5 {
+            limiter.acquire().await.unwrap().record(&failed);
+        }
+        assert_eq!(limiter.stats().max_concurrent, Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_limit_retires_permits_in_use() {
+        let config = AdaptiveConcurrencyConfig {
+            min_limit: 1,
+            max_limit: 4,
+            ..Default::default()
+        };
+        let limiter = ConcurrencyLimiter::adaptive(4, config, Some(Duration::from_millis(10)));
+        let permits: Vec<_> = futures::future::try_join_all((0..4).map(|_| limiter.acquire()))
+            .await
+            .unwrap();
+
+        // All four fail from the same overload, which backs off once
+        for permit in &permits {
+            permit.record(&overloaded());
+        }
+        assert_eq!(limiter.stats().max_concurrent, Some(2));
+        drop(permits);
+
+        // Only two of the four released slots come back
+        let _a = limiter.acquire().await.unwrap();
+        let _b = limiter.acquire().await.unwrap();
+        let busy = limiter.acquire().await.unwrap_err();
+        assert_eq!(busy.max_concurrent, 2);
+    }
+
+    #[tokio::test]
+    async fn test_permit_stream_records_how_the_stream_ended() {
+        use futures::StreamExt;
+
+        let limiter = ConcurrencyLimiter::adaptive(4, AdaptiveConcurrencyConfig::default(), None);
+        let overloaded_stream = |limiter: ConcurrencyLimiter| async move {
+            let permit = limiter.acquire().await.unwrap();
+            let chunks = futures::stream::iter(vec![Ok(1), overloaded().map(|_| 2), Ok(3)]);
+            PermitStream::new(chunks, permit).collect::<Vec<_>>().await
+        };
+        assert_eq!(overloaded_stream(limiter.clone()).await.len(), 3);
+        assert_eq!(limiter.stats().max_concurrent, Some(2));
+        assert_eq!(limiter.stats().in_flight, 0);
+
+        // Streams that finish cleanly count toward raising it
+        for _ in 0..2 {
+            let permit = limiter.acquire().await.unwrap();
+            PermitStream::new(
+                futures::stream::iter(vec![Ok::<_, anyhow::Error>(1)]),
+                permit,
+            )
+            .collect::<Vec<_>>()
+            .await;
+        }
+        assert_eq!(limiter.stats().max_concurrent, Some(3));
+    }
+
+    #[tokio::test]
+    async fn test_latency_spike_backs_off() {
+        let limiter = ConcurrencyLimiter::adaptive(4, AdaptiveConcurrencyConfig::default(), None);
+        let adaptive = limiter.adaptive.clone().unwrap();
+        for _ in 0..3 {
+            adaptive.record(Duration::from_millis(10), Outcome::Success);
+        }
+        assert_eq!(limiter.stats().max_concurrent, Some(4));
+
+        adaptive.record(Duration::from_millis(100), Outcome::Success);
+        assert_eq!(limiter.stats().max_concurrent, Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_zero_limit_is_unlimited() {
+        let limiter = ConcurrencyLimiter::new(0, Some(Duration::from_millis(1)));
+        let permits: Vec<_> = futures::future::try_join_all((0..100).map(|_| limiter.acquire()))
+            .await
+            .unwrap();
+
+        let stats = limiter.stats();
+        assert_eq!(stats.in_flight, permits.len());
+        assert_eq!(stats.max_concurrent, None);
+    }
+}
@@ -0,0 +1,355 @@
+//! Validated construction of [`ConstraintIR`]
+//!
+//! [`ConstraintBuilder`] assembles a constraint one part at a time and checks
+//! each part as it is added: regex patterns must compile, JSON schemas must be
+//! objects with well-formed `properties` and `required`, and grammars must
+//! define their start symbol. The Python `ConstraintBuilder` wraps this type,
+//! and [`ConstraintIR::validate`] applies the same checks to IR received over
+//! FFI, so every entry point rejects the same malformed input.
+//!
+//! ```
+//! use maze::constraint_builder::ConstraintBuilder;
+//!
+//! let constraint = ConstraintBuilder::new("identifier")
+//!     .with_regex(r"[a-z_][a-z0-9_]*", "i")?
+//!     .with_priority(2)
+//!     .build()?;
+//! assert_eq!(constraint.regex_patterns.len(), 1);
+//! # Ok::<(), maze::constraint_builder::InvalidConstraint>(())
+//! ```
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ffi::{ConstraintIR, Grammar, GrammarRule, JsonSchema, RegexPattern, TokenMaskRules};
+use crate::lint;
+
+/// Regex flags understood by the constraint compiler
+pub const REGEX_FLAGS: &str = "imsx";
+
+/// A constraint part was rejected by [`ConstraintBuilder`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid constraint '{constraint}': {reason}")]
+pub struct InvalidConstraint {
+    /// Name of the constraint being built
+    pub constraint: String,
+
+    /// What was wrong with the input
+    pub reason: String,
+}
+
+/// Builds a [`ConstraintIR`], validating each part as it is added
+#[derive(Debug, Clone)]
+pub struct ConstraintBuilder {
+    constraint: ConstraintIR,
+}
+
+impl ConstraintBuilder {
+    /// Start a constraint with the given name
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            constraint: ConstraintIR {
+                name: name.into(),
+                json_schema: None,
+                grammar: None,
+                regex_patterns: vec![],
+                token_masks: None,
+                type_inhabitation: None,
+                priority: 0,
+                rich_context: None,
+                feasibility_score: 0.0,
+                is_feasible: true,
+                schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+            },
+        }
+    }
+
+    /// Add a regex pattern; `flags` may contain any of [`REGEX_FLAGS`]
+    pub fn with_regex(mut self, pattern: &str, flags: &str) -> Result<Self, InvalidConstraint> {
+        let regex = RegexPattern {
+            pattern: pattern.to_string(),
+            flags: flags.to_string(),
+        };
+        check_regex(&regex).map_err(|reason| self.invalid(reason))?;
+
+        self.constraint.regex_patterns.push(regex);
+        Ok(self)
+    }
+
+    /// Set the JSON schema from its JSON representation
+    ///
+    /// Accepts `type` (default `"object"`), `properties`, `required`, and
+    /// `additionalProperties` (default `true`). Every `required` name must be
+    /// a declared property.
+    pub fn with_json_schema(
+        mut self,
+        schema: &serde_json::Value,
+    ) -> Result<Self, InvalidConstraint> {
+        let schema = json_schema_from_value(schema).map_err(|reason| self.invalid(reason))?;
+        self.constraint.json_schema = Some(schema);
+        Ok(self)
+    }
+
+    /// Set the grammar from `(lhs, rhs)` rules and a start symbol
+    pub fn with_grammar(
+        mut self,
+        rules: Vec<GrammarRule>,
+        start_symbol: &str,
+    ) -> Result<Self, InvalidConstraint> {
+        let grammar = Grammar {
+            rules,
+            start_symbol: start_symbol.to_string(),
+        };
+        check_grammar(&grammar).map_err(|reason| self.invalid(reason))?;
+
+        self.constraint.grammar = Some(grammar);
+        Ok(self)
+    }
+
+    /// Set token masks; a token may not be both allowed and forbidden
+    pub fn with_token_masks(
+        mut self,
+        allowed: Option<Vec<u32>>,
+        forbidden: Option<Vec<u32>>,
+    ) -> Result<Self, InvalidConstraint> {
+        let masks = TokenMaskRules {
+            allowed_tokens: allowed,
+            forbidden_tokens: forbidden,
+        };
+        check_token_masks(&masks).map_err(|reason| self.invalid(reason))?;
+
+        self.constraint.token_masks = Some(masks);
+        Ok(self)
+    }
+
+    /// Set the merge priority; higher wins
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.constraint.priority = priority;
+        self
+    }
+
+    /// Finish the constraint
+    ///
+    /// Fails if the name is empty or nothing was added.
+    pub fn build(self) -> Result<ConstraintIR, InvalidConstraint> {
+        let c = &self.constraint;
+        if c.name.trim().is_empty() {
+            return Err(self.invalid("constraint name is empty"));
+        }
+        if c.json_schema.is_none()
+            && c.grammar.is_none()
+            && c.regex_patterns.is_empty()
+            && c.token_masks.is_none()
+        {
+            return Err(
+                self.invalid("constraint has no regex, JSON schema, grammar, or token masks")
+            );
+        }
+        Ok(self.constraint)
+    }
+
+    fn invalid(&self, reason: impl Into<String>) -> InvalidConstraint {
+        InvalidConstraint {
+            constraint: self.constraint.name.clone(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Check that a regex uses only [`REGEX_FLAGS`] and compiles
+pub fn check_regex(regex: &RegexPattern) -> Result<(), String> {
+    if let Some(flag) = regex.flags.chars().find(|f| !REGEX_FLAGS.contains(*f)) {
+        return Err(format!(
+            "unsupported regex flag '{}' (expected any of \"{}\")",
+            flag, REGEX_FLAGS
+        ));
+    }
+    lint::compile_regex(regex)
+        .map(|_| ())
+        .map_err(|e| format!("invalid regex /{}/: {}", regex.pattern, e))
+}
+
+/// Check that a grammar has rules, none with an empty left-hand side, and a
+/// rule for its start symbol
+pub fn check_grammar(grammar: &Grammar) -> Result<(), String> {
+    if grammar.rules.is_empty() {
+        return Err("grammar has no rules".to_string());
+    }
+    if let Some(rule) = grammar.rules.iter().find(|r| r.lhs.trim().is_empty()) {
+        return Err(format!(
+            "grammar rule with empty left-hand side (rhs: {:?})",
+            rule.rhs
+        ));
+    }
+    if !grammar.rules.iter().any(|r| r.lhs == grammar.start_symbol) {
+        return Err(format!(
+            "start symbol '{}' has no rule",
+            grammar.start_symbol
+        ));
+    }
+    Ok(())
+}
+
+/// Check that a JSON schema declares every property it requires
+pub fn check_json_schema(schema: &JsonSchema) -> Result<(), String> {
+    match schema
+        .required
+        .iter()
+        .find(|r| !schema.properties.contains_key(*r))
+    {
+        Some(missing) => Err(format!(
+            "required property '{}' is not declared in \"properties\"",
+            missing
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Check that token masks allow something and no token is both allowed and
+/// forbidden
+pub fn check_token_masks(masks: &TokenMaskRules) -> Result<(), String> {
+    if masks.allowed_tokens.as_ref().is_some_and(|a| a.is_empty()) {
+        return Err("allowed token list is empty; nothing could be generated".to_string());
+    }
+    if let (Some(allowed), Some(forbidden)) = (&masks.allowed_tokens, &masks.forbidden_tokens) {
+        let forbidden: HashSet<&u32> = forbidden.iter().collect();
+        if let Some(token) = allowed.iter().find(|t| forbidden.contains(t)) {
+            return Err(format!("token {} is both allowed and forbidden", token));
+        }
+    }
+    Ok(())
+}
+
+/// Convert a JSON schema document into a [`JsonSchema`], checking its shape
+pub fn json_schema_from_value(value: &serde_json::Value) -> Result<JsonSchema, String> {
+    let object = value
+        .as_object()
+        .ok_or_else(|| format!("JSON schema must be an object, got {}", value))?;
+
+    let schema_type = match object.get("type") {
+        None => "object".to_string(),
+        Some(serde_json::Value::String(t)) => t.clone(),
+        Some(other) => return Err(format!("schema \"type\" must be a string, got {}", other)),
+    };
+
+    let properties: HashMap<String, serde_json::Value> = match object.get("properties") {
+        None => Default::default(),
+        Some(serde_json::Value::Object(props)) => {
+            props.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+        }
+        Some(other) => {
+            return Err(format!(
+                "schema \"properties\" must be an object, got {}",
+                other
+            ))
+        }
+    };
+
+    let required: Vec<String> = match object.get("required") {
+        None => vec![],
+        Some(serde_json::Value::Array(names)) => names
+            .iter()
+            .map(|n| {
+                n.as_str().map(String::from).ok_or_else(|| {
+                    format!("schema \"required\" entries must be strings, got {}", n)
+                })
+            })
+            .collect::<Result<_, _>>()?,
+        Some(other) => {
+            return Err(format!(
+                "schema \"required\" must be an array, got {}",
+                other
+            ))
+        }
+    };
+    let additional_properties = match object.get("additionalProperties") {
+        None => true,
+        Some(serde_json::Value::Bool(b)) => *b,
+        Some(other) => {
+            return Err(format!(
+                "schema \"additionalProperties\" must be a boolean, got {}",
+                other
+            ))
+        }
+    };
+
+    let schema = JsonSchema {
+        schema_type,
+        properties,
+        required,
+        additional_properties,
+    };
+    check_json_schema(&schema)?;
+    Ok(schema)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rule(lhs: &str, rhs: &[&str]) -> GrammarRule {
+        GrammarRule {
+            lhs: lhs.to_string(),
+            rhs: rhs.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_builder_assembles_all_parts() {
+        let constraint = ConstraintBuilder::new("user")
+            .with_regex(r"\w+", "i")
+            .unwrap()
+            .with_json_schema(&json!({
+                "properties": {"name": {"type": "string"}},
+                "required": ["name"],
+            }))
+            .unwrap()
+            .with_grammar(vec![rule("start", &["name"])], "start")
+            .unwrap()
+            .with_priority(3)
+            .build()
+            .unwrap();
+
+        assert_eq!(constraint.regex_patterns[0].flags, "i");
+        let schema = constraint.json_schema.unwrap();
+        assert_eq!(schema.schema_type, "object");
+        assert_eq!(schema.required, vec!["name"]);
+        assert!(schema.additional_properties);
+        assert_eq!(constraint.grammar.unwrap().start_symbol, "start");
+        assert_eq!(constraint.priority, 3);
+    }
+
+    #[test]
+    fn test_builder_rejects_malformed_parts() {
+        let b = || ConstraintBuilder::new("c");
+
+        let err = b().with_regex("(unclosed", "").unwrap_err();
+        assert_eq!(err.constraint, "c");
+        assert!(err.reason.contains("invalid regex"));
+        assert!(b().with_regex("a", "q").is_err());
+
+        assert!(b().with_json_schema(&json!([1, 2])).is_err());
+        assert!(b().with_json_schema(&json!({"required": ["id"]})).is_err());
+        assert!(b().with_json_schema(&json!({"properties": []})).is_err());
+
+        assert!(b().with_grammar(vec![], "start").is_err());
+        assert!(b()
+            .with_grammar(vec![rule("expr", &["x"])], "start")
+            .is_err());
+
+        assert!(b()
+            .with_token_masks(Some(vec![1, 2]), Some(vec![2]))
+            .is_err());
+        assert!(b().with_token_masks(Some(vec![]), None).is_err());
+    }
+
+    #[test]
+    fn test_build_requires_name_and_content() {
+        assert!(ConstraintBuilder::new("empty").build().is_err());
+        assert!(ConstraintBuilder::new(" ")
+            .with_regex("a", "")
+            .unwrap()
+            .build()
+            .is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//! Checking generated code against each constraint separately
+//!
+//! A fill that fails validation may still satisfy most of its constraints,
+//! and which ones it missed says what to do next: a fill with the wrong
+//! shape is better split into smaller holes, while one that only tripped a
+//! style rule is worth another attempt. [`check_constraints`] reports a
+//! [`ConstraintCheck`] per constraint, each classed as
+//! [`Structural`](ConstraintKind::Structural) or
+//! [`Style`](ConstraintKind::Style).
+//!
+//! Checks run locally and cover what can be checked without the decoder:
+//!
+//! - Regex patterns must match the whole output.
+//! - JSON schemas need the output to be JSON of the schema's type, with its
+//!   required properties.
+//! - Grammars are checked through the [`SyntaxValidator`](crate::SyntaxValidator),
+//!   if one is configured: its errors fail every grammar constraint.
+//! - Policies must find no violations (see [`policy`](crate::policy)).
+//!
+//! Token masks are enforced during decoding and always pass.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ffi::{ConstraintIR, JsonSchema};
+use crate::policy::PolicyConstraint;
+use crate::syntax::SyntaxError;
+
+/// What a constraint governs, which decides how its failure is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConstraintKind {
+    /// The shape of the output: grammars, JSON schemas, and regexes
+    Structural,
+
+    /// Rules about what the output may contain: policies and token masks
+    Style,
+}
+
+/// Outcome of checking one constraint against generated code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstraintCheck {
+    /// Constraint name
+    pub constraint: String,
+
+    /// What the constraint governs
+    pub kind: ConstraintKind,
+
+    /// Whether the code satisfies it
+    pub passed: bool,
+
+    /// Why the check failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Check `code` against each of `constraints`, in order
+///
+/// `syntax_errors` are the syntax validator's findings for `code`; they
+/// count against grammar constraints.
+pub fn check_constraints(
+    constraints: &[ConstraintIR],
+    code: &str,
+    syntax_errors: &[SyntaxError],
+) -> Vec<ConstraintCheck> {
+    constraints
+        .iter()
+        .map(|constraint| check_constraint(constraint, code, syntax_errors))
+        .collect()
+}
+
+fn check_constraint(
+    constraint: &ConstraintIR,
+    code: &str,
+    syntax_errors: &[SyntaxError],
+) -> ConstraintCheck {
+    let structural = constraint.grammar.is_some()
+        || constraint.json_schema.is_some()
+        || !constraint.regex_patterns.is_empty();
+    let mut failures = Vec::new();
+
+    if constraint.grammar.is_some() && !syntax_errors.is_empty() {
+        failures.push(format!("{} syntax errors", syntax_errors.len()));
+    }
+    if let Some(ref schema) = constraint.json_schema {
+        failures.extend(check_json(schema, code));
+    }
+    for pattern in &constraint.regex_patterns {
+        match crate::lint::compile_regex(pattern) {
+            Ok(regex) if !regex.is_match(code) => {
+                failures.push(format!("does not match /{}/", pattern.pattern))
+            }
+            Ok(_) => {}
+            // Look-arounds and the like are left to the decoder
+            Err(_) => {}
+        }
+    }
+    if let Some(policy) = PolicyConstraint::from_constraint(constraint) {
+        failures.extend(policy.scan(code).iter().map(|v| v.to_string()));
+    }
+
+    ConstraintCheck {
+        constraint: constraint.name.clone(),
+        kind: if structural {
+            ConstraintKind::Structural
+        } else {
+            ConstraintKind::Style
+        },
+        passed: failures.is_empty(),
+        detail: (!failures.is_empty()).then(|| failures.join("; ")),
+    }
+}
+
+/// Why `code` does not conform to `schema`, if it does not
+fn check_json(schema: &JsonSchema, code: &str) -> Option<String> {
+    let value: serde_json::Value = match serde_json::from_str(code) {
+        Ok(value) => value,
+        Err(e) => return Some(format!("not valid JSON: {}", e)),
+    };
+    let type_matches = match schema.schema_type.as_str() {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    };
+    if !type_matches {
+        return Some(format!("expected a JSON {}", schema.schema_type));
+    }
+    let missing: Vec<&str> = schema
+        .required
+        .iter()
+        .filter(|key| value.get(key.as_str()).is_none())
+        .map(String::as_str)
+        .collect();
+    (!missing.is_empty()).then(|| format!("missing required properties {:?}", missing))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraint_builder::ConstraintBuilder;
+    use crate::ffi::GrammarRule;
+
+    #[test]
+    fn test_reports_each_constraint() {
+        let signature = ConstraintBuilder::new("signature")
+            .with_regex(r"fn \w+\(\) \{.*\}", "")
+            .unwrap()
+            .build()
+            .unwrap();
+        let policy = PolicyConstraint::new("no_eval")
+            .deny(r"\beval\s*\(")
+            .unwrap()
+            .to_constraint();
+        let constraints = vec![signature, policy];
+
+        let checks = check_constraints(&constraints, "fn a() { eval(x) }", &[]);
+        assert_eq!(checks[0].kind, ConstraintKind::Structural);
+        assert!(checks[0].passed);
+        assert_eq!(checks[1].kind, ConstraintKind::Style);
+        assert!(!checks[1].passed);
+        assert!(checks[1].detail.as_ref().unwrap().contains("eval("));
+
+        let checks = check_constraints(&constraints, "let a = 1;", &[]);
+        assert!(!checks[0].passed);
+        assert!(checks[1].passed);
+    }
+
+    #[test]
+    fn test_grammar_fails_on_syntax_errors_and_schema_on_shape() {
+        let grammar = ConstraintBuilder::new("expr")
+            .with_grammar(
+                vec![GrammarRule {
+                    lhs: "start".to_string(),
+                    rhs: vec!["x".to_string()],
+                }],
+                "start",
+            )
+            .unwrap()
+            .build()
+            .unwrap();
+        let error = SyntaxError::at("x(", 1, 2, "unclosed paren");
+        assert!(check_constraints(std::slice::from_ref(&grammar), "x(", &[])[0].passed);
+        assert!(!check_constraints(&[grammar], "x(", &[error])[0].passed);
+
+        let schema = [ConstraintBuilder::new("config")
+            .with_json_schema(&serde_json::json!({
+                "properties": { "name": { "type": "string" } },
+                "required": ["name"]
+            }))
+            .unwrap()
+            .build()
+            .unwrap()];
+        assert!(check_constraints(&schema, r#"{"name": "a"}"#, &[])[0].passed);
+        let missing = &check_constraints(&schema, r#"{"id": 1}"#, &[])[0];
+        assert_eq!(missing.kind, ConstraintKind::Structural);
+        assert!(missing.detail.as_ref().unwrap().contains("name"));
+        assert!(!check_constraints(&schema, "[1]", &[])[0].passed);
+    }
+}
@@ -0,0 +1,294 @@
+//! Target formats for compiled constraints
+//!
+//! vLLM and SGLang consume the llguidance JSON shape; llama.cpp and other
+//! grammar-driven backends need a single BNF-style grammar instead. Each
+//! [`ConstraintFormat`] has a [`ConstraintCompiler`] that translates the same
+//! [`ConstraintIR`] into that target, using the deterministic merge order
+//! from [`merge`](crate::merge).
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::bnf::{self, Dialect, Expr, RuleSet, ROOT_RULE};
+use crate::ffi::{ConstraintIR, Grammar, JsonSchema, RegexPattern};
+use crate::merge::{self, ConstraintMergeReport, MergeAction, MergeEvent};
+
+/// Encoding of compiled constraints sent to the inference backend
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConstraintFormat {
+    /// llguidance JSON (vLLM, SGLang)
+    #[default]
+    Llguidance,
+
+    /// llama.cpp GBNF grammar
+    Gbnf,
+
+    /// W3C-style EBNF grammar
+    Ebnf,
+}
+
+impl ConstraintFormat {
+    /// Lowercase name of the format
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Llguidance => "llguidance",
+            Self::Gbnf => "gbnf",
+            Self::Ebnf => "ebnf",
+        }
+    }
+
+    /// Compiler for this format
+    pub fn compiler(self) -> &'static dyn ConstraintCompiler {
+        match self {
+            Self::Llguidance => &LlguidanceCompiler,
+            Self::Gbnf => &GrammarCompiler::GBNF,
+            Self::Ebnf => &GrammarCompiler::EBNF,
+        }
+    }
+}
+
+impl std::fmt::Display for ConstraintFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Translates constraints into one [`ConstraintFormat`]
+pub trait ConstraintCompiler: Send + Sync {
+    /// Format this compiler produces
+    fn format(&self) -> ConstraintFormat;
+
+    /// Compile `constraints_ir`, reporting how overlapping constraints merged
+    fn compile(
+        &self,
+        constraints_ir: &[ConstraintIR],
+    ) -> Result<(serde_json::Value, ConstraintMergeReport)>;
+}
+
+/// Compiles to the llguidance JSON shape
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LlguidanceCompiler;
+
+impl ConstraintCompiler for LlguidanceCompiler {
+    fn format(&self) -> ConstraintFormat {
+        ConstraintFormat::Llguidance
+    }
+
+    fn compile(
+        &self,
+        constraints_ir: &[ConstraintIR],
+    ) -> Result<(serde_json::Value, ConstraintMergeReport)> {
+        crate::compile_llguidance_schema(constraints_ir)
+    }
+}
+
+/// Compiles to a single GBNF or EBNF grammar
+///
+/// A grammar has exactly one start rule, so unlike llguidance the parts of
+/// a constraint set cannot all apply at once. The first grammar, JSON
+/// schema, or regex in merge order becomes the root; every later one is
+/// recorded as overridden in the merge report. Token masks have no grammar
+/// equivalent and are rejected.
+///
+/// Output: `{"format": "gbnf", "root": "root", "grammar": "<rules>"}`.
+#[derive(Debug, Clone, Copy)]
+pub struct GrammarCompiler {
+    format: ConstraintFormat,
+    dialect: Dialect,
+}
+
+impl GrammarCompiler {
+    /// llama.cpp GBNF
+    pub const GBNF: Self = Self {
+        format: ConstraintFormat::Gbnf,
+        dialect: Dialect::Gbnf,
+    };
+
+    /// W3C-style EBNF
+    pub const EBNF: Self = Self {
+        format: ConstraintFormat::Ebnf,
+        dialect: Dialect::Ebnf,
+    };
+}
+
+impl ConstraintCompiler for GrammarCompiler {
+    fn format(&self) -> ConstraintFormat {
+        self.format
+    }
+
+    fn compile(
+        &self,
+        constraints_ir: &[ConstraintIR],
+    ) -> Result<(serde_json::Value, ConstraintMergeReport)> {
+        let ordered = merge::merge_order(constraints_ir);
+        let mut report = ConstraintMergeReport::new(&ordered);
+        let labels = report.order.clone();
+
+        if let Some(c) = ordered.iter().find(|c| c.token_masks.is_some()) {
+            bail!(
+                "constraint '{}': token masks cannot be expressed in {}",
+                c.name,
+                self.format
+            );
+        }
+
+        let mut rules = RuleSet::new();
+        let mut root: Option<(String, Expr)> = None;
+        for (constraint, label) in ordered.into_iter().zip(&labels) {
+            let prefix = bnf::rule_name(&constraint.name);
+            let parts = constraint
+                .grammar
+                .iter()
+                .map(Part::Grammar)
+                .chain(constraint.json_schema.iter().map(Part::JsonSchema))
+                .chain(constraint.regex_patterns.iter().map(Part::Regex));
+
+            for part in parts {
+                if let Some((winner, _)) = &root {
+                    report.events.push(MergeEvent {
+                        action: MergeAction::Overridden,
+                        target: "root".to_string(),
+                        winner: winner.clone(),
+                        affected: vec![label.clone()],
+                    });
+                    continue;
+                }
+
+                let expr = match part {
+                    Part::Grammar(grammar) => bnf::add_grammar(&mut rules, &prefix, grammar),
+                    Part::JsonSchema(schema) => bnf::add_json_schema(&mut rules, &prefix, schema),
+                    Part::Regex(pattern) => bnf::regex_expr(pattern),
+                }
+                .with_context(|| {
+                    format!(
+                        "constraint '{}': {} cannot be expressed in {}",
+                        constraint.name,
+                        part.label(),
+                        self.format
+                    )
+                })?;
+                root = Some((label.clone(), expr));
+            }
+        }
+
+        // Without constraints any output is allowed
+        let root = root.map(|(_, expr)| expr).unwrap_or(Expr::Repeat {
+            expr: Box::new(Expr::Class {
+                ranges: vec![('\0', char::MAX)],
+                negated: false,
+            }),
+            min: 0,
+            max: None,
+        });
+        rules.add(ROOT_RULE, root);
+
+        Ok((
+            serde_json::json!({
+                "format": self.format,
+                "root": ROOT_RULE,
+                "grammar": rules.render(self.dialect),
+            }),
+            report,
+        ))
+    }
+}
+
+/// A constraint part that can become the grammar root
+#[derive(Clone, Copy)]
+enum Part<'a> {
+    Grammar(&'a Grammar),
+    JsonSchema(&'a JsonSchema),
+    Regex(&'a RegexPattern),
+}
+
+impl Part<'_> {
+    fn label(&self) -> &'static str {
+        match self {
+            Part::Grammar(_) => "grammar",
+            Part::JsonSchema(_) => "JSON schema",
+            Part::Regex(_) => "regex",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::TokenMaskRules;
+
+    fn constraint(name: &str, priority: u32, pattern: &str) -> ConstraintIR {
+        ConstraintIR {
+            name: name.to_string(),
+            json_schema: None,
+            grammar: None,
+            regex_patterns: vec![RegexPattern {
+                pattern: pattern.to_string(),
+                flags: String::new(),
+            }],
+            token_masks: None,
+            type_inhabitation: None,
+            priority,
+            rich_context: None,
+            feasibility_score: 1.0,
+            is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    #[test]
+    fn test_llguidance_compiler_matches_legacy_output() {
+        let constraints = vec![constraint("id", 1, "[a-z]+")];
+        let (schema, _) = ConstraintFormat::Llguidance
+            .compiler()
+            .compile(&constraints)
+            .unwrap();
+        assert_eq!(schema["constraints"][0]["type"], "regex");
+    }
+
+    #[test]
+    fn test_grammar_root_is_highest_priority_constraint() {
+        let constraints = vec![constraint("low", 1, "b+"), constraint("high", 5, "a+")];
+
+        let (gbnf, report) = ConstraintFormat::Gbnf
+            .compiler()
+            .compile(&constraints)
+            .unwrap();
+        assert_eq!(gbnf["format"], "gbnf");
+        assert_eq!(gbnf["grammar"], "root ::= \"a\"+\n");
+
+        let overridden: Vec<_> = report.overridden().collect();
+        assert_eq!(overridden.len(), 1);
+        assert_eq!(overridden[0].winner, "high");
+        assert_eq!(overridden[0].affected, vec!["low"]);
+
+        let (ebnf, _) = ConstraintFormat::Ebnf
+            .compiler()
+            .compile(&constraints)
+            .unwrap();
+        assert_eq!(ebnf["format"], "ebnf");
+        assert_eq!(ebnf["grammar"], "root ::= \"a\"+\n");
+    }
+
+    #[test]
+    fn test_grammar_formats_reject_token_masks() {
+        let mut masked = constraint("masked", 1, "a");
+        masked.token_masks = Some(TokenMaskRules {
+            allowed_tokens: Some(vec![1]),
+            forbidden_tokens: None,
+        });
+        let err = ConstraintFormat::Gbnf
+            .compiler()
+            .compile(&[masked])
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("token masks cannot be expressed in gbnf"));
+    }
+
+    #[test]
+    fn test_empty_constraint_set_allows_anything() {
+        let (gbnf, _) = ConstraintFormat::Gbnf.compiler().compile(&[]).unwrap();
+        assert_eq!(gbnf["grammar"], "root ::= [\\x00-\\U0010FFFF]*\n");
+    }
+}
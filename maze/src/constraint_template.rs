@@ -0,0 +1,397 @@
+//! Parameterized constraint sets
+//!
+//! Constraints often differ only in a value: the table name in a SQL
+//! grammar, or the identifiers a regex allows. A [`ConstraintTemplate`]
+//! writes them once with `{{name}}` placeholders in any string of the IR
+//! (regex patterns, grammar rules, JSON schema property names and values,
+//! `rich_context`) and [`instantiate`](ConstraintTemplate::instantiate)s
+//! them with a parameter map. Values are inserted verbatim; escape them
+//! first (e.g. with `regex::escape`) where they must match literally.
+//!
+//! [`MazeOrchestrator::compile_template`](crate::MazeOrchestrator::compile_template)
+//! keeps the template's compiled skeleton between instantiations, so each
+//! new parameter set recompiles and re-merges only the constraints that
+//! mention a placeholder.
+//!
+//! ```
+//! use maze::constraint_builder::ConstraintBuilder;
+//! use maze::constraint_template::ConstraintTemplate;
+//! use maze::ffi::GrammarRule;
+//! use std::collections::HashMap;
+//!
+//! let rule = GrammarRule {
+//!     lhs: "query".to_string(),
+//!     rhs: vec!["SELECT * FROM".to_string(), "{{table}}".to_string()],
+//! };
+//! let template = ConstraintTemplate::new(
+//!     "select",
+//!     vec![ConstraintBuilder::new("sql").with_grammar(vec![rule], "query")?.build()?],
+//! )?;
+//! let params = HashMap::from([("table".to_string(), "users".to_string())]);
+//! let constraints = template.instantiate(&params)?;
+//! assert_eq!(constraints[0].grammar.as_ref().unwrap().rules[0].rhs[1], "users");
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use crate::constraint_builder::InvalidConstraint;
+use crate::ffi::ConstraintIR;
+use crate::incremental::{IncrementalCompiler, UpdateStats};
+
+/// Opens a placeholder
+const OPEN: &str = "{{";
+
+/// Closes a placeholder
+const CLOSE: &str = "}}";
+
+/// A template cannot be built or instantiated
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TemplateError {
+    /// A `{{` has no matching `}}`, or encloses something other than a name
+    #[error("template '{template}' has a malformed placeholder in {text:?}")]
+    MalformedPlaceholder { template: String, text: String },
+
+    /// The parameter map lacks values for these placeholders, sorted
+    #[error("template '{template}' is missing parameters: {}", .missing.join(", "))]
+    MissingParameters {
+        template: String,
+        missing: Vec<String>,
+    },
+
+    /// The parameter map names placeholders the template does not have,
+    /// sorted
+    #[error("template '{template}' has no parameters named: {}", .unknown.join(", "))]
+    UnknownParameters {
+        template: String,
+        unknown: Vec<String>,
+    },
+
+    /// An instantiated constraint failed validation
+    #[error(transparent)]
+    Invalid(#[from] InvalidConstraint),
+}
+
+/// Constraint IR with named placeholders
+#[derive(Debug, Clone)]
+pub struct ConstraintTemplate {
+    name: String,
+    constraints: Vec<ConstraintIR>,
+    placeholders: BTreeSet<String>,
+
+    /// Compiled skeleton shared by every instantiation, and by clones
+    pub(crate) compiled: Arc<tokio::sync::Mutex<IncrementalCompiler>>,
+}
+
+impl ConstraintTemplate {
+    /// A template named `name` over `constraints`
+    ///
+    /// Fails if a placeholder is malformed. Placeholder names are
+    /// identifiers: letters, digits, and `_`, not starting with a digit.
+    pub fn new(
+        name: impl Into<String>,
+        constraints: Vec<ConstraintIR>,
+    ) -> Result<Self, TemplateError> {
+        let name = name.into();
+        let mut placeholders = BTreeSet::new();
+        let mut collect = |text: &str| -> Result<String, TemplateError> {
+            for placeholder in scan(&name, text)? {
+                placeholders.insert(placeholder.to_string());
+            }
+            Ok(text.to_string())
+        };
+        for constraint in &constraints {
+            map_strings(to_value(constraint), &mut collect)?;
+        }
+
+        Ok(Self {
+            name,
+            constraints,
+            placeholders,
+            compiled: Arc::new(tokio::sync::Mutex::new(IncrementalCompiler::new())),
+        })
+    }
+
+    /// Name of the template
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Placeholder names, sorted
+    pub fn placeholders(&self) -> impl Iterator<Item = &str> {
+        self.placeholders.iter().map(String::as_str)
+    }
+
+    /// Constraints as written, placeholders included
+    pub fn constraints(&self) -> &[ConstraintIR] {
+        &self.constraints
+    }
+
+    /// Whether `constraint` mentions a placeholder, and so changes between
+    /// instantiations
+    pub fn is_parameterized(&self, constraint: &ConstraintIR) -> bool {
+        let mut found = false;
+        let _ = map_strings(to_value(constraint), &mut |text: &str| {
+            found |= text.contains(OPEN);
+            Ok::<_, TemplateError>(text.to_string())
+        });
+        found
+    }
+
+    /// Concrete constraints with every placeholder replaced by its value in
+    /// `params`
+    ///
+    /// `params` must give exactly the template's placeholders: a missing
+    /// or unknown name is an error. Each instantiated constraint must pass
+    /// [`ConstraintIR::validate`].
+    pub fn instantiate(
+        &self,
+        params: &HashMap<String, String>,
+    ) -> Result<Vec<ConstraintIR>, TemplateError> {
+        let missing: Vec<String> = self
+            .placeholders
+            .iter()
+            .filter(|p| !params.contains_key(*p))
+            .cloned()
+            .collect();
+        if !missing.is_empty() {
+            return Err(TemplateError::MissingParameters {
+                template: self.name.clone(),
+                missing,
+            });
+        }
+        let mut unknown: Vec<String> = params
+            .keys()
+            .filter(|p| !self.placeholders.contains(*p))
+            .cloned()
+            .collect();
+        if !unknown.is_empty() {
+            unknown.sort();
+            return Err(TemplateError::UnknownParameters {
+                template: self.name.clone(),
+                unknown,
+            });
+        }
+
+        let mut substitute = |text: &str| substitute(&self.name, text, params);
+        self.constraints
+            .iter()
+            .map(|constraint| {
+                let value = map_strings(to_value(constraint), &mut substitute)?;
+                let instance: ConstraintIR =
+                    serde_json::from_value(value).map_err(|e| InvalidConstraint {
+                        constraint: constraint.name.clone(),
+                        reason: e.to_string(),
+                    })?;
+                instance.validate()?;
+                Ok(instance)
+            })
+            .collect()
+    }
+
+    /// What the last [`compile_template`](crate::MazeOrchestrator::compile_template)
+    /// of this template recompiled and re-merged
+    pub async fn last_update(&self) -> UpdateStats {
+        self.compiled.lock().await.last_update().clone()
+    }
+}
+
+fn to_value(constraint: &ConstraintIR) -> serde_json::Value {
+    serde_json::to_value(constraint).expect("ConstraintIR serializes to JSON")
+}
+
+/// `value` with `f` applied to every string and object key
+fn map_strings<E>(
+    value: serde_json::Value,
+    f: &mut impl FnMut(&str) -> Result<String, E>,
+) -> Result<serde_json::Value, E> {
+    use serde_json::Value;
+
+    Ok(match value {
+        Value::String(text) => Value::String(f(&text)?),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| map_strings(item, f))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| Ok((f(&key)?, map_strings(value, f)?)))
+                .collect::<Result<_, _>>()?,
+        ),
+        other => other,
+    })
+}
+
+/// Placeholder names in `text`, in order
+fn scan<'a>(template: &str, text: &'a str) -> Result<Vec<&'a str>, TemplateError> {
+    let malformed = || TemplateError::MalformedPlaceholder {
+        template: template.to_string(),
+        text: text.to_string(),
+    };
+    let mut names = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(OPEN) {
+        let after = &rest[start + OPEN.len()..];
+        let end = after.find(CLOSE).ok_or_else(malformed)?;
+        let name = after[..end].trim();
+        let identifier = name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !identifier {
+            return Err(malformed());
+        }
+        names.push(name);
+        rest = &after[end + CLOSE.len()..];
+    }
+    Ok(names)
+}
+
+/// `text` with each placeholder replaced by its value in `params`
+fn substitute(
+    template: &str,
+    text: &str,
+    params: &HashMap<String, String>,
+) -> Result<String, TemplateError> {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    for name in scan(template, text)? {
+        let start = rest.find(OPEN).expect("scanned placeholder");
+        let end = start + rest[start..].find(CLOSE).expect("scanned placeholder") + CLOSE.len();
+        out.push_str(&rest[..start]);
+        out.push_str(&params[name]);
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraint_builder::ConstraintBuilder;
+    use crate::ffi::GrammarRule;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn grammar(name: &str, rhs: &[&str]) -> ConstraintIR {
+        let rule = GrammarRule {
+            lhs: "query".to_string(),
+            rhs: rhs.iter().map(|s| s.to_string()).collect(),
+        };
+        ConstraintBuilder::new(name)
+            .with_grammar(vec![rule], "query")
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    fn template() -> ConstraintTemplate {
+        ConstraintTemplate::new(
+            "select",
+            vec![
+                grammar("sql", &["SELECT", "{{ columns }}", "FROM {{table}}"]),
+                ConstraintBuilder::new("row")
+                    .with_json_schema(&serde_json::json!({
+                        "properties": {"{{table}}_id": {"type": "integer"}},
+                        "required": ["{{table}}_id"]
+                    }))
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+                grammar("limit", &["LIMIT 10"]),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_placeholders_are_collected_from_keys_and_values() {
+        let template = template();
+        assert_eq!(
+            template.placeholders().collect::<Vec<_>>(),
+            vec!["columns", "table"]
+        );
+        let parameterized: Vec<bool> = template
+            .constraints()
+            .iter()
+            .map(|c| template.is_parameterized(c))
+            .collect();
+        assert_eq!(parameterized, vec![true, true, false]);
+
+        let constraints = template
+            .instantiate(&params(&[("columns", "id, name"), ("table", "users")]))
+            .unwrap();
+        assert_eq!(
+            constraints[0].grammar.as_ref().unwrap().rules[0].rhs,
+            vec!["SELECT", "id, name", "FROM users"]
+        );
+        let schema = constraints[1].json_schema.as_ref().unwrap();
+        assert!(schema.properties.contains_key("users_id"));
+        assert_eq!(schema.required, vec!["users_id"]);
+    }
+
+    #[test]
+    fn test_parameters_must_match_placeholders() {
+        let template = template();
+        assert_eq!(
+            template
+                .instantiate(&params(&[("table", "users")]))
+                .unwrap_err(),
+            TemplateError::MissingParameters {
+                template: "select".to_string(),
+                missing: vec!["columns".to_string()],
+            }
+        );
+        assert_eq!(
+            template
+                .instantiate(&params(&[
+                    ("columns", "id"),
+                    ("table", "t"),
+                    ("tabel", "t")
+                ]))
+                .unwrap_err(),
+            TemplateError::UnknownParameters {
+                template: "select".to_string(),
+                unknown: vec!["tabel".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_instances_must_be_valid_constraints() {
+        let mut ident = ConstraintBuilder::new("ident")
+            .with_regex("x", "")
+            .unwrap()
+            .build()
+            .unwrap();
+        ident.regex_patterns[0].pattern = "{{first}}[a-z]*".to_string();
+        let template = ConstraintTemplate::new("ident", vec![ident]).unwrap();
+
+        assert!(template.instantiate(&params(&[("first", "[a-z]")])).is_ok());
+        assert!(matches!(
+            template.instantiate(&params(&[("first", "(")])),
+            Err(TemplateError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_malformed_placeholders_are_rejected() {
+        for text in ["{{table", "{{}}", "{{1st}}", "{{two words}}"] {
+            assert!(
+                matches!(
+                    ConstraintTemplate::new("t", vec![grammar("sql", &[text])]),
+                    Err(TemplateError::MalformedPlaceholder { .. })
+                ),
+                "{}",
+                text
+            );
+        }
+    }
+}
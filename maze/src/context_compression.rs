@@ -0,0 +1,317 @@
+//! Compressing retrieved context to fit the snippet budget
+//!
+//! By default, snippets that do not fit the budget are dropped whole (see
+//! [`select_snippets`]). A [`CompressionStrategy`] shrinks them instead, so
+//! a large but relevant file still contributes its signatures:
+//! [`DropLowRelevance`](CompressionStrategy::DropLowRelevance) elides
+//! snippet bodies down to their declaration lines, and
+//! [`Summarize`](CompressionStrategy::Summarize) first asks a model for a
+//! short summary. Snippets are considered most relevant first, and kept
+//! verbatim while they fit, so compression falls on the less relevant ones.
+
+use serde::{Deserialize, Serialize};
+
+use crate::context_provider::{select_snippets, sort_by_relevance, ContextSnippet};
+use crate::context_window::TokenEstimator;
+use crate::inference::InferenceClient;
+use crate::modal_client::InferenceRequest;
+
+/// How retrieved snippets that do not fit the budget are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionStrategy {
+    /// Drop them whole
+    #[default]
+    None,
+
+    /// Elide their bodies, keeping declaration lines; drop those still too
+    /// large
+    DropLowRelevance,
+
+    /// Ask the summarizer model for a summary that fits, falling back to
+    /// [`DropLowRelevance`](Self::DropLowRelevance) if it does not
+    Summarize,
+}
+
+/// What compression did to a request's retrieved context
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContextCompression {
+    /// Strategy applied
+    pub strategy: CompressionStrategy,
+
+    /// Estimated tokens of all retrieved snippets
+    pub original_tokens: usize,
+
+    /// Estimated tokens of the snippets included in the prompt
+    pub compressed_tokens: usize,
+
+    /// Sources of snippets included with their bodies elided
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub elided: Vec<String>,
+
+    /// Sources of snippets included as a model summary
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub summarized: Vec<String>,
+
+    /// Sources of snippets left out
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped: Vec<String>,
+}
+
+/// Line standing in for elided snippet content
+pub const ELISION_MARKER: &str = "...";
+
+/// Line prefixes that introduce a declaration worth keeping when a body is
+/// elided
+const DECLARATION_PREFIXES: &[&str] = &[
+    "pub ",
+    "fn ",
+    "async ",
+    "struct ",
+    "enum ",
+    "trait ",
+    "impl",
+    "type ",
+    "mod ",
+    "use ",
+    "const ",
+    "static ",
+    "def ",
+    "class ",
+    "import ",
+    "from ",
+    "export ",
+    "function ",
+    "interface ",
+    "func ",
+    "package ",
+    "#include",
+];
+
+/// `content` reduced to its declaration lines, with each run of other lines
+/// replaced by an indented [`ELISION_MARKER`]
+///
+/// `None` when there is nothing to elide or no declaration to keep.
+pub fn elide(content: &str) -> Option<String> {
+    let mut lines = Vec::new();
+    let mut kept = 0;
+    let mut in_elision = false;
+    for line in content.lines() {
+        let trimmed = line.trim_start();
+        if DECLARATION_PREFIXES.iter().any(|p| trimmed.starts_with(p)) {
+            lines.push(line.to_string());
+            kept += 1;
+            in_elision = false;
+        } else if !trimmed.is_empty() && !in_elision {
+            let indent = &line[..line.len() - trimmed.len()];
+            lines.push(format!("{}{}", indent, ELISION_MARKER));
+            in_elision = true;
+        }
+    }
+
+    let elided = lines.join("\n");
+    (kept > 0 && elided.len() < content.len()).then_some(elided)
+}
+
+/// Fit `snippets` into `budget` tokens according to `strategy`
+///
+/// Under [`CompressionStrategy::None`] this is [`select_snippets`] and no
+/// report is returned. Otherwise each snippet, most relevant first, is kept
+/// verbatim if it fits, else summarized by `summarizer` (under
+/// [`Summarize`](CompressionStrategy::Summarize)), else elided, else
+/// dropped. A failed summary call is logged and falls back to elision.
+pub async fn compress_snippets(
+    mut snippets: Vec<ContextSnippet>,
+    budget: usize,
+    strategy: CompressionStrategy,
+    estimator: &dyn TokenEstimator,
+    summarizer: &dyn InferenceClient,
+) -> (Vec<ContextSnippet>, Option<ContextCompression>) {
+    if strategy == CompressionStrategy::None {
+        return (select_snippets(snippets, budget, estimator), None);
+    }
+
+    sort_by_relevance(&mut snippets);
+    let mut report = ContextCompression {
+        strategy,
+        original_tokens: snippets
+            .iter()
+            .map(|s| estimator.estimate(&s.content))
+            .sum(),
+        compressed_tokens: 0,
+        elided: Vec::new(),
+        summarized: Vec::new(),
+        dropped: Vec::new(),
+    };
+
+    let mut remaining = budget;
+    let mut kept = Vec::new();
+    for mut snippet in snippets {
+        let cost = estimator.estimate(&snippet.content);
+        if cost > remaining {
+            let summary = match strategy {
+                CompressionStrategy::Summarize if remaining > 0 => {
+                    summarize(summarizer, &snippet, remaining)
+                        .await
+                        .unwrap_or_else(|e| {
+                            tracing::warn!(
+                                "Failed to summarize context snippet {}: {:#}",
+                                snippet.source,
+                                e
+                            );
+                            None
+                        })
+                }
+                _ => None,
+            };
+
+            let fits = |text: &String| estimator.estimate(text) <= remaining;
+            if let Some(summary) = summary.filter(fits) {
+                report.summarized.push(snippet.source.clone());
+                snippet.content = summary;
+            } else if let Some(elided) = elide(&snippet.content).filter(fits) {
+                report.elided.push(snippet.source.clone());
+                snippet.content = elided;
+            } else {
+                report.dropped.push(snippet.source);
+                continue;
+            }
+        }
+
+        remaining -= estimator.estimate(&snippet.content);
+        kept.push(snippet);
+    }
+
+    report.compressed_tokens = budget - remaining;
+    (kept, Some(report))
+}
+
+/// Ask `summarizer` for a summary of `snippet` of at most `max_tokens`
+async fn summarize(
+    summarizer: &dyn InferenceClient,
+    snippet: &ContextSnippet,
+    max_tokens: usize,
+) -> anyhow::Result<Option<String>> {
+    let request = InferenceRequest {
+        prompt: format!(
+            "Summarize the following code context from {} for a code generation \
+             prompt. Keep signatures, type definitions, and names; omit \
+             implementation details.\n\n{}",
+            snippet.source, snippet.content
+        ),
+        constraints: serde_json::json!({}),
+        max_tokens,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+    let summary = summarizer
+        .generate_constrained(request)
+        .await?
+        .generated_text;
+    let summary = summary.trim();
+    Ok((!summary.is_empty()).then(|| summary.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context_window::CharRatioEstimator;
+    use crate::MockInferenceClient;
+
+    const USER_RS: &str = "pub struct User {\n    name: String,\n}\n\nimpl User {\n    pub fn greet(&self) -> String {\n        format!(\"hello {}\", self.name)\n    }\n}";
+
+    fn snippet(source: &str, content: &str, relevance: f32) -> ContextSnippet {
+        ContextSnippet {
+            source: source.to_string(),
+            content: content.to_string(),
+            relevance,
+        }
+    }
+
+    fn estimator() -> CharRatioEstimator {
+        CharRatioEstimator {
+            chars_per_token: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_elide_keeps_declarations() {
+        assert_eq!(
+            elide(USER_RS).unwrap(),
+            "pub struct User {\n    ...\nimpl User {\n    pub fn greet(&self) -> String {\n        ..."
+        );
+        assert_eq!(elide("let x = 1;\nx + 1"), None);
+        assert_eq!(elide("pub fn a();"), None);
+    }
+
+    #[tokio::test]
+    async fn test_drop_low_relevance_elides_then_drops() {
+        let snippets = vec![
+            snippet("noise.txt", &"x".repeat(80), 0.1),
+            snippet("user.rs", USER_RS, 0.5),
+            snippet("main.rs", "fn main() {}", 0.9),
+        ];
+        let budget = 12 + elide(USER_RS).unwrap().len();
+        let client = MockInferenceClient::new("mock");
+
+        let (kept, report) = compress_snippets(
+            snippets,
+            budget,
+            CompressionStrategy::DropLowRelevance,
+            &estimator(),
+            &client,
+        )
+        .await;
+
+        let sources: Vec<&str> = kept.iter().map(|s| s.source.as_str()).collect();
+        assert_eq!(sources, vec!["main.rs", "user.rs"]);
+        assert_eq!(kept[1].content, elide(USER_RS).unwrap());
+        let report = report.unwrap();
+        assert_eq!(report.original_tokens, 92 + USER_RS.len());
+        assert_eq!(report.compressed_tokens, budget);
+        assert_eq!(report.elided, vec!["user.rs"]);
+        assert_eq!(report.dropped, vec!["noise.txt"]);
+        assert!(client.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_summarize_asks_the_summarizer() {
+        let client = MockInferenceClient::new("cheap").then_respond("User has a name and greets.");
+        let (kept, report) = compress_snippets(
+            vec![snippet("user.rs", USER_RS, 0.5)],
+            40,
+            CompressionStrategy::Summarize,
+            &estimator(),
+            &client,
+        )
+        .await;
+
+        assert_eq!(kept[0].content, "User has a name and greets.");
+        let report = report.unwrap();
+        assert_eq!(report.summarized, vec!["user.rs"]);
+        assert_eq!(report.compressed_tokens, 27);
+        let requests = client.requests();
+        assert_eq!(requests[0].max_tokens, 40);
+        assert!(requests[0].prompt.contains("pub struct User"));
+    }
+
+    #[tokio::test]
+    async fn test_none_only_selects() {
+        let client = MockInferenceClient::new("mock");
+        let (kept, report) = compress_snippets(
+            vec![snippet("user.rs", USER_RS, 0.5)],
+            10,
+            CompressionStrategy::None,
+            &estimator(),
+            &client,
+        )
+        .await;
+        assert!(kept.is_empty());
+        assert!(report.is_none());
+    }
+}
@@ -0,0 +1,123 @@
+//! Pluggable retrieval of project context for prompts
+//!
+//! A [`ContextProvider`] supplies snippets such as imports, type definitions,
+//! or neighboring functions for a request's [`GenerationContext`]. The
+//! orchestrator calls it before assembling the prompt, keeps the most
+//! relevant snippets that fit the context budget (compressing the rest, if
+//! a [`CompressionStrategy`](crate::CompressionStrategy) is configured), and
+//! hands them to the [`PromptBuilder`](crate::PromptBuilder).
+//!
+//! Retrieval itself (RAG, an LSP-backed symbol index, ...) lives outside this
+//! crate; implement the trait to plug it in.
+
+use serde::{Deserialize, Serialize};
+
+use crate::context_window::TokenEstimator;
+use crate::GenerationContext;
+
+/// A piece of retrieved context
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContextSnippet {
+    /// Where the snippet came from, e.g. `src/auth.rs:12-40` or `lsp:User`
+    pub source: String,
+
+    /// Snippet text as it should appear in the prompt
+    pub content: String,
+
+    /// Relevance to the request; higher is more relevant
+    pub relevance: f32,
+}
+
+/// Supplies context snippets for a generation request
+pub trait ContextProvider: Send + Sync {
+    /// Fetch snippets relevant to `context`
+    fn fetch(&self, context: &GenerationContext) -> Vec<ContextSnippet>;
+}
+
+/// Provider that never returns any snippets
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopContextProvider;
+
+impl ContextProvider for NoopContextProvider {
+    fn fetch(&self, _context: &GenerationContext) -> Vec<ContextSnippet> {
+        Vec::new()
+    }
+}
+
+/// Keep the most relevant snippets that fit in `budget` tokens
+///
+/// Snippets are ordered by descending relevance (ties by source) and added
+/// greedily; a snippet that does not fit is skipped so smaller, less
+/// relevant ones can still be used.
+pub fn select_snippets(
+    mut snippets: Vec<ContextSnippet>,
+    budget: usize,
+    estimator: &dyn TokenEstimator,
+) -> Vec<ContextSnippet> {
+    sort_by_relevance(&mut snippets);
+
+    let mut remaining = budget;
+    snippets
+        .into_iter()
+        .filter(|snippet| {
+            let cost = estimator.estimate(&snippet.content);
+            if cost <= remaining {
+                remaining -= cost;
+                true
+            } else {
+                false
+            }
+        })
+        .collect()
+}
+
+/// Order snippets by descending relevance, ties by source
+pub(crate) fn sort_by_relevance(snippets: &mut [ContextSnippet]) {
+    snippets.sort_by(|a, b| {
+        b.relevance
+            .total_cmp(&a.relevance)
+            .then_with(|| a.source.cmp(&b.source))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context_window::CharRatioEstimator;
+
+    fn snippet(source: &str, len: usize, relevance: f32) -> ContextSnippet {
+        ContextSnippet {
+            source: source.to_string(),
+            content: "x".repeat(len),
+            relevance,
+        }
+    }
+
+    #[test]
+    fn test_select_snippets_orders_and_trims() {
+        let estimator = CharRatioEstimator {
+            chars_per_token: 1.0,
+        };
+        let snippets = vec![
+            snippet("low", 5, 0.1),
+            snippet("high", 10, 0.9),
+            snippet("big", 50, 0.5),
+            snippet("mid", 10, 0.5),
+        ];
+
+        let selected = select_snippets(snippets, 25, &estimator);
+        let sources: Vec<&str> = selected.iter().map(|s| s.source.as_str()).collect();
+        assert_eq!(sources, vec!["high", "mid", "low"]);
+    }
+
+    #[test]
+    fn test_noop_provider() {
+        let context = GenerationContext {
+            current_file: None,
+            language: None,
+            project_root: None,
+            metadata: Default::default(),
+        };
+        assert!(NoopContextProvider.fetch(&context).is_empty());
+    }
+}
@@ -0,0 +1,488 @@
+//! Context-window budgeting for generation requests
+//!
+//! Estimates how many tokens a request needs (prompt + context + requested
+//! output) and either rejects requests that cannot fit the model's context
+//! window or trims the least important context until they do.
+//!
+//! Counts come from a [`TokenEstimator`]. The default [`CharRatioEstimator`]
+//! needs nothing but is only approximate; with the `tokenizers` feature,
+//! [`HfTokenizer`] counts exactly with the model's own `tokenizer.json`.
+//! Setting `MazeConfig::tokenizer_path` or `RefinementConfig::tokenizer_path`
+//! loads one through [`load_tokenizer`].
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::GenerationRequest;
+
+/// Estimates token counts for text
+///
+/// The default [`CharRatioEstimator`] is a rough heuristic; implement this
+/// trait around a real tokenizer for accurate budgeting. Only
+/// [`estimate`](Self::estimate) is required; tokenizers that can also map
+/// text to token IDs and back implement [`encode`](Self::encode) and
+/// [`decode`](Self::decode).
+pub trait TokenEstimator: Send + Sync {
+    /// Estimated number of tokens in `text`
+    fn estimate(&self, text: &str) -> usize;
+
+    /// Token IDs for `text`, if this estimator has a vocabulary
+    fn encode(&self, _text: &str) -> Option<Vec<u32>> {
+        None
+    }
+
+    /// Text for token `ids`, if this estimator has a vocabulary
+    fn decode(&self, _ids: &[u32]) -> Option<String> {
+        None
+    }
+}
+
+/// Tokens to bill for `text`: the count the service `reported`, or an
+/// estimate if it reported none
+pub fn billed_tokens(reported: usize, text: &str, estimator: &dyn TokenEstimator) -> usize {
+    if reported == 0 && !text.is_empty() {
+        estimator.estimate(text)
+    } else {
+        reported
+    }
+}
+
+/// Estimates tokens as a fixed number of characters per token
+#[derive(Debug, Clone)]
+pub struct CharRatioEstimator {
+    /// Average characters per token
+    pub chars_per_token: f32,
+}
+
+impl Default for CharRatioEstimator {
+    fn default() -> Self {
+        Self {
+            chars_per_token: 4.0,
+        }
+    }
+}
+
+impl TokenEstimator for CharRatioEstimator {
+    fn estimate(&self, text: &str) -> usize {
+        (text.chars().count() as f32 / self.chars_per_token).ceil() as usize
+    }
+}
+
+/// Estimator for the `tokenizer.json` at `path`, for the configured
+/// `tokenizer_path`
+///
+/// An [`HfTokenizer`]; without the `tokenizers` feature this fails rather
+/// than falling back to the heuristic the path was set to replace.
+pub fn load_tokenizer(path: &Path) -> anyhow::Result<Arc<dyn TokenEstimator>> {
+    #[cfg(feature = "tokenizers")]
+    {
+        Ok(Arc::new(HfTokenizer::from_file(path)?))
+    }
+    #[cfg(not(feature = "tokenizers"))]
+    {
+        anyhow::bail!(
+            "Loading tokenizer {} requires the `tokenizers` feature",
+            path.display()
+        )
+    }
+}
+
+/// Exact token counts from a Hugging Face `tokenizer.json`
+///
+/// Requires the `tokenizers` feature. Load the file shipped with the model
+/// the orchestrator or refiner calls; counts from another model's
+/// vocabulary are no better than the heuristic.
+#[cfg(feature = "tokenizers")]
+pub struct HfTokenizer {
+    tokenizer: tokenizers::Tokenizer,
+}
+
+#[cfg(feature = "tokenizers")]
+impl HfTokenizer {
+    /// Load the tokenizer described by the `tokenizer.json` at `path`
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let tokenizer = tokenizers::Tokenizer::from_file(path)
+            .map_err(|e| anyhow::anyhow!("Failed to load tokenizer {}: {}", path.display(), e))?;
+        Ok(Self { tokenizer })
+    }
+}
+
+#[cfg(feature = "tokenizers")]
+impl TokenEstimator for HfTokenizer {
+    /// Exact count, or the [`CharRatioEstimator`] estimate if the text
+    /// cannot be encoded
+    fn estimate(&self, text: &str) -> usize {
+        self.encode(text)
+            .map(|ids| ids.len())
+            .unwrap_or_else(|| CharRatioEstimator::default().estimate(text))
+    }
+
+    fn encode(&self, text: &str) -> Option<Vec<u32>> {
+        match self.tokenizer.encode(text, false) {
+            Ok(encoding) => Some(encoding.get_ids().to_vec()),
+            Err(e) => {
+                tracing::warn!("Failed to tokenize text: {}", e);
+                None
+            }
+        }
+    }
+
+    fn decode(&self, ids: &[u32]) -> Option<String> {
+        self.tokenizer.decode(ids, false).ok()
+    }
+}
+
+/// What to do when a request does not fit the context window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextOverflowPolicy {
+    /// Fail with a [`ContextOverflow`] error
+    #[default]
+    Reject,
+
+    /// Trim context in `truncation_order` until the request fits
+    Truncate,
+}
+
+/// Part of a request that may be trimmed to fit the context window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextSection {
+    /// `GenerationContext::metadata` entries, largest first
+    Metadata,
+
+    /// Leading lines of the prompt, i.e. the oldest file content
+    PromptPrefix,
+
+    /// `GenerationRequest::history` turns, oldest first
+    History,
+}
+
+/// Default truncation order: drop the oldest conversation turns first, then
+/// metadata, then older prompt content
+pub fn default_truncation_order() -> Vec<ContextSection> {
+    vec![
+        ContextSection::History,
+        ContextSection::Metadata,
+        ContextSection::PromptPrefix,
+    ]
+}
+
+/// A request needs more tokens than the model's context window allows
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "request needs an estimated {estimated_tokens} tokens but model {model} allows {allowed_tokens}"
+)]
+pub struct ContextOverflow {
+    /// Model whose context window was exceeded
+    pub model: String,
+
+    /// Estimated prompt + context + max_tokens
+    pub estimated_tokens: usize,
+
+    /// The model's context window
+    pub allowed_tokens: usize,
+}
+
+/// Estimated tokens needed by a request, including requested output
+pub fn estimate_request_tokens(
+    request: &GenerationRequest,
+    estimator: &dyn TokenEstimator,
+) -> usize {
+    let context_tokens = request
+        .context
+        .as_ref()
+        .and_then(|c| serde_json::to_string(c).ok())
+        .map(|json| estimator.estimate(&json))
+        .unwrap_or(0);
+
+    let history_tokens: usize = request
+        .history
+        .iter()
+        .map(|turn| estimator.estimate(&turn.prompt) + estimator.estimate(&turn.response))
+        .sum();
+
+    estimator.estimate(&request.prompt) + context_tokens + history_tokens + request.max_tokens
+}
+
+/// Fit a request into `context_window` tokens according to `policy`
+///
+/// Returns the (possibly trimmed) request, or a [`ContextOverflow`] if it
+/// cannot be made to fit.
+pub fn fit_request(
+    mut request: GenerationRequest,
+    model: &str,
+    context_window: usize,
+    policy: ContextOverflowPolicy,
+    truncation_order: &[ContextSection],
+    estimator: &dyn TokenEstimator,
+) -> Result<GenerationRequest, ContextOverflow> {
+    let estimated = estimate_request_tokens(&request, estimator);
+    let overflow = |estimated_tokens| ContextOverflow {
+        model: model.to_string(),
+        estimated_tokens,
+        allowed_tokens: context_window,
+    };
+
+    if estimated <= context_window {
+        return Ok(request);
+    }
+    if policy == ContextOverflowPolicy::Reject {
+        return Err(overflow(estimated));
+    }
+
+    let fits = |r: &GenerationRequest| estimate_request_tokens(r, estimator) <= context_window;
+
+    for section in truncation_order {
+        match section {
+            ContextSection::Metadata => {
+                let Some(ref mut context) = request.context else {
+                    continue;
+                };
+
+                // Largest entries first; key order breaks ties
+                let mut entries: Vec<(String, usize)> = context
+                    .metadata
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.to_string().len()))
+                    .collect();
+                entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+                for (key, _) in entries {
+                    if let Some(ref mut context) = request.context {
+                        context.metadata.remove(&key);
+                    }
+                    if fits(&request) {
+                        break;
+                    }
+                }
+            }
+            ContextSection::History => {
+                // Later turns carry the state the current prompt refines
+                while !request.history.is_empty() && !fits(&request) {
+                    request.history.remove(0);
+                }
+            }
+            ContextSection::PromptPrefix => {
+                // Keep the last line: it usually carries the actual instruction
+                while !fits(&request) {
+                    match request.prompt.find('\n') {
+                        Some(idx) if idx + 1 < request.prompt.len() => {
+                            request.prompt.drain(..=idx);
+                        }
+                        _ => break,
+                    }
+                }
+            }
+        }
+
+        if fits(&request) {
+            tracing::warn!(
+                "Truncated request context to fit {} tokens (was {})",
+                context_window,
+                estimated
+            );
+            return Ok(request);
+        }
+    }
+
+    Err(overflow(estimate_request_tokens(&request, estimator)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConversationTurn, GenerationContext};
+    use std::collections::HashMap;
+
+    /// One token per character, for easy arithmetic
+    struct CharEstimator;
+
+    impl TokenEstimator for CharEstimator {
+        fn estimate(&self, text: &str) -> usize {
+            text.len()
+        }
+    }
+
+    fn request(prompt: &str, metadata: HashMap<String, serde_json::Value>) -> GenerationRequest {
+        GenerationRequest {
+            prompt: prompt.to_string(),
+            constraints_ir: vec![],
+            max_tokens: 10,
+            temperature: 0.7,
+            context: Some(GenerationContext {
+                current_file: None,
+                language: None,
+                project_root: None,
+                metadata,
+            }),
+            seed: None,
+            timeout_ms: None,
+            history: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_char_ratio_estimator() {
+        let estimator = CharRatioEstimator::default();
+        assert_eq!(estimator.estimate(""), 0);
+        assert_eq!(estimator.estimate("abcd"), 1);
+        assert_eq!(estimator.estimate("abcde"), 2);
+    }
+
+    #[test]
+    fn test_billed_tokens_estimates_only_unreported_counts() {
+        assert_eq!(billed_tokens(3, "abcdefgh", &CharEstimator), 3);
+        assert_eq!(billed_tokens(0, "abcdefgh", &CharEstimator), 8);
+        assert_eq!(billed_tokens(0, "", &CharEstimator), 0);
+        assert!(CharRatioEstimator::default().encode("abc").is_none());
+    }
+
+    #[cfg(feature = "tokenizers")]
+    #[test]
+    fn test_hf_tokenizer_counts_with_vocabulary() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tokenizer.json");
+        let tokenizer = serde_json::json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": {"type": "Whitespace"},
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": {"[UNK]": 0, "let": 1, "x": 2, "=": 3, "1": 4, ";": 5},
+                "unk_token": "[UNK]"
+            }
+        });
+        std::fs::write(&path, tokenizer.to_string()).unwrap();
+
+        let tokenizer = HfTokenizer::from_file(&path).unwrap();
+        assert_eq!(tokenizer.estimate("let x = 1;"), 5);
+        assert_eq!(tokenizer.encode("let x"), Some(vec![1, 2]));
+        assert_eq!(tokenizer.decode(&[1, 2]).as_deref(), Some("let x"));
+        assert!(HfTokenizer::from_file(dir.path().join("missing.json")).is_err());
+        assert_eq!(load_tokenizer(&path).unwrap().estimate("let x = 1;"), 5);
+    }
+
+    #[cfg(not(feature = "tokenizers"))]
+    #[test]
+    fn test_load_tokenizer_needs_the_feature() {
+        let err = load_tokenizer(Path::new("tokenizer.json")).err().unwrap();
+        assert!(err.to_string().contains("`tokenizers` feature"), "{}", err);
+    }
+
+    #[test]
+    fn test_reject_reports_estimate_and_limit() {
+        let req = request("x".repeat(100).as_str(), HashMap::new());
+        let needed = estimate_request_tokens(&req, &CharEstimator);
+
+        let err = fit_request(
+            req,
+            "small",
+            50,
+            ContextOverflowPolicy::Reject,
+            &default_truncation_order(),
+            &CharEstimator,
+        )
+        .unwrap_err();
+        assert_eq!(err.estimated_tokens, needed);
+        assert_eq!(err.allowed_tokens, 50);
+    }
+
+    #[test]
+    fn test_truncate_drops_metadata_before_prompt() {
+        let metadata = HashMap::from([
+            ("big".to_string(), serde_json::json!("y".repeat(200))),
+            ("small".to_string(), serde_json::json!("z")),
+        ]);
+        let req = request("old line\nnew line", metadata);
+        let without_big = {
+            let mut r = req.clone();
+            r.context.as_mut().unwrap().metadata.remove("big");
+            estimate_request_tokens(&r, &CharEstimator)
+        };
+
+        let fitted = fit_request(
+            req,
+            "small",
+            without_big,
+            ContextOverflowPolicy::Truncate,
+            &default_truncation_order(),
+            &CharEstimator,
+        )
+        .unwrap();
+        let metadata = &fitted.context.unwrap().metadata;
+        assert!(!metadata.contains_key("big"));
+        assert!(metadata.contains_key("small"));
+        assert_eq!(fitted.prompt, "old line\nnew line");
+    }
+
+    #[test]
+    fn test_truncate_drops_oldest_history_turns_first() {
+        let turn = |prompt: &str| ConversationTurn {
+            prompt: prompt.to_string(),
+            response: "y".repeat(50),
+        };
+        let req = GenerationRequest {
+            history: vec![turn("first"), turn("second"), turn("third")],
+            ..request(
+                "now",
+                HashMap::from([("k".to_string(), serde_json::json!("v"))]),
+            )
+        };
+        let last_turn_only = {
+            let mut r = req.clone();
+            r.history.drain(..2);
+            estimate_request_tokens(&r, &CharEstimator)
+        };
+
+        let fitted = fit_request(
+            req,
+            "small",
+            last_turn_only,
+            ContextOverflowPolicy::Truncate,
+            &default_truncation_order(),
+            &CharEstimator,
+        )
+        .unwrap();
+        assert_eq!(fitted.history, vec![turn("third")]);
+        assert!(fitted.context.unwrap().metadata.contains_key("k"));
+        assert_eq!(fitted.prompt, "now");
+    }
+
+    #[test]
+    fn test_truncate_trims_oldest_prompt_lines() {
+        let req = GenerationRequest {
+            context: None,
+            ..request("first\nsecond\nthird", HashMap::new())
+        };
+
+        let fitted = fit_request(
+            req.clone(),
+            "small",
+            "third".len() + req.max_tokens,
+            ContextOverflowPolicy::Truncate,
+            &[ContextSection::PromptPrefix],
+            &CharEstimator,
+        )
+        .unwrap();
+        assert_eq!(fitted.prompt, "third");
+
+        // Cannot fit even the last line
+        assert!(fit_request(
+            req,
+            "small",
+            3,
+            ContextOverflowPolicy::Truncate,
+            &default_truncation_order(),
+            &CharEstimator,
+        )
+        .is_err());
+    }
+}
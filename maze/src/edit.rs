@@ -0,0 +1,522 @@
+//! Edit-style generation output
+//!
+//! For in-editor refactors, regenerating a whole file is wasteful and risks
+//! unrelated churn. In edit mode the model is asked for SEARCH/REPLACE
+//! blocks against the current file:
+//!
+//! ```text
+//! <<<<<<< SEARCH
+//! fn add(a: i32, b: i32) -> i32 {
+//! =======
+//! fn add(a: i64, b: i64) -> i64 {
+//! >>>>>>> REPLACE
+//! ```
+//!
+//! [`parse_patch`] reads the blocks, [`resolve_edits`] locates each SEARCH
+//! section in the original (it must match whole lines, exactly once), and
+//! [`apply_edits`] produces the edited file. Any failure is an [`EditError`],
+//! so a patch is either applied completely or not at all.
+//!
+//! To redo one region of earlier output, such as a function body,
+//! [`MazeOrchestrator::regenerate_span`](crate::MazeOrchestrator::regenerate_span)
+//! instead shows the model the code around the span, marked with
+//! [`SPAN_MARKER`], and splices its answer in place of the span alone.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ffi::{ConstraintIR, RegexPattern};
+
+/// Opens the SEARCH section of a block
+pub const SEARCH_MARKER: &str = "<<<<<<< SEARCH";
+
+/// Separates the SEARCH and REPLACE sections
+pub const DIVIDER_MARKER: &str = "=======";
+
+/// Closes a block
+pub const REPLACE_MARKER: &str = ">>>>>>> REPLACE";
+
+/// Marks where the regenerated span goes in a [`span_prompt`]
+pub const SPAN_MARKER: &str = "<SPAN>";
+
+/// Name of the constraint produced by [`edit_constraint`]
+pub const EDIT_CONSTRAINT_NAME: &str = "edit_blocks";
+
+/// A patch that cannot be parsed or applied
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum EditError {
+    /// The patch text is not a sequence of SEARCH/REPLACE blocks
+    #[error("malformed patch at line {line}: {message}")]
+    Malformed { line: usize, message: String },
+
+    /// The patch has no blocks
+    #[error("patch contains no edits")]
+    Empty,
+
+    /// A SEARCH section does not occur in the original
+    #[error("edit {block}: SEARCH text not found in the original")]
+    NotFound { block: usize },
+
+    /// A SEARCH section occurs more than once in the original
+    #[error("edit {block}: SEARCH text matches {matches} places in the original")]
+    Ambiguous { block: usize, matches: usize },
+
+    /// Two edits touch the same text
+    #[error("edits {first} and {second} overlap")]
+    Overlapping { first: usize, second: usize },
+
+    /// A span to regenerate is not a range of whole characters in the code
+    #[error("span {start}..{end} is not within the {len}-byte code on character boundaries")]
+    InvalidSpan {
+        start: usize,
+        end: usize,
+        len: usize,
+    },
+}
+
+/// One parsed SEARCH/REPLACE block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchReplace {
+    /// Lines to find, joined with `\n`
+    pub search: String,
+
+    /// Lines to put in their place, joined with `\n`
+    pub replace: String,
+}
+
+/// A located edit: replace `start..end` of the original with `replacement`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextEdit {
+    /// Byte offset where the replaced text starts
+    pub start: usize,
+
+    /// Byte offset where the replaced text ends (exclusive)
+    pub end: usize,
+
+    /// 1-based first line of the replaced text
+    pub start_line: usize,
+
+    /// 1-based last line of the replaced text
+    pub end_line: usize,
+
+    /// Text to insert
+    pub replacement: String,
+}
+
+/// Span of earlier output replaced by
+/// [`MazeOrchestrator::regenerate_span`](crate::MazeOrchestrator::regenerate_span),
+/// recorded in [`Provenance::regenerated_span`](crate::Provenance::regenerated_span)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegeneratedSpan {
+    /// Byte offset where the span starts, in both the previous and new code
+    pub start: usize,
+
+    /// Byte offset where the span ended in the previous code (exclusive)
+    pub previous_end: usize,
+
+    /// Byte offset where the replacement ends in the new code (exclusive)
+    pub end: usize,
+
+    /// Model that generated the previous code around the span
+    pub previous_model: String,
+}
+
+/// Result of [`MazeOrchestrator::generate_edit`](crate::MazeOrchestrator::generate_edit)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditResponse {
+    /// Edits in file order
+    pub edits: Vec<TextEdit>,
+
+    /// The original with `edits` applied
+    pub code: String,
+
+    /// The underlying generation; its `code` is the raw patch text
+    pub generation: crate::GenerationResponse,
+}
+
+/// Parse SEARCH/REPLACE blocks; blank lines between blocks are ignored
+pub fn parse_patch(patch: &str) -> Result<Vec<SearchReplace>, EditError> {
+    enum State {
+        Outside,
+        Search(Vec<String>),
+        Replace(Vec<String>, Vec<String>),
+    }
+
+    let malformed = |line: usize, message: &str| EditError::Malformed {
+        line,
+        message: message.to_string(),
+    };
+
+    let mut blocks = Vec::new();
+    let mut state = State::Outside;
+    let mut last_line = 0;
+    for (idx, line) in patch.lines().enumerate() {
+        let number = idx + 1;
+        last_line = number;
+        let marker = line.trim_end();
+        state = match state {
+            State::Outside if marker.is_empty() => State::Outside,
+            State::Outside if marker == SEARCH_MARKER => State::Search(Vec::new()),
+            State::Outside => return Err(malformed(number, "expected SEARCH marker")),
+            State::Search(search) if marker == DIVIDER_MARKER => State::Replace(search, Vec::new()),
+            State::Search(_) if marker == SEARCH_MARKER || marker == REPLACE_MARKER => {
+                return Err(malformed(number, "expected divider"));
+            }
+            State::Search(mut search) => {
+                search.push(line.to_string());
+                State::Search(search)
+            }
+            State::Replace(search, replace) if marker == REPLACE_MARKER => {
+                blocks.push(SearchReplace {
+                    search: search.join("\n"),
+                    replace: replace.join("\n"),
+                });
+                State::Outside
+            }
+            State::Replace(_, _) if marker == SEARCH_MARKER || marker == DIVIDER_MARKER => {
+                return Err(malformed(number, "expected REPLACE marker"));
+            }
+            State::Replace(search, mut replace) => {
+                replace.push(line.to_string());
+                State::Replace(search, replace)
+            }
+        };
+    }
+
+    if !matches!(state, State::Outside) {
+        return Err(malformed(last_line, "unterminated block"));
+    }
+    if blocks.is_empty() {
+        return Err(EditError::Empty);
+    }
+    Ok(blocks)
+}
+
+/// Locate each block in `original`, returning edits sorted by position
+///
+/// A SEARCH section must match whole lines of the original exactly once;
+/// overlapping occurrences count separately. An empty SEARCH section is
+/// only accepted for an empty original. Blocks are written with `\n` line
+/// endings; if the original uses `\r\n`, they are matched and replaced
+/// with `\r\n` instead, so a file mixing the two may not match.
+pub fn resolve_edits(original: &str, blocks: &[SearchReplace]) -> Result<Vec<TextEdit>, EditError> {
+    let crlf = original.contains("\r\n");
+    let mut located: Vec<(usize, TextEdit)> = Vec::with_capacity(blocks.len());
+    for (idx, block) in blocks.iter().enumerate() {
+        let number = idx + 1;
+        let (search, replace) = if crlf {
+            (
+                block.search.replace('\n', "\r\n"),
+                block.replace.replace('\n', "\r\n"),
+            )
+        } else {
+            (block.search.clone(), block.replace.clone())
+        };
+        let starts: Vec<usize> = if search.is_empty() {
+            if original.is_empty() {
+                vec![0]
+            } else {
+                vec![]
+            }
+        } else {
+            // Try every line start, so a partial match cannot hide a whole
+            // line one and overlapping matches are all found
+            std::iter::once(0)
+                .chain(original.match_indices('\n').map(|(at, _)| at + 1))
+                .filter(|&start| {
+                    original[start..]
+                        .strip_prefix(search.as_str())
+                        .is_some_and(|rest| {
+                            rest.is_empty() || rest.starts_with('\n') || rest.starts_with("\r\n")
+                        })
+                })
+                .collect()
+        };
+
+        let start = match starts.as_slice() {
+            [] => return Err(EditError::NotFound { block: number }),
+            [start] => *start,
+            _ => {
+                return Err(EditError::Ambiguous {
+                    block: number,
+                    matches: starts.len(),
+                })
+            }
+        };
+        let end = start + search.len();
+        let start_line = original[..start].matches('\n').count() + 1;
+        located.push((
+            number,
+            TextEdit {
+                start,
+                end,
+                start_line,
+                end_line: start_line + search.matches('\n').count(),
+                replacement: replace,
+            },
+        ));
+    }
+
+    located.sort_by_key(|(_, edit)| edit.start);
+    for pair in located.windows(2) {
+        let ((first, a), (second, b)) = (&pair[0], &pair[1]);
+        if a.end > b.start || (a.start == b.start && a.end == b.end) {
+            return Err(EditError::Overlapping {
+                first: *first.min(second),
+                second: *first.max(second),
+            });
+        }
+    }
+    Ok(located.into_iter().map(|(_, edit)| edit).collect())
+}
+
+/// Apply sorted, non-overlapping `edits` to `original`
+pub fn apply_edits(original: &str, edits: &[TextEdit]) -> Result<String, EditError> {
+    let mut code = String::with_capacity(original.len());
+    let mut cursor = 0;
+    for (idx, edit) in edits.iter().enumerate() {
+        if edit.start < cursor || edit.end > original.len() || edit.start > edit.end {
+            return Err(EditError::Overlapping {
+                first: idx,
+                second: idx + 1,
+            });
+        }
+        code.push_str(&original[cursor..edit.start]);
+        code.push_str(&edit.replacement);
+        cursor = edit.end;
+    }
+    code.push_str(&original[cursor..]);
+    Ok(code)
+}
+
+/// Prompt asking for SEARCH/REPLACE blocks that carry out `task` on `original`
+pub fn edit_prompt(task: &str, language: Option<&str>, original: &str) -> String {
+    format!(
+        "Current file contents:\n```{}\n{}\n```\n\n\
+         Edit the file to do the following: {}\n\n\
+         Respond only with one or more edit blocks of the form\n\
+         {}\n<exact lines to replace>\n{}\n<replacement lines>\n{}\n\
+         Each SEARCH section must match whole lines of the file exactly once. \
+         Include only the lines that change, plus enough surrounding lines to be unique.\n",
+        language.unwrap_or(""),
+        original,
+        task,
+        SEARCH_MARKER,
+        DIVIDER_MARKER,
+        REPLACE_MARKER
+    )
+}
+
+/// Check that `span` is a range of whole characters in `code`
+pub fn check_span(code: &str, span: &std::ops::Range<usize>) -> Result<(), EditError> {
+    if span.start > span.end
+        || span.end > code.len()
+        || !code.is_char_boundary(span.start)
+        || !code.is_char_boundary(span.end)
+    {
+        return Err(EditError::InvalidSpan {
+            start: span.start,
+            end: span.end,
+            len: code.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Prompt asking for a replacement for `span` of `code`, with the code
+/// before and after it as context
+///
+/// `span` must pass [`check_span`]. `instructions` says how the span should
+/// change; with none, the model is asked for a fresh version.
+pub fn span_prompt(
+    instructions: &str,
+    language: Option<&str>,
+    code: &str,
+    span: &std::ops::Range<usize>,
+) -> String {
+    let mut prompt = format!(
+        "Rewrite the code at the {marker} marker below. The code before and after it stays as it is.\n\n\
+         ```{}\n{}{marker}{}\n```\n\n\
+         Current code at {marker}:\n```\n{}\n```\n\n",
+        language.unwrap_or(""),
+        &code[..span.start],
+        &code[span.end..],
+        &code[span.clone()],
+        marker = SPAN_MARKER
+    );
+    if !instructions.trim().is_empty() {
+        prompt.push_str(&format!("Change it as follows: {}\n\n", instructions));
+    }
+    prompt.push_str(&format!(
+        "Answer with only the code that replaces {}.\n",
+        SPAN_MARKER
+    ));
+    prompt
+}
+
+/// Constraint restricting output to a sequence of SEARCH/REPLACE blocks
+pub fn edit_constraint() -> ConstraintIR {
+    let line = r"[^\n]*\n";
+    ConstraintIR {
+        name: EDIT_CONSTRAINT_NAME.to_string(),
+        json_schema: None,
+        grammar: None,
+        regex_patterns: vec![RegexPattern {
+            pattern: format!(
+                "({}\\n({})*{}\\n({})*{}\\n)+",
+                regex::escape(SEARCH_MARKER),
+                line,
+                regex::escape(DIVIDER_MARKER),
+                line,
+                regex::escape(REPLACE_MARKER)
+            ),
+            flags: String::new(),
+        }],
+        token_masks: None,
+        type_inhabitation: None,
+        priority: 0,
+        rich_context: None,
+        feasibility_score: 1.0,
+        is_feasible: true,
+        schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = "fn a() {}\n\nfn b() {\n    1\n}\n";
+
+    fn block(search: &str, replace: &str) -> String {
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n",
+            SEARCH_MARKER, search, DIVIDER_MARKER, replace, REPLACE_MARKER
+        )
+    }
+
+    #[test]
+    fn test_patch_applies_and_reports_ranges() {
+        let patch = format!(
+            "{}\n{}",
+            block("    1", "    2"),
+            block("fn a() {}", "fn a() -> u8 { 0 }")
+        );
+        let blocks = parse_patch(&patch).unwrap();
+        let edits = resolve_edits(ORIGINAL, &blocks).unwrap();
+
+        // Sorted into file order
+        assert_eq!(edits[0].start_line, 1);
+        assert_eq!(edits[1].start_line, 4);
+        assert_eq!(&ORIGINAL[edits[1].start..edits[1].end], "    1");
+
+        assert_eq!(
+            apply_edits(ORIGINAL, &edits).unwrap(),
+            "fn a() -> u8 { 0 }\n\nfn b() {\n    2\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_search_must_match_whole_lines_once() {
+        let partial = parse_patch(&block("fn b", "fn c")).unwrap();
+        assert_eq!(
+            resolve_edits(ORIGINAL, &partial),
+            Err(EditError::NotFound { block: 1 })
+        );
+
+        let repeated = parse_patch(&block("}", "};")).unwrap();
+        assert_eq!(
+            resolve_edits("{\n}\n{\n}\n", &repeated),
+            Err(EditError::Ambiguous {
+                block: 1,
+                matches: 2
+            })
+        );
+
+        let overlapping = parse_patch(&format!(
+            "{}{}",
+            block("fn b() {\n    1", "x"),
+            block("    1\n}", "y")
+        ))
+        .unwrap();
+        assert_eq!(
+            resolve_edits(ORIGINAL, &overlapping),
+            Err(EditError::Overlapping {
+                first: 1,
+                second: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_partial_and_overlapping_matches() {
+        // A partial match at "xb" must not hide the whole-line one after it
+        let shifted = parse_patch(&block("b\nb", "c")).unwrap();
+        let edits = resolve_edits("xb\nb\nb", &shifted).unwrap();
+        assert_eq!(edits[0].start, 3);
+        assert_eq!(apply_edits("xb\nb\nb", &edits).unwrap(), "xb\nc");
+
+        let overlapping = parse_patch(&block("a\na", "b")).unwrap();
+        assert_eq!(
+            resolve_edits("a\na\na", &overlapping),
+            Err(EditError::Ambiguous {
+                block: 1,
+                matches: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_crlf_original_keeps_its_line_endings() {
+        let original = "fn b() {\r\n    1\r\n}\r\n";
+        let blocks = parse_patch(&block("fn b() {\n    1", "fn b() {\n    2")).unwrap();
+        let edits = resolve_edits(original, &blocks).unwrap();
+        assert_eq!(edits[0].end_line, 2);
+        assert_eq!(
+            apply_edits(original, &edits).unwrap(),
+            "fn b() {\r\n    2\r\n}\r\n"
+        );
+    }
+
+    #[test]
+    fn test_malformed_patches_are_rejected() {
+        assert_eq!(parse_patch("\n\n"), Err(EditError::Empty));
+        assert!(matches!(
+            parse_patch("here is the fix:\n"),
+            Err(EditError::Malformed { line: 1, .. })
+        ));
+        assert!(matches!(
+            parse_patch(&format!("{}\nfn a() {{}}\n", SEARCH_MARKER)),
+            Err(EditError::Malformed { line: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_span_prompt_marks_span_in_context() {
+        let span = ORIGINAL.find("    1").unwrap()..ORIGINAL.find("\n}").unwrap();
+        assert_eq!(check_span(ORIGINAL, &span), Ok(()));
+
+        let prompt = span_prompt("return 2", Some("rust"), ORIGINAL, &span);
+        assert!(prompt.contains("```rust\nfn a() {}\n\nfn b() {\n<SPAN>\n}\n"));
+        assert!(prompt.contains("Current code at <SPAN>:\n```\n    1\n```"));
+        assert!(prompt.contains("Change it as follows: return 2"));
+        assert!(!span_prompt("", None, ORIGINAL, &span).contains("Change it"));
+    }
+
+    #[test]
+    fn test_spans_outside_the_code_or_inside_a_character_are_rejected() {
+        let invalid = |start, end, len| Err(EditError::InvalidSpan { start, end, len });
+        let reversed = std::ops::Range { start: 2, end: 1 };
+        assert_eq!(check_span("abc", &reversed), invalid(2, 1, 3));
+        assert_eq!(check_span("abc", &(0..4)), invalid(0, 4, 3));
+        assert_eq!(check_span("é", &(0..1)), invalid(0, 1, 2));
+        assert_eq!(check_span("abc", &(3..3)), Ok(()));
+    }
+
+    #[test]
+    fn test_edit_constraint_accepts_blocks() {
+        let pattern = &edit_constraint().regex_patterns[0].pattern;
+        let re = regex::Regex::new(&format!("^(?:{})$", pattern)).unwrap();
+        assert!(re.is_match(&block("    1", "    2")));
+        assert!(!re.is_match("fn a() {}\n"));
+    }
+}
@@ -0,0 +1,407 @@
+//! Plain-text descriptions of what constraints enforce
+//!
+//! When a generation comes out more constrained than expected, the compiled
+//! schema says why but is hard to read. [`explain`] renders each constraint
+//! as a [`ConstraintExplanation`]: what its grammar allows, what its regexes
+//! match, which tokens it masks, and how merging with the rest of the set
+//! changed it. Explanations follow the compiled form, so a token mask that
+//! lost a conflict to a higher-priority constraint is described without the
+//! tokens it lost.
+
+use serde::{Deserialize, Serialize};
+
+use crate::ffi::{ConstraintIR, Grammar, JsonSchema, TokenMaskRules};
+use crate::merge::{self, ConstraintMergeReport, MergeAction};
+use crate::policy::PolicyConstraint;
+
+/// Tokens listed by id before the rest are summarized as a count
+const LISTED_TOKENS: usize = 8;
+
+/// What one constraint enforces, in plain text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstraintExplanation {
+    /// Constraint label (see [`distinct_names`](crate::distinct_names))
+    pub constraint: String,
+
+    /// Merge priority
+    pub priority: u32,
+
+    /// What the constraint requires of the output, one sentence per part
+    pub enforces: Vec<String>,
+
+    /// Caveats: merge overrides, parts the decoder does not enforce, and
+    /// feasibility
+    pub notes: Vec<String>,
+}
+
+impl std::fmt::Display for ConstraintExplanation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "`{}` (priority {}):", self.constraint, self.priority)?;
+        for line in &self.enforces {
+            write!(f, "\n  - {}", line)?;
+        }
+        for note in &self.notes {
+            write!(f, "\n  note: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// Explain each of `constraints`, in merge order
+pub fn explain(constraints: &[ConstraintIR]) -> Vec<ConstraintExplanation> {
+    let ordered = merge::merge_order(constraints);
+    let report = match crate::compile_llguidance_schema(constraints) {
+        Ok((_, report)) => report,
+        Err(e) => {
+            tracing::warn!("Explaining constraints without merge results: {:#}", e);
+            ConstraintMergeReport::new(&ordered)
+        }
+    };
+    let token_masks =
+        merge::resolve_token_masks(&ordered, &mut ConstraintMergeReport::new(&ordered));
+
+    ordered
+        .iter()
+        .zip(&report.order)
+        .zip(&token_masks)
+        .map(|((constraint, label), token_masks)| ConstraintExplanation {
+            constraint: label.clone(),
+            priority: constraint.priority,
+            enforces: enforced(constraint, token_masks.as_ref()),
+            notes: notes(constraint, label, &report),
+        })
+        .collect()
+}
+
+/// Sentences for each part of `constraint`, with `token_masks` as resolved
+fn enforced(constraint: &ConstraintIR, token_masks: Option<&TokenMaskRules>) -> Vec<String> {
+    let mut enforces = Vec::new();
+    if let Some(ref schema) = constraint.json_schema {
+        enforces.push(describe_schema(&constraint.name, schema));
+    }
+    if let Some(ref grammar) = constraint.grammar {
+        enforces.push(describe_grammar(grammar));
+    }
+    for pattern in &constraint.regex_patterns {
+        let mut line = format!("output must match the regex `{}` in full", pattern.pattern);
+        if !pattern.flags.is_empty() {
+            line.push_str(&format!(" (flags `{}`)", pattern.flags));
+        }
+        enforces.push(line);
+    }
+    if let Some(masks) = token_masks {
+        if let Some(ref allowed) = masks.allowed_tokens {
+            enforces.push(if allowed.is_empty() {
+                "no token may be generated: every allowed token lost a conflict".to_string()
+            } else {
+                format!("only tokens {} may be generated", list_tokens(allowed))
+            });
+        }
+        if let Some(ref forbidden) = masks.forbidden_tokens {
+            if !forbidden.is_empty() {
+                enforces.push(format!(
+                    "tokens {} are never generated",
+                    list_tokens(forbidden)
+                ));
+            }
+        }
+    }
+    if let Some(policy) = PolicyConstraint::from_constraint(constraint) {
+        if let Some(line) = describe_policy(&policy) {
+            enforces.push(line);
+        }
+    }
+    enforces
+}
+
+fn notes(constraint: &ConstraintIR, label: &str, report: &ConstraintMergeReport) -> Vec<String> {
+    let mut notes = Vec::new();
+    for event in report
+        .events
+        .iter()
+        .filter(|e| e.affected.iter().any(|a| a == label))
+    {
+        notes.push(match event.action {
+            MergeAction::Overridden => {
+                format!("`{}` is overridden by `{}`", event.target, event.winner)
+            }
+            MergeAction::Combined => format!(
+                "`{}` is combined with `{}`'s definition; both must hold",
+                event.target, event.winner
+            ),
+        });
+    }
+    if let Some(ref goal) = constraint.type_inhabitation {
+        notes.push(format!(
+            "the type goal `{}` is not enforced by the decoder",
+            goal.goal_type
+        ));
+    }
+    if !constraint.is_feasible {
+        notes.push(format!(
+            "marked infeasible by the constraint engine (feasibility {:.2})",
+            constraint.feasibility_score
+        ));
+    }
+    let is_empty = constraint.json_schema.is_none()
+        && constraint.grammar.is_none()
+        && constraint.regex_patterns.is_empty()
+        && constraint.token_masks.is_none()
+        && constraint.type_inhabitation.is_none()
+        && PolicyConstraint::from_constraint(constraint).is_none();
+    if is_empty {
+        notes.push("enforces nothing".to_string());
+    }
+    notes
+}
+
+fn describe_schema(name: &str, schema: &JsonSchema) -> String {
+    let mut line = format!(
+        "the output's `{}` property must be a JSON {}",
+        name, schema.schema_type
+    );
+    let mut properties: Vec<&String> = schema.properties.keys().collect();
+    properties.sort();
+    if !properties.is_empty() {
+        line.push_str(&format!(" with properties {}", quoted(&properties)));
+        if !schema.additional_properties {
+            line.push_str(" and no others");
+        }
+    }
+    if !schema.required.is_empty() {
+        line.push_str(&format!(", requiring {}", quoted(&schema.required)));
+    }
+    line
+}
+
+/// The grammar as `lhs ::= alternative | alternative` rules, start rule first
+fn describe_grammar(grammar: &Grammar) -> String {
+    let mut rules: Vec<(&str, Vec<String>)> = Vec::new();
+    for rule in &grammar.rules {
+        let alternative = rule.rhs.join(" ");
+        match rules.iter_mut().find(|(lhs, _)| *lhs == rule.lhs) {
+            Some((_, alternatives)) => alternatives.push(alternative),
+            None => rules.push((&rule.lhs, vec![alternative])),
+        }
+    }
+    if let Some(start) = rules
+        .iter()
+        .position(|(lhs, _)| *lhs == grammar.start_symbol)
+    {
+        let start = rules.remove(start);
+        rules.insert(0, start);
+    }
+    let rendered: Vec<String> = rules
+        .iter()
+        .map(|(lhs, alternatives)| format!("{} ::= {}", lhs, alternatives.join(" | ")))
+        .collect();
+    format!(
+        "output must follow the grammar starting at `{}`: {}",
+        grammar.start_symbol,
+        rendered.join("; ")
+    )
+}
+
+fn describe_policy(policy: &PolicyConstraint) -> Option<String> {
+    if policy.deny.is_empty() {
+        return None;
+    }
+    let mut line = format!(
+        "after generation, output matching {} ",
+        quoted(&policy.deny).replace(", ", " or ")
+    );
+    if !policy.allow.is_empty() {
+        line.push_str(&format!(
+            "(except matches of {}) ",
+            quoted(&policy.allow).replace(", ", " or ")
+        ));
+    }
+    line.push_str(if policy.regenerate_on_violation {
+        "is regenerated"
+    } else {
+        "is reported as a violation"
+    });
+    Some(line)
+}
+
+fn quoted(items: &[impl AsRef<str>]) -> String {
+    items
+        .iter()
+        .map(|item| format!("`{}`", item.as_ref()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn list_tokens(tokens: &[u32]) -> String {
+    let listed: Vec<String> = tokens
+        .iter()
+        .take(LISTED_TOKENS)
+        .map(u32::to_string)
+        .collect();
+    match tokens.len().saturating_sub(LISTED_TOKENS) {
+        0 => listed.join(", "),
+        more => format!("{} and {} more", listed.join(", "), more),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{GrammarRule, RegexPattern, TypeBinding, TypeInhabitationData, TypeLanguage};
+    use std::collections::HashMap;
+
+    fn constraint(name: &str, priority: u32) -> ConstraintIR {
+        ConstraintIR {
+            name: name.to_string(),
+            json_schema: None,
+            grammar: None,
+            regex_patterns: vec![],
+            token_masks: None,
+            type_inhabitation: None,
+            priority,
+            rich_context: None,
+            feasibility_score: 1.0,
+            is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    fn explain_one(constraint: ConstraintIR) -> ConstraintExplanation {
+        explain(&[constraint]).remove(0)
+    }
+
+    #[test]
+    fn test_explains_regex() {
+        let mut digits = constraint("digits", 1);
+        digits.regex_patterns.push(RegexPattern {
+            pattern: r"\d+".to_string(),
+            flags: "i".to_string(),
+        });
+        let explanation = explain_one(digits);
+        assert_eq!(
+            explanation.enforces,
+            vec![r"output must match the regex `\d+` in full (flags `i`)"]
+        );
+        assert!(explanation.notes.is_empty());
+    }
+
+    #[test]
+    fn test_explains_grammar_start_rule_first() {
+        let rule = |lhs: &str, rhs: &[&str]| GrammarRule {
+            lhs: lhs.to_string(),
+            rhs: rhs.iter().map(|s| s.to_string()).collect(),
+        };
+        let mut expr = constraint("expr", 1);
+        expr.grammar = Some(Grammar {
+            rules: vec![
+                rule("term", &["NUMBER"]),
+                rule("expr", &["term", "'+'", "term"]),
+                rule("expr", &["term"]),
+            ],
+            start_symbol: "expr".to_string(),
+        });
+        assert_eq!(
+            explain_one(expr).enforces,
+            vec![
+                "output must follow the grammar starting at `expr`: \
+                  expr ::= term '+' term | term; term ::= NUMBER"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explains_json_schema() {
+        let mut user = constraint("user", 1);
+        user.json_schema = Some(JsonSchema {
+            schema_type: "object".to_string(),
+            properties: HashMap::from([
+                ("name".to_string(), serde_json::json!({"type": "string"})),
+                ("age".to_string(), serde_json::json!({"type": "integer"})),
+            ]),
+            required: vec!["name".to_string()],
+            additional_properties: false,
+        });
+        assert_eq!(
+            explain_one(user).enforces,
+            vec![
+                "the output's `user` property must be a JSON object with properties \
+                  `age`, `name` and no others, requiring `name`"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explains_token_masks_as_resolved() {
+        let mut allow = constraint("allow", 1);
+        allow.token_masks = Some(TokenMaskRules {
+            allowed_tokens: Some(vec![1, 2, 3]),
+            forbidden_tokens: None,
+        });
+        let mut forbid = constraint("forbid", 5);
+        forbid.token_masks = Some(TokenMaskRules {
+            allowed_tokens: None,
+            forbidden_tokens: Some((2..12).collect()),
+        });
+
+        let explanations = explain(&[allow, forbid]);
+        assert_eq!(explanations[0].constraint, "forbid");
+        assert_eq!(
+            explanations[0].enforces,
+            vec!["tokens 2, 3, 4, 5, 6, 7, 8, 9 and 2 more are never generated"]
+        );
+        assert_eq!(
+            explanations[1].enforces,
+            vec!["only tokens 1 may be generated"]
+        );
+        assert_eq!(
+            explanations[1].notes,
+            vec![
+                "`token:2` is overridden by `forbid`",
+                "`token:3` is overridden by `forbid`"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explains_policy_type_goal_and_empty() {
+        let policy = PolicyConstraint::new("no-unwrap")
+            .deny(r"\.unwrap\(\)")
+            .unwrap()
+            .regenerate_on_violation(true)
+            .to_constraint();
+        let explanation = explain_one(policy);
+        assert!(explanation.enforces.contains(
+            &r"after generation, output matching `\.unwrap\(\)` is regenerated".to_string()
+        ));
+
+        let mut typed = constraint("typed", 1);
+        typed.type_inhabitation = Some(TypeInhabitationData {
+            goal_type: "Vec<u8>".to_string(),
+            current_type: None,
+            bindings: vec![TypeBinding {
+                name: "bytes".to_string(),
+                type_sig: "&[u8]".to_string(),
+            }],
+            token_mask: None,
+            language: TypeLanguage::Rust,
+        });
+        typed.is_feasible = false;
+        typed.feasibility_score = 0.25;
+        let explanation = explain_one(typed);
+        assert!(explanation.enforces.is_empty());
+        assert_eq!(
+            explanation.notes,
+            vec![
+                "the type goal `Vec<u8>` is not enforced by the decoder",
+                "marked infeasible by the constraint engine (feasibility 0.25)"
+            ]
+        );
+
+        let empty = explain_one(constraint("empty", 1));
+        assert_eq!(empty.notes, vec!["enforces nothing"]);
+        assert_eq!(
+            empty.to_string(),
+            "`empty` (priority 1):\n  note: enforces nothing"
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! Feeding one generation stream to several consumers
+//!
+//! An editor may want the same stream in its buffer view, a logging tap,
+//! and a metrics counter. Running the request once per consumer wastes
+//! tokens and gives each a different sample. A [`StreamFanOut`] reads the
+//! stream once and copies each chunk to every [`Subscription`].
+//!
+//! Each subscription has its own bounded buffer and [`LagPolicy`], so a
+//! slow consumer costs only itself unless it asks to hold the stream back
+//! ([`LagPolicy::Block`]). Once every subscription is dropped, the source
+//! stream is dropped too, which cancels the generation.
+
+use futures::stream::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::Notify;
+
+use crate::modal_client::{StreamChunk, StreamingResult};
+
+/// What happens when a subscription's buffer is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LagPolicy {
+    /// Hold the source until the subscription catches up; every other
+    /// subscription waits too
+    Block,
+
+    /// Drop the oldest buffered chunks to make room, then report how many
+    /// were lost with [`FanOutError::Lagged`] before the rest
+    #[default]
+    DropOldest,
+
+    /// End the subscription with [`FanOutError::Disconnected`] after the
+    /// chunks already buffered
+    Disconnect,
+}
+
+/// Error seen by a [`Subscription`]
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum FanOutError {
+    /// The subscription fell behind and missed chunks
+    #[error("subscriber fell behind and missed {skipped} chunks")]
+    Lagged { skipped: u64 },
+
+    /// The subscription fell behind under [`LagPolicy::Disconnect`] and
+    /// gets no more chunks
+    #[error("subscriber fell behind and was disconnected")]
+    Disconnected,
+
+    /// The source stream failed; every subscription sees the same error
+    #[error("{0:#}")]
+    Stream(Arc<anyhow::Error>),
+}
+
+/// What a finished fan-out delivered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FanOutSummary {
+    /// Items read from the source, chunks and errors
+    pub chunks: usize,
+
+    /// Chunks each subscription missed, in subscription order
+    pub dropped: Vec<u64>,
+}
+
+/// Copies one generation stream to several subscriptions
+///
+/// ```no_run
+/// # async fn run(orchestrator: maze::MazeOrchestrator, request: maze::GenerationRequest) -> anyhow::Result<()> {
+/// use futures::StreamExt;
+/// use maze::{LagPolicy, StreamFanOut};
+///
+/// let mut fan_out = StreamFanOut::new(orchestrator.generate_stream(request).await?);
+/// let mut view = fan_out.subscribe(64, LagPolicy::Block);
+/// let mut metrics = fan_out.subscribe(8, LagPolicy::DropOldest);
+/// let done = fan_out.start();
+///
+/// tokio::spawn(async move { while metrics.next().await.is_some() {} });
+/// while let Some(chunk) = view.next().await {
+///     print!("{}", chunk?.text);
+/// }
+/// let summary = done.await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct StreamFanOut {
+    source: StreamingResult,
+    queues: Vec<Arc<Queue>>,
+}
+
+impl StreamFanOut {
+    /// Fan out `source`; add subscriptions before [`start`](Self::start)
+    pub fn new(source: StreamingResult) -> Self {
+        Self {
+            source,
+            queues: Vec::new(),
+        }
+    }
+
+    /// A new subscription buffering up to `buffer` chunks (at least one)
+    pub fn subscribe(&mut self, buffer: usize, policy: LagPolicy) -> Subscription {
+        let queue = Arc::new(Queue {
+            capacity: buffer.max(1),
+            policy,
+            state: Mutex::new(QueueState::default()),
+            readable: Notify::new(),
+            writable: Notify::new(),
+        });
+        self.queues.push(Arc::clone(&queue));
+
+        let guard = Unsubscribe(Arc::clone(&queue));
+        let items = futures::stream::unfold(guard, |guard| async move {
+            let item = guard.0.recv().await?;
+            Some((item, guard))
+        });
+        Subscription {
+            items: Box::pin(items),
+        }
+    }
+
+    /// Start reading the source on a tokio task
+    ///
+    /// The task ends when the source does or every subscription has been
+    /// dropped, and returns what was delivered.
+    pub fn start(mut self) -> tokio::task::JoinHandle<FanOutSummary> {
+        let source = std::mem::replace(&mut self.source, Box::pin(futures::stream::empty()));
+        tokio::spawn(drive(source, std::mem::take(&mut self.queues)))
+    }
+}
+
+/// A fan-out dropped without being started ends its subscriptions
+impl Drop for StreamFanOut {
+    fn drop(&mut self) {
+        for queue in &self.queues {
+            queue.close();
+        }
+    }
+}
+
+/// One consumer's copy of a fanned-out stream
+pub struct Subscription {
+    items: Pin<Box<dyn Stream<Item = Result<StreamChunk, FanOutError>> + Send>>,
+}
+
+impl Stream for Subscription {
+    type Item = Result<StreamChunk, FanOutError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.items.as_mut().poll_next(cx)
+    }
+}
+
+async fn drive(mut source: StreamingResult, queues: Vec<Arc<Queue>>) -> FanOutSummary {
+    let mut chunks = 0;
+    let mut live = vec![true; queues.len()];
+    while live.iter().any(|&live| live) {
+        let Some(item) = source.next().await else {
+            break;
+        };
+        chunks += 1;
+        let item = item.map_err(|e| FanOutError::Stream(Arc::new(e)));
+        for (queue, live) in queues.iter().zip(live.iter_mut()) {
+            if *live {
+                *live = queue.push(item.clone()).await;
+            } else {
+                queue.miss();
+            }
+        }
+    }
+    if !live.iter().any(|&live| live) {
+        tracing::debug!(chunks, "No fan-out subscribers left; dropping the stream");
+    }
+
+    for queue in &queues {
+        queue.close();
+    }
+    FanOutSummary {
+        chunks,
+        dropped: queues.iter().map(|queue| queue.dropped()).collect(),
+    }
+}
+
+type Item = Result<StreamChunk, FanOutError>;
+
+/// A subscription's buffer, shared between the driver and the subscriber
+struct Queue {
+    capacity: usize,
+    policy: LagPolicy,
+    state: Mutex<QueueState>,
+    readable: Notify,
+    writable: Notify,
+}
+
+#[derive(Default)]
+struct QueueState {
+    items: VecDeque<Item>,
+    /// Chunks dropped since the subscriber was last told
+    unreported: u64,
+    /// Chunks dropped in total, including those sent after a disconnect
+    dropped: u64,
+    /// [`LagPolicy::Disconnect`] cut the subscription off
+    disconnected: bool,
+    /// No more items will be pushed
+    closed: bool,
+    /// The subscription was dropped
+    unsubscribed: bool,
+}
+
+impl Queue {
+    /// Buffer `item`, applying the lag policy; `false` once the
+    /// subscription takes no more items
+    async fn push(&self, item: Item) -> bool {
+        let mut item = Some(item);
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.unsubscribed || state.closed {
+                    return false;
+                }
+                let full = state.items.len() >= self.capacity;
+                match self.policy {
+                    LagPolicy::Block if full => {}
+                    LagPolicy::DropOldest if full => {
+                        state.items.pop_front();
+                        state.unreported += 1;
+                        state.dropped += 1;
+                    }
+                    LagPolicy::Disconnect if full => {
+                        state.dropped += 1;
+                        state.items.push_back(Err(FanOutError::Disconnected));
+                        state.disconnected = true;
+                        state.closed = true;
+                        self.readable.notify_one();
+                        return false;
+                    }
+                    _ => {}
+                }
+                if state.items.len() < self.capacity {
+                    state.items.push_back(item.take().expect("pushed once"));
+                    self.readable.notify_one();
+                    return true;
+                }
+            }
+            self.writable.notified().await;
+        }
+    }
+
+    /// The next item, waiting for one; `None` once closed and drained
+    async fn recv(&self) -> Option<Item> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.unreported > 0 {
+                    let skipped = std::mem::take(&mut state.unreported);
+                    return Some(Err(FanOutError::Lagged { skipped }));
+                }
+                if let Some(item) = state.items.pop_front() {
+                    self.writable.notify_one();
+                    return Some(item);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.readable.notified().await;
+        }
+    }
+
+    /// Count a chunk read after the subscription was disconnected
+    fn miss(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.disconnected {
+            state.dropped += 1;
+        }
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.readable.notify_one();
+    }
+
+    fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+}
+
+/// Marks a queue unsubscribed when its [`Subscription`] is dropped
+struct Unsubscribe(Arc<Queue>);
+
+impl Drop for Unsubscribe {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.unsubscribed = true;
+        state.items.clear();
+        self.0.writable.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn chunk(index: usize) -> anyhow::Result<StreamChunk> {
+        Ok(StreamChunk {
+            text: format!("t{} ", index),
+            is_final: false,
+            token_index: index,
+            timestamp_ms: 0,
+            finish_reason: None,
+            model: None,
+            constraint_event: None,
+            restarted: false,
+        })
+    }
+
+    fn source(count: usize) -> StreamingResult {
+        Box::pin(futures::stream::iter((0..count).map(chunk)))
+    }
+
+    fn texts(items: &[Item]) -> Vec<String> {
+        items
+            .iter()
+            .map(|item| match item {
+                Ok(chunk) => chunk.text.trim().to_string(),
+                Err(e) => e.to_string(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_every_subscriber_gets_every_chunk() {
+        let mut fan_out = StreamFanOut::new(source(5));
+        let subscriptions: Vec<Subscription> = (0..3)
+            .map(|_| fan_out.subscribe(2, LagPolicy::Block))
+            .collect();
+        let done = fan_out.start();
+
+        let received =
+            futures::future::join_all(subscriptions.into_iter().map(|s| s.collect::<Vec<_>>()))
+                .await;
+        for items in &received {
+            assert_eq!(texts(items), vec!["t0", "t1", "t2", "t3", "t4"]);
+        }
+        let summary = done.await.unwrap();
+        assert_eq!(summary.chunks, 5);
+        assert_eq!(summary.dropped, vec![0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscribers_lag_or_disconnect_without_holding_others() {
+        let mut fan_out = StreamFanOut::new(source(5));
+        let fast = fan_out.subscribe(8, LagPolicy::Block);
+        let lagging = fan_out.subscribe(2, LagPolicy::DropOldest);
+        let disconnected = fan_out.subscribe(1, LagPolicy::Disconnect);
+
+        // Nothing reads until the source is done
+        let summary = fan_out.start().await.unwrap();
+        assert_eq!(summary.chunks, 5);
+        assert_eq!(summary.dropped, vec![0, 3, 4]);
+
+        let fast: Vec<Item> = fast.collect().await;
+        assert_eq!(fast.len(), 5);
+        let lagging: Vec<Item> = lagging.collect().await;
+        assert_eq!(
+            texts(&lagging),
+            vec!["subscriber fell behind and missed 3 chunks", "t3", "t4"]
+        );
+        let disconnected: Vec<Item> = disconnected.collect().await;
+        assert_eq!(
+            texts(&disconnected),
+            vec!["t0", "subscriber fell behind and was disconnected"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unstarted_fan_out_ends_its_subscriptions() {
+        let mut fan_out = StreamFanOut::new(source(3));
+        let subscription = fan_out.subscribe(4, LagPolicy::Block);
+        drop(fan_out);
+
+        let items = tokio::time::timeout(Duration::from_secs(1), subscription.collect::<Vec<_>>())
+            .await
+            .expect("subscription ends with its fan-out");
+        assert!(items.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_blocking_subscriber_holds_the_source() {
+        let mut fan_out = StreamFanOut::new(source(3));
+        let mut blocking = fan_out.subscribe(1, LagPolicy::Block);
+        let done = fan_out.start();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!done.is_finished());
+
+        assert_eq!(blocking.next().await.unwrap().unwrap().token_index, 0);
+        let rest: Vec<Item> = blocking.collect().await;
+        assert_eq!(rest.len(), 2);
+        assert_eq!(done.await.unwrap().chunks, 3);
+    }
+
+    #[tokio::test]
+    async fn test_source_is_dropped_with_the_last_subscriber() {
+        let endless: StreamingResult = Box::pin(futures::stream::iter(0..).map(chunk));
+        let mut fan_out = StreamFanOut::new(endless);
+        let mut only = fan_out.subscribe(1, LagPolicy::Block);
+        let done = fan_out.start();
+
+        assert!(only.next().await.unwrap().is_ok());
+        drop(only);
+        let summary = tokio::time::timeout(Duration::from_secs(1), done)
+            .await
+            .expect("fan-out stops once unsubscribed")
+            .unwrap();
+        assert!(summary.chunks < 5);
+    }
+
+    #[tokio::test]
+    async fn test_source_errors_reach_every_subscriber() {
+        let failing: StreamingResult = Box::pin(futures::stream::iter(vec![
+            chunk(0),
+            Err(anyhow::anyhow!("connection reset")),
+        ]));
+        let mut fan_out = StreamFanOut::new(failing);
+        let subscriptions = [
+            fan_out.subscribe(4, LagPolicy::Block),
+            fan_out.subscribe(4, LagPolicy::DropOldest),
+        ];
+        fan_out.start().await.unwrap();
+
+        for subscription in subscriptions {
+            let items: Vec<Item> = subscription.collect().await;
+            assert_eq!(texts(&items), vec!["t0", "connection reset"]);
+        }
+    }
+}
@@ -4,12 +4,16 @@
 //! Rust Maze orchestration and Zig constraint engines (Clew/Braid)
 
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
+use std::panic::AssertUnwindSafe;
 use std::ptr;
 use std::slice;
 
+use crate::constraint_builder::{self, InvalidConstraint};
+
 /// C-compatible ConstraintIR matching Zig definition
 ///
 /// This struct must match the memory layout of the Zig ConstraintIR type.
@@ -52,6 +56,58 @@ pub const FFI_MAGIC: u64 = 0x414E4B455F464649;
 /// Sentinel value for freed allocations (matches Zig FFI_FREED)
 pub const FFI_FREED: u64 = 0xDEADDEADDEADDEAD;
 
+/// Error codes returned across the FFI (matches Zig AnankeError)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FfiErrorCode {
+    Success = 0,
+    NullPointer = 1,
+    AllocationFailure = 2,
+    InvalidInput = 3,
+    ExtractionFailed = 4,
+    CompilationFailed = 5,
+    DoubleFree = 6,
+    InvalidPointer = 7,
+    UseAfterFree = 8,
+    /// Rust code panicked; the message is available from `take_last_error_ffi`
+    Panic = 9,
+}
+
+/// Data rejected at the FFI boundary, with the code reported to the caller
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{message}")]
+pub struct FfiError {
+    pub code: FfiErrorCode,
+    pub message: String,
+}
+
+impl FfiError {
+    pub fn new(code: FfiErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Malformed data: bad UTF-8 or JSON, or IR failing [`ConstraintIR::validate`]
+    pub fn invalid_input(message: impl Into<String>) -> Self {
+        Self::new(FfiErrorCode::InvalidInput, message)
+    }
+}
+
+impl From<InvalidConstraint> for FfiError {
+    fn from(err: InvalidConstraint) -> Self {
+        Self::invalid_input(err.to_string())
+    }
+}
+
+/// Current version of the [`ConstraintIR`] and compiled schema formats
+///
+/// Bump when previously serialized IR or compiled schemas would be read
+/// differently, and add a step to
+/// [`migrate_constraints`](crate::migrate::migrate_constraints).
+pub const CONSTRAINT_SCHEMA_VERSION: u32 = 1;
+
 /// C-compatible TokenMaskRules
 #[repr(C)]
 #[derive(Debug, Clone)]
@@ -98,7 +154,10 @@ pub struct ConstraintIR {
     /// Rich context for multi-domain constrained decoding (CLaSH domains)
     /// Contains pre-serialized JSON for function_signatures, type_bindings,
     /// class_definitions, imports, control_flow, semantic_constraints
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::ir_codec::serialize_canonical"
+    )]
     pub rich_context: Option<serde_json::Value>,
 
     /// Feasibility score (0.0 = loose, 1.0 = very tight)
@@ -108,6 +167,11 @@ pub struct ConstraintIR {
     /// Whether the constraint set is feasible (no conflicts detected)
     #[serde(default = "default_true")]
     pub is_feasible: bool,
+
+    /// Version of the IR format, [`CONSTRAINT_SCHEMA_VERSION`] when built by
+    /// this crate; IR serialized before versioning deserializes as 0
+    #[serde(default)]
+    pub schema_version: u32,
 }
 
 fn default_true() -> bool {
@@ -117,7 +181,7 @@ fn default_true() -> bool {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JsonSchema {
     pub schema_type: String,
-    #[serde(default)]
+    #[serde(default, serialize_with = "crate::ir_codec::serialize_sorted")]
     pub properties: HashMap<String, serde_json::Value>,
     #[serde(default)]
     pub required: Vec<String>,
@@ -219,6 +283,10 @@ pub struct IntentFFI {
 
     /// Programming language (nullable)
     pub language: *const c_char,
+
+    /// Time allowed for the generation in milliseconds (0 = use the
+    /// configured timeout)
+    pub timeout_ms: u64,
 }
 
 /// Rust-native Intent
@@ -228,6 +296,10 @@ pub struct Intent {
     pub prompt: String,
     pub current_file: Option<String>,
     pub language: Option<String>,
+    /// Deadline for the generation in milliseconds, e.g. short for
+    /// interactive use and long for batch jobs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
 }
 
 /// C-compatible GenerationResult
@@ -269,21 +341,30 @@ impl ConstraintIR {
     ///
     /// # Safety
     /// The FFI pointer must be valid and point to properly initialized memory
-    pub unsafe fn from_ffi(ffi: *const ConstraintIRFFI) -> Result<Self, String> {
+    pub unsafe fn from_ffi(ffi: *const ConstraintIRFFI) -> Result<Self, FfiError> {
         if ffi.is_null() {
-            return Err("Null ConstraintIR pointer".to_string());
+            return Err(FfiError::new(
+                FfiErrorCode::NullPointer,
+                "Null ConstraintIR pointer",
+            ));
         }
 
         let ffi_ref = &*ffi;
 
         // Validate magic number to detect use-after-free and invalid pointers
         if ffi_ref.magic == FFI_FREED {
-            return Err("Use-after-free detected: ConstraintIR was already freed".to_string());
+            return Err(FfiError::new(
+                FfiErrorCode::UseAfterFree,
+                "Use-after-free detected: ConstraintIR was already freed",
+            ));
         }
         if ffi_ref.magic != FFI_MAGIC {
-            return Err(format!(
-                "Invalid ConstraintIR pointer: magic number mismatch (expected 0x{:x}, got 0x{:x})",
-                FFI_MAGIC, ffi_ref.magic
+            return Err(FfiError::new(
+                FfiErrorCode::InvalidPointer,
+                format!(
+                    "Invalid ConstraintIR pointer: magic number mismatch (expected 0x{:x}, got 0x{:x})",
+                    FFI_MAGIC, ffi_ref.magic
+                ),
             ));
         }
 
@@ -291,7 +372,7 @@ impl ConstraintIR {
         let name = if !ffi_ref.name.is_null() {
             CStr::from_ptr(ffi_ref.name)
                 .to_str()
-                .map_err(|e| format!("Invalid UTF-8 in name: {}", e))?
+                .map_err(|e| FfiError::invalid_input(format!("Invalid UTF-8 in name: {}", e)))?
                 .to_string()
         } else {
             "unnamed".to_string()
@@ -299,12 +380,12 @@ impl ConstraintIR {
 
         // Convert JSON schema
         let json_schema = if !ffi_ref.json_schema.is_null() {
-            let schema_str = CStr::from_ptr(ffi_ref.json_schema)
-                .to_str()
-                .map_err(|e| format!("Invalid UTF-8 in JSON schema: {}", e))?;
+            let schema_str = CStr::from_ptr(ffi_ref.json_schema).to_str().map_err(|e| {
+                FfiError::invalid_input(format!("Invalid UTF-8 in JSON schema: {}", e))
+            })?;
             Some(
                 serde_json::from_str(schema_str)
-                    .map_err(|e| format!("Invalid JSON schema: {}", e))?,
+                    .map_err(|e| FfiError::invalid_input(format!("Invalid JSON schema: {}", e)))?,
             )
         } else {
             None
@@ -314,8 +395,11 @@ impl ConstraintIR {
         let grammar = if !ffi_ref.grammar.is_null() {
             let grammar_str = CStr::from_ptr(ffi_ref.grammar)
                 .to_str()
-                .map_err(|e| format!("Invalid UTF-8 in grammar: {}", e))?;
-            Some(serde_json::from_str(grammar_str).map_err(|e| format!("Invalid grammar: {}", e))?)
+                .map_err(|e| FfiError::invalid_input(format!("Invalid UTF-8 in grammar: {}", e)))?;
+            Some(
+                serde_json::from_str(grammar_str)
+                    .map_err(|e| FfiError::invalid_input(format!("Invalid grammar: {}", e)))?,
+            )
         } else {
             None
         };
@@ -330,17 +414,20 @@ impl ConstraintIR {
                 .iter()
                 .map(|&pattern_ptr| {
                     if pattern_ptr.is_null() {
-                        return Err("Null pattern pointer".to_string());
+                        return Err(FfiError::new(
+                            FfiErrorCode::NullPointer,
+                            "Null pattern pointer",
+                        ));
                     }
-                    let pattern_str = CStr::from_ptr(pattern_ptr)
-                        .to_str()
-                        .map_err(|e| format!("Invalid UTF-8 in regex pattern: {}", e))?;
+                    let pattern_str = CStr::from_ptr(pattern_ptr).to_str().map_err(|e| {
+                        FfiError::invalid_input(format!("Invalid UTF-8 in regex pattern: {}", e))
+                    })?;
                     Ok(RegexPattern {
                         pattern: pattern_str.to_string(),
                         flags: String::new(),
                     })
                 })
-                .collect::<Result<Vec<_>, String>>()?
+                .collect::<Result<Vec<_>, FfiError>>()?
         } else {
             vec![]
         };
@@ -381,7 +468,7 @@ impl ConstraintIR {
             None
         };
 
-        Ok(ConstraintIR {
+        let constraint = ConstraintIR {
             name,
             json_schema,
             grammar,
@@ -392,7 +479,41 @@ impl ConstraintIR {
             rich_context: None, // Rich context is passed via JSON, not FFI
             feasibility_score: 0.0,
             is_feasible: true,
-        })
+            schema_version: CONSTRAINT_SCHEMA_VERSION,
+        };
+        constraint.validate().map_err(FfiError::from)?;
+        Ok(constraint)
+    }
+
+    /// Check the IR for malformed data that would fail compilation or
+    /// compile to a bad schema
+    ///
+    /// Applies the checks [`ConstraintBuilder`](crate::constraint_builder::ConstraintBuilder)
+    /// makes as parts are added: the name is not empty, regex flags are
+    /// recognized and patterns compile, grammars define their start symbol,
+    /// JSON schemas declare the properties they require, and no token is both
+    /// allowed and forbidden. Run on IR received over FFI by [`from_ffi`](Self::from_ffi).
+    pub fn validate(&self) -> Result<(), InvalidConstraint> {
+        let invalid = |reason: String| InvalidConstraint {
+            constraint: self.name.clone(),
+            reason,
+        };
+        if self.name.trim().is_empty() {
+            return Err(invalid("constraint name is empty".to_string()));
+        }
+        for regex in &self.regex_patterns {
+            constraint_builder::check_regex(regex).map_err(invalid)?;
+        }
+        if let Some(grammar) = &self.grammar {
+            constraint_builder::check_grammar(grammar).map_err(invalid)?;
+        }
+        if let Some(schema) = &self.json_schema {
+            constraint_builder::check_json_schema(schema).map_err(invalid)?;
+        }
+        if let Some(masks) = &self.token_masks {
+            constraint_builder::check_token_masks(masks).map_err(invalid)?;
+        }
+        Ok(())
     }
 
     /// Convert to C FFI representation
@@ -522,6 +643,7 @@ impl Intent {
             prompt,
             current_file,
             language,
+            timeout_ms: (ffi_ref.timeout_ms > 0).then_some(ffi_ref.timeout_ms),
         })
     }
 }
@@ -549,6 +671,57 @@ impl GenerationResult {
 // FFI Memory Management Functions (C-callable)
 // ============================================================================
 
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Record the error reported by the last failing entry point on this thread
+fn set_last_error(message: impl Into<String>) {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message.into()));
+}
+
+/// Run the body of an `extern "C"` entry point, turning a panic into
+/// [`FfiErrorCode::Panic`]
+///
+/// Unwinding out of an `extern "C"` function is undefined behavior, so every
+/// entry point runs through this. The panic message is kept for
+/// `take_last_error_ffi`. The body is treated as unwind safe: nothing it
+/// touched is used again after a panic.
+pub(crate) fn catch_panic(entry_point: &str, body: impl FnOnce() -> FfiErrorCode) -> FfiErrorCode {
+    match std::panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(code) => code,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic payload".to_string());
+            tracing::error!("Panic in {}: {}", entry_point, message);
+            set_last_error(format!("panic in {}: {}", entry_point, message));
+            FfiErrorCode::Panic
+        }
+    }
+}
+
+/// Take the message of the last error reported on this thread
+///
+/// Returns null if no entry point has failed since the last call. Free the
+/// message with `free_ffi_string`.
+#[no_mangle]
+pub extern "C" fn take_last_error_ffi() -> *mut c_char {
+    std::panic::catch_unwind(|| {
+        LAST_ERROR
+            .with(|last| last.borrow_mut().take())
+            .map(|message| {
+                CString::new(message.replace('\0', ""))
+                    .unwrap_or_default()
+                    .into_raw()
+            })
+            .unwrap_or(ptr::null_mut())
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
 /// Free a ConstraintIR FFI structure
 ///
 /// # Safety
@@ -556,75 +729,131 @@ impl GenerationResult {
 ///
 /// # Resilience
 /// - Returns early (no-op) for null pointers
-/// - Logs warning and returns for double-free attempts
-/// - Logs warning and returns for invalid pointers
+/// - Logs warning and returns [`FfiErrorCode::DoubleFree`] for double-free attempts
+/// - Logs warning and returns [`FfiErrorCode::InvalidPointer`] for invalid pointers
 #[no_mangle]
-pub unsafe extern "C" fn free_constraint_ir_ffi(ptr: *mut ConstraintIRFFI) {
-    if ptr.is_null() {
-        return;
-    }
+pub unsafe extern "C" fn free_constraint_ir_ffi(ptr: *mut ConstraintIRFFI) -> FfiErrorCode {
+    catch_panic("free_constraint_ir_ffi", || {
+        if ptr.is_null() {
+            return FfiErrorCode::Success;
+        }
 
-    // Validate magic before taking ownership
-    let magic = (*ptr).magic;
-    if magic == FFI_FREED {
-        tracing::warn!("free_constraint_ir_ffi: double-free detected (already freed)");
-        return;
-    }
-    if magic != FFI_MAGIC {
-        tracing::warn!(
-            "free_constraint_ir_ffi: invalid pointer (magic mismatch: 0x{:x})",
-            magic
-        );
-        return;
-    }
+        // Validate magic before taking ownership
+        let magic = (*ptr).magic;
+        if magic == FFI_FREED {
+            tracing::warn!("free_constraint_ir_ffi: double-free detected (already freed)");
+            set_last_error("double free of ConstraintIR");
+            return FfiErrorCode::DoubleFree;
+        }
+        if magic != FFI_MAGIC {
+            tracing::warn!(
+                "free_constraint_ir_ffi: invalid pointer (magic mismatch: 0x{:x})",
+                magic
+            );
+            set_last_error(format!(
+                "invalid ConstraintIR pointer (magic mismatch: 0x{:x})",
+                magic
+            ));
+            return FfiErrorCode::InvalidPointer;
+        }
 
-    // Mark as freed before actual deallocation
-    (*ptr).magic = FFI_FREED;
+        // Mark as freed before actual deallocation
+        (*ptr).magic = FFI_FREED;
 
-    let ffi = Box::from_raw(ptr);
+        let ffi = Box::from_raw(ptr);
 
-    // Free name
-    if !ffi.name.is_null() {
-        let _ = CString::from_raw(ffi.name as *mut c_char);
-    }
+        // Free name
+        if !ffi.name.is_null() {
+            let _ = CString::from_raw(ffi.name as *mut c_char);
+        }
 
-    // Free JSON schema
-    if !ffi.json_schema.is_null() {
-        let _ = CString::from_raw(ffi.json_schema as *mut c_char);
-    }
+        // Free JSON schema
+        if !ffi.json_schema.is_null() {
+            let _ = CString::from_raw(ffi.json_schema as *mut c_char);
+        }
 
-    // Free grammar
-    if !ffi.grammar.is_null() {
-        let _ = CString::from_raw(ffi.grammar as *mut c_char);
-    }
+        // Free grammar
+        if !ffi.grammar.is_null() {
+            let _ = CString::from_raw(ffi.grammar as *mut c_char);
+        }
 
-    // Free regex patterns
-    if !ffi.regex_patterns.is_null() {
-        let patterns = slice::from_raw_parts(ffi.regex_patterns, ffi.regex_patterns_len);
-        for &pattern in patterns {
-            if !pattern.is_null() {
-                let _ = CString::from_raw(pattern as *mut c_char);
+        // Free regex patterns
+        if !ffi.regex_patterns.is_null() {
+            let patterns = slice::from_raw_parts(ffi.regex_patterns, ffi.regex_patterns_len);
+            for &pattern in patterns {
+                if !pattern.is_null() {
+                    let _ = CString::from_raw(pattern as *mut c_char);
+                }
             }
+            let _ = Box::from_raw(ffi.regex_patterns as *mut *const c_char);
         }
-        let _ = Box::from_raw(ffi.regex_patterns as *mut *const c_char);
-    }
 
-    // Free token masks
-    if !ffi.token_masks.is_null() {
-        let masks = Box::from_raw(ffi.token_masks as *mut TokenMaskRulesFFI);
-        if !masks.allowed_tokens.is_null() {
-            let _ = Box::from_raw(core::ptr::slice_from_raw_parts_mut(
-                masks.allowed_tokens as *mut u32,
-                masks.allowed_tokens_len,
-            ));
-        }
-        if !masks.forbidden_tokens.is_null() {
-            let _ = Box::from_raw(core::ptr::slice_from_raw_parts_mut(
-                masks.forbidden_tokens as *mut u32,
-                masks.forbidden_tokens_len,
-            ));
+        // Free token masks
+        if !ffi.token_masks.is_null() {
+            let masks = Box::from_raw(ffi.token_masks as *mut TokenMaskRulesFFI);
+            if !masks.allowed_tokens.is_null() {
+                let _ = Box::from_raw(core::ptr::slice_from_raw_parts_mut(
+                    masks.allowed_tokens as *mut u32,
+                    masks.allowed_tokens_len,
+                ));
+            }
+            if !masks.forbidden_tokens.is_null() {
+                let _ = Box::from_raw(core::ptr::slice_from_raw_parts_mut(
+                    masks.forbidden_tokens as *mut u32,
+                    masks.forbidden_tokens_len,
+                ));
+            }
         }
+        FfiErrorCode::Success
+    })
+}
+
+/// Convert and validate a ConstraintIR FFI structure
+///
+/// Returns [`FfiErrorCode::Success`] if the IR is well formed. Otherwise
+/// returns the error code and, when `message_out` is not null, stores a
+/// description there, to be freed with `free_ffi_string`.
+///
+/// # Safety
+/// `ptr` must be null or point to a `ConstraintIRFFI`; `message_out` must be
+/// null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn validate_constraint_ir_ffi(
+    ptr: *const ConstraintIRFFI,
+    message_out: *mut *mut c_char,
+) -> FfiErrorCode {
+    if !message_out.is_null() {
+        *message_out = ptr::null_mut();
     }
+    catch_panic("validate_constraint_ir_ffi", || {
+        match ConstraintIR::from_ffi(ptr) {
+            Ok(_) => FfiErrorCode::Success,
+            Err(err) => {
+                tracing::warn!("Rejected ConstraintIR from FFI: {}", err);
+                if !message_out.is_null() {
+                    // Messages echo caller input, which cannot contain NUL
+                    let message = CString::new(err.message.replace('\0', "")).unwrap_or_default();
+                    *message_out = message.into_raw();
+                }
+                set_last_error(err.message);
+                err.code
+            }
+        }
+    })
+}
+
+/// Free a string returned across the FFI, such as a validation message
+///
+/// # Safety
+/// Must be called at most once, on a pointer returned by this library.
+#[no_mangle]
+pub unsafe extern "C" fn free_ffi_string(ptr: *mut c_char) -> FfiErrorCode {
+    catch_panic("free_ffi_string", || {
+        if !ptr.is_null() {
+            let _ = CString::from_raw(ptr);
+        }
+        FfiErrorCode::Success
+    })
 }
 
 /// Free a GenerationResult FFI structure
@@ -632,22 +861,326 @@ pub unsafe extern "C" fn free_constraint_ir_ffi(ptr: *mut ConstraintIRFFI) {
 /// # Safety
 /// Must be called exactly once on a pointer returned from `to_ffi`
 #[no_mangle]
-pub unsafe extern "C" fn free_generation_result_ffi(ptr: *mut GenerationResultFFI) {
-    if ptr.is_null() {
-        return;
-    }
+pub unsafe extern "C" fn free_generation_result_ffi(ptr: *mut GenerationResultFFI) -> FfiErrorCode {
+    catch_panic("free_generation_result_ffi", || {
+        if ptr.is_null() {
+            return FfiErrorCode::Success;
+        }
 
-    let result = Box::from_raw(ptr);
+        let result = Box::from_raw(ptr);
 
-    // Free code
-    if !result.code.is_null() {
-        let _ = CString::from_raw(result.code as *mut c_char);
-    }
+        // Free code
+        if !result.code.is_null() {
+            let _ = CString::from_raw(result.code as *mut c_char);
+        }
 
-    // Free error
-    if !result.error.is_null() {
-        let _ = CString::from_raw(result.error as *mut c_char);
-    }
+        // Free error
+        if !result.error.is_null() {
+            let _ = CString::from_raw(result.error as *mut c_char);
+        }
+        FfiErrorCode::Success
+    })
+}
+
+// ============================================================================
+// Constraint Cache Queries (C-callable)
+// ============================================================================
+//
+// Ownership: the orchestrator belongs to the Rust host, which lends a
+// `*const MazeOrchestrator` to the Zig side; it must outlive every call.
+// Zig owns the IR array and its strings; Rust copies what it needs during
+// the call and keeps no pointers into it afterwards.
+
+/// C-compatible cache statistics, mirroring [`CacheStats`](crate::CacheStats)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStatsFFI {
+    /// Compiled constraint sets in the cache
+    pub size: usize,
+
+    /// Capacity of the cache; 0 when caching is disabled
+    pub limit: usize,
+}
+
+/// Whether the constraint set in `ir[..len]` is already compiled
+///
+/// Zig can check this before emitting IR again. The set is keyed as Rust
+/// reads it from `ConstraintIRFFI`, so it matches a set compiled after
+/// crossing the FFI. Returns false on invalid input, with the reason
+/// available from `take_last_error_ffi`.
+///
+/// # Safety
+/// `orchestrator` must be null or a live `MazeOrchestrator`; `ir` must be
+/// null or point to `len` `ConstraintIRFFI` structures.
+#[no_mangle]
+pub unsafe extern "C" fn constraint_is_cached(
+    orchestrator: *const crate::MazeOrchestrator,
+    ir: *const ConstraintIRFFI,
+    len: usize,
+) -> bool {
+    let mut cached = false;
+    let code = catch_panic("constraint_is_cached", || {
+        if orchestrator.is_null() || (ir.is_null() && len > 0) {
+            set_last_error("constraint_is_cached: null pointer");
+            return FfiErrorCode::NullPointer;
+        }
+        let constraints = match read_constraint_array(ir, len) {
+            Ok(constraints) => constraints,
+            Err(err) => {
+                set_last_error(err.message);
+                return err.code;
+            }
+        };
+        match futures::executor::block_on((*orchestrator).is_cached(&constraints)) {
+            Ok(is_cached) => {
+                cached = is_cached;
+                FfiErrorCode::Success
+            }
+            Err(err) => {
+                set_last_error(format!("{:#}", err));
+                FfiErrorCode::InvalidInput
+            }
+        }
+    });
+    code == FfiErrorCode::Success && cached
+}
+
+/// Copy the `len` structures at `ir` into Rust constraints
+///
+/// # Safety
+/// `ir` must point to `len` `ConstraintIRFFI` structures.
+unsafe fn read_constraint_array(
+    ir: *const ConstraintIRFFI,
+    len: usize,
+) -> Result<Vec<ConstraintIR>, FfiError> {
+    (0..len)
+        .map(|i| ConstraintIR::from_ffi(ir.add(i)))
+        .collect()
+}
+
+/// Write the orchestrator's compiled-constraint cache statistics to `out`
+///
+/// # Safety
+/// `orchestrator` must be null or a live `MazeOrchestrator`; `out` must be
+/// null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn constraint_cache_stats_ffi(
+    orchestrator: *const crate::MazeOrchestrator,
+    out: *mut CacheStatsFFI,
+) -> FfiErrorCode {
+    catch_panic("constraint_cache_stats_ffi", || {
+        if orchestrator.is_null() || out.is_null() {
+            set_last_error("constraint_cache_stats_ffi: null pointer");
+            return FfiErrorCode::NullPointer;
+        }
+        let stats = futures::executor::block_on((*orchestrator).cache_stats());
+        *out = CacheStatsFFI {
+            size: stats.size,
+            limit: stats.limit,
+        };
+        FfiErrorCode::Success
+    })
+}
+
+// ============================================================================
+// Registered Constraint Sets (C-callable)
+// ============================================================================
+//
+// Zig registers a constraint set once and generates against its handle, so a
+// large grammar filled repeatedly crosses the FFI, and is validated and
+// hashed, only at registration. Rust holds the parsed set until Zig releases
+// the handle. Ownership of the orchestrator and IR array is as for the cache
+// queries above.
+
+/// Runtime driving generations started from Zig, which has none of its own
+fn ffi_runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: std::sync::OnceLock<tokio::runtime::Runtime> = std::sync::OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to start the FFI runtime")
+    })
+}
+
+/// Register the constraint set in `ir[..len]` and write its handle to
+/// `handle_out`
+///
+/// The set is copied, validated, and hashed once. Release the handle with
+/// `constraint_release_ffi`.
+///
+/// # Safety
+/// `orchestrator` must be null or a live `MazeOrchestrator`; `ir` must be
+/// null or point to `len` `ConstraintIRFFI` structures; `handle_out` must
+/// be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn constraint_register_ffi(
+    orchestrator: *const crate::MazeOrchestrator,
+    ir: *const ConstraintIRFFI,
+    len: usize,
+    handle_out: *mut u64,
+) -> FfiErrorCode {
+    catch_panic("constraint_register_ffi", || {
+        if orchestrator.is_null() || handle_out.is_null() || (ir.is_null() && len > 0) {
+            set_last_error("constraint_register_ffi: null pointer");
+            return FfiErrorCode::NullPointer;
+        }
+        let constraints = match read_constraint_array(ir, len) {
+            Ok(constraints) => constraints,
+            Err(err) => {
+                set_last_error(err.message);
+                return err.code;
+            }
+        };
+        match (*orchestrator).register_constraints(constraints) {
+            Ok(handle) => {
+                *handle_out = handle.0;
+                FfiErrorCode::Success
+            }
+            Err(err) => {
+                set_last_error(format!("{:#}", err));
+                FfiErrorCode::InvalidInput
+            }
+        }
+    })
+}
+
+/// Register the constraint set encoded in `bytes[..len]` and write its
+/// handle to `handle_out`
+///
+/// As `constraint_register_ffi`, for IR encoded as an array of constraints
+/// in the orchestrator's `MazeConfig::ir_format`; MessagePack avoids
+/// building a `ConstraintIRFFI` per constraint for large sets. The bytes are
+/// copied during the call. Undecodable input returns `InvalidInput`.
+///
+/// # Safety
+/// `orchestrator` must be null or a live `MazeOrchestrator`; `bytes` must be
+/// null or point to `len` bytes; `handle_out` must be null or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn constraint_register_encoded_ffi(
+    orchestrator: *const crate::MazeOrchestrator,
+    bytes: *const u8,
+    len: usize,
+    handle_out: *mut u64,
+) -> FfiErrorCode {
+    catch_panic("constraint_register_encoded_ffi", || {
+        if orchestrator.is_null() || handle_out.is_null() || bytes.is_null() {
+            set_last_error("constraint_register_encoded_ffi: null pointer");
+            return FfiErrorCode::NullPointer;
+        }
+        match (*orchestrator).register_encoded(slice::from_raw_parts(bytes, len)) {
+            Ok(handle) => {
+                *handle_out = handle.0;
+                FfiErrorCode::Success
+            }
+            Err(err) => {
+                set_last_error(format!("{:#}", err));
+                FfiErrorCode::InvalidInput
+            }
+        }
+    })
+}
+
+/// Release a handle returned by `constraint_register_ffi`
+///
+/// Returns `DoubleFree` for a handle already released and `InvalidPointer`
+/// for one never issued.
+///
+/// # Safety
+/// `orchestrator` must be null or a live `MazeOrchestrator`.
+#[no_mangle]
+pub unsafe extern "C" fn constraint_release_ffi(
+    orchestrator: *const crate::MazeOrchestrator,
+    handle: u64,
+) -> FfiErrorCode {
+    catch_panic("constraint_release_ffi", || {
+        if orchestrator.is_null() {
+            set_last_error("constraint_release_ffi: null pointer");
+            return FfiErrorCode::NullPointer;
+        }
+        match (*orchestrator).release_constraints(crate::ConstraintHandle(handle)) {
+            Ok(()) => FfiErrorCode::Success,
+            Err(err) => {
+                set_last_error(err.to_string());
+                if err.released {
+                    FfiErrorCode::DoubleFree
+                } else {
+                    FfiErrorCode::InvalidPointer
+                }
+            }
+        }
+    })
+}
+
+/// Generate code for `intent` under the set registered as `handle`
+///
+/// On success a result is written to `result_out`; free it with
+/// `free_generation_result_ffi`. A failed generation is still a result,
+/// with `success` false and the error set. A released handle returns
+/// `UseAfterFree` and an unknown one `InvalidPointer`, with no result.
+///
+/// Blocks until the generation finishes. Must not be called from a thread
+/// running an async runtime.
+///
+/// # Safety
+/// `orchestrator` must be null or a live `MazeOrchestrator`; `intent` must
+/// be null or a valid `IntentFFI`; `result_out` must be null or valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn generate_registered_ffi(
+    orchestrator: *const crate::MazeOrchestrator,
+    handle: u64,
+    intent: *const IntentFFI,
+    result_out: *mut *mut GenerationResultFFI,
+) -> FfiErrorCode {
+    catch_panic("generate_registered_ffi", || {
+        if orchestrator.is_null() || result_out.is_null() {
+            set_last_error("generate_registered_ffi: null pointer");
+            return FfiErrorCode::NullPointer;
+        }
+        let intent = match Intent::from_ffi(intent) {
+            Ok(intent) => intent,
+            Err(message) => {
+                set_last_error(message);
+                return FfiErrorCode::InvalidInput;
+            }
+        };
+        let orchestrator = &*orchestrator;
+        let request =
+            orchestrator.intent_to_request(&intent, Vec::new(), crate::GenerationParams::default());
+        let outcome = ffi_runtime()
+            .block_on(orchestrator.generate_registered(crate::ConstraintHandle(handle), request));
+
+        let result = match outcome {
+            Ok(response) => GenerationResult {
+                code: response.code,
+                success: true,
+                error: None,
+                tokens_generated: response.metadata.tokens_generated,
+                generation_time_ms: response.metadata.generation_time_ms,
+            },
+            Err(err) => {
+                if let Some(unknown) = err.downcast_ref::<crate::UnknownConstraintHandle>() {
+                    set_last_error(unknown.to_string());
+                    return if unknown.released {
+                        FfiErrorCode::UseAfterFree
+                    } else {
+                        FfiErrorCode::InvalidPointer
+                    };
+                }
+                GenerationResult {
+                    code: String::new(),
+                    success: false,
+                    error: Some(format!("{:#}", err)),
+                    tokens_generated: 0,
+                    generation_time_ms: 0,
+                }
+            }
+        };
+        *result_out = result.to_ffi();
+        FfiErrorCode::Success
+    })
 }
 
 // ============================================================================
@@ -765,6 +1298,7 @@ mod tests {
             rich_context: None,
             feasibility_score: 0.0,
             is_feasible: true,
+            schema_version: CONSTRAINT_SCHEMA_VERSION,
         };
 
         let ffi = constraint.to_ffi();
@@ -779,6 +1313,180 @@ mod tests {
         }
     }
 
+    fn grammar_constraint(start_symbol: &str) -> ConstraintIR {
+        ConstraintIR {
+            name: "expr".to_string(),
+            json_schema: None,
+            grammar: Some(Grammar {
+                rules: vec![GrammarRule {
+                    lhs: "expr".to_string(),
+                    rhs: vec!["'x'".to_string()],
+                }],
+                start_symbol: start_symbol.to_string(),
+            }),
+            regex_patterns: vec![],
+            token_masks: None,
+            type_inhabitation: None,
+            priority: 1,
+            rich_context: None,
+            feasibility_score: 0.0,
+            is_feasible: true,
+            schema_version: CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    #[test]
+    fn test_from_ffi_rejects_dangling_start_symbol() {
+        let ffi = grammar_constraint("stmt").to_ffi();
+        unsafe {
+            let err = ConstraintIR::from_ffi(ffi).unwrap_err();
+            assert_eq!(err.code, FfiErrorCode::InvalidInput);
+            assert_eq!(
+                err.message,
+                "invalid constraint 'expr': start symbol 'stmt' has no rule"
+            );
+            free_constraint_ir_ffi(ffi);
+        }
+    }
+
+    #[test]
+    fn test_validate_checks_name_flags_and_schema() {
+        assert!(grammar_constraint("expr").validate().is_ok());
+
+        let mut unnamed = grammar_constraint("expr");
+        unnamed.name = " ".to_string();
+        assert!(unnamed
+            .validate()
+            .unwrap_err()
+            .reason
+            .contains("name is empty"));
+
+        let mut flagged = grammar_constraint("expr");
+        flagged.regex_patterns.push(RegexPattern {
+            pattern: "[a-z]+".to_string(),
+            flags: "iq".to_string(),
+        });
+        assert!(flagged
+            .validate()
+            .unwrap_err()
+            .reason
+            .contains("unsupported regex flag 'q'"));
+
+        let mut schema = grammar_constraint("expr");
+        schema.json_schema = Some(JsonSchema {
+            schema_type: "object".to_string(),
+            properties: HashMap::new(),
+            required: vec!["id".to_string()],
+            additional_properties: false,
+        });
+        assert!(schema
+            .validate()
+            .unwrap_err()
+            .reason
+            .contains("required property 'id'"));
+    }
+
+    #[test]
+    fn test_from_ffi_rejects_non_utf8_regex() {
+        let pattern = CString::new(vec![b'a', 0xff, b'b']).unwrap();
+        let patterns = [pattern.as_ptr()];
+        let name = CString::new("bad_regex").unwrap();
+        let ffi = ConstraintIRFFI {
+            magic: FFI_MAGIC,
+            json_schema: ptr::null(),
+            grammar: ptr::null(),
+            regex_patterns: patterns.as_ptr(),
+            regex_patterns_len: 1,
+            token_masks: ptr::null(),
+            priority: 0,
+            name: name.as_ptr(),
+        };
+        unsafe {
+            let err = ConstraintIR::from_ffi(&ffi).unwrap_err();
+            assert_eq!(err.code, FfiErrorCode::InvalidInput);
+            assert!(err.message.starts_with("Invalid UTF-8 in regex pattern"));
+        }
+    }
+
+    #[test]
+    fn test_validate_constraint_ir_ffi_reports_code_and_message() {
+        unsafe {
+            let mut message: *mut c_char = ptr::null_mut();
+            assert_eq!(
+                validate_constraint_ir_ffi(ptr::null(), &mut message),
+                FfiErrorCode::NullPointer
+            );
+            free_ffi_string(message);
+
+            let valid = grammar_constraint("expr").to_ffi();
+            assert_eq!(
+                validate_constraint_ir_ffi(valid, &mut message),
+                FfiErrorCode::Success
+            );
+            assert!(message.is_null());
+            free_constraint_ir_ffi(valid);
+
+            let invalid = grammar_constraint("stmt").to_ffi();
+            assert_eq!(
+                validate_constraint_ir_ffi(invalid, &mut message),
+                FfiErrorCode::InvalidInput
+            );
+            assert!(CStr::from_ptr(message)
+                .to_str()
+                .unwrap()
+                .contains("start symbol 'stmt'"));
+            free_ffi_string(message);
+            free_constraint_ir_ffi(invalid);
+        }
+    }
+
+    /// An entry point whose body panics on an out-of-range index
+    extern "C" fn panicking_entry_point(index: usize) -> FfiErrorCode {
+        catch_panic("panicking_entry_point", || {
+            let codes = [FfiErrorCode::Success];
+            codes[index]
+        })
+    }
+
+    fn take_last_error() -> Option<String> {
+        let message = take_last_error_ffi();
+        if message.is_null() {
+            return None;
+        }
+        unsafe {
+            let text = CStr::from_ptr(message).to_str().unwrap().to_string();
+            free_ffi_string(message);
+            Some(text)
+        }
+    }
+
+    #[test]
+    fn test_panic_in_entry_point_becomes_error_code() {
+        take_last_error();
+        assert_eq!(panicking_entry_point(0), FfiErrorCode::Success);
+        assert_eq!(take_last_error(), None);
+
+        assert_eq!(panicking_entry_point(7), FfiErrorCode::Panic);
+        let message = take_last_error().unwrap();
+        assert!(message.starts_with("panic in panicking_entry_point: index out of bounds"));
+        // Taking the message clears it
+        assert_eq!(take_last_error(), None);
+    }
+
+    #[test]
+    fn test_failed_validation_sets_last_error() {
+        take_last_error();
+        let invalid = grammar_constraint("stmt").to_ffi();
+        unsafe {
+            assert_eq!(
+                validate_constraint_ir_ffi(invalid, ptr::null_mut()),
+                FfiErrorCode::InvalidInput
+            );
+            assert_eq!(free_constraint_ir_ffi(invalid), FfiErrorCode::Success);
+        }
+        assert!(take_last_error().unwrap().contains("start symbol 'stmt'"));
+    }
+
     #[test]
     fn test_generation_result_to_ffi() {
         let result = GenerationResult {
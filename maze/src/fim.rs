@@ -0,0 +1,412 @@
+//! Fill-in-the-middle generation for several holes in one request
+//!
+//! Code to be completed marks each hole with `<HOLE_n>`, where `n` is the
+//! [`HoleSpec::hole_id`]. Models that advertise
+//! [`ModelInfo::supports_fim`](crate::ModelInfo::supports_fim) get the whole
+//! file in a single prompt and answer with one `<HOLE_n>fill</HOLE_n>` block
+//! per hole; other models, and any hole missing from a FIM answer, are filled
+//! with one request per hole.
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::ffi::{ConstraintIR, HoleSpec};
+use crate::inference::InferenceClient;
+use crate::modal_client::InferenceRequest;
+
+/// Default output budget per hole
+pub const DEFAULT_MAX_TOKENS_PER_HOLE: usize = 256;
+
+/// Marker for a hole in code, e.g. `<HOLE_3>`
+pub fn hole_marker(hole_id: u64) -> String {
+    format!("<HOLE_{}>", hole_id)
+}
+
+/// Closing marker ending a hole's fill in a FIM answer, e.g. `</HOLE_3>`
+fn closing_marker(hole_id: u64) -> String {
+    format!("</HOLE_{}>", hole_id)
+}
+
+/// Code with several marked holes to fill together
+#[derive(Debug, Clone)]
+pub struct MultiHoleRequest {
+    /// Code containing one `<HOLE_n>` marker per hole
+    pub code: String,
+
+    /// The holes, in the order their fills are requested
+    pub holes: Vec<HoleSpec>,
+
+    /// Output budget per hole; a FIM request gets the sum
+    pub max_tokens_per_hole: usize,
+
+    /// Constraints sent with every request
+    pub constraints: serde_json::Value,
+}
+
+impl MultiHoleRequest {
+    /// Create a request, checking every hole is marked exactly once in `code`
+    pub fn new(code: impl Into<String>, holes: Vec<HoleSpec>) -> Result<Self> {
+        let code = code.into();
+        if holes.is_empty() {
+            bail!("multi-hole request has no holes");
+        }
+        for hole in &holes {
+            let marker = hole_marker(hole.hole_id);
+            match code.matches(&marker).count() {
+                1 => {}
+                0 => bail!("hole {} has no {} marker in the code", hole.hole_id, marker),
+                n => bail!(
+                    "hole {} marker {} appears {} times",
+                    hole.hole_id,
+                    marker,
+                    n
+                ),
+            }
+        }
+
+        Ok(Self {
+            code,
+            holes,
+            max_tokens_per_hole: DEFAULT_MAX_TOKENS_PER_HOLE,
+            constraints: serde_json::json!([]),
+        })
+    }
+
+    /// Set the output budget per hole
+    pub fn with_max_tokens_per_hole(mut self, max_tokens: usize) -> Self {
+        self.max_tokens_per_hole = max_tokens;
+        self
+    }
+
+    /// Send these constraints with every request
+    pub fn with_constraints(mut self, constraints_ir: &[ConstraintIR]) -> Result<Self> {
+        self.constraints = serde_json::to_value(constraints_ir)?;
+        Ok(self)
+    }
+
+    /// IDs of the holes, in request order
+    pub fn hole_ids(&self) -> Vec<u64> {
+        self.holes.iter().map(|h| h.hole_id).collect()
+    }
+
+    /// Prompt asking for every hole in one answer
+    pub fn fim_prompt(&self) -> String {
+        let mut prompt = String::from("Fill in every <HOLE_n> marker in the code below.\n\n");
+        prompt.push_str(&format!("Code:\n{}\n", self.code));
+        self.push_requirements(&mut prompt, &self.holes);
+
+        prompt.push_str(
+            "\nAnswer with each fill wrapped in its markers, in this order, and nothing else:\n",
+        );
+        for hole in &self.holes {
+            prompt.push_str(&format!(
+                "{}...{}\n",
+                hole_marker(hole.hole_id),
+                closing_marker(hole.hole_id)
+            ));
+        }
+        prompt
+    }
+
+    /// Prompt asking for a single hole; other markers are shown as `...`
+    pub fn single_hole_prompt(&self, hole_id: u64) -> String {
+        let mut code = self.code.clone();
+        for other in self.holes.iter().filter(|h| h.hole_id != hole_id) {
+            code = code.replace(&hole_marker(other.hole_id), "...");
+        }
+
+        let mut prompt = format!(
+            "Fill in the {} marker in the code below.\n\n",
+            hole_marker(hole_id)
+        );
+        prompt.push_str(&format!("Code:\n{}\n", code));
+        let hole: Vec<HoleSpec> = self
+            .holes
+            .iter()
+            .filter(|h| h.hole_id == hole_id)
+            .cloned()
+            .collect();
+        self.push_requirements(&mut prompt, &hole);
+        prompt.push_str("\nAnswer with only the code that replaces the marker.\n");
+        prompt
+    }
+
+    /// Single request filling every hole
+    pub fn to_fim_request(&self, temperature: f32) -> InferenceRequest {
+        InferenceRequest {
+            prompt: self.fim_prompt(),
+            constraints: self.constraints.clone(),
+            max_tokens: self.max_tokens_per_hole * self.holes.len(),
+            temperature,
+            context: None,
+            seed: None,
+            idempotency_key: None,
+            include_logprobs: false,
+            timeout_ms: None,
+            include_constraint_events: false,
+        }
+    }
+
+    /// Request filling one hole
+    pub fn to_single_request(&self, hole_id: u64, temperature: f32) -> InferenceRequest {
+        InferenceRequest {
+            prompt: self.single_hole_prompt(hole_id),
+            constraints: self.constraints.clone(),
+            max_tokens: self.max_tokens_per_hole,
+            temperature,
+            context: None,
+            seed: None,
+            idempotency_key: None,
+            include_logprobs: false,
+            timeout_ms: None,
+            include_constraint_events: false,
+        }
+    }
+
+    /// List each hole's fill constraints, schema, and grammar
+    fn push_requirements(&self, prompt: &mut String, holes: &[HoleSpec]) {
+        let described: Vec<&HoleSpec> = holes
+            .iter()
+            .filter(|h| {
+                !h.fill_constraints.is_empty()
+                    || h.fill_schema.is_some()
+                    || h.fill_grammar.is_some()
+                    || h.grammar_ref.is_some()
+            })
+            .collect();
+        if described.is_empty() {
+            return;
+        }
+
+        prompt.push_str("\nRequirements:\n");
+        for hole in described {
+            prompt.push_str(&format!("{}:\n", hole_marker(hole.hole_id)));
+            for constraint in &hole.fill_constraints {
+                prompt.push_str(&format!("- {}: {}\n", constraint.kind, constraint.value));
+            }
+            if let Some(ref schema) = hole.fill_schema {
+                prompt.push_str(&format!(
+                    "- schema: {}\n",
+                    serde_json::to_string(schema).unwrap_or_default()
+                ));
+            }
+            if let Some(ref grammar) = hole.fill_grammar {
+                prompt.push_str(&format!("- grammar start: {}\n", grammar.start_symbol));
+            }
+            if let Some(ref grammar_ref) = hole.grammar_ref {
+                prompt.push_str(&format!("- grammar: {}\n", grammar_ref));
+            }
+        }
+    }
+}
+
+/// Extract per-hole fills from a FIM answer
+///
+/// A fill runs from `<HOLE_n>` to `</HOLE_n>`, or to the next `<HOLE_`
+/// marker (or the end) if the closing marker is missing. Holes that do not
+/// appear in `text` are absent from the result.
+pub fn parse_fim_completion(text: &str, hole_ids: &[u64]) -> HashMap<u64, String> {
+    let mut fills = HashMap::new();
+
+    for &hole_id in hole_ids {
+        let marker = hole_marker(hole_id);
+        let Some(start) = text.find(&marker).map(|i| i + marker.len()) else {
+            continue;
+        };
+        let rest = &text[start..];
+        let end = rest
+            .find(&closing_marker(hole_id))
+            .or_else(|| rest.find("<HOLE_"))
+            .unwrap_or(rest.len());
+
+        let fill = rest[..end].trim_matches('\n');
+        fills.insert(hole_id, fill.to_string());
+    }
+
+    fills
+}
+
+/// Fills produced for a [`MultiHoleRequest`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiHoleFill {
+    /// Fill for each hole, by hole ID
+    pub fills: HashMap<u64, String>,
+
+    /// Whether a single FIM request was used
+    pub used_fim: bool,
+
+    /// Number of inference requests made
+    pub requests: usize,
+
+    /// Model that produced the fills
+    pub model: String,
+}
+
+impl MultiHoleFill {
+    /// `code` with every marker replaced by its fill
+    pub fn apply(&self, code: &str) -> String {
+        self.fills
+            .iter()
+            .fold(code.to_string(), |code, (id, fill)| {
+                code.replace(&hole_marker(*id), fill)
+            })
+    }
+}
+
+/// Fill every hole in `request`, in one pass when the model supports FIM
+///
+/// Holes the FIM answer leaves out are retried individually. Without FIM
+/// support, holes are filled concurrently with one request each.
+pub async fn fill_holes(
+    client: &dyn InferenceClient,
+    request: &MultiHoleRequest,
+    temperature: f32,
+) -> Result<MultiHoleFill> {
+    let info = client.model_info().await?;
+    let hole_ids = request.hole_ids();
+
+    let mut fills = HashMap::new();
+    let mut requests = 0;
+    let used_fim = info.supports_fim && hole_ids.len() > 1;
+
+    if used_fim {
+        let response = client
+            .generate_constrained(request.to_fim_request(temperature))
+            .await?;
+        requests += 1;
+        fills = parse_fim_completion(&response.generated_text, &hole_ids);
+
+        if fills.len() < hole_ids.len() {
+            tracing::warn!(
+                "FIM answer filled {}/{} holes; filling the rest individually",
+                fills.len(),
+                hole_ids.len()
+            );
+        }
+    }
+
+    let missing: Vec<u64> = hole_ids
+        .iter()
+        .copied()
+        .filter(|id| !fills.contains_key(id))
+        .collect();
+    let single_fills = futures::future::try_join_all(missing.iter().map(|&hole_id| async move {
+        let response = client
+            .generate_constrained(request.to_single_request(hole_id, temperature))
+            .await
+            .map_err(|e| anyhow!("Failed to fill hole {}: {}", hole_id, e))?;
+        Ok::<_, anyhow::Error>((hole_id, response.generated_text))
+    }))
+    .await?;
+    requests += single_fills.len();
+    fills.extend(single_fills);
+
+    Ok(MultiHoleFill {
+        fills,
+        used_fim,
+        requests,
+        model: info.name,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::FillConstraint;
+    use crate::inference::MockInferenceClient;
+    use crate::modal_client::ModelInfo;
+
+    fn request() -> MultiHoleRequest {
+        let code = "fn add(a: i32, b: i32) -> i32 { <HOLE_1> }\nconst ZERO: i32 = <HOLE_2>;";
+        let holes = vec![
+            HoleSpec::new(1)
+                .with_constraint(FillConstraint::new("type".to_string(), "i32".to_string())),
+            HoleSpec::new(2),
+        ];
+        MultiHoleRequest::new(code, holes).unwrap()
+    }
+
+    fn fim_model() -> ModelInfo {
+        ModelInfo {
+            supports_fim: true,
+            ..ModelInfo::from_name("mock")
+        }
+    }
+
+    #[test]
+    fn test_new_rejects_missing_or_repeated_markers() {
+        assert!(MultiHoleRequest::new("no markers", vec![HoleSpec::new(1)]).is_err());
+        assert!(MultiHoleRequest::new("<HOLE_1> <HOLE_1>", vec![HoleSpec::new(1)]).is_err());
+        assert!(MultiHoleRequest::new("code", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_fim_prompt_lists_holes_and_requirements() {
+        let prompt = request().fim_prompt();
+        assert!(prompt.contains("<HOLE_1>:\n- type: i32\n"));
+        assert!(prompt.ends_with("<HOLE_1>...</HOLE_1>\n<HOLE_2>...</HOLE_2>\n"));
+
+        let single = request().single_hole_prompt(2);
+        assert!(single.contains("{ ... }"));
+        assert!(single.contains("= <HOLE_2>;"));
+    }
+
+    #[test]
+    fn test_parse_fim_completion() {
+        let text = "<HOLE_1>\na + b\n</HOLE_1>\n<HOLE_2>0\n<HOLE_9>ignored</HOLE_9>";
+        let fills = parse_fim_completion(text, &[1, 2, 3]);
+        assert_eq!(fills[&1], "a + b");
+        assert_eq!(fills[&2], "0");
+        assert!(!fills.contains_key(&3));
+        assert!(!fills.contains_key(&9));
+    }
+
+    #[tokio::test]
+    async fn test_fill_holes_uses_one_fim_request() {
+        let client = MockInferenceClient::new("mock")
+            .with_model_info(fim_model())
+            .then_respond("<HOLE_1>a + b</HOLE_1><HOLE_2>0</HOLE_2>");
+
+        let result = fill_holes(&client, &request(), 0.2).await.unwrap();
+        assert!(result.used_fim);
+        assert_eq!(result.requests, 1);
+        assert_eq!(
+            client.requests()[0].max_tokens,
+            2 * DEFAULT_MAX_TOKENS_PER_HOLE
+        );
+        assert_eq!(
+            result.apply(&request().code),
+            "fn add(a: i32, b: i32) -> i32 { a + b }\nconst ZERO: i32 = 0;"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fill_holes_retries_holes_missing_from_fim_answer() {
+        let client = MockInferenceClient::new("mock")
+            .with_model_info(fim_model())
+            .then_respond("<HOLE_1>a + b</HOLE_1>")
+            .then_respond("0");
+
+        let result = fill_holes(&client, &request(), 0.2).await.unwrap();
+        assert_eq!(result.requests, 2);
+        assert_eq!(result.fills[&2], "0");
+        assert!(client.requests()[1]
+            .prompt
+            .starts_with("Fill in the <HOLE_2>"));
+    }
+
+    #[tokio::test]
+    async fn test_fill_holes_without_fim_sends_one_request_per_hole() {
+        let client = MockInferenceClient::new("mock").with_default_response("x");
+
+        let result = fill_holes(&client, &request(), 0.2).await.unwrap();
+        assert!(!result.used_fim);
+        assert_eq!(result.requests, 2);
+        assert_eq!(client.requests().len(), 2);
+        assert!(client
+            .requests()
+            .iter()
+            .all(|r| r.max_tokens == DEFAULT_MAX_TOKENS_PER_HOLE));
+    }
+}
@@ -0,0 +1,442 @@
+//! Formatting generated code before it is returned
+//!
+//! Generated code often mixes indentation styles or leaves trailing
+//! whitespace that users reformat as soon as it is inserted. A [`Formatter`]
+//! registered for a language in [`Formatters`] runs over the output after
+//! post-processing and before validation, under `MazeConfig::format_policy`.
+//! [`CommandFormatter`] shells out to a tool such as rustfmt or black;
+//! [`WhitespaceNormalizer`] is a built-in fallback for any language.
+//!
+//! A formatter that fails, say on a fragment the tool cannot parse, leaves
+//! the code as it was: the failure is recorded as a [`FormatOutcome`], not
+//! returned as an error.
+
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write as _};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::syntax::SyntaxValidators;
+
+/// When generated code is formatted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FormatPolicy {
+    /// Never format
+    #[default]
+    Off,
+
+    /// Format whenever a formatter is registered for the language
+    Always,
+
+    /// Format only code the language's syntax validator accepts, since most
+    /// formatters reject code that does not parse
+    WhenValid,
+}
+
+/// What formatting did to one generation, recorded in
+/// `ValidationResult::metadata` under `formatting`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FormatOutcome {
+    /// The formatter ran; `changed` if its output differs from its input
+    Formatted { changed: bool },
+
+    /// [`FormatPolicy::WhenValid`] skipped code with syntax errors
+    Skipped { syntax_errors: usize },
+
+    /// The formatter failed and the code was returned unformatted
+    Failed { error: String },
+}
+
+/// Rewrites generated code in a language's canonical style
+pub trait Formatter: Send + Sync {
+    /// `code` formatted, or why it could not be
+    fn format(&self, code: &str) -> Result<String>;
+}
+
+/// Formats by piping code through an external program
+///
+/// The program reads the code on stdin and writes the formatted code to
+/// stdout; a non-zero exit status is a failure, reported with its stderr.
+/// A program still running after the timeout is killed, and that too is a
+/// failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandFormatter {
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl CommandFormatter {
+    /// How long a formatter may run unless [`with_timeout`](Self::with_timeout)
+    /// says otherwise
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Run `program` with `args`
+    pub fn new(
+        program: impl Into<String>,
+        args: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        Self {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            timeout: Self::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Kill the program if it runs longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// `rustfmt`, for Rust
+    pub fn rustfmt() -> Self {
+        Self::new("rustfmt", ["--edition", "2021"])
+    }
+
+    /// `black`, for Python
+    pub fn black() -> Self {
+        Self::new("black", ["--quiet", "-"])
+    }
+}
+
+impl Formatter for CommandFormatter {
+    fn format(&self, code: &str) -> Result<String> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run formatter `{}`", self.program))?;
+
+        // Write and read from other threads so a formatter that streams its
+        // output cannot fill a pipe while we are still writing, and so we
+        // can keep watching the clock
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = code.to_string();
+        let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let stdout = drain(child.stdout.take().expect("stdout is piped"));
+        let stderr = drain(child.stderr.take().expect("stderr is piped"));
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            let exited = child
+                .try_wait()
+                .with_context(|| format!("formatter `{}` did not finish", self.program))?;
+            if let Some(status) = exited {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                // Killing closes the pipes, which ends the helper threads
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!(
+                    "formatter `{}` timed out after {:?}",
+                    self.program,
+                    self.timeout
+                );
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        let written = writer.join().expect("formatter input thread panicked");
+        let stdout = stdout.join().expect("formatter output thread panicked")?;
+        let stderr = stderr.join().expect("formatter output thread panicked")?;
+
+        // A formatter that exits early closes stdin; its stderr says why
+        if !status.success() {
+            bail!(
+                "formatter `{}` exited with {}: {}",
+                self.program,
+                status,
+                String::from_utf8_lossy(&stderr).trim()
+            );
+        }
+        written.with_context(|| format!("failed to write to formatter `{}`", self.program))?;
+        String::from_utf8(stdout)
+            .with_context(|| format!("formatter `{}` wrote invalid UTF-8", self.program))
+    }
+}
+
+/// Read `pipe` to the end on its own thread
+fn drain(
+    mut pipe: impl Read + Send + 'static,
+) -> std::thread::JoinHandle<std::io::Result<Vec<u8>>> {
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        pipe.read_to_end(&mut buf).map(|_| buf)
+    })
+}
+
+/// Built-in formatter that only normalizes whitespace
+///
+/// Converts CRLF line endings to LF, strips trailing whitespace, and, with
+/// `tab_width` set, expands tabs in indentation to that many spaces.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WhitespaceNormalizer {
+    /// Spaces per indentation tab; `None` leaves tabs alone
+    pub tab_width: Option<usize>,
+}
+
+impl Formatter for WhitespaceNormalizer {
+    fn format(&self, code: &str) -> Result<String> {
+        let lines: Vec<String> = code
+            .split('\n')
+            .map(|line| {
+                let line = line.trim_end();
+                match self.tab_width {
+                    Some(width) => {
+                        let body = line.trim_start_matches([' ', '\t']);
+                        let indent = &line[..line.len() - body.len()];
+                        format!("{}{}", indent.replace('\t', &" ".repeat(width)), body)
+                    }
+                    None => line.to_string(),
+                }
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+}
+
+/// Formatters keyed by language name (case-insensitive)
+#[derive(Clone, Default)]
+pub struct Formatters {
+    formatters: HashMap<String, Arc<dyn Formatter>>,
+}
+
+impl std::fmt::Debug for Formatters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut languages: Vec<&String> = self.formatters.keys().collect();
+        languages.sort();
+        f.debug_struct("Formatters")
+            .field("languages", &languages)
+            .finish()
+    }
+}
+
+impl Formatters {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `formatter` for `language`, replacing any existing one
+    pub fn register(&mut self, language: &str, formatter: Arc<dyn Formatter>) {
+        self.formatters
+            .insert(language.to_ascii_lowercase(), formatter);
+    }
+
+    /// Formatter for `language`, if one is registered
+    pub fn get(&self, language: &str) -> Option<&Arc<dyn Formatter>> {
+        self.formatters.get(&language.to_ascii_lowercase())
+    }
+
+    /// Format `code` as `language` in place under `policy`
+    ///
+    /// `None` when nothing was attempted: the policy is
+    /// [`FormatPolicy::Off`] or no formatter is registered for the
+    /// language. `syntax` decides [`FormatPolicy::WhenValid`]. The formatter
+    /// runs on the blocking thread pool, since it may wait on a process.
+    pub async fn apply(
+        &self,
+        policy: FormatPolicy,
+        language: Option<&str>,
+        code: &mut String,
+        syntax: &SyntaxValidators,
+    ) -> Option<FormatOutcome> {
+        if policy == FormatPolicy::Off {
+            return None;
+        }
+        let formatter = self.get(language?)?;
+        if policy == FormatPolicy::WhenValid {
+            let syntax_errors = syntax.validate(language, code).len();
+            if syntax_errors > 0 {
+                return Some(FormatOutcome::Skipped { syntax_errors });
+            }
+        }
+        let formatter = Arc::clone(formatter);
+        let input = code.clone();
+        let formatted = tokio::task::spawn_blocking(move || formatter.format(&input))
+            .await
+            .unwrap_or_else(|join| Err(anyhow::anyhow!("formatter panicked: {}", join)));
+        match formatted {
+            Ok(formatted) => {
+                let changed = formatted != *code;
+                *code = formatted;
+                Some(FormatOutcome::Formatted { changed })
+            }
+            Err(err) => {
+                tracing::warn!(
+                    language,
+                    "Returning unformatted code: formatter failed: {:#}",
+                    err
+                );
+                Some(FormatOutcome::Failed {
+                    error: format!("{:#}", err),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax::{SyntaxError, SyntaxValidator};
+
+    struct Failing;
+
+    impl Formatter for Failing {
+        fn format(&self, _code: &str) -> Result<String> {
+            bail!("cannot parse fragment")
+        }
+    }
+
+    struct RejectsTabs;
+
+    impl SyntaxValidator for RejectsTabs {
+        fn validate(&self, code: &str) -> Vec<SyntaxError> {
+            code.find('\t')
+                .map(|i| vec![SyntaxError::at(code, i, i + 1, "tab")])
+                .unwrap_or_default()
+        }
+    }
+
+    fn formatters() -> Formatters {
+        let mut formatters = Formatters::new();
+        formatters.register(
+            "Python",
+            Arc::new(WhitespaceNormalizer { tab_width: Some(4) }),
+        );
+        formatters.register("rust", Arc::new(Failing));
+        formatters
+    }
+
+    #[test]
+    fn test_whitespace_normalizer() {
+        let normalizer = WhitespaceNormalizer { tab_width: Some(2) };
+        assert_eq!(
+            normalizer.format("if x:  \r\n\treturn 'a\tb'\r\n").unwrap(),
+            "if x:\n  return 'a\tb'\n"
+        );
+        assert_eq!(
+            WhitespaceNormalizer::default().format("\tx \n").unwrap(),
+            "\tx\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_formats_registered_languages() {
+        let syntax = SyntaxValidators::new();
+        let mut code = "def f():\n\treturn 1  ".to_string();
+        let outcome = formatters()
+            .apply(FormatPolicy::Always, Some("python"), &mut code, &syntax)
+            .await;
+        assert_eq!(outcome, Some(FormatOutcome::Formatted { changed: true }));
+        assert_eq!(code, "def f():\n    return 1");
+
+        let outcome = formatters()
+            .apply(FormatPolicy::Always, Some("python"), &mut code, &syntax)
+            .await;
+        assert_eq!(outcome, Some(FormatOutcome::Formatted { changed: false }));
+    }
+
+    #[tokio::test]
+    async fn test_apply_skips_off_unregistered_and_invalid() {
+        let mut syntax = SyntaxValidators::new();
+        syntax.register("python", Arc::new(RejectsTabs));
+        let original = "def f():\n\treturn 1".to_string();
+
+        let mut code = original.clone();
+        let formatters = formatters();
+        assert_eq!(
+            formatters
+                .apply(FormatPolicy::Off, Some("python"), &mut code, &syntax)
+                .await,
+            None
+        );
+        assert_eq!(
+            formatters
+                .apply(FormatPolicy::Always, Some("go"), &mut code, &syntax)
+                .await,
+            None
+        );
+        assert_eq!(
+            formatters
+                .apply(FormatPolicy::Always, None, &mut code, &syntax)
+                .await,
+            None
+        );
+        assert_eq!(
+            formatters
+                .apply(FormatPolicy::WhenValid, Some("python"), &mut code, &syntax)
+                .await,
+            Some(FormatOutcome::Skipped { syntax_errors: 1 })
+        );
+        assert_eq!(code, original);
+    }
+
+    #[tokio::test]
+    async fn test_failed_formatter_keeps_code() {
+        let mut code = "fn f() {}".to_string();
+        let outcome = formatters()
+            .apply(
+                FormatPolicy::Always,
+                Some("rust"),
+                &mut code,
+                &SyntaxValidators::new(),
+            )
+            .await;
+        assert_eq!(
+            outcome,
+            Some(FormatOutcome::Failed {
+                error: "cannot parse fragment".to_string()
+            })
+        );
+        assert_eq!(code, "fn f() {}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_formatter() {
+        let upper = CommandFormatter::new("tr", ["a-z", "A-Z"]);
+        assert_eq!(upper.format("let x = 1;").unwrap(), "LET X = 1;");
+
+        let failing = CommandFormatter::new("sh", ["-c", "echo bad input >&2; exit 3"]);
+        let err = failing.format("x").unwrap_err().to_string();
+        assert!(err.contains("bad input"), "{}", err);
+
+        let missing = CommandFormatter::new("maze-no-such-formatter", Vec::<String>::new());
+        assert!(missing.format("x").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_formatter_times_out() {
+        let hanging = CommandFormatter::new("sleep", ["5"]).with_timeout(Duration::from_millis(50));
+        let mut formatters = Formatters::new();
+        formatters.register("rust", Arc::new(hanging));
+
+        let started = Instant::now();
+        let mut code = "fn f() {}".to_string();
+        let outcome = formatters
+            .apply(
+                FormatPolicy::Always,
+                Some("rust"),
+                &mut code,
+                &SyntaxValidators::new(),
+            )
+            .await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let Some(FormatOutcome::Failed { error }) = outcome else {
+            panic!("expected a failure, got {:?}", outcome);
+        };
+        assert!(error.contains("timed out"), "{}", error);
+        assert_eq!(code, "fn f() {}");
+    }
+}
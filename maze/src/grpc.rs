@@ -0,0 +1,470 @@
+//! gRPC transport for [`ModalClient`](crate::ModalClient)
+//!
+//! Speaks the `maze.inference.v1.Inference` service described in
+//! `proto/inference.proto`. The protobuf messages are derived by hand so
+//! building maze does not need `protoc`. Compiled constraints and context
+//! stay JSON inside the protobuf envelope; the win over HTTP is avoiding a
+//! connection and JSON body per request when filling many holes.
+
+use anyhow::{anyhow, Context, Result};
+use futures::stream::StreamExt;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::metadata::{MetadataKey, MetadataValue};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+
+use crate::modal_client::{
+    ConstraintEvent, ConstraintKindStats, FinishReason, GenerationStats, InferenceRequest,
+    InferenceResponse, ModalConfig, StreamChunk, StreamingResult, TokenLogprob, TopLogprob,
+    USER_AGENT,
+};
+
+/// Fully qualified service name
+pub const SERVICE_NAME: &str = "maze.inference.v1.Inference";
+
+/// Path of the unary `Generate` RPC
+pub const GENERATE_PATH: &str = "/maze.inference.v1.Inference/Generate";
+
+/// Path of the server-streaming `GenerateStream` RPC
+pub const GENERATE_STREAM_PATH: &str = "/maze.inference.v1.Inference/GenerateStream";
+
+/// Metadata key carrying [`InferenceRequest::idempotency_key`]
+pub const IDEMPOTENCY_KEY_METADATA: &str = "idempotency-key";
+
+/// Metadata key carrying the request ID (see
+/// [`REQUEST_ID_HEADER`](crate::modal_client::REQUEST_ID_HEADER))
+pub const REQUEST_ID_METADATA: &str = "x-request-id";
+
+/// Protobuf form of [`InferenceRequest`]
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GenerateRequest {
+    #[prost(string, tag = "1")]
+    pub prompt: String,
+
+    /// Compiled constraints, JSON-encoded
+    #[prost(string, tag = "2")]
+    pub constraints_json: String,
+
+    #[prost(uint64, tag = "3")]
+    pub max_tokens: u64,
+
+    #[prost(float, tag = "4")]
+    pub temperature: f32,
+
+    #[prost(string, tag = "5")]
+    pub model: String,
+
+    /// [`GenerationContext`](crate::GenerationContext), JSON-encoded
+    #[prost(string, optional, tag = "6")]
+    pub context_json: Option<String>,
+
+    #[prost(uint64, optional, tag = "7")]
+    pub seed: Option<u64>,
+
+    #[prost(bool, tag = "8")]
+    pub include_logprobs: bool,
+
+    #[prost(bool, tag = "9")]
+    pub include_constraint_events: bool,
+}
+
+/// Protobuf form of [`GenerationStats`]
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GenerationStatsMessage {
+    #[prost(uint64, tag = "1")]
+    pub total_time_ms: u64,
+
+    #[prost(uint64, tag = "2")]
+    pub time_per_token_us: u64,
+
+    #[prost(uint64, tag = "3")]
+    pub constraint_checks: u64,
+
+    #[prost(uint64, tag = "4")]
+    pub avg_constraint_check_us: u64,
+
+    #[prost(btree_map = "string, message", tag = "5")]
+    pub by_kind: BTreeMap<String, ConstraintKindStatsMessage>,
+}
+
+/// Protobuf form of [`ConstraintKindStats`]
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConstraintKindStatsMessage {
+    #[prost(uint64, tag = "1")]
+    pub checks: u64,
+
+    #[prost(uint64, tag = "2")]
+    pub total_us: u64,
+}
+
+/// Protobuf form of [`InferenceResponse`]
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GenerateResponse {
+    #[prost(string, tag = "1")]
+    pub generated_text: String,
+
+    #[prost(uint64, tag = "2")]
+    pub tokens_generated: u64,
+
+    #[prost(string, tag = "3")]
+    pub model: String,
+
+    #[prost(message, optional, tag = "4")]
+    pub stats: Option<GenerationStatsMessage>,
+
+    /// Empty unless logprobs were requested
+    #[prost(message, repeated, tag = "5")]
+    pub logprobs: Vec<TokenLogprobMessage>,
+
+    /// Service-reported confidence, if any
+    #[prost(float, optional, tag = "6")]
+    pub confidence: Option<f32>,
+
+    /// Why generation ended, as parsed by [`FinishReason::parse`]
+    #[prost(string, optional, tag = "7")]
+    pub finish_reason: Option<String>,
+}
+
+/// Protobuf form of [`TokenLogprob`] and [`TopLogprob`]
+///
+/// `top_logprobs` is only set on top-level entries.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TokenLogprobMessage {
+    #[prost(string, tag = "1")]
+    pub token: String,
+
+    #[prost(float, tag = "2")]
+    pub logprob: f32,
+
+    #[prost(message, repeated, tag = "3")]
+    pub top_logprobs: Vec<TokenLogprobMessage>,
+}
+
+/// One message of the `GenerateStream` response
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GenerateChunk {
+    #[prost(string, tag = "1")]
+    pub text: String,
+
+    #[prost(bool, tag = "2")]
+    pub is_final: bool,
+
+    /// Why generation ended; set on the final chunk, if at all
+    #[prost(string, optional, tag = "3")]
+    pub finish_reason: Option<String>,
+
+    /// A masked token, when constraint events were requested
+    #[prost(message, optional, tag = "4")]
+    pub constraint_event: Option<ConstraintEventMessage>,
+}
+
+/// Protobuf form of [`ConstraintEvent`]
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ConstraintEventMessage {
+    #[prost(string, tag = "1")]
+    pub token: String,
+
+    #[prost(uint64, tag = "2")]
+    pub position: u64,
+
+    #[prost(string, tag = "3")]
+    pub constraint: String,
+}
+
+impl From<ConstraintEventMessage> for ConstraintEvent {
+    fn from(event: ConstraintEventMessage) -> Self {
+        Self {
+            token: event.token,
+            position: event.position as usize,
+            constraint: event.constraint,
+        }
+    }
+}
+
+impl GenerateRequest {
+    /// Encode `request` for `model`
+    pub fn from_inference(request: &InferenceRequest, model: &str) -> Result<Self> {
+        Ok(Self {
+            prompt: request.prompt.clone(),
+            constraints_json: serde_json::to_string(&request.constraints)
+                .context("Failed to encode constraints for gRPC")?,
+            max_tokens: request.max_tokens as u64,
+            temperature: request.temperature,
+            model: model.to_string(),
+            context_json: request
+                .context
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()
+                .context("Failed to encode context for gRPC")?,
+            seed: request.seed,
+            include_logprobs: request.include_logprobs,
+            include_constraint_events: request.include_constraint_events,
+        })
+    }
+}
+
+impl From<GenerateResponse> for InferenceResponse {
+    fn from(response: GenerateResponse) -> Self {
+        let stats = response.stats.unwrap_or_default();
+        Self {
+            generated_text: response.generated_text,
+            tokens_generated: response.tokens_generated as usize,
+            model: response.model,
+            stats: GenerationStats {
+                total_time_ms: stats.total_time_ms,
+                time_per_token_us: stats.time_per_token_us,
+                constraint_checks: stats.constraint_checks as usize,
+                avg_constraint_check_us: stats.avg_constraint_check_us,
+                by_kind: stats
+                    .by_kind
+                    .into_iter()
+                    .map(|(kind, s)| {
+                        let kind_stats = ConstraintKindStats {
+                            checks: s.checks as usize,
+                            total_us: s.total_us,
+                        };
+                        (kind, kind_stats)
+                    })
+                    .collect(),
+            },
+            attempts: Vec::new(),
+            logprobs: (!response.logprobs.is_empty()).then(|| {
+                response
+                    .logprobs
+                    .into_iter()
+                    .map(|entry| TokenLogprob {
+                        token: entry.token,
+                        logprob: entry.logprob,
+                        top_logprobs: entry
+                            .top_logprobs
+                            .into_iter()
+                            .map(|alt| TopLogprob {
+                                token: alt.token,
+                                logprob: alt.logprob,
+                            })
+                            .collect(),
+                    })
+                    .collect()
+            }),
+            reported_confidence: response.confidence,
+            request_id: None,
+            finish_reason: response.finish_reason.as_deref().map(FinishReason::parse),
+        }
+    }
+}
+
+/// Lazily connected gRPC channel to the inference service
+#[derive(Clone)]
+pub(crate) struct GrpcTransport {
+    grpc: tonic::client::Grpc<Channel>,
+    config: ModalConfig,
+}
+
+impl GrpcTransport {
+    /// Build the channel; the connection is made on first use
+    pub(crate) fn new(config: &ModalConfig) -> Result<Self> {
+        let mut endpoint = Endpoint::from_shared(config.endpoint_url.clone())
+            .context("Invalid gRPC endpoint URL")?
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .user_agent(config.user_agent.as_deref().unwrap_or(USER_AGENT))
+            .context("Invalid user agent")?;
+        if config.endpoint_url.starts_with("https://") {
+            endpoint = endpoint
+                .tls_config(ClientTlsConfig::new().with_native_roots())
+                .context("Failed to configure gRPC TLS")?;
+        }
+
+        Ok(Self {
+            grpc: tonic::client::Grpc::new(endpoint.connect_lazy()),
+            config: config.clone(),
+        })
+    }
+
+    /// Unary generation
+    pub(crate) async fn generate(
+        &self,
+        request: &InferenceRequest,
+        idempotency_key: Option<&str>,
+        request_id: &str,
+    ) -> Result<InferenceResponse> {
+        let message = GenerateRequest::from_inference(request, &self.config.model)?;
+        let mut grpc = self.ready().await?;
+        let response = grpc
+            .unary(
+                self.request(message, idempotency_key, request_id, request.timeout_ms)?,
+                PathAndQuery::from_static(GENERATE_PATH),
+                ProstCodec::<GenerateRequest, GenerateResponse>::default(),
+            )
+            .await
+            .map_err(|status| {
+                self.status_error(
+                    &format!("Modal gRPC inference (request {})", request_id),
+                    status,
+                )
+            })?;
+
+        Ok(response.into_inner().into())
+    }
+
+    /// Server-streaming generation
+    pub(crate) async fn generate_stream(
+        &self,
+        request: &InferenceRequest,
+        idempotency_key: Option<&str>,
+        request_id: &str,
+    ) -> Result<StreamingResult> {
+        let message = GenerateRequest::from_inference(request, &self.config.model)?;
+        let mut grpc = self.ready().await?;
+        let response = grpc
+            .server_streaming(
+                self.request(message, idempotency_key, request_id, request.timeout_ms)?,
+                PathAndQuery::from_static(GENERATE_STREAM_PATH),
+                ProstCodec::<GenerateRequest, GenerateChunk>::default(),
+            )
+            .await
+            .map_err(|status| {
+                self.status_error(
+                    &format!("Modal gRPC streaming inference (request {})", request_id),
+                    status,
+                )
+            })?;
+
+        let start_time = Instant::now();
+        let redaction = self.config.redaction;
+        let include_constraint_events = request.include_constraint_events;
+        let stream = response.into_inner().enumerate().map(move |(idx, result)| {
+            result
+                .map(|chunk| StreamChunk {
+                    text: chunk.text,
+                    is_final: chunk.is_final,
+                    token_index: idx,
+                    timestamp_ms: start_time.elapsed().as_millis() as u64,
+                    finish_reason: chunk.finish_reason.as_deref().map(FinishReason::parse),
+                    model: None,
+                    constraint_event: chunk
+                        .constraint_event
+                        .filter(|_| include_constraint_events)
+                        .map(Into::into),
+                    restarted: false,
+                })
+                .map_err(|status| {
+                    anyhow!(
+                        "Stream read error: {:?}: {}",
+                        status.code(),
+                        redaction.apply(status.message())
+                    )
+                })
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    async fn ready(&self) -> Result<tonic::client::Grpc<Channel>> {
+        let mut grpc = self.grpc.clone();
+        grpc.ready()
+            .await
+            .map_err(|e| anyhow!("Failed to connect to Modal gRPC endpoint: {}", e))?;
+        Ok(grpc)
+    }
+
+    /// Wrap `message` with auth, idempotency, and request-id metadata,
+    /// and the request's own timeout if it has one
+    fn request<T>(
+        &self,
+        message: T,
+        idempotency_key: Option<&str>,
+        request_id: &str,
+        timeout_ms: Option<u64>,
+    ) -> Result<tonic::Request<T>> {
+        let mut request = tonic::Request::new(message);
+        if let Some(ms) = timeout_ms {
+            request.set_timeout(Duration::from_millis(ms));
+        }
+        let metadata = request.metadata_mut();
+        for (name, value) in self.config.auth_headers() {
+            metadata.insert(
+                MetadataKey::from_bytes(name.to_ascii_lowercase().as_bytes())
+                    .with_context(|| format!("Auth header {} is not valid gRPC metadata", name))?,
+                MetadataValue::try_from(value)
+                    .context("Auth credentials are not valid gRPC metadata")?,
+            );
+        }
+        metadata.insert(
+            REQUEST_ID_METADATA,
+            MetadataValue::try_from(request_id).context("Request ID is not valid gRPC metadata")?,
+        );
+        if let Some(key) = idempotency_key {
+            metadata.insert(
+                IDEMPOTENCY_KEY_METADATA,
+                MetadataValue::try_from(key)
+                    .context("Idempotency key is not valid gRPC metadata")?,
+            );
+        }
+        Ok(request)
+    }
+
+    fn status_error(&self, what: &str, status: tonic::Status) -> anyhow::Error {
+        anyhow!(
+            "{} failed with status {:?}: {}",
+            what,
+            status.code(),
+            self.config.redaction.apply(status.message())
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_round_trips_through_protobuf() {
+        use prost::Message;
+
+        let request = InferenceRequest {
+            prompt: "fn main() {".to_string(),
+            constraints: serde_json::json!({"type": "regex", "pattern": "[a-z]+"}),
+            max_tokens: 64,
+            temperature: 0.0,
+            context: None,
+            seed: Some(7),
+            idempotency_key: None,
+            include_logprobs: true,
+            timeout_ms: None,
+            include_constraint_events: false,
+        };
+
+        let message = GenerateRequest::from_inference(&request, "test-model").unwrap();
+        let decoded = GenerateRequest::decode(message.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(decoded.model, "test-model");
+        assert_eq!(decoded.seed, Some(7));
+        assert_eq!(decoded.context_json, None);
+        assert!(decoded.include_logprobs);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&decoded.constraints_json).unwrap(),
+            request.constraints
+        );
+    }
+
+    #[test]
+    fn test_response_without_stats_maps_to_zeroed_stats() {
+        let response: InferenceResponse = GenerateResponse {
+            generated_text: "x".to_string(),
+            tokens_generated: 1,
+            model: "m".to_string(),
+            stats: None,
+            logprobs: vec![],
+            confidence: None,
+            finish_reason: None,
+        }
+        .into();
+        assert_eq!(response.generated_text, "x");
+        assert_eq!(response.stats.total_time_ms, 0);
+        assert!(response.attempts.is_empty());
+        assert_eq!(response.logprobs, None);
+    }
+}
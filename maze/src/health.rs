@@ -0,0 +1,248 @@
+//! Interpreting inference service health probes
+//!
+//! Backends disagree on what healthy looks like: most answer `GET /health`
+//! with 200, some gateways with 204, and some always answer 200 with a JSON
+//! body saying whether the model is loaded. A [`HealthCheckConfig`] says
+//! which statuses count as healthy and, optionally, which field of a JSON
+//! body to consult. [`HealthStatus`] is the verdict, with the raw body kept
+//! for diagnostics.
+
+use serde::{Deserialize, Serialize};
+
+/// How [`ModalClient::health_check`](crate::ModalClient::health_check)
+/// interprets the `/health` response
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    /// Status codes that count as healthy; empty accepts any 2xx
+    pub healthy_statuses: Vec<u16>,
+
+    /// Field of a JSON body to check once the status is accepted
+    pub payload: Option<HealthPayloadCheck>,
+}
+
+/// Field of a JSON health body and the values that mean healthy or degraded
+///
+/// Any other value is [`HealthStatus::Unhealthy`]; a body that is not JSON
+/// or lacks the field is [`HealthStatus::Unknown`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthPayloadCheck {
+    /// JSON pointer to the field, e.g. `/ready` or `/model/status`; a bare
+    /// name such as `ready` is read as a top-level field
+    pub field: String,
+
+    /// Values meaning healthy
+    #[serde(default = "default_healthy_values")]
+    pub healthy: Vec<serde_json::Value>,
+
+    /// Values meaning up but impaired
+    #[serde(default)]
+    pub degraded: Vec<serde_json::Value>,
+}
+
+fn default_healthy_values() -> Vec<serde_json::Value> {
+    vec![serde_json::Value::Bool(true)]
+}
+
+impl HealthPayloadCheck {
+    /// Healthy when `field` is `true`, e.g. `{"ready": true}`
+    pub fn flag(field: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            healthy: default_healthy_values(),
+            degraded: Vec::new(),
+        }
+    }
+
+    /// Healthy when `field` holds one of `healthy`, degraded when it holds
+    /// one of `degraded`, e.g. `{"status": "healthy"}`
+    pub fn values(field: impl Into<String>, healthy: &[&str], degraded: &[&str]) -> Self {
+        let strings = |values: &[&str]| values.iter().map(|v| serde_json::json!(v)).collect();
+        Self {
+            field: field.into(),
+            healthy: strings(healthy),
+            degraded: strings(degraded),
+        }
+    }
+
+    fn pointer(&self) -> String {
+        if self.field.starts_with('/') {
+            self.field.clone()
+        } else {
+            format!("/{}", self.field)
+        }
+    }
+}
+
+/// Verdict of a health probe
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum HealthStatus {
+    /// Ready to serve
+    Healthy { status: u16, body: String },
+
+    /// Serving, but the service reports itself impaired
+    Degraded { status: u16, body: String },
+
+    /// Not ready to serve
+    Unhealthy { status: u16, body: String },
+
+    /// The probe got no usable answer: the body could not be interpreted,
+    /// or no replica responded
+    Unknown {
+        reason: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        body: Option<String>,
+    },
+}
+
+impl HealthStatus {
+    /// Whether the service can take requests: healthy or degraded
+    pub fn is_ready(&self) -> bool {
+        matches!(self, Self::Healthy { .. } | Self::Degraded { .. })
+    }
+
+    /// The response body, when there was one
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            Self::Healthy { body, .. }
+            | Self::Degraded { body, .. }
+            | Self::Unhealthy { body, .. } => Some(body),
+            Self::Unknown { body, .. } => body.as_deref(),
+        }
+    }
+
+    /// Preference when several replicas answer: healthy over degraded over
+    /// unhealthy over unknown
+    pub(crate) fn rank(&self) -> u8 {
+        match self {
+            Self::Healthy { .. } => 3,
+            Self::Degraded { .. } => 2,
+            Self::Unhealthy { .. } => 1,
+            Self::Unknown { .. } => 0,
+        }
+    }
+}
+
+impl std::fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Healthy { status, .. } => write!(f, "healthy (status {})", status),
+            Self::Degraded { status, .. } => write!(f, "degraded (status {})", status),
+            Self::Unhealthy { status, .. } => write!(f, "unhealthy (status {})", status),
+            Self::Unknown { reason, .. } => write!(f, "unknown: {}", reason),
+        }
+    }
+}
+
+impl HealthCheckConfig {
+    /// Interpret a `/health` response with status code `status` and `body`
+    pub fn evaluate(&self, status: u16, body: String) -> HealthStatus {
+        let accepted = if self.healthy_statuses.is_empty() {
+            (200..300).contains(&status)
+        } else {
+            self.healthy_statuses.contains(&status)
+        };
+        if !accepted {
+            return HealthStatus::Unhealthy { status, body };
+        }
+        let Some(ref check) = self.payload else {
+            return HealthStatus::Healthy { status, body };
+        };
+
+        let value = match serde_json::from_str::<serde_json::Value>(&body) {
+            Ok(json) => json.pointer(&check.pointer()).cloned(),
+            Err(e) => {
+                return HealthStatus::Unknown {
+                    reason: format!("health body is not JSON: {}", e),
+                    body: Some(body),
+                }
+            }
+        };
+        match value {
+            None => HealthStatus::Unknown {
+                reason: format!("health body has no field {}", check.pointer()),
+                body: Some(body),
+            },
+            Some(value) if check.healthy.contains(&value) => HealthStatus::Healthy { status, body },
+            Some(value) if check.degraded.contains(&value) => {
+                HealthStatus::Degraded { status, body }
+            }
+            Some(_) => HealthStatus::Unhealthy { status, body },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_codes_decide_without_payload_check() {
+        let any_2xx = HealthCheckConfig::default();
+        assert!(any_2xx.evaluate(204, String::new()).is_ready());
+        assert_eq!(
+            any_2xx.evaluate(503, "down".to_string()),
+            HealthStatus::Unhealthy {
+                status: 503,
+                body: "down".to_string()
+            }
+        );
+
+        let only_200 = HealthCheckConfig {
+            healthy_statuses: vec![200],
+            ..Default::default()
+        };
+        assert!(!only_200.evaluate(204, String::new()).is_ready());
+    }
+
+    #[test]
+    fn test_payload_field_decides_health() {
+        let ready = HealthCheckConfig {
+            payload: Some(HealthPayloadCheck::flag("ready")),
+            ..Default::default()
+        };
+        let verdict =
+            |config: &HealthCheckConfig, body: &str| config.evaluate(200, body.to_string());
+        assert!(matches!(
+            verdict(&ready, r#"{"ready": true}"#),
+            HealthStatus::Healthy { .. }
+        ));
+        assert!(matches!(
+            verdict(&ready, r#"{"ready": false}"#),
+            HealthStatus::Unhealthy { .. }
+        ));
+        assert!(matches!(
+            verdict(&ready, r#"{"up": true}"#),
+            HealthStatus::Unknown { .. }
+        ));
+        assert_eq!(verdict(&ready, "OK").body(), Some("OK"));
+        assert!(matches!(
+            verdict(&ready, "OK"),
+            HealthStatus::Unknown { .. }
+        ));
+
+        let status = HealthCheckConfig {
+            payload: Some(HealthPayloadCheck::values(
+                "/model/status",
+                &["healthy"],
+                &["degraded"],
+            )),
+            ..Default::default()
+        };
+        let body = |s: &str| format!(r#"{{"model": {{"status": "{}"}}}}"#, s);
+        assert!(matches!(
+            verdict(&status, &body("healthy")),
+            HealthStatus::Healthy { .. }
+        ));
+        assert!(matches!(
+            verdict(&status, &body("degraded")),
+            HealthStatus::Degraded { .. }
+        ));
+        assert!(verdict(&status, &body("degraded")).is_ready());
+        assert!(matches!(
+            verdict(&status, &body("loading")),
+            HealthStatus::Unhealthy { .. }
+        ));
+    }
+}
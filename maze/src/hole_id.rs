@@ -0,0 +1,158 @@
+//! Stable IDs for holes created by decomposition
+//!
+//! A child hole's ID is derived from its parent's ID and its index among
+//! the parent's children, not handed out by a counter, so decomposing the
+//! same hole yields the same IDs on every run whatever order holes fail
+//! in. [`HoleIdAllocator`] records every ID in use and probes past taken
+//! ones, so a derived ID never collides with a caller-supplied ID or with
+//! another child's. Built from saved hole states, it hands back the IDs
+//! already assigned, so resumed refinement keeps them too.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hasher;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::progressive_refinement::HoleState;
+
+/// Derived IDs are kept below 2^53 so they survive JSON consumers that
+/// parse numbers as doubles
+const ID_MASK: u64 = (1 << 53) - 1;
+
+/// Two holes share an ID
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("hole ID {id} is used by more than one hole")]
+pub struct DuplicateHoleId {
+    /// The shared ID
+    pub id: u64,
+}
+
+/// Hands out child hole IDs, keeping track of every ID in use
+#[derive(Debug, Clone, Default)]
+pub struct HoleIdAllocator {
+    /// IDs of all known holes
+    used: HashSet<u64>,
+
+    /// ID assigned to each `(parent, child index)`
+    children: HashMap<(u64, usize), u64>,
+}
+
+impl HoleIdAllocator {
+    /// An allocator with no IDs in use
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An allocator that knows `holes`' IDs and their decompositions
+    ///
+    /// Each hole's `child_ids` are recorded as its assigned children, so
+    /// [`child_id`](Self::child_id) returns them again. Fails if two holes
+    /// share an ID.
+    pub fn from_holes<'a>(
+        holes: impl IntoIterator<Item = &'a HoleState>,
+    ) -> Result<Self, DuplicateHoleId> {
+        let mut allocator = Self::new();
+        let mut decomposed = Vec::new();
+        for hole in holes {
+            allocator.reserve(hole.id)?;
+            decomposed.push((hole.id, &hole.child_ids));
+        }
+        for (parent, child_ids) in decomposed {
+            for (index, &child) in child_ids.iter().enumerate() {
+                allocator.used.insert(child);
+                allocator.children.insert((parent, index), child);
+            }
+        }
+        Ok(allocator)
+    }
+
+    /// Mark `id` as in use, failing if it already is
+    pub fn reserve(&mut self, id: u64) -> Result<(), DuplicateHoleId> {
+        if self.used.insert(id) {
+            Ok(())
+        } else {
+            Err(DuplicateHoleId { id })
+        }
+    }
+
+    /// Whether `id` is in use
+    pub fn contains(&self, id: u64) -> bool {
+        self.used.contains(&id)
+    }
+
+    /// ID of the `index`th child of `parent`
+    ///
+    /// Returns the ID already assigned to that child if there is one;
+    /// otherwise derives one from `parent` and `index`, probing until it
+    /// finds an ID not in use, and reserves it.
+    pub fn child_id(&mut self, parent: u64, index: usize) -> u64 {
+        if let Some(&id) = self.children.get(&(parent, index)) {
+            return id;
+        }
+        let id = (0..)
+            .map(|probe| derive(parent, index, probe))
+            .find(|id| !self.used.contains(id))
+            .expect("probing yields unbounded distinct candidates");
+        self.used.insert(id);
+        self.children.insert((parent, index), id);
+        id
+    }
+}
+
+/// Candidate ID for the `index`th child of `parent` on the `probe`th try
+fn derive(parent: u64, index: usize, probe: u64) -> u64 {
+    let mut hasher = Xxh3::new();
+    hasher.write(b"maze.hole_id");
+    hasher.write_u64(parent);
+    hasher.write_u64(index as u64);
+    hasher.write_u64(probe);
+    hasher.finish() & ID_MASK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hole(id: u64) -> HoleState {
+        HoleState::new(id, "macro".to_string(), format!("hole_{}", id))
+    }
+
+    #[test]
+    fn test_child_ids_are_stable_and_order_independent() {
+        let mut first = HoleIdAllocator::from_holes(&[hole(1), hole(2)]).unwrap();
+        let a = [first.child_id(1, 0), first.child_id(1, 1)];
+        let b = first.child_id(2, 0);
+
+        // Decomposing in the other order assigns the same IDs
+        let mut second = HoleIdAllocator::from_holes(&[hole(1), hole(2)]).unwrap();
+        assert_eq!(second.child_id(2, 0), b);
+        assert_eq!([second.child_id(1, 0), second.child_id(1, 1)], a);
+
+        // Asking again returns the assigned ID
+        assert_eq!(second.child_id(1, 1), a[1]);
+        assert!(a[0] != a[1] && a[0] != b && a[0] <= ID_MASK);
+    }
+
+    #[test]
+    fn test_taken_ids_are_probed_past() {
+        let derived = derive(7, 0, 0);
+        let mut allocator = HoleIdAllocator::from_holes(&[hole(7), hole(derived)]).unwrap();
+        let id = allocator.child_id(7, 0);
+        assert_eq!(id, derive(7, 0, 1));
+        assert!(allocator.contains(id));
+    }
+
+    #[test]
+    fn test_saved_decompositions_are_kept_and_duplicates_rejected() {
+        let mut parent = hole(1);
+        parent.child_ids = vec![40, 41];
+        let mut allocator = HoleIdAllocator::from_holes(&[parent.clone()]).unwrap();
+        assert_eq!(allocator.child_id(1, 1), 41);
+        assert!(allocator.contains(40));
+        assert_ne!(allocator.child_id(1, 2), 40);
+
+        assert_eq!(
+            HoleIdAllocator::from_holes(&[hole(3), hole(3)]).unwrap_err(),
+            DuplicateHoleId { id: 3 }
+        );
+    }
+}
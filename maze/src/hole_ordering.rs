@@ -0,0 +1,185 @@
+//! Fill order for ready holes
+//!
+//! Each refinement iteration fills the holes whose dependencies are
+//! satisfied. A [`HoleOrdering`] decides the order within that ready set:
+//! sequential fills run in it, and when a [`TokenBudget`] runs short the
+//! holes at the front are the ones that still get filled. Dependencies always
+//! take precedence, since a hole only becomes ready once every hole it
+//! depends on is filled. Ties keep hole ID order.
+//!
+//! [`TokenBudget`]: crate::TokenBudget
+
+use std::cmp::Ordering;
+
+use crate::progressive_refinement::HoleState;
+
+/// Orders the ready holes of a refinement iteration
+///
+/// Any `Fn(&HoleState, &HoleState) -> Ordering` closure is a `HoleOrdering`.
+pub trait HoleOrdering: Send + Sync {
+    /// Whether `a` should be filled before `b`
+    fn compare(&self, a: &HoleState, b: &HoleState) -> Ordering;
+}
+
+impl<F> HoleOrdering for F
+where
+    F: Fn(&HoleState, &HoleState) -> Ordering + Send + Sync,
+{
+    fn compare(&self, a: &HoleState, b: &HoleState) -> Ordering {
+        self(a, b)
+    }
+}
+
+/// Hole ID order, the default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ById;
+
+impl HoleOrdering for ById {
+    fn compare(&self, a: &HoleState, b: &HoleState) -> Ordering {
+        a.id.cmp(&b.id)
+    }
+}
+
+/// Smallest scale first (nano, micro, meso, macro), so small fills establish
+/// context for larger ones; unknown scales go last
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByScale;
+
+impl ByScale {
+    fn rank(scale: &str) -> u8 {
+        match scale {
+            "nano" => 0,
+            "micro" => 1,
+            "meso" => 2,
+            "macro" => 3,
+            _ => 4,
+        }
+    }
+}
+
+impl HoleOrdering for ByScale {
+    fn compare(&self, a: &HoleState, b: &HoleState) -> Ordering {
+        Self::rank(&a.scale).cmp(&Self::rank(&b.scale))
+    }
+}
+
+/// Source order of `HoleState::origin` (`file:line:column`), or nearest
+/// first to a cursor
+///
+/// With a cursor, holes in the cursor's file come first, ordered by line
+/// distance; holes elsewhere follow in source order. Origins that do not
+/// parse go last.
+#[derive(Debug, Clone, Default)]
+pub struct BySourceLocation {
+    /// `file:line:column` to fill outward from
+    pub cursor: Option<String>,
+}
+
+impl BySourceLocation {
+    /// Holes in file, line, column order
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Holes nearest `cursor` (`file:line:column`) first
+    pub fn nearest(cursor: impl Into<String>) -> Self {
+        Self {
+            cursor: Some(cursor.into()),
+        }
+    }
+
+    fn key<'a>(&self, origin: &'a str) -> Option<(bool, usize, &'a str, usize, usize)> {
+        let (file, line, column) = parse_origin(origin)?;
+        let cursor = self.cursor.as_deref().and_then(parse_origin);
+        Some(match cursor {
+            Some((cursor_file, cursor_line, _)) if cursor_file == file => {
+                (false, line.abs_diff(cursor_line), file, line, column)
+            }
+            Some(_) => (true, 0, file, line, column),
+            None => (false, 0, file, line, column),
+        })
+    }
+}
+
+impl HoleOrdering for BySourceLocation {
+    fn compare(&self, a: &HoleState, b: &HoleState) -> Ordering {
+        match (self.key(&a.origin), self.key(&b.origin)) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        }
+    }
+}
+
+/// Most constrained first, since their fills are the most determined
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ByConstraintCount;
+
+impl HoleOrdering for ByConstraintCount {
+    fn compare(&self, a: &HoleState, b: &HoleState) -> Ordering {
+        b.constraints.len().cmp(&a.constraints.len())
+    }
+}
+
+/// Split `file:line:column` from the right, so paths may contain colons
+fn parse_origin(origin: &str) -> Option<(&str, usize, usize)> {
+    let mut parts = origin.rsplitn(3, ':');
+    let column = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    let file = parts.next()?;
+    Some((file, line, column))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hole(id: u64, scale: &str, origin: &str) -> HoleState {
+        HoleState::new(id, scale.to_string(), origin.to_string())
+    }
+
+    fn sorted(ordering: &dyn HoleOrdering, holes: &mut [HoleState]) -> Vec<u64> {
+        holes.sort_by(|a, b| ordering.compare(a, b));
+        holes.iter().map(|h| h.id).collect()
+    }
+
+    #[test]
+    fn test_builtin_orderings() {
+        let mut holes = vec![
+            hole(1, "macro", "b.rs:3:1"),
+            hole(2, "nano", "a.rs:40:5"),
+            hole(3, "micro", "a.rs:12:1"),
+            hole(4, "meso", "weird origin"),
+        ];
+        holes[3].constraints = vec!["a".into(), "b".into()];
+        holes[0].constraints = vec!["a".into()];
+
+        assert_eq!(sorted(&ByScale, &mut holes), vec![2, 3, 4, 1]);
+        assert_eq!(
+            sorted(&BySourceLocation::new(), &mut holes),
+            vec![3, 2, 1, 4]
+        );
+        assert_eq!(
+            sorted(&BySourceLocation::nearest("a.rs:35:1"), &mut holes),
+            vec![2, 3, 1, 4]
+        );
+        assert_eq!(sorted(&ByConstraintCount, &mut holes), vec![4, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_closure_ordering() {
+        let mut holes = vec![hole(1, "nano", "a:1:1"), hole(2, "nano", "a:2:1")];
+        let reverse = |a: &HoleState, b: &HoleState| b.id.cmp(&a.id);
+        assert_eq!(sorted(&reverse, &mut holes), vec![2, 1]);
+    }
+
+    #[test]
+    fn test_parse_origin_allows_colons_in_path() {
+        assert_eq!(
+            parse_origin("C:/src/a.rs:10:5"),
+            Some(("C:/src/a.rs", 10, 5))
+        );
+        assert_eq!(parse_origin("a.rs:10"), None);
+    }
+}
@@ -0,0 +1,464 @@
+//! Incremental recompilation of edited constraint sets
+//!
+//! An editor typically changes one constraint out of many between
+//! keystrokes. [`diff`] matches the constraints of two sets by their
+//! [`distinct_names`](crate::merge::distinct_names) label and reports which
+//! were added, removed, or changed. [`IncrementalCompiler`] keeps each
+//! constraint's compiled fragment and the merged value of every JSON schema
+//! property, so an update compiles only the added and changed constraints
+//! and re-merges only the properties named after them. The grammar, regex,
+//! and token mask list is reassembled from the kept fragments, and token
+//! mask conflicts are resolved afresh. Fragments are built and properties
+//! merged by the same [`merge`](crate::merge) helpers as a full compile.
+//!
+//! The output is identical to a full llguidance compile of the same set,
+//! merge report included; see [`merge`](crate::merge) for the overlap rules.
+
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hasher;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::ffi::ConstraintIR;
+use crate::ir_codec;
+use crate::merge::{self, ConstraintMergeReport, MergeEvent};
+
+/// Constraints added, removed, or changed between two sets
+///
+/// Constraints are identified by their label, so renaming one shows up as a
+/// removal and an addition. Added and changed labels are in the new set's
+/// merge order; removed labels are sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConstraintDiff {
+    /// Labels only in the new set
+    pub added: Vec<String>,
+
+    /// Labels only in the old set
+    pub removed: Vec<String>,
+
+    /// Labels in both sets whose constraint differs
+    pub changed: Vec<String>,
+
+    /// Number of constraints identical in both sets
+    pub unchanged: usize,
+}
+
+impl ConstraintDiff {
+    /// Whether the two sets hold the same constraints
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare two constraint sets
+pub fn diff(old: &[ConstraintIR], new: &[ConstraintIR]) -> Result<ConstraintDiff> {
+    let old = fingerprints(old)?;
+    let new = fingerprints(new)?;
+    let old_by_label: HashMap<&str, u64> = old.iter().map(|(l, f)| (l.as_str(), *f)).collect();
+    let new_labels: HashSet<&str> = new.iter().map(|(l, _)| l.as_str()).collect();
+
+    let mut diff = ConstraintDiff::default();
+    for (label, fingerprint) in &new {
+        match old_by_label.get(label.as_str()) {
+            None => diff.added.push(label.clone()),
+            Some(old) if old != fingerprint => diff.changed.push(label.clone()),
+            Some(_) => diff.unchanged += 1,
+        }
+    }
+    diff.removed = old
+        .into_iter()
+        .filter(|(label, _)| !new_labels.contains(label.as_str()))
+        .map(|(label, _)| label)
+        .collect();
+    diff.removed.sort();
+    Ok(diff)
+}
+
+/// `(label, fingerprint)` of each constraint, in merge order
+fn fingerprints(constraints: &[ConstraintIR]) -> Result<Vec<(String, u64)>> {
+    let ordered = merge::merge_order(constraints);
+    let labels = ConstraintMergeReport::new(&ordered).order;
+    ordered
+        .into_iter()
+        .zip(labels)
+        .map(|(constraint, label)| Ok((label, fingerprint(constraint)?)))
+        .collect()
+}
+
+/// Hash of one constraint's canonical form
+fn fingerprint(constraint: &ConstraintIR) -> Result<u64> {
+    let mut hasher = Xxh3::new();
+    ir_codec::canonical_hash(std::slice::from_ref(constraint), &mut hasher)
+        .with_context(|| format!("Failed to fingerprint constraint '{}'", constraint.name))?;
+    Ok(hasher.finish())
+}
+
+/// One constraint's contribution to the merged schema
+#[derive(Debug, Clone)]
+struct Fragment {
+    fingerprint: u64,
+    name: String,
+    priority: u32,
+
+    /// The constraint's JSON schema, as it enters the merge
+    property: Option<serde_json::Value>,
+
+    /// Grammar and regex entries, in emission order
+    entries: Vec<serde_json::Value>,
+}
+
+/// Merged value of one property and the overlaps resolved to produce it
+#[derive(Debug, Clone)]
+struct MergedProperty {
+    value: serde_json::Value,
+
+    /// One event per constraint after the first defining the property
+    events: Vec<MergeEvent>,
+}
+
+/// Work done by the last [`IncrementalCompiler::update`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpdateStats {
+    /// Difference from the previously compiled set
+    pub diff: ConstraintDiff,
+
+    /// Properties merged again because a constraint named after them was
+    /// added, removed, or changed
+    pub remerged_properties: usize,
+}
+
+/// Compiles a changing constraint set, reusing unchanged constraints' work
+///
+/// Keep one per edited document and call [`update`](Self::update) with the
+/// whole current set after each edit. The first update compiles everything.
+///
+/// ```
+/// use maze::incremental::IncrementalCompiler;
+///
+/// let mut compiler = IncrementalCompiler::new();
+/// let (schema, _report) = compiler.update(&[])?;
+/// assert_eq!(schema["properties"], serde_json::json!({}));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct IncrementalCompiler {
+    /// Fragment of each constraint in the last set, by label
+    fragments: HashMap<String, Fragment>,
+
+    /// Merged properties of the last set, by property key
+    properties: HashMap<String, MergedProperty>,
+
+    /// The last compiled schema
+    schema: serde_json::Value,
+
+    stats: UpdateStats,
+}
+
+impl Default for IncrementalCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IncrementalCompiler {
+    /// A compiler that has compiled nothing yet
+    pub fn new() -> Self {
+        Self {
+            fragments: HashMap::new(),
+            properties: HashMap::new(),
+            schema: serde_json::json!({
+                "type": "object",
+                "properties": {},
+                "constraints": []
+            }),
+            stats: UpdateStats::default(),
+        }
+    }
+
+    /// What the last update compiled and merged
+    pub fn last_update(&self) -> &UpdateStats {
+        &self.stats
+    }
+
+    /// Compile `constraints_ir`, the whole current set, to llguidance JSON
+    pub fn update(
+        &mut self,
+        constraints_ir: &[ConstraintIR],
+    ) -> Result<(serde_json::Value, ConstraintMergeReport)> {
+        let ordered = merge::merge_order(constraints_ir);
+        let mut report = ConstraintMergeReport::new(&ordered);
+        let labels = report.order.clone();
+
+        // Compile the fragments of added and changed constraints, noting the
+        // properties they touch
+        let mut diff = ConstraintDiff::default();
+        let mut dirty: HashSet<String> = HashSet::new();
+        let mut fragments = HashMap::with_capacity(ordered.len());
+        for (constraint, label) in ordered.iter().zip(&labels) {
+            let fingerprint = fingerprint(constraint)?;
+            let fragment = match self.fragments.remove(label) {
+                Some(old) if old.fingerprint == fingerprint => {
+                    diff.unchanged += 1;
+                    old
+                }
+                old => {
+                    match old {
+                        Some(old) => {
+                            dirty.insert(old.name);
+                            diff.changed.push(label.clone());
+                        }
+                        None => diff.added.push(label.clone()),
+                    }
+                    dirty.insert(constraint.name.clone());
+                    compile_fragment(constraint, fingerprint)
+                }
+            };
+            fragments.insert(label.clone(), fragment);
+        }
+        for (label, old) in std::mem::replace(&mut self.fragments, fragments) {
+            dirty.insert(old.name);
+            diff.removed.push(label);
+        }
+        diff.removed.sort();
+
+        // Re-merge the touched properties, in merge order within each
+        let mut groups: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (constraint, label) in ordered.iter().zip(&labels) {
+            if dirty.contains(&constraint.name) && self.fragments[label].property.is_some() {
+                groups
+                    .entry(constraint.name.as_str())
+                    .or_default()
+                    .push(label);
+            }
+        }
+        let properties = self.schema["properties"]
+            .as_object_mut()
+            .context("compiled schema has no properties object")?;
+        for key in &dirty {
+            match groups.get(key.as_str()) {
+                Some(group) => {
+                    let merged = merge_property(key, group, &self.fragments);
+                    properties.insert(key.clone(), merged.value.clone());
+                    self.properties.insert(key.clone(), merged);
+                }
+                None => {
+                    properties.remove(key);
+                    self.properties.remove(key);
+                }
+            }
+        }
+
+        // Token mask conflicts span constraints, so resolve them afresh;
+        // their events come first, as in a full compile
+        let token_masks = merge::resolve_token_masks(&ordered, &mut report);
+
+        let mut constraints = Vec::new();
+        let mut property_events: HashMap<&str, &MergeEvent> = HashMap::new();
+        for merged in self.properties.values() {
+            for event in &merged.events {
+                property_events.insert(event.affected[0].as_str(), event);
+            }
+        }
+        for ((constraint, label), token_masks) in ordered.iter().zip(&labels).zip(token_masks) {
+            if let Some(event) = property_events.get(label.as_str()) {
+                report.events.push((*event).clone());
+            }
+            constraints.extend(self.fragments[label].entries.iter().cloned());
+            if let Some(token_masks) = token_masks {
+                constraints.push(merge::token_mask_entry(&constraint.name, &token_masks));
+            }
+        }
+        self.schema["constraints"] = serde_json::Value::Array(constraints);
+
+        self.stats = UpdateStats {
+            diff,
+            remerged_properties: dirty.len(),
+        };
+        Ok((self.schema.clone(), report))
+    }
+}
+
+/// Compile the parts of `constraint` that do not depend on other constraints
+fn compile_fragment(constraint: &ConstraintIR, fingerprint: u64) -> Fragment {
+    Fragment {
+        fingerprint,
+        name: constraint.name.clone(),
+        priority: constraint.priority,
+        property: constraint
+            .json_schema
+            .as_ref()
+            .map(|schema| serde_json::json!(schema)),
+        entries: merge::pattern_entries(constraint),
+    }
+}
+
+/// Merge the definitions of property `key` from the fragments labelled
+/// `group`, which are in merge order
+fn merge_property(
+    key: &str,
+    group: &[&str],
+    fragments: &HashMap<String, Fragment>,
+) -> MergedProperty {
+    let owner_label = group[0];
+    let owner = &fragments[owner_label];
+    let mut value = owner.property.clone().unwrap_or_default();
+    let events = group[1..]
+        .iter()
+        .map(|&label| {
+            let fragment = &fragments[label];
+            merge::merge_property_definition(
+                key,
+                &mut value,
+                (owner.priority, owner_label),
+                (fragment.priority, label),
+                fragment
+                    .property
+                    .as_ref()
+                    .unwrap_or(&serde_json::Value::Null),
+            )
+        })
+        .collect();
+    MergedProperty { value, events }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{JsonSchema, RegexPattern, TokenMaskRules};
+
+    fn constraint(name: &str, priority: u32, property: &str) -> ConstraintIR {
+        ConstraintIR {
+            name: name.to_string(),
+            json_schema: Some(JsonSchema {
+                schema_type: "object".to_string(),
+                properties: HashMap::from([(
+                    property.to_string(),
+                    serde_json::json!({"type": "string"}),
+                )]),
+                required: vec![],
+                additional_properties: false,
+            }),
+            grammar: None,
+            regex_patterns: vec![RegexPattern {
+                pattern: format!("^{}$", name),
+                flags: String::new(),
+            }],
+            token_masks: Some(TokenMaskRules {
+                allowed_tokens: Some(vec![priority]),
+                forbidden_tokens: None,
+            }),
+            type_inhabitation: None,
+            priority,
+            rich_context: None,
+            feasibility_score: 0.0,
+            is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    fn constraint_set(n: usize) -> Vec<ConstraintIR> {
+        (0..n)
+            .map(|i| constraint(&format!("c{}", i), (i % 3) as u32, "x"))
+            .collect()
+    }
+
+    fn assert_matches_full_compile(
+        compiled: (serde_json::Value, ConstraintMergeReport),
+        constraints: &[ConstraintIR],
+    ) {
+        let (schema, report) = crate::compile_llguidance_schema(constraints).unwrap();
+        assert_eq!(compiled.0, schema);
+        assert_eq!(compiled.1, report);
+    }
+
+    #[test]
+    fn test_single_constraint_edit_recompiles_only_that_constraint() {
+        let mut constraints = constraint_set(50);
+        let mut compiler = IncrementalCompiler::new();
+        compiler.update(&constraints).unwrap();
+        assert_eq!(compiler.last_update().diff.added.len(), 50);
+
+        constraints[7] = constraint("c7", 1, "y");
+        let compiled = compiler.update(&constraints).unwrap();
+        let stats = compiler.last_update();
+        assert_eq!(stats.diff.changed, vec!["c7"]);
+        assert!(stats.diff.added.is_empty() && stats.diff.removed.is_empty());
+        assert_eq!(stats.diff.unchanged, 49);
+        assert_eq!(stats.remerged_properties, 1);
+        assert_matches_full_compile(compiled, &constraints);
+
+        // No edit, no work
+        compiler.update(&constraints).unwrap();
+        assert!(compiler.last_update().diff.is_empty());
+        assert_eq!(compiler.last_update().remerged_properties, 0);
+    }
+
+    #[test]
+    fn test_overlapping_names_match_full_compile_through_edits() {
+        let mut constraints = vec![
+            constraint("auth", 3, "x"),
+            constraint("auth", 3, "y"),
+            constraint("auth", 1, "z"),
+            constraint("style", 2, "x"),
+        ];
+        let mut compiler = IncrementalCompiler::new();
+        assert_matches_full_compile(compiler.update(&constraints).unwrap(), &constraints);
+
+        // Demote one definition: it is now overridden instead of combined
+        constraints[1].priority = 1;
+        assert_matches_full_compile(compiler.update(&constraints).unwrap(), &constraints);
+        assert_eq!(compiler.last_update().remerged_properties, 1);
+
+        // Drop a name entirely and add another
+        constraints.retain(|c| c.name != "style");
+        constraints.push(constraint("naming", 5, "w"));
+        assert_matches_full_compile(compiler.update(&constraints).unwrap(), &constraints);
+        let diff = &compiler.last_update().diff;
+        assert_eq!(diff.added, vec!["naming"]);
+        assert_eq!(diff.removed, vec!["style"]);
+    }
+
+    #[tokio::test]
+    async fn test_orchestrator_compile_incremental_matches_full_compile() {
+        let orchestrator = crate::MazeOrchestrator::with_client(
+            crate::MockInferenceClient::new("test-model"),
+            crate::MazeConfig::default(),
+        );
+        let full = crate::MazeOrchestrator::with_client(
+            crate::MockInferenceClient::new("test-model"),
+            crate::MazeConfig::default(),
+        );
+        let mut session = IncrementalCompiler::new();
+        let mut constraints = constraint_set(10);
+        orchestrator
+            .compile_incremental(&mut session, &constraints)
+            .await
+            .unwrap();
+
+        constraints[3].priority = 7;
+        let compiled = orchestrator
+            .compile_incremental(&mut session, &constraints)
+            .await
+            .unwrap();
+        assert_eq!(session.last_update().diff.changed, vec!["c3"]);
+        let expected = full.compile_constraints(&constraints).await.unwrap();
+        assert_eq!(compiled.llguidance_schema, expected.llguidance_schema);
+        assert_eq!(compiled.hash, expected.hash);
+    }
+
+    #[test]
+    fn test_diff_matches_constraints_by_label() {
+        let old = constraint_set(4);
+        let mut new = old.clone();
+        new[1].regex_patterns.clear();
+        new.remove(3);
+        new.push(constraint("c9", 0, "x"));
+
+        let diff = diff(&old, &new).unwrap();
+        assert_eq!(diff.changed, vec!["c1"]);
+        assert_eq!(diff.removed, vec!["c3"]);
+        assert_eq!(diff.added, vec!["c9"]);
+        assert_eq!(diff.unchanged, 2);
+        assert!(super::diff(&old, &old).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,504 @@
+//! Inference client abstraction
+//!
+//! [`InferenceClient`] is the seam between the orchestration logic and the
+//! service that actually runs the model. [`ModalClient`] is the production
+//! implementation; [`MockInferenceClient`] returns scripted responses so code
+//! built on Maze can be tested without a server.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::health::HealthStatus;
+use crate::modal_client::{
+    ConstraintEvent, ConstraintsUnsupported, GenerationStats, InferenceRequest, InferenceResponse,
+    ModalClient, ModelInfo, ServiceError, StreamChunk, StreamingResult,
+    CONSTRAINTS_UNSUPPORTED_CODE,
+};
+use crate::trace::TraceRecord;
+
+/// A service that can run constrained generation
+#[async_trait]
+pub trait InferenceClient: Send + Sync {
+    /// Name of the model requests are sent to
+    fn model_name(&self) -> &str;
+
+    /// Generate code with constraints
+    async fn generate_constrained(&self, request: InferenceRequest) -> Result<InferenceResponse>;
+
+    /// Stream generation output chunk by chunk
+    ///
+    /// The default implementation runs a full generation and yields it as a
+    /// single final chunk, for services without a streaming endpoint.
+    async fn generate_stream(&self, request: InferenceRequest) -> Result<StreamingResult> {
+        let response = self.generate_constrained(request).await?;
+        let chunk = StreamChunk {
+            text: response.generated_text,
+            is_final: true,
+            token_index: 0,
+            timestamp_ms: response.stats.total_time_ms,
+            finish_reason: response.finish_reason,
+            model: None,
+            constraint_event: None,
+            restarted: false,
+        };
+        Ok(Box::pin(futures::stream::once(async move { Ok(chunk) })))
+    }
+
+    /// Whether the service is up and able to serve requests
+    async fn health_check(&self) -> Result<HealthStatus>;
+
+    /// Capabilities of the model requests are sent to
+    async fn model_info(&self) -> Result<ModelInfo>;
+}
+
+#[async_trait]
+impl InferenceClient for ModalClient {
+    fn model_name(&self) -> &str {
+        &self.config().model
+    }
+
+    async fn generate_constrained(&self, request: InferenceRequest) -> Result<InferenceResponse> {
+        ModalClient::generate_constrained(self, request).await
+    }
+
+    async fn generate_stream(&self, request: InferenceRequest) -> Result<StreamingResult> {
+        ModalClient::generate_stream(self, request).await
+    }
+
+    async fn health_check(&self) -> Result<HealthStatus> {
+        ModalClient::health_check(self).await
+    }
+
+    async fn model_info(&self) -> Result<ModelInfo> {
+        ModalClient::model_info(self).await
+    }
+}
+
+/// A scripted outcome for one call to [`MockInferenceClient::generate_constrained`]
+#[derive(Debug, Clone)]
+pub enum MockReply {
+    /// Return this response
+    Response(InferenceResponse),
+
+    /// Fail with this error message
+    Failure(String),
+
+    /// Fail as if the service returned 429 Too Many Requests
+    RateLimited,
+
+    /// Fail as if the service rejected the request's constraints with
+    /// [`CONSTRAINTS_UNSUPPORTED_CODE`](crate::modal_client::CONSTRAINTS_UNSUPPORTED_CODE)
+    ConstraintsUnsupported,
+
+    /// Stream the words of `emitted`, then fail with `message`; plain
+    /// generations fail with `message` straight away
+    StreamFailure { emitted: String, message: String },
+}
+
+/// Offline inference client that returns scripted responses
+///
+/// Replies are consumed in order; once the script is exhausted every call
+/// gets the default response. Requests are recorded for later inspection.
+///
+/// ```
+/// use maze::inference::MockInferenceClient;
+///
+/// let client = MockInferenceClient::new("mock-model")
+///     .then_rate_limited()
+///     .then_respond("fn answer() -> u32 { 42 }");
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockInferenceClient {
+    model: String,
+    info: ModelInfo,
+    health: HealthStatus,
+    latency: Duration,
+    chunk_delay: Duration,
+    constraint_events: Vec<ConstraintEvent>,
+    capacity: Option<usize>,
+    default_response: InferenceResponse,
+    script: Arc<Mutex<VecDeque<MockReply>>>,
+    requests: Arc<Mutex<Vec<InferenceRequest>>>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl MockInferenceClient {
+    /// Create a healthy mock for `model` that answers with an empty function
+    pub fn new(model: impl Into<String>) -> Self {
+        let model = model.into();
+        Self {
+            info: ModelInfo::from_name(model.clone()),
+            health: HealthStatus::Healthy {
+                status: 200,
+                body: String::new(),
+            },
+            latency: Duration::ZERO,
+            chunk_delay: Duration::ZERO,
+            constraint_events: Vec::new(),
+            capacity: None,
+            default_response: Self::response(&model, "fn mock() {}"),
+            script: Arc::new(Mutex::new(VecDeque::new())),
+            requests: Arc::new(Mutex::new(Vec::new())),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            model,
+        }
+    }
+
+    /// Create a mock for `model` that replays its calls from a trace
+    ///
+    /// Each record of a call to `model` queues its response, or its error
+    /// as a failure, in trace order; records for other models are skipped.
+    /// See [`crate::trace`].
+    pub fn from_trace(model: impl Into<String>, records: Vec<TraceRecord>) -> Self {
+        let model = model.into();
+        records
+            .into_iter()
+            .filter(|record| record.model == model)
+            .filter_map(|record| match (record.response, record.error) {
+                (Some(response), _) => Some(MockReply::Response(response)),
+                (None, Some(error)) => Some(MockReply::Failure(error)),
+                (None, None) => None,
+            })
+            .fold(Self::new(model.clone()), Self::then)
+    }
+
+    /// Build a response from this model with the given text
+    pub fn response(model: &str, text: &str) -> InferenceResponse {
+        let tokens_generated = text.split_whitespace().count().max(1);
+        InferenceResponse {
+            generated_text: text.to_string(),
+            tokens_generated,
+            model: model.to_string(),
+            stats: GenerationStats {
+                total_time_ms: 0,
+                time_per_token_us: 0,
+                constraint_checks: 0,
+                avg_constraint_check_us: 0,
+                by_kind: Default::default(),
+            },
+            attempts: vec![],
+            logprobs: None,
+            reported_confidence: None,
+            request_id: None,
+            finish_reason: None,
+        }
+    }
+
+    /// Queue a successful response with the given text
+    pub fn then_respond(self, text: &str) -> Self {
+        let response = Self::response(&self.model, text);
+        self.then(MockReply::Response(response))
+    }
+
+    /// Queue a failure with the given message
+    pub fn then_fail(self, message: &str) -> Self {
+        self.then(MockReply::Failure(message.to_string()))
+    }
+
+    /// Queue a rate-limit failure
+    pub fn then_rate_limited(self) -> Self {
+        self.then(MockReply::RateLimited)
+    }
+
+    /// Queue an arbitrary reply
+    pub fn then(self, reply: MockReply) -> Self {
+        self.script.lock().unwrap().push_back(reply);
+        self
+    }
+
+    /// Response returned once the script is exhausted
+    pub fn with_default_response(mut self, text: &str) -> Self {
+        self.default_response = Self::response(&self.model, text);
+        self
+    }
+
+    /// Delay every generation by `latency`
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Delay each streamed chunk by `delay`
+    pub fn with_chunk_delay(mut self, delay: Duration) -> Self {
+        self.chunk_delay = delay;
+        self
+    }
+
+    /// Report `events` on streams that ask for constraint events
+    ///
+    /// Each event is streamed just before the word at its position; later
+    /// positions go before the last word.
+    pub fn with_constraint_events(mut self, events: Vec<ConstraintEvent>) -> Self {
+        self.constraint_events = events;
+        self
+    }
+
+    /// Answer as rate limited, without taking a scripted reply, while more
+    /// than `max_in_flight` generations are in progress
+    ///
+    /// Pair with [`with_latency`](Self::with_latency) so generations overlap.
+    pub fn with_capacity(mut self, max_in_flight: usize) -> Self {
+        self.capacity = Some(max_in_flight);
+        self
+    }
+
+    /// Report these capabilities from `model_info`
+    pub fn with_model_info(mut self, info: ModelInfo) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Report healthy (200) or unhealthy (503) from `health_check`
+    pub fn with_health(self, healthy: bool) -> Self {
+        let body = String::new();
+        self.with_health_status(if healthy {
+            HealthStatus::Healthy { status: 200, body }
+        } else {
+            HealthStatus::Unhealthy { status: 503, body }
+        })
+    }
+
+    /// Set the result of `health_check`
+    pub fn with_health_status(mut self, health: HealthStatus) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Requests received so far, in order
+    pub fn requests(&self) -> Vec<InferenceRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Scripted replies not yet consumed
+    pub fn remaining_replies(&self) -> usize {
+        self.script.lock().unwrap().len()
+    }
+
+    /// Record `request`, wait out the latency, and take the next reply
+    async fn next_reply(&self, request: InferenceRequest) -> MockReply {
+        self.requests.lock().unwrap().push(request);
+
+        let in_flight = InFlight::enter(&self.in_flight);
+        if self
+            .capacity
+            .is_some_and(|capacity| in_flight.count > capacity)
+        {
+            return MockReply::RateLimited;
+        }
+        if !self.latency.is_zero() {
+            tokio::time::sleep(self.latency).await;
+        }
+
+        let reply = self.script.lock().unwrap().pop_front();
+        reply.unwrap_or_else(|| MockReply::Response(self.default_response.clone()))
+    }
+
+    fn constraints_unsupported(&self) -> ConstraintsUnsupported {
+        ConstraintsUnsupported {
+            model: self.model.clone(),
+            reason: format!(
+                "service rejected the request: {}",
+                CONSTRAINTS_UNSUPPORTED_CODE
+            ),
+        }
+    }
+}
+
+/// The error a rate-limited [`MockInferenceClient`] fails with
+fn rate_limited() -> ServiceError {
+    ServiceError {
+        status: 429,
+        message: "rate limited".to_string(),
+    }
+}
+
+/// Counts a mock generation as in flight for as long as it is alive
+struct InFlight {
+    counter: Arc<AtomicUsize>,
+
+    /// Generations in flight, including this one, when it started
+    count: usize,
+}
+
+impl InFlight {
+    fn enter(counter: &Arc<AtomicUsize>) -> Self {
+        let count = counter.fetch_add(1, Ordering::SeqCst) + 1;
+        Self {
+            counter: counter.clone(),
+            count,
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl InferenceClient for MockInferenceClient {
+    fn model_name(&self) -> &str {
+        &self.model
+    }
+
+    async fn generate_constrained(&self, request: InferenceRequest) -> Result<InferenceResponse> {
+        match self.next_reply(request).await {
+            MockReply::Response(response) => Ok(response),
+            MockReply::Failure(message) | MockReply::StreamFailure { message, .. } => {
+                Err(anyhow!(message))
+            }
+            MockReply::RateLimited => Err(rate_limited().into()),
+            MockReply::ConstraintsUnsupported => Err(self.constraints_unsupported().into()),
+        }
+    }
+
+    /// Streams the scripted response one whitespace-separated word at a time
+    async fn generate_stream(&self, request: InferenceRequest) -> Result<StreamingResult> {
+        use futures::StreamExt;
+
+        let include_constraint_events = request.include_constraint_events;
+        let (text, failure) = match self.next_reply(request).await {
+            MockReply::Response(response) => (response.generated_text, None),
+            MockReply::StreamFailure { emitted, message } => (emitted, Some(message)),
+            MockReply::Failure(message) => return Err(anyhow!(message)),
+            MockReply::RateLimited => return Err(rate_limited().into()),
+            MockReply::ConstraintsUnsupported => return Err(self.constraints_unsupported().into()),
+        };
+        let mut words: Vec<String> = text
+            .split_inclusive(char::is_whitespace)
+            .map(String::from)
+            .collect();
+        if words.is_empty() && failure.is_none() {
+            words.push(String::new());
+        }
+        let count = words.len();
+
+        let mut chunks: Vec<Result<StreamChunk>> = Vec::new();
+        for (token_index, text) in words.into_iter().enumerate() {
+            let events = self
+                .constraint_events
+                .iter()
+                .filter(|_| include_constraint_events)
+                .filter(|event| event.position.min(count.saturating_sub(1)) == token_index);
+            for event in events {
+                chunks.push(Ok(StreamChunk {
+                    text: String::new(),
+                    is_final: false,
+                    token_index,
+                    timestamp_ms: 0,
+                    finish_reason: None,
+                    model: None,
+                    constraint_event: Some(event.clone()),
+                    restarted: false,
+                }));
+            }
+            chunks.push(Ok(StreamChunk {
+                text,
+                is_final: failure.is_none() && token_index + 1 == count,
+                token_index,
+                timestamp_ms: 0,
+                finish_reason: None,
+                model: None,
+                constraint_event: None,
+                restarted: false,
+            }));
+        }
+        if let Some(message) = failure {
+            chunks.push(Err(anyhow!(message)));
+        }
+        let delay = self.chunk_delay;
+        Ok(Box::pin(futures::stream::iter(chunks).then(
+            move |chunk| async move {
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                chunk
+            },
+        )))
+    }
+
+    async fn health_check(&self) -> Result<HealthStatus> {
+        Ok(self.health.clone())
+    }
+
+    async fn model_info(&self) -> Result<ModelInfo> {
+        Ok(self.info.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> InferenceRequest {
+        InferenceRequest {
+            prompt: "test".to_string(),
+            constraints: serde_json::json!({}),
+            max_tokens: 10,
+            temperature: 0.0,
+            context: None,
+            seed: None,
+            idempotency_key: None,
+            include_logprobs: false,
+            timeout_ms: None,
+            include_constraint_events: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_replays_script_then_default() {
+        let client = MockInferenceClient::new("mock")
+            .then_rate_limited()
+            .then_fail("boom")
+            .then_respond("fn scripted() {}");
+
+        let err = client.generate_constrained(request()).await.unwrap_err();
+        assert!(err.to_string().contains("429"));
+        let err = client.generate_constrained(request()).await.unwrap_err();
+        assert_eq!(err.to_string(), "boom");
+
+        let response = client.generate_constrained(request()).await.unwrap();
+        assert_eq!(response.generated_text, "fn scripted() {}");
+        assert_eq!(client.remaining_replies(), 0);
+
+        let response = client.generate_constrained(request()).await.unwrap();
+        assert_eq!(response.generated_text, "fn mock() {}");
+        assert_eq!(client.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_mock_streams_words() {
+        use futures::StreamExt;
+
+        let client = MockInferenceClient::new("mock").then_respond("fn a() {}");
+        let chunks: Vec<StreamChunk> = client
+            .generate_stream(request())
+            .await
+            .unwrap()
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+
+        let text: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(text, vec!["fn ", "a() ", "{}"]);
+        assert!(chunks.last().unwrap().is_final);
+        assert!(!chunks[0].is_final);
+    }
+
+    #[tokio::test]
+    async fn test_mock_latency_and_health() {
+        let client = MockInferenceClient::new("mock")
+            .with_latency(Duration::from_millis(20))
+            .with_health(false);
+
+        let start = std::time::Instant::now();
+        client.generate_constrained(request()).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(!client.health_check().await.unwrap().is_ready());
+    }
+}
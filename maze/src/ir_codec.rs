@@ -0,0 +1,323 @@
+//! Wire formats for shipping [`ConstraintIR`] between processes
+//!
+//! JSON stays the default and the format of record. MessagePack is more
+//! compact and faster to encode and decode, which matters for large
+//! constraint sets passed across the FFI or process boundary. Structs are
+//! encoded with their field names, so optional fields and IR from older
+//! schema versions decode as they do from JSON. Decoded IR is not migrated;
+//! compilation does that.
+//!
+//! Bincode is not offered: it cannot decode `rich_context` (arbitrary JSON)
+//! or the optional fields the IR omits when empty.
+//!
+//! Cache keys never depend on the wire format. [`canonical_hash`] hashes the
+//! IR's canonical JSON form, so IR hashes the same however it arrived and
+//! whatever order its maps were built in. The IR serializes its maps with
+//! sorted keys in every format, so that form is simply its compact JSON.
+//!
+//! [`MazeConfig::ir_format`](crate::MazeConfig::ir_format) selects the format
+//! [`MazeOrchestrator::register_encoded`](crate::MazeOrchestrator::register_encoded)
+//! and the `constraint_register_encoded_ffi` entry point decode.
+//!
+//! ```
+//! use maze::ir_codec::IrFormat;
+//!
+//! let bytes = IrFormat::MessagePack.encode(&[])?;
+//! assert!(IrFormat::MessagePack.decode(&bytes)?.is_empty());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::{Context, Result};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashMap;
+use std::hash::Hasher;
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::ffi::ConstraintIR;
+
+/// Serialization format for constraint IR
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IrFormat {
+    /// JSON text
+    #[default]
+    Json,
+
+    /// MessagePack, with struct fields encoded by name
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl IrFormat {
+    /// Name of the format, as used in configuration
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::MessagePack => "msgpack",
+        }
+    }
+
+    /// Encode `constraints` in this format
+    pub fn encode(self, constraints: &[ConstraintIR]) -> Result<Vec<u8>> {
+        match self {
+            Self::Json => serde_json::to_vec(constraints).context("Failed to encode IR as JSON"),
+            Self::MessagePack => {
+                rmp_serde::to_vec_named(constraints).context("Failed to encode IR as MessagePack")
+            }
+        }
+    }
+
+    /// Decode constraints encoded with [`encode`](Self::encode) in this format
+    pub fn decode(self, bytes: &[u8]) -> Result<Vec<ConstraintIR>> {
+        match self {
+            Self::Json => serde_json::from_slice(bytes).context("Failed to decode JSON IR"),
+            Self::MessagePack => {
+                rmp_serde::from_slice(bytes).context("Failed to decode MessagePack IR")
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for IrFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "msgpack" | "messagepack" => Ok(Self::MessagePack),
+            other => anyhow::bail!("unknown IR format '{}' (expected json or msgpack)", other),
+        }
+    }
+}
+
+/// Feed the canonical form of `constraints` into `hasher`
+///
+/// The canonical form is the IR's compact JSON, whose maps are written with
+/// sorted keys, so equal IR hashes the same whatever its `HashMap` iteration
+/// order or the format it was transferred in. It is streamed into the hasher
+/// without building a string or a `serde_json::Value`.
+pub fn canonical_hash(constraints: &[ConstraintIR], hasher: &mut Xxh3) -> Result<()> {
+    serde_json::to_writer(HashWriter(hasher), constraints).context("Failed to hash constraints")
+}
+
+/// Serialize `map` with its keys in sorted order, and its JSON values as
+/// [`CanonicalValue`]s
+pub(crate) fn serialize_sorted<S: Serializer>(
+    map: &HashMap<String, serde_json::Value>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(k, _)| *k);
+    let mut out = serializer.serialize_map(Some(entries.len()))?;
+    for (key, value) in entries {
+        out.serialize_entry(key, &CanonicalValue(value))?;
+    }
+    out.end()
+}
+
+/// Serialize an optional JSON value as a [`CanonicalValue`]
+pub(crate) fn serialize_canonical<S: Serializer>(
+    value: &Option<serde_json::Value>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    value.as_ref().map(CanonicalValue).serialize(serializer)
+}
+
+/// A JSON value serialized with the keys of every object, at any depth, in
+/// sorted order
+///
+/// Like [`canonical_json`], but sorts references as it writes instead of
+/// rebuilding the value.
+pub struct CanonicalValue<'a>(pub &'a serde_json::Value);
+
+impl Serialize for CanonicalValue<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeSeq;
+        use serde_json::Value;
+
+        match self.0 {
+            Value::Object(map) => {
+                let mut entries: Vec<_> = map.iter().collect();
+                entries.sort_by_key(|(k, _)| *k);
+                let mut out = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    out.serialize_entry(key, &CanonicalValue(value))?;
+                }
+                out.end()
+            }
+            Value::Array(items) => {
+                let mut out = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    out.serialize_element(&CanonicalValue(item))?;
+                }
+                out.end()
+            }
+            other => other.serialize(serializer),
+        }
+    }
+}
+
+/// `value` with the keys of every object, at any depth, in sorted order
+///
+/// Objects are rebuilt explicitly rather than relying on `serde_json::Map`
+/// being sorted, which stops holding if anything in the build enables
+/// serde_json's `preserve_order` feature. Serialize the result with
+/// `to_string` or `to_writer` for the canonical text: no insignificant
+/// whitespace.
+pub fn canonical_json(value: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonical_json(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(canonical_json).collect()),
+        other => other,
+    }
+}
+
+/// `io::Write` adapter feeding bytes to a hasher
+struct HashWriter<'a>(&'a mut Xxh3);
+
+impl std::io::Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{JsonSchema, TokenMaskRules};
+    use std::collections::HashMap;
+
+    fn constraint(name: &str) -> ConstraintIR {
+        ConstraintIR {
+            name: name.to_string(),
+            json_schema: Some(JsonSchema {
+                schema_type: "object".to_string(),
+                properties: HashMap::from([
+                    ("id".to_string(), serde_json::json!({"type": "integer"})),
+                    ("name".to_string(), serde_json::json!({"type": "string"})),
+                    ("tags".to_string(), serde_json::json!({"type": "array"})),
+                ]),
+                required: vec!["id".to_string()],
+                additional_properties: false,
+            }),
+            grammar: None,
+            regex_patterns: vec![],
+            token_masks: Some(TokenMaskRules {
+                allowed_tokens: None,
+                forbidden_tokens: Some(vec![1, 2, 3]),
+            }),
+            type_inhabitation: None,
+            priority: 2,
+            rich_context: Some(serde_json::json!({"policy": {"deny": ["eval"]}, "n": 1.5})),
+            feasibility_score: 0.5,
+            is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    fn key(constraints: &[ConstraintIR]) -> u64 {
+        let mut hasher = Xxh3::new();
+        canonical_hash(constraints, &mut hasher).unwrap();
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_formats_round_trip() {
+        let constraints = vec![constraint("a"), constraint("b")];
+        for format in [IrFormat::Json, IrFormat::MessagePack] {
+            let bytes = format.encode(&constraints).unwrap();
+            let decoded = format.decode(&bytes).unwrap();
+            assert_eq!(
+                serde_json::to_value(&decoded).unwrap(),
+                serde_json::to_value(&constraints).unwrap(),
+                "{}",
+                format.as_str()
+            );
+        }
+    }
+
+    #[test]
+    fn test_msgpack_is_smaller_than_json() {
+        let constraints: Vec<_> = (0..20).map(|i| constraint(&format!("c{}", i))).collect();
+        let json = IrFormat::Json.encode(&constraints).unwrap();
+        let msgpack = IrFormat::MessagePack.encode(&constraints).unwrap();
+        assert!(msgpack.len() < json.len());
+    }
+
+    #[test]
+    fn test_hash_is_independent_of_transfer_format() {
+        let constraints = vec![constraint("a"), constraint("b")];
+        let expected = key(&constraints);
+        for format in [IrFormat::Json, IrFormat::MessagePack] {
+            let decoded = format
+                .decode(&format.encode(&constraints).unwrap())
+                .unwrap();
+            assert_eq!(key(&decoded), expected, "{}", format.as_str());
+        }
+    }
+
+    #[test]
+    fn test_hash_ignores_map_order() {
+        let mut reordered = constraint("a");
+        let schema = reordered.json_schema.as_mut().unwrap();
+        let mut properties: Vec<_> = schema.properties.drain().collect();
+        properties.reverse();
+        schema.properties = properties.into_iter().collect();
+        reordered.rich_context =
+            Some(serde_json::from_str(r#"{"n": 1.5, "policy": {"deny": ["eval"]}}"#).unwrap());
+        assert_eq!(key(&[reordered]), key(&[constraint("a")]));
+
+        let mut hasher = Xxh3::new();
+        hasher.write(&serde_json::to_vec(&[constraint("a")]).unwrap());
+        assert_eq!(key(&[constraint("a")]), hasher.finish());
+    }
+
+    #[test]
+    fn test_canonical_value_matches_canonical_json() {
+        let value: serde_json::Value =
+            serde_json::from_str(r#"{"b": [{"z": 1, "y": {"d": 2, "c": 3}}], "a": null}"#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&CanonicalValue(&value)).unwrap(),
+            canonical_json(value).to_string()
+        );
+    }
+
+    #[test]
+    fn test_canonical_json_sorts_nested_keys() {
+        let value: serde_json::Value =
+            serde_json::from_str(r#"{"b": [{"z": 1, "y": {"d": 2, "c": 3}}], "a": null}"#).unwrap();
+        assert_eq!(
+            canonical_json(value).to_string(),
+            r#"{"a":null,"b":[{"y":{"c":3,"d":2},"z":1}]}"#
+        );
+    }
+
+    #[test]
+    fn test_decoding_the_wrong_format_fails() {
+        let bytes = IrFormat::MessagePack.encode(&[constraint("a")]).unwrap();
+        let err = IrFormat::Json.decode(&bytes).unwrap_err();
+        assert!(err.to_string().contains("JSON"));
+        assert_eq!(
+            "msgpack".parse::<IrFormat>().unwrap(),
+            IrFormat::MessagePack
+        );
+        assert!("bincode".parse::<IrFormat>().is_err());
+    }
+}
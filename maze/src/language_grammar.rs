@@ -0,0 +1,569 @@
+//! Default grammar constraints per output language
+//!
+//! `GenerationContext::language` names the language the output should be
+//! in. [`LanguageGrammars`] maps language names to a grammar
+//! [`ConstraintIR`] that the orchestrator merges into requests for that
+//! language, so callers get syntactic constraint without writing a grammar.
+//! Requests that already carry a grammar keep their own. The merge is on
+//! by default; turn it off with `MazeConfig::language_grammars`.
+//!
+//! The registry ships with data-format grammars: [`json_grammar`],
+//! [`toml_grammar`], and [`csv_grammar`]. A programming language's grammar
+//! is too large, and too tied to a language version, to ship a useful
+//! default; register those with [`LanguageGrammars::register`] or
+//! `MazeOrchestrator::with_language_grammar`.
+//!
+//! Grammar terminals are literal strings, so character classes are spelled
+//! out as one rule per character; the bundled grammars cover printable
+//! ASCII.
+
+use std::collections::HashMap;
+
+use crate::ffi::{ConstraintIR, Grammar, GrammarRule};
+
+/// Prefix of the names of merged language grammar constraints
+pub const LANGUAGE_CONSTRAINT_PREFIX: &str = "language:";
+
+/// Grammar constraints keyed by language name (case-insensitive)
+#[derive(Debug, Clone, Default)]
+pub struct LanguageGrammars {
+    grammars: HashMap<String, ConstraintIR>,
+}
+
+impl LanguageGrammars {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry holding the bundled grammars: `json`, `toml`, and `csv`
+    pub fn bundled() -> Self {
+        let mut grammars = Self::new();
+        grammars.register("json", json_grammar());
+        grammars.register("toml", toml_grammar());
+        grammars.register("csv", csv_grammar());
+        grammars
+    }
+
+    /// Register `constraint` for `language`, replacing any existing one
+    ///
+    /// The constraint is renamed `language:<language>` so it shows up as
+    /// such in provenance and merge reports.
+    pub fn register(&mut self, language: &str, mut constraint: ConstraintIR) {
+        let language = language.to_ascii_lowercase();
+        constraint.name = format!("{}{}", LANGUAGE_CONSTRAINT_PREFIX, language);
+        self.grammars.insert(language, constraint);
+    }
+
+    /// Stop constraining `language`, returning its grammar if it had one
+    pub fn remove(&mut self, language: &str) -> Option<ConstraintIR> {
+        self.grammars.remove(&language.to_ascii_lowercase())
+    }
+
+    /// Grammar constraint for `language`, if one is registered
+    pub fn get(&self, language: &str) -> Option<&ConstraintIR> {
+        self.grammars.get(&language.to_ascii_lowercase())
+    }
+
+    /// Add the grammar for `language` to `constraints_ir`
+    ///
+    /// Nothing is added for an unknown language, or when the constraints
+    /// already include a grammar. Returns whether a grammar was added.
+    pub fn merge_into(&self, language: &str, constraints_ir: &mut Vec<ConstraintIR>) -> bool {
+        if constraints_ir.iter().any(|c| c.grammar.is_some()) {
+            return false;
+        }
+        match self.get(language) {
+            Some(grammar) => {
+                constraints_ir.push(grammar.clone());
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Grammar accepting one JSON value (RFC 8259), surrounding whitespace
+/// allowed
+///
+/// Unescaped string characters are limited to printable ASCII; other
+/// characters are written as `\u` escapes.
+pub fn json_grammar() -> ConstraintIR {
+    let mut rules = Vec::new();
+    let mut rule = |lhs: &str, rhs: &[&str]| {
+        rules.push(GrammarRule {
+            lhs: lhs.to_string(),
+            rhs: rhs.iter().map(|s| s.to_string()).collect(),
+        })
+    };
+
+    rule("json_text", &["json_ws", "json_value", "json_ws"]);
+    for value in [
+        "json_object",
+        "json_array",
+        "json_string",
+        "json_number",
+        "true",
+        "false",
+        "null",
+    ] {
+        rule("json_value", &[value]);
+    }
+
+    rule("json_object", &["{", "json_ws", "}"]);
+    rule("json_object", &["{", "json_members", "}"]);
+    rule("json_members", &["json_member"]);
+    rule("json_members", &["json_member", ",", "json_members"]);
+    rule(
+        "json_member",
+        &["json_ws", "json_string", "json_ws", ":", "json_element"],
+    );
+    rule("json_array", &["[", "json_ws", "]"]);
+    rule("json_array", &["[", "json_elements", "]"]);
+    rule("json_elements", &["json_element"]);
+    rule("json_elements", &["json_element", ",", "json_elements"]);
+    rule("json_element", &["json_ws", "json_value", "json_ws"]);
+
+    rule("json_string", &["\"", "json_chars", "\""]);
+    rule("json_chars", &[]);
+    rule("json_chars", &["json_char", "json_chars"]);
+    for c in (' '..='~').filter(|c| !matches!(c, '"' | '\\')) {
+        rule("json_char", &[&c.to_string()]);
+    }
+    rule("json_char", &["\\", "json_escape"]);
+    for escape in ["\"", "\\", "/", "b", "f", "n", "r", "t"] {
+        rule("json_escape", &[escape]);
+    }
+    rule(
+        "json_escape",
+        &["u", "json_hex", "json_hex", "json_hex", "json_hex"],
+    );
+    for c in ('0'..='9').chain('a'..='f').chain('A'..='F') {
+        rule("json_hex", &[&c.to_string()]);
+    }
+
+    rule("json_number", &["json_int", "json_frac", "json_exp"]);
+    rule("json_int", &["json_uint"]);
+    rule("json_int", &["-", "json_uint"]);
+    rule("json_uint", &["0"]);
+    rule("json_uint", &["json_onenine", "json_digits_opt"]);
+    rule("json_frac", &[]);
+    rule("json_frac", &[".", "json_digits"]);
+    rule("json_exp", &[]);
+    rule("json_exp", &["json_e", "json_sign", "json_digits"]);
+    rule("json_e", &["e"]);
+    rule("json_e", &["E"]);
+    rule("json_sign", &[]);
+    rule("json_sign", &["+"]);
+    rule("json_sign", &["-"]);
+    rule("json_digits", &["json_digit", "json_digits_opt"]);
+    rule("json_digits_opt", &[]);
+    rule("json_digits_opt", &["json_digit", "json_digits_opt"]);
+    rule("json_digit", &["0"]);
+    rule("json_digit", &["json_onenine"]);
+    for c in '1'..='9' {
+        rule("json_onenine", &[&c.to_string()]);
+    }
+
+    rule("json_ws", &[]);
+    for c in [" ", "\n", "\r", "\t"] {
+        rule("json_ws", &[c, "json_ws"]);
+    }
+
+    language_constraint("json", "json_text", rules)
+}
+
+/// Grammar accepting a TOML document (TOML 1.0), without dates, times, or
+/// multi-line strings
+///
+/// Covers key/value pairs with bare, quoted, and dotted keys, `[table]`
+/// and `[[array]]` headers, comments, basic and literal strings, integers,
+/// floats, booleans, arrays, and inline tables. String characters are
+/// limited to printable ASCII and tab; other characters are written as
+/// `\u` escapes.
+pub fn toml_grammar() -> ConstraintIR {
+    let mut rules = Vec::new();
+    let mut rule = |lhs: &str, rhs: &[&str]| {
+        rules.push(GrammarRule {
+            lhs: lhs.to_string(),
+            rhs: rhs.iter().map(|s| s.to_string()).collect(),
+        })
+    };
+
+    rule("toml_document", &["toml_expression", "toml_lines"]);
+    rule("toml_lines", &[]);
+    rule(
+        "toml_lines",
+        &["toml_newline", "toml_expression", "toml_lines"],
+    );
+    rule("toml_newline", &["\n"]);
+    rule("toml_newline", &["\r\n"]);
+    rule(
+        "toml_expression",
+        &["toml_ws", "toml_item", "toml_ws", "toml_comment"],
+    );
+    rule("toml_item", &[]);
+    rule("toml_item", &["toml_keyval"]);
+    rule("toml_item", &["[", "toml_ws", "toml_key", "toml_ws", "]"]);
+    rule("toml_item", &["[[", "toml_ws", "toml_key", "toml_ws", "]]"]);
+    rule("toml_comment", &[]);
+    rule("toml_comment", &["#", "toml_comment_chars"]);
+    rule("toml_comment_chars", &[]);
+    rule(
+        "toml_comment_chars",
+        &["toml_comment_char", "toml_comment_chars"],
+    );
+    for c in (' '..='~').chain(['\t']) {
+        rule("toml_comment_char", &[&c.to_string()]);
+    }
+
+    rule(
+        "toml_keyval",
+        &["toml_key", "toml_ws", "=", "toml_ws", "toml_value"],
+    );
+    rule("toml_key", &["toml_simple_key"]);
+    rule(
+        "toml_key",
+        &["toml_simple_key", "toml_ws", ".", "toml_ws", "toml_key"],
+    );
+    rule("toml_simple_key", &["toml_bare_key"]);
+    rule("toml_simple_key", &["toml_basic_string"]);
+    rule("toml_simple_key", &["toml_literal_string"]);
+    rule("toml_bare_key", &["toml_bare_char", "toml_bare_key_opt"]);
+    rule("toml_bare_key_opt", &[]);
+    rule(
+        "toml_bare_key_opt",
+        &["toml_bare_char", "toml_bare_key_opt"],
+    );
+    for c in ('A'..='Z')
+        .chain('a'..='z')
+        .chain('0'..='9')
+        .chain(['_', '-'])
+    {
+        rule("toml_bare_char", &[&c.to_string()]);
+    }
+
+    for value in [
+        "toml_basic_string",
+        "toml_literal_string",
+        "toml_integer",
+        "toml_float",
+        "true",
+        "false",
+        "toml_array",
+        "toml_inline_table",
+    ] {
+        rule("toml_value", &[value]);
+    }
+
+    rule("toml_basic_string", &["\"", "toml_basic_chars", "\""]);
+    rule("toml_basic_chars", &[]);
+    rule("toml_basic_chars", &["toml_basic_char", "toml_basic_chars"]);
+    for c in (' '..='~')
+        .chain(['\t'])
+        .filter(|c| !matches!(c, '"' | '\\'))
+    {
+        rule("toml_basic_char", &[&c.to_string()]);
+    }
+    rule("toml_basic_char", &["\\", "toml_escape"]);
+    for escape in ["\"", "\\", "b", "f", "n", "r", "t"] {
+        rule("toml_escape", &[escape]);
+    }
+    rule(
+        "toml_escape",
+        &["u", "toml_hex", "toml_hex", "toml_hex", "toml_hex"],
+    );
+    for c in ('0'..='9').chain('a'..='f').chain('A'..='F') {
+        rule("toml_hex", &[&c.to_string()]);
+    }
+    rule("toml_literal_string", &["'", "toml_literal_chars", "'"]);
+    rule("toml_literal_chars", &[]);
+    rule(
+        "toml_literal_chars",
+        &["toml_literal_char", "toml_literal_chars"],
+    );
+    for c in (' '..='~').chain(['\t']).filter(|&c| c != '\'') {
+        rule("toml_literal_char", &[&c.to_string()]);
+    }
+
+    rule("toml_integer", &["toml_sign", "toml_uint"]);
+    rule("toml_uint", &["0"]);
+    rule("toml_uint", &["toml_onenine", "toml_digits_opt"]);
+    rule(
+        "toml_float",
+        &["toml_integer", ".", "toml_digits", "toml_exp"],
+    );
+    rule(
+        "toml_float",
+        &["toml_integer", "toml_e", "toml_sign", "toml_digits"],
+    );
+    rule("toml_float", &["toml_sign", "inf"]);
+    rule("toml_float", &["toml_sign", "nan"]);
+    rule("toml_exp", &[]);
+    rule("toml_exp", &["toml_e", "toml_sign", "toml_digits"]);
+    rule("toml_e", &["e"]);
+    rule("toml_e", &["E"]);
+    rule("toml_sign", &[]);
+    rule("toml_sign", &["+"]);
+    rule("toml_sign", &["-"]);
+    // Underscores may separate digits
+    rule("toml_digits", &["toml_digit", "toml_digits_opt"]);
+    rule("toml_digits_opt", &[]);
+    rule("toml_digits_opt", &["toml_digit", "toml_digits_opt"]);
+    rule("toml_digits_opt", &["_", "toml_digit", "toml_digits_opt"]);
+    rule("toml_digit", &["0"]);
+    rule("toml_digit", &["toml_onenine"]);
+    for c in '1'..='9' {
+        rule("toml_onenine", &[&c.to_string()]);
+    }
+
+    rule("toml_array", &["[", "toml_array_ws", "]"]);
+    rule("toml_array", &["[", "toml_array_values", "]"]);
+    rule(
+        "toml_array",
+        &["[", "toml_array_values", ",", "toml_array_ws", "]"],
+    );
+    rule(
+        "toml_array_values",
+        &["toml_array_ws", "toml_value", "toml_array_ws"],
+    );
+    rule(
+        "toml_array_values",
+        &[
+            "toml_array_ws",
+            "toml_value",
+            "toml_array_ws",
+            ",",
+            "toml_array_values",
+        ],
+    );
+    rule("toml_array_ws", &[]);
+    for c in [" ", "\t", "\n", "\r\n"] {
+        rule("toml_array_ws", &[c, "toml_array_ws"]);
+    }
+    rule("toml_inline_table", &["{", "toml_ws", "}"]);
+    rule("toml_inline_table", &["{", "toml_inline_keyvals", "}"]);
+    rule(
+        "toml_inline_keyvals",
+        &["toml_ws", "toml_keyval", "toml_ws"],
+    );
+    rule(
+        "toml_inline_keyvals",
+        &[
+            "toml_ws",
+            "toml_keyval",
+            "toml_ws",
+            ",",
+            "toml_inline_keyvals",
+        ],
+    );
+
+    rule("toml_ws", &[]);
+    for c in [" ", "\t"] {
+        rule("toml_ws", &[c, "toml_ws"]);
+    }
+
+    language_constraint("toml", "toml_document", rules)
+}
+
+/// Grammar accepting CSV (RFC 4180), with `\n` or `\r\n` line endings
+///
+/// Fields are unquoted, or quoted with `""` for a literal quote; quoted
+/// fields may span lines. Characters are limited to printable ASCII and
+/// tab.
+pub fn csv_grammar() -> ConstraintIR {
+    let mut rules = Vec::new();
+    let mut rule = |lhs: &str, rhs: &[&str]| {
+        rules.push(GrammarRule {
+            lhs: lhs.to_string(),
+            rhs: rhs.iter().map(|s| s.to_string()).collect(),
+        })
+    };
+
+    rule("csv_file", &["csv_record", "csv_records"]);
+    rule("csv_records", &[]);
+    rule("csv_records", &["csv_newline", "csv_record", "csv_records"]);
+    rule("csv_newline", &["\n"]);
+    rule("csv_newline", &["\r\n"]);
+    rule("csv_record", &["csv_field", "csv_fields"]);
+    rule("csv_fields", &[]);
+    rule("csv_fields", &[",", "csv_field", "csv_fields"]);
+    rule("csv_field", &["csv_unquoted"]);
+    rule("csv_field", &["\"", "csv_quoted", "\""]);
+    rule("csv_unquoted", &[]);
+    rule("csv_unquoted", &["csv_char", "csv_unquoted"]);
+    for c in (' '..='~')
+        .chain(['\t'])
+        .filter(|c| !matches!(c, ',' | '"'))
+    {
+        rule("csv_char", &[&c.to_string()]);
+    }
+    rule("csv_quoted", &[]);
+    rule("csv_quoted", &["csv_quoted_char", "csv_quoted"]);
+    for c in (' '..='~').chain(['\t', '\n', '\r']).filter(|&c| c != '"') {
+        rule("csv_quoted_char", &[&c.to_string()]);
+    }
+    rule("csv_quoted_char", &["\"\""]);
+
+    language_constraint("csv", "csv_file", rules)
+}
+
+/// A bundled grammar constraint for `language`
+fn language_constraint(
+    language: &str,
+    start_symbol: &str,
+    rules: Vec<GrammarRule>,
+) -> ConstraintIR {
+    ConstraintIR {
+        name: format!("{}{}", LANGUAGE_CONSTRAINT_PREFIX, language),
+        json_schema: None,
+        grammar: Some(Grammar {
+            rules,
+            start_symbol: start_symbol.to_string(),
+        }),
+        regex_patterns: vec![],
+        token_masks: None,
+        type_inhabitation: None,
+        priority: 0,
+        rich_context: None,
+        feasibility_score: 0.0,
+        is_feasible: true,
+        schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeSet, HashMap};
+
+    /// Whether `grammar` derives exactly `text`
+    ///
+    /// Symbols matching a rule's left-hand side are nonterminals, anything
+    /// else is a literal, as in [`crate::bnf::add_grammar`]. Memoized
+    /// top-down matching; fine for grammars without left recursion.
+    fn accepts(grammar: &Grammar, text: &str) -> bool {
+        fn ends(
+            grammar: &Grammar,
+            text: &str,
+            symbol: &str,
+            pos: usize,
+            memo: &mut HashMap<(String, usize), BTreeSet<usize>>,
+        ) -> BTreeSet<usize> {
+            if !grammar.rules.iter().any(|r| r.lhs == symbol) {
+                return text[pos..]
+                    .starts_with(symbol)
+                    .then_some(pos + symbol.len())
+                    .into_iter()
+                    .collect();
+            }
+            if let Some(found) = memo.get(&(symbol.to_string(), pos)) {
+                return found.clone();
+            }
+            let mut found = BTreeSet::new();
+            for rule in grammar.rules.iter().filter(|r| r.lhs == symbol) {
+                let mut positions = BTreeSet::from([pos]);
+                for part in &rule.rhs {
+                    positions = positions
+                        .into_iter()
+                        .flat_map(|p| ends(grammar, text, part, p, memo))
+                        .collect();
+                }
+                found.extend(positions);
+            }
+            memo.insert((symbol.to_string(), pos), found.clone());
+            found
+        }
+        ends(grammar, text, &grammar.start_symbol, 0, &mut HashMap::new()).contains(&text.len())
+    }
+
+    #[test]
+    fn test_json_grammar_accepts_json_and_rejects_the_rest() {
+        let json = json_grammar();
+        let grammar = json.grammar.as_ref().unwrap();
+        for valid in [
+            r#"{"id": 7, "tags": ["a", "b\n"], "ok": true}"#,
+            " [1, -0.5, 2e10, 3.25E-2, null] ",
+            r#""caf\u00e9""#,
+            "{}",
+        ] {
+            assert!(accepts(grammar, valid), "rejected {}", valid);
+            assert!(serde_json::from_str::<serde_json::Value>(valid).is_ok());
+        }
+        for invalid in [r#"{"id": }"#, "[1, 2,]", "01", "{'a': 1}", "fn main() {}"] {
+            assert!(!accepts(grammar, invalid), "accepted {}", invalid);
+        }
+
+        // The bundled grammar is expressible in the BNF dialects too
+        let mut rules = crate::bnf::RuleSet::new();
+        crate::bnf::add_grammar(&mut rules, "json", grammar).unwrap();
+    }
+
+    #[test]
+    fn test_toml_grammar_accepts_toml_and_rejects_the_rest() {
+        let toml = toml_grammar();
+        let grammar = toml.grammar.as_ref().unwrap();
+        for valid in [
+            "name = \"maze\"\nversion = 2\n",
+            "# settings\n[server]\nhost = 'localhost' # local\nport = 8_080\n",
+            "[[bin]]\nname.first = \"a\\tb\"\nratio = -0.5e3\nok = true\n",
+            "tags = [ \"a\", \"b\", ]\npoint = { x = 1, y = 2 }\n",
+            "",
+        ] {
+            assert!(accepts(grammar, valid), "rejected {}", valid);
+        }
+        for invalid in [
+            "name = \n",
+            "[server\n",
+            "port = 08\n",
+            "a = 1 b = 2\n",
+            "{\"a\": 1}",
+        ] {
+            assert!(!accepts(grammar, invalid), "accepted {}", invalid);
+        }
+
+        let mut rules = crate::bnf::RuleSet::new();
+        crate::bnf::add_grammar(&mut rules, "toml", grammar).unwrap();
+    }
+
+    #[test]
+    fn test_csv_grammar_accepts_csv_and_rejects_the_rest() {
+        let csv = csv_grammar();
+        let grammar = csv.grammar.as_ref().unwrap();
+        for valid in [
+            "id,name\n1,ada\n2,grace\n",
+            "a,\"b, \"\"quoted\"\"\nline\",c\r\n",
+            ",,",
+        ] {
+            assert!(accepts(grammar, valid), "rejected {}", valid);
+        }
+        for invalid in ["a,\"b", "a,b\"c", "\"a\"b"] {
+            assert!(!accepts(grammar, invalid), "accepted {}", invalid);
+        }
+
+        let mut rules = crate::bnf::RuleSet::new();
+        crate::bnf::add_grammar(&mut rules, "csv", grammar).unwrap();
+    }
+
+    #[test]
+    fn test_registry_is_case_insensitive_and_keeps_existing_grammars() {
+        let mut grammars = LanguageGrammars::bundled();
+        let custom = ConstraintIR {
+            name: "anything".to_string(),
+            ..json_grammar()
+        };
+        grammars.register("JSONC", custom);
+        assert_eq!(grammars.get("jsonc").unwrap().name, "language:jsonc");
+
+        let mut constraints = Vec::new();
+        assert!(grammars.merge_into("Json", &mut constraints));
+        assert_eq!(constraints[0].name, "language:json");
+        // A request with a grammar keeps it
+        assert!(!grammars.merge_into("json", &mut constraints));
+        assert_eq!(constraints.len(), 1);
+
+        assert!(!grammars.merge_into("cobol", &mut Vec::new()));
+        assert!(grammars.remove("json").is_some());
+        assert!(grammars.get("json").is_none());
+    }
+}
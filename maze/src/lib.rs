@@ -26,6 +26,9 @@
 //!         max_tokens: 2048,
 //!         temperature: 0.7,
 //!         context: None,
+//!         seed: None,
+//!         timeout_ms: None,
+//!         history: Vec::new(),
 //!     };
 //!
 //!     let result = orchestrator.generate(request).await?;
@@ -35,20 +38,68 @@
 //! ```
 
 pub mod adaptive_selector;
+pub mod bnf;
+pub mod budget;
+pub mod cache_backend;
+pub mod calibration;
+pub mod candidate_scorer;
+pub mod clock;
+pub mod concurrency;
+pub mod constraint_builder;
+pub mod constraint_check;
+pub mod constraint_format;
+pub mod constraint_template;
+pub mod context_compression;
+pub mod context_provider;
+pub mod context_window;
 pub mod diffusion;
+pub mod edit;
+pub mod explain;
+pub mod fanout;
 pub mod ffi;
+pub mod fim;
+pub mod format;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod health;
+pub mod hole_id;
+pub mod hole_ordering;
+pub mod incremental;
+pub mod inference;
+pub mod ir_codec;
+pub mod language_grammar;
+pub mod lint;
+pub mod merge;
+pub mod migrate;
 pub mod modal_client;
+pub mod model_chain;
 pub mod model_router;
 pub mod model_selector;
+pub mod output_guard;
+pub mod policy;
+pub mod post_process;
 pub mod progressive_refinement;
+pub mod prompt;
 pub mod python;
+pub mod redaction;
+pub mod refinement_provenance;
+pub mod refinement_report;
+pub mod replicas;
+pub mod runaway;
+#[cfg(feature = "signing")]
+pub mod signing;
+mod single_flight;
+mod sse;
 pub mod strategy_stats;
+pub mod syntax;
 pub mod telemetry;
+pub mod trace;
 
 use anyhow::{Context, Result};
 use lru::LruCache;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use single_flight::SingleFlight;
+use std::collections::{BTreeMap, HashMap};
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -56,33 +107,152 @@ use tokio::sync::Mutex;
 pub use adaptive_selector::{
     AdaptiveConfig, AdaptiveStrategySelector, SelectionDecision, Strategy,
 };
+pub use budget::{BudgetExhaustedAction, BudgetState, TokenBudget};
+pub use cache_backend::{CacheBackend, InMemoryCache};
+pub use calibration::{
+    samples_from_attempts, CalibrationCurve, CalibrationSample, ConfidenceCalibration,
+};
+pub use candidate_scorer::{CandidateScorer, ConfidenceScorer, EnsembleCandidate, SyntaxScorer};
+pub use clock::{Clock, MockClock, SystemClock};
+pub use concurrency::{AdaptiveConcurrencyConfig, Busy, ConcurrencyLimiter, LoadStats};
+pub use constraint_builder::{ConstraintBuilder, InvalidConstraint};
+pub use constraint_check::{ConstraintCheck, ConstraintKind};
+pub use constraint_format::{
+    ConstraintCompiler, ConstraintFormat, GrammarCompiler, LlguidanceCompiler,
+};
+pub use constraint_template::{ConstraintTemplate, TemplateError};
+pub use context_compression::{CompressionStrategy, ContextCompression};
+pub use context_provider::{ContextProvider, ContextSnippet, NoopContextProvider};
+#[cfg(feature = "tokenizers")]
+pub use context_window::HfTokenizer;
+pub use context_window::{
+    CharRatioEstimator, ContextOverflow, ContextOverflowPolicy, ContextSection, TokenEstimator,
+};
 pub use diffusion::{DiffusionConfig, DiffusionGenerator, DiffusionResult, NoiseSchedule};
+pub use edit::{EditError, EditResponse, RegeneratedSpan, TextEdit};
+pub use explain::{explain, ConstraintExplanation};
+pub use fanout::{FanOutError, FanOutSummary, LagPolicy, StreamFanOut, Subscription};
 pub use ffi::{ConstraintIR, FillConstraint, GenerationResult, HoleSpec, Intent};
+pub use fim::{MultiHoleFill, MultiHoleRequest};
+pub use format::{
+    CommandFormatter, FormatOutcome, FormatPolicy, Formatter, Formatters, WhitespaceNormalizer,
+};
+pub use health::{HealthCheckConfig, HealthPayloadCheck, HealthStatus};
+pub use hole_id::{DuplicateHoleId, HoleIdAllocator};
+pub use hole_ordering::{ByConstraintCount, ById, ByScale, BySourceLocation, HoleOrdering};
+pub use incremental::{ConstraintDiff, IncrementalCompiler};
+pub use inference::{InferenceClient, MockInferenceClient, MockReply};
+pub use ir_codec::IrFormat;
+pub use language_grammar::LanguageGrammars;
+pub use lint::{lint_constraints, ConstraintLint, LintKind, LintSeverity};
+pub use merge::{distinct_names, ConstraintMergeReport, MergeAction, MergeEvent};
+pub use migrate::{
+    migrate_constraints, migrate_provenance, migrate_response, SchemaVersionError,
+    RESPONSE_SCHEMA_VERSION,
+};
 pub use modal_client::{
-    EnsembleClient, EnsembleConfig, EnsembleMetrics, InferenceRequest, InferenceResponse,
-    ModalClient, ModalConfig, ModelMetrics, StreamChunk, StreamingResult,
+    AttemptStatus, AttemptTiming, AuthScheme, ConstraintCostSummary, ConstraintEvent,
+    ConstraintKindStats, ConstraintsUnsupported, DeadlinePolicy, EnsembleClient, EnsembleConfig,
+    EnsembleMetrics, EnsemblePolicy, FinishReason, InferenceRequest, InferenceResponse,
+    ModalClient, ModalConfig, ModelInfo, ModelMetrics, RequestTimedOut, ResponseTooLarge,
+    ServiceError, StreamChunk, StreamDeadline, StreamDeadlineExceeded, StreamStalled,
+    StreamingResult, TokenLogprob, TopLogprob, Transport,
 };
+pub use model_chain::{ChainAttempt, ChainOutcome, ModelChainConfig};
 pub use model_router::{ModelCapability, ModelEndpoint, ModelRouter, RoutingDecision};
 pub use model_selector::{ModelChoice, ModelSelector};
+pub use output_guard::{OutputGuard, RejectedOutput};
+pub use policy::{PolicyConstraint, PolicyViolation};
+pub use post_process::{PostProcessConfig, PostProcessStep, PostProcessed};
 pub use progressive_refinement::{
-    FailureStrategy, HoleState, HoleStatus, ProgressiveRefiner, RefinementConfig, RefinementResult,
+    decomposition_tree, DecompositionNode, FailureStrategy, FillTokenLimits, HoleState, HoleStatus,
+    PlannedFill, ProgressiveRefiner, RefinementConfig, RefinementPlan, RefinementResult,
+};
+pub use prompt::{AssembledPrompt, ConversationTurn, PromptBuilder, PromptInput};
+pub use redaction::RedactionPolicy;
+pub use refinement_provenance::{
+    AttemptProvenance, HoleProvenance, RefinementProvenance, SourceSpan,
 };
+pub use refinement_report::{RefinementReport, ReportFormat, ReportRule};
+pub use replicas::{CircuitBreakerConfig, EndpointHealth, ReplicaEndpoint};
+pub use runaway::{OutputLimit, RunawayGeneration, RunawayLimits};
+pub use sse::StreamEventError;
 pub use strategy_stats::{StatsKey, StatsSummary, StrategyStats, StrategyStatsStore};
+pub use syntax::{NoopSyntaxValidator, SyntaxError, SyntaxValidator, SyntaxValidators};
 pub use telemetry::{FillOutcome, TelemetryStore};
+pub use trace::{read_trace, JsonlTraceSink, TraceRecord, TraceSink};
 
 /// Main orchestrator for constrained code generation
 ///
 /// Coordinates between Zig constraint engines and inference services
 pub struct MazeOrchestrator {
-    /// Client for communicating with Modal inference service
-    modal_client: ModalClient,
+    /// Client for the inference service (Modal in production)
+    client: Arc<dyn InferenceClient>,
 
-    /// LRU cache for compiled constraints to avoid re-compilation
-    /// Uses LRU eviction policy for O(1) cache operations
-    constraint_cache: Arc<Mutex<LruCache<String, CompiledConstraint>>>,
+    /// Cache of compiled constraints to avoid re-compilation; an LRU of
+    /// this orchestrator's own unless a shared backend is supplied
+    constraint_cache: Arc<dyn CacheBackend>,
 
     /// Configuration
     config: MazeConfig,
+
+    /// Token estimator for context-window checks
+    token_estimator: Arc<dyn TokenEstimator>,
+
+    /// Assembles the model prompt from the request prompt and context
+    prompt_builder: PromptBuilder,
+
+    /// Supplies retrieved snippets to include in the prompt
+    context_provider: Arc<dyn ContextProvider>,
+
+    /// Time source for the response cache TTL and readiness polling
+    clock: Arc<dyn Clock>,
+
+    /// Summarizes snippets under `CompressionStrategy::Summarize`; the
+    /// primary client if unset
+    summarizer: Option<Arc<dyn InferenceClient>>,
+
+    /// Per-language syntax checks run on generated code
+    syntax_validators: SyntaxValidators,
+
+    /// Per-language formatters run under `MazeConfig::format_policy`
+    formatters: Formatters,
+
+    /// Grammar constraints merged into requests for their language
+    language_grammars: LanguageGrammars,
+
+    /// LRU cache of full generation responses with their insertion time
+    response_cache: ResponseCache,
+
+    /// Caps in-flight generations at `MazeConfig::max_concurrent_requests`
+    limiter: ConcurrencyLimiter,
+
+    /// Identical generations in flight, shared with duplicate callers
+    in_flight: SingleFlight<GenerationResponse>,
+
+    /// Models tried in order when the primary fails or answers poorly
+    fallback_clients: Vec<Arc<dyn InferenceClient>>,
+
+    /// Constraint sets registered for generation by handle
+    registered: std::sync::Mutex<HashMap<u64, Arc<RegisteredConstraints>>>,
+
+    /// Id of the next registered set; handles start at 1
+    next_handle: std::sync::atomic::AtomicU64,
+
+    /// Key used to sign each response's provenance
+    #[cfg(feature = "signing")]
+    signing_key: Option<Arc<signing::SigningKey>>,
+
+    /// Records each call to a model, when tracing is on
+    trace_sink: Option<Arc<dyn TraceSink>>,
+}
+
+/// LRU cache of generation responses; `None` when the capacity is zero
+type ResponseCache = Arc<Mutex<Option<LruCache<String, (std::time::Instant, GenerationResponse)>>>>;
+
+/// An LRU cache holding `capacity` entries, or `None` if `capacity` is zero
+fn lru_with_capacity<K: std::hash::Hash + Eq, V>(capacity: usize) -> Option<LruCache<K, V>> {
+    NonZeroUsize::new(capacity).map(LruCache::new)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -96,11 +266,280 @@ pub struct MazeConfig {
     /// Enable constraint caching
     pub enable_cache: bool,
 
-    /// Cache size limit
+    /// Cache size limit; zero disables the constraint cache
     pub cache_size_limit: usize,
 
     /// Request timeout in seconds
     pub timeout_secs: u64,
+
+    /// Run the constraint linter on every compilation and log its findings
+    #[serde(default)]
+    pub lint_on_compile: bool,
+
+    /// Fail a generation whose provenance does not record every parameter
+    /// of the request the model actually received, with
+    /// [`UnrecordedParameters`]
+    ///
+    /// Debug builds always assert this; strict mode checks it in release
+    /// builds too.
+    #[serde(default)]
+    pub strict_provenance: bool,
+
+    /// What to do when a request exceeds the model's context window
+    #[serde(default)]
+    pub context_overflow: ContextOverflowPolicy,
+
+    /// Order in which context is trimmed under `ContextOverflowPolicy::Truncate`
+    #[serde(default = "context_window::default_truncation_order")]
+    pub truncation_order: Vec<ContextSection>,
+
+    /// Maximum tokens of retrieved context snippets to include in a prompt
+    #[serde(default = "default_snippet_token_budget")]
+    pub snippet_token_budget: usize,
+
+    /// How snippets that do not fit `snippet_token_budget` are compressed
+    #[serde(default)]
+    pub context_compression: CompressionStrategy,
+
+    /// Caching of full generation responses
+    #[serde(default)]
+    pub response_cache: ResponseCacheConfig,
+
+    /// Redaction of prompts and generated code in logs, errors, and
+    /// provenance; also applied to the Modal client built by
+    /// [`MazeOrchestrator::with_config`]
+    #[serde(default)]
+    pub redaction: RedactionPolicy,
+
+    /// Maximum generations in flight at once; zero means unlimited
+    ///
+    /// Excess callers wait for a slot. Response cache hits do not take one.
+    #[serde(default)]
+    pub max_concurrent_requests: usize,
+
+    /// How long a caller may wait for a generation slot before failing with
+    /// [`Busy`], in milliseconds; `None` waits indefinitely
+    #[serde(default)]
+    pub queue_timeout_ms: Option<u64>,
+
+    /// Adapt the concurrency limit to the backend within these bounds,
+    /// starting from `max_concurrent_requests`; `None` keeps the limit fixed
+    #[serde(default)]
+    pub adaptive_concurrency: Option<AdaptiveConcurrencyConfig>,
+
+    /// Format constraints are compiled to; grammar formats target
+    /// llama.cpp-style backends
+    #[serde(default)]
+    pub constraint_format: ConstraintFormat,
+
+    /// Format of encoded IR passed to
+    /// [`MazeOrchestrator::register_encoded`] and
+    /// `constraint_register_encoded_ffi`; cache keys do not depend on it
+    #[serde(default)]
+    pub ir_format: IrFormat,
+
+    /// The model's `tokenizer.json`, loaded by
+    /// [`MazeOrchestrator::with_config`] as the token estimator for context
+    /// budgets and token counts; requires the `tokenizers` feature
+    #[serde(default)]
+    pub tokenizer_path: Option<std::path::PathBuf>,
+
+    /// Share one generation between identical concurrent requests
+    ///
+    /// A request that arrives while an identical one (same prompt,
+    /// constraints, sampling parameters, seed, and context) is in flight
+    /// waits for that call's response instead of making its own. Disable
+    /// when concurrent callers need independent samples.
+    #[serde(default = "default_coalesce_requests")]
+    pub coalesce_requests: bool,
+
+    /// Constrain output to the grammar registered for the request's
+    /// `GenerationContext::language`, if any; see [`LanguageGrammars`]
+    ///
+    /// On by default; only languages with a registered grammar, such as
+    /// the bundled `json`, `toml`, and `csv`, are affected.
+    #[serde(default = "default_language_grammars")]
+    pub language_grammars: bool,
+
+    /// Rejects empty or whitespace-only generations, retrying them
+    #[serde(default)]
+    pub output_guard: OutputGuard,
+
+    /// Language-aware cleanup of generated code before validation
+    #[serde(default)]
+    pub post_process: PostProcessConfig,
+
+    /// Fallback models tried in order when the primary fails, answers with
+    /// low confidence, or produces code that does not validate
+    #[serde(default)]
+    pub model_chain: ModelChainConfig,
+
+    /// Overall time limit for streaming generations, and whether reaching
+    /// it yields the partial output or an error; `None` for no limit
+    #[serde(default)]
+    pub stream_deadline: Option<StreamDeadline>,
+
+    /// Most output one generation may produce; `None` for no limit
+    ///
+    /// Streams are cut off as soon as they cross it, returning what was
+    /// generated with [`FinishReason::Runaway`]; complete responses that
+    /// exceed it are retried like output `output_guard` rejects.
+    #[serde(default)]
+    pub runaway_limit: Option<OutputLimit>,
+
+    /// When output is run through the formatter registered for its
+    /// language (see [`MazeOrchestrator::with_formatter`])
+    #[serde(default)]
+    pub format_policy: FormatPolicy,
+
+    /// Ask for a [`ConstraintEvent`] chunk for each token the constrained
+    /// decoder masks, on streaming generations whose backend reports them
+    ///
+    /// [`generate_streamed`](MazeOrchestrator::generate_streamed) counts
+    /// them into `ValidationResult::metadata` as `constraint_events`.
+    #[serde(default)]
+    pub include_constraint_events: bool,
+
+    /// Whether a request without constraints is generated unconstrained or
+    /// rejected
+    #[serde(default)]
+    pub empty_constraints: EmptyConstraintsPolicy,
+}
+
+/// What to do with a request that carries no constraints
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyConstraintsPolicy {
+    /// Generate without constraints: nothing is compiled, the model is sent
+    /// no schema, and provenance records [`UNCONSTRAINED_HASH`]
+    #[default]
+    Unconstrained,
+
+    /// Fail with a [`NoConstraints`] error
+    Reject,
+}
+
+/// `Provenance::constraints_hash` of a generation made without constraints
+pub const UNCONSTRAINED_HASH: &str = "none";
+
+fn default_coalesce_requests() -> bool {
+    true
+}
+
+fn default_language_grammars() -> bool {
+    true
+}
+
+fn default_snippet_token_budget() -> usize {
+    1024
+}
+
+impl MazeConfig {
+    /// Load configuration from environment variables
+    ///
+    /// Reads `MAZE_MAX_TOKENS`, `MAZE_TEMPERATURE`, `MAZE_CACHE_SIZE`,
+    /// `MAZE_TIMEOUT_SECS`, `MAZE_MAX_CONCURRENT_REQUESTS`,
+    /// `MAZE_QUEUE_TIMEOUT_MS`, and `MAZE_ENABLE_CACHE`; unset variables keep
+    /// their [`Default`] values. Fails with a message naming the variable if
+    /// a value cannot be parsed or is out of range.
+    pub fn from_env() -> Result<Self> {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Build configuration from a variable lookup, as for [`from_env`](Self::from_env)
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        fn parse<T: std::str::FromStr>(
+            lookup: &impl Fn(&str) -> Option<String>,
+            name: &str,
+            expected: &str,
+        ) -> Result<Option<T>> {
+            lookup(name)
+                .map(|raw| {
+                    raw.trim().parse::<T>().map_err(|_| {
+                        anyhow::anyhow!("{} must be {}, got '{}'", name, expected, raw)
+                    })
+                })
+                .transpose()
+        }
+
+        let mut config = Self::default();
+
+        if let Some(max_tokens) = parse(&lookup, "MAZE_MAX_TOKENS", "a positive integer")? {
+            if max_tokens == 0 {
+                anyhow::bail!("MAZE_MAX_TOKENS must be a positive integer, got '0'");
+            }
+            config.max_tokens = max_tokens;
+        }
+
+        if let Some(temperature) = parse::<f32>(&lookup, "MAZE_TEMPERATURE", "a number")? {
+            if !temperature.is_finite() || temperature < 0.0 {
+                anyhow::bail!(
+                    "MAZE_TEMPERATURE must be a non-negative number, got '{}'",
+                    temperature
+                );
+            }
+            config.temperature = temperature;
+        }
+
+        if let Some(cache_size) = parse(&lookup, "MAZE_CACHE_SIZE", "an integer")? {
+            config.cache_size_limit = cache_size;
+        }
+
+        if let Some(timeout_secs) = parse(&lookup, "MAZE_TIMEOUT_SECS", "an integer")? {
+            config.timeout_secs = timeout_secs;
+        }
+
+        if let Some(max) = parse(&lookup, "MAZE_MAX_CONCURRENT_REQUESTS", "an integer")? {
+            config.max_concurrent_requests = max;
+        }
+
+        if let Some(timeout_ms) = parse(&lookup, "MAZE_QUEUE_TIMEOUT_MS", "an integer")? {
+            config.queue_timeout_ms = Some(timeout_ms);
+        }
+
+        if let Some(path) = lookup("MAZE_TOKENIZER") {
+            config.tokenizer_path = Some(path.into());
+        }
+
+        if let Some(raw) = lookup("MAZE_ENABLE_CACHE") {
+            config.enable_cache = match raw.trim().to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => true,
+                "0" | "false" | "no" | "off" => false,
+                _ => anyhow::bail!("MAZE_ENABLE_CACHE must be true or false, got '{}'", raw),
+            };
+        }
+
+        Ok(config)
+    }
+}
+
+/// Configuration for the generation response cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseCacheConfig {
+    /// Enable response caching
+    pub enabled: bool,
+
+    /// Maximum number of cached responses; zero disables the cache
+    pub size_limit: usize,
+
+    /// How long a cached response stays valid, in seconds
+    pub ttl_secs: u64,
+
+    /// Only cache deterministic requests (seed set and temperature 0)
+    ///
+    /// Caching stochastic requests would keep serving one sample forever.
+    pub deterministic_only: bool,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            size_limit: 256,
+            ttl_secs: 3600,
+            deterministic_only: true,
+        }
+    }
 }
 
 impl Default for MazeConfig {
@@ -111,6 +550,91 @@ impl Default for MazeConfig {
             enable_cache: true,
             cache_size_limit: 1000,
             timeout_secs: 300,
+            lint_on_compile: false,
+            strict_provenance: false,
+            context_overflow: ContextOverflowPolicy::Reject,
+            truncation_order: context_window::default_truncation_order(),
+            snippet_token_budget: default_snippet_token_budget(),
+            context_compression: CompressionStrategy::None,
+            response_cache: ResponseCacheConfig::default(),
+            redaction: RedactionPolicy::default(),
+            max_concurrent_requests: 0,
+            queue_timeout_ms: None,
+            adaptive_concurrency: None,
+            constraint_format: ConstraintFormat::default(),
+            ir_format: IrFormat::default(),
+            tokenizer_path: None,
+            coalesce_requests: default_coalesce_requests(),
+            language_grammars: default_language_grammars(),
+            output_guard: OutputGuard::default(),
+            post_process: PostProcessConfig::default(),
+            model_chain: ModelChainConfig::default(),
+            stream_deadline: None,
+            runaway_limit: None,
+            format_policy: FormatPolicy::default(),
+            include_constraint_events: false,
+            empty_constraints: EmptyConstraintsPolicy::default(),
+        }
+    }
+}
+
+/// Sampling parameters for [`MazeOrchestrator::generate_from_intent`]
+///
+/// Unset fields fall back to the orchestrator's [`MazeConfig`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenerationParams {
+    /// Maximum tokens to generate
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+
+    /// Sampling temperature
+    #[serde(default)]
+    pub temperature: Option<f32>,
+
+    /// Sampler seed
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+/// Settings layered over the orchestrator's [`MazeConfig`] for one call
+/// of [`MazeOrchestrator::generate_with`]
+///
+/// Unset fields leave the request and configuration as they are.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MazeConfigOverrides {
+    /// Time allowed for inference in milliseconds, replacing the request's
+    /// `timeout_ms` and the client's configured timeout
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+
+    /// Upper bound on the request's `max_tokens`
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+
+    /// Use the constraint cache, replacing `MazeConfig::enable_cache`
+    ///
+    /// `Some(false)` also skips the response cache and request coalescing,
+    /// so the call compiles and generates afresh; the caches themselves are
+    /// neither read nor updated.
+    #[serde(default)]
+    pub enable_cache: Option<bool>,
+
+    /// Sampling temperature, replacing the request's
+    #[serde(default)]
+    pub temperature: Option<f32>,
+}
+
+impl MazeConfigOverrides {
+    /// Apply the request-level overrides to `request`
+    fn apply(&self, request: &mut GenerationRequest) {
+        if let Some(timeout_ms) = self.timeout_ms {
+            request.timeout_ms = Some(timeout_ms);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            request.max_tokens = request.max_tokens.min(max_tokens);
+        }
+        if let Some(temperature) = self.temperature {
+            request.temperature = temperature;
         }
     }
 }
@@ -132,6 +656,23 @@ pub struct GenerationRequest {
 
     /// Optional context for the generation
     pub context: Option<GenerationContext>,
+
+    /// Sampler seed; with temperature 0 the same request yields the same output
+    #[serde(default)]
+    pub seed: Option<u64>,
+
+    /// Time allowed for inference in milliseconds, overriding the client's
+    /// configured timeout; see [`InferenceRequest::timeout_ms`](modal_client::InferenceRequest::timeout_ms)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+
+    /// Earlier turns of an interactive edit loop, oldest first
+    ///
+    /// Rendered into the prompt ahead of `prompt`, which then refines the
+    /// last response. Under `ContextOverflowPolicy::Truncate` the oldest
+    /// turns are dropped first when the request does not fit.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<ConversationTurn>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -155,11 +696,71 @@ pub struct CompiledConstraint {
     /// Hash of the constraint IR for caching
     pub hash: String,
 
-    /// Compiled llguidance schema
+    /// Compiled constraints; llguidance JSON unless `format` says otherwise
     pub llguidance_schema: serde_json::Value,
 
     /// Compilation timestamp
     pub compiled_at: i64,
+
+    /// Format `llguidance_schema` was compiled to
+    #[serde(default)]
+    pub format: ConstraintFormat,
+
+    /// How overlapping constraints were combined or overridden
+    #[serde(default)]
+    pub merge_report: ConstraintMergeReport,
+
+    /// Lint findings, populated when `MazeConfig::lint_on_compile` is set
+    #[serde(default)]
+    pub lints: Vec<ConstraintLint>,
+
+    /// [`ffi::CONSTRAINT_SCHEMA_VERSION`] of the compiler that produced this;
+    /// 0 for schemas compiled before versioning
+    #[serde(default)]
+    pub schema_version: u32,
+}
+
+impl CompiledConstraint {
+    /// Stand-in for an empty constraint set: no schema, hashed as
+    /// [`UNCONSTRAINED_HASH`]
+    fn unconstrained() -> Self {
+        Self {
+            hash: UNCONSTRAINED_HASH.to_string(),
+            llguidance_schema: serde_json::json!({}),
+            compiled_at: chrono::Utc::now().timestamp(),
+            format: ConstraintFormat::default(),
+            merge_report: ConstraintMergeReport::default(),
+            lints: Vec::new(),
+            schema_version: ffi::CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    /// Refuse a schema compiled by a different version of Maze
+    ///
+    /// Compiled schemas are not migrated; recompile them from their IR.
+    /// Check this before using a compiled constraint loaded from disk.
+    pub fn check_schema_version(&self) -> std::result::Result<(), SchemaVersionError> {
+        if self.schema_version == ffi::CONSTRAINT_SCHEMA_VERSION {
+            Ok(())
+        } else {
+            Err(SchemaVersionError::Stale {
+                found: self.schema_version,
+                expected: ffi::CONSTRAINT_SCHEMA_VERSION,
+            })
+        }
+    }
+
+    /// Write the compiled llguidance schema to a file as pretty-printed JSON
+    ///
+    /// Useful for sharing reproductions or inspecting exactly what was sent
+    /// to the inference service.
+    pub fn to_llguidance_file(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref();
+        let json = serde_json::to_string_pretty(&self.llguidance_schema)
+            .context("Failed to serialize llguidance schema")?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write llguidance schema to {}", path.display()))
+    }
 }
 
 /// Response from generation
@@ -176,6 +777,27 @@ pub struct GenerationResponse {
 
     /// Generation metadata
     pub metadata: GenerationMetadata,
+
+    /// Output as the model returned it, when post-processing changed it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_code: Option<String>,
+
+    /// [`RESPONSE_SCHEMA_VERSION`] of the Maze that produced this; 1 for
+    /// records stored before versioning
+    #[serde(default = "migrate::unversioned_record")]
+    pub schema_version: u32,
+}
+
+impl GenerationResponse {
+    /// Read a stored response of any supported schema version, upgraded to
+    /// the current one
+    ///
+    /// Fails on records written by a newer Maze; see
+    /// [`migrate_response`].
+    pub fn from_json(json: &str) -> Result<Self> {
+        let response = serde_json::from_str(json).context("Failed to parse generation response")?;
+        Ok(migrate_response(response)?)
+    }
 }
 
 /// Provenance tracking for generated code
@@ -193,8 +815,93 @@ pub struct Provenance {
     /// Original intent
     pub original_intent: String,
 
+    /// Structured intent, when generated through
+    /// [`MazeOrchestrator::generate_from_intent`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub intent: Option<Intent>,
+
     /// Generation parameters
     pub parameters: HashMap<String, serde_json::Value>,
+
+    /// Context assembled into the prompt, e.g. `language` or `metadata:ticket`
+    #[serde(default)]
+    pub context_included: Vec<String>,
+
+    /// Idempotency key sent with the inference request, shared by its retries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+
+    /// Hash of the constraint IR, as computed by
+    /// [`MazeOrchestrator::generate_cache_key`], or [`UNCONSTRAINED_HASH`]
+    /// for a generation without constraints
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub constraints_hash: Option<String>,
+
+    /// Models tried, in order, when a fallback chain is configured
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub model_chain: Vec<ChainAttempt>,
+
+    /// Position of this generation in a conversation: the number of prior
+    /// turns the request carried, before any were trimmed to fit the
+    /// context window. `None` for a request without history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub turn: Option<usize>,
+
+    /// Temperature the request asked for, when it was clamped to the
+    /// model's range; the temperature sent is in `parameters`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requested_temperature: Option<f32>,
+
+    /// Span of earlier output this generation replaced, when produced by
+    /// [`MazeOrchestrator::regenerate_span`]; the rest of the code is from
+    /// that earlier generation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regenerated_span: Option<RegeneratedSpan>,
+
+    /// Signature over the code and the rest of this provenance, when the
+    /// orchestrator has a signing key (see the `signing` feature)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ProvenanceSignature>,
+
+    /// [`RESPONSE_SCHEMA_VERSION`] of the Maze that recorded this; 1 for
+    /// records stored before versioning
+    #[serde(default = "migrate::unversioned_record")]
+    pub schema_version: u32,
+}
+
+impl Provenance {
+    /// Read a stored provenance record of any supported schema version,
+    /// upgraded to the current one
+    pub fn from_json(json: &str) -> Result<Self> {
+        let provenance = serde_json::from_str(json).context("Failed to parse provenance")?;
+        Ok(migrate_provenance(provenance)?)
+    }
+
+    /// Parameters sent in `request` that `parameters` omits or records with
+    /// a different value, sorted
+    pub fn unrecorded_parameters(&self, request: &InferenceRequest) -> Result<Vec<String>> {
+        let mut unrecorded: Vec<String> = request
+            .parameters()?
+            .into_iter()
+            .filter(|(name, value)| self.parameters.get(name) != Some(value))
+            .map(|(name, _)| name)
+            .collect();
+        unrecorded.sort();
+        Ok(unrecorded)
+    }
+}
+
+/// Detached signature attesting a response's code and provenance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvenanceSignature {
+    /// Signature scheme, e.g. `ed25519-sha256`
+    pub algorithm: String,
+
+    /// Hex-encoded public key of the signer
+    pub public_key: String,
+
+    /// Hex-encoded signature over the canonical digest
+    pub signature: String,
 }
 
 /// Validation results for generated code
@@ -227,106 +934,596 @@ pub struct GenerationMetadata {
 
     /// Constraint compilation time in milliseconds
     pub constraint_compile_time_ms: u64,
+
+    /// Whether the response was served from the response cache
+    #[serde(default)]
+    pub cache_hit: bool,
+
+    /// Whether the response was shared from an identical request already
+    /// in flight (see [`MazeConfig::coalesce_requests`])
+    #[serde(default)]
+    pub coalesced: bool,
+
+    /// Per-attempt timing of the inference call, including retries
+    #[serde(default)]
+    pub attempts: Vec<AttemptTiming>,
+
+    /// Why generation ended; [`FinishReason::MaxTokens`] marks output that
+    /// hit the token limit, [`FinishReason::Truncated`] partial output from
+    /// a stream cut off by `MazeConfig::stream_deadline`, and
+    /// [`FinishReason::Runaway`] one cut off by `MazeConfig::runaway_limit`
+    #[serde(default)]
+    pub finish_reason: FinishReason,
+
+    /// ID sent to the inference service with the call that produced this
+    /// output (see [`modal_client::REQUEST_ID_HEADER`]), for matching it
+    /// against server logs; `None` for streamed generations and clients
+    /// that do not assign one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+
+    /// Token counts of retrieved context before and after compression,
+    /// when `MazeConfig::context_compression` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_compression: Option<ContextCompression>,
 }
 
 impl MazeOrchestrator {
     /// Create a new Maze orchestrator
     pub fn new(modal_config: ModalConfig) -> Result<Self> {
-        let modal_client = ModalClient::new(modal_config)?;
-        let default_config = MazeConfig::default();
-
-        let cache_size = NonZeroUsize::new(default_config.cache_size_limit)
-            .expect("Cache size must be non-zero");
+        Self::with_config(modal_config, MazeConfig::default())
+    }
 
-        Ok(Self {
-            modal_client,
-            constraint_cache: Arc::new(Mutex::new(LruCache::new(cache_size))),
-            config: default_config,
-        })
+    /// Create from environment variables
+    ///
+    /// Combines [`ModalConfig::from_env`] and [`MazeConfig::from_env`].
+    pub fn from_env() -> Result<Self> {
+        Self::with_config(ModalConfig::from_env()?, MazeConfig::from_env()?)
     }
 
     /// Create with custom configuration
+    ///
+    /// Loads `maze_config.tokenizer_path`, if set, as the token estimator.
     pub fn with_config(modal_config: ModalConfig, maze_config: MazeConfig) -> Result<Self> {
-        let modal_client = ModalClient::new(modal_config)?;
+        let modal_config = modal_config.with_redaction(maze_config.redaction);
+        let fallback_configs = maze_config.model_chain.fallback_configs(&modal_config);
+        let tokenizer = maze_config
+            .tokenizer_path
+            .as_deref()
+            .map(context_window::load_tokenizer)
+            .transpose()?;
+        let mut orchestrator = Self::with_client(ModalClient::new(modal_config)?, maze_config);
+        for config in fallback_configs {
+            orchestrator = orchestrator.with_fallback_client(ModalClient::new(config)?);
+        }
+        if let Some(tokenizer) = tokenizer {
+            orchestrator.token_estimator = tokenizer;
+        }
+        Ok(orchestrator)
+    }
 
-        let cache_size =
-            NonZeroUsize::new(maze_config.cache_size_limit).expect("Cache size must be non-zero");
+    /// Create with any inference client, e.g. a [`MockInferenceClient`] for tests
+    pub fn with_client(client: impl InferenceClient + 'static, maze_config: MazeConfig) -> Self {
+        let constraint_cache = InMemoryCache::new(maze_config.cache_size_limit);
+        let response_cache = lru_with_capacity(maze_config.response_cache.size_limit);
+        let queue_timeout = maze_config
+            .queue_timeout_ms
+            .map(std::time::Duration::from_millis);
+        let limiter = match maze_config.adaptive_concurrency {
+            Some(ref adaptive) => ConcurrencyLimiter::adaptive(
+                maze_config.max_concurrent_requests,
+                adaptive.clone(),
+                queue_timeout,
+            ),
+            None => ConcurrencyLimiter::new(maze_config.max_concurrent_requests, queue_timeout),
+        };
 
-        Ok(Self {
-            modal_client,
-            constraint_cache: Arc::new(Mutex::new(LruCache::new(cache_size))),
+        Self {
+            client: Arc::new(client),
+            constraint_cache: Arc::new(constraint_cache),
             config: maze_config,
-        })
+            token_estimator: Arc::new(CharRatioEstimator::default()),
+            prompt_builder: PromptBuilder::default(),
+            context_provider: Arc::new(NoopContextProvider),
+            summarizer: None,
+            clock: Arc::new(SystemClock),
+            syntax_validators: SyntaxValidators::default(),
+            formatters: Formatters::default(),
+            language_grammars: LanguageGrammars::bundled(),
+            response_cache: Arc::new(Mutex::new(response_cache)),
+            limiter,
+            in_flight: SingleFlight::default(),
+            fallback_clients: Vec::new(),
+            registered: std::sync::Mutex::new(HashMap::new()),
+            next_handle: std::sync::atomic::AtomicU64::new(1),
+            #[cfg(feature = "signing")]
+            signing_key: None,
+            trace_sink: None,
+        }
+    }
+
+    /// Keep compiled constraints in `cache` instead of a cache of this
+    /// orchestrator's own
+    ///
+    /// Give every orchestrator in a multi-worker server the same backend so
+    /// each constraint set is compiled once across them.
+    /// `MazeConfig::cache_size_limit` is then not used; the backend's own
+    /// capacity applies.
+    pub fn with_constraint_cache(mut self, cache: Arc<dyn CacheBackend>) -> Self {
+        self.constraint_cache = cache;
+        self
+    }
+
+    /// Use a custom token estimator for context-window checks, and for
+    /// `tokens_generated` when the service does not report it
+    ///
+    /// The default estimator assumes ~4 characters per token; supply one
+    /// backed by the model's tokenizer, such as [`HfTokenizer`] with the
+    /// `tokenizers` feature, for accurate budgeting.
+    pub fn with_token_estimator(mut self, estimator: impl TokenEstimator + 'static) -> Self {
+        self.token_estimator = Arc::new(estimator);
+        self
+    }
+
+    /// Use a custom prompt builder to assemble context into the prompt
+    pub fn with_prompt_builder(mut self, builder: PromptBuilder) -> Self {
+        self.prompt_builder = builder;
+        self
+    }
+
+    /// Use a context provider to retrieve project snippets for prompts
+    pub fn with_context_provider(mut self, provider: impl ContextProvider + 'static) -> Self {
+        self.context_provider = Arc::new(provider);
+        self
+    }
+
+    /// Summarize context snippets with `client`, typically a cheaper model,
+    /// under [`CompressionStrategy::Summarize`]
+    ///
+    /// Without one, the primary client writes the summaries.
+    pub fn with_summarizer(mut self, client: impl InferenceClient + 'static) -> Self {
+        self.summarizer = Some(Arc::new(client));
+        self
+    }
+
+    /// Append a model to try when the ones before it fail, answer below
+    /// `MazeConfig::model_chain.min_confidence`, or produce invalid code
+    pub fn with_fallback_client(mut self, client: impl InferenceClient + 'static) -> Self {
+        self.fallback_clients.push(Arc::new(client));
+        self
+    }
+
+    /// Check generated code for `language` with a syntax validator
+    ///
+    /// Errors are reported in `ValidationResult::violated`, with spans under
+    /// the `syntax_errors` metadata key. The language is taken from the
+    /// request's `GenerationContext`.
+    pub fn with_syntax_validator(
+        mut self,
+        language: &str,
+        validator: impl SyntaxValidator + 'static,
+    ) -> Self {
+        self.syntax_validators
+            .register(language, Arc::new(validator));
+        self
+    }
+
+    /// Format generated code for `language` with `formatter`
+    ///
+    /// Runs after post-processing and before validation when
+    /// `MazeConfig::format_policy` allows. What happened is recorded under
+    /// the `formatting` metadata key; a failing formatter leaves the code
+    /// unformatted rather than failing the generation.
+    pub fn with_formatter(mut self, language: &str, formatter: impl Formatter + 'static) -> Self {
+        self.formatters.register(language, Arc::new(formatter));
+        self
+    }
+
+    /// Constrain output for `language` to `constraint`'s grammar
+    ///
+    /// Replaces the bundled grammar for the language, if there is one. The
+    /// grammar is merged into requests whose `GenerationContext::language`
+    /// matches (case-insensitively) and that carry no grammar of their own,
+    /// unless `MazeConfig::language_grammars` is off.
+    pub fn with_language_grammar(mut self, language: &str, constraint: ConstraintIR) -> Self {
+        self.language_grammars.register(language, constraint);
+        self
+    }
+
+    /// Sign every response's code and provenance with `key`
+    ///
+    /// Verify with [`signing::verify`] and the matching public key.
+    #[cfg(feature = "signing")]
+    pub fn with_signing_key(mut self, key: signing::SigningKey) -> Self {
+        self.signing_key = Some(Arc::new(key));
+        self
+    }
+
+    /// Record each call to the primary or a fallback model, redacted per
+    /// `MazeConfig::redaction`, to `sink`
+    ///
+    /// Streamed generations are not traced.
+    pub fn with_trace_sink(mut self, sink: impl TraceSink + 'static) -> Self {
+        self.trace_sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Read the time and sleep through `clock` for the response cache TTL
+    /// and [`wait_until_ready`](Self::wait_until_ready)
+    ///
+    /// The inference client keeps its own clock; see
+    /// [`ModalClient::with_clock`].
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Sign `response` if a signing key is configured
+    ///
+    /// Called last, after anything that touches the code or provenance.
+    fn seal(&self, response: &mut GenerationResponse) {
+        #[cfg(feature = "signing")]
+        if let Some(ref key) = self.signing_key {
+            signing::sign(response, key);
+        }
+        #[cfg(not(feature = "signing"))]
+        let _ = response;
     }
 
     /// Generate code with constraints
     ///
     /// This is the main entry point for constrained code generation.
     /// It coordinates between constraint compilation and inference.
+    #[tracing::instrument(
+        name = "maze.generate",
+        skip_all,
+        fields(
+            constraint_count = request.constraints_ir.len(),
+            max_tokens = request.max_tokens,
+            temperature = request.temperature,
+        )
+    )]
     pub async fn generate(&self, request: GenerationRequest) -> Result<GenerationResponse> {
-        let _start_time = std::time::Instant::now();
+        self.generate_routed(request, None, self.config.enable_cache)
+            .await
+    }
 
-        // Compile constraints to llguidance format
-        let compile_start = std::time::Instant::now();
-        let compiled = self.compile_constraints(&request.constraints_ir).await?;
-        let constraint_compile_time_ms = compile_start.elapsed().as_millis() as u64;
+    /// Generate with `overrides` layered over the configuration for this
+    /// call only
+    ///
+    /// For the odd heavy request that needs a longer timeout or a cache
+    /// bypass, without a second orchestrator. The overridden timeout,
+    /// token ceiling, and temperature are applied to the request before
+    /// anything else, so they are part of its response cache key; see
+    /// [`MazeConfigOverrides`] for each field.
+    pub async fn generate_with(
+        &self,
+        mut request: GenerationRequest,
+        overrides: MazeConfigOverrides,
+    ) -> Result<GenerationResponse> {
+        overrides.apply(&mut request);
+        if overrides.enable_cache == Some(false) {
+            return self.generate_fresh(request, None, None, false).await;
+        }
+        self.generate_routed(
+            request,
+            None,
+            overrides.enable_cache.unwrap_or(self.config.enable_cache),
+        )
+        .await
+    }
 
-        // Build the generation request for Modal
-        let modal_request = modal_client::InferenceRequest {
-            prompt: request.prompt.clone(),
-            constraints: compiled.llguidance_schema.clone(),
-            max_tokens: request.max_tokens,
-            temperature: request.temperature,
-            context: request.context.clone(),
-        };
+    /// Generate with the set registered under `handle` as the request's
+    /// constraints
+    ///
+    /// `request.constraints_ir` must be empty. The set was migrated,
+    /// validated, and hashed when it was registered, so none of that is
+    /// repeated here; otherwise this behaves like [`generate`](Self::generate).
+    /// Fails with [`UnknownConstraintHandle`] if the handle is not
+    /// registered.
+    pub async fn generate_registered(
+        &self,
+        handle: ConstraintHandle,
+        request: GenerationRequest,
+    ) -> Result<GenerationResponse> {
+        anyhow::ensure!(
+            request.constraints_ir.is_empty(),
+            "request for registered constraints carries its own constraints_ir"
+        );
+        let registered = self.registered_constraints(handle)?;
+        self.generate_routed(request, Some(registered), self.config.enable_cache)
+            .await
+    }
+
+    /// Generate `request` through the response cache and request
+    /// coalescing, taking its constraints from `registered` if given
+    ///
+    /// `use_cache` says whether the constraint cache is used.
+    async fn generate_routed(
+        &self,
+        request: GenerationRequest,
+        registered: Option<Arc<RegisteredConstraints>>,
+        use_cache: bool,
+    ) -> Result<GenerationResponse> {
+        let response_cache_key = self.response_cache_key(&request, registered.as_deref())?;
+
+        if let Some(ref key) = response_cache_key {
+            let ttl = std::time::Duration::from_secs(self.config.response_cache.ttl_secs);
+            let mut guard = self.response_cache.lock().await;
+            if let Some(cache) = guard.as_mut() {
+                match cache.get(key) {
+                    Some((stored_at, response))
+                        if self.clock.now().saturating_duration_since(*stored_at) < ttl =>
+                    {
+                        tracing::debug!("Response cache hit: {}", key);
+                        let mut response = response.clone();
+                        response.metadata.cache_hit = true;
+                        return Ok(response);
+                    }
+                    Some(_) => {
+                        cache.pop(key);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        if !self.config.coalesce_requests {
+            return self
+                .generate_fresh(request, response_cache_key, registered, use_cache)
+                .await;
+        }
+        let flight_key = self.request_key(&request, registered.as_deref())?;
+        let (mut response, coalesced) = self
+            .in_flight
+            .run(flight_key, || {
+                self.generate_fresh(request, response_cache_key, registered, use_cache)
+            })
+            .await?;
+        if coalesced {
+            tracing::debug!("Shared response of an identical request in flight");
+            response.metadata.coalesced = true;
+        }
+        Ok(response)
+    }
+
+    /// Generate code for several requests concurrently
+    ///
+    /// Returns one result per request, in input order whatever order they
+    /// finish in. Requests succeed or fail independently: an error, or a
+    /// panic (reported as [`GenerationPanicked`]), fills only that
+    /// request's slot. In-flight requests are capped by
+    /// `MazeConfig::max_concurrent_requests` as usual.
+    pub async fn generate_many(
+        &self,
+        requests: Vec<GenerationRequest>,
+    ) -> Vec<Result<GenerationResponse>> {
+        use futures::FutureExt;
+
+        let tasks = requests.into_iter().enumerate().map(|(index, request)| {
+            // A panic unwinds out of this request's future only; the shared
+            // caches sit behind tokio mutexes, which do not poison
+            std::panic::AssertUnwindSafe(self.generate(request))
+                .catch_unwind()
+                .map(move |outcome| {
+                    outcome.unwrap_or_else(|payload| {
+                        let message = panic_message(payload.as_ref());
+                        tracing::error!(index, "Generation panicked: {}", message);
+                        Err(GenerationPanicked { index, message }.into())
+                    })
+                })
+        });
+        futures::future::join_all(tasks).await
+    }
 
-        // Call Modal inference service
+    /// Generate, sign, and cache a response
+    async fn generate_fresh(
+        &self,
+        request: GenerationRequest,
+        response_cache_key: Option<String>,
+        registered: Option<Arc<RegisteredConstraints>>,
+        use_cache: bool,
+    ) -> Result<GenerationResponse> {
+        let mut response = self
+            .generate_uncached(request, registered, use_cache)
+            .await?;
+        self.seal(&mut response);
+
+        if let Some(key) = response_cache_key {
+            if let Some(cache) = self.response_cache.lock().await.as_mut() {
+                cache.put(key, (self.clock.now(), response.clone()));
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// Generate without consulting the response cache
+    async fn generate_uncached(
+        &self,
+        request: GenerationRequest,
+        registered: Option<Arc<RegisteredConstraints>>,
+        use_cache: bool,
+    ) -> Result<GenerationResponse> {
+        let permit = self.limiter.acquire().await?;
+        let result = self.generate_admitted(request, registered, use_cache).await;
+        permit.record(&result);
+        result
+    }
+
+    /// [`generate_uncached`](Self::generate_uncached), once a concurrency
+    /// slot is held
+    async fn generate_admitted(
+        &self,
+        request: GenerationRequest,
+        registered: Option<Arc<RegisteredConstraints>>,
+        use_cache: bool,
+    ) -> Result<GenerationResponse> {
+        let PreparedRequest {
+            request,
+            inference_request,
+            context_included,
+            constraint_compile_time_ms,
+            compiled,
+            turn,
+            context_compression,
+            ..
+        } = self.prepare(request, registered, use_cache).await?;
+
+        // Call the inference service, retrying output the guard rejects and
+        // falling back along the model chain. Fences, stray closing brackets,
+        // and partial statements are stripped, and the code formatted, so
+        // validation sees the code that will be returned.
+        let language = request.context.as_ref().and_then(|c| c.language.as_deref());
         let gen_start = std::time::Instant::now();
-        let modal_response = self
-            .modal_client
-            .generate_constrained(modal_request)
-            .await
-            .context("Failed to generate with Modal inference service")?;
+        let chain: Vec<Arc<dyn InferenceClient>> = std::iter::once(self.client.clone())
+            .chain(self.fallback_clients.iter().cloned())
+            .collect();
+        let regenerating: Vec<PolicyConstraint> = policy::policies(&request.constraints_ir)
+            .into_iter()
+            .map(|(_, policy)| policy)
+            .filter(|policy| policy.regenerate_on_violation)
+            .collect();
+        let regenerating = &regenerating;
+        let constrained = !request.constraints_ir.is_empty();
+        let shapes = shapes_output(&request.constraints_ir);
+        let chain_len = chain.len();
+        let (
+            ChainAnswer {
+                response: modal_response,
+                sent,
+                rejections,
+                processed,
+                formatting,
+                unenforced,
+                temperature,
+                syntax_errors,
+            },
+            chain_attempts,
+        ) = model_chain::run_chain(
+            &chain,
+            self.config.model_chain.min_confidence,
+            |client, position| {
+                let mut inference_request = inference_request.clone();
+                if position > 0 {
+                    // A fallback's answer must not be deduplicated
+                    // against the primary's
+                    inference_request.idempotency_key = inference_request
+                        .idempotency_key
+                        .map(|key| format!("{}-{}", key, client.model_name()));
+                }
+                let requested_temperature = request.temperature;
+                async move {
+                    // The primary's temperature was clamped when the
+                    // request was prepared; fallbacks have their own range
+                    if position > 0 {
+                        inference_request.temperature = client
+                            .model_info()
+                            .await?
+                            .clamp_temperature(requested_temperature);
+                    }
+                    let temperature = inference_request.temperature;
+                    // A model that cannot enforce the constraints passes
+                    // the request down the chain; the last one answers
+                    // unconstrained, and validation says so
+                    let last = position + 1 == chain_len;
+                    let mut unenforced = self
+                        .unsupported_constraints(client.as_ref(), constrained)
+                        .await?;
+                    if let Some(ref unsupported) = unenforced {
+                        if !last {
+                            return Err(unsupported.clone().into());
+                        }
+                        inference_request.constraints = serde_json::json!({});
+                    }
+                    let result = self
+                        .generate_guarded(client.as_ref(), inference_request.clone(), regenerating)
+                        .await;
+                    let (response, sent, rejections) = match result {
+                        Err(err) if last && unenforced.is_none() => {
+                            let Some(unsupported) = err.downcast_ref::<ConstraintsUnsupported>()
+                            else {
+                                return Err(err);
+                            };
+                            unenforced = Some(unsupported.clone());
+                            inference_request.constraints = serde_json::json!({});
+                            self.generate_guarded(client.as_ref(), inference_request, regenerating)
+                                .await?
+                        }
+                        result => result?,
+                    };
+                    let shaped = unenforced.is_none() && shapes;
+                    let (processed, formatting) = self
+                        .clean_output(language, &response.generated_text, shaped)
+                        .await;
+                    let syntax_errors = self.syntax_validators.validate(language, &processed.code);
+                    Ok(model_chain::Candidate {
+                        confidence: response.confidence(),
+                        invalid: (!syntax_errors.is_empty())
+                            .then(|| format!("{} syntax errors", syntax_errors.len())),
+                        value: ChainAnswer {
+                            response,
+                            sent,
+                            rejections,
+                            processed,
+                            formatting,
+                            unenforced,
+                            temperature,
+                            syntax_errors,
+                        },
+                    })
+                }
+            },
+        )
+        .await?;
         let generation_time_ms = gen_start.elapsed().as_millis() as u64;
 
-        // Build provenance
-        let provenance = Provenance {
-            model: modal_response.model.clone(),
-            timestamp: chrono::Utc::now().timestamp(),
-            constraints_applied: request
-                .constraints_ir
-                .iter()
-                .map(|c| c.name.clone())
-                .collect(),
-            original_intent: request.prompt.clone(),
-            parameters: {
-                let mut params = HashMap::new();
-                params.insert(
-                    "max_tokens".to_string(),
-                    serde_json::json!(request.max_tokens),
-                );
-                params.insert(
-                    "temperature".to_string(),
-                    serde_json::json!(request.temperature),
-                );
-                params
+        let mut provenance = self.provenance(
+            &request,
+            &modal_client::InferenceRequest {
+                temperature,
+                ..inference_request.clone()
             },
-        };
+            &sent,
+            modal_response.model.clone(),
+            context_included,
+            compiled.hash,
+        )?;
+        provenance.turn = turn;
+        if chain.len() > 1 {
+            provenance.model_chain = chain_attempts;
+        }
 
-        // Build validation result (llguidance ensures satisfaction)
-        let validation = ValidationResult {
-            all_satisfied: true,
-            satisfied: request
-                .constraints_ir
-                .iter()
-                .map(|c| c.name.clone())
-                .collect(),
-            violated: vec![],
-            metadata: HashMap::new(),
-        };
+        // llguidance ensures constraint satisfaction, but not necessarily
+        // full-language syntax
+        let mut validation = self.validate_checked(&request, &processed.code, syntax_errors)?;
+        if let Some(ref unsupported) = unenforced {
+            mark_unenforced(&mut validation, unsupported);
+        }
+        if processed.changed() {
+            validation.metadata.insert(
+                "post_processing".to_string(),
+                serde_json::to_value(&processed.steps)?,
+            );
+        }
+        if let Some(ref formatting) = formatting {
+            validation
+                .metadata
+                .insert("formatting".to_string(), serde_json::to_value(formatting)?);
+        }
+        if !rejections.is_empty() {
+            validation.metadata.insert(
+                "rejected_outputs".to_string(),
+                serde_json::json!(rejections),
+            );
+        }
 
         // Calculate metadata
-        let tokens_generated = modal_response.tokens_generated;
+        let tokens_generated = context_window::billed_tokens(
+            modal_response.tokens_generated,
+            &modal_response.generated_text,
+            self.token_estimator.as_ref(),
+        );
         let avg_token_time_us = if tokens_generated > 0 {
             (generation_time_ms * 1000) / tokens_generated as u64
         } else {
@@ -338,165 +1535,1816 @@ impl MazeOrchestrator {
             generation_time_ms,
             avg_token_time_us,
             constraint_compile_time_ms,
+            cache_hit: false,
+            coalesced: false,
+            finish_reason: modal_response.resolved_finish_reason(inference_request.max_tokens),
+            attempts: modal_response.attempts,
+            request_id: modal_response.request_id,
+            context_compression,
         };
 
+        tracing::info!(
+            model = %modal_response.model,
+            tokens_generated,
+            generation_time_ms,
+            constraint_compile_time_ms,
+            "Generation complete"
+        );
+
+        let raw_code = processed.changed().then_some(modal_response.generated_text);
         Ok(GenerationResponse {
-            code: modal_response.generated_text,
+            code: processed.code,
+            provenance,
+            validation,
+            metadata,
+            raw_code,
+            schema_version: RESPONSE_SCHEMA_VERSION,
+        })
+    }
+
+    /// Provenance of a generation of `request`, prepared as
+    /// `inference_request` and answered by `model` for `sent`
+    ///
+    /// Parameters are taken from `inference_request`, as adjusted for the
+    /// model that answered, and checked against `sent`, the attempt the
+    /// model actually received: an adjustment made while sending that is
+    /// not carried over to `inference_request` goes unrecorded. Debug
+    /// builds assert that nothing does; `MazeConfig::strict_provenance`
+    /// fails the generation with [`UnrecordedParameters`].
+    fn provenance(
+        &self,
+        request: &GenerationRequest,
+        inference_request: &InferenceRequest,
+        sent: &InferenceRequest,
+        model: String,
+        context_included: Vec<String>,
+        constraints_hash: String,
+    ) -> Result<Provenance> {
+        let provenance = Provenance {
+            model,
+            timestamp: chrono::Utc::now().timestamp(),
+            constraints_applied: merge::distinct_names(&request.constraints_ir),
+            original_intent: self.config.redaction.apply(&request.prompt).into_owned(),
+            intent: None,
+            parameters: inference_request.parameters()?,
+            context_included,
+            idempotency_key: sent.idempotency_key.clone(),
+            constraints_hash: Some(constraints_hash),
+            signature: None,
+            model_chain: Vec::new(),
+            turn: None,
+            requested_temperature: (request.temperature != inference_request.temperature)
+                .then_some(request.temperature),
+            regenerated_span: None,
+            schema_version: RESPONSE_SCHEMA_VERSION,
+        };
+
+        let unrecorded = provenance.unrecorded_parameters(sent)?;
+        if self.config.strict_provenance && !unrecorded.is_empty() {
+            return Err(UnrecordedParameters {
+                parameters: unrecorded,
+            }
+            .into());
+        }
+        debug_assert!(
+            unrecorded.is_empty(),
+            "provenance does not record parameters {:?}",
+            unrecorded
+        );
+        Ok(provenance)
+    }
+
+    /// Run `inference_request`, retrying output rejected by
+    /// `MazeConfig::output_guard`, exceeding `MazeConfig::runaway_limit`, or
+    /// violating one of `policies`
+    ///
+    /// Returns the accepted response, the request it answered, and why
+    /// earlier outputs were rejected. Each retry gets its own
+    /// idempotency key so the service does not replay the rejected output.
+    /// Once the guard's retries run out, output the guard or the runaway
+    /// limit rejects is an error while output violating a policy is
+    /// returned, for validation to flag.
+    async fn generate_guarded(
+        &self,
+        client: &dyn InferenceClient,
+        mut inference_request: InferenceRequest,
+        policies: &[PolicyConstraint],
+    ) -> Result<(InferenceResponse, InferenceRequest, Vec<String>)> {
+        let guard = &self.config.output_guard;
+        let base_key = inference_request.idempotency_key.clone();
+        let mut rejections = Vec::new();
+        loop {
+            let response = self
+                .traced_generate(client, inference_request.clone())
+                .await
+                .context("Failed to generate with Modal inference service")?;
+            let exhausted = rejections.len() >= guard.max_retries;
+            let checked = match self.config.runaway_limit {
+                Some(limit) => limit.check(&response.generated_text).map_err(Into::into),
+                None => Ok(()),
+            }
+            .and_then(|()| {
+                guard
+                    .check(&response.generated_text)
+                    .map_err(anyhow::Error::from)
+            });
+            let rejected = match checked {
+                Ok(()) => {
+                    let violation = policies.iter().find_map(|policy| {
+                        policy.scan(&response.generated_text).into_iter().next()
+                    });
+                    match violation {
+                        Some(violation) if !exhausted => violation.to_string(),
+                        _ => return Ok((response, inference_request, rejections)),
+                    }
+                }
+                Err(rejected) if exhausted => return Err(rejected),
+                Err(rejected) => rejected.to_string(),
+            };
+
+            tracing::warn!("Rejected generated output, retrying: {}", rejected);
+            rejections.push(rejected);
+            inference_request.idempotency_key = base_key
+                .as_ref()
+                .map(|key| format!("{}-retry{}", key, rejections.len()));
+        }
+    }
+
+    /// Send `request` to `client`, recording the call if tracing is on
+    async fn traced_generate(
+        &self,
+        client: &dyn InferenceClient,
+        request: InferenceRequest,
+    ) -> Result<InferenceResponse> {
+        let Some(ref sink) = self.trace_sink else {
+            return client.generate_constrained(request).await;
+        };
+        let started = std::time::Instant::now();
+        let result = client.generate_constrained(request.clone()).await;
+        sink.record(&trace::TraceRecord::new(
+            client.model_name(),
+            &request,
+            &result,
+            started,
+            self.config.redaction,
+        ));
+        result
+    }
+
+    /// Post-process `generated`, then format it under
+    /// `MazeConfig::format_policy`; output `shaped` by an enforced grammar or
+    /// regex only has its code fences stripped
+    async fn clean_output(
+        &self,
+        language: Option<&str>,
+        generated: &str,
+        shaped: bool,
+    ) -> (PostProcessed, Option<FormatOutcome>) {
+        let mut processed = if shaped {
+            self.config
+                .post_process
+                .fences_only()
+                .apply(language, generated)
+        } else {
+            self.config.post_process.apply(language, generated)
+        };
+        let formatting = self
+            .formatters
+            .apply(
+                self.config.format_policy,
+                language,
+                &mut processed.code,
+                &self.syntax_validators,
+            )
+            .await;
+        if formatting == Some(FormatOutcome::Formatted { changed: true }) {
+            processed.steps.push(PostProcessStep::Formatted);
+        }
+        (processed, formatting)
+    }
+
+    /// Validation result for generated `code`, checking syntax for `language`
+    fn validate_output(
+        &self,
+        request: &GenerationRequest,
+        language: Option<&str>,
+        code: &str,
+    ) -> Result<ValidationResult> {
+        let syntax_errors = self.syntax_validators.validate(language, code);
+        self.validate_checked(request, code, syntax_errors)
+    }
+
+    /// [`validate_output`](Self::validate_output) for code whose syntax was
+    /// already checked, with `syntax_errors` found
+    fn validate_checked(
+        &self,
+        request: &GenerationRequest,
+        code: &str,
+        syntax_errors: Vec<SyntaxError>,
+    ) -> Result<ValidationResult> {
+        let mut validation = ValidationResult {
+            all_satisfied: syntax_errors.is_empty(),
+            satisfied: merge::distinct_names(&request.constraints_ir),
+            violated: syntax_errors.iter().map(|e| e.to_string()).collect(),
+            metadata: HashMap::new(),
+        };
+        if !syntax_errors.is_empty() {
+            tracing::warn!("Generated code has {} syntax errors", syntax_errors.len());
+            validation.metadata.insert(
+                "syntax_errors".to_string(),
+                serde_json::to_value(&syntax_errors)?,
+            );
+        }
+
+        // Token masks cannot catch every spelling of a denied call
+        let mut violations = Vec::new();
+        let mut violated_labels = Vec::new();
+        for (index, policy) in policy::policies(&request.constraints_ir) {
+            let found = policy.scan(code);
+            if !found.is_empty() {
+                violated_labels.push(validation.satisfied[index].clone());
+                violations.extend(found);
+            }
+        }
+        if !violations.is_empty() {
+            tracing::warn!("Generated code has {} policy violations", violations.len());
+            validation.all_satisfied = false;
+            validation
+                .satisfied
+                .retain(|label| !violated_labels.contains(label));
+            validation
+                .violated
+                .extend(violations.iter().map(|v| v.to_string()));
+            validation.metadata.insert(
+                "policy_violations".to_string(),
+                serde_json::to_value(&violations)?,
+            );
+        }
+        Ok(validation)
+    }
+
+    /// Why `client` cannot enforce constraints, if the request is
+    /// `constrained` and the model's info says it does not support grammars
+    async fn unsupported_constraints(
+        &self,
+        client: &dyn InferenceClient,
+        constrained: bool,
+    ) -> Result<Option<ConstraintsUnsupported>> {
+        if !constrained || client.model_info().await?.supports_grammar {
+            return Ok(None);
+        }
+        Ok(Some(ConstraintsUnsupported {
+            model: client.model_name().to_string(),
+            reason: "model info reports no grammar support".to_string(),
+        }))
+    }
+
+    /// Generate a minimal edit to `original` instead of a whole new file
+    ///
+    /// The model is prompted and constrained to answer with SEARCH/REPLACE
+    /// blocks (see [`edit`]), which must apply cleanly to `original`; the
+    /// response carries both the edits and the edited code. The request's
+    /// `current_file` and `language` frame the prompt. Its `constraints_ir`
+    /// describe code rather than the patch, so they are not enforced token
+    /// by token here; syntax validators run on the edited code instead.
+    pub async fn generate_edit(
+        &self,
+        request: GenerationRequest,
+        original: &str,
+    ) -> Result<EditResponse> {
+        let language = request.context.as_ref().and_then(|c| c.language.clone());
+        let mut edit_request = GenerationRequest {
+            prompt: edit::edit_prompt(&request.prompt, language.as_deref(), original),
+            constraints_ir: vec![edit::edit_constraint()],
+            ..request.clone()
+        };
+        // The patch is not code in `language`; the edited result is checked below
+        if let Some(context) = edit_request.context.as_mut() {
+            context.language = None;
+        }
+
+        let mut generation = self.generate(edit_request).await?;
+        let blocks = edit::parse_patch(&generation.code)?;
+        let edits = edit::resolve_edits(original, &blocks)?;
+        let code = edit::apply_edits(original, &edits)?;
+
+        generation.validation = self.validate_output(&request, language.as_deref(), &code)?;
+        generation.provenance.original_intent =
+            self.config.redaction.apply(&request.prompt).into_owned();
+        self.seal(&mut generation);
+
+        Ok(EditResponse {
+            edits,
+            code,
+            generation,
+        })
+    }
+
+    /// Regenerate `span` (byte offsets) of `previous.code`, keeping the rest
+    /// verbatim
+    ///
+    /// The model sees the code before and after the span and answers with a
+    /// replacement for the span alone (see [`edit::span_prompt`]); the
+    /// request's prompt holds any extra instructions for it. The request's
+    /// `constraints_ir` apply to the replacement, and syntax validators run
+    /// on the spliced code. The response's code is the whole spliced code,
+    /// and [`Provenance::regenerated_span`] records which part is new.
+    pub async fn regenerate_span(
+        &self,
+        previous: &GenerationResponse,
+        span: std::ops::Range<usize>,
+        request: GenerationRequest,
+    ) -> Result<GenerationResponse> {
+        edit::check_span(&previous.code, &span)?;
+        let language = request.context.as_ref().and_then(|c| c.language.clone());
+        let mut span_request = GenerationRequest {
+            prompt: edit::span_prompt(&request.prompt, language.as_deref(), &previous.code, &span),
+            ..request.clone()
+        };
+        // The replacement is a fragment; the spliced code is checked below
+        if let Some(context) = span_request.context.as_mut() {
+            context.language = None;
+        }
+
+        let mut generation = self.generate(span_request).await?;
+        let prefix = &previous.code[..span.start];
+        let suffix = &previous.code[span.end..];
+        let end = span.start + generation.code.len();
+        generation.code = format!("{}{}{}", prefix, generation.code, suffix);
+        generation.raw_code = generation
+            .raw_code
+            .map(|raw| format!("{}{}{}", prefix, raw, suffix));
+
+        generation.validation =
+            self.validate_output(&request, language.as_deref(), &generation.code)?;
+        generation.provenance.original_intent =
+            self.config.redaction.apply(&request.prompt).into_owned();
+        generation.provenance.regenerated_span = Some(RegeneratedSpan {
+            start: span.start,
+            previous_end: span.end,
+            end,
+            previous_model: previous.provenance.model.clone(),
+        });
+        self.seal(&mut generation);
+        Ok(generation)
+    }
+
+    /// Generate code for a structured [`Intent`]
+    ///
+    /// The intent's parsed prompt (or its raw input, if the prompt is empty)
+    /// becomes the generation prompt, and its file and language become the
+    /// request context. The intent itself is recorded in
+    /// [`Provenance::intent`]; `original_intent` holds the raw input.
+    pub async fn generate_from_intent(
+        &self,
+        intent: Intent,
+        constraints_ir: Vec<ConstraintIR>,
+        params: GenerationParams,
+    ) -> Result<GenerationResponse> {
+        let request = self.intent_to_request(&intent, constraints_ir, params);
+        let mut response = self.generate(request).await?;
+
+        let redaction = self.config.redaction;
+        response.provenance.original_intent = redaction.apply(&intent.raw_input).into_owned();
+        response.provenance.intent = Some(Intent {
+            raw_input: response.provenance.original_intent.clone(),
+            prompt: redaction.apply(&intent.prompt).into_owned(),
+            ..intent
+        });
+        self.seal(&mut response);
+        Ok(response)
+    }
+
+    /// Re-run the generation recorded in `provenance`
+    ///
+    /// Rebuilds the request from the recorded intent (or `original_intent`)
+    /// and parameters, including the seed, and generates it with
+    /// `constraints_ir`, which should be the constraints the original run
+    /// used. A warning is logged if their hash differs from the recorded
+    /// one. Fails if the recorded prompt was redacted, since the original
+    /// text is gone; use [`replay_with_prompt`](Self::replay_with_prompt)
+    /// to supply it. Request context other than an intent's file and
+    /// language is not recorded, so it is not replayed.
+    pub async fn replay(
+        &self,
+        provenance: &Provenance,
+        constraints_ir: Vec<ConstraintIR>,
+    ) -> Result<GenerationResponse> {
+        self.replay_prompt(provenance, None, true, constraints_ir)
+            .await
+    }
+
+    /// Re-run the generation recorded in `provenance` with the original
+    /// `prompt`
+    ///
+    /// Like [`replay`](Self::replay), for provenance recorded under a
+    /// redacting [`RedactionPolicy`], the default in release builds.
+    /// `prompt` must match the recorded prompt: exactly, by hash under
+    /// [`RedactionPolicy::Hash`], or by prefix and length under
+    /// [`RedactionPolicy::Truncate`]. A fully redacted prompt cannot be
+    /// checked, so it is refused; see
+    /// [`replay_with_unverified_prompt`](Self::replay_with_unverified_prompt).
+    /// A redacted intent's raw input is replaced by `prompt`.
+    pub async fn replay_with_prompt(
+        &self,
+        provenance: &Provenance,
+        prompt: &str,
+        constraints_ir: Vec<ConstraintIR>,
+    ) -> Result<GenerationResponse> {
+        self.replay_prompt(provenance, Some(prompt), true, constraints_ir)
+            .await
+    }
+
+    /// Like [`replay_with_prompt`](Self::replay_with_prompt), but also
+    /// replays provenance whose prompt was recorded under
+    /// [`RedactionPolicy::Full`], trusting the caller that `prompt` is the
+    /// original
+    ///
+    /// A prompt that can be checked against the record still must match.
+    pub async fn replay_with_unverified_prompt(
+        &self,
+        provenance: &Provenance,
+        prompt: &str,
+        constraints_ir: Vec<ConstraintIR>,
+    ) -> Result<GenerationResponse> {
+        self.replay_prompt(provenance, Some(prompt), false, constraints_ir)
+            .await
+    }
+
+    /// [`replay`](Self::replay), with the original prompt if the caller
+    /// has it; `verify` refuses a prompt the record cannot be checked
+    /// against
+    async fn replay_prompt(
+        &self,
+        provenance: &Provenance,
+        original_prompt: Option<&str>,
+        verify: bool,
+        constraints_ir: Vec<ConstraintIR>,
+    ) -> Result<GenerationResponse> {
+        if provenance
+            .constraints_applied
+            .iter()
+            .any(|name| name == edit::EDIT_CONSTRAINT_NAME)
+        {
+            anyhow::bail!("edit generations cannot be replayed; call generate_edit again");
+        }
+
+        let recorded = match provenance.intent {
+            Some(ref intent) if !intent.prompt.trim().is_empty() => &intent.prompt,
+            _ => &provenance.original_intent,
+        };
+        let prompt = match original_prompt {
+            None if redaction::is_redacted(recorded)
+                || redaction::is_redacted(&provenance.original_intent) =>
+            {
+                anyhow::bail!(
+                    "provenance prompt was redacted when recorded; \
+                     pass the original to replay_with_prompt"
+                )
+            }
+            None => recorded.clone(),
+            Some(prompt) => {
+                match redaction::matches_record(recorded, prompt) {
+                    Some(true) => {}
+                    Some(false) => {
+                        anyhow::bail!("prompt does not match the one recorded in provenance")
+                    }
+                    None if verify => anyhow::bail!(
+                        "provenance prompt was fully redacted and cannot be checked; \
+                         use replay_with_unverified_prompt"
+                    ),
+                    None => tracing::warn!("Replaying with a prompt that cannot be verified"),
+                }
+                prompt.to_string()
+            }
+        };
+
+        let params = GenerationParams {
+            max_tokens: provenance
+                .parameters
+                .get("max_tokens")
+                .and_then(|v| v.as_u64())
+                .map(|v| v as usize),
+            temperature: provenance
+                .parameters
+                .get("temperature")
+                .and_then(|v| v.as_f64())
+                .map(|v| v as f32),
+            seed: provenance.parameters.get("seed").and_then(|v| v.as_u64()),
+        };
+        if params.seed.is_none() {
+            tracing::warn!("Replaying a generation without a recorded seed; output may differ");
+        }
+
+        let constraints_hash = if constraints_ir.is_empty() {
+            UNCONSTRAINED_HASH.to_string()
+        } else {
+            self.generate_cache_key(&constraints_ir)?
+        };
+        match provenance.constraints_hash {
+            Some(ref recorded) if *recorded != constraints_hash => tracing::warn!(
+                recorded = %recorded,
+                current = %constraints_hash,
+                "Replay constraints differ from the recorded run"
+            ),
+            None => {
+                let names = merge::distinct_names(&constraints_ir);
+                if names != provenance.constraints_applied {
+                    tracing::warn!(
+                        recorded = ?provenance.constraints_applied,
+                        current = ?names,
+                        "Replay constraints differ from the recorded run"
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        match provenance.intent {
+            Some(ref intent) => {
+                let mut intent = intent.clone();
+                if !intent.prompt.trim().is_empty() {
+                    intent.prompt = prompt.clone();
+                }
+                if redaction::is_redacted(&intent.raw_input) {
+                    intent.raw_input = prompt;
+                }
+                self.generate_from_intent(intent, constraints_ir, params)
+                    .await
+            }
+            None => {
+                let request = GenerationRequest {
+                    prompt,
+                    constraints_ir,
+                    max_tokens: params.max_tokens.unwrap_or(self.config.max_tokens),
+                    temperature: params.temperature.unwrap_or(self.config.temperature),
+                    context: None,
+                    seed: params.seed,
+                    timeout_ms: None,
+                    history: Vec::new(),
+                };
+                self.generate(request).await
+            }
+        }
+    }
+
+    /// Translate an intent into a generation request
+    fn intent_to_request(
+        &self,
+        intent: &Intent,
+        constraints_ir: Vec<ConstraintIR>,
+        params: GenerationParams,
+    ) -> GenerationRequest {
+        let prompt = if intent.prompt.trim().is_empty() {
+            intent.raw_input.clone()
+        } else {
+            intent.prompt.clone()
+        };
+
+        let context = (intent.current_file.is_some() || intent.language.is_some()).then(|| {
+            GenerationContext {
+                current_file: intent.current_file.clone(),
+                language: intent.language.clone(),
+                project_root: None,
+                metadata: HashMap::new(),
+            }
+        });
+
+        GenerationRequest {
+            prompt,
+            constraints_ir,
+            max_tokens: params.max_tokens.unwrap_or(self.config.max_tokens),
+            temperature: params.temperature.unwrap_or(self.config.temperature),
+            context,
+            seed: params.seed,
+            timeout_ms: intent.timeout_ms,
+            history: Vec::new(),
+        }
+    }
+
+    /// Stream generation output chunk by chunk
+    ///
+    /// Runs the same context-window, constraint compilation, and prompt
+    /// assembly steps as [`generate`](Self::generate), then streams the
+    /// inference output. Streamed generations bypass the response cache.
+    #[tracing::instrument(
+        name = "maze.generate_stream",
+        skip_all,
+        fields(
+            constraint_count = request.constraints_ir.len(),
+            max_tokens = request.max_tokens,
+        )
+    )]
+    pub async fn generate_stream(&self, request: GenerationRequest) -> Result<StreamingResult> {
+        let (_, stream) = self.start_stream(request).await?;
+        Ok(stream)
+    }
+
+    /// Stream a generation and assemble the output into a response
+    ///
+    /// When `MazeConfig::stream_deadline` cuts the stream off under
+    /// [`DeadlinePolicy::Partial`], the code generated so far is returned
+    /// with `metadata.finish_reason` set to [`FinishReason::Truncated`],
+    /// post-processed and validated like any other output. A stream cut off
+    /// by `MazeConfig::runaway_limit` is returned the same way, with
+    /// [`FinishReason::Runaway`], and fails validation. Malformed events
+    /// are skipped and counted in the `malformed_stream_events` validation
+    /// metadata; transport failures, stalls, and errors the service reports
+    /// still fail the call.
+    pub async fn generate_streamed(
+        &self,
+        request: GenerationRequest,
+    ) -> Result<GenerationResponse> {
+        use futures::StreamExt;
+
+        let start = std::time::Instant::now();
+        let (prepared, mut stream) = self.start_stream(request).await?;
+        let PreparedRequest {
+            request,
+            inference_request,
+            context_included,
+            constraint_compile_time_ms,
+            compiled,
+            turn,
+            unenforced,
+            context_compression,
+        } = prepared;
+
+        let mut generated = String::new();
+        let mut finish_reason = FinishReason::Stop;
+        let mut constraint_events: BTreeMap<String, usize> = BTreeMap::new();
+        let mut malformed_events = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) if sse::malformed_event(&err).is_some() => {
+                    tracing::debug!("Skipping malformed stream event: {:#}", err);
+                    malformed_events += 1;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if chunk.restarted {
+                generated.clear();
+                constraint_events.clear();
+            }
+            if let Some(event) = chunk.constraint_event {
+                tracing::debug!("Constraint event: {}", event);
+                *constraint_events.entry(event.constraint).or_default() += 1;
+            }
+            generated.push_str(&chunk.text);
+            if let Some(reason) = chunk.finish_reason {
+                finish_reason = reason;
+            }
+            if chunk.is_final {
+                break;
+            }
+        }
+        drop(stream);
+        let generation_time_ms = start.elapsed().as_millis() as u64;
+        // A chunk may carry any number of tokens, and streams report no
+        // count, so estimate it from the text like an unreported response
+        let tokens_generated =
+            context_window::billed_tokens(0, &generated, self.token_estimator.as_ref());
+        if finish_reason == FinishReason::Truncated {
+            tracing::info!(
+                tokens_generated,
+                "Returning partial output of a stream cut off by its deadline"
+            );
+        }
+
+        let language = request.context.as_ref().and_then(|c| c.language.as_deref());
+        let shaped = unenforced.is_none() && shapes_output(&request.constraints_ir);
+        let (processed, formatting) = self.clean_output(language, &generated, shaped).await;
+        let mut validation = self.validate_output(&request, language, &processed.code)?;
+        if let Some(ref unsupported) = unenforced {
+            mark_unenforced(&mut validation, unsupported);
+        }
+        if let Some(runaway) = self
+            .config
+            .runaway_limit
+            .and_then(|limit| limit.check(&generated).err())
+        {
+            validation.all_satisfied = false;
+            validation.violated.push(runaway.to_string());
+            validation.metadata.insert(
+                "runaway_generation".to_string(),
+                serde_json::to_value(&runaway)?,
+            );
+        }
+        if processed.changed() {
+            validation.metadata.insert(
+                "post_processing".to_string(),
+                serde_json::to_value(&processed.steps)?,
+            );
+        }
+        if let Some(ref formatting) = formatting {
+            validation
+                .metadata
+                .insert("formatting".to_string(), serde_json::to_value(formatting)?);
+        }
+        if malformed_events > 0 {
+            validation.metadata.insert(
+                "malformed_stream_events".to_string(),
+                serde_json::json!(malformed_events),
+            );
+        }
+        if inference_request.include_constraint_events {
+            validation.metadata.insert(
+                "constraint_events".to_string(),
+                serde_json::json!({
+                    "total": constraint_events.values().sum::<usize>(),
+                    "by_constraint": constraint_events,
+                }),
+            );
+        }
+
+        let mut provenance = self.provenance(
+            &request,
+            &inference_request,
+            &inference_request,
+            self.client.model_name().to_string(),
+            context_included,
+            compiled.hash,
+        )?;
+        provenance.turn = turn;
+        let metadata = GenerationMetadata {
+            tokens_generated,
+            generation_time_ms,
+            avg_token_time_us: (generation_time_ms * 1000)
+                .checked_div(tokens_generated as u64)
+                .unwrap_or(0),
+            constraint_compile_time_ms,
+            cache_hit: false,
+            coalesced: false,
+            attempts: Vec::new(),
+            finish_reason,
+            request_id: None,
+            context_compression,
+        };
+
+        let mut response = GenerationResponse {
+            raw_code: processed.changed().then_some(generated),
+            code: processed.code,
             provenance,
             validation,
             metadata,
+            schema_version: RESPONSE_SCHEMA_VERSION,
+        };
+        self.seal(&mut response);
+        Ok(response)
+    }
+
+    /// Prepare `request` and start streaming it, applying the stream
+    /// deadline and runaway limit
+    ///
+    /// The concurrency slot stays taken until the stream is dropped, and how
+    /// the stream ends is reported to an adaptive limit, as failing to start
+    /// it is. A model that cannot enforce the constraints streams without
+    /// them, recorded in the prepared request's `unenforced`.
+    async fn start_stream(
+        &self,
+        request: GenerationRequest,
+    ) -> Result<(PreparedRequest, StreamingResult)> {
+        let permit = self.limiter.acquire().await?;
+        let started = self.start_stream_admitted(request).await;
+        if started.is_err() {
+            permit.record(&started);
+        }
+        let (prepared, stream) = started?;
+        let stream: StreamingResult = Box::pin(concurrency::PermitStream::new(stream, permit));
+        Ok((prepared, stream))
+    }
+
+    /// [`start_stream`](Self::start_stream), once a concurrency slot is held
+    async fn start_stream_admitted(
+        &self,
+        request: GenerationRequest,
+    ) -> Result<(PreparedRequest, StreamingResult)> {
+        let mut prepared = self
+            .prepare(request, None, self.config.enable_cache)
+            .await?;
+        let constrained = !prepared.request.constraints_ir.is_empty();
+        prepared.unenforced = self
+            .unsupported_constraints(self.client.as_ref(), constrained)
+            .await?;
+        if prepared.unenforced.is_some() {
+            prepared.inference_request.constraints = serde_json::json!({});
+        }
+        prepared.inference_request.include_constraint_events =
+            self.config.include_constraint_events;
+        let stream = match self
+            .client
+            .generate_stream(prepared.inference_request.clone())
+            .await
+        {
+            Err(err) if prepared.unenforced.is_none() => {
+                let Some(unsupported) = err.downcast_ref::<ConstraintsUnsupported>() else {
+                    return Err(err).context("Failed to start streaming generation");
+                };
+                prepared.unenforced = Some(unsupported.clone());
+                prepared.inference_request.constraints = serde_json::json!({});
+                self.client
+                    .generate_stream(prepared.inference_request.clone())
+                    .await
+            }
+            result => result,
+        };
+        let mut stream = stream.context("Failed to start streaming generation")?;
+        if let Some(ref unsupported) = prepared.unenforced {
+            tracing::warn!("{}; streaming unconstrained output", unsupported);
+        }
+        if let Some(deadline) = self.config.stream_deadline {
+            stream = modal_client::with_deadline(stream, deadline);
+        }
+        if let Some(limit) = self.config.runaway_limit {
+            stream = runaway::with_output_limit(stream, limit);
+        }
+        Ok((prepared, stream))
+    }
+
+    /// Plan a generation without running inference
+    ///
+    /// Runs the same context-window check, constraint compilation, and prompt
+    /// assembly as [`generate`](Self::generate) and returns what would be
+    /// sent, so oversized requests and uncompilable constraints surface
+    /// before spending tokens. The context-window check may look up model
+    /// metadata; no generation request is made, though
+    /// [`CompressionStrategy::Summarize`] may ask the summarizer for snippet
+    /// summaries.
+    pub async fn plan(&self, request: GenerationRequest) -> Result<GenerationPlan> {
+        let prepared = self
+            .prepare(request, None, self.config.enable_cache)
+            .await?;
+        Ok(GenerationPlan {
+            model: self.client.model_name().to_string(),
+            inference_request: prepared.inference_request,
+            context_included: prepared.context_included,
+            compiled: prepared.compiled,
+            context_compression: prepared.context_compression,
+        })
+    }
+
+    /// Fit, compile, and assemble a request into what is sent for inference
+    ///
+    /// A `registered` set becomes the request's constraints and is compiled
+    /// under the cache key computed when it was registered. `use_cache`
+    /// says whether the constraint cache is used.
+    async fn prepare(
+        &self,
+        mut request: GenerationRequest,
+        registered: Option<Arc<RegisteredConstraints>>,
+        use_cache: bool,
+    ) -> Result<PreparedRequest> {
+        if let Some(ref registered) = registered {
+            request.constraints_ir = registered.constraints.clone();
+        }
+
+        // Constrain output to the request's language, if it has a grammar
+        let language = request.context.as_ref().and_then(|c| c.language.clone());
+        let added_grammar = match language {
+            Some(ref language) if self.config.language_grammars => self
+                .language_grammars
+                .merge_into(language, &mut request.constraints_ir),
+            _ => false,
+        };
+
+        // Mask the tokens that begin denied patterns, if the tokenizer can
+        // say which
+        let masked_policies =
+            policy::mask_denied_tokens(&mut request.constraints_ir, self.token_estimator.as_ref());
+
+        // Counted before trimming may drop the oldest turns
+        let turn = (!request.history.is_empty()).then_some(request.history.len());
+
+        // Make sure the request fits the model's context window
+        let info = self.client.model_info().await?;
+        let (request, remaining_tokens) = self.fit_context_window(request, &info)?;
+
+        // Compile constraints to llguidance format
+        let compile_start = std::time::Instant::now();
+        let compiled = match registered {
+            // An empty set would compile to a schema some backends read as
+            // "answer with an empty object"
+            _ if request.constraints_ir.is_empty() => match self.config.empty_constraints {
+                EmptyConstraintsPolicy::Unconstrained => CompiledConstraint::unconstrained(),
+                EmptyConstraintsPolicy::Reject => return Err(NoConstraints.into()),
+            },
+            // A merged grammar or policy mask changes the set, so its key no
+            // longer applies
+            Some(registered) if !added_grammar && !masked_policies => {
+                self.compile_keyed(
+                    &request.constraints_ir,
+                    registered.cache_key.clone(),
+                    use_cache,
+                )
+                .await?
+            }
+            _ => {
+                self.compile_constraints_with(&request.constraints_ir, use_cache)
+                    .await?
+            }
+        };
+        let constraint_compile_time_ms = compile_start.elapsed().as_millis() as u64;
+
+        // Retrieve project context, compressing it to the remaining budget
+        let (snippets, context_compression) = match request.context {
+            Some(ref context) => {
+                context_compression::compress_snippets(
+                    self.context_provider.fetch(context),
+                    remaining_tokens.map_or(self.config.snippet_token_budget, |r| {
+                        r.min(self.config.snippet_token_budget)
+                    }),
+                    self.config.context_compression,
+                    self.token_estimator.as_ref(),
+                    self.summarizer.as_ref().unwrap_or(&self.client).as_ref(),
+                )
+                .await
+            }
+            None => (Vec::new(), None),
+        };
+
+        // Assemble the prompt from the request prompt, context, and snippets
+        let assembled = self.prompt_builder.build(&PromptInput {
+            prompt: &request.prompt,
+            context: request.context.as_ref(),
+            snippets: &snippets,
+            history: &request.history,
+        });
+
+        // Build the generation request for Modal
+        let mut inference_request = modal_client::InferenceRequest {
+            prompt: assembled.text,
+            constraints: compiled.llguidance_schema.clone(),
+            max_tokens: request.max_tokens,
+            temperature: info.clamp_temperature(request.temperature),
+            context: request.context.clone(),
+            seed: request.seed,
+            idempotency_key: None,
+            include_logprobs: false,
+            timeout_ms: request.timeout_ms,
+            include_constraint_events: false,
+        };
+        inference_request.idempotency_key = Some(inference_request.call_key());
+
+        Ok(PreparedRequest {
+            request,
+            inference_request,
+            context_included: assembled.included,
+            constraint_compile_time_ms,
+            compiled,
+            turn,
+            unenforced: None,
+            context_compression,
         })
     }
 
+    /// Check a request against the model's context window before sending
+    ///
+    /// Skipped when the model does not advertise a context window. Oversized
+    /// requests are rejected with [`ContextOverflow`] or trimmed, depending on
+    /// `MazeConfig::context_overflow`. Also returns the tokens left in the
+    /// window, if known, for retrieved context.
+    fn fit_context_window(
+        &self,
+        request: GenerationRequest,
+        info: &ModelInfo,
+    ) -> Result<(GenerationRequest, Option<usize>)> {
+        let Some(context_window) = info.context_window else {
+            return Ok((request, None));
+        };
+
+        let request = context_window::fit_request(
+            request,
+            &info.name,
+            context_window,
+            self.config.context_overflow,
+            &self.config.truncation_order,
+            self.token_estimator.as_ref(),
+        )?;
+        let used = context_window::estimate_request_tokens(&request, self.token_estimator.as_ref());
+
+        Ok((request, Some(context_window.saturating_sub(used))))
+    }
+
     /// Compile constraints to llguidance format with caching
     /// Uses LRU cache for O(1) eviction instead of O(n) linear scan
     pub async fn compile_constraints(
         &self,
         constraints_ir: &[ConstraintIR],
     ) -> Result<CompiledConstraint> {
+        self.compile_constraints_with(constraints_ir, self.config.enable_cache)
+            .await
+    }
+
+    /// [`compile_constraints`](Self::compile_constraints), using the
+    /// constraint cache only if `use_cache`
+    #[tracing::instrument(
+        name = "maze.compile_constraints",
+        skip_all,
+        fields(
+            constraint_count = constraints_ir.len(),
+            cache_key = tracing::field::Empty,
+            cache_hit = tracing::field::Empty,
+        )
+    )]
+    async fn compile_constraints_with(
+        &self,
+        constraints_ir: &[ConstraintIR],
+        use_cache: bool,
+    ) -> Result<CompiledConstraint> {
+        // Bring IR from older versions up to date before hashing or compiling
+        let migrated;
+        let constraints_ir = if migrate::is_current(constraints_ir) {
+            constraints_ir
+        } else {
+            migrated = migrate_constraints(constraints_ir.to_vec())?;
+            &migrated[..]
+        };
+
         // Generate cache key from constraints
         let cache_key = self.generate_cache_key(constraints_ir)?;
+        self.compile_keyed(constraints_ir, cache_key, use_cache)
+            .await
+    }
+
+    /// Compile an edited constraint set, reusing `session`'s earlier work
+    ///
+    /// For callers that recompile on every edit, such as an editor between
+    /// keystrokes. A cache hit returns as [`compile_constraints`] does; on a
+    /// miss, only the constraints added or changed since `session` last
+    /// compiled are recompiled. Keep one session per edited set. Formats
+    /// other than [`ConstraintFormat::Llguidance`] compile in full.
+    ///
+    /// [`compile_constraints`]: Self::compile_constraints
+    pub async fn compile_incremental(
+        &self,
+        session: &mut IncrementalCompiler,
+        constraints_ir: &[ConstraintIR],
+    ) -> Result<CompiledConstraint> {
+        if self.config.constraint_format != ConstraintFormat::Llguidance {
+            return self.compile_constraints(constraints_ir).await;
+        }
+
+        let migrated;
+        let constraints_ir = if migrate::is_current(constraints_ir) {
+            constraints_ir
+        } else {
+            migrated = migrate_constraints(constraints_ir.to_vec())?;
+            &migrated[..]
+        };
+
+        let cache_key = self.generate_cache_key(constraints_ir)?;
+        self.compile_keyed_with(constraints_ir, cache_key, self.config.enable_cache, |ir| {
+            session.update(ir)
+        })
+        .await
+    }
+
+    /// Compile `template` instantiated with `params`
+    ///
+    /// The template keeps its compiled skeleton between calls, so a new
+    /// parameter set recompiles and re-merges only the constraints that
+    /// mention a placeholder (see [`compile_incremental`]); calls for the
+    /// same template wait for each other. Each instance is cached under its
+    /// own key, so generating with [`ConstraintTemplate::instantiate`]'s
+    /// output afterwards hits the cache.
+    ///
+    /// [`compile_incremental`]: Self::compile_incremental
+    pub async fn compile_template(
+        &self,
+        template: &ConstraintTemplate,
+        params: &HashMap<String, String>,
+    ) -> Result<CompiledConstraint> {
+        let constraints_ir = template.instantiate(params)?;
+        let mut skeleton = template.compiled.lock().await;
+        self.compile_incremental(&mut skeleton, &constraints_ir)
+            .await
+    }
+
+    /// Compile current-version constraints whose cache key is `cache_key`
+    async fn compile_keyed(
+        &self,
+        constraints_ir: &[ConstraintIR],
+        cache_key: String,
+        use_cache: bool,
+    ) -> Result<CompiledConstraint> {
+        self.compile_keyed_with(constraints_ir, cache_key, use_cache, |ir| {
+            self.compile_to_format(ir)
+        })
+        .await
+    }
+
+    /// [`compile_keyed`](Self::compile_keyed), compiling a cache miss with
+    /// `compile`
+    async fn compile_keyed_with(
+        &self,
+        constraints_ir: &[ConstraintIR],
+        cache_key: String,
+        use_cache: bool,
+        compile: impl FnOnce(&[ConstraintIR]) -> Result<(serde_json::Value, ConstraintMergeReport)>,
+    ) -> Result<CompiledConstraint> {
+        let span = tracing::Span::current();
+        span.record("cache_key", cache_key.as_str());
 
         // Check cache if enabled
-        if self.config.enable_cache {
-            let mut cache = self.constraint_cache.lock().await;
-            if let Some(cached) = cache.get(&cache_key) {
-                tracing::debug!("Cache hit for constraints: {}", cache_key);
-                return Ok(cached.clone());
+        if use_cache {
+            if let Some(cached) = self.constraint_cache.get(&cache_key).await {
+                match cached.check_schema_version() {
+                    Ok(()) => {
+                        span.record("cache_hit", true);
+                        tracing::debug!("Cache hit for constraints: {}", cache_key);
+                        return Ok(cached);
+                    }
+                    Err(err) => {
+                        tracing::warn!("Evicting cached constraint {}: {}", cache_key, err);
+                        self.constraint_cache.remove(&cache_key).await;
+                    }
+                }
             }
         }
+        span.record("cache_hit", false);
 
         // Compile constraints
-        let llguidance_schema = self.compile_to_llguidance(constraints_ir)?;
+        let (llguidance_schema, merge_report) = compile(constraints_ir)?;
+
+        let lints = if self.config.lint_on_compile {
+            let lints = lint::lint_constraints(constraints_ir);
+            for finding in &lints {
+                tracing::warn!(
+                    "Constraint lint ({:?}, {:?}) on '{}': {}",
+                    finding.severity,
+                    finding.kind,
+                    finding.constraint,
+                    finding.message
+                );
+            }
+            lints
+        } else {
+            Vec::new()
+        };
 
         let compiled = CompiledConstraint {
             hash: cache_key.clone(),
             llguidance_schema,
             compiled_at: chrono::Utc::now().timestamp(),
+            format: self.config.constraint_format,
+            merge_report,
+            lints,
+            schema_version: ffi::CONSTRAINT_SCHEMA_VERSION,
         };
 
-        // Store in cache if enabled
-        // LRU cache automatically handles eviction with O(1) complexity
-        if self.config.enable_cache {
-            let mut cache = self.constraint_cache.lock().await;
-            cache.put(cache_key, compiled.clone());
+        // Store in cache if enabled; the backend handles eviction
+        if use_cache {
+            self.constraint_cache.put(cache_key, compiled.clone()).await;
         }
 
         Ok(compiled)
     }
 
+    /// Compile and cache constraint profiles ahead of generation
+    ///
+    /// Intended for startup, when the profiles a project will use (security,
+    /// style, type constraints, ...) are known: each profile is compiled
+    /// concurrently through `compile_constraints`, so the first request using
+    /// it is a cache hit. Profiles are always linted, whatever
+    /// `MazeConfig::lint_on_compile` says, so invalid regexes and conflicting
+    /// constraints show up in the report instead of on a later request.
+    pub async fn precompile(&self, profiles: Vec<Vec<ConstraintIR>>) -> PrecompileReport {
+        let start = std::time::Instant::now();
+        let outcomes = futures::future::join_all(profiles.iter().enumerate().map(
+            |(index, profile)| async move {
+                match self.compile_constraints(profile).await {
+                    Ok(compiled) => {
+                        let lints = if self.config.lint_on_compile {
+                            compiled.lints
+                        } else {
+                            lint::lint_constraints(profile)
+                        };
+                        PrecompiledProfile {
+                            index,
+                            cache_key: Some(compiled.hash),
+                            error: None,
+                            merge_report: compiled.merge_report,
+                            lints,
+                        }
+                    }
+                    Err(err) => PrecompiledProfile {
+                        index,
+                        cache_key: None,
+                        error: Some(format!("{:#}", err)),
+                        merge_report: ConstraintMergeReport::default(),
+                        lints: lint::lint_constraints(profile),
+                    },
+                }
+            },
+        ))
+        .await;
+
+        let report = PrecompileReport {
+            profiles: outcomes,
+            elapsed_ms: start.elapsed().as_millis() as u64,
+        };
+        tracing::info!(
+            "Precompiled {} constraint profiles in {}ms ({} with problems)",
+            report.profiles.len(),
+            report.elapsed_ms,
+            report.problems().count()
+        );
+        report
+    }
+
+    /// Preview the merged llguidance schema for a set of constraints
+    ///
+    /// Goes through `compile_constraints`, so the returned schema is exactly
+    /// what `generate` would send (including cache hits).
+    pub async fn compile_preview(
+        &self,
+        constraints_ir: &[ConstraintIR],
+    ) -> Result<serde_json::Value> {
+        let compiled = self.compile_constraints(constraints_ir).await?;
+        Ok(compiled.llguidance_schema)
+    }
+
     /// Generate cache key from constraint IR
-    /// Uses xxHash3 for high-performance hashing (2-3x faster than DefaultHasher)
+    /// Uses xxHash3 for high-performance hashing (2-3x faster than DefaultHasher;
+    /// `cargo bench --bench fixture_workloads` compares the two)
     pub fn generate_cache_key(&self, constraints_ir: &[ConstraintIR]) -> Result<String> {
         use std::hash::Hasher;
         use xxhash_rust::xxh3::Xxh3;
 
-        let json = serde_json::to_string(constraints_ir)
-            .context("Failed to serialize constraints for caching")?;
-
         let mut hasher = Xxh3::new();
-        hasher.write(json.as_bytes());
+        hasher.write(self.config.constraint_format.as_str().as_bytes());
+        ir_codec::canonical_hash(constraints_ir, &mut hasher)
+            .context("Failed to serialize constraints for caching")?;
         Ok(format!("{:x}", hasher.finish()))
     }
 
-    /// Compile ConstraintIR to llguidance JSON schema
-    fn compile_to_llguidance(&self, constraints_ir: &[ConstraintIR]) -> Result<serde_json::Value> {
-        // Convert ConstraintIR to llguidance format
-        // llguidance supports JSON schema, CFG, and regex
+    /// Response cache key for a request, or `None` if it should not be cached
+    fn response_cache_key(
+        &self,
+        request: &GenerationRequest,
+        registered: Option<&RegisteredConstraints>,
+    ) -> Result<Option<String>> {
+        let cache_config = &self.config.response_cache;
+        if !cache_config.enabled {
+            return Ok(None);
+        }
+        let deterministic = request.seed.is_some() && request.temperature == 0.0;
+        if cache_config.deterministic_only && !deterministic {
+            return Ok(None);
+        }
+        self.request_key(request, registered).map(Some)
+    }
+
+    /// Key identifying identical requests, for caching and coalescing
+    ///
+    /// Hashes the normalized request (prompt, constraints, sampling
+    /// parameters, seed, context) together with the target model. A
+    /// registered set stands in for the constraints by its cache key.
+    fn request_key(
+        &self,
+        request: &GenerationRequest,
+        registered: Option<&RegisteredConstraints>,
+    ) -> Result<String> {
+        use std::hash::Hasher;
+        use xxhash_rust::xxh3::Xxh3;
 
-        let mut schema = serde_json::json!({
-            "type": "object",
-            "properties": {},
-            "constraints": []
+        let mut normalized = serde_json::json!({
+            "model": self.client.model_name(),
+            "request": serde_json::to_value(request)
+                .context("Failed to serialize request for caching")?,
         });
+        if let Some(registered) = registered {
+            normalized["constraints"] = registered.cache_key.clone().into();
+        }
 
-        for constraint in constraints_ir {
-            // Add JSON schema constraints
-            if let Some(ref json_schema) = constraint.json_schema {
-                if let Some(properties) = schema.get_mut("properties") {
-                    properties[&constraint.name] = serde_json::json!(json_schema);
-                }
-            }
+        // Canonical JSON, so metadata and IR key order are irrelevant
+        let mut hasher = Xxh3::new();
+        hasher.write(ir_codec::canonical_json(normalized).to_string().as_bytes());
+        Ok(format!("{:x}", hasher.finish()))
+    }
 
-            // Add grammar constraints
-            if let Some(ref grammar) = constraint.grammar {
-                if let Some(constraints) =
-                    schema.get_mut("constraints").and_then(|c| c.as_array_mut())
-                {
-                    constraints.push(serde_json::json!({
-                        "type": "grammar",
-                        "name": constraint.name,
-                        "rules": grammar.rules,
-                        "start": grammar.start_symbol
-                    }));
-                }
-            }
+    /// Clear the generation response cache
+    pub async fn clear_response_cache(&self) {
+        if let Some(cache) = self.response_cache.lock().await.as_mut() {
+            cache.clear();
+        }
+    }
 
-            // Add regex constraints
-            if !constraint.regex_patterns.is_empty() {
-                if let Some(constraints) =
-                    schema.get_mut("constraints").and_then(|c| c.as_array_mut())
-                {
-                    for pattern in &constraint.regex_patterns {
-                        constraints.push(serde_json::json!({
-                            "type": "regex",
-                            "pattern": pattern.pattern,
-                            "flags": pattern.flags
-                        }));
-                    }
-                }
-            }
+    /// Compile ConstraintIR in the configured [`ConstraintFormat`]
+    ///
+    /// See [`compile_llguidance_schema`] for the merge rules.
+    fn compile_to_format(
+        &self,
+        constraints_ir: &[ConstraintIR],
+    ) -> Result<(serde_json::Value, ConstraintMergeReport)> {
+        self.config
+            .constraint_format
+            .compiler()
+            .compile(constraints_ir)
+    }
 
-            // Add token mask constraints
-            if let Some(ref token_masks) = constraint.token_masks {
-                if let Some(constraints) =
-                    schema.get_mut("constraints").and_then(|c| c.as_array_mut())
-                {
-                    let mut mask_constraint = serde_json::json!({
-                        "type": "token_mask",
-                        "name": constraint.name
-                    });
+    /// Clear the constraint cache
+    pub async fn clear_cache(&self) -> Result<()> {
+        self.constraint_cache.clear().await;
+        Ok(())
+    }
 
-                    if let Some(allowed) = &token_masks.allowed_tokens {
-                        mask_constraint["allowed"] = serde_json::json!(allowed);
-                    }
+    /// Resize the constraint cache without rebuilding the orchestrator
+    ///
+    /// Shrinking evicts the least recently used entries; a capacity of zero
+    /// drops all entries and disables caching until it is raised again.
+    /// A shared backend is resized for every orchestrator using it.
+    pub async fn set_cache_capacity(&self, capacity: usize) {
+        self.constraint_cache.resize(capacity).await;
+        tracing::debug!("Constraint cache capacity set to {}", capacity);
+    }
 
-                    if let Some(forbidden) = &token_masks.forbidden_tokens {
-                        mask_constraint["forbidden"] = serde_json::json!(forbidden);
-                    }
+    /// Current in-flight and queued generation counts
+    pub fn load(&self) -> LoadStats {
+        self.limiter.stats()
+    }
 
-                    constraints.push(mask_constraint);
-                }
-            }
+    /// Register a constraint set for generation by handle
+    ///
+    /// The set is migrated, validated, and hashed once, here; pass the
+    /// handle to [`generate_registered`](Self::generate_registered) instead
+    /// of sending the IR with every request. It stays registered until
+    /// [`release_constraints`](Self::release_constraints).
+    pub fn register_constraints(
+        &self,
+        constraints_ir: Vec<ConstraintIR>,
+    ) -> Result<ConstraintHandle> {
+        let constraints = if migrate::is_current(&constraints_ir) {
+            constraints_ir
+        } else {
+            migrate_constraints(constraints_ir)?
+        };
+        for constraint in &constraints {
+            constraint.validate()?;
         }
+        let cache_key = self.generate_cache_key(&constraints)?;
+
+        let id = self
+            .next_handle
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.registered.lock().unwrap().insert(
+            id,
+            Arc::new(RegisteredConstraints {
+                constraints,
+                cache_key,
+            }),
+        );
+        Ok(ConstraintHandle(id))
+    }
 
-        Ok(schema)
+    /// Register a constraint set encoded in the configured
+    /// [`MazeConfig::ir_format`]
+    ///
+    /// As [`register_constraints`](Self::register_constraints), for IR
+    /// transferred as bytes, such as MessagePack from another process.
+    pub fn register_encoded(&self, bytes: &[u8]) -> Result<ConstraintHandle> {
+        let constraints = self.config.ir_format.decode(bytes)?;
+        self.register_constraints(constraints)
     }
 
-    /// Clear the constraint cache
-    pub async fn clear_cache(&self) -> Result<()> {
-        let mut cache = self.constraint_cache.lock().await;
-        cache.clear();
-        Ok(())
+    /// Release a set registered with
+    /// [`register_constraints`](Self::register_constraints)
+    ///
+    /// Generations already running with the set finish with it.
+    pub fn release_constraints(
+        &self,
+        handle: ConstraintHandle,
+    ) -> std::result::Result<(), UnknownConstraintHandle> {
+        match self.registered.lock().unwrap().remove(&handle.0) {
+            Some(_) => Ok(()),
+            None => Err(self.unknown_handle(handle)),
+        }
+    }
+
+    /// Number of registered constraint sets
+    pub fn registered_count(&self) -> usize {
+        self.registered.lock().unwrap().len()
+    }
+
+    fn registered_constraints(
+        &self,
+        handle: ConstraintHandle,
+    ) -> std::result::Result<Arc<RegisteredConstraints>, UnknownConstraintHandle> {
+        self.registered
+            .lock()
+            .unwrap()
+            .get(&handle.0)
+            .cloned()
+            .ok_or_else(|| self.unknown_handle(handle))
+    }
+
+    fn unknown_handle(&self, handle: ConstraintHandle) -> UnknownConstraintHandle {
+        let issued = self.next_handle.load(std::sync::atomic::Ordering::Relaxed);
+        UnknownConstraintHandle {
+            handle: handle.0,
+            released: handle.0 != 0 && handle.0 < issued,
+        }
+    }
+
+    /// Whether `constraints_ir` has a current compiled entry in the cache
+    ///
+    /// Does not compile, and does not count as a use for LRU eviction.
+    pub async fn is_cached(&self, constraints_ir: &[ConstraintIR]) -> Result<bool> {
+        let migrated;
+        let constraints_ir = if migrate::is_current(constraints_ir) {
+            constraints_ir
+        } else {
+            migrated = migrate_constraints(constraints_ir.to_vec())?;
+            &migrated[..]
+        };
+        let cache_key = self.generate_cache_key(constraints_ir)?;
+
+        Ok(self
+            .constraint_cache
+            .peek(&cache_key)
+            .await
+            .is_some_and(|cached| cached.check_schema_version().is_ok()))
     }
 
     /// Get cache statistics
     pub async fn cache_stats(&self) -> CacheStats {
-        let cache = self.constraint_cache.lock().await;
-        CacheStats {
-            size: cache.len(),
-            limit: cache.cap().get(),
-        }
+        self.constraint_cache.stats().await
     }
 
     /// Check if the Modal inference service is healthy
     ///
-    /// Returns true if the service is reachable and responding, false otherwise.
-    pub async fn health_check(&self) -> Result<bool> {
-        self.modal_client.health_check().await
+    /// Fails only if the service cannot be reached at all; otherwise the
+    /// [`HealthStatus`] says how the service answered.
+    pub async fn health_check(&self) -> Result<HealthStatus> {
+        self.client.health_check().await
+    }
+
+    /// Poll the inference service until it reports healthy or `timeout` elapses
+    ///
+    /// Health checks are retried with exponential backoff (100ms doubling,
+    /// capped at 5s). Never fails: the returned [`Readiness`] says whether the
+    /// service came up, so callers can decide whether to accept traffic.
+    pub async fn wait_until_ready(&self, timeout: std::time::Duration) -> Readiness {
+        let start = self.clock.now();
+        let elapsed = || self.clock.now().saturating_duration_since(start);
+        let mut backoff = std::time::Duration::from_millis(100);
+        let mut probes = 0;
+        let last_error = loop {
+            probes += 1;
+            let error = match self.client.health_check().await {
+                Ok(status) if status.is_ready() => {
+                    return Readiness {
+                        ready: true,
+                        probes,
+                        elapsed_ms: elapsed().as_millis() as u64,
+                        last_error: None,
+                    };
+                }
+                Ok(status) => format!("health check returned {}", status),
+                Err(e) => e.to_string(),
+            };
+
+            let remaining = timeout.saturating_sub(elapsed());
+            if remaining.is_zero() {
+                break error;
+            }
+
+            tracing::debug!(
+                "Inference service not ready after {} probe(s): {}",
+                probes,
+                error
+            );
+            self.clock.sleep(backoff.min(remaining)).await;
+            backoff = (backoff * 2).min(std::time::Duration::from_secs(5));
+        };
+
+        tracing::warn!(
+            "Inference service not ready after {} probe(s): {}",
+            probes,
+            last_error
+        );
+
+        Readiness {
+            ready: false,
+            probes,
+            elapsed_ms: elapsed().as_millis() as u64,
+            last_error: Some(last_error),
+        }
+    }
+
+    /// Send a small generation to force the model to load
+    ///
+    /// Intended to run once at startup after [`wait_until_ready`], so the
+    /// first real request does not pay the cold-start latency. Returns the
+    /// metadata of the warmup generation.
+    ///
+    /// [`wait_until_ready`]: MazeOrchestrator::wait_until_ready
+    pub async fn warmup(&self, sample_request: GenerationRequest) -> Result<GenerationMetadata> {
+        let response = self
+            .generate(sample_request)
+            .await
+            .context("Warmup generation failed")?;
+
+        tracing::info!(
+            "Warmup complete: {} tokens in {}ms",
+            response.metadata.tokens_generated,
+            response.metadata.generation_time_ms
+        );
+
+        Ok(response.metadata)
+    }
+}
+
+/// Dry-run output of [`MazeOrchestrator::plan`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GenerationPlan {
+    /// Model the request would be sent to
+    pub model: String,
+
+    /// The request that would be sent, after context fitting and prompt assembly
+    pub inference_request: modal_client::InferenceRequest,
+
+    /// Context assembled into the prompt
+    pub context_included: Vec<String>,
+
+    /// Compiled constraints, with merge report and lints
+    pub compiled: CompiledConstraint,
+
+    /// How retrieved context was compressed to fit, if it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_compression: Option<ContextCompression>,
+}
+
+/// Outcome of [`MazeOrchestrator::precompile`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrecompileReport {
+    /// One entry per profile, in the order given
+    pub profiles: Vec<PrecompiledProfile>,
+
+    /// Time spent compiling all profiles in milliseconds
+    pub elapsed_ms: u64,
+}
+
+impl PrecompileReport {
+    /// Profiles that compiled and are now cached
+    pub fn compiled(&self) -> impl Iterator<Item = &PrecompiledProfile> {
+        self.profiles.iter().filter(|p| p.error.is_none())
+    }
+
+    /// Profiles that failed to compile or have error-level lints
+    pub fn problems(&self) -> impl Iterator<Item = &PrecompiledProfile> {
+        self.profiles.iter().filter(|p| !p.is_ok())
+    }
+
+    /// Whether every profile compiled without error-level lints
+    pub fn is_ok(&self) -> bool {
+        self.problems().next().is_none()
     }
 }
 
+/// Compilation result for one precompiled profile
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrecompiledProfile {
+    /// Position of the profile in the list passed to `precompile`
+    pub index: usize,
+
+    /// Constraint cache key, when compilation succeeded
+    pub cache_key: Option<String>,
+
+    /// Why compilation failed
+    pub error: Option<String>,
+
+    /// Overlapping constraints that were combined or overridden
+    pub merge_report: ConstraintMergeReport,
+
+    /// Lint findings for the profile
+    pub lints: Vec<ConstraintLint>,
+}
+
+impl PrecompiledProfile {
+    /// Whether the profile compiled and has no error-level lints
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+            && !self
+                .lints
+                .iter()
+                .any(|lint| lint.severity == LintSeverity::Error)
+    }
+}
+
+/// A request in a [`MazeOrchestrator::generate_many`] batch panicked
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("generation of request {index} panicked: {message}")]
+pub struct GenerationPanicked {
+    /// Position of the request in the batch
+    pub index: usize,
+
+    /// The panic message, if it was a string
+    pub message: String,
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "non-string panic payload".to_string())
+}
+
+/// Handle to a constraint set registered with
+/// [`MazeOrchestrator::register_constraints`]
+///
+/// The id is what crosses the FFI; 0 is never issued.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConstraintHandle(pub u64);
+
+/// Parameters the model received that a generation's provenance omits
+///
+/// Returned when `MazeConfig::strict_provenance` is set; see
+/// [`Provenance::unrecorded_parameters`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("provenance does not record parameters sent to the model: {}", .parameters.join(", "))]
+pub struct UnrecordedParameters {
+    /// Names of the missing or misrecorded parameters, sorted
+    pub parameters: Vec<String>,
+}
+
+/// A request without constraints under [`EmptyConstraintsPolicy::Reject`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("request carries no constraints")]
+pub struct NoConstraints;
+
+/// A constraint handle that is not registered with the orchestrator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("constraint handle {handle} is not registered{}", if *.released { " (already released)" } else { "" })]
+pub struct UnknownConstraintHandle {
+    /// The handle's id
+    pub handle: u64,
+
+    /// Whether the handle was issued and has since been released
+    pub released: bool,
+}
+
+/// A constraint set held for generation by handle
+struct RegisteredConstraints {
+    /// Migrated, validated constraints
+    constraints: Vec<ConstraintIR>,
+
+    /// Compiled-constraint cache key, computed at registration
+    cache_key: String,
+}
+
+/// A request after context fitting, constraint compilation, and prompt assembly
+struct PreparedRequest {
+    request: GenerationRequest,
+    inference_request: modal_client::InferenceRequest,
+    context_included: Vec<String>,
+    constraint_compile_time_ms: u64,
+    compiled: CompiledConstraint,
+
+    /// [`Provenance::turn`]
+    turn: Option<usize>,
+
+    /// Set when the model cannot enforce the constraints and
+    /// `inference_request` was sent without them
+    unenforced: Option<ConstraintsUnsupported>,
+
+    /// [`GenerationMetadata::context_compression`]
+    context_compression: Option<ContextCompression>,
+}
+
+/// The answer of one model in the fallback chain, as selected by
+/// [`model_chain::run_chain`] in
+/// [`MazeOrchestrator::generate_admitted`]
+struct ChainAnswer {
+    response: InferenceResponse,
+
+    /// The attempt that was answered, as sent
+    sent: InferenceRequest,
+
+    /// Reasons earlier attempts were rejected by the output guard
+    rejections: Vec<String>,
+
+    /// Generated code after post-processing and formatting
+    processed: PostProcessed,
+    formatting: Option<FormatOutcome>,
+
+    /// Set when the model answered without enforcing the constraints
+    unenforced: Option<ConstraintsUnsupported>,
+
+    /// Temperature after clamping to the model's range
+    temperature: f32,
+    syntax_errors: Vec<SyntaxError>,
+}
+
+/// Readiness of the inference service, as reported by
+/// [`MazeOrchestrator::wait_until_ready`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Readiness {
+    /// Whether the service reported healthy before the timeout
+    pub ready: bool,
+
+    /// Number of health checks performed
+    pub probes: usize,
+
+    /// Time spent waiting in milliseconds
+    pub elapsed_ms: u64,
+
+    /// Error from the last failed health check, if not ready
+    pub last_error: Option<String>,
+}
+
+/// Whether `constraints` fix the shape of the output during decoding, so
+/// heuristic trimming could only cut valid code
+fn shapes_output(constraints: &[ConstraintIR]) -> bool {
+    constraints
+        .iter()
+        .any(|c| c.grammar.is_some() || !c.regex_patterns.is_empty())
+}
+
+/// Record in `validation` that output was generated without its constraints
+///
+/// Nothing is known to be satisfied, so nothing is claimed to be.
+fn mark_unenforced(validation: &mut ValidationResult, unsupported: &ConstraintsUnsupported) {
+    tracing::warn!("{}; output was generated unconstrained", unsupported);
+    validation.all_satisfied = false;
+    validation.satisfied.clear();
+    validation
+        .violated
+        .push(format!("constraints not enforced: {}", unsupported));
+    validation.metadata.insert(
+        "constraints_unenforced".to_string(),
+        serde_json::json!({
+            "model": unsupported.model,
+            "reason": unsupported.reason,
+        }),
+    );
+}
+
+/// Compile ConstraintIR to llguidance JSON schema
+///
+/// Constraints are merged deterministically by priority then name; see
+/// [`merge`] for the overlap rules. Returns the schema together with a
+/// report of every combined or overridden definition.
+pub(crate) fn compile_llguidance_schema(
+    constraints_ir: &[ConstraintIR],
+) -> Result<(serde_json::Value, ConstraintMergeReport)> {
+    // Convert ConstraintIR to llguidance format
+    // llguidance supports JSON schema, CFG, and regex
+
+    let mut schema = serde_json::json!({
+        "type": "object",
+        "properties": {},
+        "constraints": []
+    });
+
+    let ordered = merge::merge_order(constraints_ir);
+    let mut report = ConstraintMergeReport::new(&ordered);
+    let token_masks = merge::resolve_token_masks(&ordered, &mut report);
+    let labels = report.order.clone();
+
+    // Property key -> (priority, label) of the constraint that defined it
+    let mut property_owners: HashMap<String, (u32, String)> = HashMap::new();
+    let mut entries = Vec::new();
+
+    for ((constraint, token_masks), label) in ordered.iter().zip(&token_masks).zip(&labels) {
+        // Add JSON schema constraints
+        if let Some(ref json_schema) = constraint.json_schema {
+            let key = &constraint.name;
+            let definition = serde_json::json!(json_schema);
+            match property_owners.get(key) {
+                None => {
+                    schema["properties"][key] = definition;
+                    property_owners.insert(key.clone(), (constraint.priority, label.clone()));
+                }
+                Some((owner_priority, owner)) => {
+                    let event = merge::merge_property_definition(
+                        key,
+                        &mut schema["properties"][key],
+                        (*owner_priority, owner),
+                        (constraint.priority, label),
+                        &definition,
+                    );
+                    report.events.push(event);
+                }
+            }
+        }
+
+        // Add grammar and regex constraints
+        entries.extend(merge::pattern_entries(constraint));
+
+        // Add token mask constraints (after allow/forbid conflict resolution)
+        if let Some(ref token_masks) = token_masks {
+            entries.push(merge::token_mask_entry(&constraint.name, token_masks));
+        }
+    }
+    schema["constraints"] = serde_json::Value::Array(entries);
+
+    Ok((schema, report))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheStats {
     pub size: usize,
@@ -518,6 +3366,51 @@ mod tests {
         assert!(config.enable_cache);
     }
 
+    #[test]
+    fn test_maze_config_from_env_vars() {
+        let vars = HashMap::from([
+            ("MAZE_MAX_TOKENS", "512"),
+            ("MAZE_TEMPERATURE", "0.2"),
+            ("MAZE_CACHE_SIZE", "50"),
+            ("MAZE_ENABLE_CACHE", "false"),
+            ("MAZE_MAX_CONCURRENT_REQUESTS", "4"),
+            ("MAZE_QUEUE_TIMEOUT_MS", "250"),
+            ("MAZE_TOKENIZER", "/models/tokenizer.json"),
+        ]);
+        let config = MazeConfig::from_lookup(|name| vars.get(name).map(|v| v.to_string())).unwrap();
+
+        assert_eq!(config.max_tokens, 512);
+        assert_eq!(config.temperature, 0.2);
+        assert_eq!(config.cache_size_limit, 50);
+        assert!(!config.enable_cache);
+        assert_eq!(config.max_concurrent_requests, 4);
+        assert_eq!(config.queue_timeout_ms, Some(250));
+        assert_eq!(
+            config.tokenizer_path.as_deref(),
+            Some(std::path::Path::new("/models/tokenizer.json"))
+        );
+        // Unset variables keep their defaults
+        assert_eq!(config.timeout_secs, MazeConfig::default().timeout_secs);
+    }
+
+    #[test]
+    fn test_maze_config_from_env_parse_errors() {
+        let load = |name: &'static str, value: &'static str| {
+            MazeConfig::from_lookup(|n| (n == name).then(|| value.to_string()))
+        };
+
+        let err = load("MAZE_MAX_TOKENS", "lots").unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "MAZE_MAX_TOKENS must be a positive integer, got 'lots'"
+        );
+        assert_eq!(load("MAZE_CACHE_SIZE", "0").unwrap().cache_size_limit, 0);
+        assert!(load("MAZE_TEMPERATURE", "-1").is_err());
+        assert!(load("MAZE_TIMEOUT_SECS", "soon").is_err());
+        assert!(load("MAZE_ENABLE_CACHE", "maybe").is_err());
+        assert!(load("MAZE_ENABLE_CACHE", "1").unwrap().enable_cache);
+    }
+
     #[test]
     fn test_generation_request_serialization() {
         let request = GenerationRequest {
@@ -526,10 +3419,350 @@ mod tests {
             max_tokens: 100,
             temperature: 0.5,
             context: None,
+            seed: None,
+            timeout_ms: None,
+            history: Vec::new(),
         };
 
         let json = serde_json::to_string(&request).unwrap();
         let deserialized: GenerationRequest = serde_json::from_str(&json).unwrap();
         assert_eq!(request.prompt, deserialized.prompt);
     }
+
+    #[tokio::test]
+    async fn test_zero_cache_size_disables_caching() {
+        let config = MazeConfig {
+            cache_size_limit: 0,
+            response_cache: ResponseCacheConfig {
+                enabled: true,
+                size_limit: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let orchestrator = MazeOrchestrator::with_client(MockInferenceClient::new("m"), config);
+
+        let constraints = vec![schema_constraint("a", 1, "x")];
+        orchestrator
+            .compile_constraints(&constraints)
+            .await
+            .unwrap();
+        let stats = orchestrator.cache_stats().await;
+        assert_eq!((stats.size, stats.limit), (0, 0));
+
+        // Raising the capacity turns caching back on
+        orchestrator.set_cache_capacity(4).await;
+        orchestrator
+            .compile_constraints(&constraints)
+            .await
+            .unwrap();
+        assert_eq!(orchestrator.cache_stats().await.size, 1);
+    }
+
+    #[tokio::test]
+    async fn test_set_cache_capacity_evicts_least_recently_used() {
+        let orchestrator = test_orchestrator();
+        let sets: Vec<Vec<ConstraintIR>> = ["a", "b", "c"]
+            .iter()
+            .map(|name| vec![schema_constraint(name, 1, name)])
+            .collect();
+        for set in &sets {
+            orchestrator.compile_constraints(set).await.unwrap();
+        }
+        // Touch "a" so "b" becomes the least recently used
+        orchestrator.compile_constraints(&sets[0]).await.unwrap();
+
+        orchestrator.set_cache_capacity(2).await;
+        let stats = orchestrator.cache_stats().await;
+        assert_eq!((stats.size, stats.limit), (2, 2));
+
+        for (set, kept) in sets.iter().zip([true, false, true]) {
+            assert_eq!(orchestrator.is_cached(set).await.unwrap(), kept);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_precompile_caches_profiles_and_reports_problems() {
+        let orchestrator = test_orchestrator();
+        let security = vec![
+            schema_constraint("auth", 1, "low"),
+            schema_constraint("auth", 9, "high"),
+        ];
+        let mut bad_regex = schema_constraint("style", 1, "x");
+        bad_regex.regex_patterns = vec![ffi::RegexPattern {
+            pattern: "(".to_string(),
+            flags: String::new(),
+        }];
+        let mut future = schema_constraint("types", 1, "y");
+        future.schema_version = ffi::CONSTRAINT_SCHEMA_VERSION + 1;
+
+        let report = orchestrator
+            .precompile(vec![security.clone(), vec![bad_regex], vec![future]])
+            .await;
+
+        assert_eq!(report.compiled().count(), 2);
+        let problems: Vec<usize> = report.problems().map(|p| p.index).collect();
+        assert_eq!(problems, vec![1, 2]);
+        assert!(report.profiles[0].is_ok());
+        assert_eq!(report.profiles[0].merge_report.overridden().count(), 1);
+        assert!(report.profiles[1].lints[0]
+            .message
+            .contains("does not compile"));
+        assert!(report.profiles[2].cache_key.is_none());
+        assert!(report.profiles[2].error.is_some());
+
+        // Both compiled profiles are now cached
+        assert_eq!(orchestrator.cache_stats().await.size, 2);
+        assert!(orchestrator
+            .constraint_cache
+            .peek(report.profiles[0].cache_key.as_ref().unwrap())
+            .await
+            .is_some());
+    }
+
+    fn schema_constraint(name: &str, priority: u32, property: &str) -> ConstraintIR {
+        ConstraintIR {
+            name: name.to_string(),
+            json_schema: Some(ffi::JsonSchema {
+                schema_type: "object".to_string(),
+                properties: HashMap::from([(
+                    property.to_string(),
+                    serde_json::json!({"type": "string"}),
+                )]),
+                required: vec![],
+                additional_properties: false,
+            }),
+            grammar: None,
+            regex_patterns: vec![],
+            token_masks: None,
+            type_inhabitation: None,
+            priority,
+            rich_context: None,
+            feasibility_score: 0.0,
+            is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    fn test_orchestrator() -> MazeOrchestrator {
+        let config = ModalConfig::new(
+            "https://test.modal.run".to_string(),
+            "test-model".to_string(),
+        );
+        MazeOrchestrator::new(config).unwrap()
+    }
+
+    #[test]
+    fn test_compile_merge_is_order_independent() {
+        let orchestrator = test_orchestrator();
+        let a = schema_constraint("security", 5, "a");
+        let b = schema_constraint("style", 1, "b");
+
+        let (forward, report) = orchestrator
+            .compile_to_format(&[a.clone(), b.clone()])
+            .unwrap();
+        let (reverse, _) = orchestrator.compile_to_format(&[b, a]).unwrap();
+
+        assert_eq!(forward, reverse);
+        assert_eq!(report.order, vec!["security", "style"]);
+        assert!(report.is_clean());
+    }
+
+    #[test]
+    fn test_compile_merge_overlapping_properties() {
+        let orchestrator = test_orchestrator();
+
+        // Higher priority wins outright
+        let (schema, report) = orchestrator
+            .compile_to_format(&[
+                schema_constraint("auth", 1, "low"),
+                schema_constraint("auth", 9, "high"),
+            ])
+            .unwrap();
+        assert!(schema["properties"]["auth"]["properties"]["high"].is_object());
+        assert_eq!(report.overridden().count(), 1);
+
+        // Equal priority combines with allOf
+        let (schema, report) = orchestrator
+            .compile_to_format(&[
+                schema_constraint("auth", 3, "x"),
+                schema_constraint("auth", 3, "y"),
+            ])
+            .unwrap();
+        let all_of = schema["properties"]["auth"]["allOf"].as_array().unwrap();
+        assert_eq!(all_of.len(), 2);
+        assert_eq!(report.combined().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_same_named_constraints_are_reported_separately() {
+        let orchestrator = MazeOrchestrator::with_client(
+            MockInferenceClient::new("mock-model"),
+            MazeConfig::default(),
+        );
+        let request = GenerationRequest {
+            prompt: "fn login()".to_string(),
+            constraints_ir: vec![
+                schema_constraint("auth", 3, "x"),
+                schema_constraint("auth", 3, "y"),
+            ],
+            max_tokens: 16,
+            temperature: 0.0,
+            context: None,
+            seed: None,
+            timeout_ms: None,
+            history: Vec::new(),
+        };
+
+        let compiled = orchestrator
+            .compile_constraints(&request.constraints_ir)
+            .await
+            .unwrap();
+        let all_of = compiled.llguidance_schema["properties"]["auth"]["allOf"]
+            .as_array()
+            .unwrap();
+        assert_eq!(all_of.len(), 2);
+        assert_eq!(compiled.merge_report.order, vec!["auth", "auth#2"]);
+        assert_eq!(compiled.merge_report.events[0].winner, "auth");
+        assert_eq!(compiled.merge_report.events[0].affected, vec!["auth#2"]);
+
+        let response = orchestrator.generate(request).await.unwrap();
+        assert_eq!(
+            response.provenance.constraints_applied,
+            vec!["auth", "auth#2"]
+        );
+        assert_eq!(response.validation.satisfied, vec!["auth", "auth#2"]);
+    }
+
+    #[tokio::test]
+    async fn test_strict_provenance_catches_unrecorded_parameters() {
+        let orchestrator = MazeOrchestrator::with_client(
+            MockInferenceClient::new("mock-model"),
+            MazeConfig {
+                strict_provenance: true,
+                ..MazeConfig::default()
+            },
+        );
+        let request = GenerationRequest {
+            prompt: "fn add()".to_string(),
+            constraints_ir: vec![],
+            max_tokens: 16,
+            temperature: 0.0,
+            context: None,
+            seed: None,
+            timeout_ms: None,
+            history: Vec::new(),
+        };
+        let prepared = InferenceRequest {
+            prompt: request.prompt.clone(),
+            constraints: serde_json::json!({}),
+            max_tokens: 16,
+            temperature: 0.0,
+            context: None,
+            seed: None,
+            idempotency_key: Some("key".to_string()),
+            include_logprobs: false,
+            timeout_ms: None,
+            include_constraint_events: false,
+        };
+        let provenance = |sent: &InferenceRequest| {
+            orchestrator.provenance(
+                &request,
+                &prepared,
+                sent,
+                "mock-model".to_string(),
+                Vec::new(),
+                UNCONSTRAINED_HASH.to_string(),
+            )
+        };
+
+        let recorded = provenance(&prepared).unwrap();
+        assert_eq!(recorded.idempotency_key.as_deref(), Some("key"));
+
+        // Parameters set while sending, but not carried over to the
+        // prepared request provenance is built from
+        let sent = InferenceRequest {
+            include_logprobs: true,
+            seed: Some(3),
+            ..prepared.clone()
+        };
+        let err = provenance(&sent).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<UnrecordedParameters>()
+                .unwrap()
+                .parameters,
+            vec!["include_logprobs", "seed"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_registered_matches_generate() {
+        let client = MockInferenceClient::new("mock-model");
+        let orchestrator = MazeOrchestrator::with_client(client.clone(), MazeConfig::default());
+        let constraints = vec![
+            schema_constraint("auth", 3, "x"),
+            schema_constraint("style", 1, "y"),
+        ];
+        let request = |constraints_ir| GenerationRequest {
+            prompt: "fn login()".to_string(),
+            constraints_ir,
+            max_tokens: 16,
+            temperature: 0.0,
+            context: None,
+            seed: None,
+            timeout_ms: None,
+            history: Vec::new(),
+        };
+
+        let handle = orchestrator
+            .register_constraints(constraints.clone())
+            .unwrap();
+        let registered = orchestrator
+            .generate_registered(handle, request(vec![]))
+            .await
+            .unwrap();
+        let direct = orchestrator
+            .generate(request(constraints.clone()))
+            .await
+            .unwrap();
+        assert_eq!(
+            registered.provenance.constraints_hash,
+            direct.provenance.constraints_hash
+        );
+        assert_eq!(
+            registered.provenance.constraints_applied,
+            vec!["auth", "style"]
+        );
+        let sent = client.requests();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].constraints, sent[1].constraints);
+        assert_eq!(orchestrator.cache_stats().await.size, 1);
+
+        // The registered set replaces the request's constraints, never joins them
+        assert!(orchestrator
+            .generate_registered(handle, request(constraints))
+            .await
+            .is_err());
+
+        assert_eq!(orchestrator.registered_count(), 1);
+        orchestrator.release_constraints(handle).unwrap();
+        assert_eq!(orchestrator.registered_count(), 0);
+        let err = orchestrator
+            .generate_registered(handle, request(vec![]))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<UnknownConstraintHandle>(),
+            Some(&UnknownConstraintHandle {
+                handle: handle.0,
+                released: true,
+            })
+        );
+        assert!(
+            !orchestrator
+                .release_constraints(ConstraintHandle(99))
+                .unwrap_err()
+                .released
+        );
+    }
 }
@@ -0,0 +1,628 @@
+//! Static analysis of constraint sets
+//!
+//! Detects constraints that are redundant (already implied by others),
+//! shadowed (overridden during merging), or never matchable (no output can
+//! satisfy them). The checks run against the merged form produced by the
+//! compiler, so they reflect what is actually sent to llguidance.
+//!
+//! Grammar languages are analyzed by enumeration: grammars whose language
+//! is finite and small (see [`GRAMMAR_ENUMERATION_LIMIT`]) are expanded,
+//! with terminals concatenated, and regex patterns are tested against every
+//! string the grammar can produce. Recursive or larger grammars are skipped.
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::ffi::{ConstraintIR, Grammar, RegexPattern};
+use crate::merge::{ConstraintMergeReport, MergeAction};
+
+/// Maximum number of strings enumerated from a grammar before giving up
+pub const GRAMMAR_ENUMERATION_LIMIT: usize = 512;
+
+/// Severity of a lint finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum LintSeverity {
+    /// Harmless but worth knowing about
+    Info,
+
+    /// Probably not what the author intended
+    Warning,
+
+    /// The constraint set cannot be satisfied as written
+    Error,
+}
+
+/// Category of a lint finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LintKind {
+    /// The constraint adds nothing beyond what other constraints enforce
+    Redundant,
+
+    /// The constraint is overridden by a higher-priority constraint
+    Shadowed,
+
+    /// No output can satisfy the constraint
+    NeverMatchable,
+}
+
+/// A structured warning about a constraint set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConstraintLint {
+    /// Category of the finding
+    pub kind: LintKind,
+
+    /// How serious the finding is
+    pub severity: LintSeverity,
+
+    /// Constraint the finding is about
+    pub constraint: String,
+
+    /// Other constraints involved in the finding
+    pub related: Vec<String>,
+
+    /// Human-readable explanation
+    pub message: String,
+}
+
+impl ConstraintLint {
+    fn new(
+        kind: LintKind,
+        severity: LintSeverity,
+        constraint: &str,
+        related: Vec<String>,
+        message: String,
+    ) -> Self {
+        Self {
+            kind,
+            severity,
+            constraint: constraint.to_string(),
+            related,
+            message,
+        }
+    }
+}
+
+/// Lint a constraint set for redundant, shadowed, and never-matchable constraints
+///
+/// Findings are returned in merge order (descending priority, then name).
+pub fn lint_constraints(constraints: &[ConstraintIR]) -> Vec<ConstraintLint> {
+    let mut lints = Vec::new();
+    let ordered = crate::merge::merge_order(constraints);
+
+    lint_individual(&ordered, &mut lints);
+
+    match crate::compile_llguidance_schema(constraints) {
+        Ok((_, report)) => lint_shadowed(&report, &mut lints),
+        Err(e) => tracing::warn!("Skipping shadowing analysis, compilation failed: {}", e),
+    }
+
+    lint_duplicates(&ordered, &mut lints);
+    lint_token_masks(&ordered, &mut lints);
+    lint_regex_against_grammars(&ordered, &mut lints);
+
+    lints
+}
+
+/// Checks that only need a single constraint
+fn lint_individual(ordered: &[&ConstraintIR], lints: &mut Vec<ConstraintLint>) {
+    for constraint in ordered {
+        if !constraint.is_feasible {
+            lints.push(ConstraintLint::new(
+                LintKind::NeverMatchable,
+                LintSeverity::Error,
+                &constraint.name,
+                vec![],
+                "constraint was marked infeasible by the constraint engine".to_string(),
+            ));
+        }
+
+        let has_content = constraint.json_schema.is_some()
+            || constraint.grammar.is_some()
+            || !constraint.regex_patterns.is_empty()
+            || constraint.token_masks.is_some()
+            || constraint.type_inhabitation.is_some()
+            || crate::policy::PolicyConstraint::from_constraint(constraint).is_some();
+        if !has_content {
+            lints.push(ConstraintLint::new(
+                LintKind::Redundant,
+                LintSeverity::Info,
+                &constraint.name,
+                vec![],
+                "constraint has no enforceable content".to_string(),
+            ));
+        }
+
+        if let Some(ref grammar) = constraint.grammar {
+            if !grammar.rules.iter().any(|r| r.lhs == grammar.start_symbol) {
+                lints.push(ConstraintLint::new(
+                    LintKind::NeverMatchable,
+                    LintSeverity::Error,
+                    &constraint.name,
+                    vec![],
+                    format!(
+                        "grammar start symbol '{}' has no productions",
+                        grammar.start_symbol
+                    ),
+                ));
+            }
+        }
+
+        for pattern in &constraint.regex_patterns {
+            if let Err(e) = compile_regex(pattern) {
+                lints.push(ConstraintLint::new(
+                    LintKind::NeverMatchable,
+                    LintSeverity::Error,
+                    &constraint.name,
+                    vec![],
+                    format!("regex '{}' does not compile: {}", pattern.pattern, e),
+                ));
+            }
+        }
+
+        if let Some(ref masks) = constraint.token_masks {
+            if let Some(ref allowed) = masks.allowed_tokens {
+                let forbidden: HashSet<u32> =
+                    masks.forbidden_tokens.iter().flatten().copied().collect();
+                let usable = allowed.iter().filter(|t| !forbidden.contains(t)).count();
+                if usable == 0 {
+                    lints.push(ConstraintLint::new(
+                        LintKind::NeverMatchable,
+                        LintSeverity::Error,
+                        &constraint.name,
+                        vec![],
+                        "token mask leaves no allowed tokens".to_string(),
+                    ));
+                } else if usable < allowed.len() {
+                    lints.push(ConstraintLint::new(
+                        LintKind::Redundant,
+                        LintSeverity::Warning,
+                        &constraint.name,
+                        vec![],
+                        format!(
+                            "{} token(s) are both allowed and forbidden by the same mask",
+                            allowed.len() - usable
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Definitions that lost during merging
+fn lint_shadowed(report: &ConstraintMergeReport, lints: &mut Vec<ConstraintLint>) {
+    for event in report.events.iter() {
+        if event.action != MergeAction::Overridden {
+            continue;
+        }
+        for affected in &event.affected {
+            lints.push(ConstraintLint::new(
+                LintKind::Shadowed,
+                LintSeverity::Warning,
+                affected,
+                vec![event.winner.clone()],
+                format!("{} is overridden by '{}'", event.target, event.winner),
+            ));
+        }
+    }
+}
+
+/// Identical regexes or grammars declared more than once
+fn lint_duplicates(ordered: &[&ConstraintIR], lints: &mut Vec<ConstraintLint>) {
+    let mut seen_patterns: HashMap<(&str, &str), &str> = HashMap::new();
+    let mut seen_grammars: Vec<(&Grammar, &str)> = Vec::new();
+
+    for constraint in ordered {
+        for pattern in &constraint.regex_patterns {
+            let key = (pattern.pattern.as_str(), pattern.flags.as_str());
+            match seen_patterns.get(&key) {
+                Some(first) => lints.push(ConstraintLint::new(
+                    LintKind::Redundant,
+                    LintSeverity::Warning,
+                    &constraint.name,
+                    vec![first.to_string()],
+                    format!(
+                        "regex '{}' is already declared by '{}'",
+                        pattern.pattern, first
+                    ),
+                )),
+                None => {
+                    seen_patterns.insert(key, &constraint.name);
+                }
+            }
+        }
+
+        if let Some(ref grammar) = constraint.grammar {
+            match seen_grammars
+                .iter()
+                .find(|(g, _)| grammars_equal(g, grammar))
+            {
+                Some((_, first)) => lints.push(ConstraintLint::new(
+                    LintKind::Redundant,
+                    LintSeverity::Warning,
+                    &constraint.name,
+                    vec![first.to_string()],
+                    format!("grammar is identical to the one declared by '{}'", first),
+                )),
+                None => seen_grammars.push((grammar, &constraint.name)),
+            }
+        }
+    }
+}
+
+/// Cross-constraint token mask analysis
+fn lint_token_masks(ordered: &[&ConstraintIR], lints: &mut Vec<ConstraintLint>) {
+    let whitelists: Vec<(&str, BTreeSet<u32>)> = ordered
+        .iter()
+        .filter_map(|c| {
+            let allowed = c.token_masks.as_ref()?.allowed_tokens.as_ref()?;
+            Some((c.name.as_str(), allowed.iter().copied().collect()))
+        })
+        .collect();
+
+    // Several whitelists with no token in common can never all be met
+    if whitelists.len() > 1 {
+        let common = whitelists
+            .iter()
+            .skip(1)
+            .fold(whitelists[0].1.clone(), |acc, (_, set)| {
+                acc.intersection(set).copied().collect()
+            });
+        if common.is_empty() {
+            let (first, _) = whitelists[0];
+            lints.push(ConstraintLint::new(
+                LintKind::NeverMatchable,
+                LintSeverity::Error,
+                first,
+                whitelists
+                    .iter()
+                    .skip(1)
+                    .map(|(n, _)| n.to_string())
+                    .collect(),
+                "allowed-token lists have no token in common".to_string(),
+            ));
+        }
+    }
+
+    let mut forbidden_by: HashMap<u32, &str> = HashMap::new();
+    for constraint in ordered {
+        let Some(forbidden) = constraint
+            .token_masks
+            .as_ref()
+            .and_then(|m| m.forbidden_tokens.as_ref())
+        else {
+            continue;
+        };
+
+        // Forbidding a token that another constraint's whitelist excludes
+        for (other, allowed) in &whitelists {
+            if *other == constraint.name {
+                continue;
+            }
+            let excluded = forbidden.iter().filter(|t| !allowed.contains(t)).count();
+            if excluded > 0 {
+                lints.push(ConstraintLint::new(
+                    LintKind::Redundant,
+                    LintSeverity::Warning,
+                    &constraint.name,
+                    vec![other.to_string()],
+                    format!(
+                        "{} forbidden token(s) are already excluded by the allow list of '{}'",
+                        excluded, other
+                    ),
+                ));
+            }
+        }
+
+        // Forbidding a token another constraint already forbids
+        let mut duplicated: Vec<&str> = Vec::new();
+        for token in forbidden {
+            match forbidden_by.get(token) {
+                Some(first) if *first != constraint.name => {
+                    if !duplicated.contains(first) {
+                        duplicated.push(first);
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    forbidden_by.insert(*token, &constraint.name);
+                }
+            }
+        }
+        for first in duplicated {
+            lints.push(ConstraintLint::new(
+                LintKind::Redundant,
+                LintSeverity::Info,
+                &constraint.name,
+                vec![first.to_string()],
+                format!("forbids tokens already forbidden by '{}'", first),
+            ));
+        }
+    }
+}
+
+/// Compare regex patterns against the languages of finite grammars
+fn lint_regex_against_grammars(ordered: &[&ConstraintIR], lints: &mut Vec<ConstraintLint>) {
+    let languages: Vec<(&str, Vec<String>)> = ordered
+        .iter()
+        .filter_map(|c| {
+            let grammar = c.grammar.as_ref()?;
+            let language = enumerate_language(grammar, GRAMMAR_ENUMERATION_LIMIT)?;
+            Some((c.name.as_str(), language))
+        })
+        .filter(|(_, language)| !language.is_empty())
+        .collect();
+
+    if languages.is_empty() {
+        return;
+    }
+
+    for constraint in ordered {
+        for pattern in &constraint.regex_patterns {
+            let Ok(regex) = compile_regex(pattern) else {
+                continue;
+            };
+
+            for (grammar_owner, language) in &languages {
+                let matches = language.iter().filter(|s| regex.is_match(s)).count();
+                if matches == 0 {
+                    lints.push(ConstraintLint::new(
+                        LintKind::NeverMatchable,
+                        LintSeverity::Error,
+                        &constraint.name,
+                        vec![grammar_owner.to_string()],
+                        format!(
+                            "regex '{}' matches nothing the grammar of '{}' can produce",
+                            pattern.pattern, grammar_owner
+                        ),
+                    ));
+                } else if matches == language.len() {
+                    lints.push(ConstraintLint::new(
+                        LintKind::Redundant,
+                        LintSeverity::Warning,
+                        &constraint.name,
+                        vec![grammar_owner.to_string()],
+                        format!(
+                            "regex '{}' is implied by the grammar of '{}'",
+                            pattern.pattern, grammar_owner
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+/// Compile a regex pattern anchored to the whole output, honoring its flags
+pub(crate) fn compile_regex(pattern: &RegexPattern) -> Result<Regex, regex::Error> {
+    let mut builder = RegexBuilder::new(&format!("^(?:{})$", pattern.pattern));
+    for flag in pattern.flags.chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            _ => &mut builder,
+        };
+    }
+    builder.build()
+}
+
+fn grammars_equal(a: &Grammar, b: &Grammar) -> bool {
+    a.start_symbol == b.start_symbol
+        && a.rules.len() == b.rules.len()
+        && a.rules
+            .iter()
+            .zip(&b.rules)
+            .all(|(x, y)| x.lhs == y.lhs && x.rhs == y.rhs)
+}
+
+/// Enumerate every string a grammar can produce
+///
+/// Symbols appearing on a left-hand side are nonterminals; all other symbols
+/// are terminals and are concatenated without separators. Returns `None` if
+/// the grammar is recursive, its start symbol is undefined, or it produces
+/// more than `limit` strings.
+pub(crate) fn enumerate_language(grammar: &Grammar, limit: usize) -> Option<Vec<String>> {
+    let mut productions: HashMap<&str, Vec<&[String]>> = HashMap::new();
+    for rule in &grammar.rules {
+        productions
+            .entry(rule.lhs.as_str())
+            .or_default()
+            .push(rule.rhs.as_slice());
+    }
+
+    if !productions.contains_key(grammar.start_symbol.as_str()) {
+        return None;
+    }
+
+    let mut visiting = HashSet::new();
+    let mut language = expand(&grammar.start_symbol, &productions, &mut visiting, limit)?;
+    language.sort();
+    language.dedup();
+    Some(language)
+}
+
+fn expand<'a>(
+    symbol: &'a str,
+    productions: &HashMap<&'a str, Vec<&'a [String]>>,
+    visiting: &mut HashSet<&'a str>,
+    limit: usize,
+) -> Option<Vec<String>> {
+    let Some(alternatives) = productions.get(symbol) else {
+        return Some(vec![symbol.to_string()]);
+    };
+
+    if !visiting.insert(symbol) {
+        // Recursive grammar: language is (potentially) infinite
+        return None;
+    }
+
+    let mut strings = Vec::new();
+    for rhs in alternatives {
+        let mut partials = vec![String::new()];
+        for part in rhs.iter() {
+            let expansions = expand(part, productions, visiting, limit)?;
+            if partials.len() * expansions.len() > limit {
+                return None;
+            }
+            partials = partials
+                .iter()
+                .flat_map(|prefix| expansions.iter().map(move |s| format!("{}{}", prefix, s)))
+                .collect();
+        }
+        strings.extend(partials);
+        if strings.len() > limit {
+            return None;
+        }
+    }
+
+    visiting.remove(symbol);
+    Some(strings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{GrammarRule, TokenMaskRules};
+
+    fn constraint(name: &str) -> ConstraintIR {
+        ConstraintIR {
+            name: name.to_string(),
+            json_schema: None,
+            grammar: None,
+            regex_patterns: vec![],
+            token_masks: None,
+            type_inhabitation: None,
+            priority: 0,
+            rich_context: None,
+            feasibility_score: 0.0,
+            is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    fn bool_grammar() -> Grammar {
+        Grammar {
+            rules: vec![
+                GrammarRule {
+                    lhs: "S".to_string(),
+                    rhs: vec!["true".to_string()],
+                },
+                GrammarRule {
+                    lhs: "S".to_string(),
+                    rhs: vec!["false".to_string()],
+                },
+            ],
+            start_symbol: "S".to_string(),
+        }
+    }
+
+    fn regex(pattern: &str) -> RegexPattern {
+        RegexPattern {
+            pattern: pattern.to_string(),
+            flags: String::new(),
+        }
+    }
+
+    fn has(lints: &[ConstraintLint], kind: LintKind, name: &str) -> bool {
+        lints.iter().any(|l| l.kind == kind && l.constraint == name)
+    }
+
+    #[test]
+    fn test_enumerate_language_finite_and_recursive() {
+        let language = enumerate_language(&bool_grammar(), 16).unwrap();
+        assert_eq!(language, vec!["false".to_string(), "true".to_string()]);
+
+        let recursive = Grammar {
+            rules: vec![GrammarRule {
+                lhs: "S".to_string(),
+                rhs: vec!["a".to_string(), "S".to_string()],
+            }],
+            start_symbol: "S".to_string(),
+        };
+        assert!(enumerate_language(&recursive, 16).is_none());
+    }
+
+    #[test]
+    fn test_regex_implied_by_grammar_is_redundant() {
+        let mut grammar = constraint("grammar");
+        grammar.grammar = Some(bool_grammar());
+        let mut pattern = constraint("pattern");
+        pattern.regex_patterns = vec![regex("[a-z]+")];
+
+        let lints = lint_constraints(&[grammar, pattern]);
+        assert!(has(&lints, LintKind::Redundant, "pattern"));
+    }
+
+    #[test]
+    fn test_regex_disjoint_from_grammar_never_matches() {
+        let mut grammar = constraint("grammar");
+        grammar.grammar = Some(bool_grammar());
+        let mut pattern = constraint("pattern");
+        pattern.regex_patterns = vec![regex(r"\d+")];
+
+        let lints = lint_constraints(&[grammar, pattern]);
+        let lint = lints
+            .iter()
+            .find(|l| l.kind == LintKind::NeverMatchable)
+            .unwrap();
+        assert_eq!(lint.severity, LintSeverity::Error);
+        assert_eq!(lint.related, vec!["grammar".to_string()]);
+    }
+
+    #[test]
+    fn test_token_mask_forbids_already_excluded_tokens() {
+        let mut whitelist = constraint("whitelist");
+        whitelist.token_masks = Some(TokenMaskRules {
+            allowed_tokens: Some(vec![1, 2, 3]),
+            forbidden_tokens: None,
+        });
+        let mut blacklist = constraint("blacklist");
+        blacklist.token_masks = Some(TokenMaskRules {
+            allowed_tokens: None,
+            forbidden_tokens: Some(vec![10, 11]),
+        });
+
+        let lints = lint_constraints(&[whitelist, blacklist]);
+        assert!(has(&lints, LintKind::Redundant, "blacklist"));
+    }
+
+    #[test]
+    fn test_shadowed_and_unsatisfiable_masks() {
+        let mut strong = constraint("strong");
+        strong.priority = 5;
+        strong.token_masks = Some(TokenMaskRules {
+            allowed_tokens: None,
+            forbidden_tokens: Some(vec![4]),
+        });
+        let mut weak = constraint("weak");
+        weak.token_masks = Some(TokenMaskRules {
+            allowed_tokens: Some(vec![4]),
+            forbidden_tokens: Some(vec![4]),
+        });
+
+        let lints = lint_constraints(&[weak, strong]);
+        assert!(has(&lints, LintKind::Shadowed, "weak"));
+        assert!(has(&lints, LintKind::NeverMatchable, "weak"));
+    }
+
+    #[test]
+    fn test_missing_start_symbol_and_clean_set() {
+        let mut broken = constraint("broken");
+        broken.grammar = Some(Grammar {
+            rules: vec![],
+            start_symbol: "S".to_string(),
+        });
+        assert!(has(
+            &lint_constraints(&[broken]),
+            LintKind::NeverMatchable,
+            "broken"
+        ));
+
+        let mut clean = constraint("clean");
+        clean.regex_patterns = vec![regex(r"\w+")];
+        assert!(lint_constraints(&[clean]).is_empty());
+    }
+}
@@ -0,0 +1,401 @@
+//! Deterministic merging of overlapping constraints
+//!
+//! Constraints are merged in descending `priority` order, ties broken by
+//! ascending `name`, so the compiled schema does not depend on the order in
+//! which constraint sources happened to be concatenated.
+//!
+//! Overlap rules:
+//! - JSON schema properties (keyed by constraint name): a higher-priority
+//!   definition overrides lower-priority ones; equal-priority definitions are
+//!   combined with `allOf`, so both must hold.
+//! - Token masks: when one constraint allows a token that another forbids,
+//!   the higher-priority side wins; on a tie the token stays forbidden.
+//! - Grammar and regex constraints never override each other; they are
+//!   emitted in merge order and all of them apply.
+//!
+//! Names are not required to be unique. Reports refer to each constraint by
+//! its [`distinct_names`] label: the first constraint with a name in merge
+//! order keeps it and later ones become `name#2`, `name#3`, and so on.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::ffi::{ConstraintIR, TokenMaskRules};
+
+/// How an overlap between constraints was resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MergeAction {
+    /// Overlapping definitions were combined and all of them apply
+    Combined,
+
+    /// The winning constraint replaced the affected definitions
+    Overridden,
+}
+
+/// A single overlap resolved during merging
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MergeEvent {
+    /// How the overlap was resolved
+    pub action: MergeAction,
+
+    /// What overlapped, e.g. `property:auth` or `token:42`
+    pub target: String,
+
+    /// Constraint whose definition took precedence
+    pub winner: String,
+
+    /// Constraints that were combined with or overridden by the winner
+    pub affected: Vec<String>,
+}
+
+/// Report describing how a constraint set was merged
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConstraintMergeReport {
+    /// Constraint labels (see [`distinct_names`]) in the order they were merged
+    pub order: Vec<String>,
+
+    /// Overlaps that were combined or overridden
+    pub events: Vec<MergeEvent>,
+}
+
+impl ConstraintMergeReport {
+    /// Empty report for constraints already in merge order
+    pub(crate) fn new(ordered: &[&ConstraintIR]) -> Self {
+        Self {
+            order: disambiguate(ordered.iter().map(|c| c.name.as_str())),
+            events: vec![],
+        }
+    }
+
+    /// Whether the merge completed without any overlaps
+    pub fn is_clean(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Events where one constraint overrode another
+    pub fn overridden(&self) -> impl Iterator<Item = &MergeEvent> {
+        self.events
+            .iter()
+            .filter(|e| e.action == MergeAction::Overridden)
+    }
+
+    /// Events where overlapping constraints were combined
+    pub fn combined(&self) -> impl Iterator<Item = &MergeEvent> {
+        self.events
+            .iter()
+            .filter(|e| e.action == MergeAction::Combined)
+    }
+}
+
+/// Order constraints for merging: descending priority, then ascending name
+pub fn merge_order(constraints: &[ConstraintIR]) -> Vec<&ConstraintIR> {
+    let mut ordered: Vec<&ConstraintIR> = constraints.iter().collect();
+    ordered.sort_by(|a, b| merge_cmp(a, b));
+    ordered
+}
+
+fn merge_cmp(a: &ConstraintIR, b: &ConstraintIR) -> Ordering {
+    b.priority
+        .cmp(&a.priority)
+        .then_with(|| a.name.cmp(&b.name))
+}
+
+/// A label per constraint, in input order, that is unique within the set
+///
+/// Numbering follows merge order, so the label a constraint gets does not
+/// depend on how constraint sources were concatenated: of two `auth`
+/// constraints the higher-priority one stays `auth` and the other becomes
+/// `auth#2`.
+pub fn distinct_names(constraints: &[ConstraintIR]) -> Vec<String> {
+    let mut indices: Vec<usize> = (0..constraints.len()).collect();
+    indices.sort_by(|&a, &b| merge_cmp(&constraints[a], &constraints[b]));
+
+    let labels = disambiguate(indices.iter().map(|&i| constraints[i].name.as_str()));
+    let mut names = vec![String::new(); constraints.len()];
+    for (index, label) in indices.into_iter().zip(labels) {
+        names[index] = label;
+    }
+    names
+}
+
+/// Suffix repeated names with `#2`, `#3`, ..., skipping labels already taken
+fn disambiguate<'a>(names: impl Iterator<Item = &'a str> + Clone) -> Vec<String> {
+    let mut taken: HashSet<String> = names.clone().map(String::from).collect();
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    names
+        .map(|name| {
+            let count = seen.entry(name).or_insert(0);
+            *count += 1;
+            if *count == 1 {
+                return name.to_string();
+            }
+            loop {
+                let label = format!("{}#{}", name, count);
+                if taken.insert(label.clone()) {
+                    break label;
+                }
+                *count += 1;
+            }
+        })
+        .collect()
+}
+
+/// Resolve allow/forbid conflicts between token masks
+///
+/// Returns the effective token masks for each constraint in `ordered`, with
+/// losing tokens removed, and records every conflict in `report`, whose
+/// `order` must hold the labels of `ordered`.
+pub(crate) fn resolve_token_masks(
+    ordered: &[&ConstraintIR],
+    report: &mut ConstraintMergeReport,
+) -> Vec<Option<TokenMaskRules>> {
+    let mut masks: Vec<Option<TokenMaskRules>> =
+        ordered.iter().map(|c| c.token_masks.clone()).collect();
+
+    // token -> indices (into `ordered`) of constraints allowing / forbidding it
+    let mut allowers: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+    let mut forbidders: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+
+    for (idx, mask) in masks.iter().enumerate() {
+        let Some(mask) = mask else { continue };
+        for &token in mask.allowed_tokens.iter().flatten() {
+            allowers.entry(token).or_default().push(idx);
+        }
+        for &token in mask.forbidden_tokens.iter().flatten() {
+            forbidders.entry(token).or_default().push(idx);
+        }
+    }
+
+    for (token, allow_idx) in &allowers {
+        let Some(forbid_idx) = forbidders.get(token) else {
+            continue;
+        };
+
+        // `ordered` is sorted by descending priority, so the first index on
+        // each side is that side's strongest constraint
+        let allow_priority = ordered[allow_idx[0]].priority;
+        let forbid_priority = ordered[forbid_idx[0]].priority;
+
+        let (winner, losers, strip_allowed) = if allow_priority > forbid_priority {
+            (allow_idx[0], forbid_idx, false)
+        } else {
+            (forbid_idx[0], allow_idx, true)
+        };
+
+        for &loser in losers {
+            if let Some(mask) = masks[loser].as_mut() {
+                let list = if strip_allowed {
+                    mask.allowed_tokens.as_mut()
+                } else {
+                    mask.forbidden_tokens.as_mut()
+                };
+                if let Some(list) = list {
+                    list.retain(|t| t != token);
+                }
+            }
+        }
+
+        report.events.push(MergeEvent {
+            action: MergeAction::Overridden,
+            target: format!("token:{}", token),
+            winner: report.order[winner].clone(),
+            affected: losers.iter().map(|&i| report.order[i].clone()).collect(),
+        });
+    }
+
+    masks
+}
+
+/// Merge a later definition of property `key`, from the constraint labelled
+/// `label`, into `value`, the definition `owner` gave at `owner_priority`
+///
+/// Returns the event recording the overlap.
+pub(crate) fn merge_property_definition(
+    key: &str,
+    value: &mut serde_json::Value,
+    (owner_priority, owner): (u32, &str),
+    (priority, label): (u32, &str),
+    definition: &serde_json::Value,
+) -> MergeEvent {
+    let action = if owner_priority > priority {
+        MergeAction::Overridden
+    } else {
+        // Same priority: both definitions must hold
+        let existing = value.take();
+        *value = serde_json::json!({ "allOf": [existing, definition] });
+        MergeAction::Combined
+    };
+    MergeEvent {
+        action,
+        target: format!("property:{}", key),
+        winner: owner.to_string(),
+        affected: vec![label.to_string()],
+    }
+}
+
+/// llguidance entries for `constraint`'s grammar and regex patterns, in
+/// emission order
+///
+/// These never overlap with other constraints' entries, so they depend on
+/// nothing else in the set.
+pub(crate) fn pattern_entries(constraint: &ConstraintIR) -> Vec<serde_json::Value> {
+    let mut entries = Vec::new();
+    if let Some(ref grammar) = constraint.grammar {
+        entries.push(serde_json::json!({
+            "type": "grammar",
+            "name": constraint.name,
+            "rules": grammar.rules,
+            "start": grammar.start_symbol
+        }));
+    }
+    for pattern in &constraint.regex_patterns {
+        entries.push(serde_json::json!({
+            "type": "regex",
+            "pattern": pattern.pattern,
+            "flags": pattern.flags
+        }));
+    }
+    entries
+}
+
+/// llguidance entry for the token masks of constraint `name`, after
+/// [`resolve_token_masks`]
+pub(crate) fn token_mask_entry(name: &str, token_masks: &TokenMaskRules) -> serde_json::Value {
+    let mut entry = serde_json::json!({
+        "type": "token_mask",
+        "name": name
+    });
+    if let Some(allowed) = &token_masks.allowed_tokens {
+        entry["allowed"] = serde_json::json!(allowed);
+    }
+    if let Some(forbidden) = &token_masks.forbidden_tokens {
+        entry["forbidden"] = serde_json::json!(forbidden);
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn constraint(name: &str, priority: u32) -> ConstraintIR {
+        ConstraintIR {
+            name: name.to_string(),
+            json_schema: None,
+            grammar: None,
+            regex_patterns: vec![],
+            token_masks: None,
+            type_inhabitation: None,
+            priority,
+            rich_context: None,
+            feasibility_score: 0.0,
+            is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    #[test]
+    fn test_merge_order_priority_then_name() {
+        let constraints = vec![
+            constraint("style", 1),
+            constraint("security", 5),
+            constraint("naming", 1),
+        ];
+
+        let names: Vec<&str> = merge_order(&constraints)
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["security", "naming", "style"]);
+    }
+
+    #[test]
+    fn test_token_conflict_higher_priority_allow_wins() {
+        let mut policy = constraint("policy", 10);
+        policy.token_masks = Some(TokenMaskRules {
+            allowed_tokens: Some(vec![7]),
+            forbidden_tokens: None,
+        });
+        let mut style = constraint("style", 1);
+        style.token_masks = Some(TokenMaskRules {
+            allowed_tokens: None,
+            forbidden_tokens: Some(vec![7, 8]),
+        });
+
+        let constraints = vec![style, policy];
+        let ordered = merge_order(&constraints);
+        let mut report = ConstraintMergeReport::new(&ordered);
+        let masks = resolve_token_masks(&ordered, &mut report);
+
+        // style is second in merge order; token 7 removed from its forbid list
+        let style_mask = masks[1].as_ref().unwrap();
+        assert_eq!(style_mask.forbidden_tokens, Some(vec![8]));
+        assert_eq!(report.events.len(), 1);
+        assert_eq!(report.events[0].winner, "policy");
+        assert_eq!(report.events[0].affected, vec!["style".to_string()]);
+    }
+
+    #[test]
+    fn test_token_conflict_tie_keeps_forbidden() {
+        let mut a = constraint("a", 3);
+        a.token_masks = Some(TokenMaskRules {
+            allowed_tokens: Some(vec![1, 2]),
+            forbidden_tokens: None,
+        });
+        let mut b = constraint("b", 3);
+        b.token_masks = Some(TokenMaskRules {
+            allowed_tokens: None,
+            forbidden_tokens: Some(vec![2]),
+        });
+
+        let constraints = vec![a, b];
+        let ordered = merge_order(&constraints);
+        let mut report = ConstraintMergeReport::new(&ordered);
+        let masks = resolve_token_masks(&ordered, &mut report);
+
+        assert_eq!(masks[0].as_ref().unwrap().allowed_tokens, Some(vec![1]));
+        assert_eq!(masks[1].as_ref().unwrap().forbidden_tokens, Some(vec![2]));
+        assert_eq!(report.overridden().count(), 1);
+        assert_eq!(report.events[0].winner, "b");
+    }
+
+    #[test]
+    fn test_distinct_names_number_repeats_in_merge_order() {
+        let constraints = vec![
+            constraint("auth", 1),
+            constraint("auth#2", 0),
+            constraint("style", 0),
+            constraint("auth", 9),
+            constraint("auth", 1),
+        ];
+
+        assert_eq!(
+            distinct_names(&constraints),
+            vec!["auth#3", "auth#2", "style", "auth", "auth#4"]
+        );
+    }
+
+    #[test]
+    fn test_token_conflict_between_same_named_constraints() {
+        let mut high = constraint("style", 5);
+        high.token_masks = Some(TokenMaskRules {
+            allowed_tokens: Some(vec![3]),
+            forbidden_tokens: None,
+        });
+        let mut low = constraint("style", 1);
+        low.token_masks = Some(TokenMaskRules {
+            allowed_tokens: None,
+            forbidden_tokens: Some(vec![3]),
+        });
+
+        let constraints = vec![low, high];
+        let ordered = merge_order(&constraints);
+        let mut report = ConstraintMergeReport::new(&ordered);
+        resolve_token_masks(&ordered, &mut report);
+
+        assert_eq!(report.order, vec!["style", "style#2"]);
+        assert_eq!(report.events[0].winner, "style");
+        assert_eq!(report.events[0].affected, vec!["style#2".to_string()]);
+    }
+}
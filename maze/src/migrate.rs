@@ -0,0 +1,242 @@
+//! Schema versioning and migration of constraints and responses
+//!
+//! [`ConstraintIR`] and [`CompiledConstraint`] carry a `schema_version` so
+//! IR or compiled schemas persisted by an older Maze are not silently read
+//! as the current format. Older versions are upgraded one step at a time by
+//! [`migrate_constraints`]; versions newer than
+//! [`CONSTRAINT_SCHEMA_VERSION`] were written by a newer Maze and are
+//! refused.
+//!
+//! [`GenerationResponse`] and [`Provenance`] records, which services store,
+//! are versioned the same way against [`RESPONSE_SCHEMA_VERSION`] and
+//! upgraded by [`migrate_response`] and [`migrate_provenance`].
+//! [`GenerationResponse::from_json`] and [`Provenance::from_json`] read a
+//! stored record of any supported version as the current type.
+//!
+//! [`CompiledConstraint`]: crate::CompiledConstraint
+
+use crate::ffi::{ConstraintIR, CONSTRAINT_SCHEMA_VERSION};
+use crate::{GenerationResponse, Provenance};
+
+/// Version of the [`GenerationResponse`] and [`Provenance`] record format
+///
+/// Records without a `schema_version` are version 1, written before
+/// records were versioned.
+pub const RESPONSE_SCHEMA_VERSION: u32 = 2;
+
+/// A constraint or compiled schema whose version this build cannot use
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SchemaVersionError {
+    /// Written by a newer Maze than this one
+    #[error(
+        "constraint '{name}' has schema version {found}, newer than supported version {supported}"
+    )]
+    Unsupported {
+        name: String,
+        found: u32,
+        supported: u32,
+    },
+
+    /// A compiled schema from another version; recompile it from its IR
+    #[error("compiled constraint has schema version {found}, expected {expected}; recompile it")]
+    Stale { found: u32, expected: u32 },
+
+    /// A response or provenance record written by a newer Maze
+    #[error(
+        "{record} record has schema version {found}, newer than supported version {supported}"
+    )]
+    UnsupportedRecord {
+        record: &'static str,
+        found: u32,
+        supported: u32,
+    },
+}
+
+/// Upgrade `constraints` to [`CONSTRAINT_SCHEMA_VERSION`]
+///
+/// Constraints already at the current version are returned unchanged.
+pub fn migrate_constraints(
+    constraints: Vec<ConstraintIR>,
+) -> Result<Vec<ConstraintIR>, SchemaVersionError> {
+    constraints.into_iter().map(migrate_constraint).collect()
+}
+
+/// Upgrade a single constraint to [`CONSTRAINT_SCHEMA_VERSION`]
+pub fn migrate_constraint(
+    mut constraint: ConstraintIR,
+) -> Result<ConstraintIR, SchemaVersionError> {
+    if constraint.schema_version > CONSTRAINT_SCHEMA_VERSION {
+        return Err(SchemaVersionError::Unsupported {
+            name: constraint.name,
+            found: constraint.schema_version,
+            supported: CONSTRAINT_SCHEMA_VERSION,
+        });
+    }
+
+    while constraint.schema_version < CONSTRAINT_SCHEMA_VERSION {
+        constraint = match constraint.schema_version {
+            // Unversioned IR has the same fields as version 1
+            0 => constraint,
+            _ => unreachable!("no migration step from every version below the current one"),
+        };
+        constraint.schema_version += 1;
+    }
+    Ok(constraint)
+}
+
+/// Version of response and provenance records that have none
+pub(crate) fn unversioned_record() -> u32 {
+    1
+}
+
+/// Upgrade a response record, and its provenance, to
+/// [`RESPONSE_SCHEMA_VERSION`]
+pub fn migrate_response(
+    mut response: GenerationResponse,
+) -> Result<GenerationResponse, SchemaVersionError> {
+    check_record_version("response", response.schema_version)?;
+    response.provenance = migrate_provenance(response.provenance)?;
+    while response.schema_version < RESPONSE_SCHEMA_VERSION {
+        response = match response.schema_version {
+            // Version 1 lacks the fields added since, which deserialize to
+            // their defaults; no record claims version 0
+            0 | 1 => response,
+            _ => unreachable!("no migration step from every version below the current one"),
+        };
+        response.schema_version += 1;
+    }
+    Ok(response)
+}
+
+/// Upgrade a provenance record to [`RESPONSE_SCHEMA_VERSION`]
+pub fn migrate_provenance(mut provenance: Provenance) -> Result<Provenance, SchemaVersionError> {
+    check_record_version("provenance", provenance.schema_version)?;
+    while provenance.schema_version < RESPONSE_SCHEMA_VERSION {
+        provenance = match provenance.schema_version {
+            // Version 1 has no intent, context, chain, or signature fields;
+            // they deserialize to their defaults
+            0 | 1 => provenance,
+            _ => unreachable!("no migration step from every version below the current one"),
+        };
+        provenance.schema_version += 1;
+    }
+    Ok(provenance)
+}
+
+fn check_record_version(record: &'static str, found: u32) -> Result<(), SchemaVersionError> {
+    if found > RESPONSE_SCHEMA_VERSION {
+        return Err(SchemaVersionError::UnsupportedRecord {
+            record,
+            found,
+            supported: RESPONSE_SCHEMA_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// Whether every constraint is already at the current version
+pub fn is_current(constraints: &[ConstraintIR]) -> bool {
+    constraints
+        .iter()
+        .all(|c| c.schema_version == CONSTRAINT_SCHEMA_VERSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unversioned() -> ConstraintIR {
+        serde_json::from_value(serde_json::json!({
+            "name": "digits",
+            "regex_patterns": [{"pattern": "\\d+", "flags": ""}],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_unversioned_ir_migrates_to_current() {
+        let legacy = unversioned();
+        assert_eq!(legacy.schema_version, 0);
+        assert!(!is_current(std::slice::from_ref(&legacy)));
+
+        let migrated = migrate_constraints(vec![legacy]).unwrap();
+        assert_eq!(migrated[0].schema_version, CONSTRAINT_SCHEMA_VERSION);
+        assert_eq!(migrated[0].regex_patterns[0].pattern, "\\d+");
+        assert!(is_current(&migrated));
+    }
+
+    /// A response as stored before records were versioned
+    const V1_RESPONSE: &str = r#"{
+        "code": "fn add(a: i32, b: i32) -> i32 { a + b }",
+        "provenance": {
+            "model": "mock-model",
+            "timestamp": 1700000000,
+            "constraints_applied": ["types"],
+            "original_intent": "add two numbers",
+            "parameters": {"max_tokens": 64, "temperature": 0.2}
+        },
+        "validation": {
+            "all_satisfied": true,
+            "satisfied": ["types"],
+            "violated": [],
+            "metadata": {}
+        },
+        "metadata": {
+            "tokens_generated": 12,
+            "generation_time_ms": 40,
+            "avg_token_time_us": 3333,
+            "constraint_compile_time_ms": 1
+        }
+    }"#;
+
+    #[test]
+    fn test_v1_response_reads_as_current() {
+        let response = GenerationResponse::from_json(V1_RESPONSE).unwrap();
+        assert_eq!(response.schema_version, RESPONSE_SCHEMA_VERSION);
+        assert_eq!(response.provenance.schema_version, RESPONSE_SCHEMA_VERSION);
+        assert_eq!(response.provenance.parameters["max_tokens"], 64);
+        assert!(response.provenance.context_included.is_empty());
+        assert!(response.provenance.signature.is_none());
+        assert_eq!(response.metadata.tokens_generated, 12);
+        assert_eq!(response.metadata.finish_reason, crate::FinishReason::Stop);
+        assert!(!response.metadata.cache_hit);
+
+        // Once upgraded, the record round-trips at the current version
+        let stored = serde_json::to_string(&response).unwrap();
+        assert!(stored.contains(&format!("\"schema_version\":{}", RESPONSE_SCHEMA_VERSION)));
+        let reread = GenerationResponse::from_json(&stored).unwrap();
+        assert_eq!(reread.code, response.code);
+    }
+
+    #[test]
+    fn test_newer_response_is_refused() {
+        let mut record: serde_json::Value = serde_json::from_str(V1_RESPONSE).unwrap();
+        record["provenance"]["schema_version"] = (RESPONSE_SCHEMA_VERSION + 1).into();
+        let err = GenerationResponse::from_json(&record.to_string()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<SchemaVersionError>(),
+            Some(&SchemaVersionError::UnsupportedRecord {
+                record: "provenance",
+                found: RESPONSE_SCHEMA_VERSION + 1,
+                supported: RESPONSE_SCHEMA_VERSION,
+            })
+        );
+
+        let provenance = record["provenance"].to_string();
+        assert!(Provenance::from_json(&provenance).is_err());
+    }
+
+    #[test]
+    fn test_newer_version_is_refused() {
+        let mut future = unversioned();
+        future.schema_version = CONSTRAINT_SCHEMA_VERSION + 1;
+        assert_eq!(
+            migrate_constraint(future).unwrap_err(),
+            SchemaVersionError::Unsupported {
+                name: "digits".to_string(),
+                found: CONSTRAINT_SCHEMA_VERSION + 1,
+                supported: CONSTRAINT_SCHEMA_VERSION,
+            }
+        );
+    }
+}
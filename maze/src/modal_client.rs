@@ -5,15 +5,25 @@
 use anyhow::{anyhow, Context, Result};
 use futures::stream::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
+use tracing::Instrument;
 use url::Url;
 
+use crate::candidate_scorer::{self, CandidateScorer, ConfidenceScorer, EnsembleCandidate};
+use crate::clock::{Clock, SystemClock};
 use crate::ffi::{ConstraintIR, HoleSpec};
+use crate::fim::{MultiHoleFill, MultiHoleRequest};
+use crate::health::{HealthCheckConfig, HealthStatus};
+use crate::inference::InferenceClient;
 use crate::model_router::{ModelEndpoint, ModelRouter, RoutingDecision};
+use crate::redaction::RedactionPolicy;
+use crate::replicas::{CircuitBreakerConfig, EndpointHealth, ReplicaEndpoint, ReplicaSet};
+use crate::sse::EventDecoder;
+use crate::trace::{TraceRecord, TraceSink};
 use crate::GenerationContext;
 
 /// Configuration for Modal inference service
@@ -22,9 +32,18 @@ pub struct ModalConfig {
     /// Modal endpoint URL
     pub endpoint_url: String,
 
-    /// API key for authentication
+    /// API key for authentication, sent as `auth_scheme` says
     pub api_key: Option<String>,
 
+    /// How requests authenticate; applies to generation, streaming, health
+    /// checks, and model listing alike
+    #[serde(default)]
+    pub auth_scheme: AuthScheme,
+
+    /// `User-Agent` sent with every request; `None` sends [`USER_AGENT`]
+    #[serde(default)]
+    pub user_agent: Option<String>,
+
     /// Request timeout in seconds
     pub timeout_secs: u64,
 
@@ -36,6 +55,118 @@ pub struct ModalConfig {
 
     /// Maximum retry attempts
     pub max_retries: usize,
+
+    /// How long `list_models` results are cached, in seconds (0 disables caching)
+    #[serde(default = "default_models_cache_ttl_secs")]
+    pub models_cache_ttl_secs: u64,
+
+    /// Redaction applied to prompts and response bodies in logs and errors
+    #[serde(default)]
+    pub redaction: RedactionPolicy,
+
+    /// Wire protocol used for generation requests
+    #[serde(default)]
+    pub transport: Transport,
+
+    /// Advertise and transparently decode gzip, brotli, and deflate
+    /// responses; turn off to see raw bodies when debugging
+    #[serde(default = "default_decompress_responses")]
+    pub decompress_responses: bool,
+
+    /// Abort a stream with [`StreamStalled`] when no chunk arrives for this
+    /// many seconds (`None` waits for the overall request timeout)
+    #[serde(default = "default_stream_idle_timeout_secs")]
+    pub stream_idle_timeout_secs: Option<u64>,
+
+    /// Replicas to spread generation requests across by weight; when
+    /// empty, everything goes to `endpoint_url`
+    #[serde(default)]
+    pub replicas: Vec<ReplicaEndpoint>,
+
+    /// When a failing replica is taken out of rotation
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+
+    /// Send a duplicate generation request when the first has not answered
+    /// after this many milliseconds, and use whichever answers first
+    ///
+    /// Both requests carry the same idempotency key, so a service that
+    /// deduplicates on it bills the generation once. `None` disables
+    /// hedging; streaming requests are never hedged.
+    #[serde(default)]
+    pub hedge_after_ms: Option<u64>,
+
+    /// Largest response body read, in bytes after decompression, before
+    /// failing with [`ResponseTooLarge`] (`None` reads bodies of any size)
+    ///
+    /// Bodies are read chunk by chunk whether or not the service sends a
+    /// `Content-Length`, so the cap also bounds chunked responses.
+    #[serde(default = "default_max_response_bytes")]
+    pub max_response_bytes: Option<usize>,
+
+    /// Which `/health` responses count as healthy
+    #[serde(default)]
+    pub health_check: HealthCheckConfig,
+}
+
+/// How requests authenticate with the inference service
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthScheme {
+    /// `Authorization: Bearer <api_key>`
+    #[default]
+    Bearer,
+
+    /// The API key in a header of its own, such as `x-api-key`
+    ApiKeyHeader {
+        /// Header name
+        name: String,
+    },
+
+    /// HTTP basic auth; `api_key` is not sent
+    Basic {
+        /// User name
+        user: String,
+
+        /// Password
+        pass: String,
+    },
+
+    /// These headers, verbatim; `api_key` is not sent
+    Custom(HashMap<String, String>),
+}
+
+/// Wire protocol between [`ModalClient`] and the inference service
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// HTTP with JSON bodies and Server-Sent Events for streaming
+    #[default]
+    Http,
+
+    /// gRPC (`proto/inference.proto`), with server-streaming RPC for
+    /// streaming; requires the `grpc` feature
+    ///
+    /// Only generation uses gRPC. Health checks and model listing still go
+    /// over HTTP to the same endpoint, and `model_info` falls back to a
+    /// name-only descriptor if the endpoint does not answer them.
+    Grpc,
+}
+
+fn default_models_cache_ttl_secs() -> u64 {
+    300
+}
+
+fn default_decompress_responses() -> bool {
+    true
+}
+
+fn default_stream_idle_timeout_secs() -> Option<u64> {
+    Some(60)
+}
+
+fn default_max_response_bytes() -> Option<usize> {
+    Some(DEFAULT_MAX_RESPONSE_BYTES)
 }
 
 impl ModalConfig {
@@ -57,10 +188,22 @@ impl ModalConfig {
         Ok(Self {
             endpoint_url,
             api_key,
+            auth_scheme: AuthScheme::default(),
+            user_agent: None,
             timeout_secs: 300,
             model,
             enable_retry: true,
             max_retries: 3,
+            models_cache_ttl_secs: default_models_cache_ttl_secs(),
+            redaction: RedactionPolicy::default(),
+            transport: Transport::default(),
+            decompress_responses: default_decompress_responses(),
+            stream_idle_timeout_secs: default_stream_idle_timeout_secs(),
+            replicas: Vec::new(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            hedge_after_ms: None,
+            max_response_bytes: default_max_response_bytes(),
+            health_check: HealthCheckConfig::default(),
         })
     }
 
@@ -69,10 +212,22 @@ impl ModalConfig {
         Self {
             endpoint_url,
             api_key: None,
+            auth_scheme: AuthScheme::default(),
+            user_agent: None,
             timeout_secs: 300,
             model,
             enable_retry: true,
             max_retries: 3,
+            models_cache_ttl_secs: default_models_cache_ttl_secs(),
+            redaction: RedactionPolicy::default(),
+            transport: Transport::default(),
+            decompress_responses: default_decompress_responses(),
+            stream_idle_timeout_secs: default_stream_idle_timeout_secs(),
+            replicas: Vec::new(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            hedge_after_ms: None,
+            max_response_bytes: default_max_response_bytes(),
+            health_check: HealthCheckConfig::default(),
         }
     }
 
@@ -82,13 +237,252 @@ impl ModalConfig {
         self
     }
 
+    /// Set how requests authenticate
+    pub fn with_auth_scheme(mut self, auth_scheme: AuthScheme) -> Self {
+        self.auth_scheme = auth_scheme;
+        self
+    }
+
+    /// Identify requests with `user_agent` instead of [`USER_AGENT`]
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Headers that authenticate a request, as (name, value) pairs
+    ///
+    /// Empty for the key-based schemes when no `api_key` is set.
+    pub fn auth_headers(&self) -> Vec<(String, String)> {
+        use base64::Engine;
+
+        match (&self.auth_scheme, &self.api_key) {
+            (AuthScheme::Bearer, Some(key)) => {
+                vec![("Authorization".to_string(), format!("Bearer {}", key))]
+            }
+            (AuthScheme::ApiKeyHeader { name }, Some(key)) => vec![(name.clone(), key.clone())],
+            (AuthScheme::Bearer | AuthScheme::ApiKeyHeader { .. }, None) => Vec::new(),
+            (AuthScheme::Basic { user, pass }, _) => {
+                let credentials =
+                    base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass));
+                vec![(
+                    "Authorization".to_string(),
+                    format!("Basic {}", credentials),
+                )]
+            }
+            (AuthScheme::Custom(headers), _) => headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
     /// Set timeout
     pub fn with_timeout(mut self, timeout_secs: u64) -> Self {
         self.timeout_secs = timeout_secs;
         self
     }
+
+    /// Set how long `list_models` results are cached
+    pub fn with_models_cache_ttl(mut self, ttl_secs: u64) -> Self {
+        self.models_cache_ttl_secs = ttl_secs;
+        self
+    }
+
+    /// Set how prompts and response bodies are redacted in logs and errors
+    pub fn with_redaction(mut self, redaction: RedactionPolicy) -> Self {
+        self.redaction = redaction;
+        self
+    }
+
+    /// Set the wire protocol used for generation requests
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Enable or disable compressed response decoding
+    pub fn with_decompression(mut self, enabled: bool) -> Self {
+        self.decompress_responses = enabled;
+        self
+    }
+
+    /// Spread generation requests across weighted replicas
+    pub fn with_replicas(mut self, replicas: Vec<ReplicaEndpoint>) -> Self {
+        self.replicas = replicas;
+        self
+    }
+
+    /// Hedge generation requests that have not answered after `delay_ms`
+    pub fn with_hedge_after_ms(mut self, delay_ms: u64) -> Self {
+        self.hedge_after_ms = Some(delay_ms);
+        self
+    }
+
+    /// Interpret `/health` responses according to `health_check`
+    pub fn with_health_check(mut self, health_check: HealthCheckConfig) -> Self {
+        self.health_check = health_check;
+        self
+    }
+
+    /// Cap response bodies at `max_bytes` (`None` removes the cap)
+    pub fn with_max_response_bytes(mut self, max_bytes: Option<usize>) -> Self {
+        self.max_response_bytes = max_bytes;
+        self
+    }
+
+    /// Set the inter-chunk timeout for streaming generation
+    pub fn with_stream_idle_timeout(mut self, idle_secs: Option<u64>) -> Self {
+        self.stream_idle_timeout_secs = idle_secs;
+        self
+    }
+}
+
+/// Capabilities of a model served by the inference service
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelInfo {
+    /// Model name
+    pub name: String,
+
+    /// Context window in tokens (prompt + output), if known
+    #[serde(default)]
+    pub context_window: Option<usize>,
+
+    /// Maximum tokens the model will generate per request, if known
+    #[serde(default)]
+    pub max_output_tokens: Option<usize>,
+
+    /// Whether grammar constraints are supported
+    #[serde(default = "default_supports_grammar")]
+    pub supports_grammar: bool,
+
+    /// Whether diffusion generation is supported
+    #[serde(default)]
+    pub supports_diffusion: bool,
+
+    /// Whether the model can fill several `<HOLE_n>` markers in one pass
+    #[serde(default)]
+    pub supports_fim: bool,
+
+    /// Lowest sampling temperature the model accepts, if known
+    #[serde(default)]
+    pub min_temperature: Option<f32>,
+
+    /// Highest sampling temperature the model accepts, if known; many
+    /// backends reject anything above 1.0
+    #[serde(default)]
+    pub max_temperature: Option<f32>,
+}
+
+fn default_supports_grammar() -> bool {
+    true
+}
+
+impl ModelInfo {
+    /// Descriptor for a model whose endpoint only reports its name
+    ///
+    /// Limits are unknown; grammar support is assumed since every model is
+    /// served behind llguidance.
+    pub fn from_name(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            context_window: None,
+            max_output_tokens: None,
+            supports_grammar: true,
+            supports_diffusion: false,
+            supports_fim: false,
+            min_temperature: None,
+            max_temperature: None,
+        }
+    }
+
+    /// `temperature` clamped to the range this model accepts
+    ///
+    /// Logs a warning when the temperature is out of range, since the
+    /// request then samples differently than asked. Unknown bounds do not
+    /// clamp.
+    pub fn clamp_temperature(&self, temperature: f32) -> f32 {
+        let mut effective = temperature;
+        if let Some(max) = self.max_temperature {
+            effective = effective.min(max);
+        }
+        if let Some(min) = self.min_temperature {
+            effective = effective.max(min);
+        }
+        if effective != temperature {
+            tracing::warn!(
+                model = %self.name,
+                requested = temperature,
+                effective,
+                "Temperature outside the model's range; clamped"
+            );
+        }
+        effective
+    }
+
+    /// Reject a request whose `max_tokens` exceeds this model's limits
+    pub fn check_max_tokens(&self, max_tokens: usize) -> Result<()> {
+        if let Some(limit) = self.max_output_tokens {
+            if max_tokens > limit {
+                return Err(anyhow!(
+                    "max_tokens {} exceeds the output limit of model {} ({})",
+                    max_tokens,
+                    self.name,
+                    limit
+                ));
+            }
+        }
+        if let Some(window) = self.context_window {
+            if max_tokens > window {
+                return Err(anyhow!(
+                    "max_tokens {} exceeds the context window of model {} ({})",
+                    max_tokens,
+                    self.name,
+                    window
+                ));
+            }
+        }
+        Ok(())
+    }
 }
 
+/// Entry in a `/models` response: a bare name or a full descriptor
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ModelEntry {
+    Name(String),
+    Info(ModelInfo),
+}
+
+impl From<ModelEntry> for ModelInfo {
+    fn from(entry: ModelEntry) -> Self {
+        match entry {
+            ModelEntry::Name(name) => ModelInfo::from_name(name),
+            ModelEntry::Info(info) => info,
+        }
+    }
+}
+
+/// Default [`ModalConfig::max_response_bytes`]: 16 MiB
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Header carrying [`InferenceRequest::idempotency_key`]
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Default `User-Agent` sent with every request to the inference service
+pub const USER_AGENT: &str = concat!("maze/", env!("CARGO_PKG_VERSION"));
+
+/// Header carrying the ID of one logical generation request
+///
+/// The ID is generated per `generate_constrained` or `generate_stream`
+/// call and shared by its retries and hedges. It is recorded on the
+/// `modal.generate` span, included in errors, and returned as
+/// [`InferenceResponse::request_id`], so both sides of a failed call can be
+/// matched up.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Cached `list_models` result with the time it was fetched
+type ModelsCache = Arc<Mutex<Option<(Instant, Vec<ModelInfo>)>>>;
+
 /// Client for Modal inference service
 #[derive(Clone)]
 pub struct ModalClient {
@@ -98,8 +492,21 @@ pub struct ModalClient {
     /// Configuration
     config: ModalConfig,
 
-    /// Base URL for API calls
-    base_url: Url,
+    /// Base URLs for API calls, with load-balancing and breaker state
+    replicas: ReplicaSet,
+
+    /// Cached `list_models` result and when it was fetched
+    models_cache: ModelsCache,
+
+    /// gRPC channel, when `config.transport` is [`Transport::Grpc`]
+    #[cfg(feature = "grpc")]
+    grpc: Option<crate::grpc::GrpcTransport>,
+
+    /// Records each generation, when tracing is on
+    trace_sink: Option<Arc<dyn TraceSink>>,
+
+    /// Time source for retry backoff and the models cache TTL
+    clock: Arc<dyn Clock>,
 }
 
 /// Request to Modal inference service
@@ -120,6 +527,111 @@ pub struct InferenceRequest {
     /// Optional context
     #[serde(skip_serializing_if = "Option::is_none")]
     pub context: Option<GenerationContext>,
+
+    /// Sampler seed for reproducible generation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+
+    /// Key the server uses to deduplicate retried requests
+    ///
+    /// When unset, [`ModalClient`] derives one per call with
+    /// [`InferenceRequest::call_key`] and reuses it for that call's retries.
+    /// Set a key to deduplicate across calls, e.g. when resubmitting after
+    /// a crash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+
+    /// Ask the service for per-token logprobs with top-k alternatives
+    ///
+    /// Off by default: logprobs add several objects per generated token to
+    /// the response, typically growing it 10-50x, and some backends slow
+    /// down sampling to compute the alternatives. Streaming generation does
+    /// not return logprobs.
+    #[serde(default)]
+    pub include_logprobs: bool,
+
+    /// Time allowed for the whole call in milliseconds, retries and backoff
+    /// included
+    ///
+    /// Overrides [`ModalConfig::timeout_secs`] for this request, in either
+    /// direction: an interactive caller can give up after 500ms while a
+    /// batch job waits longer than the default. Exceeding it fails with
+    /// [`RequestTimedOut`]. For streams it bounds the whole stream. Not
+    /// part of [`content_key`](Self::content_key).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+
+    /// Ask the service to report tokens the constrained decoder masks
+    ///
+    /// Streaming only: each masked token arrives as a chunk carrying a
+    /// [`ConstraintEvent`], interleaved with the text chunks. Off by default,
+    /// since the service may report a masked token for every position. Not
+    /// part of [`content_key`](Self::content_key), as it does not change
+    /// the output.
+    #[serde(default)]
+    pub include_constraint_events: bool,
+}
+
+impl InferenceRequest {
+    /// Request fields that [`Provenance`](crate::Provenance) records outside
+    /// its `parameters`: the prompt as `original_intent`, the constraints as
+    /// `constraints_hash`, the context as `context_included`, and the
+    /// idempotency key as itself
+    pub const RECORDED_SEPARATELY: [&'static str; 4] =
+        ["prompt", "constraints", "context", "idempotency_key"];
+
+    /// Parameters sent to the service, as they appear in the request body
+    ///
+    /// Every serialized field except [`RECORDED_SEPARATELY`]. Provenance
+    /// records exactly this map, so a field added to the request is audited
+    /// without touching the provenance code.
+    ///
+    /// [`RECORDED_SEPARATELY`]: Self::RECORDED_SEPARATELY
+    pub fn parameters(&self) -> Result<HashMap<String, serde_json::Value>> {
+        let serde_json::Value::Object(fields) =
+            serde_json::to_value(self).context("Failed to serialize inference request")?
+        else {
+            anyhow::bail!("inference request did not serialize to an object");
+        };
+        Ok(fields
+            .into_iter()
+            .filter(|(field, _)| !Self::RECORDED_SEPARATELY.contains(&field.as_str()))
+            .collect())
+    }
+
+    /// Stable hash of the request content, excluding `idempotency_key`
+    pub fn content_key(&self) -> String {
+        use std::hash::Hasher;
+        use xxhash_rust::xxh3::Xxh3;
+
+        // Serializing through Value sorts map keys, so the hash is order-independent
+        let content = serde_json::json!({
+            "prompt": self.prompt,
+            "constraints": self.constraints,
+            "max_tokens": self.max_tokens,
+            "temperature": self.temperature,
+            "context": self.context,
+            "seed": self.seed,
+            "include_logprobs": self.include_logprobs,
+        });
+        let mut hasher = Xxh3::new();
+        hasher.write(content.to_string().as_bytes());
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Idempotency key for one logical call of this request
+    ///
+    /// The [`content_key`](Self::content_key), with a random suffix unless
+    /// a `seed` makes the output reproducible: calling twice with the same
+    /// unseeded request asks for two samples, which the server must not
+    /// deduplicate into one.
+    pub fn call_key(&self) -> String {
+        let key = self.content_key();
+        match self.seed {
+            Some(_) => key,
+            None => format!("{}-{}", key, uuid::Uuid::new_v4().simple()),
+        }
+    }
 }
 
 /// Response from Modal inference service
@@ -136,30 +648,134 @@ pub struct InferenceResponse {
 
     /// Generation statistics
     pub stats: GenerationStats,
+
+    /// Timing of each attempt made by the client, including failed retries
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<AttemptTiming>,
+
+    /// Per-token logprobs, when requested with
+    /// [`InferenceRequest::include_logprobs`] and supported by the service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<Vec<TokenLogprob>>,
+
+    /// Confidence reported by the service, if any
+    ///
+    /// Only used by [`confidence`](Self::confidence) when there are no
+    /// logprobs.
+    #[serde(
+        default,
+        rename = "confidence",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub reported_confidence: Option<f32>,
+
+    /// ID the client sent in [`REQUEST_ID_HEADER`]; set by the client, not
+    /// the service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+
+    /// Why generation ended, if the service reports it; see
+    /// [`resolved_finish_reason`](Self::resolved_finish_reason)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<FinishReason>,
 }
 
 impl InferenceResponse {
-    /// Calculate confidence score based on generation stats
-    pub fn confidence(&self) -> f32 {
-        // Simple heuristic: higher confidence if generation was fast and had few constraint checks
-        // In production, this should use actual model confidence scores
-        if self.tokens_generated == 0 {
-            return 0.0;
+    /// Why generation ended, for a request limited to `max_tokens`
+    ///
+    /// The service-reported reason when there is one; otherwise
+    /// [`FinishReason::MaxTokens`] if `tokens_generated` reached the limit,
+    /// and [`FinishReason::Stop`] if not.
+    pub fn resolved_finish_reason(&self, max_tokens: usize) -> FinishReason {
+        match self.finish_reason {
+            Some(reason) => reason,
+            None if max_tokens > 0 && self.tokens_generated >= max_tokens => {
+                FinishReason::MaxTokens
+            }
+            None => FinishReason::Stop,
         }
+    }
 
-        let token_speed = self.stats.time_per_token_us as f32;
-        let constraint_overhead = self.stats.avg_constraint_check_us as f32;
-
-        // Normalize to 0-1 range (faster = higher confidence)
-        let speed_score = 1.0 - (token_speed / 10000.0).min(1.0);
-        let constraint_score = 1.0 - (constraint_overhead / 1000.0).min(1.0);
+    /// Confidence in the generation, in 0-1, or `None` if unknown
+    ///
+    /// Derived, in order of preference, from:
+    /// 1. `logprobs`: the geometric mean token probability,
+    ///    `exp(mean(logprob))`, so one unlikely token pulls the score down
+    ///    more than an arithmetic mean would
+    /// 2. `reported_confidence` from the service, clamped to 0-1
+    ///
+    /// Returns `None` when the response carries neither, rather than
+    /// guessing; callers choose how to treat unscored responses.
+    pub fn confidence(&self) -> Option<f32> {
+        if let Some(logprobs) = self.logprobs.as_ref().filter(|l| !l.is_empty()) {
+            let mean = logprobs.iter().map(|t| t.logprob).sum::<f32>() / logprobs.len() as f32;
+            return Some(mean.exp().clamp(0.0, 1.0));
+        }
 
-        (speed_score * 0.6 + constraint_score * 0.4).clamp(0.0, 1.0)
+        self.reported_confidence
+            .filter(|c| !c.is_nan())
+            .map(|c| c.clamp(0.0, 1.0))
     }
 }
 
+/// Log probability of one generated token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    /// Token text
+    pub token: String,
+
+    /// Natural log probability of the token
+    pub logprob: f32,
+
+    /// Most likely alternatives at this position, most likely first
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// An alternative token considered at a position
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopLogprob {
+    /// Token text
+    pub token: String,
+
+    /// Natural log probability of the token
+    pub logprob: f32,
+}
+
+/// Outcome of a single request attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttemptStatus {
+    /// The attempt returned a response
+    Success,
+
+    /// The attempt failed and was retried or gave up
+    Failed,
+}
+
+/// Timing of a single request attempt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttemptTiming {
+    /// Attempt index, starting at 1
+    pub attempt: usize,
+
+    /// Outcome of the attempt
+    pub status: AttemptStatus,
+
+    /// Time spent on the attempt in milliseconds
+    pub duration_ms: u64,
+
+    /// Backoff slept after the attempt before retrying, in milliseconds
+    pub backoff_ms: u64,
+
+    /// Whether the response came from a hedge request (see
+    /// [`ModalConfig::hedge_after_ms`])
+    #[serde(default)]
+    pub hedged: bool,
+}
+
 /// Statistics from generation
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct GenerationStats {
     /// Total generation time in milliseconds
     pub total_time_ms: u64,
@@ -172,6 +788,88 @@ pub struct GenerationStats {
 
     /// Average constraint check time in microseconds
     pub avg_constraint_check_us: u64,
+
+    /// Checks and time per constraint kind (`grammar`, `regex`,
+    /// `token_mask`, ...); empty when the backend reports only the totals
+    #[serde(default)]
+    pub by_kind: BTreeMap<String, ConstraintKindStats>,
+}
+
+/// Enforcement cost of one kind of constraint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstraintKindStats {
+    /// Number of checks
+    #[serde(default)]
+    pub checks: usize,
+
+    /// Total check time in microseconds
+    #[serde(default)]
+    pub total_us: u64,
+}
+
+impl ConstraintKindStats {
+    /// Average time per check in microseconds
+    pub fn avg_us(&self) -> u64 {
+        if self.checks == 0 {
+            0
+        } else {
+            self.total_us / self.checks as u64
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.checks += other.checks;
+        self.total_us += other.total_us;
+    }
+}
+
+/// Constraint enforcement cost summed over many responses
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstraintCostSummary {
+    /// Constraint checks across all responses
+    pub constraint_checks: usize,
+
+    /// Check time across all responses in microseconds, from each
+    /// response's `avg_constraint_check_us`
+    pub constraint_check_us: u64,
+
+    /// Per-kind totals from responses that broke them down
+    pub by_kind: BTreeMap<String, ConstraintKindStats>,
+
+    /// Responses recorded
+    pub responses: usize,
+
+    /// Responses that reported only the totals, so are missing from `by_kind`
+    pub aggregate_only: usize,
+}
+
+impl ConstraintCostSummary {
+    /// Add one response's stats
+    pub fn record(&mut self, stats: &GenerationStats) {
+        self.responses += 1;
+        self.constraint_checks += stats.constraint_checks;
+        self.constraint_check_us += stats.constraint_checks as u64 * stats.avg_constraint_check_us;
+        if stats.by_kind.is_empty() {
+            if stats.constraint_checks > 0 {
+                self.aggregate_only += 1;
+            }
+            return;
+        }
+        for (kind, kind_stats) in &stats.by_kind {
+            self.by_kind
+                .entry(kind.clone())
+                .or_default()
+                .add(kind_stats);
+        }
+    }
+
+    /// The kind with the most total check time, if any was broken down
+    pub fn most_expensive(&self) -> Option<(&str, &ConstraintKindStats)> {
+        self.by_kind
+            .iter()
+            .max_by_key(|(_, stats)| stats.total_us)
+            .map(|(kind, stats)| (kind.as_str(), stats))
+    }
 }
 
 /// A chunk of streaming generation output
@@ -188,44 +886,412 @@ pub struct StreamChunk {
 
     /// Generation timestamp in milliseconds
     pub timestamp_ms: u64,
+
+    /// Why the stream ended; set on the final chunk when the service
+    /// reports it or the stream was cut short, `None` otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<FinishReason>,
+
+    /// Model that produced the output; set on the final chunk of
+    /// [`EnsembleClient::generate_stream_routed`] streams
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    /// A token the constrained decoder masked; set, with empty `text`, on
+    /// chunks requested with [`InferenceRequest::include_constraint_events`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub constraint_event: Option<ConstraintEvent>,
+
+    /// Set on the first chunk after a mid-stream fallback: output received
+    /// before it is discarded and this chunk starts the text over
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub restarted: bool,
+}
+
+/// A token the model wanted that a constraint masked during decoding
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConstraintEvent {
+    /// The masked token
+    pub token: String,
+
+    /// Position of the token in the generated sequence
+    pub position: usize,
+
+    /// Name of the constraint that masked it
+    pub constraint: String,
+}
+
+impl std::fmt::Display for ConstraintEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "masked token {:?} at position {} because of constraint {}",
+            self.token, self.position, self.constraint
+        )
+    }
+}
+
+/// Why a generation ended
+///
+/// Deserializes from the reasons inference services commonly report
+/// (`stop`, `length`, `stop_sequence`, ...); unrecognized reasons count as
+/// [`Stop`](Self::Stop).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", from = "String")]
+pub enum FinishReason {
+    /// The model finished on its own
+    #[default]
+    Stop,
+
+    /// The output hit a stop sequence
+    StopSequence,
+
+    /// The generation reached `max_tokens`; the output may be incomplete
+    MaxTokens,
+
+    /// The constraint allowed no further tokens
+    Constraint,
+
+    /// A stream deadline cut the generation off; the output is partial
+    Truncated,
+
+    /// The output exceeded its [`OutputLimit`](crate::OutputLimit) and was
+    /// cut off or rejected
+    Runaway,
+}
+
+impl FinishReason {
+    /// Interpret a finish reason reported by an inference service
+    pub fn parse(reason: &str) -> Self {
+        match reason.trim().to_ascii_lowercase().as_str() {
+            "stop" | "eos" | "end_turn" | "" => Self::Stop,
+            "stop_sequence" | "stop_string" => Self::StopSequence,
+            "length" | "max_tokens" | "max_length" => Self::MaxTokens,
+            "constraint" | "grammar" => Self::Constraint,
+            "truncated" | "deadline" => Self::Truncated,
+            "runaway" => Self::Runaway,
+            other => {
+                tracing::debug!("Unrecognized finish reason '{}', treating as stop", other);
+                Self::Stop
+            }
+        }
+    }
+
+    /// Whether the output may have been cut off before it was complete
+    pub fn is_incomplete(self) -> bool {
+        matches!(self, Self::MaxTokens | Self::Truncated | Self::Runaway)
+    }
+}
+
+impl From<String> for FinishReason {
+    fn from(reason: String) -> Self {
+        Self::parse(&reason)
+    }
 }
 
 /// Type alias for the streaming generation result
 pub type StreamingResult = Pin<Box<dyn Stream<Item = Result<StreamChunk>> + Send>>;
 
-/// Server-Sent Event data from Modal streaming endpoint
-#[derive(Debug, Deserialize)]
-struct SSEData {
-    token: Option<String>,
-    done: Option<bool>,
-    #[allow(dead_code)]
-    error: Option<String>,
+/// A stream stayed open but produced no chunk within the idle timeout
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("stream stalled: no new tokens for {idle_secs}s after {chunks_received} chunks")]
+pub struct StreamStalled {
+    /// Idle timeout that elapsed, in seconds
+    pub idle_secs: u64,
+
+    /// Chunks received before the stall
+    pub chunks_received: usize,
+}
+
+/// A request did not finish within its [`InferenceRequest::timeout_ms`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("request timed out after {timeout_ms}ms ({attempts} attempts)")]
+pub struct RequestTimedOut {
+    /// Time allowed for the request, in milliseconds
+    pub timeout_ms: u64,
+
+    /// Attempts started before the time ran out
+    pub attempts: usize,
+}
+
+/// A response body exceeded [`ModalConfig::max_response_bytes`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("response body exceeds the {limit}-byte limit")]
+pub struct ResponseTooLarge {
+    /// The configured limit, in bytes
+    pub limit: usize,
+}
+
+/// The inference service answered a generation request with an error status
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Modal inference failed with status {}: {message}", status_line(*.status))]
+pub struct ServiceError {
+    /// HTTP status code
+    pub status: u16,
+
+    /// Response body, redacted per the client's policy
+    pub message: String,
+}
+
+impl ServiceError {
+    /// Whether the service is shedding load: 429 Too Many Requests or
+    /// 503 Service Unavailable
+    pub fn is_overload(&self) -> bool {
+        matches!(self.status, 429 | 503)
+    }
+}
+
+/// `status` with its canonical reason, e.g. `429 Too Many Requests`
+fn status_line(status: u16) -> String {
+    reqwest::StatusCode::from_u16(status).map_or_else(|_| status.to_string(), |s| s.to_string())
+}
+
+/// Error code in a 4xx response body from a service that cannot enforce the
+/// request's constraints
+pub const CONSTRAINTS_UNSUPPORTED_CODE: &str = "constraints_unsupported";
+
+/// A model cannot enforce constraints, as reported by its
+/// [`ModelInfo::supports_grammar`] or by the service rejecting the request
+/// with [`CONSTRAINTS_UNSUPPORTED_CODE`]
+///
+/// Not retried: the same request would be rejected again.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("model {model} does not support constrained decoding: {reason}")]
+pub struct ConstraintsUnsupported {
+    /// Model that cannot enforce constraints
+    pub model: String,
+
+    /// What reported it
+    pub reason: String,
+}
+
+/// Overall time limit for a streaming generation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamDeadline {
+    /// Time allowed from the start of the stream, in milliseconds
+    pub after_ms: u64,
+
+    /// What the caller gets when the deadline passes
+    #[serde(default)]
+    pub policy: DeadlinePolicy,
+}
+
+/// Outcome of a stream that runs past its [`StreamDeadline`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadlinePolicy {
+    /// End the stream with a final chunk whose `finish_reason` is
+    /// [`FinishReason::Truncated`], keeping what was generated
+    #[default]
+    Partial,
+
+    /// End the stream with a [`StreamDeadlineExceeded`] error
+    Error,
+}
+
+/// A stream ran past its deadline under [`DeadlinePolicy::Error`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("stream deadline of {deadline_ms}ms exceeded after {chunks_received} chunks")]
+pub struct StreamDeadlineExceeded {
+    /// Deadline that passed, in milliseconds
+    pub deadline_ms: u64,
+
+    /// Chunks received before the deadline
+    pub chunks_received: usize,
+}
+
+/// A fresh ID for [`REQUEST_ID_HEADER`]
+fn new_request_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Cut `stream` off at `deadline`, finishing it as the policy says
+///
+/// Only the deadline is handled here; errors from the stream itself pass
+/// through unchanged, so callers can tell a cutoff from a transport failure.
+pub(crate) fn with_deadline(stream: StreamingResult, deadline: StreamDeadline) -> StreamingResult {
+    let at = tokio::time::Instant::now() + Duration::from_millis(deadline.after_ms);
+    let limited = futures::stream::unfold(Some((stream, 0usize)), move |state| async move {
+        let (mut stream, received) = state?;
+        match tokio::time::timeout_at(at, stream.next()).await {
+            Ok(Some(item)) => Some((item, Some((stream, received + 1)))),
+            Ok(None) => None,
+            Err(_) => {
+                tracing::warn!(
+                    deadline_ms = deadline.after_ms,
+                    chunks_received = received,
+                    "Streaming generation reached its deadline"
+                );
+                let item = match deadline.policy {
+                    DeadlinePolicy::Partial => Ok(StreamChunk {
+                        text: String::new(),
+                        is_final: true,
+                        token_index: received,
+                        timestamp_ms: deadline.after_ms,
+                        finish_reason: Some(FinishReason::Truncated),
+                        model: None,
+                        constraint_event: None,
+                        restarted: false,
+                    }),
+                    DeadlinePolicy::Error => Err(StreamDeadlineExceeded {
+                        deadline_ms: deadline.after_ms,
+                        chunks_received: received,
+                    }
+                    .into()),
+                };
+                Some((item, None))
+            }
+        }
+    });
+    Box::pin(limited)
+}
+
+/// End `stream` with a [`StreamStalled`] error if it goes quiet for `idle`
+fn with_idle_timeout(stream: StreamingResult, idle: Duration) -> StreamingResult {
+    let watched = futures::stream::unfold(Some((stream, 0usize)), move |state| async move {
+        let (mut stream, received) = state?;
+        match tokio::time::timeout(idle, stream.next()).await {
+            Ok(Some(item)) => Some((item, Some((stream, received + 1)))),
+            Ok(None) => None,
+            Err(_) => {
+                tracing::warn!(
+                    idle_secs = idle.as_secs(),
+                    chunks_received = received,
+                    "Streaming generation stalled"
+                );
+                let stalled = StreamStalled {
+                    idle_secs: idle.as_secs(),
+                    chunks_received: received,
+                };
+                Some((Err(stalled.into()), None))
+            }
+        }
+    });
+    Box::pin(watched)
 }
 
 impl ModalClient {
     /// Create a new Modal client
     pub fn new(config: ModalConfig) -> Result<Self> {
-        // Parse and validate endpoint URL
-        let base_url = Url::parse(&config.endpoint_url).context("Invalid Modal endpoint URL")?;
+        // Parse and validate endpoint URLs
+        let endpoints = if config.replicas.is_empty() {
+            vec![(
+                Url::parse(&config.endpoint_url).context("Invalid Modal endpoint URL")?,
+                1,
+            )]
+        } else {
+            config
+                .replicas
+                .iter()
+                .map(|r| {
+                    Url::parse(&r.url)
+                        .with_context(|| format!("Invalid replica URL {}", r.url))
+                        .map(|url| (url, r.weight))
+                })
+                .collect::<Result<_>>()?
+        };
+        if config.transport == Transport::Grpc && !config.replicas.is_empty() {
+            return Err(anyhow!(
+                "replicas are only supported with the HTTP transport"
+            ));
+        }
+        let replicas = ReplicaSet::new(endpoints, config.circuit_breaker);
 
-        // Build HTTP client with timeout
+        // Build HTTP client with timeout. With decompression on, reqwest sends
+        // Accept-Encoding and decodes the body (and strips Content-Encoding)
+        // before we parse it.
         let client = reqwest::Client::builder()
             .timeout(Duration::from_secs(config.timeout_secs))
+            .gzip(config.decompress_responses)
+            .brotli(config.decompress_responses)
+            .deflate(config.decompress_responses)
             .build()
             .context("Failed to build HTTP client")?;
 
+        #[cfg(feature = "grpc")]
+        let grpc = match config.transport {
+            Transport::Http => None,
+            Transport::Grpc => Some(crate::grpc::GrpcTransport::new(&config)?),
+        };
+        #[cfg(not(feature = "grpc"))]
+        if config.transport == Transport::Grpc {
+            return Err(anyhow!(
+                "gRPC transport requires maze to be built with the `grpc` feature"
+            ));
+        }
+
         Ok(Self {
             client,
             config,
-            base_url,
+            replicas,
+            models_cache: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "grpc")]
+            grpc,
+            trace_sink: None,
+            clock: Arc::new(SystemClock),
         })
     }
 
+    /// Record each `generate_constrained` call, redacted per
+    /// `config.redaction`, to `sink`
+    pub fn with_trace_sink(mut self, sink: impl TraceSink + 'static) -> Self {
+        self.trace_sink = Some(Arc::new(sink));
+        self
+    }
+
+    /// Read the time and sleep between retries through `clock`
+    ///
+    /// Tests pass a [`MockClock`](crate::MockClock) to check backoff delays
+    /// and the models cache TTL without waiting.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Configuration this client was created with
+    pub fn config(&self) -> &ModalConfig {
+        &self.config
+    }
+
+    /// Circuit-breaker state and request counts for each replica
+    ///
+    /// A client without `replicas` reports its `endpoint_url` alone.
+    pub fn endpoint_health(&self) -> Vec<EndpointHealth> {
+        self.replicas.health()
+    }
+
     /// Generate code with constraints
     pub async fn generate_constrained(
         &self,
         request: InferenceRequest,
     ) -> Result<InferenceResponse> {
+        let Some(ref sink) = self.trace_sink else {
+            return self.generate_untraced(request).await;
+        };
+        let started = Instant::now();
+        let result = self.generate_untraced(request.clone()).await;
+        sink.record(&TraceRecord::new(
+            &self.config.model,
+            &request,
+            &result,
+            started,
+            self.config.redaction,
+        ));
+        result
+    }
+
+    /// [`generate_constrained`](Self::generate_constrained), without tracing
+    #[tracing::instrument(
+        name = "modal.generate",
+        skip_all,
+        fields(
+            model = %self.config.model,
+            max_tokens = request.max_tokens,
+            temperature = request.temperature,
+            request_id = tracing::field::Empty,
+        )
+    )]
+    async fn generate_untraced(&self, request: InferenceRequest) -> Result<InferenceResponse> {
         let mut attempts = 0;
         let max_attempts = if self.config.enable_retry {
             self.config.max_retries
@@ -233,33 +1299,158 @@ impl ModalClient {
             1
         };
 
+        let mut timings = Vec::new();
+
+        // One key for every attempt, so the server can tell retries apart
+        // from new requests
+        let idempotency_key = request
+            .idempotency_key
+            .clone()
+            .unwrap_or_else(|| request.call_key());
+        let request_id = new_request_id();
+        tracing::Span::current().record("request_id", request_id.as_str());
+
+        // A per-request timeout bounds every attempt and backoff together
+        let deadline = request
+            .timeout_ms
+            .map(|ms| tokio::time::Instant::now() + Duration::from_millis(ms));
+        let timed_out = |attempts| RequestTimedOut {
+            timeout_ms: request.timeout_ms.unwrap_or_default(),
+            attempts,
+        };
+
         loop {
             attempts += 1;
 
-            match self.generate_internal(&request).await {
-                Ok(response) => return Ok(response),
+            let attempt_span = tracing::debug_span!("modal.attempt", attempt = attempts);
+            let attempt_start = std::time::Instant::now();
+            let attempt = self
+                .generate_hedged(&request, &idempotency_key, &request_id)
+                .instrument(attempt_span);
+            let result = match deadline {
+                Some(deadline) => match tokio::time::timeout_at(deadline, attempt).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        return Err(timed_out(attempts))
+                            .context(format!("Gave up on request {}", request_id))
+                    }
+                },
+                None => attempt.await,
+            };
+            let duration_ms = attempt_start.elapsed().as_millis() as u64;
+
+            match result {
+                Ok((mut response, hedged)) => {
+                    timings.push(AttemptTiming {
+                        attempt: attempts,
+                        status: AttemptStatus::Success,
+                        duration_ms,
+                        backoff_ms: 0,
+                        hedged,
+                    });
+                    response.attempts = timings;
+                    response.request_id = Some(request_id);
+                    return Ok(response);
+                }
                 Err(e) => {
-                    if attempts >= max_attempts {
-                        return Err(e).context(format!("Failed after {} attempts", attempts));
+                    // Neither goes away by asking again
+                    if attempts >= max_attempts
+                        || e.is::<ConstraintsUnsupported>()
+                        || e.is::<ResponseTooLarge>()
+                    {
+                        return Err(e).context(format!(
+                            "Failed after {} attempts (request {})",
+                            attempts, request_id
+                        ));
                     }
 
                     tracing::warn!("Generation attempt {} failed: {}. Retrying...", attempts, e);
 
                     // Exponential backoff
                     let backoff = Duration::from_millis(100 * 2_u64.pow(attempts as u32 - 1));
-                    tokio::time::sleep(backoff).await;
+                    if deadline
+                        .is_some_and(|deadline| tokio::time::Instant::now() + backoff >= deadline)
+                    {
+                        return Err(e)
+                            .context(timed_out(attempts))
+                            .context(format!("Gave up on request {}", request_id));
+                    }
+                    timings.push(AttemptTiming {
+                        attempt: attempts,
+                        status: AttemptStatus::Failed,
+                        duration_ms,
+                        backoff_ms: backoff.as_millis() as u64,
+                        hedged: false,
+                    });
+                    self.clock.sleep(backoff).await;
                 }
             }
         }
     }
 
+    /// One generation attempt, hedged if `hedge_after_ms` is set
+    ///
+    /// Returns the response and whether the hedge request produced it. The
+    /// slower request is cancelled by dropping it. If the first request to
+    /// finish fails, the other is awaited instead.
+    async fn generate_hedged(
+        &self,
+        request: &InferenceRequest,
+        idempotency_key: &str,
+        request_id: &str,
+    ) -> Result<(InferenceResponse, bool)> {
+        let primary = self.generate_internal(request, idempotency_key, request_id);
+        let Some(delay) = self.config.hedge_after_ms.map(Duration::from_millis) else {
+            return primary.await.map(|response| (response, false));
+        };
+        tokio::pin!(primary);
+
+        tokio::select! {
+            result = &mut primary => return result.map(|response| (response, false)),
+            _ = tokio::time::sleep(delay) => {}
+        }
+        tracing::debug!(
+            "No response after {}ms, sending hedge request",
+            delay.as_millis()
+        );
+        let hedge = self.generate_internal(request, idempotency_key, request_id);
+        tokio::pin!(hedge);
+
+        tokio::select! {
+            result = &mut primary => match result {
+                Ok(response) => Ok((response, false)),
+                Err(e) => {
+                    tracing::warn!("Request failed, waiting for its hedge: {}", e);
+                    hedge.await.map(|response| (response, true))
+                }
+            },
+            result = &mut hedge => match result {
+                Ok(response) => Ok((response, true)),
+                Err(e) => {
+                    tracing::warn!("Hedge request failed, waiting for the original: {}", e);
+                    primary.await.map(|response| (response, false))
+                }
+            },
+        }
+    }
+
     /// Internal generation method
-    async fn generate_internal(&self, request: &InferenceRequest) -> Result<InferenceResponse> {
-        // Build request URL
-        let url = self
-            .base_url
-            .join("/generate")
-            .context("Failed to build request URL")?;
+    async fn generate_internal(
+        &self,
+        request: &InferenceRequest,
+        idempotency_key: &str,
+        request_id: &str,
+    ) -> Result<InferenceResponse> {
+        #[cfg(feature = "grpc")]
+        if let Some(ref grpc) = self.grpc {
+            tracing::debug!(
+                "Sending gRPC generation request to Modal: {:?}",
+                self.config.redaction.apply(&request.prompt)
+            );
+            return grpc
+                .generate(request, Some(idempotency_key), request_id)
+                .await;
+        }
 
         // Build request body
         let body = serde_json::json!({
@@ -269,48 +1460,88 @@ impl ModalClient {
             "temperature": request.temperature,
             "model": self.config.model,
             "context": request.context,
+            "seed": request.seed,
+            "logprobs": request.include_logprobs,
         });
 
-        // Build HTTP request
-        let mut http_request = self
-            .client
-            .post(url)
-            .header("Content-Type", "application/json")
-            .json(&body);
-
-        // Add API key if present
-        if let Some(ref api_key) = self.config.api_key {
-            http_request = http_request.header("Authorization", format!("Bearer {}", api_key));
-        }
+        // Try replicas in weighted order, moving on when one is unreachable
+        let mut tried = Vec::new();
+        let (replica, response) = loop {
+            let (replica, base_url) = self
+                .replicas
+                .pick(&tried)
+                .context("No inference replica available")?;
+            let url = base_url
+                .join("/generate")
+                .context("Failed to build request URL")?;
+
+            let mut http_request = self
+                .http_request(reqwest::Method::POST, url, Some(&body))
+                .header(IDEMPOTENCY_KEY_HEADER, idempotency_key)
+                .header(REQUEST_ID_HEADER, request_id);
+            if let Some(ms) = request.timeout_ms {
+                // Replaces the client-wide timeout, which may be shorter
+                http_request = http_request.timeout(Duration::from_millis(ms));
+            }
 
-        // Send request
-        tracing::debug!("Sending generation request to Modal: {:?}", request.prompt);
-        let response = http_request
-            .send()
-            .await
-            .context("Failed to send request to Modal")?;
+            // Send request
+            tracing::debug!(
+                "Sending generation request to Modal: {:?}",
+                self.config.redaction.apply(&request.prompt)
+            );
+            match http_request.send().await {
+                Ok(response) => break (replica, response),
+                Err(e) => {
+                    self.replicas.record_failure(replica);
+                    tried.push(replica);
+                    if !e.is_connect() || tried.len() >= self.replicas.len() {
+                        return Err(e).context("Failed to send request to Modal");
+                    }
+                    tracing::warn!("Replica {} unreachable, failing over: {}", base_url, e);
+                }
+            }
+        };
 
         // Check response status
         let status = response.status();
         if !status.is_success() {
-            let error_text = response
-                .text()
+            // A 4xx is the request's fault, not the replica's
+            if status.is_server_error() {
+                self.replicas.record_failure(replica);
+            } else {
+                self.replicas.record_success(replica);
+            }
+            let error_text = self
+                .read_text(response)
                 .await
                 .unwrap_or_else(|_| "Unable to read error response".to_string());
-            return Err(anyhow!(
-                "Modal inference failed with status {}: {}",
-                status,
-                error_text
-            ));
+            if status.is_client_error() && error_text.contains(CONSTRAINTS_UNSUPPORTED_CODE) {
+                return Err(ConstraintsUnsupported {
+                    model: self.config.model.clone(),
+                    reason: format!(
+                        "service rejected the request with status {}: {}",
+                        status,
+                        self.config.redaction.apply(&error_text)
+                    ),
+                }
+                .into());
+            }
+            return Err(ServiceError {
+                status: status.as_u16(),
+                message: self.config.redaction.apply(&error_text).into_owned(),
+            }
+            .into());
         }
+        self.replicas.record_success(replica);
 
         // Parse response
-        let inference_response: InferenceResponse = response
-            .json()
-            .await
-            .context("Failed to parse Modal response")?;
+        let body = self.read_body(response).await?;
+        let inference_response: InferenceResponse =
+            serde_json::from_slice(&body).context("Failed to parse Modal response")?;
 
         tracing::debug!(
+            tokens_generated = inference_response.tokens_generated,
+            total_time_ms = inference_response.stats.total_time_ms,
             "Generated {} tokens in {}ms",
             inference_response.tokens_generated,
             inference_response.stats.total_time_ms
@@ -319,33 +1550,148 @@ impl ModalClient {
         Ok(inference_response)
     }
 
-    /// Health check for Modal service
-    pub async fn health_check(&self) -> Result<bool> {
-        let url = self
-            .base_url
-            .join("/health")
-            .context("Failed to build health check URL")?;
+    /// Read a response body to the end, chunk by chunk
+    ///
+    /// Nothing is sized from `Content-Length`, which chunked responses do
+    /// not send and a misbehaving server may get wrong; it only fails a body
+    /// that announces more than [`ModalConfig::max_response_bytes`] early.
+    /// Any body that grows past the cap fails with [`ResponseTooLarge`].
+    async fn read_body(&self, mut response: reqwest::Response) -> Result<Vec<u8>> {
+        let limit = self.config.max_response_bytes;
+        let too_large = |limit| anyhow::Error::new(ResponseTooLarge { limit });
+        if let (Some(limit), Some(length)) = (limit, response.content_length()) {
+            if length > limit as u64 {
+                return Err(too_large(limit));
+            }
+        }
 
-        let response = self
-            .client
-            .get(url)
-            .send()
+        let mut body = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
             .await
-            .context("Health check request failed")?;
+            .context("Failed to read Modal response body")?
+        {
+            if let Some(limit) = limit {
+                if body.len() + chunk.len() > limit {
+                    return Err(too_large(limit));
+                }
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+
+    /// [`read_body`](Self::read_body) as text, replacing invalid UTF-8
+    async fn read_text(&self, response: reqwest::Response) -> Result<String> {
+        let body = self.read_body(response).await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Start a request to the inference service
+    ///
+    /// Every request goes through here so that all of them carry the
+    /// configured authentication and the user agent; a JSON `body` also
+    /// sets the content type.
+    fn http_request(
+        &self,
+        method: reqwest::Method,
+        url: Url,
+        body: Option<&serde_json::Value>,
+    ) -> reqwest::RequestBuilder {
+        let user_agent = self.config.user_agent.as_deref().unwrap_or(USER_AGENT);
+        let mut request = self
+            .client
+            .request(method, url)
+            .header(reqwest::header::USER_AGENT, user_agent);
+        for (name, value) in self.config.auth_headers() {
+            request = request.header(name, value);
+        }
+        if let Some(body) = body {
+            request = request
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .json(body);
+        }
+        request
+    }
+
+    /// Health check for Modal service
+    ///
+    /// Probes `GET /health` and interprets the answer per
+    /// [`ModalConfig::health_check`]. With several replicas, reports the
+    /// best answer: healthy if any of them is.
+    pub async fn health_check(&self) -> Result<HealthStatus> {
+        let mut best: Option<HealthStatus> = None;
+        let mut last_error = None;
+        for endpoint in self.replicas.health() {
+            let url = Url::parse(&endpoint.url)
+                .and_then(|base| base.join("/health"))
+                .context("Failed to build health check URL")?;
+
+            let response = match self
+                .http_request(reqwest::Method::GET, url, None)
+                .send()
+                .await
+            {
+                Ok(response) => response,
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            let code = response.status().as_u16();
+            let status = match self.read_text(response).await {
+                Ok(body) => self.config.health_check.evaluate(code, body),
+                Err(e) => HealthStatus::Unknown {
+                    reason: format!("failed to read health response: {:#}", e),
+                    body: None,
+                },
+            };
+            if matches!(status, HealthStatus::Healthy { .. }) {
+                return Ok(status);
+            }
+            if best.as_ref().is_none_or(|best| status.rank() > best.rank()) {
+                best = Some(status);
+            }
+        }
 
-        Ok(response.status().is_success())
+        // An unreachable single endpoint is an error; with replicas, the
+        // per-replica picture is in `endpoint_health`
+        match (best, last_error) {
+            (Some(status), _) => Ok(status),
+            (None, Some(e)) if self.replicas.len() == 1 => {
+                Err(e).context("Health check request failed")
+            }
+            (None, e) => Ok(HealthStatus::Unknown {
+                reason: match e {
+                    Some(e) => format!("no replica answered: {}", e),
+                    None => "no replica to probe".to_string(),
+                },
+                body: None,
+            }),
+        }
     }
 
-    /// Get available models from Modal service
-    pub async fn list_models(&self) -> Result<Vec<String>> {
+    /// Get available models and their capabilities from Modal service
+    ///
+    /// Results are cached for `models_cache_ttl_secs`. Endpoints that only
+    /// return model names yield name-only descriptors (see
+    /// [`ModelInfo::from_name`]).
+    pub async fn list_models(&self) -> Result<Vec<ModelInfo>> {
+        let ttl = Duration::from_secs(self.config.models_cache_ttl_secs);
+        if let Some((fetched_at, models)) = self.models_cache.lock().await.as_ref() {
+            if self.clock.now().saturating_duration_since(*fetched_at) < ttl {
+                return Ok(models.clone());
+            }
+        }
+
         let url = self
-            .base_url
+            .replicas
+            .primary()
             .join("/models")
             .context("Failed to build models URL")?;
 
         let response = self
-            .client
-            .get(url)
+            .http_request(reqwest::Method::GET, url, None)
             .send()
             .await
             .context("Models request failed")?;
@@ -357,18 +1703,60 @@ impl ModalClient {
             ));
         }
 
-        let models: Vec<String> = response
-            .json()
-            .await
-            .context("Failed to parse models response")?;
+        let body = self.read_body(response).await?;
+        let entries: Vec<ModelEntry> =
+            serde_json::from_slice(&body).context("Failed to parse models response")?;
+        let models: Vec<ModelInfo> = entries.into_iter().map(ModelInfo::from).collect();
+
+        if !ttl.is_zero() {
+            *self.models_cache.lock().await = Some((self.clock.now(), models.clone()));
+        }
 
         Ok(models)
     }
 
+    /// Drop the cached `list_models` result so the next call refetches
+    pub async fn invalidate_models_cache(&self) {
+        *self.models_cache.lock().await = None;
+    }
+
+    /// Capabilities of the configured model
+    ///
+    /// Falls back to a name-only descriptor when the service does not list
+    /// the model or has no `/models` endpoint. The fallback is cached like a
+    /// normal listing so unsupported endpoints are not polled on every call.
+    pub async fn model_info(&self) -> Result<ModelInfo> {
+        let models = match self.list_models().await {
+            Ok(models) => models,
+            Err(e) => {
+                tracing::debug!("Model listing unavailable, using name only: {}", e);
+                let fallback = vec![ModelInfo::from_name(self.config.model.clone())];
+                if self.config.models_cache_ttl_secs > 0 {
+                    *self.models_cache.lock().await = Some((self.clock.now(), fallback.clone()));
+                }
+                fallback
+            }
+        };
+
+        Ok(models
+            .into_iter()
+            .find(|m| m.name == self.config.model)
+            .unwrap_or_else(|| ModelInfo::from_name(self.config.model.clone())))
+    }
+
+    /// Check a request against the configured model's limits before sending
+    pub async fn validate_request(&self, request: &InferenceRequest) -> Result<()> {
+        self.model_info()
+            .await?
+            .check_max_tokens(request.max_tokens)
+    }
+
     /// Stream generation with token-by-token output
     ///
     /// Returns a stream of `StreamChunk` items representing each token as it's generated.
     /// The stream completes when the final token is received (chunk with `is_final = true`).
+    /// If no chunk arrives within `stream_idle_timeout_secs`, it yields a
+    /// [`StreamStalled`] error and ends.
     ///
     /// # Example
     /// ```ignore
@@ -381,9 +1769,36 @@ impl ModalClient {
     /// }
     /// ```
     pub async fn generate_stream(&self, request: InferenceRequest) -> Result<StreamingResult> {
+        let stream = self.generate_stream_unwatched(request).await?;
+        Ok(match self.config.stream_idle_timeout_secs {
+            Some(idle_secs) => with_idle_timeout(stream, Duration::from_secs(idle_secs)),
+            None => stream,
+        })
+    }
+
+    async fn generate_stream_unwatched(
+        &self,
+        request: InferenceRequest,
+    ) -> Result<StreamingResult> {
+        let request_id = new_request_id();
+
+        #[cfg(feature = "grpc")]
+        if let Some(ref grpc) = self.grpc {
+            tracing::debug!(
+                request_id,
+                "Starting gRPC streaming generation request to Modal"
+            );
+            return grpc
+                .generate_stream(&request, request.idempotency_key.as_deref(), &request_id)
+                .await;
+        }
+
         // Build request URL for streaming endpoint
-        let url = self
-            .base_url
+        let (replica, base_url) = self
+            .replicas
+            .pick(&[])
+            .context("No inference replica available")?;
+        let url = base_url
             .join("/generate/stream")
             .context("Failed to build streaming request URL")?;
 
@@ -395,40 +1810,64 @@ impl ModalClient {
             "temperature": request.temperature,
             "model": self.config.model,
             "context": request.context,
+            "seed": request.seed,
             "stream": true,
+            "constraint_events": request.include_constraint_events,
         });
 
-        // Build HTTP request
         let mut http_request = self
-            .client
-            .post(url)
-            .header("Content-Type", "application/json")
+            .http_request(reqwest::Method::POST, url, Some(&body))
             .header("Accept", "text/event-stream")
-            .json(&body);
+            .header(REQUEST_ID_HEADER, &request_id);
 
-        // Add API key if present
-        if let Some(ref api_key) = self.config.api_key {
-            http_request = http_request.header("Authorization", format!("Bearer {}", api_key));
+        if let Some(ref key) = request.idempotency_key {
+            http_request = http_request.header(IDEMPOTENCY_KEY_HEADER, key);
+        }
+        if let Some(ms) = request.timeout_ms {
+            http_request = http_request.timeout(Duration::from_millis(ms));
         }
 
         // Send request and get streaming response
-        tracing::debug!("Starting streaming generation request to Modal");
-        let response = http_request
-            .send()
-            .await
-            .context("Failed to send streaming request to Modal")?;
+        tracing::debug!(request_id, "Starting streaming generation request to Modal");
+        let response = match http_request.send().await {
+            Ok(response) => response,
+            Err(e) => {
+                self.replicas.record_failure(replica);
+                return Err(e).context(format!(
+                    "Failed to send streaming request to Modal (request {})",
+                    request_id
+                ));
+            }
+        };
 
         // Check response status
         let status = response.status();
+        if status.is_server_error() {
+            self.replicas.record_failure(replica);
+        } else {
+            self.replicas.record_success(replica);
+        }
         if !status.is_success() {
-            let error_text = response
-                .text()
+            let error_text = self
+                .read_text(response)
                 .await
                 .unwrap_or_else(|_| "Unable to read error response".to_string());
+            if status.is_client_error() && error_text.contains(CONSTRAINTS_UNSUPPORTED_CODE) {
+                return Err(ConstraintsUnsupported {
+                    model: self.config.model.clone(),
+                    reason: format!(
+                        "service rejected the stream with status {}: {}",
+                        status,
+                        self.config.redaction.apply(&error_text)
+                    ),
+                }
+                .into());
+            }
             return Err(anyhow!(
-                "Modal streaming inference failed with status {}: {}",
+                "Modal streaming inference failed with status {} (request {}): {}",
                 status,
-                error_text
+                request_id,
+                self.config.redaction.apply(&error_text)
             ));
         }
 
@@ -436,63 +1875,71 @@ impl ModalClient {
         let byte_stream = response.bytes_stream();
         let start_time = std::time::Instant::now();
 
-        // Transform the byte stream into StreamChunk items
+        // Buffer across chunk boundaries, which rarely match event boundaries.
+        // A bad event yields an error item without ending the stream.
+        let redaction = self.config.redaction;
+        let include_constraint_events = request.include_constraint_events;
+        let mut decoder = EventDecoder::default();
+        let mut token_index = 0;
         let stream = byte_stream
-            .enumerate()
-            .map(move |(idx, result)| {
-                match result {
-                    Ok(bytes) => {
-                        // Parse SSE data (newline-delimited JSON)
-                        let text = String::from_utf8_lossy(&bytes);
-
-                        // Handle SSE format: "data: {...}\n\n"
-                        let chunks: Vec<StreamChunk> = text
-                            .lines()
-                            .filter(|line| line.starts_with("data: "))
-                            .filter_map(|line| {
-                                let json_str = line.strip_prefix("data: ")?;
-                                if json_str == "[DONE]" {
-                                    Some(StreamChunk {
+            .map(Some)
+            .chain(futures::stream::once(async { None }))
+            .flat_map(move |item| {
+                let events = match item {
+                    Some(Ok(bytes)) => decoder.feed(&bytes),
+                    Some(Err(e)) => {
+                        return futures::stream::iter(vec![Err(anyhow!(
+                            "Stream read error: {}",
+                            e
+                        ))])
+                    }
+                    None => decoder.finish(),
+                };
+                let mut items = Vec::with_capacity(events.len());
+                for event in events {
+                    match decoder.decode(event, redaction) {
+                        Ok(Some(mut token)) => {
+                            // Events come ahead of the text of their payload
+                            if let Some(event) = token.constraint_event.take() {
+                                if include_constraint_events {
+                                    items.push(Ok(StreamChunk {
                                         text: String::new(),
-                                        is_final: true,
-                                        token_index: idx,
+                                        is_final: false,
+                                        token_index,
                                         timestamp_ms: start_time.elapsed().as_millis() as u64,
-                                    })
-                                } else {
-                                    serde_json::from_str::<SSEData>(json_str).ok().map(|sse| {
-                                        StreamChunk {
-                                            text: sse.token.unwrap_or_default(),
-                                            is_final: sse.done.unwrap_or(false),
-                                            token_index: idx,
-                                            timestamp_ms: start_time.elapsed().as_millis() as u64,
-                                        }
-                                    })
+                                        finish_reason: None,
+                                        model: None,
+                                        constraint_event: Some(event),
+                                        restarted: false,
+                                    }));
                                 }
-                            })
-                            .collect();
-
-                        // Return first chunk or empty
-                        if let Some(chunk) = chunks.into_iter().next() {
-                            Ok(chunk)
-                        } else {
-                            // If no valid SSE data, return raw text as a chunk
-                            Ok(StreamChunk {
-                                text: text.trim().to_string(),
-                                is_final: false,
-                                token_index: idx,
+                                if token.text.is_empty()
+                                    && !token.is_final
+                                    && token.finish_reason.is_none()
+                                {
+                                    continue;
+                                }
+                            }
+                            items.push(Ok(StreamChunk {
+                                text: token.text,
+                                is_final: token.is_final,
+                                token_index,
                                 timestamp_ms: start_time.elapsed().as_millis() as u64,
-                            })
+                                finish_reason: token.finish_reason,
+                                model: None,
+                                constraint_event: None,
+                                restarted: false,
+                            }));
+                            token_index += 1;
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            tracing::warn!("Skipping stream event: {}", e);
+                            items.push(Err(e.into()));
                         }
                     }
-                    Err(e) => Err(anyhow!("Stream read error: {}", e)),
                 }
-            })
-            .filter(|result| {
-                // Filter out empty chunks
-                futures::future::ready(match result {
-                    Ok(chunk) => !chunk.text.is_empty() || chunk.is_final,
-                    Err(_) => true,
-                })
+                futures::stream::iter(items)
             });
 
         Ok(Box::pin(stream))
@@ -523,6 +1970,21 @@ impl Default for EnsembleConfig {
     }
 }
 
+/// When [`EnsembleClient::generate_ensemble`] stops waiting for models
+///
+/// Without a policy every routed model is awaited, so the slowest one sets
+/// the latency. With one, the ensemble proceeds once `quorum` models have
+/// answered or `deadline` has passed, whichever comes first, and cancels
+/// the rest; they are counted in [`ModelMetrics::timeouts`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnsemblePolicy {
+    /// Successful answers to wait for (at least one)
+    pub quorum: usize,
+
+    /// Longest to wait, from the start of the call
+    pub deadline: Duration,
+}
+
 /// Metrics for ensemble operations
 #[derive(Debug, Default, Clone)]
 pub struct EnsembleMetrics {
@@ -531,13 +1993,53 @@ pub struct EnsembleMetrics {
     pub total_fallbacks: u64,
 }
 
+impl EnsembleMetrics {
+    fn record_success(&mut self, model: &str, latency_ms: u64, confidence: Option<f32>) {
+        self.total_requests += 1;
+
+        let model_metrics = self.per_model.entry(model.to_string()).or_default();
+        model_metrics.requests += 1;
+        model_metrics.successes += 1;
+        model_metrics.total_latency_ms += latency_ms;
+
+        // Rolling average over the responses that reported a confidence
+        if let Some(confidence) = confidence {
+            model_metrics.scored += 1;
+            let scored = model_metrics.scored as f32;
+            model_metrics.avg_confidence =
+                (model_metrics.avg_confidence * (scored - 1.0) + confidence) / scored;
+        }
+    }
+
+    fn record_failure(&mut self, model: &str) {
+        self.total_requests += 1;
+
+        let model_metrics = self.per_model.entry(model.to_string()).or_default();
+        model_metrics.requests += 1;
+        model_metrics.failures += 1;
+    }
+
+    fn record_timeout(&mut self, model: &str) {
+        self.per_model
+            .entry(model.to_string())
+            .or_default()
+            .timeouts += 1;
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct ModelMetrics {
     pub requests: u64,
     pub successes: u64,
     pub failures: u64,
     pub total_latency_ms: u64,
+    /// Average confidence over the `scored` successes that reported one
     pub avg_confidence: f32,
+    /// Successful responses that carried a confidence
+    pub scored: u64,
+    /// Ensemble calls that went ahead without this model under an
+    /// [`EnsemblePolicy`]; not counted in `requests`
+    pub timeouts: u64,
 }
 
 impl ModelMetrics {
@@ -560,10 +2062,14 @@ impl ModelMetrics {
 
 /// Ensemble client wrapping multiple ModalClient instances
 pub struct EnsembleClient {
-    clients: HashMap<String, ModalClient>,
-    router: ModelRouter,
+    clients: HashMap<String, Arc<dyn InferenceClient>>,
+    // Boxed to keep the client small enough to hold inline in an enum
+    router: Box<ModelRouter>,
     config: EnsembleConfig,
     metrics: Arc<Mutex<EnsembleMetrics>>,
+    scorer: Arc<dyn CandidateScorer>,
+    policy: Option<EnsemblePolicy>,
+    clock: Arc<dyn Clock>,
 }
 
 impl EnsembleClient {
@@ -575,24 +2081,72 @@ impl EnsembleClient {
             let modal_config = ModalConfig {
                 endpoint_url: endpoint.endpoint_url.clone(),
                 api_key: endpoint.api_key.clone(),
+                auth_scheme: AuthScheme::default(),
+                user_agent: None,
                 timeout_secs: endpoint.timeout_secs,
                 model: endpoint.model.clone(),
                 enable_retry: true,
                 max_retries: 3,
+                models_cache_ttl_secs: default_models_cache_ttl_secs(),
+                redaction: RedactionPolicy::default(),
+                transport: Transport::default(),
+                decompress_responses: default_decompress_responses(),
+                stream_idle_timeout_secs: default_stream_idle_timeout_secs(),
+                replicas: Vec::new(),
+                circuit_breaker: CircuitBreakerConfig::default(),
+                hedge_after_ms: None,
+                max_response_bytes: default_max_response_bytes(),
+                health_check: HealthCheckConfig::default(),
             };
 
-            let client = ModalClient::new(modal_config)?;
+            let client: Arc<dyn InferenceClient> = Arc::new(ModalClient::new(modal_config)?);
             clients.insert(endpoint.name.clone(), client);
         }
 
-        let router = ModelRouter::new(config.endpoints.clone());
+        Ok(Self::with_clients(config, clients))
+    }
 
-        Ok(Self {
+    /// Create from ensemble configuration and a client per endpoint name
+    ///
+    /// `config.endpoints` still drives routing; their URLs and keys are not
+    /// used. Useful for ensembles of non-Modal or mock clients.
+    pub fn with_clients(
+        config: EnsembleConfig,
+        clients: HashMap<String, Arc<dyn InferenceClient>>,
+    ) -> Self {
+        let router = Box::new(ModelRouter::new(config.endpoints.clone()));
+
+        Self {
             clients,
             router,
             config,
             metrics: Arc::new(Mutex::new(EnsembleMetrics::default())),
-        })
+            scorer: Arc::new(ConfidenceScorer),
+            policy: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Choose among answers in [`generate_ensemble`](Self::generate_ensemble)
+    /// and [`generate_best_of_n`](Self::generate_best_of_n) with `scorer`
+    /// instead of by confidence
+    pub fn with_scorer(mut self, scorer: impl CandidateScorer + 'static) -> Self {
+        self.scorer = Arc::new(scorer);
+        self
+    }
+
+    /// Stop waiting for stragglers in
+    /// [`generate_ensemble`](Self::generate_ensemble) under `policy`
+    pub fn with_policy(mut self, policy: EnsemblePolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Time the [`EnsemblePolicy`] deadline with `clock` instead of the
+    /// system clock
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
     }
 
     /// Generate with automatic routing and fallback
@@ -611,6 +2165,99 @@ impl EnsembleClient {
         }
     }
 
+    /// Capabilities of the ensemble's `model` endpoint
+    pub async fn model_info(&self, model: &str) -> Result<ModelInfo> {
+        self.clients
+            .get(model)
+            .ok_or_else(|| anyhow!("Model {} not found", model))?
+            .model_info()
+            .await
+    }
+
+    /// Fill several marked holes with the model routed for the first hole
+    ///
+    /// Uses a single FIM request when the routed model supports it, and one
+    /// request per hole otherwise; see [`crate::fim::fill_holes`].
+    pub async fn fill_holes(
+        &self,
+        request: &MultiHoleRequest,
+        constraints: &[ConstraintIR],
+        temperature: f32,
+    ) -> Result<MultiHoleFill> {
+        let first = request
+            .holes
+            .first()
+            .ok_or_else(|| anyhow!("multi-hole request has no holes"))?;
+        let routing = self.router.route(first, constraints);
+        let client = self
+            .clients
+            .get(&routing.primary_model)
+            .ok_or_else(|| anyhow!("Model {} not found", routing.primary_model))?;
+
+        crate::fim::fill_holes(client.as_ref(), request, temperature).await
+    }
+
+    /// Stream from whichever routed model starts producing first
+    ///
+    /// For latency-sensitive callers. Starts a stream on each routed model
+    /// (up to `max_fallback_attempts`; only the primary model when fallback
+    /// is disabled) and commits to the first whose first event is a token
+    /// rather than an error, such as a rejected request or unsatisfiable
+    /// constraints; the other streams are cancelled. If the committed model
+    /// fails mid-stream, the models that have not failed race again and the
+    /// output restarts from the new winner's first token, with `token_index`
+    /// back at 0, so consumers should discard what they have so far. The
+    /// final chunk names the winning model in [`StreamChunk::model`].
+    pub async fn generate_stream_routed(
+        &self,
+        request: InferenceRequest,
+        hole_spec: &HoleSpec,
+        constraints: &[ConstraintIR],
+    ) -> Result<StreamingResult> {
+        let routing = self.router.route(hole_spec, constraints);
+        let models: Vec<&String> = if self.config.fallback_enabled {
+            routing
+                .all_models()
+                .take(self.config.max_fallback_attempts.max(1))
+                .collect()
+        } else {
+            vec![&routing.primary_model]
+        };
+        let candidates: Vec<(String, Arc<dyn InferenceClient>)> = models
+            .into_iter()
+            .filter_map(|model_name| match self.clients.get(model_name) {
+                Some(client) => Some((model_name.clone(), Arc::clone(client))),
+                None => {
+                    tracing::warn!("Model {} not found in ensemble", model_name);
+                    None
+                }
+            })
+            .collect();
+
+        let mut race = StreamRace {
+            request,
+            candidates,
+            metrics: Arc::clone(&self.metrics),
+        };
+        let start = Instant::now();
+        let (winner, first, stream) = race.run().await?;
+
+        let routed = RoutedStream {
+            race,
+            winner,
+            pending: Some(first),
+            stream,
+            start,
+            done: false,
+        };
+        let stream = futures::stream::unfold(routed, |mut routed| async move {
+            let item = routed.next_item().await?;
+            Some((item, routed))
+        });
+
+        Ok(Box::pin(stream))
+    }
+
     /// Generate with fallback on failure
     pub async fn generate_with_fallback(
         &self,
@@ -662,6 +2309,136 @@ impl EnsembleClient {
         Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No models available in ensemble")))
     }
 
+    /// Ask every routed model and keep the answer the scorer ranks highest
+    ///
+    /// The models run concurrently; failures are recorded and skipped, and
+    /// the call fails only if every model does. Routing picks the models as
+    /// for [`generate_routed`](Self::generate_routed), without the
+    /// `max_fallback_attempts` cap. Under an [`EnsemblePolicy`] the call
+    /// goes ahead with the answers it has once the quorum is reached or the
+    /// deadline passes, cancelling the models still running.
+    pub async fn generate_ensemble(
+        &self,
+        request: InferenceRequest,
+        hole_spec: &HoleSpec,
+        constraints: &[ConstraintIR],
+    ) -> Result<InferenceResponse> {
+        let routing = self.router.route(hole_spec, constraints);
+        let mut models: Vec<&String> = Vec::new();
+        for model in routing.all_models() {
+            if !models.contains(&model) {
+                models.push(model);
+            }
+        }
+
+        let count = models.len();
+        let (quorum, mut deadline) = match self.policy {
+            Some(policy) => (
+                policy.quorum.max(1),
+                Some(self.clock.sleep(policy.deadline)),
+            ),
+            None => (count, None),
+        };
+
+        let mut pending: futures::stream::FuturesUnordered<_> = models
+            .iter()
+            .enumerate()
+            .map(|(index, &model_name)| {
+                let request = request.clone();
+                async move { (index, self.ensemble_member(model_name, request).await) }
+            })
+            .collect();
+        let mut finished = vec![false; count];
+        let mut responses: Vec<(usize, InferenceResponse)> = Vec::new();
+        let mut last_error = None;
+        while responses.len() < quorum {
+            let next = match deadline.as_mut() {
+                Some(deadline) => tokio::select! {
+                    biased;
+                    next = pending.next() => next,
+                    _ = deadline => break,
+                },
+                None => pending.next().await,
+            };
+            let Some((index, result)) = next else {
+                break;
+            };
+            finished[index] = true;
+            match result {
+                Ok(response) => responses.push((index, response)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        drop(pending);
+
+        let timed_out: Vec<&String> = models
+            .iter()
+            .zip(&finished)
+            .filter(|(_, &finished)| !finished)
+            .map(|(&model, _)| model)
+            .collect();
+        if !timed_out.is_empty() {
+            tracing::info!(
+                answered = responses.len(),
+                ?timed_out,
+                "Ensemble proceeding without slower models"
+            );
+            let mut metrics = self.metrics.lock().await;
+            for model in &timed_out {
+                metrics.record_timeout(model);
+            }
+        }
+
+        // Back in routing order, so ties still go to the primary model
+        responses.sort_by_key(|(index, _)| *index);
+        let responses = responses
+            .into_iter()
+            .map(|(_, response)| response)
+            .collect();
+        self.select(responses).ok_or_else(|| {
+            if !timed_out.is_empty() {
+                return anyhow!(
+                    "No ensemble model answered within the deadline ({} failed, {} timed out)",
+                    count - timed_out.len(),
+                    timed_out.len()
+                );
+            }
+            last_error
+                .unwrap_or_else(|| anyhow!("No models available in ensemble"))
+                .context(format!("All {} ensemble models failed", count))
+        })
+    }
+
+    /// One model's answer in [`generate_ensemble`](Self::generate_ensemble),
+    /// recorded in the metrics
+    async fn ensemble_member(
+        &self,
+        model_name: &str,
+        request: InferenceRequest,
+    ) -> Result<InferenceResponse> {
+        let client = self
+            .clients
+            .get(model_name)
+            .ok_or_else(|| anyhow!("Model {} not found", model_name))?;
+        let start = std::time::Instant::now();
+        match client.generate_constrained(request).await {
+            Ok(response) => {
+                self.record_success(
+                    model_name,
+                    start.elapsed().as_millis() as u64,
+                    response.confidence(),
+                )
+                .await;
+                Ok(response)
+            }
+            Err(e) => {
+                self.record_failure(model_name).await;
+                tracing::warn!("Model {} failed in ensemble: {}", model_name, e);
+                Err(e)
+            }
+        }
+    }
+
     /// Generate using best-of-N selection
     pub async fn generate_best_of_n(
         &self,
@@ -674,24 +2451,40 @@ impl EnsembleClient {
             .get(model_name)
             .ok_or_else(|| anyhow::anyhow!("Model {} not found", model_name))?;
 
+        // Each sample is its own generation, so none may be deduplicated
+        // against another
+        let base_key = request
+            .idempotency_key
+            .clone()
+            .unwrap_or_else(|| request.call_key());
         let tasks: Vec<_> = (0..n)
-            .map(|_| {
-                let req = request.clone();
-                let cli = client.clone();
+            .map(|sample| {
+                let mut req = request.clone();
+                req.idempotency_key = Some(format!("{}-sample{}", base_key, sample));
+                let cli = Arc::clone(client);
                 async move { cli.generate_constrained(req).await }
             })
             .collect();
 
         let results = futures::future::join_all(tasks).await;
 
-        // Select best by confidence
-        let best = results.into_iter().filter_map(Result::ok).max_by(|a, b| {
-            a.confidence()
-                .partial_cmp(&b.confidence())
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
+        let responses = results.into_iter().filter_map(Result::ok).collect();
+        self.select(responses)
+            .ok_or_else(|| anyhow::anyhow!("All {} attempts failed", n))
+    }
 
-        best.ok_or_else(|| anyhow::anyhow!("All {} attempts failed", n))
+    /// The one of `responses` the scorer ranks highest
+    fn select(&self, mut responses: Vec<InferenceResponse>) -> Option<InferenceResponse> {
+        let candidates: Vec<EnsembleCandidate<'_>> = responses
+            .iter()
+            .map(|response| EnsembleCandidate {
+                code: &response.generated_text,
+                confidence: response.confidence(),
+                model: &response.model,
+            })
+            .collect();
+        let best = candidate_scorer::select(self.scorer.as_ref(), &candidates)?;
+        Some(responses.swap_remove(best))
     }
 
     async fn generate_single(
@@ -722,28 +2515,15 @@ impl EnsembleClient {
         }
     }
 
-    async fn record_success(&self, model: &str, latency_ms: u64, confidence: f32) {
-        let mut metrics = self.metrics.lock().await;
-        metrics.total_requests += 1;
-
-        let model_metrics = metrics.per_model.entry(model.to_string()).or_default();
-        model_metrics.requests += 1;
-        model_metrics.successes += 1;
-        model_metrics.total_latency_ms += latency_ms;
-
-        // Update rolling average confidence
-        let total_success = model_metrics.successes as f32;
-        model_metrics.avg_confidence =
-            (model_metrics.avg_confidence * (total_success - 1.0) + confidence) / total_success;
+    async fn record_success(&self, model: &str, latency_ms: u64, confidence: Option<f32>) {
+        self.metrics
+            .lock()
+            .await
+            .record_success(model, latency_ms, confidence);
     }
 
     async fn record_failure(&self, model: &str) {
-        let mut metrics = self.metrics.lock().await;
-        metrics.total_requests += 1;
-
-        let model_metrics = metrics.per_model.entry(model.to_string()).or_default();
-        model_metrics.requests += 1;
-        model_metrics.failures += 1;
+        self.metrics.lock().await.record_failure(model);
     }
 
     /// Get current metrics
@@ -757,6 +2537,140 @@ impl EnsembleClient {
     }
 }
 
+/// Models racing to produce the first token of a routed stream
+struct StreamRace {
+    request: InferenceRequest,
+    candidates: Vec<(String, Arc<dyn InferenceClient>)>,
+    metrics: Arc<Mutex<EnsembleMetrics>>,
+}
+
+impl StreamRace {
+    /// Start every candidate's stream and return the first to yield a token,
+    /// with that token and the rest of its stream
+    ///
+    /// Candidates that fail first are dropped from the race. Returning
+    /// cancels the streams still starting.
+    async fn run(&mut self) -> Result<(String, StreamChunk, StreamingResult)> {
+        let mut starting: futures::stream::FuturesUnordered<_> = self
+            .candidates
+            .iter()
+            .map(|(model_name, client)| {
+                let request = self.request.clone();
+                async move {
+                    let first = async {
+                        let mut stream = client.generate_stream(request).await?;
+                        match stream.next().await {
+                            Some(Ok(chunk)) => Ok((chunk, stream)),
+                            Some(Err(e)) => Err(e),
+                            None => Err(anyhow!("stream ended before its first token")),
+                        }
+                    }
+                    .await;
+                    (model_name.clone(), first)
+                }
+            })
+            .collect();
+
+        let mut failed = Vec::new();
+        let mut last_error = None;
+        let mut winner = None;
+        while let Some((model_name, first)) = starting.next().await {
+            match first {
+                Ok((chunk, stream)) => {
+                    winner = Some((model_name, chunk, stream));
+                    break;
+                }
+                Err(e) => {
+                    tracing::warn!("Model {} failed to start streaming: {}", model_name, e);
+                    self.metrics.lock().await.record_failure(&model_name);
+                    failed.push(model_name);
+                    last_error = Some(e);
+                }
+            }
+        }
+        drop(starting);
+
+        self.candidates
+            .retain(|(model_name, _)| !failed.contains(model_name));
+        winner
+            .ok_or_else(|| last_error.unwrap_or_else(|| anyhow!("No models available in ensemble")))
+    }
+}
+
+/// Output of the model a [`StreamRace`] committed to, falling back to the
+/// rest of the race if it fails
+struct RoutedStream {
+    race: StreamRace,
+    winner: String,
+    pending: Option<StreamChunk>,
+    stream: StreamingResult,
+    start: Instant,
+    done: bool,
+}
+
+impl RoutedStream {
+    async fn next_item(&mut self) -> Option<Result<StreamChunk>> {
+        if self.done {
+            return None;
+        }
+        let mut chunk = match self.pending.take() {
+            Some(chunk) => chunk,
+            None => match self.stream.next().await {
+                Some(Ok(chunk)) => chunk,
+                // Streams that close without a final chunk still completed
+                None => {
+                    self.done = true;
+                    self.record_completion().await;
+                    return None;
+                }
+                Some(Err(e)) => {
+                    tracing::warn!("Model {} failed mid-stream: {}", self.winner, e);
+                    {
+                        let mut metrics = self.race.metrics.lock().await;
+                        metrics.record_failure(&self.winner);
+                        metrics.total_fallbacks += 1;
+                    }
+                    let failed = self.winner.clone();
+                    self.race
+                        .candidates
+                        .retain(|(model_name, _)| *model_name != failed);
+                    match self.race.run().await {
+                        Ok((winner, mut first, stream)) => {
+                            tracing::info!("Falling back from {} to {}", failed, winner);
+                            self.winner = winner;
+                            self.stream = stream;
+                            first.restarted = true;
+                            first
+                        }
+                        Err(fallback) => {
+                            tracing::warn!("No fallback stream started: {}", fallback);
+                            self.done = true;
+                            return Some(Err(
+                                e.context(format!("Model {} failed mid-stream", failed))
+                            ));
+                        }
+                    }
+                }
+            },
+        };
+
+        if chunk.is_final {
+            self.done = true;
+            chunk.model = Some(self.winner.clone());
+            self.record_completion().await;
+        }
+        Some(Ok(chunk))
+    }
+
+    async fn record_completion(&mut self) {
+        self.race.metrics.lock().await.record_success(
+            &self.winner,
+            self.start.elapsed().as_millis() as u64,
+            None,
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -788,6 +2702,329 @@ mod tests {
         assert_eq!(metrics.avg_latency_ms(), 100);
     }
 
+    fn racing_ensemble(models: Vec<(&str, crate::MockInferenceClient)>) -> EnsembleClient {
+        let endpoints = models
+            .iter()
+            .enumerate()
+            .map(|(priority, (name, _))| ModelEndpoint {
+                name: name.to_string(),
+                priority: priority as u32,
+                ..Default::default()
+            })
+            .collect();
+        let clients = models
+            .into_iter()
+            .map(|(name, client)| {
+                let client: Arc<dyn InferenceClient> = Arc::new(client);
+                (name.to_string(), client)
+            })
+            .collect();
+        EnsembleClient::with_clients(
+            EnsembleConfig {
+                endpoints,
+                ..Default::default()
+            },
+            clients,
+        )
+    }
+
+    async fn collect_routed(ensemble: &EnsembleClient) -> Vec<Result<StreamChunk>> {
+        let request = InferenceRequest {
+            prompt: "test".to_string(),
+            constraints: serde_json::json!({}),
+            max_tokens: 100,
+            temperature: 0.0,
+            context: None,
+            seed: None,
+            idempotency_key: None,
+            include_logprobs: false,
+            timeout_ms: None,
+            include_constraint_events: false,
+        };
+        ensemble
+            .generate_stream_routed(request, &HoleSpec::default(), &[])
+            .await
+            .unwrap()
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_routed_stream_commits_to_first_model_to_produce() {
+        let slow = crate::MockInferenceClient::new("slow")
+            .with_latency(Duration::from_millis(300))
+            .with_default_response("fn slow() {}");
+        let fast = crate::MockInferenceClient::new("fast")
+            .with_latency(Duration::from_millis(10))
+            .with_default_response("fn fast() {}");
+        let ensemble = racing_ensemble(vec![("slow", slow), ("fast", fast)]);
+
+        let started = Instant::now();
+        let chunks: Vec<StreamChunk> = collect_routed(&ensemble)
+            .await
+            .into_iter()
+            .map(|c| c.unwrap())
+            .collect();
+        assert!(started.elapsed() < Duration::from_millis(300));
+
+        let text: String = chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(text, "fn fast() {}");
+        assert_eq!(chunks.last().unwrap().model.as_deref(), Some("fast"));
+        assert!(chunks[..chunks.len() - 1].iter().all(|c| c.model.is_none()));
+
+        // The slow stream was cancelled, not counted
+        let metrics = ensemble.get_metrics();
+        let metrics = metrics.lock().await;
+        assert_eq!(metrics.per_model["fast"].successes, 1);
+        assert!(!metrics.per_model.contains_key("slow"));
+    }
+
+    #[tokio::test]
+    async fn test_best_of_n_samples_get_distinct_keys() {
+        let client = crate::MockInferenceClient::new("model");
+        let ensemble = racing_ensemble(vec![("model", client.clone())]);
+        let request = InferenceRequest {
+            prompt: "test".to_string(),
+            constraints: serde_json::json!({}),
+            max_tokens: 100,
+            temperature: 0.7,
+            context: None,
+            seed: None,
+            idempotency_key: Some("call".to_string()),
+            include_logprobs: false,
+            timeout_ms: None,
+            include_constraint_events: false,
+        };
+        ensemble
+            .generate_best_of_n(request, 3, "model")
+            .await
+            .unwrap();
+
+        let mut keys: Vec<String> = client
+            .requests()
+            .into_iter()
+            .filter_map(|r| r.idempotency_key)
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["call-sample0", "call-sample1", "call-sample2"]);
+    }
+
+    struct InverseConfidence;
+
+    impl CandidateScorer for InverseConfidence {
+        fn score(&self, candidate: &EnsembleCandidate<'_>) -> f64 {
+            -f64::from(candidate.confidence.unwrap_or(1.0))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ensemble_selects_with_the_scorer() {
+        let scored = |model: &str, text: &str, confidence: f32| {
+            let mut response = crate::MockInferenceClient::response(model, text);
+            response.reported_confidence = Some(confidence);
+            crate::MockInferenceClient::new(model).then(crate::MockReply::Response(response))
+        };
+        let ensemble = || {
+            racing_ensemble(vec![
+                ("sure", scored("sure", "fn sure() {}", 0.9)),
+                (
+                    "broken",
+                    crate::MockInferenceClient::new("broken").then_fail("unavailable"),
+                ),
+                ("unsure", scored("unsure", "fn unsure() {}", 0.2)),
+            ])
+        };
+        let request = InferenceRequest {
+            prompt: "test".to_string(),
+            constraints: serde_json::json!({}),
+            max_tokens: 100,
+            temperature: 0.0,
+            context: None,
+            seed: None,
+            idempotency_key: None,
+            include_logprobs: false,
+            timeout_ms: None,
+            include_constraint_events: false,
+        };
+
+        let by_confidence = ensemble();
+        let response = by_confidence
+            .generate_ensemble(request.clone(), &HoleSpec::default(), &[])
+            .await
+            .unwrap();
+        assert_eq!(response.generated_text, "fn sure() {}");
+        let metrics = by_confidence.get_metrics();
+        let metrics = metrics.lock().await;
+        assert_eq!(metrics.per_model["sure"].successes, 1);
+        assert_eq!(metrics.per_model["unsure"].successes, 1);
+        assert_eq!(metrics.per_model["broken"].failures, 1);
+
+        let inverted = ensemble().with_scorer(InverseConfidence);
+        let response = inverted
+            .generate_ensemble(request, &HoleSpec::default(), &[])
+            .await
+            .unwrap();
+        assert_eq!(response.generated_text, "fn unsure() {}");
+    }
+
+    #[tokio::test]
+    async fn test_ensemble_policy_drops_slow_models() {
+        let model = |name: &str, latency_ms: u64, confidence: f32| {
+            let mut response =
+                crate::MockInferenceClient::response(name, &format!("fn {}() {{}}", name));
+            response.reported_confidence = Some(confidence);
+            crate::MockInferenceClient::new(name)
+                .with_latency(Duration::from_millis(latency_ms))
+                .then(crate::MockReply::Response(response))
+        };
+        // The slow model would win on confidence if it were waited for
+        let ensemble = |policy| {
+            racing_ensemble(vec![
+                ("fast", model("fast", 10, 0.5)),
+                ("medium", model("medium", 40, 0.6)),
+                ("slow", model("slow", 5_000, 0.99)),
+            ])
+            .with_policy(policy)
+        };
+        let request = InferenceRequest {
+            prompt: "test".to_string(),
+            constraints: serde_json::json!({}),
+            max_tokens: 100,
+            temperature: 0.0,
+            context: None,
+            seed: None,
+            idempotency_key: None,
+            include_logprobs: false,
+            timeout_ms: None,
+            include_constraint_events: false,
+        };
+
+        // Quorum reached by the two fastest
+        let by_quorum = ensemble(EnsemblePolicy {
+            quorum: 2,
+            deadline: Duration::from_secs(30),
+        });
+        let started = Instant::now();
+        let response = by_quorum
+            .generate_ensemble(request.clone(), &HoleSpec::default(), &[])
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(response.generated_text, "fn medium() {}");
+        let metrics = by_quorum.get_metrics();
+        let metrics = metrics.lock().await;
+        assert_eq!(metrics.per_model["slow"].timeouts, 1);
+        assert_eq!(metrics.per_model["slow"].requests, 0);
+        assert_eq!(metrics.per_model["fast"].timeouts, 0);
+        drop(metrics);
+
+        // Deadline passes before the quorum of three
+        let by_deadline = ensemble(EnsemblePolicy {
+            quorum: 3,
+            deadline: Duration::from_millis(200),
+        });
+        let response = by_deadline
+            .generate_ensemble(request.clone(), &HoleSpec::default(), &[])
+            .await
+            .unwrap();
+        assert_eq!(response.generated_text, "fn medium() {}");
+
+        // Nobody answers in time
+        let too_short = ensemble(EnsemblePolicy {
+            quorum: 1,
+            deadline: Duration::from_millis(1),
+        });
+        let err = too_short
+            .generate_ensemble(request.clone(), &HoleSpec::default(), &[])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("3 timed out"), "{}", err);
+
+        // The deadline is timed by the ensemble's clock
+        let clock = crate::MockClock::new();
+        let mocked = ensemble(EnsemblePolicy {
+            quorum: 3,
+            deadline: Duration::from_secs(30),
+        })
+        .with_clock(clock.clone());
+        let started = Instant::now();
+        let err = mocked
+            .generate_ensemble(request, &HoleSpec::default(), &[])
+            .await
+            .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(err.to_string().contains("within the deadline"), "{}", err);
+        assert_eq!(clock.sleeps(), vec![Duration::from_secs(30)]);
+    }
+
+    #[tokio::test]
+    async fn test_routed_stream_skips_models_that_fail_to_start() {
+        let broken = crate::MockInferenceClient::new("broken").then_fail("constraint rejected");
+        let slow = crate::MockInferenceClient::new("slow")
+            .with_latency(Duration::from_millis(30))
+            .with_default_response("fn slow() {}");
+        let ensemble = racing_ensemble(vec![("broken", broken), ("slow", slow)]);
+
+        let chunks = collect_routed(&ensemble).await;
+        let last = chunks.last().unwrap().as_ref().unwrap();
+        assert_eq!(last.model.as_deref(), Some("slow"));
+
+        let metrics = ensemble.get_metrics();
+        let metrics = metrics.lock().await;
+        assert_eq!(metrics.per_model["broken"].failures, 1);
+        assert_eq!(metrics.total_fallbacks, 0);
+    }
+
+    #[tokio::test]
+    async fn test_routed_stream_falls_back_when_winner_fails_mid_stream() {
+        let flaky = crate::MockInferenceClient::new("flaky")
+            .with_latency(Duration::from_millis(10))
+            .then(crate::MockReply::StreamFailure {
+                emitted: "fn half".to_string(),
+                message: "connection reset".to_string(),
+            });
+        let steady = crate::MockInferenceClient::new("steady")
+            .with_latency(Duration::from_millis(100))
+            .with_default_response("fn whole() {}");
+        let ensemble = racing_ensemble(vec![("flaky", flaky), ("steady", steady)]);
+
+        let chunks: Vec<StreamChunk> = collect_routed(&ensemble)
+            .await
+            .into_iter()
+            .map(|c| c.unwrap())
+            .collect();
+
+        // The fallback's first token is marked, and starts the output over
+        let restart = chunks.iter().position(|c| c.restarted).unwrap();
+        assert!(restart > 0);
+        assert_eq!(chunks[restart].token_index, 0);
+        assert_eq!(chunks.iter().filter(|c| c.restarted).count(), 1);
+        let text: String = chunks[restart..].iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(text, "fn whole() {}");
+        assert_eq!(chunks.last().unwrap().model.as_deref(), Some("steady"));
+
+        let metrics = ensemble.get_metrics();
+        let metrics = metrics.lock().await;
+        assert_eq!(metrics.per_model["flaky"].failures, 1);
+        assert_eq!(metrics.per_model["steady"].successes, 1);
+        assert_eq!(metrics.total_fallbacks, 1);
+    }
+
+    #[tokio::test]
+    async fn test_routed_stream_reports_mid_stream_failure_without_fallback() {
+        let flaky =
+            crate::MockInferenceClient::new("flaky").then(crate::MockReply::StreamFailure {
+                emitted: "fn half".to_string(),
+                message: "connection reset".to_string(),
+            });
+        let ensemble = racing_ensemble(vec![("flaky", flaky)]);
+
+        let chunks = collect_routed(&ensemble).await;
+        let err = chunks.last().unwrap().as_ref().unwrap_err();
+        assert!(err.to_string().contains("Model flaky failed mid-stream"));
+        assert!(chunks[..chunks.len() - 1].iter().all(|c| c.is_ok()));
+    }
+
     #[test]
     fn test_ensemble_metrics_default() {
         let metrics = EnsembleMetrics::default();
@@ -827,6 +3064,11 @@ mod tests {
             max_tokens: 100,
             temperature: 0.7,
             context: None,
+            seed: None,
+            idempotency_key: None,
+            include_logprobs: false,
+            timeout_ms: None,
+            include_constraint_events: false,
         };
 
         let json = serde_json::to_string(&request).unwrap();
@@ -846,4 +3088,104 @@ mod tests {
         let client = ModalClient::new(config);
         assert!(client.is_ok());
     }
+
+    fn response_with(logprobs: Option<Vec<f32>>, reported: Option<f32>) -> InferenceResponse {
+        let mut response = crate::MockInferenceClient::response("m", "x");
+        response.logprobs = logprobs.map(|values| {
+            values
+                .into_iter()
+                .map(|logprob| TokenLogprob {
+                    token: "t".to_string(),
+                    logprob,
+                    top_logprobs: vec![],
+                })
+                .collect()
+        });
+        response.reported_confidence = reported;
+        response
+    }
+
+    #[test]
+    fn test_confidence_is_geometric_mean_of_token_probabilities() {
+        // All tokens certain
+        let certain = response_with(Some(vec![0.0, 0.0, 0.0]), None);
+        assert_eq!(certain.confidence(), Some(1.0));
+
+        // p = 0.5 and p = 0.125 -> sqrt(0.0625) = 0.25
+        let mixed = response_with(Some(vec![0.5f32.ln(), 0.125f32.ln()]), None);
+        assert!((mixed.confidence().unwrap() - 0.25).abs() < 1e-6);
+
+        // One very unlikely token dominates
+        let outlier = response_with(Some(vec![0.0, 0.0, 0.0, -20.0]), None);
+        assert!(outlier.confidence().unwrap() < 0.01);
+    }
+
+    #[test]
+    fn test_confidence_prefers_logprobs_over_reported_value() {
+        let both = response_with(Some(vec![0.0]), Some(0.1));
+        assert_eq!(both.confidence(), Some(1.0));
+
+        // Empty logprobs carry no signal
+        let empty = response_with(Some(vec![]), Some(0.4));
+        assert_eq!(empty.confidence(), Some(0.4));
+    }
+
+    #[test]
+    fn test_confidence_reported_value_is_clamped_and_unknown_is_none() {
+        assert_eq!(response_with(None, Some(1.7)).confidence(), Some(1.0));
+        assert_eq!(response_with(None, Some(f32::NAN)).confidence(), None);
+        assert_eq!(response_with(None, None).confidence(), None);
+
+        let parsed: InferenceResponse = serde_json::from_value(serde_json::json!({
+            "generated_text": "x",
+            "tokens_generated": 1,
+            "model": "m",
+            "stats": {
+                "total_time_ms": 1,
+                "time_per_token_us": 1,
+                "constraint_checks": 0,
+                "avg_constraint_check_us": 0
+            },
+            "confidence": 0.6
+        }))
+        .unwrap();
+        assert_eq!(parsed.confidence(), Some(0.6));
+    }
+
+    #[test]
+    fn test_constraint_cost_summary_sums_kinds_and_counts_aggregate_only() {
+        let kinds = |entries: &[(&str, usize, u64)]| {
+            entries
+                .iter()
+                .map(|&(kind, checks, total_us)| {
+                    (kind.to_string(), ConstraintKindStats { checks, total_us })
+                })
+                .collect()
+        };
+        let broken_down = GenerationStats {
+            constraint_checks: 5,
+            avg_constraint_check_us: 20,
+            by_kind: kinds(&[("grammar", 3, 90), ("regex", 2, 10)]),
+            ..Default::default()
+        };
+        let totals_only = GenerationStats {
+            constraint_checks: 4,
+            avg_constraint_check_us: 10,
+            ..Default::default()
+        };
+
+        let mut summary = ConstraintCostSummary::default();
+        summary.record(&broken_down);
+        summary.record(&broken_down);
+        summary.record(&totals_only);
+
+        assert_eq!(summary.responses, 3);
+        assert_eq!(summary.aggregate_only, 1);
+        assert_eq!(summary.constraint_checks, 14);
+        assert_eq!(summary.constraint_check_us, 240);
+        assert_eq!(summary.by_kind["grammar"].checks, 6);
+        assert_eq!(summary.by_kind["regex"].avg_us(), 5);
+        let (kind, stats) = summary.most_expensive().unwrap();
+        assert_eq!((kind, stats.total_us), ("grammar", 180));
+    }
 }
@@ -0,0 +1,303 @@
+//! Sequential failover across models
+//!
+//! HTTP retries repeat a call against the same model, and the
+//! [`EnsembleClient`](crate::EnsembleClient) routes among models it knows
+//! about. A model chain is simpler: try the primary model, and when it fails,
+//! answers below `min_confidence`, or produces output that does not validate,
+//! try the next configured model. Each model tried is recorded as a
+//! [`ChainAttempt`]. If no model produces an acceptable answer, the best
+//! answer seen is used; only when every model fails is the last error
+//! returned.
+//!
+//! A call the service rejects as malformed or unauthorized (a 4xx
+//! [`ServiceError`] other than 404, 408 or 429) ends the chain with that
+//! error, since every fallback shares the endpoint and would reject it too.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::inference::InferenceClient;
+use crate::modal_client::{ModalConfig, ServiceError};
+
+/// Fallback models to try, in order, after the primary model
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelChainConfig {
+    /// Fallback model names, served from the primary's endpoint
+    ///
+    /// Resolved only when the client is built from a [`ModalConfig`];
+    /// with a custom client, add fallbacks with `with_fallback_client`.
+    pub models: Vec<String>,
+
+    /// Answers scoring below this fall through to the next model; unscored
+    /// answers are accepted
+    pub min_confidence: Option<f32>,
+}
+
+impl ModelChainConfig {
+    /// The primary's `ModalConfig` pointed at each fallback model
+    pub fn fallback_configs(&self, primary: &ModalConfig) -> Vec<ModalConfig> {
+        self.models
+            .iter()
+            .map(|model| ModalConfig {
+                model: model.clone(),
+                ..primary.clone()
+            })
+            .collect()
+    }
+}
+
+/// How one model in the chain fared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainOutcome {
+    /// The answer was accepted
+    Accepted,
+
+    /// The call failed
+    Failed,
+
+    /// The answer scored below `min_confidence`
+    LowConfidence,
+
+    /// The answer did not validate
+    Invalid,
+}
+
+/// One model tried in a chain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChainAttempt {
+    /// Model the request was sent to
+    pub model: String,
+
+    /// How the attempt fared
+    pub outcome: ChainOutcome,
+
+    /// Confidence of the answer, if scored
+    pub confidence: Option<f32>,
+
+    /// Error or validation failure
+    pub error: Option<String>,
+
+    /// Whether this attempt's answer was used; when no answer is accepted,
+    /// the best rejected one is
+    pub selected: bool,
+
+    /// Time spent on the attempt in milliseconds
+    pub duration_ms: u64,
+}
+
+/// An answer from one model, with what is needed to judge it
+pub(crate) struct Candidate<T> {
+    pub(crate) value: T,
+    pub(crate) confidence: Option<f32>,
+
+    /// Why the answer is invalid, if it is
+    pub(crate) invalid: Option<String>,
+}
+
+/// Whether a failed call should be retried on the next model: anything but a
+/// client error the shared endpoint would return for every model
+fn fails_over(err: &anyhow::Error) -> bool {
+    !err.chain()
+        .filter_map(|cause| cause.downcast_ref::<ServiceError>())
+        .any(|e| (400..500).contains(&e.status) && !matches!(e.status, 404 | 408 | 429))
+}
+
+/// Run `attempt` against each client in turn until one gives an acceptable
+/// answer
+///
+/// `attempt` receives the client and its position in the chain (0 for the
+/// primary). Returns the answer used and the attempts made.
+pub(crate) async fn run_chain<T, F, Fut>(
+    clients: &[Arc<dyn InferenceClient>],
+    min_confidence: Option<f32>,
+    mut attempt: F,
+) -> Result<(T, Vec<ChainAttempt>)>
+where
+    F: FnMut(Arc<dyn InferenceClient>, usize) -> Fut,
+    Fut: Future<Output = Result<Candidate<T>>>,
+{
+    let mut attempts = Vec::with_capacity(clients.len());
+    let mut rejected: Vec<(usize, Candidate<T>)> = Vec::new();
+    let mut last_error = None;
+
+    for (position, client) in clients.iter().enumerate() {
+        let model = client.model_name().to_string();
+        let start = std::time::Instant::now();
+        let result = attempt(client.clone(), position).await;
+        let duration_ms = start.elapsed().as_millis() as u64;
+
+        let candidate = match result {
+            Ok(candidate) => candidate,
+            Err(err) => {
+                tracing::warn!("Model {} failed in chain: {:#}", model, err);
+                attempts.push(ChainAttempt {
+                    model,
+                    outcome: ChainOutcome::Failed,
+                    confidence: None,
+                    error: Some(format!("{:#}", err)),
+                    selected: false,
+                    duration_ms,
+                });
+                if !fails_over(&err) {
+                    return Err(err);
+                }
+                last_error = Some(err);
+                continue;
+            }
+        };
+
+        let low = matches!(
+            (candidate.confidence, min_confidence),
+            (Some(confidence), Some(min)) if confidence < min
+        );
+        let (outcome, error) = match (&candidate.invalid, low) {
+            (Some(reason), _) => (ChainOutcome::Invalid, Some(reason.clone())),
+            (None, true) => (ChainOutcome::LowConfidence, None),
+            (None, false) => (ChainOutcome::Accepted, None),
+        };
+        attempts.push(ChainAttempt {
+            model,
+            outcome,
+            confidence: candidate.confidence,
+            error,
+            selected: outcome == ChainOutcome::Accepted,
+            duration_ms,
+        });
+        if outcome == ChainOutcome::Accepted {
+            return Ok((candidate.value, attempts));
+        }
+        if position + 1 < clients.len() {
+            tracing::warn!(
+                "Model {} answer was {:?}, trying next model in chain",
+                attempts[position].model,
+                outcome
+            );
+        }
+        rejected.push((position, candidate));
+    }
+
+    // Nothing was accepted: prefer valid answers, then the most confident
+    let best = rejected
+        .into_iter()
+        .enumerate()
+        .max_by(|(a_order, (_, a)), (b_order, (_, b))| {
+            let key = |c: &Candidate<T>| (c.invalid.is_none(), c.confidence.unwrap_or(0.0));
+            let (a_key, b_key) = (key(a), key(b));
+            a_key
+                .0
+                .cmp(&b_key.0)
+                .then(a_key.1.total_cmp(&b_key.1))
+                // Earlier models win ties
+                .then(b_order.cmp(a_order))
+        })
+        .map(|(_, best)| best);
+    match best {
+        Some((position, candidate)) => {
+            attempts[position].selected = true;
+            Ok((candidate.value, attempts))
+        }
+        None => Err(last_error.unwrap_or_else(|| anyhow::anyhow!("Model chain is empty"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference::MockInferenceClient;
+
+    fn chain(models: &[&str]) -> Vec<Arc<dyn InferenceClient>> {
+        models
+            .iter()
+            .map(|model| Arc::new(MockInferenceClient::new(*model)) as Arc<dyn InferenceClient>)
+            .collect()
+    }
+
+    /// Scripted judgement per model: `Err` fails, otherwise (confidence, valid)
+    async fn run(
+        clients: &[Arc<dyn InferenceClient>],
+        min_confidence: Option<f32>,
+        script: &[Result<(f32, bool), &str>],
+    ) -> Result<(usize, Vec<ChainAttempt>)> {
+        run_chain(clients, min_confidence, |_, position| {
+            let reply = script[position];
+            async move {
+                let (confidence, valid) = reply.map_err(|e| anyhow::anyhow!("{}", e))?;
+                Ok(Candidate {
+                    value: position,
+                    confidence: Some(confidence),
+                    invalid: (!valid).then(|| "syntax error".to_string()),
+                })
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_chain_falls_through_failures_and_low_confidence() {
+        let clients = chain(&["primary", "backup", "last"]);
+        let (used, attempts) = run(
+            &clients,
+            Some(0.5),
+            &[Err("status 500"), Ok((0.2, true)), Ok((0.9, true))],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(used, 2);
+        let outcomes: Vec<_> = attempts.iter().map(|a| a.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                ChainOutcome::Failed,
+                ChainOutcome::LowConfidence,
+                ChainOutcome::Accepted
+            ]
+        );
+        assert_eq!(attempts[0].error.as_deref(), Some("status 500"));
+        assert_eq!(attempts[2].model, "last");
+        assert!(attempts[2].selected);
+    }
+
+    #[tokio::test]
+    async fn test_best_rejected_answer_is_used_when_none_accepted() {
+        let clients = chain(&["primary", "backup", "last"]);
+        let (used, attempts) = run(
+            &clients,
+            Some(0.8),
+            &[Ok((0.9, false)), Ok((0.3, true)), Ok((0.6, true))],
+        )
+        .await
+        .unwrap();
+        assert_eq!(used, 2);
+        assert!(attempts[2].selected && !attempts[0].selected);
+
+        let err = run(&clients, None, &[Err("a"), Err("b"), Err("c")])
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "c");
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_end_the_chain() {
+        let clients = chain(&["primary", "backup"]);
+        let calls = std::sync::atomic::AtomicUsize::new(0);
+        let err = run_chain(&clients, None, |_, _| {
+            calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async {
+                Err::<Candidate<()>, _>(anyhow::Error::new(ServiceError {
+                    status: 400,
+                    message: "bad request".to_string(),
+                }))
+            }
+        })
+        .await
+        .unwrap_err();
+
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(err.downcast_ref::<ServiceError>().unwrap().status, 400);
+    }
+}
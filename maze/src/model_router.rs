@@ -167,6 +167,12 @@ impl ModelRouter {
     pub fn get_endpoint(&self, name: &str) -> Option<&ModelEndpoint> {
         self.endpoints.get(name)
     }
+
+    /// Endpoint by name, or else by the model it serves
+    pub fn find_endpoint(&self, name_or_model: &str) -> Option<&ModelEndpoint> {
+        self.get_endpoint(name_or_model)
+            .or_else(|| self.endpoints.values().find(|e| e.model == name_or_model))
+    }
 }
 
 #[cfg(test)]
@@ -239,6 +245,7 @@ mod tests {
             rich_context: None,
             feasibility_score: 0.0,
             is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
         }];
 
         let routing = router.route(&spec, &constraints);
@@ -311,6 +318,7 @@ mod tests {
             rich_context: None,
             feasibility_score: 0.0,
             is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
         }];
 
         let routing = router.route(&spec, &constraints);
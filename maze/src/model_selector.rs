@@ -3,9 +3,11 @@
 //! Determines whether to use autoregressive or diffusion models based on
 //! hole characteristics, constraint complexity, and heuristics.
 
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::ffi::HoleSpec;
+use crate::modal_client::ModelInfo;
 
 /// Model choice for filling a hole
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -192,6 +194,22 @@ impl ModelSelector {
         }
     }
 
+    /// Check a model choice against the capabilities the target model advertises
+    ///
+    /// Rejects autoregressive choices whose `max_tokens` exceeds the model's
+    /// limits and diffusion choices for models without diffusion support, so
+    /// the request fails before it is sent.
+    pub fn check_capabilities(&self, choice: &ModelChoice, model: &ModelInfo) -> Result<()> {
+        match choice {
+            ModelChoice::Autoregressive { max_tokens, .. } => model.check_max_tokens(*max_tokens),
+            ModelChoice::Diffusion { .. } if !model.supports_diffusion => Err(anyhow!(
+                "model {} does not support diffusion generation",
+                model.name
+            )),
+            ModelChoice::Diffusion { .. } => Ok(()),
+        }
+    }
+
     /// Estimate complexity of a hole based on its specification
     ///
     /// Returns a score from 0.0 (simple) to 1.0 (very complex)
@@ -438,6 +456,48 @@ mod tests {
         assert!(complexity > 0.5);
     }
 
+    #[test]
+    fn test_check_capabilities() {
+        let selector = ModelSelector::default();
+        let model = ModelInfo {
+            name: "small".to_string(),
+            context_window: Some(4096),
+            max_output_tokens: Some(1024),
+            supports_grammar: true,
+            supports_diffusion: false,
+            supports_fim: false,
+            min_temperature: None,
+            max_temperature: None,
+        };
+
+        let within = ModelChoice::Autoregressive {
+            temperature: 0.7,
+            max_tokens: 512,
+            top_p: None,
+            model: None,
+        };
+        assert!(selector.check_capabilities(&within, &model).is_ok());
+
+        let too_long = ModelChoice::Autoregressive {
+            temperature: 0.7,
+            max_tokens: 2048,
+            top_p: None,
+            model: None,
+        };
+        assert!(selector.check_capabilities(&too_long, &model).is_err());
+
+        let diffusion = ModelChoice::Diffusion {
+            num_steps: 50,
+            guidance_scale: 7.5,
+            noise_schedule: "cosine".to_string(),
+        };
+        assert!(selector.check_capabilities(&diffusion, &model).is_err());
+
+        // Name-only descriptors have no known limits
+        let unknown = ModelInfo::from_name("unknown");
+        assert!(selector.check_capabilities(&too_long, &unknown).is_ok());
+    }
+
     #[test]
     fn test_estimate_max_tokens() {
         let selector = ModelSelector::default();
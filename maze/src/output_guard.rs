@@ -0,0 +1,103 @@
+//! Rejection of empty generations
+//!
+//! A model occasionally answers with empty or whitespace-only text. It
+//! parses fine and would otherwise be accepted as a fill, silently leaving
+//! the hole blank. An [`OutputGuard`] treats such output as a failed
+//! generation: [`MazeOrchestrator::generate`](crate::MazeOrchestrator::generate)
+//! retries and then fails with [`RejectedOutput`], and the refiner fails the
+//! attempt so its failure strategy applies.
+
+use serde::{Deserialize, Serialize};
+
+/// Minimum requirements for generated text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputGuard {
+    /// Fewest characters accepted, not counting leading and trailing
+    /// whitespace; zero accepts empty output
+    pub min_output_chars: usize,
+
+    /// Reject output that is entirely whitespace
+    pub reject_whitespace_only: bool,
+
+    /// Extra generations `generate` makes after a rejected one
+    pub max_retries: usize,
+}
+
+impl Default for OutputGuard {
+    fn default() -> Self {
+        Self {
+            min_output_chars: 1,
+            reject_whitespace_only: true,
+            max_retries: 1,
+        }
+    }
+}
+
+impl OutputGuard {
+    /// A guard that accepts everything
+    pub fn disabled() -> Self {
+        Self {
+            min_output_chars: 0,
+            reject_whitespace_only: false,
+            max_retries: 0,
+        }
+    }
+
+    /// Why `text` is rejected, if it is
+    pub fn check(&self, text: &str) -> Result<(), RejectedOutput> {
+        let trimmed = text.trim();
+        if self.reject_whitespace_only && trimmed.is_empty() && !text.is_empty() {
+            return Err(RejectedOutput::WhitespaceOnly { len: text.len() });
+        }
+        let chars = trimmed.chars().count();
+        if chars < self.min_output_chars {
+            return Err(RejectedOutput::TooShort {
+                chars,
+                min: self.min_output_chars,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Generated text that failed an [`OutputGuard`]
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RejectedOutput {
+    /// Output was nothing but whitespace
+    #[error("generated output is whitespace only ({len} bytes)")]
+    WhitespaceOnly { len: usize },
+
+    /// Output, once trimmed, was shorter than `min_output_chars`
+    #[error("generated output has {chars} characters, fewer than the minimum of {min}")]
+    TooShort { chars: usize, min: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_guard_rejects_blank_output() {
+        let guard = OutputGuard::default();
+        assert_eq!(
+            guard.check(""),
+            Err(RejectedOutput::TooShort { chars: 0, min: 1 })
+        );
+        assert_eq!(
+            guard.check(" \n\t"),
+            Err(RejectedOutput::WhitespaceOnly { len: 3 })
+        );
+        assert_eq!(guard.check(" x "), Ok(()));
+        assert_eq!(OutputGuard::disabled().check(" "), Ok(()));
+    }
+
+    #[test]
+    fn test_min_output_chars_counts_trimmed_characters() {
+        let guard = OutputGuard {
+            min_output_chars: 3,
+            ..Default::default()
+        };
+        assert!(guard.check("  ab  ").is_err());
+        assert_eq!(guard.check("é✓x"), Ok(()));
+    }
+}
@@ -0,0 +1,406 @@
+//! Allow/deny policies over generated code
+//!
+//! A [`PolicyConstraint`] forbids API calls, imports, or other identifiers
+//! (`eval(`, `os.system`, ...) whatever the model would prefer. It is
+//! enforced in two layers:
+//!
+//! - During decoding, through token masks: tokenizer ids listed in
+//!   `forbidden_tokens` are masked out, at a priority high enough to win
+//!   allow/forbid conflicts with other constraints (see [`merge`](crate::merge)).
+//!   With a tokenizer that can encode, such as
+//!   [`HfTokenizer`](crate::HfTokenizer), the orchestrator also masks the
+//!   tokens that begin a deny match ([`PolicyConstraint::denied_tokens`]).
+//! - After decoding, by scanning the output with the `deny` regexes. Matches
+//!   that an `allow` regex accepts in full are exempt. Violations are listed
+//!   in [`ValidationResult::violated`](crate::ValidationResult::violated), and
+//!   with `regenerate_on_violation` the output is regenerated within
+//!   `OutputGuard::max_retries`.
+//!
+//! The policy travels inside the [`ConstraintIR`] it produces (under the
+//! `policy` key of `rich_context`), so it composes with any other constraint
+//! source and is found again wherever the constraint set goes.
+//!
+//! ```
+//! use maze::policy::PolicyConstraint;
+//!
+//! let policy = PolicyConstraint::new("no_shell")
+//!     .deny(r"\bos\.system\b")?
+//!     .deny(r"\beval\s*\(")?
+//!     .regenerate_on_violation(true);
+//! assert_eq!(policy.scan("os.system('ls')").len(), 1);
+//!
+//! let constraint = policy.to_constraint();
+//! # Ok::<(), maze::constraint_builder::InvalidConstraint>(())
+//! ```
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::constraint_builder::InvalidConstraint;
+use crate::context_window::TokenEstimator;
+use crate::ffi::{ConstraintIR, TokenMaskRules};
+
+/// Key of `ConstraintIR::rich_context` that carries a policy
+pub const POLICY_CONTEXT_KEY: &str = "policy";
+
+/// Priority of policy constraints unless set with [`PolicyConstraint::with_priority`]
+pub const DEFAULT_POLICY_PRIORITY: u32 = 100;
+
+/// Patterns generated code must not contain
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PolicyConstraint {
+    /// Name of the produced constraint
+    pub name: String,
+
+    /// Regexes whose matches are violations
+    #[serde(default)]
+    pub deny: Vec<String>,
+
+    /// Regexes exempting a deny match they match in full
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    /// Tokenizer ids masked out during decoding; model specific
+    #[serde(default)]
+    pub forbidden_tokens: Vec<u32>,
+
+    /// Merge priority of the produced constraint
+    #[serde(default = "default_priority")]
+    pub priority: u32,
+
+    /// Regenerate output that violates the policy
+    #[serde(default)]
+    pub regenerate_on_violation: bool,
+}
+
+fn default_priority() -> u32 {
+    DEFAULT_POLICY_PRIORITY
+}
+
+impl PolicyConstraint {
+    /// Empty policy with the given constraint name
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            deny: vec![],
+            allow: vec![],
+            forbidden_tokens: vec![],
+            priority: DEFAULT_POLICY_PRIORITY,
+            regenerate_on_violation: false,
+        }
+    }
+
+    /// Forbid output matching `pattern`
+    pub fn deny(mut self, pattern: &str) -> Result<Self, InvalidConstraint> {
+        self.check_pattern(pattern)?;
+        self.deny.push(pattern.to_string());
+        Ok(self)
+    }
+
+    /// Exempt deny matches that `pattern` matches in full
+    pub fn allow(mut self, pattern: &str) -> Result<Self, InvalidConstraint> {
+        self.check_pattern(pattern)?;
+        self.allow.push(pattern.to_string());
+        Ok(self)
+    }
+
+    /// Mask out these tokenizer ids during decoding
+    pub fn forbid_tokens(mut self, tokens: impl IntoIterator<Item = u32>) -> Self {
+        self.forbidden_tokens.extend(tokens);
+        self
+    }
+
+    /// Set the merge priority of the produced constraint
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Regenerate output that violates the policy
+    pub fn regenerate_on_violation(mut self, regenerate: bool) -> Self {
+        self.regenerate_on_violation = regenerate;
+        self
+    }
+
+    fn check_pattern(&self, pattern: &str) -> Result<(), InvalidConstraint> {
+        Regex::new(pattern)
+            .map(|_| ())
+            .map_err(|e| InvalidConstraint {
+                constraint: self.name.clone(),
+                reason: format!("invalid policy regex /{}/: {}", pattern, e),
+            })
+    }
+
+    /// Constraint enforcing this policy, for use alongside other constraints
+    pub fn to_constraint(&self) -> ConstraintIR {
+        ConstraintIR {
+            name: self.name.clone(),
+            json_schema: None,
+            grammar: None,
+            regex_patterns: vec![],
+            token_masks: (!self.forbidden_tokens.is_empty()).then(|| TokenMaskRules {
+                allowed_tokens: None,
+                forbidden_tokens: Some(self.forbidden_tokens.clone()),
+            }),
+            type_inhabitation: None,
+            priority: self.priority,
+            rich_context: Some(serde_json::json!({ POLICY_CONTEXT_KEY: self })),
+            feasibility_score: 0.0,
+            is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    /// Policy carried by `constraint`, if it was produced by [`to_constraint`](Self::to_constraint)
+    pub fn from_constraint(constraint: &ConstraintIR) -> Option<Self> {
+        let policy = constraint.rich_context.as_ref()?.get(POLICY_CONTEXT_KEY)?;
+        match serde_json::from_value(policy.clone()) {
+            Ok(policy) => Some(policy),
+            Err(e) => {
+                tracing::warn!("Ignoring malformed policy on '{}': {}", constraint.name, e);
+                None
+            }
+        }
+    }
+
+    /// Deny matches in `code` that no allow pattern exempts
+    ///
+    /// Patterns that fail to compile (possible only for policies built
+    /// without [`deny`](Self::deny) and [`allow`](Self::allow)) are skipped
+    /// with a warning.
+    pub fn scan(&self, code: &str) -> Vec<PolicyViolation> {
+        let allow: Vec<Regex> = self
+            .allow
+            .iter()
+            .filter_map(|pattern| self.compile(&format!("^(?:{})$", pattern)))
+            .collect();
+
+        let mut violations = Vec::new();
+        for pattern in &self.deny {
+            let Some(deny) = self.compile(pattern) else {
+                continue;
+            };
+            for found in deny.find_iter(code) {
+                if found.as_str().is_empty() || allow.iter().any(|a| a.is_match(found.as_str())) {
+                    continue;
+                }
+                violations.push(PolicyViolation {
+                    policy: self.name.clone(),
+                    pattern: pattern.clone(),
+                    matched: found.as_str().to_string(),
+                    line: code[..found.start()].matches('\n').count() + 1,
+                });
+            }
+        }
+        violations.sort_by_key(|v| v.line);
+        violations
+    }
+
+    /// Tokens `tokenizer` has for the literal text a deny match starts with,
+    /// to mask out during decoding
+    ///
+    /// Each deny pattern's literal prefixes are encoded alone and after a
+    /// space, which many vocabularies fold into the token; prefixes that
+    /// encode to a single token give that token. Longer prefixes, and
+    /// patterns with no literal prefix, are left to the output scan. A
+    /// policy with allow patterns gives none, since a mask cannot exempt
+    /// matches. Empty unless `tokenizer` can [`encode`](TokenEstimator::encode).
+    pub fn denied_tokens(&self, tokenizer: &dyn TokenEstimator) -> Vec<u32> {
+        use regex_syntax::hir::literal::{ExtractKind, Extractor};
+
+        if !self.allow.is_empty() {
+            return vec![];
+        }
+        let mut tokens = Vec::new();
+        for pattern in &self.deny {
+            let Ok(hir) = regex_syntax::parse(pattern) else {
+                continue;
+            };
+            let prefixes = Extractor::new().kind(ExtractKind::Prefix).extract(&hir);
+            for literal in prefixes.literals().into_iter().flatten() {
+                let Ok(text) = std::str::from_utf8(literal.as_bytes()) else {
+                    continue;
+                };
+                if text.is_empty() {
+                    continue;
+                }
+                for variant in [text.to_string(), format!(" {}", text)] {
+                    if let Some([token]) = tokenizer.encode(&variant).as_deref() {
+                        tokens.push(*token);
+                    }
+                }
+            }
+        }
+        tokens.sort_unstable();
+        tokens.dedup();
+        tokens
+    }
+
+    fn compile(&self, pattern: &str) -> Option<Regex> {
+        Regex::new(pattern)
+            .inspect_err(|e| {
+                tracing::warn!("Skipping policy '{}' regex /{}/: {}", self.name, pattern, e)
+            })
+            .ok()
+    }
+}
+
+/// Policies carried by `constraints`, paired with their index
+pub fn policies(constraints: &[ConstraintIR]) -> Vec<(usize, PolicyConstraint)> {
+    constraints
+        .iter()
+        .enumerate()
+        .filter_map(|(index, c)| PolicyConstraint::from_constraint(c).map(|p| (index, p)))
+        .collect()
+}
+
+/// Add each policy's [`denied_tokens`](PolicyConstraint::denied_tokens) to
+/// the forbidden tokens of the constraint carrying it
+///
+/// Returns whether any constraint changed.
+pub(crate) fn mask_denied_tokens(
+    constraints: &mut [ConstraintIR],
+    tokenizer: &dyn TokenEstimator,
+) -> bool {
+    let mut changed = false;
+    for (index, policy) in policies(constraints) {
+        let denied = policy.denied_tokens(tokenizer);
+        if denied.is_empty() {
+            continue;
+        }
+        let masks = constraints[index]
+            .token_masks
+            .get_or_insert(TokenMaskRules {
+                allowed_tokens: None,
+                forbidden_tokens: None,
+            });
+        let forbidden = masks.forbidden_tokens.get_or_insert_with(Vec::new);
+        for token in denied {
+            if !forbidden.contains(&token) {
+                forbidden.push(token);
+                changed = true;
+            }
+        }
+    }
+    changed
+}
+
+/// Generated code matching a deny pattern of a [`PolicyConstraint`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyViolation {
+    /// Name of the violated policy
+    pub policy: String,
+
+    /// Deny pattern that matched
+    pub pattern: String,
+
+    /// Text that matched
+    pub matched: String,
+
+    /// 1-based line of the match
+    pub line: usize,
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "policy '{}': line {}: `{}` matches denied pattern /{}/",
+            self.policy, self.line, self.matched, self.pattern
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scan_reports_deny_matches_not_exempted_by_allow() {
+        let policy = PolicyConstraint::new("no_os")
+            .deny(r"\bos\.\w+")
+            .unwrap()
+            .allow(r"os\.path")
+            .unwrap();
+
+        let code = "import os\np = os.path.join(a, b)\nos.system(cmd)\n";
+        let violations = policy.scan(code);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].matched, "os.system");
+        assert_eq!(violations[0].line, 3);
+        assert_eq!(
+            violations[0].to_string(),
+            r"policy 'no_os': line 3: `os.system` matches denied pattern /\bos\.\w+/"
+        );
+    }
+
+    #[test]
+    fn test_constraint_round_trips_policy_and_masks_tokens() {
+        let policy = PolicyConstraint::new("no_eval")
+            .deny(r"\beval\(")
+            .unwrap()
+            .forbid_tokens([101, 102])
+            .regenerate_on_violation(true);
+
+        let constraint = policy.to_constraint();
+        assert_eq!(constraint.priority, DEFAULT_POLICY_PRIORITY);
+        assert_eq!(
+            constraint.token_masks.as_ref().unwrap().forbidden_tokens,
+            Some(vec![101, 102])
+        );
+        assert_eq!(PolicyConstraint::from_constraint(&constraint), Some(policy));
+        assert_eq!(policies(&[constraint]).len(), 1);
+    }
+
+    /// Encodes words in its vocabulary as one token, anything else as two
+    struct Vocab(&'static [(&'static str, u32)]);
+
+    impl TokenEstimator for Vocab {
+        fn estimate(&self, text: &str) -> usize {
+            text.len()
+        }
+
+        fn encode(&self, text: &str) -> Option<Vec<u32>> {
+            Some(match self.0.iter().find(|(word, _)| *word == text) {
+                Some((_, token)) => vec![*token],
+                None => vec![0, 0],
+            })
+        }
+    }
+
+    #[test]
+    fn test_denied_tokens_mask_single_token_prefixes() {
+        let vocab = Vocab(&[("eval", 7), (" eval", 8), ("exec", 9)]);
+        let policy = PolicyConstraint::new("no_eval")
+            .deny(r"\beval\s*\(")
+            .unwrap()
+            .deny(r"os\.system")
+            .unwrap()
+            .forbid_tokens([7]);
+        assert_eq!(policy.denied_tokens(&vocab), vec![7, 8]);
+        assert!(policy
+            .denied_tokens(&crate::CharRatioEstimator::default())
+            .is_empty());
+        let exempting = policy.clone().allow("eval").unwrap();
+        assert!(exempting.denied_tokens(&vocab).is_empty());
+
+        let mut constraints = vec![policy.to_constraint()];
+        assert!(mask_denied_tokens(&mut constraints, &vocab));
+        assert_eq!(
+            constraints[0]
+                .token_masks
+                .as_ref()
+                .unwrap()
+                .forbidden_tokens,
+            Some(vec![7, 8])
+        );
+        assert!(!mask_denied_tokens(&mut constraints, &vocab));
+    }
+
+    #[test]
+    fn test_invalid_pattern_is_rejected() {
+        let err = PolicyConstraint::new("broken").deny("(").unwrap_err();
+        assert_eq!(err.constraint, "broken");
+        assert!(err.reason.contains("invalid policy regex"));
+    }
+}
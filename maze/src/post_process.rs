@@ -0,0 +1,710 @@
+//! Language-aware cleanup of generated code
+//!
+//! Models regularly return more than the fill asked for: the code wrapped in
+//! a markdown fence with prose around it, an extra closing brace that belongs
+//! to the code after the hole, a trailing `fn main()` with example usage, or
+//! a half-written statement where the token limit cut them off. A
+//! [`PostProcessConfig`] removes these before the output is validated. The
+//! bracket and statement rules are keyed on `GenerationContext::language`;
+//! for languages without rules only fences and stop sequences apply.
+//!
+//! Only fence stripping is on by default. The other steps cut output on
+//! heuristics that can be wrong for valid code, so they are opt-in (see
+//! [`PostProcessConfig::all`]), and they never run on output generated
+//! under a grammar or regex constraint, which decoding already shaped.
+
+use serde::{Deserialize, Serialize};
+
+/// Which cleanup steps to run on generated code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessConfig {
+    /// Run post-processing at all
+    pub enabled: bool,
+
+    /// Keep only the contents of the first markdown code fence
+    pub strip_code_fences: bool,
+
+    /// Cut the output at the language's default stop sequences (see
+    /// [`default_stop_sequences`]) and at `extra_stop_sequences`
+    pub stop_sequences: bool,
+
+    /// Cut the output at the first closing bracket with no opening partner
+    pub balance_brackets: bool,
+
+    /// Drop trailing lines left mid-expression, such as an unclosed call,
+    /// an unterminated string, or a line ending in a binary operator
+    pub trim_partial_statements: bool,
+
+    /// Stop sequences applied in addition to the language defaults
+    pub extra_stop_sequences: Vec<String>,
+}
+
+impl Default for PostProcessConfig {
+    /// Strip code fences only
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            strip_code_fences: true,
+            stop_sequences: false,
+            balance_brackets: false,
+            trim_partial_statements: false,
+            extra_stop_sequences: Vec::new(),
+        }
+    }
+}
+
+/// A cleanup step that changed the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostProcessStep {
+    /// Removed a markdown code fence and anything around it
+    StrippedCodeFence,
+
+    /// Cut the output at a stop sequence
+    StopSequence,
+
+    /// Cut the output at an unbalanced closing bracket
+    UnbalancedBracket,
+
+    /// Removed a trailing partial statement
+    PartialStatement,
+
+    /// Reformatted by the language's formatter (see
+    /// [`FormatPolicy`](crate::FormatPolicy))
+    Formatted,
+}
+
+/// Output of [`PostProcessConfig::apply`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostProcessed {
+    /// The cleaned code
+    pub code: String,
+
+    /// Steps that changed the code, in the order they ran
+    pub steps: Vec<PostProcessStep>,
+}
+
+impl PostProcessed {
+    /// Whether any step changed the code
+    pub fn changed(&self) -> bool {
+        !self.steps.is_empty()
+    }
+}
+
+impl PostProcessConfig {
+    /// Configuration that leaves output untouched
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Default::default()
+        }
+    }
+
+    /// Configuration that runs every step
+    pub fn all() -> Self {
+        Self {
+            stop_sequences: true,
+            balance_brackets: true,
+            trim_partial_statements: true,
+            ..Default::default()
+        }
+    }
+
+    /// This configuration with only fence stripping left on, for output
+    /// whose shape a grammar or regex constraint already enforced
+    pub fn fences_only(&self) -> Self {
+        Self {
+            stop_sequences: false,
+            balance_brackets: false,
+            trim_partial_statements: false,
+            ..self.clone()
+        }
+    }
+
+    /// Clean `code` generated for `language`
+    pub fn apply(&self, language: Option<&str>, code: &str) -> PostProcessed {
+        let mut processed = PostProcessed {
+            code: code.to_string(),
+            steps: Vec::new(),
+        };
+        if !self.enabled {
+            return processed;
+        }
+        let syntax = language.and_then(Syntax::for_language);
+
+        if self.strip_code_fences {
+            if let Some(inner) = strip_code_fence(&processed.code) {
+                processed.code = inner;
+                processed.steps.push(PostProcessStep::StrippedCodeFence);
+            }
+        }
+
+        if self.stop_sequences {
+            let stops = default_stop_sequences(language)
+                .into_iter()
+                .chain(self.extra_stop_sequences.iter().map(String::as_str));
+            let cut = stops
+                .filter(|stop| !stop.is_empty())
+                .filter_map(|stop| processed.code.find(stop))
+                .min();
+            if let Some(cut) = cut {
+                processed.code = processed.code[..cut].trim_end().to_string();
+                processed.steps.push(PostProcessStep::StopSequence);
+            }
+        }
+
+        let Some(syntax) = syntax else {
+            return processed;
+        };
+
+        if self.balance_brackets {
+            if let Some(cut) = syntax.scan(&processed.code).unmatched_closer {
+                processed.code = processed.code[..cut].trim_end().to_string();
+                processed.steps.push(PostProcessStep::UnbalancedBracket);
+            }
+        }
+
+        if self.trim_partial_statements {
+            if let Some(trimmed) = syntax.trim_partial_statements(&processed.code) {
+                processed.code = trimmed;
+                processed.steps.push(PostProcessStep::PartialStatement);
+            }
+        }
+
+        processed
+    }
+}
+
+/// Special tokens some models emit instead of stopping
+const MODEL_STOP_SEQUENCES: &[&str] = &["<|endoftext|>", "<|im_end|>", "<|file_separator|>"];
+
+/// Stop sequences applied to output generated for `language`
+///
+/// Besides special tokens models emit instead of stopping, these mark
+/// trailing example code that models like to append after a fill, such as
+/// a `fn main()` in Rust or an `if __name__ == ...` block in Python.
+pub fn default_stop_sequences(language: Option<&str>) -> Vec<&'static str> {
+    let mut stops = MODEL_STOP_SEQUENCES.to_vec();
+    if let Some(syntax) = language.and_then(Syntax::for_language) {
+        stops.extend_from_slice(syntax.stop_sequences);
+    }
+    stops
+}
+
+/// The contents of the first markdown code fence in `code`, if it has one
+///
+/// Prose before the opening fence and everything from the closing fence on
+/// is dropped; an unclosed fence runs to the end of the output.
+fn strip_code_fence(code: &str) -> Option<String> {
+    let mut offset = 0;
+    let mut start = None;
+    for line in code.split_inclusive('\n') {
+        let is_fence = line.trim_start().starts_with("```");
+        match start {
+            None if is_fence => start = Some(offset + line.len()),
+            Some(start) if is_fence => {
+                return Some(code[start..offset].trim_end().to_string());
+            }
+            _ => {}
+        }
+        offset += line.len();
+    }
+    start.map(|start| code[start..].trim_end().to_string())
+}
+
+/// Lexical rules needed to find brackets outside strings and comments
+#[derive(Debug)]
+struct Syntax {
+    line_comment: &'static str,
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+
+    /// Python-style `"""` and `'''` strings
+    triple_quotes: bool,
+
+    /// `'` starts a char literal only when one follows (Rust lifetimes)
+    char_literals: bool,
+
+    /// `{` opens a block of statements rather than an expression
+    block_braces: bool,
+
+    /// Rust-style `r"..."` and `r#"..."#` strings, with no escapes
+    raw_strings: bool,
+
+    /// JavaScript-style `/.../flags` regex literals
+    regex_literals: bool,
+
+    /// Line endings that leave an expression unfinished
+    continuations: &'static [&'static str],
+
+    stop_sequences: &'static [&'static str],
+}
+
+/// Operators that cannot end an expression in C-family languages
+const C_CONTINUATIONS: &[&str] = &[
+    "=", "+", "-", "*", "/", "%", "&&", "||", "&", "|", "^", ".", "::", "->", "=>", "<",
+];
+
+const RUST: Syntax = Syntax {
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    triple_quotes: false,
+    char_literals: true,
+    block_braces: true,
+    raw_strings: true,
+    regex_literals: false,
+    continuations: C_CONTINUATIONS,
+    stop_sequences: &["\nfn main()", "\n#[cfg(test)]"],
+};
+
+const JAVASCRIPT: Syntax = Syntax {
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+    triple_quotes: false,
+    char_literals: false,
+    block_braces: true,
+    raw_strings: false,
+    regex_literals: true,
+    continuations: C_CONTINUATIONS,
+    stop_sequences: &["\n// Example usage"],
+};
+
+const GO: Syntax = Syntax {
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+    triple_quotes: false,
+    char_literals: false,
+    block_braces: true,
+    raw_strings: false,
+    regex_literals: false,
+    continuations: C_CONTINUATIONS,
+    stop_sequences: &["\nfunc main()"],
+};
+
+const C_FAMILY: Syntax = Syntax {
+    line_comment: "//",
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\''],
+    triple_quotes: false,
+    char_literals: false,
+    block_braces: true,
+    raw_strings: false,
+    regex_literals: false,
+    continuations: C_CONTINUATIONS,
+    stop_sequences: &["\n// Example usage"],
+};
+
+const PYTHON: Syntax = Syntax {
+    line_comment: "#",
+    block_comment: None,
+    quotes: &['"', '\''],
+    triple_quotes: true,
+    char_literals: false,
+    block_braces: false,
+    raw_strings: false,
+    regex_literals: false,
+    continuations: &[
+        "=", "+", "-", "*", "/", "%", "<", ">", "and", "or", "not", "in", "is", ".", "\\", ":",
+        ",", "@",
+    ],
+    stop_sequences: &["\nif __name__ ==", "\n# Example usage"],
+};
+
+/// Result of scanning code for brackets
+#[derive(Debug, Default)]
+struct Scan {
+    /// Byte offset of the first closing bracket with no opening partner
+    unmatched_closer: Option<usize>,
+
+    /// Openers (brackets, strings, block comments) still open at the end,
+    /// outermost first
+    unclosed: Vec<(usize, char)>,
+}
+
+impl Syntax {
+    fn for_language(language: &str) -> Option<&'static Syntax> {
+        match language.to_ascii_lowercase().as_str() {
+            "rust" | "rs" => Some(&RUST),
+            "python" | "py" => Some(&PYTHON),
+            "javascript" | "js" | "jsx" | "typescript" | "ts" | "tsx" => Some(&JAVASCRIPT),
+            "go" | "golang" => Some(&GO),
+            "c" | "cpp" | "c++" | "java" | "csharp" | "c#" | "cs" | "kotlin" | "swift" | "zig" => {
+                Some(&C_FAMILY)
+            }
+            _ => None,
+        }
+    }
+
+    /// Find unbalanced brackets, skipping strings and comments
+    fn scan(&self, code: &str) -> Scan {
+        let mut scan = Scan::default();
+        let mut i = 0;
+        while i < code.len() {
+            let rest = &code[i..];
+            let c = rest.chars().next().unwrap_or_default();
+
+            if rest.starts_with(self.line_comment) {
+                i += rest.find('\n').unwrap_or(rest.len());
+                continue;
+            }
+            if let Some((open, close)) = self.block_comment {
+                if let Some(comment) = rest.strip_prefix(open) {
+                    match comment.find(close) {
+                        Some(end) => i += open.len() + end + close.len(),
+                        None => {
+                            scan.unclosed.push((i, '*'));
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            }
+            if self.triple_quotes && (rest.starts_with("\"\"\"") || rest.starts_with("'''")) {
+                match rest[3..].find(&rest[..3]) {
+                    Some(end) => i += 3 + end + 3,
+                    None => {
+                        scan.unclosed.push((i, c));
+                        break;
+                    }
+                }
+                continue;
+            }
+            if self.raw_strings && starts_token(code, i) {
+                if let Some(end) = raw_string_end(rest) {
+                    match end {
+                        Some(end) => i += end,
+                        None => {
+                            scan.unclosed.push((i, '"'));
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            }
+            if c == '/' && self.regex_literals && regex_allowed(&code[..i]) {
+                if let Some(end) = regex_literal_end(rest) {
+                    i += end;
+                    continue;
+                }
+            }
+            if self.quotes.contains(&c) {
+                if c == '\'' && self.char_literals && !is_char_literal(rest) {
+                    i += 1;
+                    continue;
+                }
+                match string_end(rest, c) {
+                    Some(end) => i += end,
+                    None => {
+                        scan.unclosed.push((i, c));
+                        break;
+                    }
+                }
+                continue;
+            }
+
+            match c {
+                '(' | '[' | '{' => scan.unclosed.push((i, c)),
+                ')' | ']' | '}' => {
+                    let opener = match c {
+                        ')' => '(',
+                        ']' => '[',
+                        _ => '{',
+                    };
+                    if scan.unclosed.last().map(|&(_, open)| open) == Some(opener) {
+                        scan.unclosed.pop();
+                    } else {
+                        scan.unmatched_closer = Some(i);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            i += c.len_utf8();
+        }
+        scan
+    }
+
+    /// `code` without trailing partial statements, if it has any
+    ///
+    /// Cuts back to the line holding the outermost unclosed expression (a
+    /// call, index, string, or comment; unclosed blocks are left alone),
+    /// then drops final lines ending in a continuation. Never empties the
+    /// output.
+    fn trim_partial_statements(&self, code: &str) -> Option<String> {
+        let mut trimmed = code.trim_end();
+
+        let scan = self.scan(trimmed);
+        let expression = scan
+            .unclosed
+            .iter()
+            .find(|&&(_, open)| !(self.block_braces && open == '{'));
+        if let Some(&(offset, _)) = expression {
+            let line_start = trimmed[..offset].rfind('\n').map_or(0, |n| n + 1);
+            trimmed = trimmed[..line_start].trim_end();
+        }
+
+        while let Some((rest, last_line)) = trimmed.rsplit_once('\n') {
+            if !self.ends_in_continuation(last_line) {
+                break;
+            }
+            trimmed = rest.trim_end();
+        }
+
+        if trimmed.is_empty() || trimmed.len() == code.len() {
+            None
+        } else {
+            Some(trimmed.to_string())
+        }
+    }
+
+    fn ends_in_continuation(&self, line: &str) -> bool {
+        let line = line.trim_end();
+        if line.trim_start().starts_with(self.line_comment) {
+            return false;
+        }
+        // Increments, decrements, open ranges, and glob imports end lines
+        if ["++", "--", "..", "import *"]
+            .iter()
+            .any(|op| line.ends_with(op))
+        {
+            return false;
+        }
+        self.continuations.iter().any(|op| {
+            line.ends_with(op) && {
+                // Word operators must stand alone, not end an identifier
+                let before = &line[..line.len() - op.len()];
+                !op.starts_with(char::is_alphabetic)
+                    || before.is_empty()
+                    || before.ends_with(char::is_whitespace)
+            }
+        })
+    }
+}
+
+/// Whether the `'` starting `rest` opens a char literal rather than a lifetime
+fn is_char_literal(rest: &str) -> bool {
+    let mut chars = rest.chars().skip(1);
+    matches!(
+        (chars.next(), chars.next()),
+        (Some('\\'), _) | (Some(_), Some('\''))
+    )
+}
+
+/// Whether the character at byte `i` of `code` does not continue an
+/// identifier
+fn starts_token(code: &str, i: usize) -> bool {
+    !code[..i]
+        .chars()
+        .next_back()
+        .is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Byte length of the raw string starting `rest` (`r"..."`, `r#"..."#`,
+/// optionally `b`-prefixed), `Some(None)` if it is unterminated, or `None`
+/// if `rest` does not start one
+fn raw_string_end(rest: &str) -> Option<Option<usize>> {
+    let prefix = if rest.starts_with("br") { 2 } else { 1 };
+    if !rest[prefix - 1..].starts_with('r') {
+        return None;
+    }
+    let hashes = rest[prefix..].bytes().take_while(|&b| b == b'#').count();
+    let body = prefix + hashes;
+    if !rest[body..].starts_with('"') {
+        return None;
+    }
+    let close = format!("\"{}", "#".repeat(hashes));
+    Some(
+        rest[body + 1..]
+            .find(&close)
+            .map(|end| body + 1 + end + close.len()),
+    )
+}
+
+/// Whether a `/` after `before` starts a regex literal rather than dividing
+///
+/// A regex can only appear where an expression starts: after an operator,
+/// an opening bracket, or a keyword such as `return`.
+fn regex_allowed(before: &str) -> bool {
+    let before = before.trim_end();
+    let Some(last) = before.chars().next_back() else {
+        return true;
+    };
+    if "(,=:[!&|?{};+-*%<>~^".contains(last) {
+        return true;
+    }
+    let word_start = before
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .map_or(0, |n| n + 1);
+    matches!(
+        &before[word_start..],
+        "return"
+            | "typeof"
+            | "case"
+            | "in"
+            | "of"
+            | "delete"
+            | "void"
+            | "throw"
+            | "new"
+            | "yield"
+            | "await"
+    )
+}
+
+/// Byte length of the regex literal starting `rest`, including its flags;
+/// `None` if the line ends first
+fn regex_literal_end(rest: &str) -> Option<usize> {
+    let mut escaped = false;
+    let mut in_class = false;
+    for (i, c) in rest.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\n' => return None,
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => {
+                let flags = rest[i + 1..]
+                    .bytes()
+                    .take_while(|b| b.is_ascii_alphabetic())
+                    .count();
+                return Some(i + 1 + flags);
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Byte length of the string literal starting `rest`, including its quotes
+fn string_end(rest: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in rest.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\n' if quote != '`' => return None,
+            c if c == quote => return Some(i + c.len_utf8()),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean(language: &str, code: &str) -> PostProcessed {
+        PostProcessConfig::all().apply(Some(language), code)
+    }
+
+    #[test]
+    fn test_default_only_strips_fences() {
+        let config = PostProcessConfig::default();
+        let code = "a + b\n}\n\nfn main() {\n    run(\n";
+        assert!(!config.apply(Some("rust"), code).changed());
+        assert_eq!(
+            config.apply(Some("rust"), "```rust\nx\n```").steps,
+            vec![PostProcessStep::StrippedCodeFence]
+        );
+        assert_eq!(PostProcessConfig::all().fences_only(), config);
+    }
+
+    #[test]
+    fn test_regex_literals_and_raw_strings_keep_their_brackets() {
+        let code = "const re = /}/;\nconst parts = s.split(/[)\\]]/g);\nconst half = a / 2;";
+        assert!(!clean("javascript", code).changed());
+        assert_eq!(
+            clean("javascript", "const n = total / (count + 1);\n}").code,
+            "const n = total / (count + 1);"
+        );
+        assert!(!clean("javascript", "run();\nconsole.log(run());").changed());
+
+        let code = "let s = r#\"a\" }\"#;\nlet t = br\"(\";\nlet r = bar(1);";
+        assert!(!clean("rust", code).changed());
+        assert_eq!(
+            clean("rust", "let a = 1;\nlet s = r#\"open").code,
+            "let a = 1;"
+        );
+    }
+
+    #[test]
+    fn test_strips_fence_and_surrounding_prose() {
+        let processed = clean(
+            "rust",
+            "Here is the fill:\n```rust\nlet x = 1;\nx + 1\n```\nThis adds one.",
+        );
+        assert_eq!(processed.code, "let x = 1;\nx + 1");
+        assert_eq!(processed.steps, vec![PostProcessStep::StrippedCodeFence]);
+
+        // Unclosed fences run to the end
+        assert_eq!(clean("python", "```python\nreturn x\n").code, "return x");
+    }
+
+    #[test]
+    fn test_cuts_extra_closing_brace_ignoring_strings_and_comments() {
+        let processed = clean(
+            "rust",
+            "if x { '}' } // }\nlet s = \"}\"; fn f<'a>(v: &'a str) {}\n}\n\nfn next() {}",
+        );
+        assert_eq!(
+            processed.code,
+            "if x { '}' } // }\nlet s = \"}\"; fn f<'a>(v: &'a str) {}"
+        );
+        assert_eq!(processed.steps, vec![PostProcessStep::UnbalancedBracket]);
+    }
+
+    #[test]
+    fn test_trims_trailing_partial_statements() {
+        assert_eq!(
+            clean("typescript", "const a = 1;\nconst b = compute(a,\n  2").code,
+            "const a = 1;"
+        );
+        assert_eq!(clean("go", "x := 1\ny := x +").code, "x := 1");
+        assert_eq!(
+            clean(
+                "python",
+                "total = 0\nfor x in xs:\n    total += x\nif total >"
+            )
+            .code,
+            "total = 0\nfor x in xs:\n    total += x"
+        );
+        assert_eq!(
+            clean("python", "doc = 1\ns = \"\"\"unfinished\n").code,
+            "doc = 1"
+        );
+
+        // Unclosed blocks, increments, and ranges are not partial statements
+        assert!(!clean("rust", "fn f() {\n    let a = 1;").changed());
+        assert!(!clean("c", "int i = 0;\ni++").changed());
+        assert!(!clean("rust", "let r = 0..").changed());
+    }
+
+    #[test]
+    fn test_stop_sequences_and_unknown_languages() {
+        let processed = clean(
+            "rust",
+            "a + b\n\nfn main() {\n    println!(\"{}\", add(1, 2));\n}",
+        );
+        assert_eq!(processed.code, "a + b");
+        assert_eq!(processed.steps, vec![PostProcessStep::StopSequence]);
+
+        // Without language rules only fences and generic stops apply
+        let processed = PostProcessConfig::all().apply(None, "```\nx = (1,\n```<|endoftext|>");
+        assert_eq!(processed.code, "x = (1,");
+
+        let config = PostProcessConfig {
+            extra_stop_sequences: vec!["// END".to_string()],
+            ..PostProcessConfig::all()
+        };
+        assert_eq!(config.apply(Some("zig"), "a;\n// END\nb;").code, "a;");
+        assert!(!PostProcessConfig::disabled()
+            .apply(Some("rust"), "}")
+            .changed());
+    }
+}
@@ -3,12 +3,31 @@
 //! Implements iterative constraint-driven code generation with progressive
 //! refinement, supporting multiple fill strategies and dependency resolution.
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::budget::{
+    BudgetAccountant, BudgetExhaustedAction, BudgetState, Reservation, TokenBudget,
+};
+use crate::calibration::ConfidenceCalibration;
+use crate::constraint_check::{check_constraints, ConstraintCheck, ConstraintKind};
+use crate::context_window::{billed_tokens, load_tokenizer, CharRatioEstimator, TokenEstimator};
 use crate::ffi::{ConstraintIR, HoleSpec};
-use crate::modal_client::{EnsembleClient, InferenceRequest, ModalClient};
+use crate::hole_id::HoleIdAllocator;
+use crate::hole_ordering::{ById, HoleOrdering};
+use crate::inference::InferenceClient;
+use crate::merge::ConstraintMergeReport;
+use crate::modal_client::{
+    ConstraintCostSummary, EnsembleClient, FinishReason, GenerationStats, InferenceRequest,
+    InferenceResponse, ModalClient,
+};
+use crate::model_chain::{self, ChainAttempt, ModelChainConfig};
+use crate::output_guard::OutputGuard;
+use crate::refinement_provenance::RefinementProvenance;
+use crate::runaway::{self, OutputLimit, RunawayGeneration, RunawayLimits};
+use crate::syntax::SyntaxValidator;
 
 /// Configuration for progressive refinement
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,9 +35,25 @@ pub struct RefinementConfig {
     /// Maximum number of refinement iterations
     pub max_iterations: usize,
 
+    /// Fill attempts one hole may make, counting requests that errored,
+    /// before it is marked failed instead of being retried under
+    /// [`FailureStrategy::RetryAlternate`]; `None` lets a hole retry every
+    /// iteration
+    #[serde(default)]
+    pub max_attempts_per_hole: Option<usize>,
+
     /// Minimum confidence threshold to accept a fill (0.0-1.0)
     pub min_confidence: f32,
 
+    /// Confidence recorded for fills whose response has neither logprobs
+    /// nor a service-reported confidence (see
+    /// [`InferenceResponse::confidence`](crate::InferenceResponse::confidence))
+    ///
+    /// The default of 1.0 gates such fills on validation alone; set it
+    /// below `min_confidence` to reject them instead.
+    #[serde(default = "default_unscored_confidence")]
+    pub unscored_confidence: f32,
+
     /// Enable parallel hole filling
     pub parallel_fill: bool,
 
@@ -31,17 +66,140 @@ pub struct RefinementConfig {
 
     /// Enable diffusion model support (experimental)
     pub enable_diffusion: bool,
+
+    /// Token and cost limits for one `refine` call; unlimited by default
+    #[serde(default)]
+    pub budget: TokenBudget,
+
+    /// Rejects empty or whitespace-only fills, which then go through
+    /// `failure_strategy`; the guard's `max_retries` is not used here
+    #[serde(default)]
+    pub output_guard: OutputGuard,
+
+    /// Fallback models for fills that fail, score low, or do not validate;
+    /// applies to the single-client backend (an ensemble routes itself)
+    #[serde(default)]
+    pub model_chain: ModelChainConfig,
+
+    /// Per-model curves applied to raw fill confidence before it is
+    /// compared with `min_confidence` or `model_chain.min_confidence`
+    #[serde(default)]
+    pub calibration: ConfidenceCalibration,
+
+    /// Token limit of a hole's first fill, by hole scale
+    #[serde(default)]
+    pub fill_tokens: FillTokenLimits,
+
+    /// Fail fills that stop at their token limit, and double a hole's limit
+    /// for each such fill, up to this many tokens; `None` accepts fills
+    /// that hit the limit
+    #[serde(default = "default_max_fill_tokens")]
+    pub max_fill_tokens: Option<usize>,
+
+    /// Most output a fill may produce, by hole scale; larger fills are
+    /// rejected with [`FinishReason::Runaway`] and go through
+    /// `failure_strategy`. `None` for no limit
+    #[serde(default = "default_runaway_limits")]
+    pub runaway_limits: Option<RunawayLimits>,
+
+    /// Reject fills that fail a local check of one of their hole's
+    /// constraints (see [`check_constraints`])
+    ///
+    /// Off by default, since the checks are approximate: the service
+    /// enforces the constraints while decoding, and checks are recorded in
+    /// `FillAttempt::constraint_checks` either way.
+    /// [`FailureStrategy::Adaptive`] always rejects.
+    #[serde(default)]
+    pub reject_failed_constraints: bool,
+
+    /// Stream single-model fills, so one crossing its hole's
+    /// `runaway_limits` is cut off then rather than checked once complete
+    ///
+    /// Off by default: streamed fills carry no confidence score, so they
+    /// are rated `unscored_confidence`. Ensemble fills are never streamed.
+    #[serde(default)]
+    pub stream_fills: bool,
+
+    /// The model's `tokenizer.json`, loaded by
+    /// [`ProgressiveRefiner::from_config`] to count fill tokens the service
+    /// does not report against `budget`; requires the `tokenizers` feature
+    #[serde(default)]
+    pub tokenizer_path: Option<std::path::PathBuf>,
+}
+
+/// Token limit of a fill, by the scale of its hole
+///
+/// Larger holes need more tokens: too low a limit truncates fills, too high
+/// a one reserves budget the fill never uses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FillTokenLimits {
+    /// Limit per scale (`nano`, `micro`, `meso`, `macro`, ...)
+    pub by_scale: HashMap<String, usize>,
+
+    /// Limit for scales missing from `by_scale`
+    pub default: usize,
+}
+
+impl Default for FillTokenLimits {
+    fn default() -> Self {
+        Self {
+            by_scale: HashMap::from([
+                ("nano".to_string(), 64),
+                ("micro".to_string(), 256),
+                ("meso".to_string(), 512),
+                ("macro".to_string(), 1024),
+            ]),
+            default: 256,
+        }
+    }
+}
+
+impl FillTokenLimits {
+    /// Limit for holes of `scale`
+    pub fn for_scale(&self, scale: &str) -> usize {
+        self.by_scale.get(scale).copied().unwrap_or(self.default)
+    }
+
+    /// Set the limit for holes of `scale`
+    pub fn with_scale(mut self, scale: impl Into<String>, max_tokens: usize) -> Self {
+        self.by_scale.insert(scale.into(), max_tokens);
+        self
+    }
+}
+
+fn default_unscored_confidence() -> f32 {
+    1.0
+}
+
+fn default_max_fill_tokens() -> Option<usize> {
+    Some(4096)
+}
+
+fn default_runaway_limits() -> Option<RunawayLimits> {
+    Some(RunawayLimits::default())
 }
 
 impl Default for RefinementConfig {
     fn default() -> Self {
         Self {
             max_iterations: 10,
+            max_attempts_per_hole: None,
             min_confidence: 0.8,
+            unscored_confidence: default_unscored_confidence(),
             parallel_fill: true,
             temperature_schedule: vec![0.9, 0.7, 0.5, 0.3, 0.1],
             failure_strategy: FailureStrategy::RetryAlternate,
             enable_diffusion: false,
+            budget: TokenBudget::default(),
+            output_guard: OutputGuard::default(),
+            model_chain: ModelChainConfig::default(),
+            calibration: ConfidenceCalibration::default(),
+            fill_tokens: FillTokenLimits::default(),
+            max_fill_tokens: default_max_fill_tokens(),
+            runaway_limits: default_runaway_limits(),
+            reject_failed_constraints: false,
+            stream_fills: false,
+            tokenizer_path: None,
         }
     }
 }
@@ -60,6 +218,12 @@ pub enum FailureStrategy {
 
     /// Retry with alternate parameters/models
     RetryAlternate,
+
+    /// Choose from which constraints the fill failed: decompose when a
+    /// structural constraint failed (retrying nano-scale holes, which cannot
+    /// be decomposed), retry otherwise. Implies
+    /// `RefinementConfig::reject_failed_constraints`
+    Adaptive,
 }
 
 /// Status of a hole during refinement
@@ -93,9 +257,14 @@ pub struct FillAttempt {
     /// The generated fill code
     pub code: String,
 
-    /// Confidence score (0.0-1.0)
+    /// Confidence score (0.0-1.0), after calibration
     pub confidence: f32,
 
+    /// Confidence as reported by the model, before calibration; `None` for
+    /// unscored fills
+    #[serde(default)]
+    pub raw_confidence: Option<f32>,
+
     /// Temperature used for this attempt
     pub temperature: f32,
 
@@ -110,6 +279,38 @@ pub struct FillAttempt {
 
     /// Error message if validation failed
     pub error: Option<String>,
+
+    /// Tokens billed for this attempt
+    #[serde(default)]
+    pub tokens_generated: usize,
+
+    /// Generation and constraint-check statistics reported for the attempt
+    #[serde(default)]
+    pub stats: Option<GenerationStats>,
+
+    /// Models tried for this attempt, when a fallback chain is configured
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub model_chain: Vec<ChainAttempt>,
+
+    /// Why generation of the fill ended
+    #[serde(default)]
+    pub finish_reason: FinishReason,
+
+    /// Result of checking the fill against each of the hole's constraints;
+    /// empty when the fill was rejected before they were checked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub constraint_checks: Vec<ConstraintCheck>,
+}
+
+impl FillAttempt {
+    /// Names of the constraints this fill did not satisfy
+    pub fn failed_constraints(&self) -> Vec<&str> {
+        self.constraint_checks
+            .iter()
+            .filter(|check| !check.passed)
+            .map(|check| check.constraint.as_str())
+            .collect()
+    }
 }
 
 /// State of a typed hole during refinement
@@ -127,9 +328,20 @@ pub struct HoleState {
     /// Expected type of the fill (optional)
     pub expected_type: Option<String>,
 
-    /// Constraints that must be satisfied
+    /// Constraints that must be satisfied, described in the fill prompt
     pub constraints: Vec<String>,
 
+    /// Names of the `ConstraintIR` entries, from the set passed to
+    /// [`ProgressiveRefiner::refine`], enforced on this hole's fills;
+    /// `None` enforces the whole set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub constraint_refs: Option<Vec<String>>,
+
+    /// Token limit for this hole's first fill, in place of the limit
+    /// `RefinementConfig::fill_tokens` gives its scale
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+
     /// Current fill candidate (if any)
     pub current_fill: Option<String>,
 
@@ -150,6 +362,12 @@ pub struct HoleState {
 
     /// Child hole IDs if this hole has been decomposed
     pub child_ids: Vec<u64>,
+
+    /// Fill requests that failed before producing a fill, so are not in
+    /// `attempts`; they still count toward
+    /// `RefinementConfig::max_attempts_per_hole`
+    #[serde(default)]
+    pub failed_requests: usize,
 }
 
 impl HoleState {
@@ -161,6 +379,8 @@ impl HoleState {
             origin,
             expected_type: None,
             constraints: vec![],
+            constraint_refs: None,
+            max_tokens: None,
             current_fill: None,
             confidence: 0.0,
             attempts: vec![],
@@ -168,10 +388,14 @@ impl HoleState {
             depends_on: vec![],
             parent_id: None,
             child_ids: vec![],
+            failed_requests: 0,
         }
     }
 
     /// Create a child hole from decomposition of a parent hole
+    ///
+    /// Take `id` from [`HoleIdAllocator::child_id`] so it is unique and the
+    /// same on every run.
     pub fn new_child(id: u64, parent: &HoleState, scale: String, origin: String) -> Self {
         Self {
             id,
@@ -179,6 +403,8 @@ impl HoleState {
             origin,
             expected_type: parent.expected_type.clone(),
             constraints: parent.constraints.clone(),
+            constraint_refs: parent.constraint_refs.clone(),
+            max_tokens: None,
             current_fill: None,
             confidence: 0.0,
             attempts: vec![],
@@ -186,9 +412,26 @@ impl HoleState {
             depends_on: vec![],
             parent_id: Some(parent.id),
             child_ids: vec![],
+            failed_requests: 0,
         }
     }
 
+    /// Enforce only the named constraints on this hole's fills
+    pub fn with_constraint_refs<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.constraint_refs = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Start this hole's fills at `max_tokens` instead of its scale's limit
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
     /// Check if all dependencies are satisfied
     pub fn dependencies_satisfied(&self, hole_states: &HashMap<u64, HoleState>) -> bool {
         self.depends_on.iter().all(|dep_id| {
@@ -211,7 +454,8 @@ pub struct RefinementResult {
     /// The refined code with filled holes
     pub code: String,
 
-    /// Final state of all holes
+    /// Final state of all holes, in input order with each decomposed hole
+    /// followed by its children in decomposition order
     pub holes: Vec<HoleState>,
 
     /// Whether refinement completed successfully
@@ -223,10 +467,146 @@ pub struct RefinementResult {
     /// Number of iterations performed
     pub iterations: usize,
 
+    /// Constraints failed by the last attempt at each hole left unfilled,
+    /// by hole ID
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unsatisfied_constraints: BTreeMap<u64, Vec<String>>,
+
+    /// Models, constraints, temperatures and times behind each hole's fill
+    #[serde(default)]
+    pub provenance: RefinementProvenance,
+
     /// Additional metadata about the refinement
     pub metadata: RefinementMetadata,
 }
 
+impl RefinementResult {
+    /// How holes were decomposed, as a tree per top-level hole
+    ///
+    /// See [`decomposition_tree`].
+    pub fn decomposition_tree(&self) -> Vec<DecompositionNode> {
+        decomposition_tree(&self.holes)
+    }
+}
+
+/// A hole and the sub-holes it was decomposed into
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DecompositionNode {
+    /// Hole ID
+    pub hole_id: u64,
+
+    /// Scale of the hole (nano, micro, meso, macro)
+    pub scale: String,
+
+    /// Origin/source location of the hole
+    pub origin: String,
+
+    /// Final status
+    pub status: HoleStatus,
+
+    /// Confidence in the fill; the children's average for a decomposed hole
+    pub confidence: f32,
+
+    /// The fill; for a decomposed hole, its children's fills joined by lines
+    pub fill: Option<String>,
+
+    /// Fill attempts made on this hole itself
+    pub attempts: usize,
+
+    /// Sub-holes in decomposition order
+    pub children: Vec<DecompositionNode>,
+}
+
+impl DecompositionNode {
+    /// Levels of decomposition below this node; 0 for an undecomposed hole
+    pub fn depth(&self) -> usize {
+        self.children
+            .iter()
+            .map(|child| child.depth() + 1)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// `states` in the order of `roots`, each hole followed by its children
+/// (depth first, in `child_ids` order)
+fn tree_order(roots: &[u64], states: &HashMap<u64, HoleState>) -> Vec<HoleState> {
+    fn visit(
+        id: u64,
+        states: &HashMap<u64, HoleState>,
+        visited: &mut HashSet<u64>,
+        ordered: &mut Vec<HoleState>,
+    ) {
+        let Some(hole) = states.get(&id) else {
+            return;
+        };
+        if !visited.insert(id) {
+            return;
+        }
+        ordered.push(hole.clone());
+        for &child in &hole.child_ids {
+            visit(child, states, visited, ordered);
+        }
+    }
+
+    let mut visited = HashSet::new();
+    let mut ordered = Vec::with_capacity(states.len());
+    for &root in roots {
+        visit(root, states, &mut visited, &mut ordered);
+    }
+    ordered
+}
+
+/// Rebuild the decomposition forest from flat hole states
+///
+/// Roots are holes without a parent (or whose parent is missing), in ID
+/// order; children follow each parent's `child_ids`. Holes that were never
+/// decomposed appear as leaf roots.
+pub fn decomposition_tree(holes: &[HoleState]) -> Vec<DecompositionNode> {
+    let by_id: HashMap<u64, &HoleState> = holes.iter().map(|h| (h.id, h)).collect();
+    let mut roots: Vec<&HoleState> = holes
+        .iter()
+        .filter(|h| {
+            h.parent_id
+                .is_none_or(|parent| !by_id.contains_key(&parent))
+        })
+        .collect();
+    roots.sort_by_key(|h| h.id);
+
+    // Guards against parent links that loop in hand-edited states
+    let mut visited = HashSet::new();
+    roots
+        .into_iter()
+        .filter_map(|hole| decomposition_node(hole, &by_id, &mut visited))
+        .collect()
+}
+
+fn decomposition_node(
+    hole: &HoleState,
+    by_id: &HashMap<u64, &HoleState>,
+    visited: &mut HashSet<u64>,
+) -> Option<DecompositionNode> {
+    if !visited.insert(hole.id) {
+        return None;
+    }
+    let children = hole
+        .child_ids
+        .iter()
+        .filter_map(|id| by_id.get(id))
+        .filter_map(|child| decomposition_node(child, by_id, visited))
+        .collect();
+    Some(DecompositionNode {
+        hole_id: hole.id,
+        scale: hole.scale.clone(),
+        origin: hole.origin.clone(),
+        status: hole.status,
+        confidence: hole.confidence,
+        fill: hole.current_fill.clone(),
+        attempts: hole.attempts.len(),
+        children,
+    })
+}
+
 /// Metadata about the refinement process
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RefinementMetadata {
@@ -250,6 +630,20 @@ pub struct RefinementMetadata {
 
     /// Model usage statistics
     pub model_usage: HashMap<String, usize>,
+
+    /// Spending against `RefinementConfig::budget`
+    #[serde(default)]
+    pub budget: BudgetState,
+
+    /// Constraint enforcement cost across all fill attempts, per kind where
+    /// the backend breaks it down
+    #[serde(default)]
+    pub constraint_cost: ConstraintCostSummary,
+
+    /// Holes marked failed on reaching
+    /// `RefinementConfig::max_attempts_per_hole`
+    #[serde(default)]
+    pub attempt_cap_hits: Vec<u64>,
 }
 
 impl Default for RefinementMetadata {
@@ -262,14 +656,124 @@ impl Default for RefinementMetadata {
             avg_confidence: 0.0,
             iterations: 0,
             model_usage: HashMap::new(),
+            budget: BudgetState::default(),
+            constraint_cost: ConstraintCostSummary::default(),
+            attempt_cap_hits: Vec::new(),
+        }
+    }
+}
+
+/// A hole fill scheduled by [`ProgressiveRefiner::plan`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedFill {
+    /// Hole to fill
+    pub hole_id: u64,
+
+    /// Refinement iteration the fill runs in (0-based)
+    pub iteration: usize,
+
+    /// Sampling temperature for the fill, as scheduled; fills clamp it to
+    /// the model's range
+    pub temperature: f32,
+
+    /// Model the fill would be sent to
+    pub model: String,
+
+    /// Prompt that would be sent
+    pub prompt: String,
+
+    /// Token limit for the fill
+    pub max_tokens: usize,
+}
+
+/// Dry-run output of [`ProgressiveRefiner::plan`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefinementPlan {
+    /// Fills in execution order
+    pub fills: Vec<PlannedFill>,
+
+    /// Number of iterations the plan uses
+    pub iterations: usize,
+
+    /// Holes still pending when `max_iterations` runs out
+    pub unscheduled: Vec<u64>,
+
+    /// How the constraints were merged during compilation
+    pub merge_report: ConstraintMergeReport,
+}
+
+/// Constraints enforced on one hole, ready to send
+struct HoleConstraints {
+    /// Selected entries, in the order of the full set
+    ir: Vec<ConstraintIR>,
+
+    /// `ir` serialized for the inference request
+    payload: serde_json::Value,
+}
+
+/// Per-hole selections from one refinement's constraint set
+///
+/// Each distinct selection is resolved and serialized once, however many
+/// holes and attempts use it.
+struct ConstraintSubsets<'a> {
+    all: &'a [ConstraintIR],
+    cache: std::sync::Mutex<HashMap<Option<Vec<String>>, Arc<HoleConstraints>>>,
+}
+
+impl<'a> ConstraintSubsets<'a> {
+    fn new(all: &'a [ConstraintIR]) -> Self {
+        Self {
+            all,
+            cache: std::sync::Mutex::new(HashMap::new()),
         }
     }
+
+    /// Constraints enforced on `hole`; fails on names not in the set
+    fn for_hole(&self, hole: &HoleState) -> Result<Arc<HoleConstraints>> {
+        let key = hole.constraint_refs.as_ref().map(|refs| {
+            let mut refs = refs.clone();
+            refs.sort_unstable();
+            refs.dedup();
+            refs
+        });
+        if let Some(cached) = self.cache.lock().unwrap().get(&key) {
+            return Ok(cached.clone());
+        }
+
+        let ir = match &key {
+            None => self.all.to_vec(),
+            Some(refs) => {
+                let unknown: Vec<&String> = refs
+                    .iter()
+                    .filter(|name| !self.all.iter().any(|c| &c.name == *name))
+                    .collect();
+                if !unknown.is_empty() {
+                    bail!(
+                        "Hole {} references unknown constraints {:?}",
+                        hole.id,
+                        unknown
+                    );
+                }
+                self.all
+                    .iter()
+                    .filter(|c| refs.contains(&c.name))
+                    .cloned()
+                    .collect()
+            }
+        };
+        let subset = Arc::new(HoleConstraints {
+            payload: serde_json::to_value(&ir)?,
+            ir,
+        });
+        self.cache.lock().unwrap().insert(key, subset.clone());
+        Ok(subset)
+    }
 }
 
 /// Client backend for inference
 pub enum InferenceBackend {
-    /// Single modal client
-    Single(ModalClient),
+    /// Single inference client
+    Single(Arc<dyn InferenceClient>),
     /// Ensemble of multiple models
     Ensemble(EnsembleClient),
 }
@@ -281,14 +785,60 @@ pub struct ProgressiveRefiner {
 
     /// Configuration
     config: RefinementConfig,
+
+    /// Syntax check applied to every fill
+    syntax_validator: Option<Arc<dyn SyntaxValidator>>,
+
+    /// Order in which each iteration's ready holes are filled
+    hole_ordering: Arc<dyn HoleOrdering>,
+
+    /// Models tried in order after the single client
+    fallback_clients: Vec<Arc<dyn InferenceClient>>,
+
+    /// Counts tokens the service does not report, for budget accounting
+    token_estimator: Arc<dyn TokenEstimator>,
 }
 
 impl ProgressiveRefiner {
     /// Create a new progressive refiner with single modal client
+    ///
+    /// Fallback models named in `config.model_chain` are not added, nor is
+    /// `config.tokenizer_path` loaded; use [`from_config`](Self::from_config)
+    /// for those.
     pub fn new(modal_client: ModalClient, config: RefinementConfig) -> Self {
+        Self::with_client(modal_client, config)
+    }
+
+    /// Create a progressive refiner with `modal_client` followed by the
+    /// fallback models named in `config.model_chain`, served from the same
+    /// endpoint as `modal_client`, counting tokens with the tokenizer at
+    /// `config.tokenizer_path` if set
+    pub fn from_config(modal_client: ModalClient, config: RefinementConfig) -> Result<Self> {
+        let fallback_configs = config.model_chain.fallback_configs(modal_client.config());
+        let tokenizer = config
+            .tokenizer_path
+            .as_deref()
+            .map(load_tokenizer)
+            .transpose()?;
+        let mut refiner = Self::new(modal_client, config);
+        for fallback in fallback_configs {
+            refiner = refiner.with_fallback_client(ModalClient::new(fallback)?);
+        }
+        if let Some(tokenizer) = tokenizer {
+            refiner.token_estimator = tokenizer;
+        }
+        Ok(refiner)
+    }
+
+    /// Create a new progressive refiner with any inference client
+    pub fn with_client(client: impl InferenceClient + 'static, config: RefinementConfig) -> Self {
         Self {
-            backend: InferenceBackend::Single(modal_client),
+            backend: InferenceBackend::Single(Arc::new(client)),
             config,
+            syntax_validator: None,
+            hole_ordering: Arc::new(ById),
+            fallback_clients: Vec::new(),
+            token_estimator: Arc::new(CharRatioEstimator::default()),
         }
     }
 
@@ -297,56 +847,309 @@ impl ProgressiveRefiner {
         Self {
             backend: InferenceBackend::Ensemble(ensemble_client),
             config,
+            syntax_validator: None,
+            hole_ordering: Arc::new(ById),
+            fallback_clients: Vec::new(),
+            token_estimator: Arc::new(CharRatioEstimator::default()),
         }
     }
 
+    /// Append a model to try when the ones before it fail a fill, answer
+    /// below `RefinementConfig::model_chain.min_confidence`, or produce an
+    /// invalid fill; ignored with an ensemble backend
+    pub fn with_fallback_client(mut self, client: impl InferenceClient + 'static) -> Self {
+        self.fallback_clients.push(Arc::new(client));
+        self
+    }
+
+    /// Reject fills that fail `validator`, triggering the failure strategy
+    pub fn with_syntax_validator(mut self, validator: impl SyntaxValidator + 'static) -> Self {
+        self.syntax_validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Fill each iteration's ready holes in the order given by `ordering`
+    /// instead of by hole ID
+    pub fn with_hole_ordering(mut self, ordering: impl HoleOrdering + 'static) -> Self {
+        self.hole_ordering = Arc::new(ordering);
+        self
+    }
+
+    /// Count generated tokens with `estimator` when the service does not
+    /// report them, so budgets still see what each fill used
+    ///
+    /// The default estimator assumes ~4 characters per token.
+    pub fn with_token_estimator(mut self, estimator: impl TokenEstimator + 'static) -> Self {
+        self.token_estimator = Arc::new(estimator);
+        self
+    }
+
     /// Fill a single hole using the configured backend
+    #[tracing::instrument(
+        name = "refiner.fill_hole",
+        skip_all,
+        fields(
+            hole_id = hole.id,
+            scale = %hole.scale,
+            attempt = hole.attempts.len() + 1,
+            temperature,
+        )
+    )]
     async fn fill_single_hole_backend(
         &self,
         hole: &HoleState,
-        constraints_ir: &[ConstraintIR],
+        constraints: &HoleConstraints,
         temperature: f32,
     ) -> Result<FillAttempt> {
+        let start = std::time::Instant::now();
         let hole_spec = self.build_hole_spec(hole)?;
+        let request = self.build_request(hole, &constraints.payload, temperature);
 
-        let request = InferenceRequest {
+        // The schedule is clamped to the range of the model that answers
+        let (response, (error, constraint_checks), chain_attempts, temperature) = match &self
+            .backend
+        {
+            InferenceBackend::Single(client) => {
+                let chain: Vec<Arc<dyn InferenceClient>> = std::iter::once(client.clone())
+                    .chain(self.fallback_clients.iter().cloned())
+                    .collect();
+                let ((response, error, sent), attempts) = model_chain::run_chain(
+                    &chain,
+                    self.config.model_chain.min_confidence,
+                    |client, position| {
+                        let mut request = request.clone();
+                        if position > 0 {
+                            request.idempotency_key = request
+                                .idempotency_key
+                                .map(|key| format!("{}-{}", key, client.model_name()));
+                        }
+                        async move {
+                            request.temperature =
+                                client.model_info().await?.clamp_temperature(temperature);
+                            let sent = request.temperature;
+                            let max_tokens = request.max_tokens;
+                            let response = match self.stream_limit(hole) {
+                                Some(limit) => {
+                                    runaway::generate_limited(client.as_ref(), request, limit)
+                                        .await?
+                                }
+                                None => client.generate_constrained(request).await?,
+                            };
+                            let (error, checks) =
+                                self.check_fill(hole, &constraints.ir, &response, max_tokens);
+                            Ok(model_chain::Candidate {
+                                confidence: self.calibrated_confidence(&response),
+                                invalid: error.clone(),
+                                value: (response, (error, checks), sent),
+                            })
+                        }
+                    },
+                )
+                .await?;
+                let attempts = if chain.len() > 1 {
+                    attempts
+                } else {
+                    Vec::new()
+                };
+                (response, error, attempts, sent)
+            }
+            InferenceBackend::Ensemble(ensemble) => {
+                let model = self.planned_model(&hole_spec, &constraints.ir);
+                let sent = ensemble
+                    .model_info(&model)
+                    .await?
+                    .clamp_temperature(temperature);
+                let response = ensemble
+                    .generate_routed(
+                        InferenceRequest {
+                            temperature: sent,
+                            ..request.clone()
+                        },
+                        &hole_spec,
+                        &constraints.ir,
+                    )
+                    .await?;
+                let checked = self.check_fill(hole, &constraints.ir, &response, request.max_tokens);
+                (response, checked, Vec::new(), sent)
+            }
+        };
+
+        let scored = response.confidence();
+        let confidence = self
+            .calibrated_confidence(&response)
+            .unwrap_or(self.config.unscored_confidence);
+        tracing::debug!(
+            model = %response.model,
+            tokens_generated = response.tokens_generated,
+            confidence,
+            scored = scored.is_some(),
+            elapsed_ms = start.elapsed().as_millis() as u64,
+            "Hole fill complete"
+        );
+
+        let finish_reason = if self.runaway(hole, &response.generated_text).is_some() {
+            FinishReason::Runaway
+        } else {
+            response.resolved_finish_reason(request.max_tokens)
+        };
+        let tokens_generated = billed_tokens(
+            response.tokens_generated,
+            &response.generated_text,
+            self.token_estimator.as_ref(),
+        );
+        Ok(FillAttempt {
+            code: response.generated_text,
+            confidence,
+            raw_confidence: scored,
+            temperature,
+            model: response.model,
+            timestamp: chrono::Utc::now().timestamp(),
+            validation_passed: error.is_none(),
+            error,
+            tokens_generated,
+            stats: Some(response.stats),
+            model_chain: chain_attempts,
+            finish_reason,
+            constraint_checks,
+        })
+    }
+
+    /// Confidence of `response` after the responding model's calibration
+    /// curve, if it is scored
+    fn calibrated_confidence(&self, response: &InferenceResponse) -> Option<f32> {
+        response
+            .confidence()
+            .map(|raw| self.config.calibration.calibrate(&response.model, raw))
+    }
+
+    /// Why `response` is rejected as a fill for `hole`, if it is, and how
+    /// it fared against each of `constraints`
+    ///
+    /// An empty fill, one that does not parse, one exceeding its
+    /// `runaway_limits`, (with `max_fill_tokens` set) one that stopped at its
+    /// `max_tokens` limit, or (when [`rejects_failed_constraints`]) one that
+    /// fails any of its constraints is rejected like any failed validation.
+    /// Fills rejected before the constraints are checked have no checks.
+    ///
+    /// [`rejects_failed_constraints`]: Self::rejects_failed_constraints
+    fn check_fill(
+        &self,
+        hole: &HoleState,
+        constraints: &[ConstraintIR],
+        response: &InferenceResponse,
+        max_tokens: usize,
+    ) -> (Option<String>, Vec<ConstraintCheck>) {
+        let code = response.generated_text.as_str();
+        // Checked first: a runaway fill is not retried with a larger limit
+        if let Some(runaway) = self.runaway(hole, code) {
+            tracing::warn!(hole_id = hole.id, "Rejected fill: {}", runaway);
+            return (Some(runaway.to_string()), Vec::new());
+        }
+        if self.config.max_fill_tokens.is_some()
+            && response.resolved_finish_reason(max_tokens) == FinishReason::MaxTokens
+        {
+            tracing::warn!(hole_id = hole.id, max_tokens, "Fill hit its token limit");
+            let error = format!(
+                "fill stopped at its limit of {} tokens and may be incomplete",
+                max_tokens
+            );
+            return (Some(error), Vec::new());
+        }
+        if let Err(rejected) = self.config.output_guard.check(code) {
+            tracing::warn!(hole_id = hole.id, "Rejected fill: {}", rejected);
+            return (Some(rejected.to_string()), Vec::new());
+        }
+        let syntax_errors = self
+            .syntax_validator
+            .as_ref()
+            .map(|v| v.validate(code))
+            .unwrap_or_default();
+        let checks = check_constraints(constraints, code, &syntax_errors);
+
+        let mut problems: Vec<String> = syntax_errors.iter().map(|e| e.to_string()).collect();
+        let rejected = self.rejects_failed_constraints();
+        problems.extend(checks.iter().filter(|c| rejected && !c.passed).map(|c| {
+            format!(
+                "constraint '{}' not satisfied: {}",
+                c.constraint,
+                c.detail.as_deref().unwrap_or_default()
+            )
+        }));
+        ((!problems.is_empty()).then(|| problems.join("; ")), checks)
+    }
+
+    /// Whether fills failing a constraint check are rejected
+    fn rejects_failed_constraints(&self) -> bool {
+        self.config.reject_failed_constraints
+            || self.config.failure_strategy == FailureStrategy::Adaptive
+    }
+
+    /// Why `code` is too large a fill for `hole`, if it is
+    fn runaway(&self, hole: &HoleState, code: &str) -> Option<RunawayGeneration> {
+        let limits = self.config.runaway_limits.as_ref()?;
+        limits.for_scale(&hole.scale).check(code).err()
+    }
+
+    /// Limit to cut a streamed fill of `hole` off at, when fills are streamed
+    fn stream_limit(&self, hole: &HoleState) -> Option<OutputLimit> {
+        let limits = self.config.runaway_limits.as_ref()?;
+        self.config
+            .stream_fills
+            .then(|| limits.for_scale(&hole.scale))
+    }
+
+    /// Reserve budget for one fill of `hole`, or `None` if it does not fit
+    fn reserve_fill(
+        &self,
+        hole: &HoleState,
+        constraints: &HoleConstraints,
+        budget: &mut BudgetAccountant,
+    ) -> Result<Option<Reservation>> {
+        let model = self.planned_model(&self.build_hole_spec(hole)?, &constraints.ir);
+        Ok(budget.reserve(self.estimate_max_tokens(hole), self.cost_per_1k(&model)))
+    }
+
+    /// Cost per 1000 tokens for `model`, if its ensemble endpoint sets one
+    fn cost_per_1k(&self, model: &str) -> Option<f64> {
+        match &self.backend {
+            InferenceBackend::Single(_) => None,
+            InferenceBackend::Ensemble(ensemble) => ensemble
+                .router()
+                .find_endpoint(model)
+                .map(|e| e.cost_per_1k_tokens as f64),
+        }
+    }
+
+    /// Build the inference request for one fill of `hole`
+    fn build_request(
+        &self,
+        hole: &HoleState,
+        constraints: &serde_json::Value,
+        temperature: f32,
+    ) -> InferenceRequest {
+        InferenceRequest {
             prompt: self.build_prompt(hole),
-            constraints: serde_json::to_value(constraints_ir)?,
+            constraints: constraints.clone(),
             max_tokens: self.estimate_max_tokens(hole),
             temperature,
             context: None,
-        };
+            seed: None,
+            idempotency_key: None,
+            include_logprobs: false,
+            timeout_ms: None,
+            include_constraint_events: false,
+        }
+    }
 
+    /// Model the backend would use for `hole`
+    fn planned_model(&self, hole_spec: &HoleSpec, constraints_ir: &[ConstraintIR]) -> String {
         match &self.backend {
-            InferenceBackend::Single(client) => {
-                let response = client.generate_constrained(request).await?;
-                let confidence = response.confidence();
-
-                Ok(FillAttempt {
-                    code: response.generated_text,
-                    confidence,
-                    temperature,
-                    model: response.model,
-                    timestamp: chrono::Utc::now().timestamp(),
-                    validation_passed: true,
-                    error: None,
-                })
-            }
+            InferenceBackend::Single(client) => client.model_name().to_string(),
             InferenceBackend::Ensemble(ensemble) => {
-                let response = ensemble
-                    .generate_routed(request, &hole_spec, constraints_ir)
-                    .await?;
-                let confidence = response.confidence();
-
-                Ok(FillAttempt {
-                    code: response.generated_text,
-                    confidence,
-                    temperature,
-                    model: response.model,
-                    timestamp: chrono::Utc::now().timestamp(),
-                    validation_passed: true,
-                    error: None,
-                })
+                ensemble
+                    .router()
+                    .route(hole_spec, constraints_ir)
+                    .primary_model
             }
         }
     }
@@ -386,15 +1189,24 @@ impl ProgressiveRefiner {
     }
 
     /// Estimate max tokens for a hole
-    fn estimate_max_tokens(&self, hole: &HoleState) -> usize {
-        // Simple heuristic based on hole scale
-        match hole.scale.as_str() {
-            "nano" => 64,
-            "micro" => 256,
-            "meso" => 512,
-            "macro" => 1024,
-            _ => 256,
-        }
+    ///
+    /// Starts from the hole's own `max_tokens`, or else the limit
+    /// `RefinementConfig::fill_tokens` gives its scale, and doubles, up to
+    /// `RefinementConfig::max_fill_tokens`, for each earlier attempt that
+    /// stopped at its limit.
+    pub fn estimate_max_tokens(&self, hole: &HoleState) -> usize {
+        let base = hole
+            .max_tokens
+            .unwrap_or_else(|| self.config.fill_tokens.for_scale(&hole.scale));
+        let Some(ceiling) = self.config.max_fill_tokens else {
+            return base;
+        };
+        hole.attempts
+            .iter()
+            .filter(|a| a.finish_reason == FinishReason::MaxTokens)
+            .fold(base, |tokens, _| {
+                tokens.saturating_mul(2).min(ceiling.max(base))
+            })
     }
 
     /// Perform progressive refinement on code with typed holes
@@ -402,7 +1214,8 @@ impl ProgressiveRefiner {
     /// # Arguments
     /// * `code` - The code containing typed holes
     /// * `holes` - Initial state of all holes to be filled
-    /// * `constraints_ir` - Constraint IR from Zig constraint engines
+    /// * `constraints_ir` - Constraint IR from Zig constraint engines; a hole
+    ///   with `constraint_refs` is held to the named entries only
     ///
     /// # Returns
     /// The refined code with metadata about the refinement process
@@ -413,13 +1226,22 @@ impl ProgressiveRefiner {
         constraints_ir: Vec<ConstraintIR>,
     ) -> Result<RefinementResult> {
         let start_time = std::time::Instant::now();
-        let mut current_code = code;
+        let current_code = code;
         let mut metadata = RefinementMetadata::default();
+        let mut budget = BudgetAccountant::new(self.config.budget.clone());
 
-        // Build hole state map for efficient lookups
+        // Build hole state map for efficient lookups; IDs must be unique,
+        // as decomposed holes' children are keyed off them
+        HoleIdAllocator::from_holes(&holes)?;
         let mut hole_states: HashMap<u64, HoleState> =
             holes.iter().map(|h| (h.id, h.clone())).collect();
 
+        // Resolve every hole's constraints before spending anything
+        let subsets = ConstraintSubsets::new(&constraints_ir);
+        for hole in &holes {
+            subsets.for_hole(hole)?;
+        }
+
         // Iterative refinement loop
         for iteration in 0..self.config.max_iterations {
             tracing::debug!(
@@ -432,7 +1254,7 @@ impl ProgressiveRefiner {
             let temperature = self.get_temperature_for_iteration(iteration);
 
             // Get ready holes (dependencies satisfied)
-            let ready_holes = self.get_ready_holes(&hole_states);
+            let ready_holes = self.order_ready_holes(&hole_states);
             if ready_holes.is_empty() {
                 tracing::debug!("No more ready holes, checking completion");
                 if self.all_holes_resolved(&hole_states) {
@@ -449,29 +1271,54 @@ impl ProgressiveRefiner {
             // Fill ready holes (in parallel if enabled)
             if self.config.parallel_fill {
                 self.fill_holes_parallel(
-                    &mut current_code,
                     &mut hole_states,
                     &ready_holes,
-                    &constraints_ir,
+                    &subsets,
                     temperature,
                     &mut metadata,
+                    &mut budget,
                 )
                 .await?;
             } else {
                 self.fill_holes_sequential(
-                    &mut current_code,
                     &mut hole_states,
                     &ready_holes,
-                    &constraints_ir,
+                    &subsets,
                     temperature,
                     &mut metadata,
+                    &mut budget,
                 )
                 .await?;
             }
+
+            if budget.is_exhausted() {
+                tracing::warn!("Refinement budget exhausted, stopping");
+                break;
+            }
         }
 
+        // Holes the budget could not pay for are resolved per policy
+        let mut unfilled = Vec::new();
+        if budget.is_exhausted() {
+            let status = match budget.on_exhausted() {
+                BudgetExhaustedAction::Skip => HoleStatus::Skipped,
+                BudgetExhaustedAction::HumanReview => HoleStatus::NeedsHuman,
+            };
+            for hole in hole_states.values_mut() {
+                if hole.status == HoleStatus::Pending {
+                    hole.status = status;
+                    unfilled.push(hole.id);
+                }
+            }
+            if status == HoleStatus::Skipped {
+                metadata.skipped_holes += unfilled.len();
+            }
+        }
+        metadata.budget = budget.into_state(unfilled);
+
         // Collect final hole states and review list
-        holes = hole_states.values().cloned().collect();
+        let roots: Vec<u64> = holes.iter().map(|h| h.id).collect();
+        holes = tree_order(&roots, &hole_states);
         let needs_review: Vec<u64> = holes
             .iter()
             .filter(|h| h.status == HoleStatus::NeedsHuman || h.status == HoleStatus::Failed)
@@ -498,16 +1345,110 @@ impl ProgressiveRefiner {
             .max_iterations
             .min(holes.iter().map(|h| h.attempts.len()).max().unwrap_or(0));
 
+        let unsatisfied_constraints = holes
+            .iter()
+            .filter(|h| h.status != HoleStatus::Filled)
+            .filter_map(|h| {
+                let failed = h.attempts.last()?.failed_constraints();
+                (!failed.is_empty()).then(|| (h.id, failed.into_iter().map(String::from).collect()))
+            })
+            .collect();
+        let provenance = RefinementProvenance::new(&holes, &constraints_ir);
+
         Ok(RefinementResult {
             code: current_code,
             holes,
             complete,
             needs_review,
             iterations: metadata.iterations,
+            unsatisfied_constraints,
+            provenance,
             metadata,
         })
     }
 
+    /// Plan a refinement without calling the inference backend
+    ///
+    /// Compiles the constraints and walks the same iteration, temperature,
+    /// and dependency scheduling as [`refine`](Self::refine), assuming every
+    /// fill succeeds on its first attempt. Fails on constraints that do not
+    /// compile and on holes that can never become ready (dependency cycles or
+    /// missing dependencies), so pipelines can be checked before spending
+    /// tokens. Decomposition and retries depend on fill outcomes and are not
+    /// planned.
+    pub fn plan(
+        &self,
+        holes: &[HoleState],
+        constraints_ir: &[ConstraintIR],
+    ) -> Result<RefinementPlan> {
+        let (_, merge_report) = crate::compile_llguidance_schema(constraints_ir)
+            .context("Constraints do not compile")?;
+        let subsets = ConstraintSubsets::new(constraints_ir);
+
+        let mut hole_states: HashMap<u64, HoleState> =
+            holes.iter().map(|h| (h.id, h.clone())).collect();
+        let mut fills = Vec::new();
+        let mut iterations = 0;
+
+        for iteration in 0..self.config.max_iterations {
+            let temperature = self.get_temperature_for_iteration(iteration);
+
+            let ready_holes = self.order_ready_holes(&hole_states);
+            if ready_holes.is_empty() {
+                if self.all_holes_resolved(&hole_states) {
+                    break;
+                }
+                let mut stuck: Vec<u64> = hole_states
+                    .values()
+                    .filter(|h| h.status == HoleStatus::Pending)
+                    .map(|h| h.id)
+                    .collect();
+                stuck.sort_unstable();
+                bail!(
+                    "Holes {:?} can never be filled: dependency cycle or missing dependency",
+                    stuck
+                );
+            }
+            iterations = iteration + 1;
+
+            for hole_id in ready_holes {
+                let hole = &hole_states[&hole_id];
+                let hole_spec = self.build_hole_spec(hole)?;
+                let constraints = subsets.for_hole(hole)?;
+                let request = self.build_request(hole, &constraints.payload, temperature);
+                fills.push(PlannedFill {
+                    hole_id,
+                    iteration,
+                    temperature,
+                    model: self.planned_model(&hole_spec, &constraints.ir),
+                    max_tokens: request.max_tokens,
+                    prompt: request.prompt,
+                });
+            }
+
+            // Later iterations see this one's holes as filled
+            for fill in &fills {
+                if let Some(hole) = hole_states.get_mut(&fill.hole_id) {
+                    hole.status = HoleStatus::Filled;
+                }
+            }
+        }
+
+        let mut unscheduled: Vec<u64> = hole_states
+            .values()
+            .filter(|h| h.status == HoleStatus::Pending)
+            .map(|h| h.id)
+            .collect();
+        unscheduled.sort_unstable();
+
+        Ok(RefinementPlan {
+            fills,
+            iterations,
+            unscheduled,
+            merge_report,
+        })
+    }
+
     /// Get holes that are ready to be filled (dependencies satisfied)
     pub fn get_ready_holes(&self, states: &HashMap<u64, HoleState>) -> Vec<u64> {
         states
@@ -517,6 +1458,14 @@ impl ProgressiveRefiner {
             .collect()
     }
 
+    /// Ready holes in fill order, ties broken by hole ID
+    fn order_ready_holes(&self, states: &HashMap<u64, HoleState>) -> Vec<u64> {
+        let mut ready = self.get_ready_holes(states);
+        ready.sort_unstable();
+        ready.sort_by(|a, b| self.hole_ordering.compare(&states[a], &states[b]));
+        ready
+    }
+
     /// Check if all holes are resolved (filled or explicitly handled)
     pub fn all_holes_resolved(&self, states: &HashMap<u64, HoleState>) -> bool {
         states.values().all(|hole| {
@@ -543,11 +1492,6 @@ impl ProgressiveRefiner {
         })
     }
 
-    /// Get the next available hole ID
-    fn next_hole_id(&self, states: &HashMap<u64, HoleState>) -> u64 {
-        states.keys().max().map(|max| max + 1).unwrap_or(1)
-    }
-
     /// Decompose a hole into smaller sub-holes based on its scale
     ///
     /// Scale hierarchy (largest to smallest):
@@ -576,19 +1520,26 @@ impl ProgressiveRefiner {
             _ => 2,
         };
 
-        let mut child_ids = Vec::with_capacity(num_children);
-        let base_id = self.next_hole_id(states);
+        // Child IDs derive from the parent's, so replays assign the same ones
+        let mut ids = match HoleIdAllocator::from_holes(states.values()) {
+            Ok(ids) => ids,
+            Err(e) => {
+                tracing::error!("Cannot decompose hole {}: {}", hole.id, e);
+                return vec![];
+            }
+        };
+        let mut child_ids: Vec<u64> = Vec::with_capacity(num_children);
 
         for i in 0..num_children {
-            let child_id = base_id + i as u64;
+            let child_id = ids.child_id(hole.id, i);
             let child_origin = format!("{}:child_{}", hole.origin, i);
 
             let mut child =
                 HoleState::new_child(child_id, hole, child_scale.to_string(), child_origin);
 
             // Later children depend on earlier children (sequential decomposition)
-            if i > 0 {
-                child.depends_on = vec![base_id + (i - 1) as u64];
+            if let Some(&previous) = child_ids.last() {
+                child.depends_on = vec![previous];
             }
 
             child_ids.push(child_id);
@@ -620,31 +1571,45 @@ impl ProgressiveRefiner {
     /// Fill holes in parallel
     async fn fill_holes_parallel(
         &self,
-        _code: &mut str,
         hole_states: &mut HashMap<u64, HoleState>,
         ready_holes: &[u64],
-        constraints_ir: &[ConstraintIR],
+        subsets: &ConstraintSubsets<'_>,
         temperature: f32,
         metadata: &mut RefinementMetadata,
+        budget: &mut BudgetAccountant,
     ) -> Result<()> {
         use futures::future::join_all;
 
-        // Mark holes as in progress
+        // Reserve budget up front so the batch as a whole cannot overshoot
+        let mut reservations = Vec::with_capacity(ready_holes.len());
         for hole_id in ready_holes {
+            let Some(hole) = hole_states.get(hole_id) else {
+                continue;
+            };
+            let constraints = subsets.for_hole(hole)?;
+            match self.reserve_fill(hole, &constraints, budget)? {
+                Some(reservation) => reservations.push((*hole_id, reservation, constraints)),
+                None => break,
+            }
+        }
+        let ready_holes: Vec<u64> = reservations.iter().map(|(id, ..)| *id).collect();
+
+        // Mark holes as in progress
+        for hole_id in &ready_holes {
             if let Some(hole) = hole_states.get_mut(hole_id) {
                 hole.status = HoleStatus::InProgress;
             }
         }
 
         // Create fill tasks for each ready hole
-        let fill_tasks: Vec<_> = ready_holes
+        let fill_tasks: Vec<_> = reservations
             .iter()
-            .filter_map(|&hole_id| {
-                hole_states.get(&hole_id).map(|hole| {
+            .filter_map(|(hole_id, _, constraints)| {
+                hole_states.get(hole_id).map(|hole| {
                     let hole_clone = hole.clone();
-                    let constraints_clone = constraints_ir.to_vec();
+                    let constraints = constraints.clone();
                     async move {
-                        self.fill_single_hole_backend(&hole_clone, &constraints_clone, temperature)
+                        self.fill_single_hole_backend(&hole_clone, &constraints, temperature)
                             .await
                     }
                 })
@@ -659,7 +1624,15 @@ impl ProgressiveRefiner {
 
         // Process results
         for (idx, result) in results.into_iter().enumerate() {
-            let hole_id = ready_holes[idx];
+            let (hole_id, reservation) = (reservations[idx].0, reservations[idx].1);
+            match result {
+                Ok(ref attempt) => budget.settle(
+                    reservation,
+                    attempt.tokens_generated,
+                    self.cost_per_1k(&attempt.model),
+                ),
+                Err(_) => budget.settle(reservation, 0, None),
+            }
             if let Some(hole) = hole_states.get_mut(&hole_id) {
                 match result {
                     Ok(attempt) => {
@@ -673,10 +1646,14 @@ impl ProgressiveRefiner {
                         } else {
                             failed_holes.push(hole_id);
                         }
+                        if let Some(ref stats) = attempt.stats {
+                            metadata.constraint_cost.record(stats);
+                        }
                         hole.attempts.push(attempt);
                     }
                     Err(e) => {
                         tracing::error!("Fill failed for hole {}: {}", hole_id, e);
+                        hole.failed_requests += 1;
                         failed_holes.push(hole_id);
                     }
                 }
@@ -697,21 +1674,33 @@ impl ProgressiveRefiner {
     /// Fill holes sequentially
     async fn fill_holes_sequential(
         &self,
-        _code: &mut str,
         hole_states: &mut HashMap<u64, HoleState>,
         ready_holes: &[u64],
-        constraints_ir: &[ConstraintIR],
+        subsets: &ConstraintSubsets<'_>,
         temperature: f32,
         metadata: &mut RefinementMetadata,
+        budget: &mut BudgetAccountant,
     ) -> Result<()> {
         for &hole_id in ready_holes {
             let fill_result = {
                 if let Some(hole) = hole_states.get_mut(&hole_id) {
+                    let constraints = subsets.for_hole(hole)?;
+                    let Some(reservation) = self.reserve_fill(hole, &constraints, budget)? else {
+                        break;
+                    };
                     hole.status = HoleStatus::InProgress;
-                    Some(
-                        self.fill_single_hole_backend(hole, constraints_ir, temperature)
-                            .await,
-                    )
+                    let result = self
+                        .fill_single_hole_backend(hole, &constraints, temperature)
+                        .await;
+                    match result {
+                        Ok(ref attempt) => budget.settle(
+                            reservation,
+                            attempt.tokens_generated,
+                            self.cost_per_1k(&attempt.model),
+                        ),
+                        Err(_) => budget.settle(reservation, 0, None),
+                    }
+                    Some(result)
                 } else {
                     None
                 }
@@ -732,10 +1721,14 @@ impl ProgressiveRefiner {
                             } else {
                                 failed = true;
                             }
+                            if let Some(ref stats) = attempt.stats {
+                                metadata.constraint_cost.record(stats);
+                            }
                             hole.attempts.push(attempt);
                         }
                         Err(e) => {
                             tracing::error!("Fill failed for hole {}: {}", hole_id, e);
+                            hole.failed_requests += 1;
                             failed = true;
                         }
                     }
@@ -761,12 +1754,16 @@ impl ProgressiveRefiner {
         metadata: &mut RefinementMetadata,
     ) {
         // First, handle the simple cases that don't need hole_states
-        let (strategy, scale) = {
+        let (strategy, scale, attempts) = {
             let hole = match hole_states.get(&hole_id) {
                 Some(h) => h,
                 None => return,
             };
-            (self.config.failure_strategy, hole.scale.clone())
+            (
+                self.resolve_failure_strategy(hole),
+                hole.scale.clone(),
+                hole.attempts.len() + hole.failed_requests,
+            )
         };
 
         match strategy {
@@ -821,15 +1818,56 @@ impl ProgressiveRefiner {
                 }
                 metadata.failed_fills += 1;
             }
-            FailureStrategy::RetryAlternate => {
+            FailureStrategy::RetryAlternate | FailureStrategy::Adaptive => {
+                let capped = self
+                    .config
+                    .max_attempts_per_hole
+                    .is_some_and(|max| attempts >= max);
+                if capped {
+                    tracing::warn!(
+                        "Hole {} reached its cap of {} attempts, marking as failed",
+                        hole_id,
+                        attempts
+                    );
+                    metadata.attempt_cap_hits.push(hole_id);
+                }
                 if let Some(hole) = hole_states.get_mut(&hole_id) {
-                    hole.status = HoleStatus::Pending;
+                    hole.status = if capped {
+                        HoleStatus::Failed
+                    } else {
+                        HoleStatus::Pending
+                    };
                 }
                 metadata.failed_fills += 1;
             }
         }
     }
 
+    /// The configured failure strategy, with
+    /// [`FailureStrategy::Adaptive`] resolved from `hole`'s last attempt
+    fn resolve_failure_strategy(&self, hole: &HoleState) -> FailureStrategy {
+        if self.config.failure_strategy != FailureStrategy::Adaptive {
+            return self.config.failure_strategy;
+        }
+        let structural_failure = hole.attempts.last().is_some_and(|attempt| {
+            attempt
+                .constraint_checks
+                .iter()
+                .any(|check| !check.passed && check.kind == ConstraintKind::Structural)
+        });
+        let strategy = if structural_failure && hole.scale != "nano" {
+            FailureStrategy::Decompose
+        } else {
+            FailureStrategy::RetryAlternate
+        };
+        tracing::debug!(
+            hole_id = hole.id,
+            ?strategy,
+            "Resolved adaptive failure strategy"
+        );
+        strategy
+    }
+
     /// Check and aggregate completed child holes back into parents
     fn check_and_aggregate_children(&self, hole_states: &mut HashMap<u64, HoleState>) {
         // Find all parent holes waiting for children
@@ -1012,4 +2050,885 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_plan_orders_fills_by_dependency() {
+        let client = crate::MockInferenceClient::new("mock-model");
+        let refiner = ProgressiveRefiner::with_client(client.clone(), RefinementConfig::default());
+
+        let first = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let mut second = HoleState::new(2, "macro".to_string(), "a.rs:2:1".to_string());
+        second.depends_on = vec![1];
+        let independent = HoleState::new(3, "micro".to_string(), "a.rs:3:1".to_string());
+
+        let plan = refiner.plan(&[second, first, independent], &[]).unwrap();
+
+        let order: Vec<_> = plan
+            .fills
+            .iter()
+            .map(|f| (f.hole_id, f.iteration, f.temperature))
+            .collect();
+        assert_eq!(order, vec![(1, 0, 0.9), (3, 0, 0.9), (2, 1, 0.7)]);
+        assert_eq!(plan.fills[2].max_tokens, 1024);
+        assert!(plan.fills.iter().all(|f| f.model == "mock-model"));
+        assert_eq!(plan.iterations, 2);
+        assert!(plan.unscheduled.is_empty());
+        assert!(client.requests().is_empty());
+    }
+
+    #[test]
+    fn test_plan_rejects_dependency_cycle() {
+        let refiner = ProgressiveRefiner::with_client(
+            crate::MockInferenceClient::new("mock-model"),
+            RefinementConfig::default(),
+        );
+
+        let mut a = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        a.depends_on = vec![2];
+        let mut b = HoleState::new(2, "nano".to_string(), "a.rs:2:1".to_string());
+        b.depends_on = vec![1];
+
+        let error = refiner.plan(&[a, b], &[]).unwrap_err();
+        assert!(error.to_string().contains("[1, 2]"));
+    }
+
+    #[test]
+    fn test_plan_reports_holes_beyond_max_iterations() {
+        let config = RefinementConfig {
+            max_iterations: 1,
+            ..Default::default()
+        };
+        let refiner =
+            ProgressiveRefiner::with_client(crate::MockInferenceClient::new("mock-model"), config);
+
+        let first = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let mut second = HoleState::new(2, "nano".to_string(), "a.rs:2:1".to_string());
+        second.depends_on = vec![1];
+
+        let plan = refiner.plan(&[first, second], &[]).unwrap();
+        assert_eq!(plan.fills.len(), 1);
+        assert_eq!(plan.unscheduled, vec![2]);
+    }
+
+    #[tokio::test]
+    async fn test_unscored_fills_use_configured_confidence() {
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+
+        let lenient = ProgressiveRefiner::with_client(
+            crate::MockInferenceClient::new("mock-model").then_respond("x"),
+            RefinementConfig::default(),
+        );
+        let result = lenient
+            .refine("?".to_string(), vec![hole.clone()], vec![])
+            .await
+            .unwrap();
+        assert_eq!(result.holes[0].status, HoleStatus::Filled);
+        assert_eq!(result.holes[0].confidence, 1.0);
+
+        let strict = ProgressiveRefiner::with_client(
+            crate::MockInferenceClient::new("mock-model"),
+            RefinementConfig {
+                max_iterations: 1,
+                unscored_confidence: 0.0,
+                ..Default::default()
+            },
+        );
+        let result = strict
+            .refine("?".to_string(), vec![hole], vec![])
+            .await
+            .unwrap();
+        assert_ne!(result.holes[0].status, HoleStatus::Filled);
+        assert_eq!(result.holes[0].attempts[0].confidence, 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_hole_ordering_sets_fill_order() {
+        let holes = vec![
+            HoleState::new(1, "nano".to_string(), "a.rs:5:1".to_string()),
+            HoleState::new(2, "nano".to_string(), "a.rs:90:1".to_string()),
+            HoleState::new(3, "nano".to_string(), "a.rs:50:1".to_string()),
+        ];
+        let client = crate::MockInferenceClient::new("mock-model");
+        let refiner = ProgressiveRefiner::with_client(
+            client.clone(),
+            RefinementConfig {
+                parallel_fill: false,
+                ..Default::default()
+            },
+        )
+        .with_hole_ordering(crate::BySourceLocation::nearest("a.rs:80:1"));
+
+        let plan = refiner.plan(&holes, &[]).unwrap();
+        let planned: Vec<u64> = plan.fills.iter().map(|f| f.hole_id).collect();
+        assert_eq!(planned, vec![2, 3, 1]);
+
+        refiner
+            .refine("?".to_string(), holes, vec![])
+            .await
+            .unwrap();
+        let origins: Vec<bool> = ["a.rs:90:1", "a.rs:50:1", "a.rs:5:1"]
+            .iter()
+            .zip(client.requests())
+            .map(|(origin, request)| request.prompt.contains(origin))
+            .collect();
+        assert_eq!(origins, vec![true; 3]);
+    }
+
+    #[tokio::test]
+    async fn test_decomposition_tree_over_two_levels() {
+        // The macro hole and its first meso child fail, so both decompose
+        let client = crate::MockInferenceClient::new("mock-model")
+            .then_fail("too big")
+            .then_fail("still too big")
+            .with_default_response("x");
+        let refiner = ProgressiveRefiner::with_client(
+            client,
+            RefinementConfig {
+                parallel_fill: false,
+                failure_strategy: FailureStrategy::Decompose,
+                ..Default::default()
+            },
+        );
+        let hole = HoleState::new(1, "macro".to_string(), "a.rs:1:1".to_string());
+        let result = refiner
+            .refine("?".to_string(), vec![hole], vec![])
+            .await
+            .unwrap();
+
+        let tree = result.decomposition_tree();
+        assert_eq!(tree.len(), 1);
+        let root = &tree[0];
+        assert_eq!(root.depth(), 2);
+        assert_eq!(root.status, HoleStatus::Filled);
+        let mut ids = HoleIdAllocator::new();
+        let meso: Vec<u64> = (0..3).map(|i| ids.child_id(1, i)).collect();
+        let micro: Vec<u64> = (0..2).map(|i| ids.child_id(meso[0], i)).collect();
+        assert_eq!(
+            root.children
+                .iter()
+                .map(|c| (c.hole_id, c.scale.as_str()))
+                .collect::<Vec<_>>(),
+            meso.iter().map(|&id| (id, "meso")).collect::<Vec<_>>()
+        );
+        let first = &root.children[0];
+        assert_eq!(
+            first.children.iter().map(|c| c.hole_id).collect::<Vec<_>>(),
+            micro
+        );
+        assert!(first
+            .children
+            .iter()
+            .all(|c| c.scale == "micro" && c.attempts == 1));
+        assert_eq!(first.fill.as_deref(), Some("x\nx"));
+        assert_eq!(root.fill.as_deref(), Some("x\nx\nx\nx"));
+
+        let json = serde_json::to_value(&tree).unwrap();
+        assert_eq!(json[0]["children"][0]["children"][1]["hole_id"], micro[1]);
+    }
+
+    #[tokio::test]
+    async fn test_provenance_covers_decomposed_children() {
+        let client = crate::MockInferenceClient::new("mock-model")
+            .then_respond("does not match")
+            .with_default_response("x");
+        let refiner = ProgressiveRefiner::with_client(
+            client,
+            RefinementConfig {
+                parallel_fill: false,
+                failure_strategy: FailureStrategy::Decompose,
+                reject_failed_constraints: true,
+                ..Default::default()
+            },
+        );
+        let hole = HoleState::new(1, "meso".to_string(), "a.rs:1:1".to_string())
+            .with_constraint_refs(["body"]);
+        let constraints = vec![
+            regex_constraint("signature", "^fn"),
+            regex_constraint("body", "^x$"),
+        ];
+        let result = refiner
+            .refine("?".to_string(), vec![hole], constraints)
+            .await
+            .unwrap();
+
+        let provenance = &result.provenance;
+        assert_eq!(provenance.holes.len(), result.holes.len());
+        assert_eq!(provenance.constraints, vec!["body".to_string()]);
+        let root = provenance.hole(1).unwrap();
+        assert_eq!(root.model, None);
+        assert_eq!(root.attempts.len(), 1);
+        let children = &result.holes[0].child_ids;
+        assert!(!children.is_empty());
+        assert_eq!(root.models["mock-model"], 1 + children.len());
+        for &id in children {
+            let child = provenance.hole(id).unwrap();
+            assert_eq!(child.parent_id, Some(1));
+            assert_eq!(child.model.as_deref(), Some("mock-model"));
+            assert_eq!(child.constraints, vec!["body".to_string()]);
+        }
+        assert_eq!(provenance.in_file("a.rs").count(), provenance.holes.len());
+    }
+
+    #[tokio::test]
+    async fn test_decomposed_hole_ids_are_stable_across_runs() {
+        let run = || async {
+            let refiner = ProgressiveRefiner::with_client(
+                crate::MockInferenceClient::new("mock-model")
+                    .then_fail("too big")
+                    .then_fail("too big")
+                    .with_default_response("x"),
+                RefinementConfig {
+                    failure_strategy: FailureStrategy::Decompose,
+                    ..Default::default()
+                },
+            );
+            let holes = vec![
+                HoleState::new(10, "meso".to_string(), "a.rs:1:1".to_string()),
+                HoleState::new(3, "meso".to_string(), "a.rs:9:1".to_string()),
+            ];
+            refiner
+                .refine("?".to_string(), holes, vec![])
+                .await
+                .unwrap()
+        };
+
+        let first = run().await;
+        let ids: Vec<u64> = first.holes.iter().map(|h| h.id).collect();
+        let mut allocator = HoleIdAllocator::new();
+        let expected: Vec<u64> = [10, 3]
+            .into_iter()
+            .flat_map(|parent| {
+                let children = [allocator.child_id(parent, 0), allocator.child_id(parent, 1)];
+                std::iter::once(parent).chain(children)
+            })
+            .collect();
+        assert_eq!(ids, expected);
+        assert_eq!(
+            run().await.holes.iter().map(|h| h.id).collect::<Vec<_>>(),
+            ids
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_hole_ids_are_rejected() {
+        let refiner = ProgressiveRefiner::with_client(
+            crate::MockInferenceClient::new("mock-model"),
+            RefinementConfig::default(),
+        );
+        let holes = vec![
+            HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string()),
+            HoleState::new(1, "nano".to_string(), "a.rs:2:1".to_string()),
+        ];
+        let err = refiner
+            .refine("?".to_string(), holes, vec![])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<crate::DuplicateHoleId>(),
+            Some(&crate::DuplicateHoleId { id: 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blank_fill_fails_attempt_and_is_retried() {
+        let refiner = ProgressiveRefiner::with_client(
+            crate::MockInferenceClient::new("mock-model")
+                .then_respond("   ")
+                .with_default_response("x"),
+            RefinementConfig::default(),
+        );
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let result = refiner
+            .refine("?".to_string(), vec![hole], vec![])
+            .await
+            .unwrap();
+
+        let hole = &result.holes[0];
+        assert_eq!(hole.status, HoleStatus::Filled);
+        assert_eq!(hole.current_fill.as_deref(), Some("x"));
+        assert!(!hole.attempts[0].validation_passed);
+        assert!(hole.attempts[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("whitespace only"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_fill_falls_back_to_next_model() {
+        let refiner = ProgressiveRefiner::with_client(
+            crate::MockInferenceClient::new("primary").with_default_response("  "),
+            RefinementConfig::default(),
+        )
+        .with_fallback_client(crate::MockInferenceClient::new("backup").with_default_response("x"));
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let result = refiner
+            .refine("?".to_string(), vec![hole], vec![])
+            .await
+            .unwrap();
+
+        let hole = &result.holes[0];
+        assert_eq!(hole.current_fill.as_deref(), Some("x"));
+        assert_eq!(hole.attempts.len(), 1);
+        let attempt = &hole.attempts[0];
+        assert_eq!(attempt.model, "backup");
+        assert!(attempt.validation_passed);
+        let outcomes: Vec<_> = attempt.model_chain.iter().map(|a| a.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                model_chain::ChainOutcome::Invalid,
+                model_chain::ChainOutcome::Accepted
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_budget_stops_refinement_and_skips_remaining_holes() {
+        let holes: Vec<HoleState> = (1..=3)
+            .map(|id| HoleState::new(id, "nano".to_string(), format!("a.rs:{}:1", id)))
+            .collect();
+
+        // Each nano fill reserves 64 tokens, so only two fit
+        let refiner = ProgressiveRefiner::with_client(
+            crate::MockInferenceClient::new("mock-model"),
+            RefinementConfig {
+                budget: TokenBudget {
+                    max_total_tokens: Some(150),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let result = refiner
+            .refine("?".to_string(), holes, vec![])
+            .await
+            .unwrap();
+
+        let budget = &result.metadata.budget;
+        assert!(budget.exhausted);
+        assert_eq!(budget.unfilled_holes, vec![3]);
+        assert_eq!(
+            budget.tokens_used,
+            result
+                .holes
+                .iter()
+                .flat_map(|h| &h.attempts)
+                .map(|a| a.tokens_generated)
+                .sum::<usize>()
+        );
+        let skipped = result.holes.iter().find(|h| h.id == 3).unwrap();
+        assert_eq!(skipped.status, HoleStatus::Skipped);
+        assert!(skipped.attempts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unreported_tokens_are_billed_by_estimate() {
+        let mut response = crate::MockInferenceClient::response("mock-model", "let x = 1;");
+        response.tokens_generated = 0;
+        let refiner = ProgressiveRefiner::with_client(
+            crate::MockInferenceClient::new("mock-model")
+                .then(crate::MockReply::Response(response)),
+            RefinementConfig {
+                budget: TokenBudget {
+                    max_total_tokens: Some(1000),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        )
+        .with_token_estimator(CharRatioEstimator {
+            chars_per_token: 1.0,
+        });
+        let result = refiner
+            .refine(
+                "?".to_string(),
+                vec![HoleState::new(
+                    1,
+                    "nano".to_string(),
+                    "a.rs:1:1".to_string(),
+                )],
+                vec![],
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(result.holes[0].attempts[0].tokens_generated, 10);
+        assert_eq!(result.metadata.budget.tokens_used, 10);
+    }
+
+    fn regex_constraint(name: &str, pattern: &str) -> ConstraintIR {
+        ConstraintIR {
+            name: name.to_string(),
+            json_schema: None,
+            grammar: None,
+            regex_patterns: vec![crate::ffi::RegexPattern {
+                pattern: pattern.to_string(),
+                flags: String::new(),
+            }],
+            token_masks: None,
+            type_inhabitation: None,
+            priority: 0,
+            rich_context: None,
+            feasibility_score: 0.0,
+            is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_holes_are_sent_only_their_referenced_constraints() {
+        let constraints = vec![
+            regex_constraint("type_annotation", "^[A-Z]\\w*$"),
+            regex_constraint("function_body", "^\\{"),
+        ];
+        let annotation = HoleState::new(1, "nano".to_string(), "a.rs:1:8".to_string())
+            .with_constraint_refs(["type_annotation"]);
+        let body = HoleState::new(2, "meso".to_string(), "a.rs:2:1".to_string())
+            .with_constraint_refs(["function_body"]);
+        let unrestricted = HoleState::new(3, "nano".to_string(), "a.rs:9:1".to_string());
+
+        let client = crate::MockInferenceClient::new("mock-model");
+        let refiner = ProgressiveRefiner::with_client(
+            client.clone(),
+            RefinementConfig {
+                parallel_fill: false,
+                ..Default::default()
+            },
+        );
+        refiner
+            .refine(
+                "?".to_string(),
+                vec![annotation, body, unrestricted],
+                constraints,
+            )
+            .await
+            .unwrap();
+
+        let sent: Vec<Vec<String>> = client
+            .requests()
+            .iter()
+            .map(|request| {
+                request
+                    .constraints
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|c| c["name"].as_str().unwrap().to_string())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            sent,
+            vec![
+                vec!["type_annotation".to_string()],
+                vec!["function_body".to_string()],
+                vec!["type_annotation".to_string(), "function_body".to_string()],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_strategy_decomposes_only_structural_failures() {
+        let constraints = vec![
+            regex_constraint("signature", r"fn \w+\(\) \{.*\}"),
+            crate::policy::PolicyConstraint::new("no_eval")
+                .deny(r"\beval\s*\(")
+                .unwrap()
+                .to_constraint(),
+        ];
+        let shaped = HoleState::new(1, "meso".to_string(), "a.rs:1:1".to_string())
+            .with_constraint_refs(["signature"]);
+        let styled = HoleState::new(2, "meso".to_string(), "a.rs:9:1".to_string())
+            .with_constraint_refs(["no_eval"]);
+
+        let client = crate::MockInferenceClient::new("mock-model")
+            .then_respond("let x = 1;")
+            .then_respond("eval(input)")
+            .with_default_response("fn ok() {}");
+        let refiner = ProgressiveRefiner::with_client(
+            client,
+            RefinementConfig {
+                parallel_fill: false,
+                failure_strategy: FailureStrategy::Adaptive,
+                ..Default::default()
+            },
+        );
+        let result = refiner
+            .refine("?".to_string(), vec![shaped, styled], constraints)
+            .await
+            .unwrap();
+        assert!(result.complete);
+
+        // The wrong shape is decomposed...
+        let shaped = &result.holes[0];
+        assert_eq!(shaped.attempts[0].failed_constraints(), vec!["signature"]);
+        assert_eq!(shaped.child_ids.len(), 2);
+
+        // ...while a style violation is retried as is
+        let styled = result.holes.iter().find(|h| h.id == 2).unwrap();
+        let check = &styled.attempts[0].constraint_checks[0];
+        assert_eq!(check.kind, ConstraintKind::Style);
+        assert!(!check.passed);
+        assert!(styled.child_ids.is_empty());
+        assert_eq!(styled.attempts.len(), 2);
+        assert!(styled.attempts[1].validation_passed);
+    }
+
+    #[tokio::test]
+    async fn test_unsatisfied_constraints_are_reported_for_unfilled_holes() {
+        let constraints = vec![
+            regex_constraint("signature", r"fn \w+\(\) \{.*\}"),
+            regex_constraint("short", r".{0,40}"),
+        ];
+        let refiner = |reject_failed_constraints| {
+            let client = crate::MockInferenceClient::new("mock-model").then_respond("let x = 1;");
+            ProgressiveRefiner::with_client(
+                client,
+                RefinementConfig {
+                    failure_strategy: FailureStrategy::HumanReview,
+                    reject_failed_constraints,
+                    ..Default::default()
+                },
+            )
+        };
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+
+        // By default failed checks are recorded but do not reject the fill
+        let result = refiner(false)
+            .refine("?".to_string(), vec![hole.clone()], constraints.clone())
+            .await
+            .unwrap();
+        let attempt = &result.holes[0].attempts[0];
+        assert!(attempt.validation_passed);
+        assert!(!attempt.constraint_checks[0].passed);
+        assert_eq!(result.holes[0].status, HoleStatus::Filled);
+
+        let result = refiner(true)
+            .refine("?".to_string(), vec![hole], constraints)
+            .await
+            .unwrap();
+        let attempt = &result.holes[0].attempts[0];
+        assert!(!attempt.validation_passed);
+        assert!(attempt.constraint_checks[1].passed);
+        assert!(attempt
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("constraint 'signature' not satisfied"));
+        assert_eq!(
+            result.unsatisfied_constraints,
+            BTreeMap::from([(1, vec!["signature".to_string()])])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_constraint_reference_is_rejected() {
+        let client = crate::MockInferenceClient::new("mock-model");
+        let refiner = ProgressiveRefiner::with_client(client.clone(), RefinementConfig::default());
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string())
+            .with_constraint_refs(["missing"]);
+        let constraints = vec![regex_constraint("present", "x")];
+
+        let error = refiner
+            .plan(std::slice::from_ref(&hole), &constraints)
+            .unwrap_err();
+        assert!(error.to_string().contains("\"missing\""));
+        let error = refiner
+            .refine("?".to_string(), vec![hole], constraints)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("unknown constraints"));
+        assert!(client.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_hole_stops_retrying_at_attempt_cap() {
+        let client = crate::MockInferenceClient::new("mock-model").with_default_response("  ");
+        let refiner = ProgressiveRefiner::with_client(
+            client.clone(),
+            RefinementConfig {
+                max_attempts_per_hole: Some(2),
+                parallel_fill: false,
+                ..Default::default()
+            },
+        );
+        let stuck = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let result = refiner
+            .refine("?".to_string(), vec![stuck], vec![])
+            .await
+            .unwrap();
+
+        let hole = &result.holes[0];
+        assert_eq!(hole.status, HoleStatus::Failed);
+        assert_eq!(hole.attempts.len(), 2);
+        assert_eq!(client.requests().len(), 2);
+        assert_eq!(result.metadata.attempt_cap_hits, vec![1]);
+        assert_eq!(result.needs_review, vec![1]);
+        assert!(!result.complete);
+    }
+
+    #[tokio::test]
+    async fn test_failed_requests_count_toward_attempt_cap() {
+        let client = crate::MockInferenceClient::new("mock-model")
+            .then_fail("service unavailable")
+            .then_fail("service unavailable")
+            .then_fail("service unavailable")
+            .with_default_response("x");
+        let refiner = ProgressiveRefiner::with_client(
+            client.clone(),
+            RefinementConfig {
+                max_attempts_per_hole: Some(2),
+                ..Default::default()
+            },
+        );
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let result = refiner
+            .refine("?".to_string(), vec![hole], vec![])
+            .await
+            .unwrap();
+
+        let hole = &result.holes[0];
+        assert_eq!(hole.status, HoleStatus::Failed);
+        assert!(hole.attempts.is_empty());
+        assert_eq!(hole.failed_requests, 2);
+        assert_eq!(client.requests().len(), 2);
+        assert_eq!(result.metadata.attempt_cap_hits, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_temperature_is_clamped_to_model_range() {
+        let client = crate::MockInferenceClient::new("mock-model")
+            .with_model_info(crate::ModelInfo {
+                max_temperature: Some(0.5),
+                ..crate::ModelInfo::from_name("mock-model")
+            })
+            .with_default_response("  ");
+        let refiner = ProgressiveRefiner::with_client(
+            client.clone(),
+            RefinementConfig {
+                temperature_schedule: vec![0.9, 0.3],
+                max_attempts_per_hole: Some(2),
+                parallel_fill: false,
+                ..Default::default()
+            },
+        );
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let result = refiner
+            .refine("?".to_string(), vec![hole], vec![])
+            .await
+            .unwrap();
+
+        let sent: Vec<f32> = client.requests().iter().map(|r| r.temperature).collect();
+        assert_eq!(sent, vec![0.5, 0.3]);
+        let recorded: Vec<f32> = result.holes[0]
+            .attempts
+            .iter()
+            .map(|a| a.temperature)
+            .collect();
+        assert_eq!(recorded, vec![0.5, 0.3]);
+    }
+
+    #[tokio::test]
+    async fn test_fill_at_token_limit_retries_with_larger_limit() {
+        let mut cut_off = crate::MockInferenceClient::response("mock-model", "let x =");
+        cut_off.finish_reason = Some(FinishReason::MaxTokens);
+        let client = crate::MockInferenceClient::new("mock-model")
+            .then(crate::MockReply::Response(cut_off.clone()))
+            .then(crate::MockReply::Response(cut_off))
+            .then_respond("let x = 1;");
+        let refiner = ProgressiveRefiner::with_client(
+            client.clone(),
+            RefinementConfig {
+                parallel_fill: false,
+                max_fill_tokens: Some(200),
+                ..Default::default()
+            },
+        );
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let result = refiner
+            .refine("?".to_string(), vec![hole], vec![])
+            .await
+            .unwrap();
+
+        let hole = &result.holes[0];
+        assert_eq!(hole.status, HoleStatus::Filled);
+        assert_eq!(hole.current_fill.as_deref(), Some("let x = 1;"));
+        assert_eq!(hole.attempts[0].finish_reason, FinishReason::MaxTokens);
+        assert!(!hole.attempts[0].validation_passed);
+        assert_eq!(hole.attempts[2].finish_reason, FinishReason::Stop);
+
+        let limits: Vec<usize> = client.requests().iter().map(|r| r.max_tokens).collect();
+        assert_eq!(limits, vec![64, 128, 200]);
+    }
+
+    #[tokio::test]
+    async fn test_runaway_fill_is_rejected_without_raising_the_limit() {
+        let mut runaway = crate::MockInferenceClient::response("mock-model", &"x = 1\n".repeat(40));
+        runaway.finish_reason = Some(FinishReason::MaxTokens);
+        let client = crate::MockInferenceClient::new("mock-model")
+            .then(crate::MockReply::Response(runaway))
+            .then_respond("x = 1");
+        let refiner = ProgressiveRefiner::with_client(
+            client.clone(),
+            RefinementConfig {
+                parallel_fill: false,
+                ..Default::default()
+            },
+        );
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let result = refiner
+            .refine("?".to_string(), vec![hole], vec![])
+            .await
+            .unwrap();
+
+        let hole = &result.holes[0];
+        assert_eq!(hole.status, HoleStatus::Filled);
+        assert_eq!(hole.attempts[0].finish_reason, FinishReason::Runaway);
+        assert!(hole.attempts[0]
+            .error
+            .as_ref()
+            .unwrap()
+            .starts_with("runaway generation: 240 bytes in 40 lines"));
+
+        let limits: Vec<usize> = client.requests().iter().map(|r| r.max_tokens).collect();
+        assert_eq!(limits, vec![64, 64]);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_fill_is_cut_off_at_its_hole_limit() {
+        let client = crate::MockInferenceClient::new("mock-model")
+            .then_respond(&"x = 1\n".repeat(40))
+            .then_respond("x = 1");
+        let refiner = ProgressiveRefiner::with_client(
+            client.clone(),
+            RefinementConfig {
+                parallel_fill: false,
+                stream_fills: true,
+                ..Default::default()
+            },
+        );
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let result = refiner
+            .refine("?".to_string(), vec![hole], vec![])
+            .await
+            .unwrap();
+
+        // Nano fills may span 32 lines; the stream stops on the 33rd
+        let hole = &result.holes[0];
+        assert_eq!(hole.status, HoleStatus::Filled);
+        assert_eq!(hole.attempts[0].finish_reason, FinishReason::Runaway);
+        assert_eq!(hole.attempts[0].code.lines().count(), 33);
+        assert!(hole.attempts[0]
+            .error
+            .as_ref()
+            .unwrap()
+            .starts_with("runaway generation: 194 bytes in 33 lines"));
+        assert_eq!(hole.attempts[1].code, "x = 1");
+    }
+
+    #[test]
+    fn test_token_estimate_follows_scale_and_overrides() {
+        let refiner = ProgressiveRefiner::with_client(
+            crate::MockInferenceClient::new("mock-model"),
+            RefinementConfig {
+                fill_tokens: FillTokenLimits::default().with_scale("macro", 2000),
+                max_fill_tokens: Some(3000),
+                ..Default::default()
+            },
+        );
+        let hole = |scale: &str| HoleState::new(1, scale.to_string(), "a.rs:1:1".to_string());
+
+        assert_eq!(refiner.estimate_max_tokens(&hole("nano")), 64);
+        assert_eq!(refiner.estimate_max_tokens(&hole("meso")), 512);
+        assert_eq!(refiner.estimate_max_tokens(&hole("macro")), 2000);
+        assert_eq!(refiner.estimate_max_tokens(&hole("unknown")), 256);
+        assert_eq!(
+            refiner.estimate_max_tokens(&hole("nano").with_max_tokens(100)),
+            100
+        );
+
+        // Each truncated attempt doubles the limit, up to max_fill_tokens
+        let mut truncated = hole("macro");
+        let attempt = FillAttempt {
+            code: String::new(),
+            confidence: 0.0,
+            raw_confidence: None,
+            temperature: 0.0,
+            model: "mock-model".to_string(),
+            timestamp: 0,
+            validation_passed: false,
+            error: None,
+            tokens_generated: 2000,
+            stats: None,
+            model_chain: vec![],
+            finish_reason: FinishReason::MaxTokens,
+            constraint_checks: vec![],
+        };
+        truncated.attempts.push(attempt.clone());
+        assert_eq!(refiner.estimate_max_tokens(&truncated), 3000);
+        truncated.attempts.push(FillAttempt {
+            finish_reason: FinishReason::Stop,
+            ..attempt
+        });
+        assert_eq!(refiner.estimate_max_tokens(&truncated), 3000);
+    }
+
+    #[tokio::test]
+    async fn test_fill_at_token_limit_is_accepted_without_max_fill_tokens() {
+        let client =
+            crate::MockInferenceClient::new("mock-model").with_default_response(&"x ".repeat(64));
+        let refiner = ProgressiveRefiner::with_client(
+            client,
+            RefinementConfig {
+                max_fill_tokens: None,
+                ..Default::default()
+            },
+        );
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let result = refiner
+            .refine("?".to_string(), vec![hole], vec![])
+            .await
+            .unwrap();
+
+        let hole = &result.holes[0];
+        assert_eq!(hole.status, HoleStatus::Filled);
+        assert_eq!(hole.attempts[0].finish_reason, FinishReason::MaxTokens);
+    }
+
+    #[tokio::test]
+    async fn test_calibrated_confidence_gates_fills() {
+        let mut overconfident = crate::MockInferenceClient::response("mock-model", "x");
+        overconfident.reported_confidence = Some(0.9);
+        let client = crate::MockInferenceClient::new("mock-model")
+            .then(crate::MockReply::Response(overconfident));
+        let curve = crate::CalibrationCurve::piecewise(vec![(0.0, 0.0), (1.0, 0.5)]).unwrap();
+        let refiner = ProgressiveRefiner::with_client(
+            client,
+            RefinementConfig {
+                max_iterations: 1,
+                calibration: ConfidenceCalibration::default().with_model("mock-model", curve),
+                ..Default::default()
+            },
+        );
+        let hole = HoleState::new(1, "nano".to_string(), "a.rs:1:1".to_string());
+        let result = refiner
+            .refine("?".to_string(), vec![hole], vec![])
+            .await
+            .unwrap();
+
+        let attempt = &result.holes[0].attempts[0];
+        assert_eq!(attempt.raw_confidence, Some(0.9));
+        assert!((attempt.confidence - 0.45).abs() < 1e-6);
+        assert_ne!(result.holes[0].status, HoleStatus::Filled);
+
+        let samples = crate::samples_from_attempts(&result.holes[0].attempts, Some("mock-model"));
+        assert_eq!(
+            samples,
+            vec![crate::CalibrationSample {
+                confidence: 0.9,
+                passed: true
+            }]
+        );
+    }
 }
@@ -0,0 +1,335 @@
+//! Prompt assembly from generation context
+//!
+//! [`PromptBuilder`] turns a user prompt plus its [`GenerationContext`] into
+//! the text sent to the model. The default template renders:
+//!
+//! ````text
+//! Language: <language>
+//! File: <current_file>
+//! Project: <project_root>
+//! <key>: <value>            (one line per metadata entry, sorted by key)
+//!
+//! Surrounding code:
+//! ```<language>
+//! <metadata["surrounding_code"]>
+//! ```
+//!
+//! Relevant context:
+//! // <snippet source>
+//! <snippet content>         (one block per retrieved snippet)
+//!
+//! Previous turns:
+//! Request: <turn prompt>    (one request/response pair per prior turn,
+//! Response:                  oldest first)
+//! <turn response>
+//!
+//! <prompt>
+//! ````
+//!
+//! Missing fields are omitted, and a request without context is sent as the
+//! bare prompt. Register a custom template with [`PromptBuilder::with_template`].
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::context_provider::ContextSnippet;
+use crate::GenerationContext;
+
+/// Metadata key whose value is rendered as a code block, not a `key: value` line
+pub const SURROUNDING_CODE_KEY: &str = "surrounding_code";
+
+/// An earlier request and the code it produced, carried into a follow-up
+/// request such as "make it also handle X"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationTurn {
+    /// The earlier request's prompt
+    pub prompt: String,
+
+    /// The code generated for it
+    pub response: String,
+}
+
+/// A prompt ready to send, plus a record of which context went into it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AssembledPrompt {
+    /// Text sent to the model
+    pub text: String,
+
+    /// Context included in `text`, e.g. `language`, `current_file`,
+    /// `metadata:ticket`, or `history:2` for two prior turns
+    pub included: Vec<String>,
+}
+
+/// Everything a template may draw on
+#[derive(Debug, Clone, Copy)]
+pub struct PromptInput<'a> {
+    /// The caller's prompt
+    pub prompt: &'a str,
+
+    /// Request context, if any
+    pub context: Option<&'a GenerationContext>,
+
+    /// Retrieved snippets, most relevant first
+    pub snippets: &'a [ContextSnippet],
+
+    /// Prior turns of the conversation, oldest first
+    pub history: &'a [ConversationTurn],
+}
+
+/// Template function turning a [`PromptInput`] into an [`AssembledPrompt`]
+pub type PromptTemplate = Arc<dyn Fn(&PromptInput<'_>) -> AssembledPrompt + Send + Sync>;
+
+/// Composes the model prompt from a request's prompt and context
+#[derive(Clone)]
+pub struct PromptBuilder {
+    template: PromptTemplate,
+}
+
+impl Default for PromptBuilder {
+    fn default() -> Self {
+        Self {
+            template: Arc::new(default_template),
+        }
+    }
+}
+
+impl std::fmt::Debug for PromptBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PromptBuilder").finish_non_exhaustive()
+    }
+}
+
+impl PromptBuilder {
+    /// Create a builder using the default template
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the template with a custom function
+    pub fn with_template<F>(mut self, template: F) -> Self
+    where
+        F: Fn(&PromptInput<'_>) -> AssembledPrompt + Send + Sync + 'static,
+    {
+        self.template = Arc::new(template);
+        self
+    }
+
+    /// Assemble the prompt for a request
+    pub fn build(&self, input: &PromptInput<'_>) -> AssembledPrompt {
+        (self.template)(input)
+    }
+}
+
+/// The default template described in the module docs
+pub fn default_template(input: &PromptInput<'_>) -> AssembledPrompt {
+    let mut preamble = String::new();
+    let mut included = Vec::new();
+
+    if let Some(context) = input.context {
+        render_context(context, &mut preamble, &mut included);
+    }
+
+    if !input.snippets.is_empty() {
+        preamble.push_str("\nRelevant context:\n");
+        for snippet in input.snippets {
+            preamble.push_str(&format!("// {}\n{}\n", snippet.source, snippet.content));
+            included.push(format!("snippet:{}", snippet.source));
+        }
+    }
+
+    if !input.history.is_empty() {
+        preamble.push_str("\nPrevious turns:\n");
+        for turn in input.history {
+            preamble.push_str(&format!(
+                "Request: {}\nResponse:\n{}\n",
+                turn.prompt, turn.response
+            ));
+        }
+        included.push(format!("history:{}", input.history.len()));
+    }
+
+    if preamble.is_empty() {
+        return AssembledPrompt {
+            text: input.prompt.to_string(),
+            included,
+        };
+    }
+
+    AssembledPrompt {
+        text: format!("{}\n{}", preamble.trim_start_matches('\n'), input.prompt),
+        included,
+    }
+}
+
+/// Render context fields, metadata, and surrounding code into `preamble`
+fn render_context(context: &GenerationContext, preamble: &mut String, included: &mut Vec<String>) {
+    let fields = [
+        ("Language", "language", &context.language),
+        ("File", "current_file", &context.current_file),
+        ("Project", "project_root", &context.project_root),
+    ];
+    for (label, name, value) in fields {
+        if let Some(value) = value {
+            preamble.push_str(&format!("{}: {}\n", label, value));
+            included.push(name.to_string());
+        }
+    }
+
+    let mut keys: Vec<&String> = context
+        .metadata
+        .keys()
+        .filter(|k| k.as_str() != SURROUNDING_CODE_KEY)
+        .collect();
+    keys.sort();
+    for key in keys {
+        let value = &context.metadata[key];
+        let rendered = match value.as_str() {
+            Some(s) => s.to_string(),
+            None => value.to_string(),
+        };
+        preamble.push_str(&format!("{}: {}\n", key, rendered));
+        included.push(format!("metadata:{}", key));
+    }
+
+    if let Some(code) = context
+        .metadata
+        .get(SURROUNDING_CODE_KEY)
+        .and_then(|v| v.as_str())
+    {
+        let fence_lang = context.language.as_deref().unwrap_or("");
+        preamble.push_str(&format!(
+            "\nSurrounding code:\n```{}\n{}\n```\n",
+            fence_lang, code
+        ));
+        included.push(format!("metadata:{}", SURROUNDING_CODE_KEY));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_default_template_without_context() {
+        let assembled = PromptBuilder::new().build(&PromptInput {
+            prompt: "Write a parser",
+            context: None,
+            snippets: &[],
+            history: &[],
+        });
+        assert_eq!(assembled.text, "Write a parser");
+        assert!(assembled.included.is_empty());
+    }
+
+    #[test]
+    fn test_default_template_renders_context() {
+        let context = GenerationContext {
+            current_file: Some("src/lib.rs".to_string()),
+            language: Some("rust".to_string()),
+            project_root: None,
+            metadata: HashMap::from([
+                ("ticket".to_string(), serde_json::json!("ENG-1")),
+                (
+                    SURROUNDING_CODE_KEY.to_string(),
+                    serde_json::json!("fn helper() {}"),
+                ),
+            ]),
+        };
+
+        let assembled = PromptBuilder::new().build(&PromptInput {
+            prompt: "Write a parser",
+            context: Some(&context),
+            snippets: &[],
+            history: &[],
+        });
+        assert_eq!(
+            assembled.text,
+            "Language: rust\nFile: src/lib.rs\nticket: ENG-1\n\n\
+             Surrounding code:\n```rust\nfn helper() {}\n```\n\nWrite a parser"
+        );
+        assert_eq!(
+            assembled.included,
+            vec![
+                "language",
+                "current_file",
+                "metadata:ticket",
+                "metadata:surrounding_code"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_custom_template() {
+        let builder = PromptBuilder::new().with_template(|input| AssembledPrompt {
+            text: format!(
+                "[{}] {}",
+                input
+                    .context
+                    .and_then(|c| c.language.as_deref())
+                    .unwrap_or("any"),
+                input.prompt
+            ),
+            included: vec!["language".to_string()],
+        });
+
+        let input = PromptInput {
+            prompt: "hi",
+            context: None,
+            snippets: &[],
+            history: &[],
+        };
+        assert_eq!(builder.build(&input).text, "[any] hi");
+    }
+
+    #[test]
+    fn test_default_template_renders_snippets() {
+        let snippets = vec![ContextSnippet {
+            source: "src/user.rs".to_string(),
+            content: "struct User;".to_string(),
+            relevance: 0.9,
+        }];
+
+        let assembled = PromptBuilder::new().build(&PromptInput {
+            prompt: "Add a login fn",
+            context: None,
+            snippets: &snippets,
+            history: &[],
+        });
+        assert_eq!(
+            assembled.text,
+            "Relevant context:\n// src/user.rs\nstruct User;\n\nAdd a login fn"
+        );
+        assert_eq!(assembled.included, vec!["snippet:src/user.rs"]);
+    }
+
+    #[test]
+    fn test_default_template_renders_history_oldest_first() {
+        let history = vec![
+            ConversationTurn {
+                prompt: "Write a parser".to_string(),
+                response: "fn parse() {}".to_string(),
+            },
+            ConversationTurn {
+                prompt: "Return a Result".to_string(),
+                response: "fn parse() -> Result<()> { Ok(()) }".to_string(),
+            },
+        ];
+
+        let assembled = PromptBuilder::new().build(&PromptInput {
+            prompt: "Also handle empty input",
+            context: None,
+            snippets: &[],
+            history: &history,
+        });
+        assert_eq!(
+            assembled.text,
+            "Previous turns:\n\
+             Request: Write a parser\nResponse:\nfn parse() {}\n\
+             Request: Return a Result\nResponse:\nfn parse() -> Result<()> { Ok(()) }\n\n\
+             Also handle empty input"
+        );
+        assert_eq!(assembled.included, vec!["history:2"]);
+    }
+}
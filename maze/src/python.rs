@@ -2,17 +2,29 @@
 //!
 //! Exposes Rust types and functions to Python via PyO3.
 //! Provides async/await support through pyo3-async-runtimes.
-
-use pyo3::exceptions::PyRuntimeError;
+//!
+//! No entry point holds the GIL while waiting on the inference service:
+//! async methods hand their future to the tokio runtime with
+//! `future_into_py`, which runs it without the GIL and reattaches only to
+//! build the result, and blocking methods (`generate_sync`) detach from the
+//! GIL around `block_on`.
+
+use futures::StreamExt;
+use pyo3::exceptions::{PyRuntimeError, PyStopAsyncIteration, PyValueError};
 use pyo3::prelude::*;
 use pyo3::types::PyDict;
 use pyo3_async_runtimes::tokio::future_into_py;
 use std::collections::HashMap;
 use std::sync::Arc;
+use tokio::sync::watch;
 
 use crate::{
-    ffi::ConstraintIR, GenerationContext, GenerationRequest, GenerationResponse, MazeConfig,
-    MazeOrchestrator, ModalConfig,
+    constraint_builder::{ConstraintBuilder, InvalidConstraint},
+    context_window,
+    ffi::ConstraintIR,
+    ConstraintFormat, ContextOverflowPolicy, GenerationContext, GenerationRequest,
+    GenerationResponse, MazeConfig, MazeOrchestrator, ModalConfig, RedactionPolicy,
+    ResponseCacheConfig, StreamingResult, Transport,
 };
 
 /// Python wrapper for ModalConfig
@@ -37,9 +49,21 @@ impl PyModalConfig {
             endpoint_url,
             model,
             api_key,
+            auth_scheme: crate::modal_client::AuthScheme::default(),
+            user_agent: None,
             timeout_secs,
             enable_retry: true,
             max_retries,
+            models_cache_ttl_secs: 300,
+            redaction: RedactionPolicy::default(),
+            transport: Transport::default(),
+            decompress_responses: true,
+            stream_idle_timeout_secs: Some(60),
+            replicas: Vec::new(),
+            circuit_breaker: crate::CircuitBreakerConfig::default(),
+            hedge_after_ms: None,
+            max_response_bytes: Some(crate::modal_client::DEFAULT_MAX_RESPONSE_BYTES),
+            health_check: crate::HealthCheckConfig::default(),
         };
         Ok(Self { inner: config })
     }
@@ -79,6 +103,11 @@ pub struct PyConstraintIR {
 
     #[pyo3(get, set)]
     pub regex_patterns: Vec<String>,
+
+    /// Full constraint from `ConstraintBuilder`, kept for the regex flags,
+    /// token masks, and priority that the fields above cannot express; the
+    /// fields themselves always win
+    validated: Option<ConstraintIR>,
 }
 
 #[pymethods]
@@ -96,6 +125,7 @@ impl PyConstraintIR {
             json_schema,
             grammar,
             regex_patterns,
+            validated: None,
         }
     }
 
@@ -104,6 +134,152 @@ impl PyConstraintIR {
     }
 }
 
+/// Fluent, validating builder for PyConstraintIR
+///
+/// Each method checks its input immediately and raises `ValueError` if it is
+/// malformed, using the same validation as the Rust `ConstraintBuilder`.
+#[pyclass(name = "ConstraintBuilder")]
+pub struct PyConstraintBuilder {
+    builder: ConstraintBuilder,
+}
+
+#[pymethods]
+impl PyConstraintBuilder {
+    #[new]
+    #[pyo3(signature = (name="constraint".to_string()))]
+    fn new(name: String) -> Self {
+        Self {
+            builder: ConstraintBuilder::new(name),
+        }
+    }
+
+    /// Add a regex pattern. `flags` may contain any of "imsx".
+    #[pyo3(signature = (pattern, flags=""))]
+    fn with_regex<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        pattern: &str,
+        flags: &str,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        slf.builder = slf
+            .builder
+            .clone()
+            .with_regex(pattern, flags)
+            .map_err(invalid_constraint)?;
+        Ok(slf)
+    }
+
+    /// Set the JSON schema from a dict (or a JSON string)
+    fn with_json_schema<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        schema: &Bound<'py, PyAny>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        let json: String = match schema.extract::<String>() {
+            Ok(text) => text,
+            Err(_) => schema
+                .py()
+                .import("json")?
+                .call_method1("dumps", (schema,))
+                .map_err(|e| {
+                    PyValueError::new_err(format!("Schema is not JSON-serializable: {}", e))
+                })?
+                .extract()?,
+        };
+        let value: serde_json::Value = serde_json::from_str(&json)
+            .map_err(|e| PyValueError::new_err(format!("Invalid JSON schema: {}", e)))?;
+
+        slf.builder = slf
+            .builder
+            .clone()
+            .with_json_schema(&value)
+            .map_err(invalid_constraint)?;
+        Ok(slf)
+    }
+
+    /// Set the grammar from `(lhs, [rhs...])` rules and a start symbol
+    fn with_grammar<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        rules: Vec<(String, Vec<String>)>,
+        start: &str,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        let rules = rules
+            .into_iter()
+            .map(|(lhs, rhs)| crate::ffi::GrammarRule { lhs, rhs })
+            .collect();
+        slf.builder = slf
+            .builder
+            .clone()
+            .with_grammar(rules, start)
+            .map_err(invalid_constraint)?;
+        Ok(slf)
+    }
+
+    /// Set allowed and/or forbidden token IDs
+    #[pyo3(signature = (allowed=None, forbidden=None))]
+    fn with_token_masks<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        allowed: Option<Vec<u32>>,
+        forbidden: Option<Vec<u32>>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        slf.builder = slf
+            .builder
+            .clone()
+            .with_token_masks(allowed, forbidden)
+            .map_err(invalid_constraint)?;
+        Ok(slf)
+    }
+
+    /// Set the merge priority; higher wins
+    fn with_priority(mut slf: PyRefMut<'_, Self>, priority: u32) -> PyRefMut<'_, Self> {
+        slf.builder = slf.builder.clone().with_priority(priority);
+        slf
+    }
+
+    /// Finish the constraint
+    ///
+    /// Raises:
+    ///     ValueError: If the name is empty or nothing was added
+    fn build(&self) -> PyResult<PyConstraintIR> {
+        let constraint = self.builder.clone().build().map_err(invalid_constraint)?;
+
+        let grammar = constraint.grammar.as_ref().map(|g| {
+            serde_json::json!({
+                "rules": g.rules.iter().map(|r| serde_json::json!({"lhs": r.lhs, "rhs": r.rhs})).collect::<Vec<_>>(),
+                "start_symbol": g.start_symbol,
+            })
+            .to_string()
+        });
+        let json_schema = constraint.json_schema.as_ref().map(|s| {
+            serde_json::json!({
+                "type": s.schema_type,
+                "properties": s.properties,
+                "required": s.required,
+                "additionalProperties": s.additional_properties,
+            })
+            .to_string()
+        });
+
+        Ok(PyConstraintIR {
+            name: constraint.name.clone(),
+            json_schema,
+            grammar,
+            regex_patterns: constraint
+                .regex_patterns
+                .iter()
+                .map(|r| r.pattern.clone())
+                .collect(),
+            validated: Some(constraint),
+        })
+    }
+
+    fn __repr__(&self) -> String {
+        format!("ConstraintBuilder({:?})", self.builder)
+    }
+}
+
+fn invalid_constraint(e: InvalidConstraint) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
 /// Python wrapper for GenerationContext
 #[pyclass]
 #[derive(Clone)]
@@ -160,18 +336,26 @@ pub struct PyGenerationRequest {
 
     #[pyo3(get, set)]
     pub context: Option<PyGenerationContext>,
+
+    #[pyo3(get, set)]
+    pub seed: Option<u64>,
+
+    #[pyo3(get, set)]
+    pub timeout_ms: Option<u64>,
 }
 
 #[pymethods]
 impl PyGenerationRequest {
     #[new]
-    #[pyo3(signature = (prompt, constraints_ir=vec![], max_tokens=2048, temperature=0.7, context=None))]
+    #[pyo3(signature = (prompt, constraints_ir=vec![], max_tokens=2048, temperature=0.7, context=None, seed=None, timeout_ms=None))]
     fn new(
         prompt: String,
         constraints_ir: Vec<PyConstraintIR>,
         max_tokens: usize,
         temperature: f32,
         context: Option<PyGenerationContext>,
+        seed: Option<u64>,
+        timeout_ms: Option<u64>,
     ) -> Self {
         Self {
             prompt,
@@ -179,6 +363,8 @@ impl PyGenerationRequest {
             max_tokens,
             temperature,
             context,
+            seed,
+            timeout_ms,
         }
     }
 
@@ -207,6 +393,9 @@ pub struct PyProvenance {
 
     #[pyo3(get)]
     pub original_intent: String,
+
+    #[pyo3(get)]
+    pub idempotency_key: Option<String>,
 }
 
 #[pymethods]
@@ -303,6 +492,159 @@ impl PyGenerationResponse {
     }
 }
 
+/// Python wrapper for StreamChunk
+#[pyclass]
+#[derive(Clone)]
+pub struct PyStreamChunk {
+    #[pyo3(get)]
+    pub text: String,
+
+    #[pyo3(get)]
+    pub is_final: bool,
+
+    #[pyo3(get)]
+    pub token_index: usize,
+
+    #[pyo3(get)]
+    pub timestamp_ms: u64,
+
+    /// True on the first chunk after a fallback; earlier text is discarded
+    #[pyo3(get)]
+    pub restarted: bool,
+}
+
+#[pymethods]
+impl PyStreamChunk {
+    fn __repr__(&self) -> String {
+        format!(
+            "PyStreamChunk(index={}, final={}, text={:?})",
+            self.token_index, self.is_final, self.text
+        )
+    }
+}
+
+/// Handle for cancelling an in-flight streaming generation
+///
+/// Pass to `Ananke.generate_stream` and call `cancel()` from any thread or
+/// task; the stream stops at its next chunk and the HTTP request is dropped.
+#[pyclass]
+#[derive(Clone)]
+pub struct PyCancellationHandle {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+#[pymethods]
+impl PyCancellationHandle {
+    #[new]
+    fn new() -> Self {
+        Self {
+            sender: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    /// Request cancellation. Idempotent.
+    fn cancel(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Whether `cancel()` has been called
+    #[getter]
+    fn cancelled(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("PyCancellationHandle(cancelled={})", self.cancelled())
+    }
+}
+
+/// State of a [`PyGenerationStream`]
+enum StreamState {
+    /// Not started; the request is sent on the first `__anext__`. Boxed,
+    /// since a request is much larger than a running stream
+    Pending(Arc<MazeOrchestrator>, Box<GenerationRequest>),
+    Running(StreamingResult),
+    Done,
+}
+
+/// Async iterator over the chunks of a streaming generation
+///
+/// Returned by `Ananke.generate_stream`; use with `async for`.
+#[pyclass]
+pub struct PyGenerationStream {
+    state: Arc<tokio::sync::Mutex<StreamState>>,
+    cancel: Option<watch::Receiver<bool>>,
+}
+
+#[pymethods]
+impl PyGenerationStream {
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let state = self.state.clone();
+        let cancel = self.cancel.clone();
+
+        // The GIL is not held while this future runs: starting the request
+        // and waiting for each chunk happen on the tokio runtime, and the GIL
+        // is reacquired only to hand the finished chunk back to Python.
+        future_into_py(py, async move {
+            let mut state = state.lock().await;
+
+            // Send the request on first use; a failure leaves the stream done
+            *state = match std::mem::replace(&mut *state, StreamState::Done) {
+                StreamState::Pending(orch, request) => {
+                    let stream = orch.generate_stream(*request).await.map_err(|e| {
+                        PyRuntimeError::new_err(format!("Streaming generation failed: {}", e))
+                    })?;
+                    StreamState::Running(stream)
+                }
+                other => other,
+            };
+
+            let StreamState::Running(ref mut stream) = *state else {
+                return Err(PyStopAsyncIteration::new_err("stream exhausted"));
+            };
+
+            let next = match cancel {
+                Some(mut cancel) => tokio::select! {
+                    chunk = stream.next() => chunk,
+                    _ = wait_cancelled(&mut cancel) => None,
+                },
+                None => stream.next().await,
+            };
+
+            match next {
+                Some(Ok(chunk)) => {
+                    if chunk.is_final {
+                        *state = StreamState::Done;
+                    }
+                    Ok(PyStreamChunk {
+                        text: chunk.text,
+                        is_final: chunk.is_final,
+                        token_index: chunk.token_index,
+                        timestamp_ms: chunk.timestamp_ms,
+                        restarted: chunk.restarted,
+                    })
+                }
+                Some(Err(e)) => {
+                    *state = StreamState::Done;
+                    Err(PyRuntimeError::new_err(format!(
+                        "Streaming generation failed: {}",
+                        e
+                    )))
+                }
+                None => {
+                    // Exhausted or cancelled; dropping the stream closes the connection
+                    *state = StreamState::Done;
+                    Err(PyStopAsyncIteration::new_err("stream exhausted"))
+                }
+            }
+        })
+    }
+}
+
 /// Main Python API class for Ananke
 ///
 /// This wraps the Rust MazeOrchestrator and provides a Pythonic async interface.
@@ -357,9 +699,21 @@ impl Ananke {
             endpoint_url: modal_endpoint,
             model,
             api_key: modal_api_key,
+            auth_scheme: crate::modal_client::AuthScheme::default(),
+            user_agent: None,
             timeout_secs,
             enable_retry: true,
             max_retries: 3,
+            models_cache_ttl_secs: 300,
+            redaction: RedactionPolicy::default(),
+            transport: Transport::default(),
+            decompress_responses: true,
+            stream_idle_timeout_secs: Some(60),
+            replicas: Vec::new(),
+            circuit_breaker: crate::CircuitBreakerConfig::default(),
+            hedge_after_ms: None,
+            max_response_bytes: Some(crate::modal_client::DEFAULT_MAX_RESPONSE_BYTES),
+            health_check: crate::HealthCheckConfig::default(),
         };
 
         let maze_config = MazeConfig {
@@ -368,6 +722,30 @@ impl Ananke {
             enable_cache,
             cache_size_limit: cache_size,
             timeout_secs,
+            lint_on_compile: false,
+            strict_provenance: false,
+            language_grammars: true,
+            context_overflow: ContextOverflowPolicy::Reject,
+            truncation_order: context_window::default_truncation_order(),
+            snippet_token_budget: 1024,
+            context_compression: crate::CompressionStrategy::None,
+            response_cache: ResponseCacheConfig::default(),
+            redaction: RedactionPolicy::default(),
+            max_concurrent_requests: 0,
+            queue_timeout_ms: None,
+            adaptive_concurrency: None,
+            constraint_format: ConstraintFormat::default(),
+            ir_format: crate::IrFormat::default(),
+            tokenizer_path: None,
+            coalesce_requests: true,
+            output_guard: crate::OutputGuard::default(),
+            post_process: crate::PostProcessConfig::default(),
+            model_chain: crate::ModelChainConfig::default(),
+            stream_deadline: None,
+            runaway_limit: None,
+            format_policy: crate::FormatPolicy::default(),
+            include_constraint_events: false,
+            empty_constraints: crate::EmptyConstraintsPolicy::default(),
         };
 
         let orchestrator =
@@ -389,13 +767,16 @@ impl Ananke {
     ///     - MODAL_ENDPOINT (required): Modal inference service URL
     ///     - MODAL_API_KEY (optional): API key for authentication
     ///     - MODAL_MODEL (optional): Model name (default: "meta-llama/Llama-3.1-8B-Instruct")
-    ///     - ANANKE_CACHE_SIZE (optional): Cache size (default: 1000)
+    ///     - MAZE_MAX_TOKENS, MAZE_TEMPERATURE, MAZE_CACHE_SIZE, MAZE_TIMEOUT_SECS,
+    ///       MAZE_ENABLE_CACHE (optional): Orchestrator settings, see `MazeConfig::from_env`
+    ///     - ANANKE_CACHE_SIZE (optional): Cache size when MAZE_CACHE_SIZE is unset (default: 1000)
     ///
     /// Returns:
     ///     Ananke: An initialized Ananke orchestrator instance.
     ///
     /// Raises:
-    ///     RuntimeError: If MODAL_ENDPOINT is not set or configuration fails.
+    ///     RuntimeError: If MODAL_ENDPOINT is not set, a MAZE_* value is invalid,
+    ///         or configuration fails.
     ///
     /// Example (Python):
     ///     ```
@@ -416,18 +797,23 @@ impl Ananke {
             ))
         })?;
 
-        let cache_size = std::env::var("ANANKE_CACHE_SIZE")
-            .ok()
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(1000);
+        let mut maze_config = MazeConfig::from_env().map_err(|e| {
+            PyRuntimeError::new_err(format!(
+                "Failed to load Maze config from environment: {}",
+                e
+            ))
+        })?;
 
-        let maze_config = MazeConfig {
-            max_tokens: 2048,
-            temperature: 0.7,
-            enable_cache: true,
-            cache_size_limit: cache_size,
-            timeout_secs: modal_config.timeout_secs,
-        };
+        // ANANKE_CACHE_SIZE predates MAZE_CACHE_SIZE and is still honored
+        if std::env::var("MAZE_CACHE_SIZE").is_err() {
+            if let Some(cache_size) = std::env::var("ANANKE_CACHE_SIZE")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .filter(|&size| size > 0)
+            {
+                maze_config.cache_size_limit = cache_size;
+            }
+        }
 
         let orchestrator =
             MazeOrchestrator::with_config(modal_config, maze_config).map_err(|e| {
@@ -506,6 +892,94 @@ impl Ananke {
         })
     }
 
+    /// Generate code with constraints, blocking the calling thread.
+    ///
+    /// For threaded servers and scripts that are not running an event loop.
+    /// The GIL is released for the whole call (constraint compilation and
+    /// the network round-trip to Modal), so other Python threads keep
+    /// running and concurrent `generate_sync` calls overlap.
+    ///
+    /// Args:
+    ///     request (PyGenerationRequest): Generation request, as for `generate`
+    ///
+    /// Returns:
+    ///     PyGenerationResponse: The generation result
+    ///
+    /// Raises:
+    ///     RuntimeError: If generation fails (network error, timeout, inference error)
+    ///
+    /// Example (Python):
+    ///     ```
+    ///     # from concurrent.futures import ThreadPoolExecutor
+    ///     #
+    ///     # with ThreadPoolExecutor(max_workers=4) as pool:
+    ///     #     results = list(pool.map(ananke.generate_sync, requests))
+    ///     ```
+    fn generate_sync(
+        &self,
+        py: Python<'_>,
+        request: PyGenerationRequest,
+    ) -> PyResult<PyGenerationResponse> {
+        let rust_request = python_request_to_rust(request)?;
+        let orch = self.orchestrator.clone();
+
+        let result = py
+            .detach(|| {
+                pyo3_async_runtimes::tokio::get_runtime().block_on(orch.generate(rust_request))
+            })
+            .map_err(|e| PyRuntimeError::new_err(format!("Generation failed: {}", e)))?;
+
+        rust_response_to_python(result)
+    }
+
+    /// Stream generated code chunk by chunk as it is produced.
+    ///
+    /// Returns an async iterator; the request is sent when iteration starts.
+    /// Constraints are compiled and the prompt assembled exactly as in
+    /// `generate`, but the response cache is not used.
+    ///
+    /// The GIL is released for all network waits: sending the request and
+    /// awaiting each chunk run on the tokio runtime, and the GIL is only
+    /// reacquired to convert each chunk to a `PyStreamChunk`. Other Python
+    /// threads and tasks keep running while tokens are in flight.
+    ///
+    /// Args:
+    ///     request (PyGenerationRequest): Generation request, as for `generate`
+    ///     cancel (Optional[PyCancellationHandle]): Handle whose `cancel()`
+    ///         ends the stream early. Cancelling the awaiting asyncio task
+    ///         also stops the stream.
+    ///
+    /// Returns:
+    ///     PyGenerationStream: Async iterator of `PyStreamChunk`
+    ///
+    /// Raises:
+    ///     RuntimeError: If the request cannot be prepared or the stream fails
+    ///
+    /// Example (Python):
+    ///     ```
+    ///     # handle = PyCancellationHandle()
+    ///     # async for chunk in ananke.generate_stream(request, cancel=handle):
+    ///     #     print(chunk.text, end="", flush=True)
+    ///     #     if chunk.token_index > 200:
+    ///     #         handle.cancel()
+    ///     ```
+    #[pyo3(signature = (request, cancel=None))]
+    fn generate_stream(
+        &self,
+        request: PyGenerationRequest,
+        cancel: Option<PyCancellationHandle>,
+    ) -> PyResult<PyGenerationStream> {
+        let rust_request = python_request_to_rust(request)?;
+
+        Ok(PyGenerationStream {
+            state: Arc::new(tokio::sync::Mutex::new(StreamState::Pending(
+                self.orchestrator.clone(),
+                Box::new(rust_request),
+            ))),
+            cancel: cancel.map(|handle| handle.sender.subscribe()),
+        })
+    }
+
     /// Compile constraints to llguidance format
     ///
     /// Args:
@@ -522,21 +996,8 @@ impl Ananke {
         constraints: Vec<PyConstraintIR>,
     ) -> PyResult<Bound<'py, PyAny>> {
         // Convert Python constraints to Rust constraints
-        let rust_constraints: Vec<ConstraintIR> = constraints
-            .iter()
-            .map(|py_c| ConstraintIR {
-                name: py_c.name.clone(),
-                json_schema: None,
-                grammar: None,
-                regex_patterns: vec![],
-                token_masks: None,
-                type_inhabitation: None,
-                priority: 2,
-                rich_context: None,
-                feasibility_score: 0.0,
-                is_feasible: true,
-            })
-            .collect();
+        let rust_constraints: Vec<ConstraintIR> =
+            constraints.iter().map(python_constraint_to_rust).collect();
 
         let orch = self.orchestrator.clone();
 
@@ -564,13 +1025,13 @@ impl Ananke {
     /// Check if Modal inference service is healthy
     ///
     /// Returns:
-    ///     bool: True if service is healthy, False otherwise
+    ///     bool: True if service is healthy or degraded, False otherwise
     fn health_check<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
         let orch = self.orchestrator.clone();
 
         future_into_py(py, async move {
             match orch.health_check().await {
-                Ok(is_healthy) => Ok(is_healthy),
+                Ok(status) => Ok(status.is_ready()),
                 Err(e) => {
                     tracing::warn!("Health check failed: {}", e);
                     Ok(false)
@@ -620,99 +1081,135 @@ impl Ananke {
     }
 }
 
+/// Resolve once cancellation is requested; never resolves if the handle is dropped
+async fn wait_cancelled(cancel: &mut watch::Receiver<bool>) {
+    if cancel.wait_for(|cancelled| *cancelled).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
 // Helper functions for type conversion
 
-fn python_request_to_rust(py_req: PyGenerationRequest) -> PyResult<GenerationRequest> {
-    let constraints_ir: Vec<ConstraintIR> = py_req
-        .constraints_ir
-        .iter()
-        .map(|py_c| {
-            // Parse JSON schema from Python string if provided
-            let json_schema = py_c.json_schema.as_ref().and_then(|schema_str| {
-                serde_json::from_str::<serde_json::Value>(schema_str)
-                    .ok()
-                    .map(|value| crate::ffi::JsonSchema {
-                        schema_type: value
-                            .get("type")
-                            .and_then(|t| t.as_str())
-                            .unwrap_or("object")
-                            .to_string(),
-                        properties: value
-                            .get("properties")
-                            .and_then(|p| p.as_object())
-                            .map(|obj| obj.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
-                            .unwrap_or_default(),
-                        required: value
-                            .get("required")
-                            .and_then(|r| r.as_array())
-                            .map(|arr| {
-                                arr.iter()
-                                    .filter_map(|v| v.as_str().map(String::from))
-                                    .collect()
-                            })
-                            .unwrap_or_default(),
-                        additional_properties: value
-                            .get("additionalProperties")
-                            .and_then(|a| a.as_bool())
-                            .unwrap_or(true),
+/// Convert a Python constraint
+///
+/// The fields are read as they are now, so edits made from Python after
+/// `ConstraintBuilder.build()` take effect. What the fields cannot express
+/// (token masks, priority, and the flags of regexes still present) comes
+/// from the builder's constraint.
+fn python_constraint_to_rust(py_c: &PyConstraintIR) -> ConstraintIR {
+    let mut constraint = constraint_from_fields(py_c);
+    if let Some(ref validated) = py_c.validated {
+        constraint.token_masks = validated.token_masks.clone();
+        constraint.type_inhabitation = validated.type_inhabitation.clone();
+        constraint.priority = validated.priority;
+        for pattern in &mut constraint.regex_patterns {
+            if let Some(built) = validated
+                .regex_patterns
+                .iter()
+                .find(|built| built.pattern == pattern.pattern)
+            {
+                pattern.flags = built.flags.clone();
+            }
+        }
+    }
+    constraint
+}
+
+/// A constraint from the Python fields alone
+fn constraint_from_fields(py_c: &PyConstraintIR) -> ConstraintIR {
+    // Parse JSON schema from Python string if provided
+    let json_schema = py_c.json_schema.as_ref().and_then(|schema_str| {
+        serde_json::from_str::<serde_json::Value>(schema_str)
+            .ok()
+            .map(|value| crate::ffi::JsonSchema {
+                schema_type: value
+                    .get("type")
+                    .and_then(|t| t.as_str())
+                    .unwrap_or("object")
+                    .to_string(),
+                properties: value
+                    .get("properties")
+                    .and_then(|p| p.as_object())
+                    .map(|obj| obj.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+                    .unwrap_or_default(),
+                required: value
+                    .get("required")
+                    .and_then(|r| r.as_array())
+                    .map(|arr| {
+                        arr.iter()
+                            .filter_map(|v| v.as_str().map(String::from))
+                            .collect()
                     })
-            });
-
-            // Parse grammar from Python string if provided
-            // Expected format: JSON with { "rules": [...], "start_symbol": "..." }
-            let grammar = py_c.grammar.as_ref().and_then(|grammar_str| {
-                serde_json::from_str::<serde_json::Value>(grammar_str)
-                    .ok()
-                    .and_then(|value| {
-                        let rules = value
-                            .get("rules")?
-                            .as_array()?
-                            .iter()
-                            .filter_map(|rule| {
-                                Some(crate::ffi::GrammarRule {
-                                    lhs: rule.get("lhs")?.as_str()?.to_string(),
-                                    rhs: rule
-                                        .get("rhs")?
-                                        .as_array()?
-                                        .iter()
-                                        .filter_map(|r| r.as_str().map(String::from))
-                                        .collect(),
-                                })
-                            })
-                            .collect();
-                        let start_symbol = value.get("start_symbol")?.as_str()?.to_string();
-                        Some(crate::ffi::Grammar {
-                            rules,
-                            start_symbol,
+                    .unwrap_or_default(),
+                additional_properties: value
+                    .get("additionalProperties")
+                    .and_then(|a| a.as_bool())
+                    .unwrap_or(true),
+            })
+    });
+
+    // Parse grammar from Python string if provided
+    // Expected format: JSON with { "rules": [...], "start_symbol": "..." }
+    let grammar = py_c.grammar.as_ref().and_then(|grammar_str| {
+        serde_json::from_str::<serde_json::Value>(grammar_str)
+            .ok()
+            .and_then(|value| {
+                let rules = value
+                    .get("rules")?
+                    .as_array()?
+                    .iter()
+                    .filter_map(|rule| {
+                        Some(crate::ffi::GrammarRule {
+                            lhs: rule.get("lhs")?.as_str()?.to_string(),
+                            rhs: rule
+                                .get("rhs")?
+                                .as_array()?
+                                .iter()
+                                .filter_map(|r| r.as_str().map(String::from))
+                                .collect(),
                         })
                     })
-            });
-
-            // Convert regex patterns from Python strings
-            let regex_patterns: Vec<crate::ffi::RegexPattern> = py_c
-                .regex_patterns
-                .iter()
-                .map(|pattern| crate::ffi::RegexPattern {
-                    pattern: pattern.clone(),
-                    flags: String::new(),
+                    .collect();
+                let start_symbol = value.get("start_symbol")?.as_str()?.to_string();
+                Some(crate::ffi::Grammar {
+                    rules,
+                    start_symbol,
                 })
-                .collect();
-
-            ConstraintIR {
-                name: py_c.name.clone(),
-                json_schema,
-                grammar,
-                regex_patterns,
-                token_masks: None,
-                type_inhabitation: None,
-                priority: 2,
-                rich_context: None,
-                feasibility_score: 0.0,
-                is_feasible: true,
-            }
+            })
+    });
+
+    // Convert regex patterns from Python strings
+    let regex_patterns: Vec<crate::ffi::RegexPattern> = py_c
+        .regex_patterns
+        .iter()
+        .map(|pattern| crate::ffi::RegexPattern {
+            pattern: pattern.clone(),
+            flags: String::new(),
         })
         .collect();
 
+    ConstraintIR {
+        name: py_c.name.clone(),
+        json_schema,
+        grammar,
+        regex_patterns,
+        token_masks: None,
+        type_inhabitation: None,
+        priority: 2,
+        rich_context: None,
+        feasibility_score: 0.0,
+        is_feasible: true,
+        schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+    }
+}
+
+fn python_request_to_rust(py_req: PyGenerationRequest) -> PyResult<GenerationRequest> {
+    let constraints_ir: Vec<ConstraintIR> = py_req
+        .constraints_ir
+        .iter()
+        .map(python_constraint_to_rust)
+        .collect();
+
     let context = py_req.context.map(|py_ctx| GenerationContext {
         current_file: py_ctx.current_file,
         language: py_ctx.language,
@@ -726,6 +1223,9 @@ fn python_request_to_rust(py_req: PyGenerationRequest) -> PyResult<GenerationReq
         max_tokens: py_req.max_tokens,
         temperature: py_req.temperature,
         context,
+        seed: py_req.seed,
+        timeout_ms: py_req.timeout_ms,
+        history: Vec::new(),
     })
 }
 
@@ -737,6 +1237,7 @@ fn rust_response_to_python(response: GenerationResponse) -> PyResult<PyGeneratio
             timestamp: response.provenance.timestamp,
             constraints_applied: response.provenance.constraints_applied,
             original_intent: response.provenance.original_intent,
+            idempotency_key: response.provenance.idempotency_key,
         },
         validation: PyValidationResult {
             all_satisfied: response.validation.all_satisfied,
@@ -758,11 +1259,15 @@ fn ananke(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<Ananke>()?;
     m.add_class::<PyModalConfig>()?;
     m.add_class::<PyConstraintIR>()?;
+    m.add_class::<PyConstraintBuilder>()?;
     m.add_class::<PyGenerationRequest>()?;
     m.add_class::<PyGenerationResponse>()?;
     m.add_class::<PyGenerationContext>()?;
     m.add_class::<PyProvenance>()?;
     m.add_class::<PyValidationResult>()?;
     m.add_class::<PyGenerationMetadata>()?;
+    m.add_class::<PyStreamChunk>()?;
+    m.add_class::<PyCancellationHandle>()?;
+    m.add_class::<PyGenerationStream>()?;
     Ok(())
 }
@@ -0,0 +1,176 @@
+//! Redaction of prompts and generated code in logs, errors, and provenance
+//!
+//! Prompts and outputs can carry secrets or proprietary code. Wherever Maze
+//! logs them, embeds them in an error, or records them in [`Provenance`],
+//! it passes them through the configured [`RedactionPolicy`] first.
+//!
+//! [`Provenance`]: crate::Provenance
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// How prompts and generated code are redacted before leaving the process
+///
+/// Defaults to [`Hash`](Self::Hash) in release builds and
+/// [`None`](Self::None) in debug builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionPolicy {
+    /// Keep text as-is
+    None,
+
+    /// Replace text with a content hash and its length, so identical
+    /// inputs can still be correlated across log lines
+    Hash,
+
+    /// Keep the first `n` characters
+    Truncate {
+        /// Characters to keep
+        n: usize,
+    },
+
+    /// Replace text with a fixed marker
+    Full,
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> Self {
+        if cfg!(debug_assertions) {
+            Self::None
+        } else {
+            Self::Hash
+        }
+    }
+}
+
+impl RedactionPolicy {
+    /// Redact `text` according to this policy
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match *self {
+            Self::None => Cow::Borrowed(text),
+            Self::Hash => Cow::Owned(format!(
+                "[redacted xxh3:{:016x} len={}]",
+                xxhash_rust::xxh3::xxh3_64(text.as_bytes()),
+                text.len()
+            )),
+            Self::Truncate { n } => match text.char_indices().nth(n) {
+                None => Cow::Borrowed(text),
+                Some((cut, _)) => Cow::Owned(format!(
+                    "{}...[{} more chars]",
+                    &text[..cut],
+                    text[cut..].chars().count()
+                )),
+            },
+            Self::Full => Cow::Borrowed("[redacted]"),
+        }
+    }
+}
+
+/// Whether `text` looks like the output of a redacting [`RedactionPolicy`]
+///
+/// Used to refuse work that needs the original text, such as replaying a
+/// generation from its provenance.
+pub fn is_redacted(text: &str) -> bool {
+    if text == "[redacted]" || (text.starts_with("[redacted xxh3:") && text.ends_with(']')) {
+        return true;
+    }
+    text.strip_suffix(" more chars]")
+        .and_then(|rest| rest.rsplit_once("...["))
+        .is_some_and(|(_, count)| !count.is_empty() && count.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Whether `text` is the original of `recorded`, a possibly redacted
+/// record of it
+///
+/// Unredacted and hashed records are compared exactly, and truncated ones
+/// by prefix and remaining length. `None` for a fully redacted record,
+/// which no text can be checked against.
+pub fn matches_record(recorded: &str, text: &str) -> Option<bool> {
+    if !is_redacted(recorded) {
+        return Some(recorded == text);
+    }
+    if recorded == "[redacted]" {
+        return None;
+    }
+    if recorded.starts_with("[redacted xxh3:") {
+        return Some(RedactionPolicy::Hash.apply(text) == recorded);
+    }
+    let (prefix, count) = recorded
+        .strip_suffix(" more chars]")
+        .and_then(|rest| rest.rsplit_once("...["))?;
+    let count: usize = count.parse().ok()?;
+    Some(
+        text.strip_prefix(prefix)
+            .is_some_and(|rest| rest.chars().count() == count),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policies() {
+        let secret = "api_key = \"sk-123\"";
+
+        assert_eq!(RedactionPolicy::None.apply(secret), secret);
+        assert_eq!(RedactionPolicy::Full.apply(secret), "[redacted]");
+
+        let hashed = RedactionPolicy::Hash.apply(secret);
+        assert!(!hashed.contains("sk-123"));
+        assert!(hashed.ends_with("len=18]"));
+        assert_eq!(hashed, RedactionPolicy::Hash.apply(secret));
+
+        let truncated = RedactionPolicy::Truncate { n: 7 }.apply(secret);
+        assert_eq!(truncated, "api_key...[11 more chars]");
+        assert_eq!(RedactionPolicy::Truncate { n: 100 }.apply(secret), secret);
+    }
+
+    #[test]
+    fn test_truncate_respects_char_boundaries() {
+        let text = "héllo";
+        assert_eq!(
+            RedactionPolicy::Truncate { n: 2 }.apply(text),
+            "hé...[3 more chars]"
+        );
+    }
+
+    #[test]
+    fn test_is_redacted() {
+        let secret = "api_key = \"sk-123\"";
+        assert!(!is_redacted(secret));
+        assert!(is_redacted(&RedactionPolicy::Hash.apply(secret)));
+        assert!(is_redacted(&RedactionPolicy::Full.apply(secret)));
+        assert!(is_redacted(
+            &RedactionPolicy::Truncate { n: 7 }.apply(secret)
+        ));
+        assert!(!is_redacted("see [redacted] below"));
+    }
+
+    #[test]
+    fn test_matches_record() {
+        let secret = "api_key = \"sk-123\"";
+        for policy in [
+            RedactionPolicy::None,
+            RedactionPolicy::Hash,
+            RedactionPolicy::Truncate { n: 7 },
+        ] {
+            let recorded = policy.apply(secret);
+            assert_eq!(matches_record(&recorded, secret), Some(true));
+            assert_eq!(
+                matches_record(&recorded, "api_key = \"sk-999\""),
+                Some(policy == RedactionPolicy::Truncate { n: 7 })
+            );
+            assert_eq!(matches_record(&recorded, "api_key"), Some(false));
+        }
+        assert_eq!(matches_record("[redacted]", secret), None);
+    }
+
+    #[test]
+    fn test_serde_format() {
+        let json = serde_json::to_string(&RedactionPolicy::Truncate { n: 40 }).unwrap();
+        assert_eq!(json, r#"{"truncate":{"n":40}}"#);
+        let policy: RedactionPolicy = serde_json::from_str(r#""hash""#).unwrap();
+        assert_eq!(policy, RedactionPolicy::Hash);
+    }
+}
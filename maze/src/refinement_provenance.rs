@@ -0,0 +1,429 @@
+//! Provenance of a whole refinement run
+//!
+//! A single generation carries a [`Provenance`](crate::Provenance); a
+//! refined file is assembled from many fills, possibly by different models
+//! at different temperatures. [`RefinementProvenance`] records, for every
+//! hole, which models and constraints produced its fill and when, so an
+//! audit can trace each region of the output back to how it was generated.
+//!
+//! Each [`HoleProvenance`] is located by the hole's origin, read as
+//! `file:line:column` with line and column optional. A decomposed hole's
+//! fill is its children's fills, so its model counts and attempt times
+//! include those of all its descendants.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::ffi::ConstraintIR;
+use crate::progressive_refinement::{HoleState, HoleStatus};
+
+/// Where a hole sits in the source, parsed from its origin
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SourceSpan {
+    /// File path or URI
+    pub file: String,
+
+    /// 1-based line, if the origin has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>,
+
+    /// 1-based column, if the origin has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub column: Option<u64>,
+}
+
+impl SourceSpan {
+    /// Parse an origin of the form `file[:line[:column]]`
+    ///
+    /// The `:child_<index>` suffixes decomposition appends are dropped, so
+    /// a sub-hole maps to its parent's location. Any other suffix that is
+    /// not a number is part of the file, so `C:/code/a.rs` is a file without
+    /// a line. `None` for an empty origin.
+    pub fn parse(mut origin: &str) -> Option<Self> {
+        while let Some((rest, index)) = origin.rsplit_once(":child_") {
+            if index.is_empty() || !index.bytes().all(|b| b.is_ascii_digit()) {
+                break;
+            }
+            origin = rest;
+        }
+        if origin.is_empty() {
+            return None;
+        }
+        let whole = || (origin, None, None);
+        let mut parts = origin.rsplitn(3, ':');
+        let last = parts.next().unwrap_or_default();
+        let middle = parts.next();
+        let (file, line, column) = match (middle, parts.next()) {
+            (Some(line), Some(file)) => match (line.parse::<u64>(), last.parse::<u64>()) {
+                (Ok(line), Ok(column)) => (file, Some(line), Some(column)),
+                _ => whole(),
+            },
+            (Some(file), None) => match last.parse::<u64>() {
+                Ok(line) => (file, Some(line), None),
+                Err(_) => whole(),
+            },
+            _ => whole(),
+        };
+        Some(Self {
+            file: file.to_string(),
+            line,
+            column,
+        })
+    }
+}
+
+/// One fill attempt, as recorded in provenance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AttemptProvenance {
+    /// Model that generated the fill
+    pub model: String,
+
+    /// Sampling temperature
+    pub temperature: f32,
+
+    /// Unix timestamp of the attempt, in seconds
+    pub timestamp: i64,
+
+    /// Whether the fill passed validation
+    pub accepted: bool,
+}
+
+/// How one hole's fill was produced
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HoleProvenance {
+    /// Hole ID
+    pub hole_id: u64,
+
+    /// Hole this one was decomposed from, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<u64>,
+
+    /// Origin/source location of the hole
+    pub origin: String,
+
+    /// `origin` parsed; `None` when it is empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub span: Option<SourceSpan>,
+
+    /// Scale of the hole (nano, micro, meso, macro)
+    pub scale: String,
+
+    /// Final status
+    pub status: HoleStatus,
+
+    /// Model of the accepted fill on this hole itself; `None` if no fill
+    /// was accepted, as for a decomposed hole
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    /// Temperature of the accepted fill
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    /// Timestamp of the accepted fill
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<i64>,
+
+    /// Constraints enforced on the hole's fills, in the order of the set
+    pub constraints: Vec<String>,
+
+    /// Attempts on this hole itself, in order
+    pub attempts: Vec<AttemptProvenance>,
+
+    /// Attempts per model, on this hole and its descendants
+    pub models: BTreeMap<String, usize>,
+
+    /// Earliest attempt on this hole or its descendants
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_attempt_at: Option<i64>,
+
+    /// Latest attempt on this hole or its descendants
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_attempt_at: Option<i64>,
+}
+
+/// How every hole of a refined file was produced
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RefinementProvenance {
+    /// One entry per hole, in the order of `RefinementResult::holes`
+    pub holes: Vec<HoleProvenance>,
+
+    /// Attempts per model across all holes
+    pub models: BTreeMap<String, usize>,
+
+    /// Constraints enforced on at least one hole, in the order of the set
+    pub constraints: Vec<String>,
+
+    /// Earliest attempt of the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_attempt_at: Option<i64>,
+
+    /// Latest attempt of the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_attempt_at: Option<i64>,
+}
+
+impl RefinementProvenance {
+    /// Collect provenance from final hole states and the constraint set they
+    /// were refined under
+    pub fn new(holes: &[HoleState], constraints: &[ConstraintIR]) -> Self {
+        let mut names: Vec<&str> = Vec::new();
+        for constraint in constraints {
+            if !names.contains(&constraint.name.as_str()) {
+                names.push(&constraint.name);
+            }
+        }
+
+        let by_id: HashMap<u64, &HoleState> = holes.iter().map(|h| (h.id, h)).collect();
+        let holes: Vec<HoleProvenance> = holes
+            .iter()
+            .map(|hole| HoleProvenance::new(hole, &names, &by_id))
+            .collect();
+
+        let mut models = BTreeMap::new();
+        for attempt in holes.iter().flat_map(|h| &h.attempts) {
+            *models.entry(attempt.model.clone()).or_insert(0) += 1;
+        }
+        let applied: HashSet<&str> = holes
+            .iter()
+            .flat_map(|h| &h.constraints)
+            .map(String::as_str)
+            .collect();
+        let timestamps = || holes.iter().flat_map(|h| &h.attempts).map(|a| a.timestamp);
+
+        Self {
+            models,
+            constraints: names
+                .into_iter()
+                .filter(|name| applied.contains(name))
+                .map(str::to_string)
+                .collect(),
+            first_attempt_at: timestamps().min(),
+            last_attempt_at: timestamps().max(),
+            holes,
+        }
+    }
+
+    /// Provenance of hole `id`
+    pub fn hole(&self, id: u64) -> Option<&HoleProvenance> {
+        self.holes.iter().find(|h| h.hole_id == id)
+    }
+
+    /// Holes located in `file`, in order
+    pub fn in_file<'a>(&'a self, file: &'a str) -> impl Iterator<Item = &'a HoleProvenance> {
+        self.holes
+            .iter()
+            .filter(move |h| h.span.as_ref().is_some_and(|span| span.file == file))
+    }
+}
+
+impl HoleProvenance {
+    fn new(hole: &HoleState, names: &[&str], by_id: &HashMap<u64, &HoleState>) -> Self {
+        let attempts: Vec<AttemptProvenance> = hole
+            .attempts
+            .iter()
+            .map(|a| AttemptProvenance {
+                model: a.model.clone(),
+                temperature: a.temperature,
+                timestamp: a.timestamp,
+                accepted: a.validation_passed,
+            })
+            .collect();
+        let accepted = attempts.iter().rev().find(|a| a.accepted);
+
+        let constraints = match &hole.constraint_refs {
+            None => names.iter().map(|name| name.to_string()).collect(),
+            Some(refs) => names
+                .iter()
+                .filter(|name| refs.iter().any(|r| r == *name))
+                .map(|name| name.to_string())
+                .collect(),
+        };
+
+        // Merge in every descendant's attempts; guards against parent links
+        // that loop in hand-edited states
+        let mut models = BTreeMap::new();
+        let mut timestamps = Vec::new();
+        let mut visited = HashSet::new();
+        let mut stack = vec![hole];
+        while let Some(current) = stack.pop() {
+            if !visited.insert(current.id) {
+                continue;
+            }
+            for attempt in &current.attempts {
+                *models.entry(attempt.model.clone()).or_insert(0) += 1;
+                timestamps.push(attempt.timestamp);
+            }
+            stack.extend(current.child_ids.iter().filter_map(|id| by_id.get(id)));
+        }
+
+        Self {
+            hole_id: hole.id,
+            parent_id: hole.parent_id,
+            origin: hole.origin.clone(),
+            span: SourceSpan::parse(&hole.origin),
+            scale: hole.scale.clone(),
+            status: hole.status,
+            model: accepted.map(|a| a.model.clone()),
+            temperature: accepted.map(|a| a.temperature),
+            timestamp: accepted.map(|a| a.timestamp),
+            constraints,
+            models,
+            first_attempt_at: timestamps.iter().copied().min(),
+            last_attempt_at: timestamps.iter().copied().max(),
+            attempts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progressive_refinement::FillAttempt;
+
+    fn attempt(model: &str, timestamp: i64, passed: bool) -> FillAttempt {
+        FillAttempt {
+            code: "x".to_string(),
+            confidence: 0.8,
+            raw_confidence: None,
+            temperature: timestamp as f32 / 10.0,
+            model: model.to_string(),
+            timestamp,
+            validation_passed: passed,
+            error: (!passed).then(|| "rejected".to_string()),
+            tokens_generated: 1,
+            stats: None,
+            model_chain: vec![],
+            finish_reason: Default::default(),
+            constraint_checks: vec![],
+        }
+    }
+
+    fn constraint(name: &str) -> ConstraintIR {
+        ConstraintIR {
+            name: name.to_string(),
+            json_schema: None,
+            grammar: None,
+            regex_patterns: vec![],
+            token_masks: None,
+            type_inhabitation: None,
+            priority: 0,
+            rich_context: None,
+            feasibility_score: 0.0,
+            is_feasible: true,
+            schema_version: crate::ffi::CONSTRAINT_SCHEMA_VERSION,
+        }
+    }
+
+    /// A decomposed hole with two children, and an unrelated hole
+    fn holes() -> Vec<HoleState> {
+        let mut parent = HoleState::new(1, "meso".to_string(), "src/a.rs:3:5".to_string())
+            .with_constraint_refs(["body"]);
+        parent.attempts = vec![attempt("big", 1, false)];
+        parent.status = HoleStatus::Filled;
+        parent.child_ids = vec![10, 11];
+
+        let mut first = HoleState::new(10, "micro".to_string(), "src/a.rs:3:5".to_string());
+        first.parent_id = Some(1);
+        first.constraint_refs = parent.constraint_refs.clone();
+        first.attempts = vec![attempt("small", 2, true)];
+        first.status = HoleStatus::Filled;
+
+        let mut second = HoleState::new(11, "micro".to_string(), "src/a.rs:4:5".to_string());
+        second.parent_id = Some(1);
+        second.constraint_refs = parent.constraint_refs.clone();
+        second.attempts = vec![attempt("small", 3, false), attempt("big", 4, true)];
+        second.status = HoleStatus::Filled;
+
+        let mut other = HoleState::new(2, "nano".to_string(), "src/b.rs".to_string());
+        other.attempts = vec![attempt("small", 5, false)];
+        other.status = HoleStatus::Failed;
+
+        vec![parent, first, second, other]
+    }
+
+    #[test]
+    fn test_decomposed_hole_merges_children() {
+        let constraints = [constraint("signature"), constraint("body")];
+        let provenance = RefinementProvenance::new(&holes(), &constraints);
+
+        let parent = provenance.hole(1).unwrap();
+        assert_eq!(parent.model, None);
+        assert_eq!(parent.attempts.len(), 1);
+        assert_eq!(
+            parent.models,
+            BTreeMap::from([("big".to_string(), 2), ("small".to_string(), 2)])
+        );
+        assert_eq!(
+            (parent.first_attempt_at, parent.last_attempt_at),
+            (Some(1), Some(4))
+        );
+        assert_eq!(parent.constraints, vec!["body".to_string()]);
+
+        let second = provenance.hole(11).unwrap();
+        assert_eq!(second.parent_id, Some(1));
+        assert_eq!(second.model.as_deref(), Some("big"));
+        assert_eq!(second.temperature, Some(0.4));
+        assert_eq!(second.timestamp, Some(4));
+        assert_eq!(second.models.len(), 2);
+    }
+
+    #[test]
+    fn test_run_totals() {
+        let constraints = [constraint("signature"), constraint("body")];
+        let provenance = RefinementProvenance::new(&holes(), &constraints);
+
+        assert_eq!(
+            provenance.models,
+            BTreeMap::from([("big".to_string(), 2), ("small".to_string(), 3)])
+        );
+        // The unrestricted hole is held to the whole set
+        assert_eq!(provenance.hole(2).unwrap().constraints.len(), 2);
+        assert_eq!(provenance.constraints, vec!["signature", "body"]);
+        assert_eq!(provenance.first_attempt_at, Some(1));
+        assert_eq!(provenance.last_attempt_at, Some(5));
+
+        let failed = provenance.hole(2).unwrap();
+        assert_eq!(failed.model, None);
+        assert_eq!(failed.status, HoleStatus::Failed);
+    }
+
+    #[test]
+    fn test_holes_map_to_source_spans() {
+        let provenance = RefinementProvenance::new(&holes(), &[]);
+        let in_a: Vec<u64> = provenance.in_file("src/a.rs").map(|h| h.hole_id).collect();
+        assert_eq!(in_a, vec![1, 10, 11]);
+        assert_eq!(
+            provenance.hole(11).unwrap().span,
+            Some(SourceSpan {
+                file: "src/a.rs".to_string(),
+                line: Some(4),
+                column: Some(5),
+            })
+        );
+        assert_eq!(
+            provenance.hole(2).unwrap().span.as_ref().unwrap().line,
+            None
+        );
+        assert!(provenance.constraints.is_empty());
+    }
+
+    #[test]
+    fn test_source_span_parsing() {
+        let span = SourceSpan::parse("src/a.rs:3").unwrap();
+        assert_eq!(
+            (span.file.as_str(), span.line, span.column),
+            ("src/a.rs", Some(3), None)
+        );
+
+        // Not a line number, so the whole origin is the file
+        let span = SourceSpan::parse("C:/code/a.rs").unwrap();
+        assert_eq!((span.file.as_str(), span.line), ("C:/code/a.rs", None));
+
+        // Decomposed holes are located at their ancestor
+        let span = SourceSpan::parse("src/a.rs:3:5:child_1:child_0").unwrap();
+        assert_eq!((span.line, span.column), (Some(3), Some(5)));
+
+        assert_eq!(SourceSpan::parse(""), None);
+    }
+}
@@ -0,0 +1,602 @@
+//! Machine-readable reports of a refinement run, for CI
+//!
+//! [`RefinementResult::to_report`] renders a run as JSON or as SARIF 2.1.0,
+//! so the outcome can be attached to a pull request or uploaded to a
+//! code-scanning UI. The JSON report is a [`RefinementReport`]; its layout is
+//! versioned by [`REPORT_SCHEMA_VERSION`] and, unlike the `Debug` or serde
+//! form of [`RefinementResult`], does not change when internal fields do.
+//!
+//! In SARIF, each hole that did not end up filled is a result located at the
+//! hole's origin (`file:line:column`, with line and column optional) and
+//! reported under one of the [`ReportRule`]s, followed by a result for each
+//! constraint its last checked fill failed. Sub-holes are located at their
+//! parent's origin. Filled holes produce no results; every hole is still
+//! listed, with its confidence, under the run's `properties`.
+//!
+//! ```
+//! use maze::progressive_refinement::{RefinementMetadata, RefinementResult};
+//! use maze::refinement_provenance::RefinementProvenance;
+//! use maze::refinement_report::ReportFormat;
+//!
+//! let result = RefinementResult {
+//!     code: String::new(),
+//!     holes: vec![],
+//!     complete: true,
+//!     needs_review: vec![],
+//!     iterations: 0,
+//!     unsatisfied_constraints: Default::default(),
+//!     provenance: RefinementProvenance::default(),
+//!     metadata: RefinementMetadata::default(),
+//! };
+//! let sarif: serde_json::Value = serde_json::from_str(&result.to_report(ReportFormat::Sarif)?)?;
+//! assert_eq!(sarif["version"], "2.1.0");
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::json;
+
+use crate::constraint_check::{ConstraintCheck, ConstraintKind};
+use crate::progressive_refinement::{HoleState, HoleStatus, RefinementResult};
+use crate::refinement_provenance::SourceSpan;
+
+/// Version of the [`RefinementReport`] layout, bumped on breaking changes
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Output format of [`RefinementResult::to_report`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    /// A [`RefinementReport`] as JSON
+    #[default]
+    Json,
+
+    /// SARIF 2.1.0
+    Sarif,
+}
+
+impl ReportFormat {
+    /// Name of the format, as used in configuration
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Sarif => "sarif",
+        }
+    }
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "sarif" => Ok(Self::Sarif),
+            other => bail!("Unknown report format: {} (expected json or sarif)", other),
+        }
+    }
+}
+
+/// Why a hole is reported, as a SARIF rule
+///
+/// Serialized as its [`id`](Self::id).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReportRule {
+    /// Every fill attempt was rejected
+    FillFailed,
+
+    /// The hole was handed to a human
+    NeedsReview,
+
+    /// The failure strategy skipped the hole
+    HoleSkipped,
+
+    /// The run ended before the hole was filled
+    HoleUnfilled,
+
+    /// The last checked fill failed a grammar, JSON schema, or regex
+    StructuralConstraint,
+
+    /// The last checked fill failed a policy
+    StyleConstraint,
+}
+
+impl ReportRule {
+    /// Every rule, in the order listed in SARIF output
+    pub const ALL: [ReportRule; 6] = [
+        Self::FillFailed,
+        Self::NeedsReview,
+        Self::HoleSkipped,
+        Self::HoleUnfilled,
+        Self::StructuralConstraint,
+        Self::StyleConstraint,
+    ];
+
+    /// Rule for a hole ending with `status`, or `None` if it was filled
+    pub fn for_status(status: HoleStatus) -> Option<Self> {
+        match status {
+            HoleStatus::Filled => None,
+            HoleStatus::Failed => Some(Self::FillFailed),
+            HoleStatus::NeedsHuman => Some(Self::NeedsReview),
+            HoleStatus::Skipped => Some(Self::HoleSkipped),
+            HoleStatus::Pending | HoleStatus::InProgress | HoleStatus::PendingChildren => {
+                Some(Self::HoleUnfilled)
+            }
+        }
+    }
+
+    /// Rule ID
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::FillFailed => "maze/fill-failed",
+            Self::NeedsReview => "maze/needs-review",
+            Self::HoleSkipped => "maze/hole-skipped",
+            Self::HoleUnfilled => "maze/hole-unfilled",
+            Self::StructuralConstraint => "maze/structural-constraint",
+            Self::StyleConstraint => "maze/style-constraint",
+        }
+    }
+
+    /// Rule for a failed constraint check
+    pub fn for_check(check: &ConstraintCheck) -> Self {
+        match check.kind {
+            ConstraintKind::Structural => Self::StructuralConstraint,
+            ConstraintKind::Style => Self::StyleConstraint,
+        }
+    }
+
+    /// One-line description of the rule
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::FillFailed => "No fill for the hole passed validation",
+            Self::NeedsReview => "The hole needs human review",
+            Self::HoleSkipped => "The hole was skipped after its fills failed",
+            Self::HoleUnfilled => "Refinement ended before the hole was filled",
+            Self::StructuralConstraint => "The fill does not have the shape a constraint requires",
+            Self::StyleConstraint => "The fill breaks a policy constraint",
+        }
+    }
+
+    /// SARIF level of results for the rule
+    pub fn level(self) -> &'static str {
+        match self {
+            Self::FillFailed | Self::StructuralConstraint => "error",
+            Self::NeedsReview | Self::HoleSkipped | Self::HoleUnfilled | Self::StyleConstraint => {
+                "warning"
+            }
+        }
+    }
+}
+
+impl Serialize for ReportRule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for ReportRule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        Self::ALL
+            .into_iter()
+            .find(|rule| rule.id() == id)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown report rule: {}", id)))
+    }
+}
+
+/// Outcome of a refinement run, in the layout of the JSON report
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RefinementReport {
+    /// [`REPORT_SCHEMA_VERSION`] at the time of writing
+    pub schema_version: u32,
+
+    /// Whether every hole was resolved and none needs review
+    pub complete: bool,
+
+    /// Refinement iterations performed
+    pub iterations: usize,
+
+    /// Total time spent in milliseconds
+    pub total_time_ms: u64,
+
+    /// Holes that need human review
+    pub needs_review: Vec<u64>,
+
+    /// Every hole, in [`RefinementResult::holes`] order
+    pub holes: Vec<HoleReport>,
+
+    /// Holes that did not end up filled, each followed by the constraints
+    /// its last checked fill failed
+    pub violations: Vec<Violation>,
+}
+
+/// One hole of a [`RefinementReport`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HoleReport {
+    /// Hole ID
+    pub id: u64,
+
+    /// Origin/source location of the hole
+    pub origin: String,
+
+    /// Scale of the hole (nano, micro, meso, macro)
+    pub scale: String,
+
+    /// Final status
+    pub status: HoleStatus,
+
+    /// Confidence in the final fill (0.0-1.0)
+    pub confidence: f32,
+
+    /// Fill attempts made
+    pub attempts: usize,
+
+    /// Model of the last attempt, if any was made
+    pub model: Option<String>,
+
+    /// Generation time across all attempts, in milliseconds
+    pub generation_time_ms: u64,
+
+    /// Why each rejected attempt was rejected, in attempt order
+    pub rejections: Vec<String>,
+
+    /// Hole this one was decomposed from
+    pub parent_id: Option<u64>,
+}
+
+/// A hole that did not end up filled
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Violation {
+    /// Hole ID
+    pub hole_id: u64,
+
+    /// Rule the hole is reported under
+    pub rule: ReportRule,
+
+    /// The failed constraint, for constraint rules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub constraint: Option<String>,
+
+    /// Human-readable explanation
+    pub message: String,
+}
+
+impl RefinementReport {
+    /// Report on `result`
+    pub fn new(result: &RefinementResult) -> Self {
+        Self {
+            schema_version: REPORT_SCHEMA_VERSION,
+            complete: result.complete,
+            iterations: result.iterations,
+            total_time_ms: result.metadata.total_time_ms,
+            needs_review: result.needs_review.clone(),
+            holes: result.holes.iter().map(HoleReport::new).collect(),
+            violations: result.holes.iter().flat_map(Violation::for_hole).collect(),
+        }
+    }
+
+    /// The report as SARIF 2.1.0
+    pub fn to_sarif(&self) -> serde_json::Value {
+        let rules: Vec<_> = ReportRule::ALL
+            .iter()
+            .map(|rule| {
+                json!({
+                    "id": rule.id(),
+                    "shortDescription": { "text": rule.description() },
+                    "defaultConfiguration": { "level": rule.level() },
+                })
+            })
+            .collect();
+        let results: Vec<_> = self
+            .violations
+            .iter()
+            .map(|violation| {
+                let origin = self
+                    .holes
+                    .iter()
+                    .find(|h| h.id == violation.hole_id)
+                    .map_or("", |h| h.origin.as_str());
+                json!({
+                    "ruleId": violation.rule.id(),
+                    "ruleIndex": ReportRule::ALL.iter().position(|r| *r == violation.rule),
+                    "level": violation.rule.level(),
+                    "message": { "text": violation.message },
+                    "locations": sarif_locations(origin),
+                    "properties": {
+                        "holeId": violation.hole_id,
+                        "constraint": violation.constraint,
+                    },
+                })
+            })
+            .collect();
+
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "maze",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "rules": rules,
+                    }
+                },
+                "results": results,
+                "properties": {
+                    "schemaVersion": self.schema_version,
+                    "complete": self.complete,
+                    "iterations": self.iterations,
+                    "totalTimeMs": self.total_time_ms,
+                    "holes": self.holes,
+                },
+            }],
+        })
+    }
+}
+
+impl HoleReport {
+    fn new(hole: &HoleState) -> Self {
+        Self {
+            id: hole.id,
+            origin: hole.origin.clone(),
+            scale: hole.scale.clone(),
+            status: hole.status,
+            confidence: hole.confidence,
+            attempts: hole.attempts.len(),
+            model: hole.attempts.last().map(|a| a.model.clone()),
+            generation_time_ms: hole
+                .attempts
+                .iter()
+                .filter_map(|a| a.stats.as_ref())
+                .map(|s| s.total_time_ms)
+                .sum(),
+            rejections: hole
+                .attempts
+                .iter()
+                .filter(|a| !a.validation_passed)
+                .map(|a| a.error.clone().unwrap_or_else(|| "rejected".to_string()))
+                .collect(),
+            parent_id: hole.parent_id,
+        }
+    }
+}
+
+impl Violation {
+    /// The violations of a hole that did not end up filled; none otherwise
+    fn for_hole(hole: &HoleState) -> Vec<Self> {
+        let Some(rule) = ReportRule::for_status(hole.status) else {
+            return Vec::new();
+        };
+        let mut message = format!("Hole {}: {}", hole.id, rule.description());
+        let last_error = hole.attempts.iter().rev().find_map(|a| a.error.as_deref());
+        if let Some(error) = last_error {
+            message.push_str(&format!(" (last error: {})", error));
+        }
+        let mut violations = vec![Self {
+            hole_id: hole.id,
+            rule,
+            constraint: None,
+            message,
+        }];
+
+        let checks = hole
+            .attempts
+            .iter()
+            .rev()
+            .map(|a| &a.constraint_checks)
+            .find(|checks| !checks.is_empty());
+        for check in checks.into_iter().flatten().filter(|c| !c.passed) {
+            let mut message = format!("Hole {}: constraint {} failed", hole.id, check.constraint);
+            if let Some(ref detail) = check.detail {
+                message.push_str(&format!(": {}", detail));
+            }
+            violations.push(Self {
+                hole_id: hole.id,
+                rule: ReportRule::for_check(check),
+                constraint: Some(check.constraint.clone()),
+                message,
+            });
+        }
+        violations
+    }
+}
+
+/// SARIF locations for a hole origin of the form `file[:line[:column]]`
+fn sarif_locations(origin: &str) -> serde_json::Value {
+    let Some(span) = SourceSpan::parse(origin) else {
+        return json!([]);
+    };
+
+    let mut location = json!({ "artifactLocation": { "uri": span.file } });
+    if let Some(line) = span.line {
+        let mut region = json!({ "startLine": line.max(1) });
+        if let Some(column) = span.column {
+            region["startColumn"] = json!(column.max(1));
+        }
+        location["region"] = region;
+    }
+    json!([{ "physicalLocation": location }])
+}
+
+impl RefinementResult {
+    /// Render the run as a machine-readable report in `format`
+    ///
+    /// See the [`refinement_report`](crate::refinement_report) module.
+    pub fn to_report(&self, format: ReportFormat) -> Result<String> {
+        let report = RefinementReport::new(self);
+        let value = match format {
+            ReportFormat::Json => serde_json::to_value(&report),
+            ReportFormat::Sarif => Ok(report.to_sarif()),
+        };
+        value
+            .and_then(|v| serde_json::to_string_pretty(&v))
+            .with_context(|| format!("Failed to write {} report", format.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progressive_refinement::{FillAttempt, RefinementMetadata};
+
+    fn attempt(error: Option<&str>) -> FillAttempt {
+        FillAttempt {
+            code: "x".to_string(),
+            confidence: 0.8,
+            raw_confidence: None,
+            temperature: 0.7,
+            model: "mock-model".to_string(),
+            timestamp: 0,
+            validation_passed: error.is_none(),
+            error: error.map(str::to_string),
+            tokens_generated: 1,
+            stats: None,
+            model_chain: vec![],
+            finish_reason: Default::default(),
+            constraint_checks: vec![],
+        }
+    }
+
+    fn result() -> RefinementResult {
+        let mut filled = HoleState::new(1, "nano".to_string(), "src/a.rs:3:5".to_string());
+        filled.status = HoleStatus::Filled;
+        filled.confidence = 0.8;
+        filled.attempts = vec![attempt(Some("2 syntax errors")), attempt(None)];
+        let mut failed = HoleState::new(2, "micro".to_string(), "src/b.rs:10".to_string());
+        failed.status = HoleStatus::Failed;
+        let mut checked = attempt(Some("does not match"));
+        checked.constraint_checks = vec![
+            ConstraintCheck {
+                constraint: "identifier".to_string(),
+                kind: ConstraintKind::Structural,
+                passed: false,
+                detail: Some("no match".to_string()),
+            },
+            ConstraintCheck {
+                constraint: "no_unwrap".to_string(),
+                kind: ConstraintKind::Style,
+                passed: true,
+                detail: None,
+            },
+        ];
+        failed.attempts = vec![checked, attempt(Some("empty fill"))];
+        RefinementResult {
+            code: String::new(),
+            holes: vec![filled, failed],
+            complete: false,
+            needs_review: vec![2],
+            iterations: 2,
+            unsatisfied_constraints: Default::default(),
+            provenance: Default::default(),
+            metadata: RefinementMetadata::default(),
+        }
+    }
+
+    #[test]
+    fn test_json_report_lists_holes_and_violations() {
+        let json = result().to_report(ReportFormat::Json).unwrap();
+        let report: RefinementReport = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(report.schema_version, REPORT_SCHEMA_VERSION);
+        assert_eq!(report.holes.len(), 2);
+        assert_eq!(report.holes[0].rejections, vec!["2 syntax errors"]);
+        assert_eq!(report.holes[0].model.as_deref(), Some("mock-model"));
+        assert_eq!(report.violations.len(), 2);
+        assert_eq!(report.violations[0].hole_id, 2);
+        assert_eq!(report.violations[0].rule, ReportRule::FillFailed);
+        assert!(report.violations[0].message.contains("empty fill"));
+
+        // The last fill that got as far as the checks failed one of them
+        let constraint = &report.violations[1];
+        assert_eq!(constraint.rule, ReportRule::StructuralConstraint);
+        assert_eq!(constraint.constraint.as_deref(), Some("identifier"));
+        assert!(constraint.message.contains("no match"));
+
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["violations"][0]["rule"], "maze/fill-failed");
+        assert_eq!(value["violations"][1]["rule"], "maze/structural-constraint");
+    }
+
+    #[test]
+    fn test_rules_serialize_as_their_ids() {
+        for rule in ReportRule::ALL {
+            let value = serde_json::to_value(rule).unwrap();
+            assert_eq!(value, rule.id());
+            assert_eq!(serde_json::from_value::<ReportRule>(value).unwrap(), rule);
+        }
+        assert!(serde_json::from_value::<ReportRule>(json!("maze/other")).is_err());
+    }
+
+    #[test]
+    fn test_sarif_maps_holes_to_locations_and_rules() {
+        let sarif: serde_json::Value =
+            serde_json::from_str(&result().to_report(ReportFormat::Sarif).unwrap()).unwrap();
+        let run = &sarif["runs"][0];
+
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), ReportRule::ALL.len());
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1]["ruleId"], "maze/structural-constraint");
+        assert_eq!(results[1]["properties"]["constraint"], "identifier");
+        let failed = &results[0];
+        assert_eq!(failed["ruleId"], "maze/fill-failed");
+        assert_eq!(
+            rules[failed["ruleIndex"].as_u64().unwrap() as usize]["id"],
+            "maze/fill-failed"
+        );
+        assert_eq!(failed["level"], "error");
+        let location = &failed["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/b.rs");
+        assert_eq!(location["region"]["startLine"], 10);
+        assert!(location["region"].get("startColumn").is_none());
+
+        assert_eq!(run["properties"]["holes"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_sarif_locates_sub_holes_at_their_parent() {
+        let mut result = result();
+        let mut child = HoleState::new(3, "nano".to_string(), "src/c.rs:7:2:child_0".to_string());
+        child.status = HoleStatus::NeedsHuman;
+        result.holes.push(child);
+
+        let sarif: serde_json::Value =
+            serde_json::from_str(&result.to_report(ReportFormat::Sarif).unwrap()).unwrap();
+        let child = sarif["runs"][0]["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|r| r["properties"]["holeId"] == 3)
+            .unwrap();
+        let location = &child["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/c.rs");
+        assert_eq!(location["region"]["startLine"], 7);
+        assert_eq!(location["region"]["startColumn"], 2);
+    }
+
+    #[test]
+    fn test_origin_parsing() {
+        let location = &sarif_locations("src/a.rs:3:5")[0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/a.rs");
+        assert_eq!(location["region"]["startColumn"], 5);
+
+        // Not a line number, so the whole origin is the artifact
+        let location = &sarif_locations("C:/code/a.rs")[0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "C:/code/a.rs");
+        assert!(location.get("region").is_none());
+
+        assert_eq!(sarif_locations(""), json!([]));
+    }
+
+    #[test]
+    fn test_report_format_from_str() {
+        assert_eq!(
+            "sarif".parse::<ReportFormat>().unwrap(),
+            ReportFormat::Sarif
+        );
+        assert!("xml".parse::<ReportFormat>().is_err());
+    }
+}
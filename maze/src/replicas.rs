@@ -0,0 +1,268 @@
+//! Weighted round-robin selection across inference replicas
+//!
+//! A [`ModalClient`](crate::ModalClient) configured with several replicas
+//! spreads requests over them in proportion to their weights, using smooth
+//! weighted round robin so a heavy replica's turns are interleaved with the
+//! others rather than bunched together. Each replica has a circuit breaker:
+//! after `failure_threshold` consecutive failures it is skipped for
+//! `cooldown_secs`, then given one trial request. If every replica's
+//! breaker is open, selection ignores the breakers rather than failing
+//! outright, so a single-endpoint client behaves as it always has.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// One inference replica and its share of traffic
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaEndpoint {
+    /// Base URL of the replica
+    pub url: String,
+
+    /// Relative share of requests; zero takes the replica out of rotation
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl ReplicaEndpoint {
+    /// A replica with the given weight
+    pub fn new(url: impl Into<String>, weight: u32) -> Self {
+        Self {
+            url: url.into(),
+            weight,
+        }
+    }
+}
+
+/// When a replica is taken out of rotation after failures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures that open the breaker
+    pub failure_threshold: u32,
+
+    /// How long an open breaker skips the replica, in seconds
+    pub cooldown_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            cooldown_secs: 30,
+        }
+    }
+}
+
+/// Health of one replica, as returned by
+/// [`ModalClient::endpoint_health`](crate::ModalClient::endpoint_health)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EndpointHealth {
+    /// Base URL of the replica
+    pub url: String,
+
+    /// Configured weight
+    pub weight: u32,
+
+    /// Whether the circuit breaker is currently skipping this replica
+    pub circuit_open: bool,
+
+    /// Failures since the last success
+    pub consecutive_failures: u32,
+
+    /// Requests sent to this replica
+    pub requests: u64,
+
+    /// Requests that failed
+    pub failures: u64,
+}
+
+/// Selection and breaker state for one replica
+#[derive(Debug)]
+struct Replica {
+    url: Url,
+    weight: u32,
+    current_weight: i64,
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    requests: u64,
+    failures: u64,
+}
+
+impl Replica {
+    fn is_open(&self, now: Instant) -> bool {
+        self.open_until.is_some_and(|until| now < until)
+    }
+}
+
+/// Replicas shared by clones of a client
+#[derive(Debug, Clone)]
+pub(crate) struct ReplicaSet {
+    replicas: Arc<Mutex<Vec<Replica>>>,
+    breaker: CircuitBreakerConfig,
+}
+
+impl ReplicaSet {
+    /// Build from parsed replica URLs and weights
+    pub(crate) fn new(endpoints: Vec<(Url, u32)>, breaker: CircuitBreakerConfig) -> Self {
+        let replicas = endpoints
+            .into_iter()
+            .map(|(url, weight)| Replica {
+                url,
+                weight,
+                current_weight: 0,
+                consecutive_failures: 0,
+                open_until: None,
+                requests: 0,
+                failures: 0,
+            })
+            .collect();
+        Self {
+            replicas: Arc::new(Mutex::new(replicas)),
+            breaker,
+        }
+    }
+
+    /// Choose the next replica, skipping those in `exclude`
+    ///
+    /// Returns its index and base URL, or `None` when every weighted
+    /// replica has been excluded.
+    pub(crate) fn pick(&self, exclude: &[usize]) -> Option<(usize, Url)> {
+        let mut replicas = self.replicas.lock().unwrap();
+        let now = Instant::now();
+        let eligible = |idx: usize, r: &Replica| r.weight > 0 && !exclude.contains(&idx);
+
+        let mut candidates: Vec<usize> = (0..replicas.len())
+            .filter(|&idx| eligible(idx, &replicas[idx]) && !replicas[idx].is_open(now))
+            .collect();
+        if candidates.is_empty() {
+            // Every breaker is open; trying one beats refusing outright
+            candidates = (0..replicas.len())
+                .filter(|&idx| eligible(idx, &replicas[idx]))
+                .collect();
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+
+        // Smooth weighted round robin over the candidates
+        let total: i64 = candidates.iter().map(|&i| replicas[i].weight as i64).sum();
+        for &idx in &candidates {
+            replicas[idx].current_weight += replicas[idx].weight as i64;
+        }
+        let chosen = *candidates
+            .iter()
+            .max_by_key(|&&idx| (replicas[idx].current_weight, std::cmp::Reverse(idx)))
+            .expect("candidates is not empty");
+        let replica = &mut replicas[chosen];
+        replica.current_weight -= total;
+        replica.requests += 1;
+        Some((chosen, replica.url.clone()))
+    }
+
+    /// Base URL of the first replica, for calls that are not load balanced
+    pub(crate) fn primary(&self) -> Url {
+        self.replicas.lock().unwrap()[0].url.clone()
+    }
+
+    /// Close the replica's breaker after a successful request
+    pub(crate) fn record_success(&self, idx: usize) {
+        let mut replicas = self.replicas.lock().unwrap();
+        let replica = &mut replicas[idx];
+        replica.consecutive_failures = 0;
+        replica.open_until = None;
+    }
+
+    /// Count a failure, opening the breaker at the threshold
+    pub(crate) fn record_failure(&self, idx: usize) {
+        let mut replicas = self.replicas.lock().unwrap();
+        let replica = &mut replicas[idx];
+        replica.failures += 1;
+        replica.consecutive_failures += 1;
+        if replica.consecutive_failures >= self.breaker.failure_threshold.max(1) {
+            replica.open_until =
+                Some(Instant::now() + Duration::from_secs(self.breaker.cooldown_secs));
+            tracing::warn!(
+                replica = %replica.url,
+                failures = replica.consecutive_failures,
+                "Opening circuit breaker for replica"
+            );
+        }
+    }
+
+    /// Number of replicas
+    pub(crate) fn len(&self) -> usize {
+        self.replicas.lock().unwrap().len()
+    }
+
+    /// Current health of every replica
+    pub(crate) fn health(&self) -> Vec<EndpointHealth> {
+        let now = Instant::now();
+        self.replicas
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|r| EndpointHealth {
+                url: r.url.to_string(),
+                weight: r.weight,
+                circuit_open: r.is_open(now),
+                consecutive_failures: r.consecutive_failures,
+                requests: r.requests,
+                failures: r.failures,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(weights: &[u32]) -> ReplicaSet {
+        ReplicaSet::new(
+            weights
+                .iter()
+                .enumerate()
+                .map(|(i, &w)| (Url::parse(&format!("http://replica{}", i)).unwrap(), w))
+                .collect(),
+            CircuitBreakerConfig {
+                failure_threshold: 2,
+                cooldown_secs: 60,
+            },
+        )
+    }
+
+    #[test]
+    fn test_smooth_weighted_order() {
+        let replicas = set(&[5, 1, 1]);
+        let order: Vec<usize> = (0..7).map(|_| replicas.pick(&[]).unwrap().0).collect();
+        // The heavy replica's turns are spread out, not bunched
+        assert_eq!(order, vec![0, 0, 1, 0, 2, 0, 0]);
+    }
+
+    #[test]
+    fn test_open_breaker_is_skipped_until_all_are_open() {
+        let replicas = set(&[1, 1]);
+        replicas.record_failure(0);
+        assert!(!replicas.health()[0].circuit_open);
+        replicas.record_failure(0);
+        assert!(replicas.health()[0].circuit_open);
+
+        for _ in 0..4 {
+            assert_eq!(replicas.pick(&[]).unwrap().0, 1);
+        }
+
+        replicas.record_failure(1);
+        replicas.record_failure(1);
+        assert!(replicas.pick(&[]).is_some());
+        assert!(replicas.pick(&[0, 1]).is_none());
+
+        replicas.record_success(0);
+        assert_eq!(replicas.health()[0].consecutive_failures, 0);
+        assert!(!replicas.health()[0].circuit_open);
+    }
+}
@@ -0,0 +1,319 @@
+//! Aborting generations that run away
+//!
+//! A model that ignores its stop conditions keeps generating until
+//! `max_tokens`, and some paths (diffusion, FIM) do not enforce even that.
+//! The output is then validated and post-processed at a cost out of all
+//! proportion to its use. An [`OutputLimit`] caps the bytes and lines a
+//! generation may produce: streams are cut off as soon as they cross it,
+//! with [`FinishReason::Runaway`], and complete responses are checked after
+//! the fact. [`RunawayLimits`] scales the cap with the hole being filled;
+//! with `RefinementConfig::stream_fills` set, fills are streamed so their
+//! hole's limit cuts them off too.
+
+use anyhow::Result;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::inference::InferenceClient;
+use crate::modal_client::{
+    FinishReason, GenerationStats, InferenceRequest, InferenceResponse, StreamChunk,
+    StreamingResult,
+};
+
+/// Most output one generation may produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputLimit {
+    /// Bytes of generated text
+    pub max_bytes: usize,
+
+    /// Lines of generated text
+    pub max_lines: usize,
+}
+
+impl OutputLimit {
+    /// Why `text` is too large, if it is
+    pub fn check(&self, text: &str) -> Result<(), RunawayGeneration> {
+        let mut size = OutputSize::default();
+        size.push(text);
+        self.check_size(&size)
+    }
+
+    fn check_size(&self, size: &OutputSize) -> Result<(), RunawayGeneration> {
+        let (bytes, lines) = (size.bytes, size.lines());
+        if bytes > self.max_bytes || lines > self.max_lines {
+            return Err(RunawayGeneration {
+                bytes,
+                lines,
+                limit: *self,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Running size of text received in pieces, counted as [`str::lines`]
+/// would count the whole
+#[derive(Debug, Clone, Copy, Default)]
+struct OutputSize {
+    bytes: usize,
+    newlines: usize,
+    /// The text so far is non-empty and does not end with a newline
+    open_line: bool,
+}
+
+impl OutputSize {
+    fn push(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        self.bytes += text.len();
+        self.newlines += text.bytes().filter(|&b| b == b'\n').count();
+        self.open_line = !text.ends_with('\n');
+    }
+
+    fn lines(&self) -> usize {
+        self.newlines + usize::from(self.open_line)
+    }
+}
+
+/// Output limit of a fill, by the scale of its hole
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunawayLimits {
+    /// Limit per scale (`nano`, `micro`, `meso`, `macro`, ...)
+    pub by_scale: HashMap<String, OutputLimit>,
+
+    /// Limit for scales missing from `by_scale`
+    pub default: OutputLimit,
+}
+
+impl Default for RunawayLimits {
+    fn default() -> Self {
+        let limit = |max_bytes, max_lines| OutputLimit {
+            max_bytes,
+            max_lines,
+        };
+        Self {
+            by_scale: HashMap::from([
+                ("nano".to_string(), limit(1024, 32)),
+                ("micro".to_string(), limit(4 * 1024, 128)),
+                ("meso".to_string(), limit(16 * 1024, 512)),
+                ("macro".to_string(), limit(64 * 1024, 2048)),
+            ]),
+            default: limit(16 * 1024, 512),
+        }
+    }
+}
+
+impl RunawayLimits {
+    /// Limit for holes of `scale`
+    pub fn for_scale(&self, scale: &str) -> OutputLimit {
+        self.by_scale.get(scale).copied().unwrap_or(self.default)
+    }
+
+    /// Set the limit for holes of `scale`
+    pub fn with_scale(mut self, scale: impl Into<String>, limit: OutputLimit) -> Self {
+        self.by_scale.insert(scale.into(), limit);
+        self
+    }
+}
+
+/// Generated output exceeded its [`OutputLimit`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, thiserror::Error)]
+#[error(
+    "runaway generation: {bytes} bytes in {lines} lines exceeds the limit of {} bytes or {} lines",
+    limit.max_bytes,
+    limit.max_lines
+)]
+pub struct RunawayGeneration {
+    /// Bytes generated when the limit was crossed
+    pub bytes: usize,
+
+    /// Lines generated when the limit was crossed
+    pub lines: usize,
+
+    /// Limit crossed
+    pub limit: OutputLimit,
+}
+
+/// Cut `stream` off once its text exceeds `limit`
+///
+/// The chunk that crosses the limit becomes the final chunk, with
+/// `finish_reason` [`FinishReason::Runaway`], and the stream is dropped so
+/// the service stops generating. Errors pass through unchanged.
+pub(crate) fn with_output_limit(stream: StreamingResult, limit: OutputLimit) -> StreamingResult {
+    let limited = futures::stream::unfold(
+        Some((stream, OutputSize::default(), 0usize)),
+        move |state| async move {
+            let (mut stream, mut size, received) = state?;
+            let item = stream.next().await?;
+            let chunk = match item {
+                Ok(chunk) => chunk,
+                Err(err) => return Some((Err(err), Some((stream, size, received + 1)))),
+            };
+            if chunk.restarted {
+                size = OutputSize::default();
+            }
+            size.push(&chunk.text);
+            match limit.check_size(&size) {
+                Ok(()) => Some((Ok(chunk), Some((stream, size, received + 1)))),
+                Err(runaway) => {
+                    tracing::warn!(chunks_received = received, "Aborting stream: {}", runaway);
+                    let chunk = StreamChunk {
+                        is_final: true,
+                        finish_reason: Some(FinishReason::Runaway),
+                        ..chunk
+                    };
+                    Some((Ok(chunk), None))
+                }
+            }
+        },
+    );
+    Box::pin(limited)
+}
+
+/// Generate `request` with `client` as a stream cut off at `limit`, and
+/// assemble the chunks into a response
+///
+/// Streams carry no logprobs, so the response is unscored. Malformed
+/// events are skipped.
+pub(crate) async fn generate_limited(
+    client: &dyn InferenceClient,
+    request: InferenceRequest,
+    limit: OutputLimit,
+) -> Result<InferenceResponse> {
+    let started = std::time::Instant::now();
+    let mut stream = with_output_limit(client.generate_stream(request).await?, limit);
+    let mut text = String::new();
+    let mut tokens_generated = 0;
+    let mut finish_reason = None;
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) if crate::sse::malformed_event(&err).is_some() => {
+                tracing::debug!("Skipping malformed stream event: {:#}", err);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        if chunk.restarted {
+            text.clear();
+            tokens_generated = 0;
+        }
+        if !chunk.text.is_empty() {
+            tokens_generated += 1;
+        }
+        text.push_str(&chunk.text);
+        finish_reason = chunk.finish_reason.or(finish_reason);
+        if chunk.is_final {
+            break;
+        }
+    }
+
+    let total_time_ms = started.elapsed().as_millis() as u64;
+    Ok(InferenceResponse {
+        generated_text: text,
+        tokens_generated,
+        model: client.model_name().to_string(),
+        stats: GenerationStats {
+            total_time_ms,
+            time_per_token_us: (total_time_ms * 1000)
+                .checked_div(tokens_generated as u64)
+                .unwrap_or(0),
+            constraint_checks: 0,
+            avg_constraint_check_us: 0,
+            by_kind: Default::default(),
+        },
+        attempts: vec![],
+        logprobs: None,
+        reported_confidence: None,
+        request_id: None,
+        finish_reason,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(text: &str, index: usize) -> anyhow::Result<StreamChunk> {
+        Ok(StreamChunk {
+            text: text.to_string(),
+            is_final: false,
+            token_index: index,
+            timestamp_ms: 0,
+            finish_reason: None,
+            model: None,
+            constraint_event: None,
+            restarted: false,
+        })
+    }
+
+    #[test]
+    fn test_check_counts_bytes_and_lines() {
+        let limit = OutputLimit {
+            max_bytes: 10,
+            max_lines: 2,
+        };
+        assert!(limit.check("a\nb").is_ok());
+        assert_eq!(
+            limit.check("a\nb\nc"),
+            Err(RunawayGeneration {
+                bytes: 5,
+                lines: 3,
+                limit
+            })
+        );
+        assert!(limit.check(&"x".repeat(11)).is_err());
+        assert!(limit.check("a\nb\n").is_ok());
+        assert!(limit.check("").is_ok());
+        assert_eq!(RunawayLimits::default().for_scale("unknown").max_lines, 512);
+    }
+
+    #[tokio::test]
+    async fn test_stream_is_cut_off_at_the_limit() {
+        let stream: StreamingResult = Box::pin(futures::stream::iter(vec![
+            chunk("abcd", 0),
+            chunk("efgh", 1),
+            chunk("ijkl", 2),
+            chunk("mnop", 3),
+        ]));
+        let limit = OutputLimit {
+            max_bytes: 10,
+            max_lines: 100,
+        };
+
+        let chunks: Vec<StreamChunk> = with_output_limit(stream, limit)
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+
+        let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["abcd", "efgh", "ijkl"]);
+        assert!(chunks[2].is_final);
+        assert_eq!(chunks[2].finish_reason, Some(FinishReason::Runaway));
+    }
+
+    #[tokio::test]
+    async fn test_stream_lines_are_counted_across_chunks() {
+        // "a\nb" split mid-line is two lines, and so is "a\nb\n"
+        let stream: StreamingResult = Box::pin(futures::stream::iter(vec![
+            chunk("a", 0),
+            chunk("\nb", 1),
+            chunk("\n", 2),
+            chunk("c", 3),
+        ]));
+        let limit = OutputLimit {
+            max_bytes: 100,
+            max_lines: 2,
+        };
+
+        let chunks: Vec<StreamChunk> = with_output_limit(stream, limit)
+            .map(|c| c.unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[2].finish_reason, None);
+        assert_eq!(chunks[3].finish_reason, Some(FinishReason::Runaway));
+    }
+}
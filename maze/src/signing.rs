@@ -0,0 +1,241 @@
+//! Provenance signing and verification
+//!
+//! Lets a team attest that a code blob came out of a particular constrained
+//! run. [`canonical_digest`] hashes the generated code together with its
+//! [`Provenance`] (minus any existing signature) and [`ValidationResult`] as
+//! key-sorted JSON, and [`sign`] stores an Ed25519 signature over that
+//! digest in [`Provenance::signature`]. [`verify`] recomputes the digest, so
+//! changing the code, the validation verdict, or any provenance field after
+//! signing invalidates it.
+//!
+//! Requires the `signing` feature.
+
+use ed25519_dalek::{Signature, Signer, Verifier};
+use sha2::{Digest, Sha256};
+
+use crate::{GenerationResponse, Provenance, ProvenanceSignature, ValidationResult};
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+/// Value of [`ProvenanceSignature::algorithm`] for signatures made here
+pub const ALGORITHM: &str = "ed25519-sha256";
+
+/// Prefix hashed ahead of the canonical JSON, so the digest cannot be
+/// mistaken for one made over some other structure
+///
+/// `v2` added the validation result to the signed document.
+const DOMAIN: &[u8] = b"maze-provenance-v2\n";
+
+/// Why a response failed verification
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SignatureError {
+    /// The provenance carries no signature
+    #[error("provenance is not signed")]
+    Missing,
+
+    /// The signature was made with an algorithm this build cannot check
+    #[error("unsupported signature algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+
+    /// The stored signature or key is not valid hex of the right length
+    #[error("malformed signature: {0}")]
+    Malformed(String),
+
+    /// The response was signed by a different key
+    #[error("signed by a different key")]
+    KeyMismatch,
+
+    /// The code or provenance changed after signing
+    #[error("signature does not match the code and provenance")]
+    Invalid,
+}
+
+/// SHA-256 over the code, provenance, and validation result, excluding the
+/// signature itself
+///
+/// The schema version is left out too, so a record signed before
+/// versioning, or upgraded since, still verifies.
+pub fn canonical_digest(
+    code: &str,
+    provenance: &Provenance,
+    validation: &ValidationResult,
+) -> [u8; 32] {
+    let mut provenance = provenance.clone();
+    provenance.signature = None;
+    let mut provenance = serde_json::to_value(&provenance).expect("provenance serializes");
+    if let serde_json::Value::Object(ref mut fields) = provenance {
+        fields.remove("schema_version");
+    }
+    let document = serde_json::json!({
+        "code": code,
+        "provenance": provenance,
+        "validation": validation,
+    });
+
+    let mut hasher = Sha256::new();
+    hasher.update(DOMAIN);
+    write_canonical(&document, &mut hasher);
+    hasher.finalize().into()
+}
+
+/// Sign `response`, replacing any existing signature
+pub fn sign(response: &mut GenerationResponse, key: &SigningKey) {
+    let digest = canonical_digest(&response.code, &response.provenance, &response.validation);
+    let signature = key.sign(&digest);
+    response.provenance.signature = Some(ProvenanceSignature {
+        algorithm: ALGORITHM.to_string(),
+        public_key: to_hex(key.verifying_key().as_bytes()),
+        signature: to_hex(&signature.to_bytes()),
+    });
+}
+
+/// Check that `response` was signed by `public_key` and is unchanged since
+pub fn verify(
+    response: &GenerationResponse,
+    public_key: &VerifyingKey,
+) -> Result<(), SignatureError> {
+    let stored = response
+        .provenance
+        .signature
+        .as_ref()
+        .ok_or(SignatureError::Missing)?;
+    if stored.algorithm != ALGORITHM {
+        return Err(SignatureError::UnsupportedAlgorithm(
+            stored.algorithm.clone(),
+        ));
+    }
+
+    let signer: [u8; 32] = from_hex(&stored.public_key)?;
+    if signer != *public_key.as_bytes() {
+        return Err(SignatureError::KeyMismatch);
+    }
+    let signature = Signature::from_bytes(&from_hex(&stored.signature)?);
+
+    let digest = canonical_digest(&response.code, &response.provenance, &response.validation);
+    public_key
+        .verify(&digest, &signature)
+        .map_err(|_| SignatureError::Invalid)
+}
+
+/// Feed `value` to `hasher` as compact JSON with object keys sorted
+///
+/// `Provenance::parameters` is a `HashMap`, so plain serialization would
+/// not be stable from one run to the next.
+fn write_canonical(value: &serde_json::Value, hasher: &mut Sha256) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            hasher.update(b"{");
+            for (idx, key) in keys.into_iter().enumerate() {
+                if idx > 0 {
+                    hasher.update(b",");
+                }
+                hasher.update(serde_json::Value::from(key.as_str()).to_string().as_bytes());
+                hasher.update(b":");
+                write_canonical(&map[key], hasher);
+            }
+            hasher.update(b"}");
+        }
+        serde_json::Value::Array(items) => {
+            hasher.update(b"[");
+            for (idx, item) in items.iter().enumerate() {
+                if idx > 0 {
+                    hasher.update(b",");
+                }
+                write_canonical(item, hasher);
+            }
+            hasher.update(b"]");
+        }
+        scalar => hasher.update(scalar.to_string().as_bytes()),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex<const N: usize>(text: &str) -> Result<[u8; N], SignatureError> {
+    let malformed = || SignatureError::Malformed(format!("expected {} hex bytes", N));
+    if text.len() != N * 2 || !text.is_ascii() {
+        return Err(malformed());
+    }
+    let mut bytes = [0u8; N];
+    for (idx, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&text[idx * 2..idx * 2 + 2], 16).map_err(|_| malformed())?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn provenance(parameters: &[(&str, serde_json::Value)]) -> Provenance {
+        Provenance {
+            model: "test-model".to_string(),
+            timestamp: 1_700_000_000,
+            constraints_applied: vec!["types".to_string()],
+            original_intent: "add two numbers".to_string(),
+            intent: None,
+            parameters: parameters
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect::<HashMap<_, _>>(),
+            context_included: vec![],
+            idempotency_key: None,
+            constraints_hash: None,
+            signature: None,
+            model_chain: vec![],
+            turn: None,
+            requested_temperature: None,
+            regenerated_span: None,
+            schema_version: crate::RESPONSE_SCHEMA_VERSION,
+        }
+    }
+
+    fn validation(all_satisfied: bool) -> ValidationResult {
+        ValidationResult {
+            all_satisfied,
+            satisfied: vec!["types".to_string()],
+            violated: vec![],
+            metadata: [("checked".to_string(), serde_json::json!(true))].into(),
+        }
+    }
+
+    #[test]
+    fn test_digest_ignores_key_order_and_signature() {
+        let a = provenance(&[("max_tokens", 64.into()), ("temperature", 0.2.into())]);
+        let mut b = provenance(&[("temperature", 0.2.into()), ("max_tokens", 64.into())]);
+        let v = validation(true);
+        assert_eq!(canonical_digest("x", &a, &v), canonical_digest("x", &b, &v));
+
+        b.signature = Some(ProvenanceSignature {
+            algorithm: ALGORITHM.to_string(),
+            public_key: String::new(),
+            signature: String::new(),
+        });
+        assert_eq!(canonical_digest("x", &a, &v), canonical_digest("x", &b, &v));
+        assert_ne!(canonical_digest("x", &a, &v), canonical_digest("y", &a, &v));
+
+        // Upgrading a record does not invalidate its signature
+        b.schema_version = 1;
+        assert_eq!(canonical_digest("x", &a, &v), canonical_digest("x", &b, &v));
+
+        // The validation verdict is signed
+        assert_ne!(
+            canonical_digest("x", &a, &v),
+            canonical_digest("x", &a, &validation(false))
+        );
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let bytes = [0x00, 0x7f, 0xff, 0x10];
+        assert_eq!(to_hex(&bytes), "007fff10");
+        assert_eq!(from_hex::<4>("007fff10").unwrap(), bytes);
+        assert!(from_hex::<4>("007fff1").is_err());
+        assert!(from_hex::<4>("007fff1g").is_err());
+    }
+}
@@ -0,0 +1,159 @@
+//! Coalescing of identical concurrent requests
+//!
+//! Editors often fire the same completion request several times on rapid
+//! keystrokes. [`SingleFlight`] lets the first caller for a key (the leader)
+//! do the work while later callers with the same key wait for and share its
+//! result, so identical concurrent requests cost one inference call. If the
+//! leader is cancelled before finishing, a waiting caller takes over.
+//! Followers of a failed leader get an error carrying the leader's message;
+//! only the leader sees the original typed error.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// Outcome published by a leader; errors are shared as their message
+type Shared<T> = Option<Result<T, Arc<String>>>;
+
+/// Calls in flight, keyed by request
+#[derive(Debug)]
+pub(crate) struct SingleFlight<T> {
+    in_flight: Arc<Mutex<HashMap<String, watch::Receiver<Shared<T>>>>>,
+}
+
+impl<T> Default for SingleFlight<T> {
+    fn default() -> Self {
+        Self {
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl<T: Clone> SingleFlight<T> {
+    /// Run `work` for `key`, or share the result of a call already in flight
+    ///
+    /// Returns the result and whether it came from another caller's call.
+    pub(crate) async fn run<F, Fut>(&self, key: String, work: F) -> anyhow::Result<(T, bool)>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut work = Some(work);
+        loop {
+            let (sender, waiting) = {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                match in_flight.get(&key) {
+                    Some(receiver) => (None, Some(receiver.clone())),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        in_flight.insert(key.clone(), receiver);
+                        (Some(sender), None)
+                    }
+                }
+            };
+
+            if let Some(mut receiver) = waiting {
+                let shared = receiver
+                    .wait_for(Option::is_some)
+                    .await
+                    .ok()
+                    .and_then(|outcome| outcome.clone());
+                match shared {
+                    Some(Ok(value)) => return Ok((value, true)),
+                    Some(Err(message)) => anyhow::bail!("{}", message),
+                    // The leader was cancelled; try to lead instead
+                    None => continue,
+                }
+            }
+
+            let sender = sender.expect("either leading or waiting");
+            let _leader = LeaderGuard {
+                in_flight: &self.in_flight,
+                key: &key,
+            };
+            let work = work
+                .take()
+                .expect("only the first leadership runs the work");
+            let result = work().await;
+            sender.send_replace(Some(match &result {
+                Ok(value) => Ok(value.clone()),
+                Err(err) => Err(Arc::new(format!("{:#}", err))),
+            }));
+            return result.map(|value| (value, false));
+        }
+    }
+
+    /// Number of distinct calls in flight
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+}
+
+/// Removes the leader's entry when it finishes or is cancelled
+struct LeaderGuard<'a, T> {
+    in_flight: &'a Mutex<HashMap<String, watch::Receiver<Shared<T>>>>,
+    key: &'a str,
+}
+
+impl<T> Drop for LeaderGuard<'_, T> {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_callers_share_one_call() {
+        let flight = SingleFlight::default();
+        let calls = AtomicUsize::new(0);
+        let work = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(42)
+        };
+
+        let (a, b) = tokio::join!(
+            flight.run("k".to_string(), work),
+            flight.run("k".to_string(), work)
+        );
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!((a.0, b.0), (42, 42));
+        assert!(a.1 ^ b.1, "exactly one caller shares the other's result");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(flight.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_followers_see_leader_error_and_take_over_on_cancel() {
+        let flight: SingleFlight<u32> = SingleFlight::default();
+        let (a, b) = tokio::join!(
+            flight.run("k".to_string(), || async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                anyhow::bail!("service down")
+            }),
+            flight.run("k".to_string(), || async { Ok(1) })
+        );
+        assert!(a.is_err());
+        assert!(b.unwrap_err().to_string().contains("service down"));
+
+        // A cancelled leader hands the call to the waiting caller
+        let leader = flight.run("k".to_string(), || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(1)
+        });
+        let cancelled = tokio::time::timeout(Duration::from_millis(20), leader);
+        let (cancelled, follower) = tokio::join!(cancelled, async {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            flight.run("k".to_string(), || async { Ok(2) }).await
+        });
+        assert!(cancelled.is_err());
+        assert_eq!(follower.unwrap(), (2, false));
+    }
+}
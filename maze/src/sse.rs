@@ -0,0 +1,361 @@
+//! Buffered decoding of streaming inference responses
+//!
+//! Network chunk boundaries rarely line up with event boundaries: a chunk
+//! can end mid-line, mid-JSON, or even mid-way through a multi-byte
+//! character. [`EventDecoder`] buffers raw bytes until a line is complete,
+//! then assembles Server-Sent Events (joining multi-line `data:` fields)
+//! or newline-delimited JSON objects. Each complete event is decoded on its
+//! own, so a malformed event becomes a [`StreamEventError`] for that event
+//! while the rest of the stream carries on.
+
+use serde::Deserialize;
+
+use crate::modal_client::{ConstraintEvent, FinishReason};
+use crate::redaction::RedactionPolicy;
+
+/// A streamed event that could not be used
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum StreamEventError {
+    /// The event payload is not valid JSON of the expected shape
+    #[error("malformed stream event {event}: {message}")]
+    Malformed {
+        /// Position of the event in the stream (0-based)
+        event: usize,
+
+        /// Parser message
+        message: String,
+
+        /// The payload, redacted per the client's policy
+        data: String,
+    },
+
+    /// The inference service reported an error in the event
+    #[error("inference service error in stream event {event}: {message}")]
+    Server {
+        /// Position of the event in the stream (0-based)
+        event: usize,
+
+        /// Message reported by the service
+        message: String,
+    },
+}
+
+/// The malformed event behind a stream item's `err`, if that is what it is
+///
+/// Consumers skip these and read on; transport failures and errors the
+/// service reported still end the stream.
+pub(crate) fn malformed_event(err: &anyhow::Error) -> Option<&StreamEventError> {
+    err.downcast_ref::<StreamEventError>()
+        .filter(|e| matches!(e, StreamEventError::Malformed { .. }))
+}
+
+/// A complete event from the stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RawEvent {
+    /// SSE `data` (multi-line fields joined by `\n`) or one JSON line
+    Data(String),
+
+    /// A line that is neither SSE nor JSON, passed through as text
+    Text(String),
+}
+
+/// Payload of a streamed event
+#[derive(Debug, Deserialize)]
+struct EventData {
+    token: Option<String>,
+    done: Option<bool>,
+    error: Option<String>,
+    finish_reason: Option<FinishReason>,
+    constraint_event: Option<ConstraintEvent>,
+}
+
+/// A decoded token, or the end of generation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DecodedToken {
+    pub(crate) text: String,
+    pub(crate) is_final: bool,
+    pub(crate) finish_reason: Option<FinishReason>,
+    pub(crate) constraint_event: Option<ConstraintEvent>,
+}
+
+/// Reassembles events from arbitrarily split chunks
+#[derive(Debug, Default)]
+pub(crate) struct EventDecoder {
+    /// Bytes of the line not yet terminated
+    partial: Vec<u8>,
+
+    /// `data` lines of the SSE event being assembled
+    data: Vec<String>,
+
+    /// Events decoded so far
+    events: usize,
+}
+
+impl EventDecoder {
+    /// Feed the next network chunk, returning the events it completes
+    pub(crate) fn feed(&mut self, bytes: &[u8]) -> Vec<RawEvent> {
+        let mut events = Vec::new();
+        let mut rest = bytes;
+        // Splitting on the byte is safe: '\n' never occurs inside a
+        // multi-byte UTF-8 sequence
+        while let Some(pos) = rest.iter().position(|&b| b == b'\n') {
+            self.partial.extend_from_slice(&rest[..pos]);
+            rest = &rest[pos + 1..];
+            let line = std::mem::take(&mut self.partial);
+            self.line(&String::from_utf8_lossy(&line), &mut events);
+        }
+        self.partial.extend_from_slice(rest);
+        events
+    }
+
+    /// Flush what remains once the stream ends
+    ///
+    /// A final event does not need its terminating blank line. A stream cut
+    /// off mid-character leaves an incomplete UTF-8 sequence at the end;
+    /// it is dropped rather than emitted as a replacement character.
+    pub(crate) fn finish(&mut self) -> Vec<RawEvent> {
+        let mut events = Vec::new();
+        if !self.partial.is_empty() {
+            let mut line = std::mem::take(&mut self.partial);
+            if let Err(e) = std::str::from_utf8(&line) {
+                if e.error_len().is_none() {
+                    tracing::warn!(
+                        "Stream ended inside a UTF-8 character; dropping {} trailing bytes",
+                        line.len() - e.valid_up_to()
+                    );
+                    line.truncate(e.valid_up_to());
+                }
+            }
+            self.line(&String::from_utf8_lossy(&line), &mut events);
+        }
+        self.line("", &mut events);
+        events
+    }
+
+    /// Decode a complete event into a token
+    ///
+    /// Returns `Ok(None)` for events that carry nothing, such as an empty
+    /// non-final token.
+    pub(crate) fn decode(
+        &mut self,
+        event: RawEvent,
+        redaction: RedactionPolicy,
+    ) -> Result<Option<DecodedToken>, StreamEventError> {
+        let index = self.events;
+        self.events += 1;
+
+        let data = match event {
+            RawEvent::Text(text) => {
+                return Ok((!text.is_empty()).then_some(DecodedToken {
+                    text,
+                    is_final: false,
+                    finish_reason: None,
+                    constraint_event: None,
+                }))
+            }
+            RawEvent::Data(data) => data,
+        };
+        if data.trim() == "[DONE]" {
+            return Ok(Some(DecodedToken {
+                text: String::new(),
+                is_final: true,
+                finish_reason: None,
+                constraint_event: None,
+            }));
+        }
+
+        let payload: EventData =
+            serde_json::from_str(&data).map_err(|e| StreamEventError::Malformed {
+                event: index,
+                message: e.to_string(),
+                data: redaction.apply(&data).into_owned(),
+            })?;
+        if let Some(message) = payload.error {
+            return Err(StreamEventError::Server {
+                event: index,
+                message: redaction.apply(&message).into_owned(),
+            });
+        }
+
+        let token = DecodedToken {
+            text: payload.token.unwrap_or_default(),
+            is_final: payload.done.unwrap_or(false),
+            finish_reason: payload.finish_reason,
+            constraint_event: payload.constraint_event,
+        };
+        Ok((!token.text.is_empty()
+            || token.is_final
+            || token.finish_reason.is_some()
+            || token.constraint_event.is_some())
+        .then_some(token))
+    }
+
+    fn line(&mut self, line: &str, events: &mut Vec<RawEvent>) {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        // A blank line ends the SSE event being assembled
+        if line.is_empty() {
+            if !self.data.is_empty() {
+                events.push(RawEvent::Data(std::mem::take(&mut self.data).join("\n")));
+            }
+            return;
+        }
+
+        // Comments, often sent as keep-alives
+        if line.starts_with(':') {
+            return;
+        }
+
+        // Newline-delimited JSON outside an SSE event
+        if self.data.is_empty() && line.trim_start().starts_with('{') {
+            events.push(RawEvent::Data(line.to_string()));
+            return;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "data" => self.data.push(value.to_string()),
+            "event" | "id" | "retry" => {}
+            _ => events.push(RawEvent::Text(line.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `chunks` and decode everything, as the client does
+    fn decode_all(chunks: &[&[u8]]) -> Vec<Result<DecodedToken, StreamEventError>> {
+        let mut decoder = EventDecoder::default();
+        let mut events = Vec::new();
+        for chunk in chunks {
+            events.extend(decoder.feed(chunk));
+        }
+        events.extend(decoder.finish());
+        events
+            .into_iter()
+            .filter_map(|event| decoder.decode(event, RedactionPolicy::None).transpose())
+            .collect()
+    }
+
+    fn texts(results: &[Result<DecodedToken, StreamEventError>]) -> Vec<String> {
+        results
+            .iter()
+            .map(|r| match r {
+                Ok(token) if token.is_final => "<done>".to_string(),
+                Ok(token) => token.text.clone(),
+                Err(_) => "<error>".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_finish_reason_is_decoded() {
+        let body =
+            "data: {\"token\": \"x\"}\n\ndata: {\"done\": true, \"finish_reason\": \"length\"}\n\n";
+        let results = decode_all(&[body.as_bytes()]);
+        let reasons: Vec<Option<FinishReason>> = results
+            .iter()
+            .map(|r| r.as_ref().unwrap().finish_reason)
+            .collect();
+        assert_eq!(reasons, vec![None, Some(FinishReason::MaxTokens)]);
+    }
+
+    #[test]
+    fn test_constraint_event_is_decoded() {
+        let body = "data: {\"constraint_event\": {\"token\": \"unsafe\", \"position\": 4, \"constraint\": \"no_unsafe\"}}\n\n";
+        let results = decode_all(&[body.as_bytes()]);
+        let token = results[0].as_ref().unwrap();
+        assert!(token.text.is_empty() && !token.is_final);
+        assert_eq!(
+            token.constraint_event,
+            Some(ConstraintEvent {
+                token: "unsafe".to_string(),
+                position: 4,
+                constraint: "no_unsafe".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_events_split_at_every_byte() {
+        let body =
+            "data: {\"token\": \"fn\"}\n\ndata: {\"token\": \" é✓\"}\r\n\r\ndata: [DONE]\n\n";
+        let bytes: Vec<&[u8]> = body.as_bytes().chunks(1).collect();
+        assert_eq!(texts(&decode_all(&bytes)), vec!["fn", " é✓", "<done>"]);
+    }
+
+    #[test]
+    fn test_multi_byte_characters_split_across_chunks() {
+        // A 4-byte emoji and a 2-byte identifier character, each split
+        // between chunks, in both SSE and JSON-lines framing
+        let body =
+            "data: {\"token\": \"let caf\u{e9}_\u{1f980} = 1;\"}\n\n{\"token\": \"\u{1f980}\"}\n";
+        let bytes = body.as_bytes();
+        let crab = body.find('\u{1f980}').unwrap();
+        let e_acute = body.find('\u{e9}').unwrap();
+        let last_crab = body.rfind('\u{1f980}').unwrap();
+        let chunks = [
+            &bytes[..e_acute + 1],
+            &bytes[e_acute + 1..crab + 2],
+            &bytes[crab + 2..crab + 3],
+            &bytes[crab + 3..last_crab + 1],
+            &bytes[last_crab + 1..],
+        ];
+        assert_eq!(
+            texts(&decode_all(&chunks)),
+            vec!["let caf\u{e9}_\u{1f980} = 1;", "\u{1f980}"]
+        );
+
+        // A stream cut off mid-character drops the incomplete tail
+        let mut decoder = EventDecoder::default();
+        assert!(decoder.feed("caf\u{e9}".as_bytes()).is_empty());
+        assert!(decoder.feed(&"\u{1f980}".as_bytes()[..2]).is_empty());
+        assert_eq!(
+            decoder.finish(),
+            vec![RawEvent::Text("caf\u{e9}".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_multi_line_data_and_comments() {
+        let results = decode_all(&[
+            b": keep-alive\nevent: token\nid: 7\ndata: {\"token\":\ndata:  \"x\"}\n",
+            b"\n",
+        ]);
+        assert_eq!(texts(&results), vec!["x"]);
+    }
+
+    #[test]
+    fn test_json_lines_and_unterminated_final_event() {
+        let results = decode_all(&[
+            b"{\"token\": \"a\"}\n{\"tok",
+            b"en\": \"b\"}\n{\"done\": true}",
+        ]);
+        assert_eq!(texts(&results), vec!["a", "b", "<done>"]);
+    }
+
+    #[test]
+    fn test_bad_event_does_not_end_stream() {
+        let results = decode_all(&[
+            b"data: {\"token\": \"a\"}\n\ndata: {\"token\": \n\n",
+            b"data: {\"error\": \"overloaded\"}\n\ndata: {\"token\": \"b\"}\n\n",
+        ]);
+        assert_eq!(texts(&results), vec!["a", "<error>", "<error>", "b"]);
+        assert!(matches!(
+            results[1],
+            Err(StreamEventError::Malformed { event: 1, .. })
+        ));
+        assert_eq!(
+            results[2].as_ref().unwrap_err(),
+            &StreamEventError::Server {
+                event: 2,
+                message: "overloaded".to_string()
+            }
+        );
+    }
+}
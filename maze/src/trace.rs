@@ -0,0 +1,358 @@
+//! Replayable traces of inference traffic
+//!
+//! A [`TraceSink`] taps each call to the inference service and records the
+//! request, the response or error, and how long it took. Install one with
+//! [`MazeOrchestrator::with_trace_sink`](crate::MazeOrchestrator::with_trace_sink),
+//! which traces every model the orchestrator calls, or with
+//! [`ModalClient::with_trace_sink`](crate::ModalClient::with_trace_sink) to
+//! trace a client used on its own. Nothing is recorded, or even built, until
+//! a sink is installed.
+//!
+//! Prompts, generated text, and the request context pass through the
+//! tapping component's [`RedactionPolicy`] before reaching the sink, and
+//! token logprobs, which spell out the generated text, are dropped unless
+//! the policy is [`RedactionPolicy::None`]. A trace taken under
+//! [`RedactionPolicy::None`] can be replayed:
+//! [`MockInferenceClient::from_trace`](crate::MockInferenceClient::from_trace)
+//! answers with the recorded responses and errors, in order, for offline
+//! reproductions and regression fixtures.
+//!
+//! ```no_run
+//! use maze::trace::{read_trace, JsonlTraceSink};
+//! use maze::{MazeOrchestrator, MockInferenceClient};
+//!
+//! let sink = JsonlTraceSink::create("generations.jsonl")?;
+//! let orchestrator =
+//!     MazeOrchestrator::with_client(MockInferenceClient::new("m"), Default::default())
+//!         .with_trace_sink(sink);
+//!
+//! // Later, offline
+//! let replay = MockInferenceClient::from_trace("m", read_trace("generations.jsonl")?);
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use crate::modal_client::{InferenceRequest, InferenceResponse};
+use crate::redaction::RedactionPolicy;
+
+/// One traced call to the inference service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceRecord {
+    /// When the call started, in milliseconds since the Unix epoch
+    pub timestamp_ms: i64,
+
+    /// Model the request was sent to
+    pub model: String,
+
+    /// The request, with its prompt and context redacted
+    pub request: InferenceRequest,
+
+    /// The response, with its generated text redacted and, when redacting,
+    /// its logprobs dropped, if the call succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<InferenceResponse>,
+
+    /// Why the call failed, if it did, as redacted by the client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// How long the call took, in milliseconds
+    pub duration_ms: u64,
+}
+
+impl TraceRecord {
+    /// Record of a call to `model` that started at `started` and ended with
+    /// `result`, redacted per `redaction`
+    pub fn new(
+        model: &str,
+        request: &InferenceRequest,
+        result: &Result<InferenceResponse>,
+        started: std::time::Instant,
+        redaction: RedactionPolicy,
+    ) -> Self {
+        let elapsed = started.elapsed();
+        let mut request = request.clone();
+        request.prompt = redaction.apply(&request.prompt).into_owned();
+        if let Some(context) = request.context.as_mut() {
+            if let Some(file) = context.current_file.as_mut() {
+                *file = redaction.apply(file).into_owned();
+            }
+            for value in context.metadata.values_mut() {
+                redact_json(value, redaction);
+            }
+        }
+        let (response, error) = match result {
+            Ok(response) => {
+                let mut response = response.clone();
+                response.generated_text = redaction.apply(&response.generated_text).into_owned();
+                if redaction != RedactionPolicy::None {
+                    response.logprobs = None;
+                }
+                (Some(response), None)
+            }
+            // Errors redact any prompt or output they quote when raised
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+        Self {
+            timestamp_ms: chrono::Utc::now().timestamp_millis() - elapsed.as_millis() as i64,
+            model: model.to_string(),
+            request,
+            response,
+            error,
+            duration_ms: elapsed.as_millis() as u64,
+        }
+    }
+}
+
+/// Redact every string in `value` in place
+fn redact_json(value: &mut serde_json::Value, redaction: RedactionPolicy) {
+    match value {
+        serde_json::Value::String(text) => *text = redaction.apply(text).into_owned(),
+        serde_json::Value::Array(items) => {
+            for item in items {
+                redact_json(item, redaction);
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for field in fields.values_mut() {
+                redact_json(field, redaction);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Destination for [`TraceRecord`]s
+///
+/// Recording must not fail a generation, so sinks handle their own errors,
+/// typically by logging them.
+pub trait TraceSink: Send + Sync {
+    /// Record one call
+    fn record(&self, record: &TraceRecord);
+}
+
+/// Appends each record to a file as one line of JSON
+///
+/// Lines are written by a background thread, so recording never blocks the
+/// async executor on file I/O. Dropping the sink waits for the lines already
+/// recorded to be written.
+pub struct JsonlTraceSink {
+    lines: Option<mpsc::Sender<Vec<u8>>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl JsonlTraceSink {
+    /// Append to the trace file at `path`, creating it if needed
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open trace file {}", path.display()))?;
+        let (lines, queued) = mpsc::channel::<Vec<u8>>();
+        let writer = std::thread::Builder::new()
+            .name("maze-trace-writer".to_string())
+            .spawn(move || {
+                let mut file = file;
+                // One write per line, so records do not interleave
+                for line in queued {
+                    if let Err(e) = file.write_all(&line) {
+                        tracing::warn!("Failed to write trace record: {}", e);
+                    }
+                }
+            })
+            .context("Failed to start trace writer thread")?;
+        Ok(Self {
+            lines: Some(lines),
+            writer: Some(writer),
+        })
+    }
+}
+
+impl Drop for JsonlTraceSink {
+    fn drop(&mut self) {
+        // Closing the channel ends the writer once it has drained it
+        drop(self.lines.take());
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl TraceSink for JsonlTraceSink {
+    fn record(&self, record: &TraceRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!("Failed to serialize trace record: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        let sent = self.lines.as_ref().map(|lines| lines.send(line));
+        if !matches!(sent, Some(Ok(()))) {
+            tracing::warn!("Failed to write trace record: trace writer stopped");
+        }
+    }
+}
+
+/// Read the records of a trace file written by [`JsonlTraceSink`], in order
+pub fn read_trace(path: impl AsRef<Path>) -> Result<Vec<TraceRecord>> {
+    let path = path.as_ref();
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open trace file {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(index, line)| {
+            let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+            serde_json::from_str(&line).with_context(|| {
+                format!("Invalid trace record at {}:{}", path.display(), index + 1)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockInferenceClient;
+
+    fn request(prompt: &str) -> InferenceRequest {
+        InferenceRequest {
+            prompt: prompt.to_string(),
+            constraints: serde_json::json!({}),
+            max_tokens: 16,
+            temperature: 0.5,
+            context: None,
+            seed: None,
+            idempotency_key: None,
+            include_logprobs: false,
+            timeout_ms: None,
+            include_constraint_events: false,
+        }
+    }
+
+    #[test]
+    fn test_records_round_trip_through_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let sink = JsonlTraceSink::create(&path).unwrap();
+        let started = std::time::Instant::now();
+        let ok = Ok(MockInferenceClient::response("m", "let x = 1;"));
+        sink.record(&TraceRecord::new(
+            "m",
+            &request("a"),
+            &ok,
+            started,
+            RedactionPolicy::None,
+        ));
+        let failed = Err(anyhow::anyhow!("service unavailable"));
+        sink.record(&TraceRecord::new(
+            "m",
+            &request("b"),
+            &failed,
+            started,
+            RedactionPolicy::None,
+        ));
+        drop(sink);
+
+        let records = read_trace(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].request.prompt, "a");
+        assert_eq!(
+            records[0].response.as_ref().unwrap().generated_text,
+            "let x = 1;"
+        );
+        assert_eq!(records[1].error.as_deref(), Some("service unavailable"));
+        assert!(records[1].response.is_none());
+    }
+
+    #[test]
+    fn test_sink_writes_off_the_caller_thread() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("trace.jsonl");
+        let sink = JsonlTraceSink::create(&path).unwrap();
+        let ok = Ok(MockInferenceClient::response("m", "x"));
+        for prompt in ["a", "b", "c"] {
+            sink.record(&TraceRecord::new(
+                "m",
+                &request(prompt),
+                &ok,
+                std::time::Instant::now(),
+                RedactionPolicy::None,
+            ));
+        }
+        // Dropping the sink waits for queued records
+        drop(sink);
+        let prompts: Vec<String> = read_trace(&path)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.request.prompt)
+            .collect();
+        assert_eq!(prompts, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_record_is_redacted() {
+        let ok = Ok(MockInferenceClient::response("m", "password = hunter2"));
+        let record = TraceRecord::new(
+            "m",
+            &request("use hunter2"),
+            &ok,
+            std::time::Instant::now(),
+            RedactionPolicy::Full,
+        );
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(!json.contains("hunter2"));
+    }
+
+    #[test]
+    fn test_record_redacts_context_and_logprobs() {
+        let mut traced = request("complete this");
+        traced.context = Some(crate::GenerationContext {
+            current_file: Some("let key = \"hunter2\";".to_string()),
+            language: Some("rust".to_string()),
+            project_root: None,
+            metadata: [(
+                "surrounding_code".to_string(),
+                serde_json::json!({"before": ["let key = hunter2;"], "lines": 1}),
+            )]
+            .into(),
+        });
+        let mut response = MockInferenceClient::response("m", "hunter2");
+        let token = |token: &str| crate::modal_client::TopLogprob {
+            token: token.to_string(),
+            logprob: -0.1,
+        };
+        response.logprobs = Some(vec![crate::modal_client::TokenLogprob {
+            token: "hunter2".to_string(),
+            logprob: -0.1,
+            top_logprobs: vec![token("hunter2"), token("hunter3")],
+        }]);
+        let ok = Ok(response);
+        let record =
+            |redaction| TraceRecord::new("m", &traced, &ok, std::time::Instant::now(), redaction);
+
+        let json = serde_json::to_string(&record(RedactionPolicy::Full)).unwrap();
+        assert!(!json.contains("hunter"), "{}", json);
+        let redacted = record(RedactionPolicy::Full);
+        let context = redacted.request.context.unwrap();
+        assert_eq!(context.language.as_deref(), Some("rust"));
+        assert_eq!(context.metadata["surrounding_code"]["lines"], 1);
+        assert!(redacted.response.unwrap().logprobs.is_none());
+
+        // A replayable trace keeps everything
+        let json = serde_json::to_string(&record(RedactionPolicy::None)).unwrap();
+        assert_eq!(json.matches("hunter2").count(), 5);
+    }
+}
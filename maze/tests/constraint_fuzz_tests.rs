@@ -0,0 +1,361 @@
+//! Property tests for constraint compilation
+//!
+//! Generates arbitrary mixes of JSON schema, grammar, regex, and token mask
+//! constraints and checks that every compiler produces well-formed output,
+//! that compiled schemas and IR round-trip through every transfer format,
+//! and that cache keys are stable for equal inputs and distinct for
+//! different ones.
+
+use maze::ffi::{
+    ConstraintIR, Grammar, GrammarRule, JsonSchema, RegexPattern, TokenMaskRules,
+    CONSTRAINT_SCHEMA_VERSION,
+};
+use maze::ir_codec::IrFormat;
+use maze::{ConstraintFormat, MazeConfig, MazeOrchestrator, MockInferenceClient};
+use proptest::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// Names drawn from a small pool so that collisions are common
+fn arb_name() -> impl Strategy<Value = String> {
+    prop_oneof![
+        3 => prop::sample::select(vec!["types", "style", "security", "a", "b"])
+            .prop_map(String::from),
+        1 => "[a-z_]{1,8}",
+    ]
+}
+
+fn arb_json_value() -> impl Strategy<Value = serde_json::Value> {
+    prop_oneof![
+        Just(serde_json::json!({"type": "string"})),
+        Just(serde_json::json!({"type": "integer", "minimum": 0})),
+        Just(serde_json::json!({"type": "array", "items": {"type": "number"}})),
+        "[a-z]{0,6}".prop_map(|s| serde_json::json!({"const": s})),
+    ]
+}
+
+fn arb_json_schema() -> impl Strategy<Value = JsonSchema> {
+    (
+        prop::sample::select(vec!["object", "string", "array"]),
+        prop::collection::hash_map("[a-z]{1,6}", arb_json_value(), 0..5),
+        prop::collection::vec("[a-z]{1,6}", 0..3),
+        any::<bool>(),
+    )
+        .prop_map(
+            |(schema_type, properties, required, additional_properties)| JsonSchema {
+                schema_type: schema_type.to_string(),
+                properties,
+                required,
+                additional_properties,
+            },
+        )
+}
+
+fn arb_grammar() -> impl Strategy<Value = Grammar> {
+    let rule = (
+        "[a-z]{1,6}",
+        prop::collection::vec("[a-z'\"|()*+ ]{0,8}", 0..4),
+    )
+        .prop_map(|(lhs, rhs)| GrammarRule { lhs, rhs });
+    (prop::collection::vec(rule, 0..5), "[a-z]{1,6}").prop_map(|(rules, start_symbol)| Grammar {
+        rules,
+        start_symbol,
+    })
+}
+
+fn arb_regex() -> impl Strategy<Value = RegexPattern> {
+    (
+        prop::sample::select(vec!["[0-9]+", "[a-z_]+", "\\w+\\(\\)", "(foo|bar)?", ".*"]),
+        prop::sample::select(vec!["", "i", "m"]),
+    )
+        .prop_map(|(pattern, flags)| RegexPattern {
+            pattern: pattern.to_string(),
+            flags: flags.to_string(),
+        })
+}
+
+fn arb_token_masks() -> impl Strategy<Value = TokenMaskRules> {
+    (
+        prop::option::of(prop::collection::vec(0u32..64, 0..6)),
+        prop::option::of(prop::collection::vec(0u32..64, 0..6)),
+    )
+        .prop_map(|(allowed_tokens, forbidden_tokens)| TokenMaskRules {
+            allowed_tokens,
+            forbidden_tokens,
+        })
+}
+
+fn arb_constraint() -> impl Strategy<Value = ConstraintIR> {
+    (
+        arb_name(),
+        prop::option::of(arb_json_schema()),
+        prop::option::of(arb_grammar()),
+        prop::collection::vec(arb_regex(), 0..3),
+        prop::option::of(arb_token_masks()),
+        0u32..4,
+    )
+        .prop_map(
+            |(name, json_schema, grammar, regex_patterns, token_masks, priority)| ConstraintIR {
+                name,
+                json_schema,
+                grammar,
+                regex_patterns,
+                token_masks,
+                type_inhabitation: None,
+                priority,
+                rich_context: None,
+                feasibility_score: 0.0,
+                is_feasible: true,
+                schema_version: CONSTRAINT_SCHEMA_VERSION,
+            },
+        )
+}
+
+fn arb_constraints() -> impl Strategy<Value = Vec<ConstraintIR>> {
+    prop::collection::vec(arb_constraint(), 0..6)
+}
+
+fn orchestrator(format: ConstraintFormat) -> MazeOrchestrator {
+    MazeOrchestrator::with_client(
+        MockInferenceClient::new("mock-model"),
+        MazeConfig {
+            constraint_format: format,
+            ..Default::default()
+        },
+    )
+}
+
+/// Rebuild every `HashMap` in the IR so its iteration order may change
+fn reshuffled(constraints: &[ConstraintIR]) -> Vec<ConstraintIR> {
+    constraints
+        .iter()
+        .cloned()
+        .map(|mut constraint| {
+            if let Some(schema) = constraint.json_schema.as_mut() {
+                let mut entries: Vec<_> = schema.properties.drain().collect();
+                entries.reverse();
+                schema.properties = entries.into_iter().collect::<HashMap<_, _>>();
+            }
+            constraint
+        })
+        .collect()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn llguidance_output_has_expected_shape(constraints in arb_constraints()) {
+        let (schema, report) = ConstraintFormat::Llguidance
+            .compiler()
+            .compile(&constraints)
+            .unwrap();
+
+        prop_assert_eq!(&schema["type"], "object");
+        let properties = schema["properties"].as_object().unwrap();
+        let entries = schema["constraints"].as_array().unwrap();
+
+        // One property per distinct name that carries a JSON schema
+        let schema_names: HashSet<&str> = constraints
+            .iter()
+            .filter(|c| c.json_schema.is_some())
+            .map(|c| c.name.as_str())
+            .collect();
+        let property_names: HashSet<&str> = properties.keys().map(String::as_str).collect();
+        prop_assert_eq!(property_names, schema_names);
+
+        let count = |kind: &str| entries.iter().filter(|e| e["type"] == kind).count();
+        prop_assert_eq!(
+            count("grammar"),
+            constraints.iter().filter(|c| c.grammar.is_some()).count()
+        );
+        prop_assert_eq!(
+            count("regex"),
+            constraints.iter().map(|c| c.regex_patterns.len()).sum::<usize>()
+        );
+        prop_assert!(
+            count("token_mask") <= constraints.iter().filter(|c| c.token_masks.is_some()).count()
+        );
+        prop_assert_eq!(
+            count("grammar") + count("regex") + count("token_mask"),
+            entries.len()
+        );
+
+        prop_assert_eq!(report.order.len(), constraints.len());
+        let labels: HashSet<&String> = report.order.iter().collect();
+        prop_assert_eq!(labels.len(), constraints.len());
+    }
+
+    #[test]
+    fn grammar_formats_emit_a_grammar_or_name_the_culprit(
+        constraints in arb_constraints(),
+        format in prop::sample::select(vec![ConstraintFormat::Gbnf, ConstraintFormat::Ebnf]),
+    ) {
+        match format.compiler().compile(&constraints) {
+            Ok((schema, _)) => {
+                prop_assert_eq!(&schema["format"], format.as_str());
+                prop_assert!(schema["root"].is_string());
+                prop_assert!(schema["grammar"].is_string());
+            }
+            Err(err) => {
+                let message = err.to_string();
+                prop_assert!(
+                    constraints
+                        .iter()
+                        .any(|c| message.starts_with(&format!("constraint '{}'", c.name))),
+                    "error does not name a constraint: {}",
+                    message
+                );
+                prop_assert!(message.ends_with(format.as_str()), "{}", message);
+            }
+        }
+    }
+
+    #[test]
+    fn compiled_schema_and_ir_round_trip(constraints in arb_constraints()) {
+        let compiler = ConstraintFormat::Llguidance.compiler();
+        let (schema, _) = compiler.compile(&constraints).unwrap();
+
+        let text = serde_json::to_string(&schema).unwrap();
+        let reparsed: serde_json::Value = serde_json::from_str(&text).unwrap();
+        prop_assert_eq!(&reparsed, &schema);
+
+        let ir_text = serde_json::to_string(&constraints).unwrap();
+        let ir: Vec<ConstraintIR> = serde_json::from_str(&ir_text).unwrap();
+        prop_assert_eq!(compiler.compile(&ir).unwrap().0, schema);
+
+        let orchestrator = orchestrator(ConstraintFormat::Llguidance);
+        prop_assert_eq!(
+            orchestrator.generate_cache_key(&ir).unwrap(),
+            orchestrator.generate_cache_key(&constraints).unwrap()
+        );
+    }
+
+    #[test]
+    fn cache_key_ignores_map_iteration_order(constraints in arb_constraints()) {
+        let orchestrator = orchestrator(ConstraintFormat::Llguidance);
+        prop_assert_eq!(
+            orchestrator.generate_cache_key(&reshuffled(&constraints)).unwrap(),
+            orchestrator.generate_cache_key(&constraints).unwrap()
+        );
+    }
+
+    #[test]
+    fn cache_key_ignores_transfer_format(
+        constraints in arb_constraints(),
+        format in prop::sample::select(vec![IrFormat::Json, IrFormat::MessagePack]),
+    ) {
+        let decoded = format.decode(&format.encode(&constraints).unwrap()).unwrap();
+
+        let orchestrator = orchestrator(ConstraintFormat::Llguidance);
+        prop_assert_eq!(
+            orchestrator.generate_cache_key(&decoded).unwrap(),
+            orchestrator.generate_cache_key(&constraints).unwrap()
+        );
+    }
+
+    #[test]
+    fn distinct_constraints_get_distinct_cache_keys(
+        a in arb_constraints(),
+        b in arb_constraints(),
+    ) {
+        prop_assume!(serde_json::to_value(&a).unwrap() != serde_json::to_value(&b).unwrap());
+
+        let orchestrator = orchestrator(ConstraintFormat::Llguidance);
+        prop_assert_ne!(
+            orchestrator.generate_cache_key(&a).unwrap(),
+            orchestrator.generate_cache_key(&b).unwrap()
+        );
+    }
+
+    #[test]
+    fn cache_key_depends_on_format(constraints in arb_constraints()) {
+        let keys: HashSet<String> = [
+            ConstraintFormat::Llguidance,
+            ConstraintFormat::Gbnf,
+            ConstraintFormat::Ebnf,
+        ]
+        .into_iter()
+        .map(|format| orchestrator(format).generate_cache_key(&constraints).unwrap())
+        .collect();
+        prop_assert_eq!(keys.len(), 3);
+    }
+}
+
+/// Two constraints sharing a name must not silently drop either schema
+#[test]
+fn same_name_schemas_are_combined_not_overwritten() {
+    let constraint = |required: &str| ConstraintIR {
+        name: "types".to_string(),
+        json_schema: Some(JsonSchema {
+            schema_type: "object".to_string(),
+            properties: HashMap::new(),
+            required: vec![required.to_string()],
+            additional_properties: false,
+        }),
+        grammar: None,
+        regex_patterns: vec![],
+        token_masks: None,
+        type_inhabitation: None,
+        priority: 1,
+        rich_context: None,
+        feasibility_score: 0.0,
+        is_feasible: true,
+        schema_version: CONSTRAINT_SCHEMA_VERSION,
+    };
+
+    let (schema, report) = ConstraintFormat::Llguidance
+        .compiler()
+        .compile(&[constraint("x"), constraint("y")])
+        .unwrap();
+
+    let all_of = schema["properties"]["types"]["allOf"].as_array().unwrap();
+    assert_eq!(all_of.len(), 2);
+    assert_eq!(report.combined().count(), 1);
+}
+
+/// Schemas written with their keys in a different order share a cache key
+#[test]
+fn cache_key_ignores_json_key_order() {
+    let constraint = |properties: &[(&str, &str)], context: &str| ConstraintIR {
+        name: "user".to_string(),
+        json_schema: Some(JsonSchema {
+            schema_type: "object".to_string(),
+            properties: properties
+                .iter()
+                .map(|(name, schema)| (name.to_string(), serde_json::from_str(schema).unwrap()))
+                .collect(),
+            required: vec!["id".to_string()],
+            additional_properties: false,
+        }),
+        grammar: None,
+        regex_patterns: vec![],
+        token_masks: None,
+        type_inhabitation: None,
+        priority: 1,
+        rich_context: Some(serde_json::from_str(context).unwrap()),
+        feasibility_score: 0.0,
+        is_feasible: true,
+        schema_version: CONSTRAINT_SCHEMA_VERSION,
+    };
+
+    let a = constraint(
+        &[
+            ("id", r#"{"type": "integer", "minimum": 0}"#),
+            ("name", r#"{"type": "string", "maxLength": 64}"#),
+        ],
+        r#"{"module": "users", "deny": {"calls": ["eval"], "imports": ["os"]}}"#,
+    );
+    let b = constraint(
+        &[
+            ("name", r#"{"maxLength": 64, "type": "string"}"#),
+            ("id", r#"{"minimum": 0, "type": "integer"}"#),
+        ],
+        r#"{"deny": {"imports": ["os"], "calls": ["eval"]}, "module": "users"}"#,
+    );
+
+    let orchestrator = orchestrator(ConstraintFormat::Llguidance);
+    assert_eq!(
+        orchestrator.generate_cache_key(&[a]).unwrap(),
+        orchestrator.generate_cache_key(&[b]).unwrap()
+    );
+}
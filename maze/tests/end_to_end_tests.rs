@@ -28,6 +28,10 @@ async fn test_e2e_simple_generation_with_mock() {
 
     let _m = server
         .mock("POST", "/generate")
+        .match_header(
+            maze::modal_client::REQUEST_ID_HEADER,
+            mockito::Matcher::Regex("^[0-9a-f-]{36}$".to_string()),
+        )
         .with_status(200)
         .with_header("content-type", "application/json")
         .with_body(response_body.to_string())
@@ -52,6 +56,7 @@ async fn test_e2e_simple_generation_with_mock() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }];
 
     let request = GenerationRequest {
@@ -65,6 +70,9 @@ async fn test_e2e_simple_generation_with_mock() {
             project_root: None,
             metadata: HashMap::new(),
         }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     let response = orchestrator.generate(request).await.unwrap();
@@ -74,6 +82,10 @@ async fn test_e2e_simple_generation_with_mock() {
     assert_eq!(response.metadata.tokens_generated, 25);
     assert!(response.validation.all_satisfied);
     assert_eq!(response.provenance.model, "test-model");
+    assert_eq!(
+        response.metadata.request_id.as_ref().map(String::len),
+        Some(36)
+    );
 }
 
 #[tokio::test]
@@ -119,6 +131,7 @@ async fn test_e2e_typescript_constraints() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
         ConstraintIR {
             name: "type_annotations".to_string(),
@@ -140,6 +153,7 @@ async fn test_e2e_typescript_constraints() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
     ];
 
@@ -154,6 +168,9 @@ async fn test_e2e_typescript_constraints() {
             project_root: None,
             metadata: HashMap::new(),
         }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     let response = orchestrator.generate(request).await.unwrap();
@@ -217,6 +234,7 @@ async fn test_e2e_python_with_json_schema() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }];
 
     let request = GenerationRequest {
@@ -230,6 +248,9 @@ async fn test_e2e_python_with_json_schema() {
             project_root: None,
             metadata: HashMap::new(),
         }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     let response = orchestrator.generate(request).await.unwrap();
@@ -290,6 +311,7 @@ async fn test_e2e_with_grammar_constraint() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }];
 
     let request = GenerationRequest {
@@ -298,6 +320,9 @@ async fn test_e2e_with_grammar_constraint() {
         max_tokens: 50,
         temperature: 0.5,
         context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     let response = orchestrator.generate(request).await.unwrap();
@@ -348,6 +373,7 @@ async fn test_e2e_with_token_masks() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }];
 
     let request = GenerationRequest {
@@ -356,6 +382,9 @@ async fn test_e2e_with_token_masks() {
         max_tokens: 50,
         temperature: 0.7,
         context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     let response = orchestrator.generate(request).await.unwrap();
@@ -405,6 +434,7 @@ async fn test_e2e_constraint_caching() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }];
 
     let request1 = GenerationRequest {
@@ -413,6 +443,9 @@ async fn test_e2e_constraint_caching() {
         max_tokens: 50,
         temperature: 0.7,
         context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     let request2 = GenerationRequest {
@@ -421,6 +454,9 @@ async fn test_e2e_constraint_caching() {
         max_tokens: 50,
         temperature: 0.7,
         context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     // First request - should compile constraints
@@ -483,6 +519,7 @@ async fn test_e2e_multiple_constraints_with_priorities() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
         ConstraintIR {
             name: "type_safety".to_string(),
@@ -498,6 +535,7 @@ async fn test_e2e_multiple_constraints_with_priorities() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
         ConstraintIR {
             name: "async_handling".to_string(),
@@ -513,6 +551,7 @@ async fn test_e2e_multiple_constraints_with_priorities() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
     ];
 
@@ -522,6 +561,9 @@ async fn test_e2e_multiple_constraints_with_priorities() {
         max_tokens: 100,
         temperature: 0.7,
         context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     let response = orchestrator.generate(request).await.unwrap();
@@ -554,6 +596,9 @@ async fn test_e2e_generation_failure_handling() {
         max_tokens: 50,
         temperature: 0.7,
         context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     let result = orchestrator.generate(request).await;
@@ -602,6 +647,7 @@ async fn test_e2e_provenance_tracking() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }];
 
     let request = GenerationRequest {
@@ -610,6 +656,9 @@ async fn test_e2e_provenance_tracking() {
         max_tokens: 50,
         temperature: 0.8,
         context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     let response = orchestrator.generate(request).await.unwrap();
@@ -625,3 +674,437 @@ async fn test_e2e_provenance_tracking() {
     assert!(response.provenance.parameters.contains_key("max_tokens"));
     assert!(response.provenance.parameters.contains_key("temperature"));
 }
+
+#[tokio::test]
+async fn test_e2e_wait_until_ready_then_warmup() {
+    let mut server = Server::new_async().await;
+
+    // Cold container: first probe fails, second succeeds
+    let cold = server
+        .mock("GET", "/health")
+        .with_status(503)
+        .expect(1)
+        .create_async()
+        .await;
+    let warm = server
+        .mock("GET", "/health")
+        .with_status(200)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let response_body = serde_json::json!({
+        "generated_text": "ok",
+        "tokens_generated": 1,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 10,
+            "time_per_token_us": 10000,
+            "constraint_checks": 0,
+            "avg_constraint_check_us": 0
+        }
+    });
+    let _gen = server
+        .mock("POST", "/generate")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(response_body.to_string())
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    let orchestrator = MazeOrchestrator::new(config).unwrap();
+
+    let readiness = orchestrator
+        .wait_until_ready(std::time::Duration::from_secs(5))
+        .await;
+    assert!(readiness.ready);
+    assert_eq!(readiness.probes, 2);
+    assert!(readiness.last_error.is_none());
+    cold.assert_async().await;
+    warm.assert_async().await;
+
+    let metadata = orchestrator
+        .warmup(GenerationRequest {
+            prompt: "ping".to_string(),
+            constraints_ir: vec![],
+            max_tokens: 1,
+            temperature: 0.0,
+            context: None,
+            seed: None,
+            timeout_ms: None,
+            history: Vec::new(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(metadata.tokens_generated, 1);
+}
+
+#[tokio::test]
+async fn test_e2e_wait_until_ready_times_out() {
+    let mut server = Server::new_async().await;
+
+    let _m = server
+        .mock("GET", "/health")
+        .with_status(503)
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    let orchestrator = MazeOrchestrator::new(config).unwrap();
+
+    let readiness = orchestrator
+        .wait_until_ready(std::time::Duration::from_millis(250))
+        .await;
+    assert!(!readiness.ready);
+    assert!(readiness.probes >= 2);
+    assert!(readiness.last_error.is_some());
+}
+
+async fn mock_small_context_model(server: &mut mockito::ServerGuard) -> mockito::Mock {
+    server
+        .mock("GET", "/models")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(serde_json::json!([{ "name": "test-model", "context_window": 200 }]).to_string())
+        .create_async()
+        .await
+}
+
+#[tokio::test]
+async fn test_e2e_context_overflow_rejected_before_sending() {
+    let mut server = Server::new_async().await;
+    let _models = mock_small_context_model(&mut server).await;
+    let generate = server
+        .mock("POST", "/generate")
+        .expect(0)
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    let orchestrator = MazeOrchestrator::new(config).unwrap();
+
+    let request = GenerationRequest {
+        prompt: "x".repeat(1000),
+        constraints_ir: vec![],
+        max_tokens: 100,
+        temperature: 0.7,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let error = orchestrator.generate(request).await.unwrap_err();
+    let overflow = error.downcast_ref::<maze::ContextOverflow>().unwrap();
+    assert_eq!(overflow.allowed_tokens, 200);
+    assert_eq!(overflow.estimated_tokens, 350);
+    generate.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_e2e_context_overflow_truncates_metadata() {
+    let mut server = Server::new_async().await;
+    let _models = mock_small_context_model(&mut server).await;
+
+    let response_body = serde_json::json!({
+        "generated_text": "ok",
+        "tokens_generated": 1,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 10,
+            "time_per_token_us": 10000,
+            "constraint_checks": 0,
+            "avg_constraint_check_us": 0
+        }
+    });
+    // The small metadata entry must survive truncation
+    let generate = server
+        .mock("POST", "/generate")
+        .match_body(mockito::Matcher::PartialJson(
+            serde_json::json!({ "context": { "metadata": { "note": "keep" } } }),
+        ))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(response_body.to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    let maze_config = maze::MazeConfig {
+        context_overflow: maze::ContextOverflowPolicy::Truncate,
+        ..Default::default()
+    };
+    let orchestrator = MazeOrchestrator::with_config(config, maze_config).unwrap();
+
+    let mut metadata = HashMap::new();
+    metadata.insert("history".to_string(), serde_json::json!("h".repeat(800)));
+    metadata.insert("note".to_string(), serde_json::json!("keep"));
+
+    let request = GenerationRequest {
+        prompt: "Implement a function".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 100,
+        temperature: 0.7,
+        context: Some(GenerationContext {
+            current_file: None,
+            language: Some("rust".to_string()),
+            project_root: None,
+            metadata,
+        }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let response = orchestrator.generate(request).await.unwrap();
+    assert_eq!(response.code, "ok");
+    generate.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_e2e_prompt_assembled_from_context() {
+    let mut server = Server::new_async().await;
+
+    let response_body = serde_json::json!({
+        "generated_text": "fn parse() {}",
+        "tokens_generated": 5,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 50,
+            "time_per_token_us": 10000,
+            "constraint_checks": 2,
+            "avg_constraint_check_us": 50
+        }
+    });
+    let generate = server
+        .mock("POST", "/generate")
+        .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+            "prompt": "Language: rust\nFile: src/parser.rs\n\nWrite a parser"
+        })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(response_body.to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    let orchestrator = MazeOrchestrator::new(config).unwrap();
+
+    let request = GenerationRequest {
+        prompt: "Write a parser".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 50,
+        temperature: 0.7,
+        context: Some(GenerationContext {
+            current_file: Some("src/parser.rs".to_string()),
+            language: Some("rust".to_string()),
+            project_root: None,
+            metadata: HashMap::new(),
+        }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let response = orchestrator.generate(request).await.unwrap();
+    generate.assert_async().await;
+
+    assert_eq!(response.provenance.original_intent, "Write a parser");
+    assert_eq!(
+        response.provenance.context_included,
+        vec!["language", "current_file"]
+    );
+}
+
+#[tokio::test]
+async fn test_e2e_context_provider_snippets_in_prompt() {
+    struct StaticProvider;
+
+    impl maze::ContextProvider for StaticProvider {
+        fn fetch(&self, _context: &GenerationContext) -> Vec<maze::ContextSnippet> {
+            vec![
+                maze::ContextSnippet {
+                    source: "src/huge.rs".to_string(),
+                    content: "x".repeat(100_000),
+                    relevance: 1.0,
+                },
+                maze::ContextSnippet {
+                    source: "src/user.rs".to_string(),
+                    content: "struct User;".to_string(),
+                    relevance: 0.8,
+                },
+            ]
+        }
+    }
+
+    let mut server = Server::new_async().await;
+    let response_body = serde_json::json!({
+        "generated_text": "fn login() {}",
+        "tokens_generated": 5,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 50,
+            "time_per_token_us": 10000,
+            "constraint_checks": 2,
+            "avg_constraint_check_us": 50
+        }
+    });
+    let generate = server
+        .mock("POST", "/generate")
+        .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+            "prompt": "Language: rust\n\nRelevant context:\n// src/user.rs\nstruct User;\n\nAdd login"
+        })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(response_body.to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    let orchestrator = MazeOrchestrator::new(config)
+        .unwrap()
+        .with_context_provider(StaticProvider);
+
+    let request = GenerationRequest {
+        prompt: "Add login".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 50,
+        temperature: 0.7,
+        context: Some(GenerationContext {
+            current_file: None,
+            language: Some("rust".to_string()),
+            project_root: None,
+            metadata: HashMap::new(),
+        }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    // The oversized snippet exceeds the budget and is dropped
+    let response = orchestrator.generate(request).await.unwrap();
+    generate.assert_async().await;
+    assert_eq!(
+        response.provenance.context_included,
+        vec!["language", "snippet:src/user.rs"]
+    );
+}
+
+#[tokio::test]
+async fn test_e2e_seeded_generation_is_reproducible() {
+    let mut server = Server::new_async().await;
+
+    // Echo the seed back, standing in for a sampler pinned by it
+    let _m = server
+        .mock("POST", "/generate")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body_from_request(|request| {
+            let body: serde_json::Value = serde_json::from_slice(request.body().unwrap()).unwrap();
+            serde_json::json!({
+                "generated_text": format!("fn seeded_{}() {{}}", body["seed"]),
+                "tokens_generated": 5,
+                "model": "test-model",
+                "stats": {
+                    "total_time_ms": 50,
+                    "time_per_token_us": 10000,
+                    "constraint_checks": 0,
+                    "avg_constraint_check_us": 0
+                }
+            })
+            .to_string()
+            .into_bytes()
+        })
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    let orchestrator = MazeOrchestrator::new(config).unwrap();
+
+    let request = |seed| GenerationRequest {
+        prompt: "Implement a function".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 50,
+        temperature: 0.0,
+        context: None,
+        seed: Some(seed),
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let first = orchestrator.generate(request(42)).await.unwrap();
+    let replay = orchestrator.generate(request(42)).await.unwrap();
+    let other = orchestrator.generate(request(7)).await.unwrap();
+
+    assert_eq!(first.code, "fn seeded_42() {}");
+    assert_eq!(first.code, replay.code);
+    assert_ne!(first.code, other.code);
+    assert_eq!(
+        first.provenance.parameters.get("seed"),
+        Some(&serde_json::json!(42))
+    );
+}
+
+#[tokio::test]
+async fn test_e2e_response_cache_serves_deterministic_requests() {
+    let mut server = Server::new_async().await;
+
+    let response_body = serde_json::json!({
+        "generated_text": "fn cached() {}",
+        "tokens_generated": 5,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 50,
+            "time_per_token_us": 10000,
+            "constraint_checks": 0,
+            "avg_constraint_check_us": 0
+        }
+    });
+    // One call for the deterministic pair, two for the stochastic pair
+    let generate = server
+        .mock("POST", "/generate")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(response_body.to_string())
+        .expect(3)
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    let maze_config = maze::MazeConfig {
+        response_cache: maze::ResponseCacheConfig {
+            enabled: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let orchestrator = MazeOrchestrator::with_config(config, maze_config).unwrap();
+
+    let request = |temperature, seed| GenerationRequest {
+        prompt: "Implement a function".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 50,
+        temperature,
+        context: None,
+        seed,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let first = orchestrator.generate(request(0.0, Some(1))).await.unwrap();
+    let second = orchestrator.generate(request(0.0, Some(1))).await.unwrap();
+    assert!(!first.metadata.cache_hit);
+    assert!(second.metadata.cache_hit);
+    assert_eq!(first.code, second.code);
+
+    let stochastic = orchestrator.generate(request(0.7, None)).await.unwrap();
+    let again = orchestrator.generate(request(0.7, None)).await.unwrap();
+    assert!(!stochastic.metadata.cache_hit);
+    assert!(!again.metadata.cache_hit);
+
+    generate.assert_async().await;
+}
@@ -3,8 +3,11 @@
 //! Tests the C-compatible FFI layer between Rust and Zig
 
 use maze::ffi::{
-    ConstraintIR, GenerationResult, Grammar, GrammarRule, Intent, JsonSchema, RegexPattern,
-    TokenMaskRules,
+    constraint_cache_stats_ffi, constraint_is_cached, constraint_register_encoded_ffi,
+    constraint_register_ffi, constraint_release_ffi, free_constraint_ir_ffi, free_ffi_string,
+    free_generation_result_ffi, generate_registered_ffi, take_last_error_ffi, CacheStatsFFI,
+    ConstraintIR, ConstraintIRFFI, FfiErrorCode, GenerationResult, GenerationResultFFI, Grammar,
+    GrammarRule, Intent, IntentFFI, JsonSchema, RegexPattern, TokenMaskRules,
 };
 use std::collections::HashMap;
 
@@ -21,6 +24,7 @@ fn test_constraint_ir_ffi_roundtrip_simple() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = constraint.to_ffi();
@@ -56,6 +60,7 @@ fn test_constraint_ir_ffi_with_regex() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = constraint.to_ffi();
@@ -101,6 +106,7 @@ fn test_constraint_ir_ffi_with_json_schema() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = constraint.to_ffi();
@@ -142,6 +148,7 @@ fn test_constraint_ir_ffi_with_grammar() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = constraint.to_ffi();
@@ -173,6 +180,7 @@ fn test_constraint_ir_ffi_with_token_masks() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = constraint.to_ffi();
@@ -199,7 +207,10 @@ fn test_constraint_ir_ffi_complex() {
             additional_properties: true,
         }),
         grammar: Some(Grammar {
-            rules: vec![],
+            rules: vec![GrammarRule {
+                lhs: "S".to_string(),
+                rhs: vec!["'a'".to_string()],
+            }],
             start_symbol: "S".to_string(),
         }),
         regex_patterns: vec![RegexPattern {
@@ -215,6 +226,7 @@ fn test_constraint_ir_ffi_complex() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = constraint.to_ffi();
@@ -242,6 +254,7 @@ fn test_intent_from_ffi_minimal() {
         prompt: prompt.as_ptr(),
         current_file: std::ptr::null(),
         language: std::ptr::null(),
+        timeout_ms: 0,
     };
 
     unsafe {
@@ -250,6 +263,7 @@ fn test_intent_from_ffi_minimal() {
         assert_eq!(intent.prompt, "implement auth handler");
         assert!(intent.current_file.is_none());
         assert!(intent.language.is_none());
+        assert!(intent.timeout_ms.is_none());
     }
 }
 
@@ -268,6 +282,7 @@ fn test_intent_from_ffi_complete() {
         prompt: prompt.as_ptr(),
         current_file: current_file.as_ptr(),
         language: language.as_ptr(),
+        timeout_ms: 500,
     };
 
     unsafe {
@@ -276,6 +291,7 @@ fn test_intent_from_ffi_complete() {
         assert_eq!(intent.prompt, "implement auth handler");
         assert_eq!(intent.current_file, Some("src/auth.rs".to_string()));
         assert_eq!(intent.language, Some("rust".to_string()));
+        assert_eq!(intent.timeout_ms, Some(500));
     }
 }
 
@@ -340,6 +356,7 @@ fn test_multiple_constraints_array() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
         ConstraintIR {
             name: "constraint2".to_string(),
@@ -352,6 +369,7 @@ fn test_multiple_constraints_array() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
         ConstraintIR {
             name: "constraint3".to_string(),
@@ -364,6 +382,7 @@ fn test_multiple_constraints_array() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
     ];
 
@@ -401,6 +420,7 @@ fn test_constraint_ir_serialization() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     // Test JSON serialization
@@ -422,6 +442,7 @@ fn test_intent_serialization() {
         prompt: "test prompt".to_string(),
         current_file: Some("test.rs".to_string()),
         language: Some("rust".to_string()),
+        timeout_ms: None,
     };
 
     let json = serde_json::to_string(&intent).unwrap();
@@ -432,3 +453,252 @@ fn test_intent_serialization() {
     assert_eq!(intent.current_file, deserialized.current_file);
     assert_eq!(intent.language, deserialized.language);
 }
+
+/// Copy boxed FFI structures into the contiguous array Zig would pass
+fn ffi_array(constraints: &[ConstraintIR]) -> (Vec<*mut ConstraintIRFFI>, Vec<ConstraintIRFFI>) {
+    let owned: Vec<_> = constraints.iter().map(ConstraintIR::to_ffi).collect();
+    let array = owned.iter().map(|&ptr| unsafe { (*ptr).clone() }).collect();
+    (owned, array)
+}
+
+#[tokio::test]
+async fn test_cache_query_across_ffi() {
+    let orchestrator = maze::MazeOrchestrator::with_client(
+        maze::MockInferenceClient::new("mock-model"),
+        maze::MazeConfig::default(),
+    );
+    let constraint = |name: &str| ConstraintIR {
+        name: name.to_string(),
+        json_schema: None,
+        grammar: None,
+        regex_patterns: vec![RegexPattern {
+            pattern: "[a-z]+".to_string(),
+            flags: String::new(),
+        }],
+        token_masks: None,
+        priority: 1,
+        rich_context: None,
+        feasibility_score: 0.0,
+        is_feasible: true,
+        type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+    };
+    let compiled = vec![constraint("ident"), constraint("style")];
+    let (owned, array) = ffi_array(&compiled);
+    let (other_owned, other) = ffi_array(&[constraint("other")]);
+
+    unsafe {
+        let mut stats = CacheStatsFFI::default();
+        assert_eq!(
+            constraint_cache_stats_ffi(&orchestrator, &mut stats),
+            FfiErrorCode::Success
+        );
+        assert_eq!(stats.size, 0);
+        assert!(stats.limit > 0);
+        assert!(!constraint_is_cached(
+            &orchestrator,
+            array.as_ptr(),
+            array.len()
+        ));
+
+        // Compile the set as Rust reads it from Zig
+        let received: Vec<ConstraintIR> = owned
+            .iter()
+            .map(|&ptr| ConstraintIR::from_ffi(ptr).unwrap())
+            .collect();
+        orchestrator.compile_constraints(&received).await.unwrap();
+
+        assert!(constraint_is_cached(
+            &orchestrator,
+            array.as_ptr(),
+            array.len()
+        ));
+        assert!(!constraint_is_cached(
+            &orchestrator,
+            other.as_ptr(),
+            other.len()
+        ));
+        assert_eq!(
+            constraint_cache_stats_ffi(&orchestrator, &mut stats),
+            FfiErrorCode::Success
+        );
+        assert_eq!(stats.size, 1);
+
+        // Bad input is reported, not treated as a cache miss silently
+        assert!(!constraint_is_cached(std::ptr::null(), array.as_ptr(), 1));
+        let message = take_last_error_ffi();
+        assert!(std::ffi::CStr::from_ptr(message)
+            .to_str()
+            .unwrap()
+            .contains("null pointer"));
+        free_ffi_string(message);
+
+        for ptr in owned.into_iter().chain(other_owned) {
+            free_constraint_ir_ffi(ptr);
+        }
+    }
+}
+
+#[test]
+fn test_registered_constraints_across_ffi() {
+    let client =
+        maze::MockInferenceClient::new("mock-model").with_default_response("fn ident() {}");
+    let orchestrator =
+        maze::MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+    let constraints: Vec<_> = ["ident", "style"]
+        .iter()
+        .map(|name| ConstraintIR {
+            name: name.to_string(),
+            json_schema: None,
+            grammar: None,
+            regex_patterns: vec![RegexPattern {
+                pattern: "[a-z]+".to_string(),
+                flags: String::new(),
+            }],
+            token_masks: None,
+            priority: 1,
+            rich_context: None,
+            feasibility_score: 0.0,
+            is_feasible: true,
+            type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+        })
+        .collect();
+    let (owned, array) = ffi_array(&constraints);
+    let prompt = std::ffi::CString::new("write an identifier").unwrap();
+    let intent = IntentFFI {
+        raw_input: prompt.as_ptr(),
+        prompt: std::ptr::null(),
+        current_file: std::ptr::null(),
+        language: std::ptr::null(),
+        timeout_ms: 0,
+    };
+
+    unsafe {
+        let mut handle = 0;
+        assert_eq!(
+            constraint_register_ffi(&orchestrator, array.as_ptr(), array.len(), &mut handle),
+            FfiErrorCode::Success
+        );
+        assert_ne!(handle, 0);
+        // The IR is copied at registration; Zig may free it straight away
+        for ptr in owned {
+            free_constraint_ir_ffi(ptr);
+        }
+
+        for _ in 0..2 {
+            let mut result: *mut GenerationResultFFI = std::ptr::null_mut();
+            assert_eq!(
+                generate_registered_ffi(&orchestrator, handle, &intent, &mut result),
+                FfiErrorCode::Success
+            );
+            assert!((*result).success);
+            assert_eq!(
+                std::ffi::CStr::from_ptr((*result).code).to_str().unwrap(),
+                "fn ident() {}"
+            );
+            free_generation_result_ffi(result);
+        }
+        let sent = client.requests();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].constraints, sent[1].constraints);
+
+        assert_eq!(
+            constraint_release_ffi(&orchestrator, handle),
+            FfiErrorCode::Success
+        );
+        let mut result: *mut GenerationResultFFI = std::ptr::null_mut();
+        assert_eq!(
+            generate_registered_ffi(&orchestrator, handle, &intent, &mut result),
+            FfiErrorCode::UseAfterFree
+        );
+        assert!(result.is_null());
+        let message = take_last_error_ffi();
+        assert!(std::ffi::CStr::from_ptr(message)
+            .to_str()
+            .unwrap()
+            .contains("already released"));
+        free_ffi_string(message);
+        assert_eq!(
+            constraint_release_ffi(&orchestrator, handle),
+            FfiErrorCode::DoubleFree
+        );
+        assert_eq!(
+            constraint_release_ffi(&orchestrator, handle + 100),
+            FfiErrorCode::InvalidPointer
+        );
+
+        // Invalid sets are rejected at registration
+        let mut unnamed = constraints[0].clone();
+        unnamed.name = String::new();
+        let (owned, array) = ffi_array(&[unnamed]);
+        assert_eq!(
+            constraint_register_ffi(&orchestrator, array.as_ptr(), 1, &mut handle),
+            FfiErrorCode::InvalidInput
+        );
+        for ptr in owned {
+            free_constraint_ir_ffi(ptr);
+        }
+    }
+    assert_eq!(orchestrator.registered_count(), 0);
+}
+
+#[test]
+fn test_register_encoded_constraints_across_ffi() {
+    let orchestrator = maze::MazeOrchestrator::with_client(
+        maze::MockInferenceClient::new("mock-model"),
+        maze::MazeConfig {
+            ir_format: maze::IrFormat::MessagePack,
+            ..Default::default()
+        },
+    );
+    let constraints = vec![ConstraintIR {
+        name: "ident".to_string(),
+        json_schema: None,
+        grammar: None,
+        regex_patterns: vec![RegexPattern {
+            pattern: "[a-z]+".to_string(),
+            flags: String::new(),
+        }],
+        token_masks: None,
+        priority: 1,
+        rich_context: None,
+        feasibility_score: 0.0,
+        is_feasible: true,
+        type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+    }];
+    let bytes = maze::IrFormat::MessagePack.encode(&constraints).unwrap();
+
+    unsafe {
+        let mut handle = 0;
+        assert_eq!(
+            constraint_register_encoded_ffi(
+                &orchestrator,
+                bytes.as_ptr(),
+                bytes.len(),
+                &mut handle
+            ),
+            FfiErrorCode::Success
+        );
+        assert_ne!(handle, 0);
+        assert_eq!(
+            constraint_release_ffi(&orchestrator, handle),
+            FfiErrorCode::Success
+        );
+
+        // JSON is not the configured format
+        let json = maze::IrFormat::Json.encode(&constraints).unwrap();
+        assert_eq!(
+            constraint_register_encoded_ffi(&orchestrator, json.as_ptr(), json.len(), &mut handle),
+            FfiErrorCode::InvalidInput
+        );
+        let message = take_last_error_ffi();
+        assert!(std::ffi::CStr::from_ptr(message)
+            .to_str()
+            .unwrap()
+            .contains("MessagePack"));
+        free_ffi_string(message);
+    }
+    assert_eq!(orchestrator.registered_count(), 0);
+}
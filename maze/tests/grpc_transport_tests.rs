@@ -0,0 +1,240 @@
+//! Integration tests for the Modal client's gRPC transport
+//!
+//! Runs a minimal hand-written `maze.inference.v1.Inference` server in-process.
+
+#![cfg(feature = "grpc")]
+
+use futures::StreamExt;
+use maze::grpc::{
+    ConstraintKindStatsMessage, GenerateChunk, GenerateRequest, GenerateResponse,
+    GenerationStatsMessage, GENERATE_PATH, GENERATE_STREAM_PATH, IDEMPOTENCY_KEY_METADATA,
+    SERVICE_NAME,
+};
+use maze::modal_client::{InferenceRequest, ModalClient, ModalConfig, Transport};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, BoxFuture, BoxStream, Context, Poll, Service};
+use tonic::server::{Grpc, NamedService, ServerStreamingService, UnaryService};
+use tonic::{Request, Response, Status};
+
+/// Requests seen by the server, with their idempotency keys
+type Seen = Arc<Mutex<Vec<(GenerateRequest, Option<String>)>>>;
+
+/// Records each request; a prompt of "fail" is rejected as unavailable
+#[derive(Clone, Default)]
+struct MockInference {
+    seen: Seen,
+}
+
+impl MockInference {
+    /// Record `request`, returning the message unless it should fail
+    fn record(&self, request: &Request<GenerateRequest>) -> Option<GenerateRequest> {
+        let key = request
+            .metadata()
+            .get(IDEMPOTENCY_KEY_METADATA)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let message = request.get_ref().clone();
+        self.seen.lock().unwrap().push((message.clone(), key));
+        (message.prompt != "fail").then_some(message)
+    }
+}
+
+impl UnaryService<GenerateRequest> for MockInference {
+    type Response = GenerateResponse;
+    type Future = BoxFuture<Response<GenerateResponse>, Status>;
+
+    fn call(&mut self, request: Request<GenerateRequest>) -> Self::Future {
+        let message = self.record(&request);
+        Box::pin(async move {
+            let message = message.ok_or_else(|| Status::unavailable("model is loading"))?;
+            Ok(Response::new(GenerateResponse {
+                generated_text: format!("{} }}", message.prompt),
+                tokens_generated: 2,
+                model: message.model,
+                stats: Some(GenerationStatsMessage {
+                    total_time_ms: 12,
+                    time_per_token_us: 6000,
+                    constraint_checks: 2,
+                    avg_constraint_check_us: 40,
+                    by_kind: [(
+                        "grammar".to_string(),
+                        ConstraintKindStatsMessage {
+                            checks: 2,
+                            total_us: 80,
+                        },
+                    )]
+                    .into(),
+                }),
+                logprobs: vec![],
+                confidence: None,
+                finish_reason: Some("stop_sequence".to_string()),
+            }))
+        })
+    }
+}
+
+impl ServerStreamingService<GenerateRequest> for MockInference {
+    type Response = GenerateChunk;
+    type ResponseStream = BoxStream<GenerateChunk>;
+    type Future = BoxFuture<Response<Self::ResponseStream>, Status>;
+
+    fn call(&mut self, request: Request<GenerateRequest>) -> Self::Future {
+        let message = self.record(&request);
+        Box::pin(async move {
+            message.ok_or_else(|| Status::unavailable("model is loading"))?;
+            let chunks = [
+                ("fn", false),
+                (" main", false),
+                ("() {}", false),
+                ("", true),
+            ]
+            .map(|(text, is_final)| GenerateChunk {
+                text: text.to_string(),
+                is_final,
+                finish_reason: None,
+                constraint_event: None,
+            });
+            let stream: Self::ResponseStream = Box::pin(futures::stream::iter(chunks.map(Ok)));
+            Ok(Response::new(stream))
+        })
+    }
+}
+
+impl NamedService for MockInference {
+    const NAME: &'static str = SERVICE_NAME;
+}
+
+impl Service<http::Request<BoxBody>> for MockInference {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move {
+            Ok(match request.uri().path() {
+                GENERATE_PATH => {
+                    Grpc::new(ProstCodec::<GenerateResponse, GenerateRequest>::default())
+                        .unary(service, request)
+                        .await
+                }
+                GENERATE_STREAM_PATH => {
+                    Grpc::new(ProstCodec::<GenerateChunk, GenerateRequest>::default())
+                        .server_streaming(service, request)
+                        .await
+                }
+                _ => Status::unimplemented("unknown method").into_http(),
+            })
+        })
+    }
+}
+
+/// Serve `service` on a local port and return a gRPC client config for it
+async fn serve(service: MockInference) -> ModalConfig {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+    );
+
+    ModalConfig::new(format!("http://{}", addr), "test-model".to_string())
+        .with_transport(Transport::Grpc)
+}
+
+fn request(prompt: &str) -> InferenceRequest {
+    InferenceRequest {
+        prompt: prompt.to_string(),
+        constraints: serde_json::json!({"type": "regex", "pattern": "[a-z ]+"}),
+        max_tokens: 16,
+        temperature: 0.0,
+        context: None,
+        seed: Some(3),
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    }
+}
+
+#[tokio::test]
+async fn test_grpc_generate_maps_request_and_response() {
+    let service = MockInference::default();
+    let client = ModalClient::new(serve(service.clone()).await).unwrap();
+
+    let request = request("fn main() {");
+    let response = client.generate_constrained(request.clone()).await.unwrap();
+
+    assert_eq!(response.generated_text, "fn main() { }");
+    assert_eq!(response.model, "test-model");
+    assert_eq!(response.tokens_generated, 2);
+    assert_eq!(response.stats.constraint_checks, 2);
+    assert_eq!(response.stats.by_kind["grammar"].avg_us(), 40);
+    assert_eq!(
+        response.finish_reason,
+        Some(maze::modal_client::FinishReason::StopSequence)
+    );
+    assert_eq!(response.attempts.len(), 1);
+
+    let seen = service.seen.lock().unwrap();
+    let (sent, key) = &seen[0];
+    assert_eq!(sent.model, "test-model");
+    assert_eq!(sent.seed, Some(3));
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&sent.constraints_json).unwrap(),
+        request.constraints
+    );
+    assert_eq!(key.as_deref(), Some(request.content_key().as_str()));
+}
+
+#[tokio::test]
+async fn test_grpc_generate_stream_uses_server_streaming() {
+    let client = ModalClient::new(serve(MockInference::default()).await).unwrap();
+
+    let chunks: Vec<_> = client
+        .generate_stream(request("fn main"))
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap())
+        .collect()
+        .await;
+
+    let text: String = chunks.iter().map(|c| c.text.as_str()).collect();
+    assert_eq!(text, "fn main() {}");
+    assert!(chunks.last().unwrap().is_final);
+    assert_eq!(chunks[1].token_index, 1);
+}
+
+#[tokio::test]
+async fn test_grpc_status_errors_are_reported_and_retried() {
+    let service = MockInference::default();
+    let mut config = serve(service.clone()).await;
+    config.max_retries = 2;
+    let client = ModalClient::new(config).unwrap();
+
+    let err = client
+        .generate_constrained(request("fail"))
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", err).contains("Unavailable"));
+
+    // Both attempts carried the same idempotency key
+    let seen = service.seen.lock().unwrap();
+    assert_eq!(seen.len(), 2);
+    assert_eq!(seen[0].1, seen[1].1);
+}
+
+#[test]
+fn test_grpc_transport_rejects_invalid_endpoint() {
+    let config = ModalConfig::new("not a url".to_string(), "test-model".to_string())
+        .with_transport(Transport::Grpc);
+    assert!(ModalClient::new(config).is_err());
+}
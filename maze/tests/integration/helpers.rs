@@ -29,6 +29,7 @@ pub fn simple_constraint(name: &str, priority: u32) -> ConstraintIR {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }
 }
 
@@ -48,6 +49,7 @@ pub fn regex_constraint(name: &str, pattern: &str, priority: u32) -> ConstraintI
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }
 }
 
@@ -74,6 +76,7 @@ pub fn json_schema_constraint(
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }
 }
 
@@ -98,6 +101,7 @@ pub fn grammar_constraint(
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }
 }
 
@@ -122,6 +126,7 @@ pub fn token_mask_constraint(
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     }
 }
 
@@ -137,6 +142,9 @@ pub fn test_request(
         max_tokens,
         temperature: 0.7,
         context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     }
 }
 
@@ -159,6 +167,9 @@ pub fn test_request_with_context(
             project_root: None,
             metadata: HashMap::new(),
         }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     }
 }
 
@@ -209,6 +209,7 @@ async fn test_ffi_boundary_data_integrity() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     // Convert to FFI and back
@@ -360,6 +361,9 @@ async fn test_provenance_tracking_completeness() {
                 m
             },
         }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     let response = orchestrator
@@ -447,6 +451,30 @@ async fn test_cache_lru_eviction() {
         enable_cache: true,
         cache_size_limit: 5,
         timeout_secs: 300,
+        lint_on_compile: false,
+        strict_provenance: false,
+        language_grammars: true,
+        context_overflow: maze::ContextOverflowPolicy::Reject,
+        truncation_order: maze::context_window::default_truncation_order(),
+        snippet_token_budget: 1024,
+        context_compression: maze::CompressionStrategy::None,
+        response_cache: maze::ResponseCacheConfig::default(),
+        redaction: maze::RedactionPolicy::default(),
+        max_concurrent_requests: 0,
+        queue_timeout_ms: None,
+        adaptive_concurrency: None,
+        constraint_format: maze::ConstraintFormat::default(),
+        ir_format: maze::IrFormat::default(),
+        tokenizer_path: None,
+        coalesce_requests: true,
+        output_guard: maze::OutputGuard::default(),
+        post_process: maze::PostProcessConfig::default(),
+        model_chain: maze::ModelChainConfig::default(),
+        stream_deadline: None,
+        runaway_limit: None,
+        format_policy: maze::FormatPolicy::default(),
+        include_constraint_events: false,
+        empty_constraints: maze::EmptyConstraintsPolicy::default(),
     };
 
     let orchestrator = MazeOrchestrator::with_config(modal_config, maze_config)
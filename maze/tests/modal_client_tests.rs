@@ -2,8 +2,17 @@
 //!
 //! Tests HTTP communication with Modal inference service
 
-use maze::modal_client::{InferenceRequest, InferenceResponse, ModalClient, ModalConfig};
+use maze::modal_client::{
+    AttemptStatus, AuthScheme, ConstraintsUnsupported, FinishReason, InferenceRequest,
+    InferenceResponse, ModalClient, ModalConfig, RequestTimedOut, ResponseTooLarge,
+    IDEMPOTENCY_KEY_HEADER, REQUEST_ID_HEADER,
+};
+use maze::{
+    CircuitBreakerConfig, HealthCheckConfig, HealthPayloadCheck, HealthStatus, MockClock,
+    RedactionPolicy, ReplicaEndpoint,
+};
 use mockito::Server;
+use std::time::Duration;
 
 #[tokio::test]
 async fn test_modal_client_health_check() {
@@ -22,7 +31,7 @@ async fn test_modal_client_health_check() {
     let result = client.health_check().await;
 
     assert!(result.is_ok());
-    assert!(result.unwrap());
+    assert!(result.unwrap().is_ready());
 }
 
 #[tokio::test]
@@ -42,7 +51,82 @@ async fn test_modal_client_health_check_failure() {
     let result = client.health_check().await;
 
     assert!(result.is_ok());
-    assert!(!result.unwrap());
+    assert!(!result.unwrap().is_ready());
+}
+
+#[tokio::test]
+async fn test_health_check_accepts_configured_statuses() {
+    let mut server = Server::new_async().await;
+    let _m = server
+        .mock("GET", "/health")
+        .with_status(204)
+        .create_async()
+        .await;
+
+    let any_2xx =
+        ModalClient::new(ModalConfig::new(server.url(), "test-model".to_string())).unwrap();
+    assert!(matches!(
+        any_2xx.health_check().await.unwrap(),
+        HealthStatus::Healthy { status: 204, .. }
+    ));
+
+    let only_200 = ModalConfig::new(server.url(), "test-model".to_string()).with_health_check(
+        HealthCheckConfig {
+            healthy_statuses: vec![200],
+            ..Default::default()
+        },
+    );
+    let client = ModalClient::new(only_200).unwrap();
+    assert!(matches!(
+        client.health_check().await.unwrap(),
+        HealthStatus::Unhealthy { status: 204, .. }
+    ));
+}
+
+#[tokio::test]
+async fn test_health_check_reads_json_payload() {
+    let mut server = Server::new_async().await;
+    let not_ready = server
+        .mock("GET", "/health")
+        .with_status(200)
+        .with_body(r#"{"ready": false}"#)
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string()).with_health_check(
+        HealthCheckConfig {
+            payload: Some(HealthPayloadCheck::flag("ready")),
+            ..Default::default()
+        },
+    );
+    let client = ModalClient::new(config).unwrap();
+    let status = client.health_check().await.unwrap();
+    assert_eq!(
+        status,
+        HealthStatus::Unhealthy {
+            status: 200,
+            body: r#"{"ready": false}"#.to_string()
+        }
+    );
+    not_ready.remove_async().await;
+
+    let _degraded = server
+        .mock("GET", "/health")
+        .with_status(200)
+        .with_body(r#"{"status": "degraded"}"#)
+        .create_async()
+        .await;
+    let config = ModalConfig::new(server.url(), "test-model".to_string()).with_health_check(
+        HealthCheckConfig {
+            payload: Some(HealthPayloadCheck::values("status", &["ok"], &["degraded"])),
+            ..Default::default()
+        },
+    );
+    let client = ModalClient::new(config).unwrap();
+    let status = client.health_check().await.unwrap();
+    assert!(matches!(status, HealthStatus::Degraded { .. }));
+    assert!(status.is_ready());
+    assert_eq!(status.body(), Some(r#"{"status": "degraded"}"#));
 }
 
 #[tokio::test]
@@ -63,9 +147,95 @@ async fn test_modal_client_list_models() {
     let models = client.list_models().await.unwrap();
 
     assert_eq!(models.len(), 3);
-    assert_eq!(models[0], "model1");
-    assert_eq!(models[1], "model2");
-    assert_eq!(models[2], "model3");
+    assert_eq!(models[0].name, "model1");
+    assert_eq!(models[1].name, "model2");
+    assert_eq!(models[2].name, "model3");
+
+    // Name-only payloads carry no limits
+    assert_eq!(models[0].context_window, None);
+    assert!(models[0].supports_grammar);
+    assert!(!models[0].supports_diffusion);
+}
+
+#[tokio::test]
+async fn test_modal_client_list_models_with_capabilities_is_cached() {
+    let mut server = Server::new_async().await;
+
+    let m = server
+        .mock("GET", "/models")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            serde_json::json!([
+                {
+                    "name": "test-model",
+                    "context_window": 8192,
+                    "max_output_tokens": 2048,
+                    "supports_grammar": true,
+                    "supports_diffusion": true
+                },
+                "legacy-model"
+            ])
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    let client = ModalClient::new(config).unwrap();
+
+    let models = client.list_models().await.unwrap();
+    assert_eq!(models[0].context_window, Some(8192));
+    assert!(models[0].supports_diffusion);
+    assert_eq!(models[1].name, "legacy-model");
+    assert_eq!(models[1].max_output_tokens, None);
+
+    // Served from the cache: the endpoint is hit only once
+    let info = client.model_info().await.unwrap();
+    assert_eq!(info.max_output_tokens, Some(2048));
+
+    let request = InferenceRequest {
+        prompt: "test".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 4096,
+        temperature: 0.5,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+    assert!(client.validate_request(&request).await.is_err());
+
+    m.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_models_cache_expires_after_ttl() {
+    let mut server = Server::new_async().await;
+    let m = server
+        .mock("GET", "/models")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(serde_json::json!(["test-model"]).to_string())
+        .expect(2)
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    let ttl = Duration::from_secs(config.models_cache_ttl_secs);
+    let clock = MockClock::new();
+    let client = ModalClient::new(config).unwrap().with_clock(clock.clone());
+
+    client.list_models().await.unwrap();
+    clock.advance(ttl - Duration::from_secs(1));
+    client.list_models().await.unwrap();
+    clock.advance(Duration::from_secs(1));
+    client.list_models().await.unwrap();
+
+    m.assert_async().await;
 }
 
 #[tokio::test]
@@ -102,6 +272,11 @@ async fn test_modal_client_generate_success() {
         max_tokens: 100,
         temperature: 0.7,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let response = client.generate_constrained(request).await.unwrap();
@@ -113,6 +288,345 @@ async fn test_modal_client_generate_success() {
     assert_eq!(response.stats.constraint_checks, 5);
 }
 
+#[tokio::test]
+async fn test_modal_client_traces_each_call_redacted() {
+    let mut server = Server::new_async().await;
+    let response_body = serde_json::json!({
+        "generated_text": "let token = \"hunter2\";",
+        "tokens_generated": 5,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 10,
+            "time_per_token_us": 2000,
+            "constraint_checks": 0,
+            "avg_constraint_check_us": 0
+        }
+    });
+    let _ok = server
+        .mock("POST", "/generate")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(response_body.to_string())
+        .expect(1)
+        .create_async()
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trace.jsonl");
+    let mut config = ModalConfig::new(server.url(), "test-model".to_string())
+        .with_redaction(RedactionPolicy::Full);
+    config.enable_retry = false;
+    let client = ModalClient::new(config)
+        .unwrap()
+        .with_trace_sink(maze::JsonlTraceSink::create(&path).unwrap());
+
+    let request = InferenceRequest {
+        prompt: "store hunter2".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 16,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+    client.generate_constrained(request.clone()).await.unwrap();
+    server.reset();
+    server
+        .mock("POST", "/generate")
+        .with_status(503)
+        .create_async()
+        .await;
+    client.generate_constrained(request).await.unwrap_err();
+    // Dropping the sink flushes it
+    drop(client);
+
+    let records = maze::read_trace(&path).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].model, "test-model");
+    assert_eq!(records[0].request.max_tokens, 16);
+    assert_eq!(records[0].response.as_ref().unwrap().tokens_generated, 5);
+    assert!(records[1].response.is_none());
+    assert!(records[1].error.is_some());
+    let trace = std::fs::read_to_string(&path).unwrap();
+    assert!(!trace.contains("hunter2"));
+}
+
+#[tokio::test]
+async fn test_modal_client_parses_finish_reason() {
+    let mut server = Server::new_async().await;
+
+    let response_body = serde_json::json!({
+        "generated_text": "fn main() {",
+        "tokens_generated": 4,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 10,
+            "time_per_token_us": 2500,
+            "constraint_checks": 0,
+            "avg_constraint_check_us": 0
+        },
+        "finish_reason": "length"
+    });
+    let _m = server
+        .mock("POST", "/generate")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(response_body.to_string())
+        .create_async()
+        .await;
+
+    let client =
+        ModalClient::new(ModalConfig::new(server.url(), "test-model".to_string())).unwrap();
+    let request = InferenceRequest {
+        prompt: "implement main".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 100,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+
+    let response = client.generate_constrained(request).await.unwrap();
+    assert_eq!(response.finish_reason, Some(FinishReason::MaxTokens));
+    assert_eq!(
+        response.resolved_finish_reason(100),
+        FinishReason::MaxTokens
+    );
+    assert!(response.resolved_finish_reason(100).is_incomplete());
+}
+
+#[test]
+fn test_finish_reason_parsing_and_inference() {
+    assert_eq!(FinishReason::parse("stop"), FinishReason::Stop);
+    assert_eq!(
+        FinishReason::parse("stop_sequence"),
+        FinishReason::StopSequence
+    );
+    assert_eq!(FinishReason::parse("LENGTH"), FinishReason::MaxTokens);
+    assert_eq!(FinishReason::parse("grammar"), FinishReason::Constraint);
+    assert_eq!(FinishReason::parse("something_new"), FinishReason::Stop);
+
+    // Without a reported reason, reaching the limit counts as MaxTokens
+    let mut response: InferenceResponse = serde_json::from_value(serde_json::json!({
+        "generated_text": "a b c",
+        "tokens_generated": 3,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 1,
+            "time_per_token_us": 1,
+            "constraint_checks": 0,
+            "avg_constraint_check_us": 0
+        }
+    }))
+    .unwrap();
+    assert_eq!(response.finish_reason, None);
+    assert_eq!(response.resolved_finish_reason(3), FinishReason::MaxTokens);
+    assert_eq!(response.resolved_finish_reason(4), FinishReason::Stop);
+
+    response.finish_reason = Some(FinishReason::StopSequence);
+    assert_eq!(
+        response.resolved_finish_reason(3),
+        FinishReason::StopSequence
+    );
+    assert_eq!(
+        serde_json::to_value(FinishReason::MaxTokens).unwrap(),
+        "max_tokens"
+    );
+}
+
+#[tokio::test]
+async fn test_modal_client_parses_logprobs() {
+    let mut server = Server::new_async().await;
+
+    let response_body = serde_json::json!({
+        "generated_text": "ab",
+        "tokens_generated": 2,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 10,
+            "time_per_token_us": 5000,
+            "constraint_checks": 2,
+            "avg_constraint_check_us": 50
+        },
+        "logprobs": [
+            {"token": "a", "logprob": -0.1, "top_logprobs": [{"token": "b", "logprob": -2.5}]},
+            {"token": "b", "logprob": -0.3}
+        ]
+    });
+
+    let m = server
+        .mock("POST", "/generate")
+        .match_body(mockito::Matcher::PartialJson(
+            serde_json::json!({"logprobs": true}),
+        ))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(response_body.to_string())
+        .create_async()
+        .await;
+
+    let client =
+        ModalClient::new(ModalConfig::new(server.url(), "test-model".to_string())).unwrap();
+    let request = InferenceRequest {
+        prompt: "letters".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 2,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: true,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+
+    let response = client.generate_constrained(request).await.unwrap();
+    m.assert_async().await;
+
+    let logprobs = response.logprobs.as_ref().unwrap();
+    assert_eq!(logprobs.len(), 2);
+    assert_eq!(logprobs[0].top_logprobs[0].token, "b");
+    assert!(logprobs[1].top_logprobs.is_empty());
+
+    // Geometric mean probability of the two tokens
+    assert!((response.confidence().unwrap() - (-0.2f32).exp()).abs() < 1e-6);
+}
+
+fn gzip(body: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(body).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[tokio::test]
+async fn test_modal_client_decodes_gzip_response() {
+    let mut server = Server::new_async().await;
+
+    let response_body = serde_json::json!({
+        "generated_text": "fn compressed() {}",
+        "tokens_generated": 4,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 10,
+            "time_per_token_us": 2500,
+            "constraint_checks": 4,
+            "avg_constraint_check_us": 50
+        }
+    });
+
+    let m = server
+        .mock("POST", "/generate")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_header("content-encoding", "gzip")
+        .with_body(gzip(response_body.to_string().as_bytes()))
+        .expect(2)
+        .create_async()
+        .await;
+
+    let request = InferenceRequest {
+        prompt: "compress".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 4,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+
+    let client =
+        ModalClient::new(ModalConfig::new(server.url(), "test-model".to_string())).unwrap();
+    let response = client.generate_constrained(request.clone()).await.unwrap();
+    assert_eq!(response.generated_text, "fn compressed() {}");
+
+    // With decoding off the compressed body reaches the JSON parser and fails
+    let mut config =
+        ModalConfig::new(server.url(), "test-model".to_string()).with_decompression(false);
+    config.enable_retry = false;
+    let client = ModalClient::new(config).unwrap();
+    assert!(client.generate_constrained(request).await.is_err());
+
+    m.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_chunked_response_is_read_up_to_the_size_cap() {
+    let mut server = Server::new_async().await;
+
+    let body = serde_json::json!({
+        "generated_text": "x".repeat(256 * 1024),
+        "tokens_generated": 65536,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 10,
+            "time_per_token_us": 1,
+            "constraint_checks": 0,
+            "avg_constraint_check_us": 0
+        }
+    })
+    .to_string();
+    let chunks = body.clone().into_bytes();
+
+    // Chunked, with no Content-Length; served to both clients below
+    let m = server
+        .mock("POST", "/generate")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_chunked_body(move |w| {
+            for chunk in chunks.chunks(8 * 1024) {
+                w.write_all(chunk)?;
+            }
+            Ok(())
+        })
+        .expect(2)
+        .create_async()
+        .await;
+
+    let request = InferenceRequest {
+        prompt: "big".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 65536,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string())
+        .with_max_response_bytes(Some(body.len()));
+    let client = ModalClient::new(config).unwrap();
+    let response = client.generate_constrained(request.clone()).await.unwrap();
+    assert_eq!(response.generated_text.len(), 256 * 1024);
+
+    // One byte over the cap fails, and is not retried
+    let config = ModalConfig::new(server.url(), "test-model".to_string())
+        .with_max_response_bytes(Some(body.len() - 1));
+    let client = ModalClient::new(config).unwrap();
+    let err = client.generate_constrained(request).await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<ResponseTooLarge>(),
+        Some(&ResponseTooLarge {
+            limit: body.len() - 1
+        })
+    );
+
+    m.assert_async().await;
+}
+
 #[tokio::test]
 async fn test_modal_client_generate_with_api_key() {
     let mut server = Server::new_async().await;
@@ -149,12 +663,191 @@ async fn test_modal_client_generate_with_api_key() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let response = client.generate_constrained(request).await.unwrap();
     assert_eq!(response.generated_text, "result");
 }
 
+/// Assert that generation, health checks, and model listing all send
+/// `header: value` under `config`
+async fn assert_every_request_authenticated(
+    server: &mut mockito::ServerGuard,
+    config: ModalConfig,
+    header: &str,
+    value: &str,
+) {
+    let generate = server
+        .mock("POST", "/generate")
+        .match_header(header, value)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            serde_json::json!({
+                "generated_text": "result",
+                "tokens_generated": 1,
+                "model": "test-model",
+                "stats": {
+                    "total_time_ms": 1,
+                    "time_per_token_us": 1,
+                    "constraint_checks": 0,
+                    "avg_constraint_check_us": 0
+                }
+            })
+            .to_string(),
+        )
+        .expect(1)
+        .create_async()
+        .await;
+    let health = server
+        .mock("GET", "/health")
+        .match_header(header, value)
+        .with_status(200)
+        .expect(1)
+        .create_async()
+        .await;
+    let models = server
+        .mock("GET", "/models")
+        .match_header(header, value)
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(r#"["test-model"]"#)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let client = ModalClient::new(config).unwrap();
+    let request = InferenceRequest {
+        prompt: "test".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 10,
+        temperature: 0.5,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+    client.generate_constrained(request).await.unwrap();
+    assert!(client.health_check().await.unwrap().is_ready());
+    client.list_models().await.unwrap();
+
+    generate.assert_async().await;
+    health.assert_async().await;
+    models.assert_async().await;
+}
+
+#[tokio::test]
+async fn test_bearer_auth_applies_to_every_request() {
+    let mut server = Server::new_async().await;
+    let config =
+        ModalConfig::new(server.url(), "test-model".to_string()).with_api_key("secret".to_string());
+    assert_every_request_authenticated(&mut server, config, "Authorization", "Bearer secret").await;
+}
+
+#[tokio::test]
+async fn test_every_request_sends_user_agent() {
+    let mut server = Server::new_async().await;
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    assert!(maze::modal_client::USER_AGENT.starts_with("maze/"));
+    assert_every_request_authenticated(
+        &mut server,
+        config,
+        "User-Agent",
+        maze::modal_client::USER_AGENT,
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_configured_user_agent_replaces_default() {
+    let mut server = Server::new_async().await;
+    let config = ModalConfig::new(server.url(), "test-model".to_string())
+        .with_user_agent("editor-plugin/2.1");
+    assert_every_request_authenticated(&mut server, config, "User-Agent", "editor-plugin/2.1")
+        .await;
+}
+
+#[tokio::test]
+async fn test_request_id_is_sent_on_every_retry_and_reported_in_errors() {
+    let mut server = Server::new_async().await;
+    let uuid = "[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[0-9a-f]{4}-[0-9a-f]{12}";
+    let failing = server
+        .mock("POST", "/generate")
+        .match_header(
+            REQUEST_ID_HEADER,
+            mockito::Matcher::Regex(format!("^{}$", uuid)),
+        )
+        .with_status(503)
+        .with_body("overloaded")
+        .expect(2)
+        .create_async()
+        .await;
+
+    let mut config = ModalConfig::new(server.url(), "test-model".to_string());
+    config.max_retries = 2;
+    let client = ModalClient::new(config).unwrap();
+    let err = client
+        .generate_constrained(replica_request())
+        .await
+        .unwrap_err();
+
+    failing.assert_async().await;
+    let message = format!("{:#}", err);
+    let pattern = regex::Regex::new(&format!(r"\(request {}\)", uuid)).unwrap();
+    assert!(pattern.is_match(&message), "{}", message);
+}
+
+#[tokio::test]
+async fn test_api_key_header_auth() {
+    let mut server = Server::new_async().await;
+    let config = ModalConfig::new(server.url(), "test-model".to_string())
+        .with_api_key("secret".to_string())
+        .with_auth_scheme(AuthScheme::ApiKeyHeader {
+            name: "x-api-key".to_string(),
+        });
+    assert_every_request_authenticated(&mut server, config, "x-api-key", "secret").await;
+}
+
+#[tokio::test]
+async fn test_basic_auth() {
+    let mut server = Server::new_async().await;
+    let config = ModalConfig::new(server.url(), "test-model".to_string()).with_auth_scheme(
+        AuthScheme::Basic {
+            user: "aladdin".to_string(),
+            pass: "opensesame".to_string(),
+        },
+    );
+    assert_every_request_authenticated(
+        &mut server,
+        config,
+        "Authorization",
+        "Basic YWxhZGRpbjpvcGVuc2VzYW1l",
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_custom_header_auth() {
+    let mut server = Server::new_async().await;
+    let config = ModalConfig::new(server.url(), "test-model".to_string())
+        .with_api_key("unused".to_string())
+        .with_auth_scheme(AuthScheme::Custom(
+            [("X-Gateway-Token".to_string(), "tok-123".to_string())].into(),
+        ));
+    assert!(config
+        .auth_headers()
+        .iter()
+        .all(|(name, _)| name != "Authorization"));
+    assert_every_request_authenticated(&mut server, config, "x-gateway-token", "tok-123").await;
+}
+
 #[tokio::test]
 async fn test_modal_client_generate_failure_401() {
     let mut server = Server::new_async().await;
@@ -176,6 +869,11 @@ async fn test_modal_client_generate_failure_401() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let response = client.generate_constrained(request).await;
@@ -185,14 +883,143 @@ async fn test_modal_client_generate_failure_401() {
     // The important thing is that the client properly handles HTTP errors
 }
 
+#[tokio::test]
+async fn test_modal_client_redacts_error_body() {
+    let mut server = Server::new_async().await;
+
+    // Error bodies may echo the request, secrets included
+    let _m = server
+        .mock("POST", "/generate")
+        .with_status(401)
+        .with_body("Unauthorized for prompt: token = sk-secret")
+        .create_async()
+        .await;
+
+    let mut config = ModalConfig::new(server.url(), "test-model".to_string())
+        .with_redaction(RedactionPolicy::Full);
+    config.enable_retry = false;
+    let client = ModalClient::new(config).unwrap();
+
+    let request = InferenceRequest {
+        prompt: "token = sk-secret".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 10,
+        temperature: 0.5,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+
+    let error = format!(
+        "{:#}",
+        client.generate_constrained(request).await.unwrap_err()
+    );
+    assert!(error.contains("401"));
+    assert!(error.contains("[redacted]"));
+    assert!(!error.contains("sk-secret"));
+}
+
+#[tokio::test]
+async fn test_constraints_unsupported_is_typed_and_not_retried() {
+    let mut server = Server::new_async().await;
+
+    let m = server
+        .mock("POST", "/generate")
+        .with_status(422)
+        .with_body(r#"{"error": "constraints_unsupported", "detail": "no grammar backend"}"#)
+        .expect(1)
+        .create_async()
+        .await;
+
+    let client =
+        ModalClient::new(ModalConfig::new(server.url(), "plain-model".to_string())).unwrap();
+    let request = InferenceRequest {
+        prompt: "test".to_string(),
+        constraints: serde_json::json!({"constraints": [{"type": "regex", "pattern": "a+"}]}),
+        max_tokens: 10,
+        temperature: 0.5,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+
+    let error = client.generate_constrained(request).await.unwrap_err();
+    let unsupported = error.downcast_ref::<ConstraintsUnsupported>().unwrap();
+    assert_eq!(unsupported.model, "plain-model");
+    assert!(unsupported.reason.contains("422"));
+    m.assert_async().await;
+}
+
 #[tokio::test]
 async fn test_modal_client_generate_failure_500() {
     let mut server = Server::new_async().await;
 
     let _m = server
         .mock("POST", "/generate")
-        .with_status(500)
-        .with_body("Internal Server Error")
+        .with_status(500)
+        .with_body("Internal Server Error")
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+
+    let client = ModalClient::new(config).unwrap();
+
+    let request = InferenceRequest {
+        prompt: "test".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 10,
+        temperature: 0.5,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+
+    let response = client.generate_constrained(request).await;
+    assert!(response.is_err());
+}
+
+#[tokio::test]
+async fn test_modal_client_retry_on_failure() {
+    let mut server = Server::new_async().await;
+
+    // First attempt fails
+    let m1 = server
+        .mock("POST", "/generate")
+        .with_status(503)
+        .with_body("Service Unavailable")
+        .expect(1)
+        .create_async()
+        .await;
+
+    // Second attempt succeeds
+    let response_body = serde_json::json!({
+        "generated_text": "success",
+        "tokens_generated": 3,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 30,
+            "time_per_token_us": 10000,
+            "constraint_checks": 1,
+            "avg_constraint_check_us": 30
+        }
+    });
+
+    let m2 = server
+        .mock("POST", "/generate")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(response_body.to_string())
+        .expect(1)
         .create_async()
         .await;
 
@@ -206,26 +1033,57 @@ async fn test_modal_client_generate_failure_500() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
-    let response = client.generate_constrained(request).await;
-    assert!(response.is_err());
+    let response = client.generate_constrained(request).await.unwrap();
+    assert_eq!(response.generated_text, "success");
+
+    // Both attempts are reported, with the backoff after the failed one
+    assert_eq!(response.attempts.len(), 2);
+    assert_eq!(response.attempts[0].attempt, 1);
+    assert_eq!(response.attempts[0].status, AttemptStatus::Failed);
+    assert_eq!(response.attempts[0].backoff_ms, 100);
+    assert_eq!(response.attempts[1].status, AttemptStatus::Success);
+    assert_eq!(response.attempts[1].backoff_ms, 0);
+
+    m1.assert_async().await;
+    m2.assert_async().await;
 }
 
 #[tokio::test]
-async fn test_modal_client_retry_on_failure() {
+async fn test_modal_client_retries_reuse_idempotency_key() {
     let mut server = Server::new_async().await;
 
-    // First attempt fails
+    let request = InferenceRequest {
+        prompt: "test".to_string(),
+        constraints: serde_json::json!({"regex": "[a-z]+"}),
+        max_tokens: 10,
+        temperature: 0.5,
+        context: None,
+        seed: Some(7),
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+    let key = request.call_key();
+    assert_eq!(key, request.content_key());
+
+    // Both attempts must carry the same derived key
     let m1 = server
         .mock("POST", "/generate")
+        .match_header(IDEMPOTENCY_KEY_HEADER, key.as_str())
         .with_status(503)
         .with_body("Service Unavailable")
         .expect(1)
         .create_async()
         .await;
 
-    // Second attempt succeeds
     let response_body = serde_json::json!({
         "generated_text": "success",
         "tokens_generated": 3,
@@ -237,9 +1095,9 @@ async fn test_modal_client_retry_on_failure() {
             "avg_constraint_check_us": 30
         }
     });
-
     let m2 = server
         .mock("POST", "/generate")
+        .match_header(IDEMPOTENCY_KEY_HEADER, key.as_str())
         .with_status(200)
         .with_header("content-type", "application/json")
         .with_body(response_body.to_string())
@@ -247,23 +1105,35 @@ async fn test_modal_client_retry_on_failure() {
         .create_async()
         .await;
 
-    let config = ModalConfig::new(server.url(), "test-model".to_string());
-
-    let client = ModalClient::new(config).unwrap();
-
-    let request = InferenceRequest {
-        prompt: "test".to_string(),
-        constraints: serde_json::json!({}),
-        max_tokens: 10,
-        temperature: 0.5,
-        context: None,
-    };
-
-    let response = client.generate_constrained(request).await.unwrap();
+    let client =
+        ModalClient::new(ModalConfig::new(server.url(), "test-model".to_string())).unwrap();
+    let response = client.generate_constrained(request.clone()).await.unwrap();
     assert_eq!(response.generated_text, "success");
 
     m1.assert_async().await;
     m2.assert_async().await;
+
+    // The key depends only on content, and an explicit key is left alone
+    assert_eq!(request.clone().content_key(), key);
+    let other = InferenceRequest {
+        prompt: "other".to_string(),
+        ..request.clone()
+    };
+    assert_ne!(other.content_key(), key);
+    let explicit = InferenceRequest {
+        idempotency_key: Some("caller-key".to_string()),
+        ..request.clone()
+    };
+    assert_eq!(explicit.content_key(), key);
+
+    // Unseeded calls sample afresh, so each gets its own key
+    let unseeded = InferenceRequest {
+        seed: None,
+        ..request
+    };
+    let first = unseeded.call_key();
+    assert!(first.starts_with(&unseeded.content_key()));
+    assert_ne!(unseeded.call_key(), first);
 }
 
 #[tokio::test]
@@ -289,6 +1159,11 @@ async fn test_modal_client_retry_exhausted() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let response = client.generate_constrained(request).await;
@@ -321,6 +1196,11 @@ fn test_inference_request_serialization() {
         max_tokens: 100,
         temperature: 0.7,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let json = serde_json::to_string(&request).unwrap();
@@ -377,6 +1257,11 @@ async fn test_error_connection_timeout() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -433,6 +1318,11 @@ async fn test_error_read_timeout_slow_server() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -466,12 +1356,456 @@ async fn test_error_long_running_inference_timeout() {
         max_tokens: 1000,
         temperature: 0.7,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
     assert!(result.is_err());
 }
 
+#[tokio::test]
+async fn test_request_timeout_overrides_config_timeout() {
+    let mut server = Server::new_async().await;
+    let _m = server
+        .mock("POST", "/generate")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_chunked_body(|w| {
+            std::thread::sleep(std::time::Duration::from_millis(1500));
+            w.write_all(generate_body("fn slow() {}").as_bytes())
+        })
+        .expect_at_least(1)
+        .create_async()
+        .await;
+
+    // The 1s client timeout would cut this request off; its own does not
+    let client =
+        ModalClient::new(ModalConfig::new(server.url(), "test-model".to_string()).with_timeout(1))
+            .unwrap();
+    let request = |timeout_ms| InferenceRequest {
+        timeout_ms: Some(timeout_ms),
+        ..replica_request()
+    };
+    let response = client.generate_constrained(request(5000)).await.unwrap();
+    assert_eq!(response.generated_text, "fn slow() {}");
+
+    // An interactive deadline gives up well before the configured timeout
+    let start = std::time::Instant::now();
+    let err = client.generate_constrained(request(200)).await.unwrap_err();
+    assert!(start.elapsed() < std::time::Duration::from_millis(900));
+    let timed_out = err.downcast_ref::<RequestTimedOut>().unwrap();
+    assert_eq!(timed_out.timeout_ms, 200);
+    assert_eq!(timed_out.attempts, 1);
+}
+
+#[tokio::test]
+async fn test_stream_stall_aborts_before_request_timeout() {
+    use futures::StreamExt;
+
+    let mut server = Server::new_async().await;
+
+    // One token, then the connection stays open without producing more
+    let _m = server
+        .mock("POST", "/generate/stream")
+        .with_status(200)
+        .with_header("content-type", "text/event-stream")
+        .with_chunked_body(|w| {
+            w.write_all(b"data: {\"token\": \"fn\"}\n\n")?;
+            w.flush()?;
+            std::thread::sleep(std::time::Duration::from_secs(4));
+            w.write_all(b"data: [DONE]\n\n")
+        })
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string())
+        .with_timeout(30)
+        .with_stream_idle_timeout(Some(1));
+    let client = ModalClient::new(config).unwrap();
+
+    let request = InferenceRequest {
+        prompt: "fn main".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 10,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+
+    let started = std::time::Instant::now();
+    let items: Vec<_> = client
+        .generate_stream(request)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    assert!(started.elapsed() < std::time::Duration::from_secs(3));
+
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].as_ref().unwrap().text, "fn");
+    let err = items[1].as_ref().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<maze::StreamStalled>(),
+        Some(&maze::StreamStalled {
+            idle_secs: 1,
+            chunks_received: 1
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_stream_reassembles_events_split_across_chunks() {
+    use futures::StreamExt;
+
+    let mut server = Server::new_async().await;
+
+    // Chunks break mid-JSON, mid-character, and between an event and its
+    // blank line; one event in the middle is malformed
+    let _m = server
+        .mock("POST", "/generate/stream")
+        .with_status(200)
+        .with_header("content-type", "text/event-stream")
+        .with_chunked_body(|w| {
+            let euro = "€".as_bytes();
+            let chunks: [&[u8]; 7] = [
+                b"data: {\"tok",
+                b"en\": \"fn\"}\n",
+                b"\ndata: {\"token\": \" ",
+                &euro[..1],
+                &euro[1..],
+                b"\"}\n\ndata: {\"token\" oops}\n\n",
+                b"data: {\"token\": \"()\"}\n\ndata: [DONE]\n\n",
+            ];
+            for chunk in chunks {
+                w.write_all(chunk)?;
+                w.flush()?;
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+            Ok(())
+        })
+        .create_async()
+        .await;
+
+    let client =
+        ModalClient::new(ModalConfig::new(server.url(), "test-model".to_string())).unwrap();
+    let request = InferenceRequest {
+        prompt: "fn main".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 10,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+
+    let items: Vec<_> = client
+        .generate_stream(request)
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    assert_eq!(items.len(), 5);
+    let texts: Vec<&str> = items
+        .iter()
+        .filter_map(|item| item.as_ref().ok())
+        .map(|chunk| chunk.text.as_str())
+        .collect();
+    assert_eq!(texts, vec!["fn", " €", "()", ""]);
+    assert!(items[4].as_ref().unwrap().is_final);
+    assert_eq!(items[3].as_ref().unwrap().token_index, 2);
+
+    let err = items[2].as_ref().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<maze::StreamEventError>(),
+        Some(maze::StreamEventError::Malformed { event: 2, .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_streamed_generation_skips_malformed_event() {
+    let mut server = Server::new_async().await;
+    let _m = server
+        .mock("POST", "/generate/stream")
+        .with_status(200)
+        .with_header("content-type", "text/event-stream")
+        .with_body(concat!(
+            "data: {\"token\": \"let x\"}\n\n",
+            "data: {\"token\" oops}\n\n",
+            "data: {\"token\": \" = 1;\"}\n\n",
+            "data: [DONE]\n\n",
+        ))
+        .create_async()
+        .await;
+
+    let client =
+        ModalClient::new(ModalConfig::new(server.url(), "test-model".to_string())).unwrap();
+    let orchestrator = maze::MazeOrchestrator::with_client(client, Default::default());
+    let response = orchestrator
+        .generate_streamed(maze::GenerationRequest {
+            prompt: "Declare x".to_string(),
+            constraints_ir: vec![],
+            max_tokens: 10,
+            temperature: 0.0,
+            context: None,
+            seed: None,
+            timeout_ms: None,
+            history: Vec::new(),
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(response.code, "let x = 1;");
+    assert_eq!(response.metadata.finish_reason, FinishReason::Stop);
+    assert_eq!(response.validation.metadata["malformed_stream_events"], 1);
+}
+
+#[tokio::test]
+async fn test_stream_interleaves_requested_constraint_events() {
+    use futures::StreamExt;
+
+    let mut server = Server::new_async().await;
+    let body = concat!(
+        "data: {\"token\": \"let\"}\n\n",
+        "data: {\"constraint_event\": {\"token\": \"var\", \"position\": 1, \"constraint\": \"style\"}}\n\n",
+        "data: {\"token\": \" x\"}\n\n",
+        "data: [DONE]\n\n",
+    );
+    let requested = server
+        .mock("POST", "/generate/stream")
+        .match_body(mockito::Matcher::PartialJson(
+            serde_json::json!({"constraint_events": true}),
+        ))
+        .with_status(200)
+        .with_header("content-type", "text/event-stream")
+        .with_body(body)
+        .create_async()
+        .await;
+    let _unrequested = server
+        .mock("POST", "/generate/stream")
+        .match_body(mockito::Matcher::PartialJson(
+            serde_json::json!({"constraint_events": false}),
+        ))
+        .with_status(200)
+        .with_header("content-type", "text/event-stream")
+        .with_body(body)
+        .create_async()
+        .await;
+
+    let client =
+        ModalClient::new(ModalConfig::new(server.url(), "test-model".to_string())).unwrap();
+    let request = |include_constraint_events| InferenceRequest {
+        prompt: "let".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 10,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events,
+    };
+
+    let chunks: Vec<_> = client
+        .generate_stream(request(true))
+        .await
+        .unwrap()
+        .map(|item| item.unwrap())
+        .collect()
+        .await;
+    let event = chunks[1].constraint_event.as_ref().unwrap();
+    assert_eq!(
+        event.to_string(),
+        "masked token \"var\" at position 1 because of constraint style"
+    );
+    assert!(chunks[1].text.is_empty());
+    let texts: Vec<&str> = chunks.iter().map(|c| c.text.as_str()).collect();
+    assert_eq!(texts, vec!["let", "", " x", ""]);
+    // Event chunks do not take a token index
+    assert_eq!(chunks[2].token_index, 1);
+    requested.assert_async().await;
+
+    // Events a backend sends anyway are dropped when not requested
+    let chunks: Vec<_> = client
+        .generate_stream(request(false))
+        .await
+        .unwrap()
+        .map(|item| item.unwrap())
+        .collect()
+        .await;
+    assert!(chunks.iter().all(|c| c.constraint_event.is_none()));
+    assert_eq!(chunks.len(), 3);
+}
+
+#[tokio::test]
+async fn test_stream_reassembles_identifier_sent_byte_by_byte() {
+    use futures::StreamExt;
+
+    let mut server = Server::new_async().await;
+
+    // Newline-delimited JSON, sent one byte per chunk, so every multi-byte
+    // character arrives in pieces
+    let _m = server
+        .mock("POST", "/generate/stream")
+        .with_status(200)
+        .with_header("content-type", "application/x-ndjson")
+        .with_chunked_body(|w| {
+            let body = "{\"token\": \"let 🦀_naïve\"}\n{\"token\": \" = 1;\", \"done\": true}\n";
+            for byte in body.as_bytes() {
+                w.write_all(std::slice::from_ref(byte))?;
+                w.flush()?;
+            }
+            Ok(())
+        })
+        .create_async()
+        .await;
+
+    let client =
+        ModalClient::new(ModalConfig::new(server.url(), "test-model".to_string())).unwrap();
+    let text: String = client
+        .generate_stream(replica_request())
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap().text)
+        .collect::<Vec<_>>()
+        .await
+        .concat();
+    assert_eq!(text, "let 🦀_naïve = 1;");
+}
+
+fn generate_body(text: &str) -> String {
+    serde_json::json!({
+        "generated_text": text,
+        "tokens_generated": 1,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 1,
+            "time_per_token_us": 1000,
+            "constraint_checks": 1,
+            "avg_constraint_check_us": 10
+        }
+    })
+    .to_string()
+}
+
+fn replica_request() -> InferenceRequest {
+    InferenceRequest {
+        prompt: "balance".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 1,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    }
+}
+
+#[tokio::test]
+async fn test_replicas_receive_traffic_by_weight() {
+    let mut servers = Vec::new();
+    let mut mocks = Vec::new();
+    for (weight, expected) in [(3, 6), (2, 4), (1, 2)] {
+        let mut server = Server::new_async().await;
+        let mock = server
+            .mock("POST", "/generate")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(generate_body(&format!("weight {}", weight)))
+            .expect(expected)
+            .create_async()
+            .await;
+        mocks.push(mock);
+        servers.push((server, weight));
+    }
+
+    let replicas = servers
+        .iter()
+        .map(|(server, weight)| ReplicaEndpoint::new(server.url(), *weight))
+        .collect();
+    let client = ModalClient::new(
+        ModalConfig::new(String::new(), "test-model".to_string()).with_replicas(replicas),
+    )
+    .unwrap();
+
+    for _ in 0..12 {
+        client
+            .generate_constrained(replica_request())
+            .await
+            .unwrap();
+    }
+
+    for mock in &mocks {
+        mock.assert_async().await;
+    }
+    let health = client.endpoint_health();
+    assert_eq!(
+        health.iter().map(|h| h.requests).collect::<Vec<_>>(),
+        vec![6, 4, 2]
+    );
+    assert!(health.iter().all(|h| !h.circuit_open && h.failures == 0));
+}
+
+#[tokio::test]
+async fn test_replicas_fail_over_and_open_breaker() {
+    // A port with nothing listening
+    let dead = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    };
+
+    let mut server = Server::new_async().await;
+    let live = server
+        .mock("POST", "/generate")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(generate_body("ok"))
+        .expect(4)
+        .create_async()
+        .await;
+
+    let mut config = ModalConfig::new(String::new(), "test-model".to_string()).with_replicas(vec![
+        ReplicaEndpoint::new(dead.clone(), 5),
+        ReplicaEndpoint::new(server.url(), 1),
+    ]);
+    config.circuit_breaker = CircuitBreakerConfig {
+        failure_threshold: 2,
+        cooldown_secs: 60,
+    };
+    config.enable_retry = false;
+    let client = ModalClient::new(config).unwrap();
+
+    // Each request fails over within a single attempt
+    for _ in 0..4 {
+        let response = client
+            .generate_constrained(replica_request())
+            .await
+            .unwrap();
+        assert_eq!(response.generated_text, "ok");
+        assert_eq!(response.attempts.len(), 1);
+    }
+    live.assert_async().await;
+
+    // After two connection failures the dead replica is skipped
+    let health = client.endpoint_health();
+    assert!(health[0].circuit_open);
+    assert_eq!(health[0].failures, 2);
+    assert!(!health[1].circuit_open);
+}
+
 // ---------------------------------------------------------------------------
 // 2. RATE LIMITING TESTS (429)
 // ---------------------------------------------------------------------------
@@ -499,6 +1833,11 @@ async fn test_error_rate_limit_429() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -561,6 +1900,11 @@ async fn test_error_rate_limit_with_retry_after_eventual_success() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let start = std::time::Instant::now();
@@ -597,7 +1941,8 @@ async fn test_error_exponential_backoff_timing() {
 
     let config = ModalConfig::new(server.url(), "test-model".to_string());
 
-    let client = ModalClient::new(config).unwrap();
+    let clock = MockClock::new();
+    let client = ModalClient::new(config).unwrap().with_clock(clock.clone());
 
     let request = InferenceRequest {
         prompt: "test".to_string(),
@@ -605,19 +1950,20 @@ async fn test_error_exponential_backoff_timing() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
-    let start = std::time::Instant::now();
     let result = client.generate_constrained(request).await;
-    let elapsed = start.elapsed();
-
     assert!(result.is_err());
 
-    // Exponential backoff: 100ms * 2^0 + 100ms * 2^1 = 100ms + 200ms = 300ms minimum
-    assert!(
-        elapsed.as_millis() >= 300,
-        "Expected exponential backoff of at least 300ms, got {:?}",
-        elapsed
+    // Exponential backoff: 100ms * 2^0, then 100ms * 2^1
+    assert_eq!(
+        clock.sleeps(),
+        vec![Duration::from_millis(100), Duration::from_millis(200)]
     );
 }
 
@@ -642,6 +1988,11 @@ async fn test_error_connection_refused() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -673,6 +2024,11 @@ async fn test_error_invalid_hostname_dns_failure() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -729,6 +2085,11 @@ async fn test_error_invalid_json_response() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -778,6 +2139,11 @@ async fn test_error_missing_required_field_generated_text() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -822,6 +2188,11 @@ async fn test_error_missing_required_field_stats() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -863,6 +2234,11 @@ async fn test_error_wrong_data_type_tokens_generated() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -892,6 +2268,11 @@ async fn test_error_empty_response_body() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -926,6 +2307,11 @@ async fn test_error_unauthorized_401_invalid_api_key() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -965,6 +2351,11 @@ async fn test_error_forbidden_403_insufficient_permissions() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1004,6 +2395,11 @@ async fn test_error_missing_api_key_when_required() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1035,6 +2431,11 @@ async fn test_error_internal_server_error_500() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1095,6 +2496,11 @@ async fn test_error_service_unavailable_503_with_retry() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1123,6 +2529,11 @@ async fn test_error_bad_gateway_502() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1163,6 +2574,11 @@ async fn test_error_invalid_prompt_validation() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1199,6 +2615,11 @@ async fn test_error_invalid_constraints_format() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1235,6 +2656,11 @@ async fn test_error_max_tokens_exceeded() {
         max_tokens: 1000000, // Unreasonably large
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1268,6 +2694,11 @@ async fn test_error_retry_disabled_fails_immediately() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1308,6 +2739,11 @@ async fn test_error_retry_counts_accurate() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1340,6 +2776,11 @@ async fn test_error_no_retry_on_client_errors_4xx() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1422,6 +2863,11 @@ async fn test_error_partial_response_truncated() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
@@ -1450,8 +2896,194 @@ async fn test_error_unexpected_content_type() {
         max_tokens: 10,
         temperature: 0.5,
         context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
     };
 
     let result = client.generate_constrained(request).await;
     assert!(result.is_err());
 }
+
+#[tokio::test]
+async fn test_modal_client_generate_emits_spans() {
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::{Context, SubscriberExt};
+
+    /// Records the names of spans as they are created
+    struct SpanRecorder(Arc<Mutex<Vec<String>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanRecorder {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: Context<'_, S>,
+        ) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(attrs.metadata().name().to_string());
+        }
+    }
+
+    let spans = Arc::new(Mutex::new(Vec::new()));
+    let subscriber = tracing_subscriber::registry().with(SpanRecorder(spans.clone()));
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let mut server = Server::new_async().await;
+    let response_body = serde_json::json!({
+        "generated_text": "fn main() {}",
+        "tokens_generated": 10,
+        "model": "test-model",
+        "stats": {
+            "total_time_ms": 100,
+            "time_per_token_us": 10000,
+            "constraint_checks": 5,
+            "avg_constraint_check_us": 50
+        }
+    });
+    let _m = server
+        .mock("POST", "/generate")
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(response_body.to_string())
+        .create_async()
+        .await;
+
+    let config = ModalConfig::new(server.url(), "test-model".to_string());
+    let client = ModalClient::new(config).unwrap();
+    let request = InferenceRequest {
+        prompt: "implement a function".to_string(),
+        constraints: serde_json::json!({}),
+        max_tokens: 100,
+        temperature: 0.7,
+        context: None,
+        seed: None,
+        idempotency_key: None,
+        include_logprobs: false,
+        timeout_ms: None,
+        include_constraint_events: false,
+    };
+    client.generate_constrained(request).await.unwrap();
+
+    let spans = spans.lock().unwrap();
+    assert!(spans.iter().any(|s| s == "modal.generate"));
+    assert!(spans.iter().any(|s| s == "modal.attempt"));
+}
+
+#[cfg(not(feature = "grpc"))]
+#[test]
+fn test_grpc_transport_requires_feature() {
+    let config = ModalConfig::new("http://localhost:50051".to_string(), "m".to_string())
+        .with_transport(maze::Transport::Grpc);
+    let err = ModalClient::new(config).err().unwrap();
+    assert!(err.to_string().contains("`grpc` feature"));
+}
+
+/// Value of header `name` in a raw HTTP request head
+fn header(head: &str, name: &str) -> Option<String> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// Serve `/generate` with the body from `generate_body`, delaying the first
+/// connection by `first_delay`; returns the URL and the request headers
+/// received, in arrival order
+async fn serve_with_slow_first_request(
+    first_delay: std::time::Duration,
+) -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let heads = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let received = heads.clone();
+    tokio::spawn(async move {
+        for connection in 0.. {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let heads = received.clone();
+            tokio::spawn(async move {
+                // Read headers and body before answering
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                let head = loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        return;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).into_owned();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = header(&text[..end], "content-length")
+                            .map_or(0, |v| v.parse::<usize>().unwrap());
+                        if request.len() >= end + 4 + length {
+                            break text[..end].to_string();
+                        }
+                    }
+                };
+                heads.lock().unwrap().push(head);
+
+                if connection == 0 {
+                    tokio::time::sleep(first_delay).await;
+                }
+                let body = generate_body(if connection == 0 { "original" } else { "hedge" });
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    (url, heads)
+}
+
+#[tokio::test]
+async fn test_hedge_request_wins_when_first_is_slow() {
+    let (url, heads) = serve_with_slow_first_request(std::time::Duration::from_secs(5)).await;
+    let config = ModalConfig::new(url, "test-model".to_string()).with_hedge_after_ms(50);
+    let client = ModalClient::new(config).unwrap();
+
+    let start = std::time::Instant::now();
+    let response = client
+        .generate_constrained(replica_request())
+        .await
+        .unwrap();
+
+    assert_eq!(response.generated_text, "hedge");
+    assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    assert_eq!(response.attempts.len(), 1);
+    assert!(response.attempts[0].hedged);
+
+    // Both requests carry the same idempotency key and request ID
+    let heads = heads.lock().unwrap().clone();
+    assert_eq!(heads.len(), 2);
+    for name in [IDEMPOTENCY_KEY_HEADER, REQUEST_ID_HEADER] {
+        let values: Vec<_> = heads.iter().map(|head| header(head, name)).collect();
+        assert!(values[0].is_some(), "{} missing", name);
+        assert_eq!(values[0], values[1]);
+    }
+    assert_eq!(response.request_id, header(&heads[0], REQUEST_ID_HEADER),);
+}
+
+#[tokio::test]
+async fn test_fast_response_is_not_hedged() {
+    let (url, heads) = serve_with_slow_first_request(std::time::Duration::ZERO).await;
+    let config = ModalConfig::new(url, "test-model".to_string()).with_hedge_after_ms(2_000);
+    let client = ModalClient::new(config).unwrap();
+
+    let response = client
+        .generate_constrained(replica_request())
+        .await
+        .unwrap();
+    assert_eq!(response.generated_text, "original");
+    assert!(!response.attempts[0].hedged);
+    assert_eq!(heads.lock().unwrap().len(), 1);
+}
@@ -34,6 +34,30 @@ fn test_orchestrator_with_custom_config() {
         enable_cache: true,
         cache_size_limit: 500,
         timeout_secs: 600,
+        lint_on_compile: false,
+        strict_provenance: false,
+        language_grammars: true,
+        context_overflow: maze::ContextOverflowPolicy::Reject,
+        truncation_order: maze::context_window::default_truncation_order(),
+        snippet_token_budget: 1024,
+        context_compression: maze::CompressionStrategy::None,
+        response_cache: maze::ResponseCacheConfig::default(),
+        redaction: maze::RedactionPolicy::default(),
+        max_concurrent_requests: 0,
+        queue_timeout_ms: None,
+        adaptive_concurrency: None,
+        constraint_format: maze::ConstraintFormat::default(),
+        ir_format: maze::IrFormat::default(),
+        tokenizer_path: None,
+        coalesce_requests: true,
+        output_guard: maze::OutputGuard::default(),
+        post_process: maze::PostProcessConfig::default(),
+        model_chain: maze::ModelChainConfig::default(),
+        stream_deadline: None,
+        runaway_limit: None,
+        format_policy: maze::FormatPolicy::default(),
+        include_constraint_events: false,
+        empty_constraints: maze::EmptyConstraintsPolicy::default(),
     };
 
     let orchestrator = MazeOrchestrator::with_config(modal_config, maze_config);
@@ -61,6 +85,40 @@ async fn test_cache_operations() {
     assert_eq!(stats.size, 0);
 }
 
+#[tokio::test]
+async fn test_orchestrators_share_a_constraint_cache() {
+    let shared: std::sync::Arc<dyn maze::CacheBackend> =
+        std::sync::Arc::new(maze::InMemoryCache::new(8));
+    let worker = || {
+        MazeOrchestrator::with_client(
+            maze::MockInferenceClient::new("mock-model"),
+            maze::MazeConfig::default(),
+        )
+        .with_constraint_cache(shared.clone())
+    };
+    let (first, second) = (worker(), worker());
+    let constraints = vec![maze::PolicyConstraint::new("no_eval")
+        .deny(r"\beval\(")
+        .unwrap()
+        .to_constraint()];
+
+    first.compile_constraints(&constraints).await.unwrap();
+    assert!(second.is_cached(&constraints).await.unwrap());
+    let stats = second.cache_stats().await;
+    assert_eq!((stats.size, stats.limit), (1, 8));
+
+    second.clear_cache().await.unwrap();
+    assert!(!first.is_cached(&constraints).await.unwrap());
+
+    // Without a shared backend, each orchestrator compiles for itself
+    let own = MazeOrchestrator::with_client(
+        maze::MockInferenceClient::new("mock-model"),
+        maze::MazeConfig::default(),
+    );
+    first.compile_constraints(&constraints).await.unwrap();
+    assert!(!own.is_cached(&constraints).await.unwrap());
+}
+
 #[test]
 fn test_generation_request_creation() {
     let request = GenerationRequest {
@@ -69,6 +127,9 @@ fn test_generation_request_creation() {
         max_tokens: 1024,
         temperature: 0.7,
         context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     assert_eq!(request.max_tokens, 1024);
@@ -94,6 +155,9 @@ fn test_generation_request_with_context() {
         max_tokens: 1024,
         temperature: 0.7,
         context: Some(context.clone()),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     assert!(request.context.is_some());
@@ -120,6 +184,7 @@ fn test_generation_request_with_constraints() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
         ConstraintIR {
             name: "documentation".to_string(),
@@ -135,6 +200,7 @@ fn test_generation_request_with_constraints() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
     ];
 
@@ -144,6 +210,9 @@ fn test_generation_request_with_constraints() {
         max_tokens: 1024,
         temperature: 0.7,
         context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     assert_eq!(request.constraints_ir.len(), 2);
@@ -159,6 +228,9 @@ fn test_generation_request_serialization() {
         max_tokens: 100,
         temperature: 0.5,
         context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
     };
 
     let json = serde_json::to_string(&request).unwrap();
@@ -209,6 +281,30 @@ fn test_maze_config_custom() {
         enable_cache: false,
         cache_size_limit: 2000,
         timeout_secs: 600,
+        lint_on_compile: false,
+        strict_provenance: false,
+        language_grammars: true,
+        context_overflow: maze::ContextOverflowPolicy::Reject,
+        truncation_order: maze::context_window::default_truncation_order(),
+        snippet_token_budget: 1024,
+        context_compression: maze::CompressionStrategy::None,
+        response_cache: maze::ResponseCacheConfig::default(),
+        redaction: maze::RedactionPolicy::default(),
+        max_concurrent_requests: 0,
+        queue_timeout_ms: None,
+        adaptive_concurrency: None,
+        constraint_format: maze::ConstraintFormat::default(),
+        ir_format: maze::IrFormat::default(),
+        tokenizer_path: None,
+        coalesce_requests: true,
+        output_guard: maze::OutputGuard::default(),
+        post_process: maze::PostProcessConfig::default(),
+        model_chain: maze::ModelChainConfig::default(),
+        stream_deadline: None,
+        runaway_limit: None,
+        format_policy: maze::FormatPolicy::default(),
+        include_constraint_events: false,
+        empty_constraints: maze::EmptyConstraintsPolicy::default(),
     };
 
     assert_eq!(config.max_tokens, 4096);
@@ -228,3 +324,2304 @@ fn test_cache_stats_structure() {
     assert_eq!(stats.size, 10);
     assert_eq!(stats.limit, 100);
 }
+
+#[tokio::test]
+async fn test_compile_preview_matches_compiled_schema() {
+    let config = ModalConfig::new(
+        "https://test.modal.run".to_string(),
+        "test-model".to_string(),
+    );
+    let orchestrator = MazeOrchestrator::new(config).unwrap();
+
+    let constraints = vec![ConstraintIR {
+        name: "digits".to_string(),
+        json_schema: None,
+        grammar: None,
+        regex_patterns: vec![RegexPattern {
+            pattern: r"\d+".to_string(),
+            flags: String::new(),
+        }],
+        token_masks: None,
+        priority: 0,
+        rich_context: None,
+        feasibility_score: 0.0,
+        is_feasible: true,
+        type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+    }];
+
+    let preview = orchestrator.compile_preview(&constraints).await.unwrap();
+    assert_eq!(orchestrator.cache_stats().await.size, 1);
+
+    let compiled = orchestrator
+        .compile_constraints(&constraints)
+        .await
+        .unwrap();
+    assert_eq!(preview, compiled.llguidance_schema);
+    assert_eq!(preview["constraints"][0]["pattern"], r"\d+");
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("schema.json");
+    compiled.to_llguidance_file(&path).unwrap();
+
+    let written: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(written, preview);
+}
+
+#[tokio::test]
+async fn test_compile_migrates_unversioned_constraints() {
+    let config = ModalConfig::new(
+        "https://test.modal.run".to_string(),
+        "test-model".to_string(),
+    );
+    let orchestrator = MazeOrchestrator::new(config).unwrap();
+
+    // IR serialized before schema versioning has no schema_version field
+    let legacy: ConstraintIR = serde_json::from_value(serde_json::json!({
+        "name": "digits",
+        "regex_patterns": [{"pattern": "\\d+", "flags": ""}],
+    }))
+    .unwrap();
+    assert_eq!(legacy.schema_version, 0);
+
+    let mut current = legacy.clone();
+    current.schema_version = maze::ffi::CONSTRAINT_SCHEMA_VERSION;
+
+    let from_legacy = orchestrator.compile_constraints(&[legacy]).await.unwrap();
+    let from_current = orchestrator.compile_constraints(&[current]).await.unwrap();
+    assert_eq!(from_legacy.hash, from_current.hash);
+    assert_eq!(
+        from_legacy.schema_version,
+        maze::ffi::CONSTRAINT_SCHEMA_VERSION
+    );
+    assert_eq!(orchestrator.cache_stats().await.size, 1);
+
+    // A compiled schema persisted by another version is refused
+    let mut stale = from_current.clone();
+    stale.schema_version = 0;
+    assert!(matches!(
+        stale.check_schema_version(),
+        Err(maze::SchemaVersionError::Stale { found: 0, .. })
+    ));
+
+    // IR from a newer Maze cannot be compiled
+    let mut future: ConstraintIR =
+        serde_json::from_value(serde_json::json!({"name": "digits"})).unwrap();
+    future.schema_version = maze::ffi::CONSTRAINT_SCHEMA_VERSION + 1;
+    let err = orchestrator
+        .compile_constraints(&[future])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<maze::SchemaVersionError>(),
+        Some(maze::SchemaVersionError::Unsupported { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_gbnf_format_compiles_grammar_with_distinct_cache_key() {
+    let client = maze::MockInferenceClient::new("mock-model");
+    let llguidance = MazeOrchestrator::with_client(client.clone(), Default::default());
+    let gbnf = MazeOrchestrator::with_client(
+        client,
+        maze::MazeConfig {
+            constraint_format: maze::ConstraintFormat::Gbnf,
+            ..Default::default()
+        },
+    );
+
+    let constraints = vec![ConstraintIR {
+        name: "digits".to_string(),
+        json_schema: None,
+        grammar: None,
+        regex_patterns: vec![RegexPattern {
+            pattern: "[0-9]+".to_string(),
+            flags: String::new(),
+        }],
+        token_masks: None,
+        priority: 0,
+        rich_context: None,
+        feasibility_score: 0.0,
+        is_feasible: true,
+        type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+    }];
+
+    assert_ne!(
+        llguidance.generate_cache_key(&constraints).unwrap(),
+        gbnf.generate_cache_key(&constraints).unwrap()
+    );
+
+    let compiled = gbnf.compile_constraints(&constraints).await.unwrap();
+    assert_eq!(compiled.format, maze::ConstraintFormat::Gbnf);
+    assert_eq!(compiled.llguidance_schema["format"], "gbnf");
+    assert_eq!(compiled.llguidance_schema["grammar"], "root ::= [0-9]+\n");
+}
+
+#[tokio::test]
+async fn test_lint_on_compile_is_opt_in() {
+    let digits = ConstraintIR {
+        name: "digits".to_string(),
+        json_schema: None,
+        grammar: None,
+        regex_patterns: vec![RegexPattern {
+            pattern: r"\d+".to_string(),
+            flags: String::new(),
+        }],
+        token_masks: None,
+        priority: 0,
+        rich_context: None,
+        feasibility_score: 0.0,
+        is_feasible: true,
+        type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+    };
+    let mut duplicate = digits.clone();
+    duplicate.name = "more_digits".to_string();
+    let constraints = vec![digits, duplicate];
+
+    let modal_config = ModalConfig::new(
+        "https://test.modal.run".to_string(),
+        "test-model".to_string(),
+    );
+    let orchestrator = MazeOrchestrator::new(modal_config.clone()).unwrap();
+    let compiled = orchestrator
+        .compile_constraints(&constraints)
+        .await
+        .unwrap();
+    assert!(compiled.lints.is_empty());
+
+    let maze_config = maze::MazeConfig {
+        lint_on_compile: true,
+        language_grammars: true,
+        ..Default::default()
+    };
+    let orchestrator = MazeOrchestrator::with_config(modal_config, maze_config).unwrap();
+    let compiled = orchestrator
+        .compile_constraints(&constraints)
+        .await
+        .unwrap();
+    assert_eq!(compiled.lints, maze::lint_constraints(&constraints));
+    assert!(compiled
+        .lints
+        .iter()
+        .any(|l| l.kind == maze::LintKind::Redundant && l.constraint == "more_digits"));
+}
+
+#[tokio::test]
+async fn test_orchestrator_with_mock_inference_client() {
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond("fn first() {}")
+        .then_rate_limited();
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), Default::default());
+
+    let request = || GenerationRequest {
+        prompt: "Implement a function".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 64,
+        temperature: 0.2,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let response = orchestrator.generate(request()).await.unwrap();
+    assert_eq!(response.code, "fn first() {}");
+    assert_eq!(response.provenance.model, "mock-model");
+
+    let error = orchestrator.generate(request()).await.unwrap_err();
+    assert!(format!("{:#}", error).contains("429"));
+
+    let sent = client.requests();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0].prompt, "Implement a function");
+    assert_eq!(sent[0].max_tokens, 64);
+
+    // The key sent to the client is recorded in provenance
+    let key = sent[0]
+        .idempotency_key
+        .clone()
+        .expect("idempotency key set");
+    assert!(key.starts_with(&sent[0].content_key()));
+    assert_eq!(response.provenance.idempotency_key, Some(key.clone()));
+
+    // The same unseeded request sent again is a new call with a new key
+    assert_eq!(sent[1].content_key(), sent[0].content_key());
+    assert_ne!(sent[1].idempotency_key, Some(key));
+}
+
+#[tokio::test]
+async fn test_orchestrator_generate_stream_with_mock_client() {
+    use futures::StreamExt;
+
+    let client = maze::MockInferenceClient::new("mock-model").then_respond("let x = 1;");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), Default::default());
+
+    let request = GenerationRequest {
+        prompt: "Bind x".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 16,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let chunks: Vec<_> = orchestrator
+        .generate_stream(request)
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap())
+        .collect()
+        .await;
+
+    let text: String = chunks.iter().map(|c| c.text.as_str()).collect();
+    assert_eq!(text, "let x = 1;");
+    assert!(chunks.last().unwrap().is_final);
+    assert_eq!(client.requests()[0].prompt, "Bind x");
+}
+
+#[tokio::test]
+async fn test_output_at_token_limit_reports_max_tokens() {
+    let client = maze::MockInferenceClient::new("mock-model").then_respond("let a = b +");
+    let orchestrator = MazeOrchestrator::with_client(
+        client,
+        maze::MazeConfig {
+            post_process: maze::post_process::PostProcessConfig::disabled(),
+            ..Default::default()
+        },
+    );
+
+    let response = orchestrator
+        .generate(GenerationRequest {
+            prompt: "Bind a".to_string(),
+            constraints_ir: vec![],
+            max_tokens: 4,
+            temperature: 0.0,
+            context: None,
+            seed: None,
+            timeout_ms: None,
+            history: Vec::new(),
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.metadata.finish_reason,
+        maze::FinishReason::MaxTokens
+    );
+    assert!(response.metadata.finish_reason.is_incomplete());
+}
+
+fn policy_request(policy: &maze::PolicyConstraint) -> GenerationRequest {
+    GenerationRequest {
+        prompt: "Run the command".to_string(),
+        constraints_ir: vec![policy.to_constraint()],
+        max_tokens: 32,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn test_policy_violation_is_reported_in_validation() {
+    let client = maze::MockInferenceClient::new("mock-model").then_respond("os.system(cmd)");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), Default::default());
+    let policy = maze::PolicyConstraint::new("no_shell")
+        .deny(r"\bos\.system\b")
+        .unwrap();
+
+    let response = orchestrator
+        .generate(policy_request(&policy))
+        .await
+        .unwrap();
+
+    assert_eq!(client.requests().len(), 1);
+    assert!(!response.validation.all_satisfied);
+    assert!(response.validation.satisfied.is_empty());
+    assert_eq!(
+        response.validation.violated,
+        vec![r"policy 'no_shell': line 1: `os.system` matches denied pattern /\bos\.system\b/"]
+    );
+    assert!(response
+        .validation
+        .metadata
+        .contains_key("policy_violations"));
+}
+
+#[tokio::test]
+async fn test_policy_violation_triggers_regeneration() {
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond("eval(user_input)")
+        .then_respond("int(user_input)");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), Default::default());
+    let policy = maze::PolicyConstraint::new("no_eval")
+        .deny(r"\beval\s*\(")
+        .unwrap()
+        .forbid_tokens([42])
+        .regenerate_on_violation(true);
+
+    let response = orchestrator
+        .generate(policy_request(&policy))
+        .await
+        .unwrap();
+
+    assert_eq!(response.code, "int(user_input)");
+    assert!(response.validation.all_satisfied);
+    assert_eq!(response.validation.satisfied, vec!["no_eval"]);
+    assert_eq!(
+        response.validation.metadata["rejected_outputs"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // The token mask reaches the compiled constraints sent to the service
+    let sent = client.requests();
+    assert_eq!(sent.len(), 2);
+    assert!(sent[0]
+        .constraints
+        .to_string()
+        .contains("\"forbidden\":[42]"));
+}
+
+/// Encodes "eval" and " eval" as one token each, anything else as two
+struct EvalVocab;
+
+impl maze::TokenEstimator for EvalVocab {
+    fn estimate(&self, text: &str) -> usize {
+        text.len()
+    }
+
+    fn encode(&self, text: &str) -> Option<Vec<u32>> {
+        Some(match text {
+            "eval" => vec![7],
+            " eval" => vec![8],
+            _ => vec![0, 0],
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_policy_deny_patterns_are_masked_with_tokenizer() {
+    let client = maze::MockInferenceClient::new("mock-model").then_respond("int(user_input)");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), Default::default())
+        .with_token_estimator(EvalVocab);
+    let policy = maze::PolicyConstraint::new("no_eval")
+        .deny(r"\beval\s*\(")
+        .unwrap()
+        .forbid_tokens([42]);
+
+    orchestrator
+        .generate(policy_request(&policy))
+        .await
+        .unwrap();
+
+    let sent = client.requests();
+    assert!(sent[0]
+        .constraints
+        .to_string()
+        .contains("\"forbidden\":[42,7,8]"));
+}
+
+fn deadline_orchestrator(
+    client: maze::MockInferenceClient,
+    policy: maze::DeadlinePolicy,
+) -> MazeOrchestrator {
+    MazeOrchestrator::with_client(
+        client,
+        maze::MazeConfig {
+            post_process: maze::post_process::PostProcessConfig::disabled(),
+            stream_deadline: Some(maze::StreamDeadline {
+                after_ms: 150,
+                policy,
+            }),
+            ..Default::default()
+        },
+    )
+}
+
+fn words_request() -> GenerationRequest {
+    GenerationRequest {
+        prompt: "Count".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 64,
+        temperature: 0.0,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn test_stream_deadline_returns_partial_output() {
+    let full = "one two three four five six seven eight nine ten";
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond(full)
+        .with_chunk_delay(std::time::Duration::from_millis(50));
+    let orchestrator = deadline_orchestrator(client, maze::DeadlinePolicy::Partial);
+
+    let response = orchestrator
+        .generate_streamed(words_request())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.metadata.finish_reason,
+        maze::FinishReason::Truncated
+    );
+    assert!(!response.code.is_empty());
+    assert!(full.starts_with(&response.code));
+    assert!(response.code.len() < full.len());
+    assert!(response.metadata.tokens_generated < 10);
+}
+
+#[tokio::test]
+async fn test_stream_finishing_before_deadline_is_complete() {
+    let client = maze::MockInferenceClient::new("mock-model").then_respond("let x = 1;");
+    let orchestrator = deadline_orchestrator(client, maze::DeadlinePolicy::Partial);
+
+    let response = orchestrator
+        .generate_streamed(words_request())
+        .await
+        .unwrap();
+
+    assert_eq!(response.code, "let x = 1;");
+    assert_eq!(response.metadata.finish_reason, maze::FinishReason::Stop);
+}
+
+#[tokio::test]
+async fn test_streamed_tokens_are_estimated_from_text() {
+    let client = maze::MockInferenceClient::new("mock-model").then_respond("let x = 1;");
+    let orchestrator =
+        MazeOrchestrator::with_client(client, Default::default()).with_token_estimator(EvalVocab);
+
+    let response = orchestrator
+        .generate_streamed(words_request())
+        .await
+        .unwrap();
+
+    // Four chunks, but EvalVocab counts a token per byte
+    assert_eq!(response.metadata.tokens_generated, 10);
+}
+
+#[tokio::test]
+async fn test_stream_deadline_error_policy_fails() {
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond("one two three four five six seven eight nine ten")
+        .with_chunk_delay(std::time::Duration::from_millis(50));
+    let orchestrator = deadline_orchestrator(client, maze::DeadlinePolicy::Error);
+
+    let err = orchestrator
+        .generate_streamed(words_request())
+        .await
+        .unwrap_err();
+    let exceeded = err
+        .downcast_ref::<maze::StreamDeadlineExceeded>()
+        .expect("deadline error");
+    assert_eq!(exceeded.deadline_ms, 150);
+    assert!(exceeded.chunks_received < 10);
+}
+
+#[tokio::test]
+async fn test_runaway_output_is_cut_off_or_retried() {
+    let limited = |client: &maze::MockInferenceClient| {
+        MazeOrchestrator::with_client(
+            client.clone(),
+            maze::MazeConfig {
+                post_process: maze::post_process::PostProcessConfig::disabled(),
+                runaway_limit: Some(maze::OutputLimit {
+                    max_bytes: 12,
+                    max_lines: 100,
+                }),
+                ..Default::default()
+            },
+        )
+    };
+    let full = "one two three four five six seven eight nine ten";
+
+    // A stream stops at the chunk that crosses the limit
+    let client = maze::MockInferenceClient::new("mock-model").then_respond(full);
+    let response = limited(&client)
+        .generate_streamed(words_request())
+        .await
+        .unwrap();
+    assert_eq!(response.metadata.finish_reason, maze::FinishReason::Runaway);
+    assert!(full.starts_with(&response.code));
+    assert!(response.code.len() > 12 && response.code.len() < full.len());
+    assert!(!response.validation.all_satisfied);
+    assert_eq!(
+        response.validation.metadata["runaway_generation"]["limit"]["max_bytes"],
+        12
+    );
+
+    // A complete response over the limit is retried, then fails
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond(full)
+        .with_default_response("let x = 1;");
+    let response = limited(&client).generate(words_request()).await.unwrap();
+    assert_eq!(response.code, "let x = 1;");
+    assert!(response.validation.metadata["rejected_outputs"][0]
+        .as_str()
+        .unwrap()
+        .starts_with("runaway generation"));
+
+    let client = maze::MockInferenceClient::new("mock-model").with_default_response(full);
+    let err = limited(&client)
+        .generate(words_request())
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<maze::RunawayGeneration>().unwrap().bytes,
+        full.len()
+    );
+}
+
+struct RejectingFormatter;
+
+impl maze::Formatter for RejectingFormatter {
+    fn format(&self, _code: &str) -> anyhow::Result<String> {
+        anyhow::bail!("cannot format a fragment")
+    }
+}
+
+#[tokio::test]
+async fn test_output_is_formatted_under_the_format_policy() {
+    let generated = "def f():\n\treturn 1";
+    let request = |language: &str| GenerationRequest {
+        context: Some(GenerationContext {
+            current_file: None,
+            language: Some(language.to_string()),
+            project_root: None,
+            metadata: HashMap::new(),
+        }),
+        ..words_request()
+    };
+    let orchestrator = |policy| {
+        let client = maze::MockInferenceClient::new("mock-model").with_default_response(generated);
+        MazeOrchestrator::with_client(
+            client,
+            maze::MazeConfig {
+                format_policy: policy,
+                ..Default::default()
+            },
+        )
+        .with_formatter("python", maze::WhitespaceNormalizer { tab_width: Some(4) })
+        .with_formatter("go", RejectingFormatter)
+    };
+
+    let response = orchestrator(maze::FormatPolicy::Always)
+        .generate(request("python"))
+        .await
+        .unwrap();
+    assert_eq!(response.code, "def f():\n    return 1");
+    assert_eq!(response.raw_code.as_deref(), Some(generated));
+    assert_eq!(
+        response.validation.metadata["formatting"],
+        serde_json::json!({"status": "formatted", "changed": true})
+    );
+
+    // Off by default
+    let response = orchestrator(maze::FormatPolicy::default())
+        .generate_streamed(request("python"))
+        .await
+        .unwrap();
+    assert_eq!(response.code, generated);
+    assert!(!response.validation.metadata.contains_key("formatting"));
+
+    // A failing formatter is recorded, not fatal
+    let response = orchestrator(maze::FormatPolicy::Always)
+        .generate(request("go"))
+        .await
+        .unwrap();
+    assert_eq!(response.code, generated);
+    assert_eq!(
+        response.validation.metadata["formatting"]["status"],
+        "failed"
+    );
+    assert!(response.validation.metadata["formatting"]["error"]
+        .as_str()
+        .unwrap()
+        .contains("cannot format a fragment"));
+}
+
+#[tokio::test]
+async fn test_replay_reruns_recorded_request() {
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond("fn one() {}")
+        .then_respond("fn one() {}");
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            redaction: maze::RedactionPolicy::None,
+            ..Default::default()
+        },
+    );
+
+    let constraints = vec![ConstraintIR {
+        name: "fn_only".to_string(),
+        json_schema: None,
+        grammar: None,
+        regex_patterns: vec![RegexPattern {
+            pattern: r"fn \w+\(\) \{\}".to_string(),
+            flags: String::new(),
+        }],
+        token_masks: None,
+        priority: 0,
+        rich_context: None,
+        feasibility_score: 1.0,
+        is_feasible: true,
+        type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+    }];
+    let request = GenerationRequest {
+        prompt: "Define one".to_string(),
+        constraints_ir: constraints.clone(),
+        max_tokens: 24,
+        temperature: 0.0,
+        context: None,
+        seed: Some(11),
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let original = orchestrator.generate(request).await.unwrap();
+    assert_eq!(
+        original.provenance.constraints_hash,
+        Some(orchestrator.generate_cache_key(&constraints).unwrap())
+    );
+
+    let replayed = orchestrator
+        .replay(&original.provenance, constraints)
+        .await
+        .unwrap();
+    assert_eq!(replayed.code, original.code);
+    assert_eq!(
+        replayed.provenance.constraints_hash,
+        original.provenance.constraints_hash
+    );
+
+    let sent = client.requests();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[1].prompt, sent[0].prompt);
+    assert_eq!(sent[1].seed, Some(11));
+    assert_eq!(sent[1].max_tokens, 24);
+    assert_eq!(sent[1].constraints, sent[0].constraints);
+
+    // A redacted prompt cannot be reconstructed
+    let mut redacted = original.provenance.clone();
+    redacted.original_intent = maze::RedactionPolicy::Hash
+        .apply(&redacted.original_intent)
+        .into_owned();
+    let err = orchestrator.replay(&redacted, vec![]).await.unwrap_err();
+    assert!(err.to_string().contains("redacted"));
+}
+
+#[tokio::test]
+async fn test_replay_with_prompt_under_release_redaction() {
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond("fn add() {}")
+        .then_respond("fn add() {}");
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            redaction: maze::RedactionPolicy::Hash,
+            ..Default::default()
+        },
+    );
+    let intent = maze::Intent {
+        raw_input: "please add two numbers".to_string(),
+        prompt: "Implement add(a, b)".to_string(),
+        current_file: None,
+        language: Some("rust".to_string()),
+        timeout_ms: None,
+    };
+    let params = maze::GenerationParams {
+        seed: Some(3),
+        ..Default::default()
+    };
+
+    let original = orchestrator
+        .generate_from_intent(intent, vec![], params)
+        .await
+        .unwrap();
+    let provenance = &original.provenance;
+
+    let err = orchestrator.replay(provenance, vec![]).await.unwrap_err();
+    assert!(err.to_string().contains("replay_with_prompt"));
+
+    let err = orchestrator
+        .replay_with_prompt(provenance, "Implement sub(a, b)", vec![])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("does not match"));
+
+    let replayed = orchestrator
+        .replay_with_prompt(provenance, "Implement add(a, b)", vec![])
+        .await
+        .unwrap();
+    assert_eq!(replayed.code, original.code);
+    assert_eq!(
+        replayed.provenance.intent.as_ref().unwrap().prompt,
+        provenance.intent.as_ref().unwrap().prompt
+    );
+
+    let sent = client.requests();
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[1].prompt, sent[0].prompt);
+    assert_eq!(sent[1].seed, Some(3));
+}
+
+#[tokio::test]
+async fn test_replay_with_prompt_checks_truncated_and_full_records() {
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond("fn add() {}")
+        .then_respond("fn add() {}")
+        .then_respond("fn add() {}");
+    let truncating = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            redaction: maze::RedactionPolicy::Truncate { n: 9 },
+            ..Default::default()
+        },
+    );
+    let request = GenerationRequest {
+        prompt: "Implement add(a, b)".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 24,
+        temperature: 0.0,
+        context: None,
+        seed: Some(5),
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let original = truncating.generate(request).await.unwrap();
+    assert_eq!(
+        original.provenance.original_intent,
+        "Implement...[10 more chars]"
+    );
+
+    // Same prefix, different length or tail
+    for wrong in ["Implement sub(a)", "Implement sub(a, b)x"] {
+        let err = truncating
+            .replay_with_prompt(&original.provenance, wrong, vec![])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("does not match"));
+    }
+    truncating
+        .replay_with_prompt(&original.provenance, "Implement add(a, b)", vec![])
+        .await
+        .unwrap();
+
+    // A fully redacted prompt is replayed only when the caller opts in
+    let mut full = original.provenance.clone();
+    full.original_intent = maze::RedactionPolicy::Full
+        .apply(&full.original_intent)
+        .into_owned();
+    let err = truncating
+        .replay_with_prompt(&full, "Implement add(a, b)", vec![])
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("replay_with_unverified_prompt"));
+    truncating
+        .replay_with_unverified_prompt(&full, "Implement add(a, b)", vec![])
+        .await
+        .unwrap();
+
+    let sent = client.requests();
+    assert_eq!(sent.len(), 3);
+    assert_eq!(sent[2].prompt, sent[0].prompt);
+}
+
+#[tokio::test]
+async fn test_generate_from_intent_records_structured_intent() {
+    let client = maze::MockInferenceClient::new("mock-model").then_respond("fn add() {}");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), Default::default());
+
+    let intent = maze::Intent {
+        raw_input: "please add two numbers".to_string(),
+        prompt: "Implement add(a, b)".to_string(),
+        current_file: Some("src/math.rs".to_string()),
+        language: Some("rust".to_string()),
+        timeout_ms: None,
+    };
+    let params = maze::GenerationParams {
+        seed: Some(7),
+        ..Default::default()
+    };
+
+    let response = orchestrator
+        .generate_from_intent(intent, vec![], params)
+        .await
+        .unwrap();
+
+    let recorded = response.provenance.intent.expect("intent recorded");
+    assert_eq!(recorded.prompt, "Implement add(a, b)");
+    assert_eq!(recorded.language.as_deref(), Some("rust"));
+    assert_eq!(
+        response.provenance.original_intent,
+        "please add two numbers"
+    );
+
+    let sent = &client.requests()[0];
+    assert!(sent
+        .prompt
+        .starts_with("Language: rust\nFile: src/math.rs\n"));
+    assert!(sent.prompt.ends_with("Implement add(a, b)"));
+    assert_eq!(sent.max_tokens, maze::MazeConfig::default().max_tokens);
+    assert_eq!(sent.seed, Some(7));
+}
+
+/// Rejects code whose parentheses do not balance
+struct ParenValidator;
+
+impl maze::SyntaxValidator for ParenValidator {
+    fn validate(&self, code: &str) -> Vec<maze::SyntaxError> {
+        let open = code.matches('(').count();
+        let close = code.matches(')').count();
+        if open == close {
+            vec![]
+        } else {
+            vec![maze::SyntaxError::at(
+                code,
+                0,
+                code.len(),
+                "unbalanced parentheses",
+            )]
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_syntax_validator_reports_errors_for_context_language() {
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond("fn broken( {}")
+        .then_respond("fn broken( {}");
+    let orchestrator = MazeOrchestrator::with_client(
+        client,
+        maze::MazeConfig {
+            enable_cache: false,
+            ..Default::default()
+        },
+    )
+    .with_syntax_validator("Rust", ParenValidator);
+
+    let request = |language: &str| GenerationRequest {
+        prompt: "Implement broken".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 16,
+        temperature: 0.0,
+        context: Some(GenerationContext {
+            current_file: None,
+            language: Some(language.to_string()),
+            project_root: None,
+            metadata: HashMap::new(),
+        }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let response = orchestrator.generate(request("rust")).await.unwrap();
+    assert!(!response.validation.all_satisfied);
+    assert_eq!(
+        response.validation.violated,
+        vec!["syntax error at 1:1: unbalanced parentheses"]
+    );
+    assert!(response.validation.metadata.contains_key("syntax_errors"));
+
+    // No validator registered for Python, so the same output passes
+    let response = orchestrator.generate(request("python")).await.unwrap();
+    assert!(response.validation.all_satisfied);
+    assert!(response.validation.violated.is_empty());
+}
+
+#[tokio::test]
+async fn test_generate_edit_applies_patch_and_validates_result() {
+    let original = "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n";
+    let patch = "<<<<<<< SEARCH\nfn add(a: i32, b: i32) -> i32 {\n=======\nfn add(a: i64, b: i64) -> i64 {\n>>>>>>> REPLACE\n";
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond(patch)
+        .then_respond("<<<<<<< SEARCH\nfn sub(\n=======\nfn sub((\n>>>>>>> REPLACE\n");
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            enable_cache: false,
+            ..Default::default()
+        },
+    )
+    .with_syntax_validator("rust", ParenValidator);
+
+    let request = GenerationRequest {
+        prompt: "Widen add to i64".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 64,
+        temperature: 0.0,
+        context: Some(GenerationContext {
+            current_file: Some("src/math.rs".to_string()),
+            language: Some("rust".to_string()),
+            project_root: None,
+            metadata: HashMap::new(),
+        }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let response = orchestrator
+        .generate_edit(request.clone(), original)
+        .await
+        .unwrap();
+    assert_eq!(response.edits.len(), 1);
+    assert_eq!(response.edits[0].start_line, 1);
+    assert_eq!(
+        response.code,
+        "fn add(a: i64, b: i64) -> i64 {\n    a + b\n}\n"
+    );
+    assert_eq!(response.generation.code, patch);
+    assert!(response.generation.validation.all_satisfied);
+    assert_eq!(
+        response.generation.provenance.original_intent,
+        "Widen add to i64"
+    );
+
+    let sent = &client.requests()[0];
+    assert!(sent.prompt.contains("```rust\nfn add(a: i32"));
+    assert!(sent.prompt.contains("Widen add to i64"));
+    assert_eq!(sent.constraints["constraints"][0]["type"], "regex");
+
+    // SEARCH text that is not in the file fails instead of guessing
+    let err = orchestrator
+        .generate_edit(request, original)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<maze::EditError>(),
+        Some(&maze::EditError::NotFound { block: 1 })
+    );
+}
+
+#[tokio::test]
+async fn test_regenerate_span_replaces_only_the_span() {
+    let original = "fn a() -> i32 {\n    1\n}\n\nfn b() {}";
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond(original)
+        .then_respond("    (1 + 1)");
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            enable_cache: false,
+            ..Default::default()
+        },
+    )
+    .with_syntax_validator("rust", ParenValidator);
+
+    let request = |prompt: &str| GenerationRequest {
+        prompt: prompt.to_string(),
+        constraints_ir: vec![],
+        max_tokens: 64,
+        temperature: 0.0,
+        context: Some(GenerationContext {
+            current_file: Some("src/lib.rs".to_string()),
+            language: Some("rust".to_string()),
+            project_root: None,
+            metadata: HashMap::new(),
+        }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+    let previous = orchestrator
+        .generate(request("Write a and b"))
+        .await
+        .unwrap();
+    assert_eq!(previous.code, original);
+
+    let start = original.find("    1").unwrap();
+    let span = start..start + "    1".len();
+    let response = orchestrator
+        .regenerate_span(&previous, span.clone(), request("Return 1 + 1"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response.code,
+        "fn a() -> i32 {\n    (1 + 1)\n}\n\nfn b() {}"
+    );
+    assert!(response.validation.all_satisfied);
+    assert_eq!(response.provenance.original_intent, "Return 1 + 1");
+    assert_eq!(
+        response.provenance.regenerated_span,
+        Some(maze::RegeneratedSpan {
+            start,
+            previous_end: span.end,
+            end: start + "    (1 + 1)".len(),
+            previous_model: "mock-model".to_string(),
+        })
+    );
+
+    let sent = &client.requests()[1];
+    assert!(sent
+        .prompt
+        .contains("```rust\nfn a() -> i32 {\n<SPAN>\n}\n\nfn b() {}\n```"));
+    assert!(sent.prompt.contains("Return 1 + 1"));
+
+    // Offsets past the end of the code fail before any request is sent
+    let err = orchestrator
+        .regenerate_span(&previous, 0..original.len() + 1, request("Again"))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<maze::EditError>(),
+        Some(maze::EditError::InvalidSpan { .. })
+    ));
+    assert_eq!(client.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_plan_prepares_request_without_generating() {
+    let client = maze::MockInferenceClient::new("mock-model");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), Default::default());
+
+    let request = GenerationRequest {
+        prompt: "Implement a parser".to_string(),
+        constraints_ir: vec![ConstraintIR {
+            name: "ident".to_string(),
+            json_schema: None,
+            grammar: None,
+            regex_patterns: vec![RegexPattern {
+                pattern: "[a-z]+".to_string(),
+                flags: String::new(),
+            }],
+            token_masks: None,
+            type_inhabitation: None,
+            priority: 1,
+            rich_context: None,
+            feasibility_score: 1.0,
+            is_feasible: true,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+        }],
+        max_tokens: 64,
+        temperature: 0.2,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let plan = orchestrator.plan(request.clone()).await.unwrap();
+    assert_eq!(plan.model, "mock-model");
+    assert!(plan.inference_request.prompt.contains("Implement a parser"));
+    assert_eq!(plan.compiled.merge_report.order, vec!["ident"]);
+    assert!(client.requests().is_empty());
+
+    // Context overflow is caught before any tokens are spent
+    let small_window = maze::MockInferenceClient::new("tiny").with_model_info(maze::ModelInfo {
+        context_window: Some(16),
+        ..maze::ModelInfo::from_name("tiny")
+    });
+    let orchestrator = MazeOrchestrator::with_client(small_window.clone(), Default::default());
+    let error = orchestrator.plan(request).await.unwrap_err();
+    assert!(error.downcast_ref::<maze::ContextOverflow>().is_some());
+    assert!(small_window.requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_temperature_is_clamped_to_model_range() {
+    let client = maze::MockInferenceClient::new("cool").with_model_info(maze::ModelInfo {
+        min_temperature: Some(0.1),
+        max_temperature: Some(0.6),
+        ..maze::ModelInfo::from_name("cool")
+    });
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), Default::default());
+    let request = |temperature| GenerationRequest {
+        prompt: "fn main() {}".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 32,
+        temperature,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let response = orchestrator.generate(request(0.9)).await.unwrap();
+    assert_eq!(client.requests()[0].temperature, 0.6);
+    let provenance = response.provenance;
+    assert_eq!(provenance.requested_temperature, Some(0.9));
+    assert_eq!(
+        provenance.parameters["temperature"].as_f64(),
+        Some(0.6f32 as f64)
+    );
+
+    // In range, nothing is clamped or recorded
+    let response = orchestrator.generate(request(0.3)).await.unwrap();
+    assert_eq!(client.requests()[1].temperature, 0.3);
+    assert_eq!(response.provenance.requested_temperature, None);
+}
+
+#[tokio::test]
+async fn test_empty_constraints_generate_unconstrained_or_are_rejected() {
+    let request = GenerationRequest {
+        prompt: "fn main() {}".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 32,
+        temperature: 0.2,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let client = maze::MockInferenceClient::new("mock-model");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), Default::default());
+    let response = orchestrator.generate(request.clone()).await.unwrap();
+    assert_eq!(client.requests()[0].constraints, serde_json::json!({}));
+    assert_eq!(
+        response.provenance.constraints_hash.as_deref(),
+        Some(maze::UNCONSTRAINED_HASH)
+    );
+    assert!(response.provenance.constraints_applied.is_empty());
+
+    let client = maze::MockInferenceClient::new("mock-model");
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            empty_constraints: maze::EmptyConstraintsPolicy::Reject,
+            ..Default::default()
+        },
+    );
+    let error = orchestrator.generate(request).await.unwrap_err();
+    assert!(error.downcast_ref::<maze::NoConstraints>().is_some());
+    assert!(client.requests().is_empty());
+}
+
+#[tokio::test]
+async fn test_traced_generations_replay_through_mock() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("trace.jsonl");
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond("let a = 1;")
+        .then_respond("let b = 2;");
+    let orchestrator = MazeOrchestrator::with_client(
+        client,
+        maze::MazeConfig {
+            redaction: maze::RedactionPolicy::None,
+            ..Default::default()
+        },
+    )
+    .with_trace_sink(maze::JsonlTraceSink::create(&path).unwrap());
+    let request = |prompt: &str| GenerationRequest {
+        prompt: prompt.to_string(),
+        constraints_ir: vec![],
+        max_tokens: 32,
+        temperature: 0.2,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+    orchestrator.generate(request("first")).await.unwrap();
+    orchestrator.generate(request("second")).await.unwrap();
+    // Dropping the sink flushes it
+    drop(orchestrator);
+
+    let records = maze::read_trace(&path).unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(records[0].request.prompt.contains("first"));
+    assert_eq!(records[0].model, "mock-model");
+
+    // The trace answers the same requests offline
+    let replay = MazeOrchestrator::with_client(
+        maze::MockInferenceClient::from_trace("mock-model", records),
+        Default::default(),
+    );
+    assert_eq!(
+        replay.generate(request("first")).await.unwrap().code,
+        "let a = 1;"
+    );
+    assert_eq!(
+        replay.generate(request("second")).await.unwrap().code,
+        "let b = 2;"
+    );
+}
+
+#[tokio::test]
+async fn test_provenance_redacts_prompt() {
+    let client = maze::MockInferenceClient::new("mock-model");
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            redaction: maze::RedactionPolicy::Hash,
+            ..Default::default()
+        },
+    );
+
+    let intent = maze::Intent {
+        raw_input: "connect with password hunter2".to_string(),
+        prompt: "Connect using password hunter2".to_string(),
+        current_file: None,
+        language: None,
+        timeout_ms: None,
+    };
+    let response = orchestrator
+        .generate_from_intent(intent, vec![], Default::default())
+        .await
+        .unwrap();
+
+    let provenance = response.provenance;
+    assert!(provenance.original_intent.starts_with("[redacted xxh3:"));
+    let intent = provenance.intent.unwrap();
+    assert!(!intent.raw_input.contains("hunter2"));
+    assert!(!intent.prompt.contains("hunter2"));
+
+    // Only recorded copies are redacted; the model still gets the prompt
+    assert!(client.requests()[0].prompt.contains("hunter2"));
+}
+
+#[tokio::test]
+async fn test_intent_timeout_reaches_inference_request() {
+    let client = maze::MockInferenceClient::new("mock-model");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+
+    let intent = maze::Intent {
+        raw_input: "complete this identifier".to_string(),
+        prompt: String::new(),
+        current_file: None,
+        language: None,
+        timeout_ms: Some(500),
+    };
+    orchestrator
+        .generate_from_intent(intent.clone(), vec![], Default::default())
+        .await
+        .unwrap();
+    orchestrator
+        .generate_from_intent(
+            maze::Intent {
+                timeout_ms: None,
+                ..intent
+            },
+            vec![],
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+    let requests = client.requests();
+    assert_eq!(requests[0].timeout_ms, Some(500));
+    // Unset falls back to the client's configured timeout
+    assert_eq!(requests[1].timeout_ms, None);
+}
+
+#[tokio::test]
+async fn test_adaptive_concurrency_backs_off_when_rate_limited() {
+    // The service answers 429 above four generations in flight
+    let client = maze::MockInferenceClient::new("mock-model")
+        .with_latency(std::time::Duration::from_millis(20))
+        .with_capacity(4);
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            max_concurrent_requests: 16,
+            adaptive_concurrency: Some(maze::AdaptiveConcurrencyConfig {
+                min_limit: 1,
+                max_limit: 16,
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+    );
+    let request = |prompt: String| GenerationRequest {
+        prompt,
+        constraints_ir: vec![],
+        max_tokens: 16,
+        temperature: 0.7,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+    assert_eq!(orchestrator.load().max_concurrent, Some(16));
+
+    let wave = |n: usize| {
+        let orchestrator = &orchestrator;
+        futures::future::join_all(
+            (0..16).map(move |i| orchestrator.generate(request(format!("wave {} #{}", n, i)))),
+        )
+    };
+    let first = wave(0).await;
+    assert!(first.iter().any(|r| r.is_err()));
+    let limit = orchestrator.load().max_concurrent.unwrap();
+    assert!(limit <= 8, "limit stayed at {}", limit);
+
+    // Below the service's capacity, later waves are rate limited less
+    let later = wave(1).await;
+    let rejected = |results: &[anyhow::Result<maze::GenerationResponse>]| {
+        results.iter().filter(|r| r.is_err()).count()
+    };
+    assert!(rejected(&later) < rejected(&first));
+}
+
+#[tokio::test]
+async fn test_concurrency_limit_queues_and_times_out() {
+    let client = maze::MockInferenceClient::new("mock-model")
+        .with_latency(std::time::Duration::from_millis(200));
+    let orchestrator = std::sync::Arc::new(MazeOrchestrator::with_client(
+        client,
+        maze::MazeConfig {
+            max_concurrent_requests: 1,
+            queue_timeout_ms: Some(50),
+            ..Default::default()
+        },
+    ));
+    let request = |prompt: &str| GenerationRequest {
+        prompt: prompt.to_string(),
+        constraints_ir: vec![],
+        max_tokens: 16,
+        temperature: 0.7,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let first = {
+        let orchestrator = orchestrator.clone();
+        let request = request("first");
+        tokio::spawn(async move { orchestrator.generate(request).await })
+    };
+    while orchestrator.load().in_flight == 0 {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(orchestrator.load().max_concurrent, Some(1));
+
+    // The slot is held for the mock's latency, longer than the queue timeout
+    let error = orchestrator.generate(request("second")).await.unwrap_err();
+    let busy = error.downcast_ref::<maze::Busy>().expect("Busy error");
+    assert_eq!(busy.max_concurrent, 1);
+
+    first.await.unwrap().unwrap();
+    let load = orchestrator.load();
+    assert_eq!((load.in_flight, load.queued), (0, 0));
+}
+
+#[tokio::test]
+async fn test_identical_concurrent_requests_share_one_call() {
+    let client = maze::MockInferenceClient::new("mock-model")
+        .with_latency(std::time::Duration::from_millis(100));
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+    let request = || GenerationRequest {
+        prompt: "complete this".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 16,
+        temperature: 0.7,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let (a, b) = tokio::join!(
+        orchestrator.generate(request()),
+        orchestrator.generate(request())
+    );
+    let (a, b) = (a.unwrap(), b.unwrap());
+    assert_eq!(client.requests().len(), 1);
+    assert_eq!(a.code, b.code);
+    assert!(a.metadata.coalesced ^ b.metadata.coalesced);
+
+    // Once the first call is done, an identical request is sent again
+    orchestrator.generate(request()).await.unwrap();
+    assert_eq!(client.requests().len(), 2);
+
+    let independent = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            coalesce_requests: false,
+            ..Default::default()
+        },
+    );
+    let (a, b) = tokio::join!(
+        independent.generate(request()),
+        independent.generate(request())
+    );
+    assert!(!a.unwrap().metadata.coalesced && !b.unwrap().metadata.coalesced);
+    assert_eq!(client.requests().len(), 4);
+}
+
+#[tokio::test]
+async fn test_blank_output_is_retried_then_rejected() {
+    let request = || GenerationRequest {
+        prompt: "complete this".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 16,
+        temperature: 0.0,
+        context: None,
+        seed: Some(1),
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    // A whitespace-only answer is retried under a fresh idempotency key
+    let client = maze::MockInferenceClient::new("mock-model")
+        .then_respond(" \n ")
+        .with_default_response("fn ok() {}");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+    let response = orchestrator.generate(request()).await.unwrap();
+    assert_eq!(response.code, "fn ok() {}");
+    assert_eq!(
+        response.validation.metadata["rejected_outputs"],
+        serde_json::json!(["generated output is whitespace only (3 bytes)"])
+    );
+    let keys: Vec<_> = client
+        .requests()
+        .into_iter()
+        .map(|r| r.idempotency_key.unwrap())
+        .collect();
+    assert_eq!(keys.len(), 2);
+    assert_ne!(keys[0], keys[1]);
+    assert_eq!(response.provenance.idempotency_key.as_ref(), Some(&keys[1]));
+
+    // Once retries run out the generation fails
+    let client = maze::MockInferenceClient::new("mock-model").with_default_response("");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+    let err = orchestrator.generate(request()).await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<maze::RejectedOutput>(),
+        Some(&maze::RejectedOutput::TooShort { chars: 0, min: 1 })
+    );
+    assert_eq!(client.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_output_is_post_processed_before_validation() {
+    let request = || GenerationRequest {
+        prompt: "complete this".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 16,
+        temperature: 0.0,
+        context: Some(maze::GenerationContext {
+            current_file: None,
+            language: Some("rust".to_string()),
+            project_root: None,
+            metadata: HashMap::new(),
+        }),
+        seed: Some(1),
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+    let raw = "```rust\nlet total = a + b;\ntotal\n}\n```\nThis sums the values.";
+
+    // By default only the fence is stripped
+    let client = maze::MockInferenceClient::new("mock-model").with_default_response(raw);
+    let orchestrator = MazeOrchestrator::with_client(client, maze::MazeConfig::default());
+    let response = orchestrator.generate(request()).await.unwrap();
+    assert_eq!(response.code, "let total = a + b;\ntotal\n}");
+    assert_eq!(
+        response.validation.metadata["post_processing"],
+        serde_json::json!(["stripped_code_fence"])
+    );
+
+    let all = || maze::MazeConfig {
+        post_process: maze::PostProcessConfig::all(),
+        ..Default::default()
+    };
+    let client = maze::MockInferenceClient::new("mock-model").with_default_response(raw);
+    let orchestrator = MazeOrchestrator::with_client(client, all());
+    let response = orchestrator.generate(request()).await.unwrap();
+    assert_eq!(response.code, "let total = a + b;\ntotal");
+    assert_eq!(response.raw_code.as_deref(), Some(raw));
+    assert_eq!(
+        response.validation.metadata["post_processing"],
+        serde_json::json!(["stripped_code_fence", "unbalanced_bracket"])
+    );
+
+    // Output a regex constraint shaped is not trimmed
+    let mut constrained = request();
+    constrained.constraints_ir = vec![ConstraintIR {
+        name: "shape".to_string(),
+        json_schema: None,
+        grammar: None,
+        regex_patterns: vec![RegexPattern {
+            pattern: "[\\s\\S]*".to_string(),
+            flags: String::new(),
+        }],
+        token_masks: None,
+        type_inhabitation: None,
+        priority: 0,
+        rich_context: None,
+        feasibility_score: 0.0,
+        is_feasible: true,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+    }];
+    let client = maze::MockInferenceClient::new("mock-model").with_default_response(raw);
+    let orchestrator = MazeOrchestrator::with_client(client, all());
+    let response = orchestrator.generate(constrained).await.unwrap();
+    assert_eq!(response.code, "let total = a + b;\ntotal\n}");
+
+    let client = maze::MockInferenceClient::new("mock-model").with_default_response(raw);
+    let config = maze::MazeConfig {
+        post_process: maze::PostProcessConfig::disabled(),
+        ..Default::default()
+    };
+    let response = MazeOrchestrator::with_client(client, config)
+        .generate(request())
+        .await
+        .unwrap();
+    assert_eq!(response.code, raw);
+    assert!(response.raw_code.is_none());
+}
+
+#[tokio::test]
+async fn test_model_chain_falls_back_on_failure_and_low_confidence() {
+    let request = GenerationRequest {
+        prompt: "complete this".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 16,
+        temperature: 0.0,
+        context: None,
+        seed: Some(1),
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+    let unsure = maze::InferenceResponse {
+        reported_confidence: Some(0.3),
+        ..maze::MockInferenceClient::response("backup", "fn guess() {}")
+    };
+
+    let primary = maze::MockInferenceClient::new("primary")
+        .then_fail("Modal inference failed with status 500 Internal Server Error");
+    let backup = maze::MockInferenceClient::new("backup").then(maze::MockReply::Response(unsure));
+    let last = maze::MockInferenceClient::new("last").with_default_response("fn sure() {}");
+    let config = maze::MazeConfig {
+        model_chain: maze::ModelChainConfig {
+            models: vec![],
+            min_confidence: Some(0.5),
+        },
+        ..Default::default()
+    };
+    let orchestrator = MazeOrchestrator::with_client(primary.clone(), config)
+        .with_fallback_client(backup.clone())
+        .with_fallback_client(last.clone());
+
+    let response = orchestrator.generate(request).await.unwrap();
+    assert_eq!(response.code, "fn sure() {}");
+    assert_eq!(response.provenance.model, "last");
+    let chain: Vec<_> = response
+        .provenance
+        .model_chain
+        .iter()
+        .map(|a| (a.model.as_str(), a.outcome, a.selected))
+        .collect();
+    assert_eq!(
+        chain,
+        vec![
+            ("primary", maze::ChainOutcome::Failed, false),
+            ("backup", maze::ChainOutcome::LowConfidence, false),
+            ("last", maze::ChainOutcome::Accepted, true),
+        ]
+    );
+
+    // Each fallback is sent under its own idempotency key
+    let primary_key = primary.requests()[0].idempotency_key.clone().unwrap();
+    let last_key = last.requests()[0].idempotency_key.clone().unwrap();
+    assert_eq!(last_key, format!("{}-last", primary_key));
+    assert_eq!(response.provenance.idempotency_key, Some(last_key));
+}
+
+/// Returns one large source file and one small one
+struct LargeFileProvider;
+
+impl maze::ContextProvider for LargeFileProvider {
+    fn fetch(&self, _context: &GenerationContext) -> Vec<maze::ContextSnippet> {
+        let body = "    let total = items.iter().sum();\n".repeat(200);
+        vec![
+            maze::ContextSnippet {
+                source: "src/orders.rs".to_string(),
+                content: format!("pub fn order_total(items: &[u32]) -> u32 {{\n{}}}", body),
+                relevance: 0.5,
+            },
+            maze::ContextSnippet {
+                source: "src/user.rs".to_string(),
+                content: "pub struct User;".to_string(),
+                relevance: 0.9,
+            },
+        ]
+    }
+}
+
+#[tokio::test]
+async fn test_context_compression_elides_oversized_snippets() {
+    let client = maze::MockInferenceClient::new("mock-model").with_default_response("fn ok() {}");
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            snippet_token_budget: 100,
+            context_compression: maze::CompressionStrategy::DropLowRelevance,
+            ..Default::default()
+        },
+    )
+    .with_context_provider(LargeFileProvider);
+
+    let response = orchestrator
+        .generate(GenerationRequest {
+            prompt: "Add a discount".to_string(),
+            constraints_ir: vec![],
+            max_tokens: 16,
+            temperature: 0.0,
+            context: Some(GenerationContext {
+                current_file: None,
+                language: None,
+                project_root: None,
+                metadata: HashMap::new(),
+            }),
+            seed: None,
+            timeout_ms: None,
+            history: Vec::new(),
+        })
+        .await
+        .unwrap();
+
+    let prompt = &client.requests()[0].prompt;
+    assert!(prompt.contains("pub fn order_total(items: &[u32]) -> u32 {\n    ...\n"));
+    assert!(!prompt.contains("items.iter().sum()"));
+    assert_eq!(
+        response.provenance.context_included,
+        vec!["snippet:src/user.rs", "snippet:src/orders.rs"]
+    );
+
+    let compression = response.metadata.context_compression.unwrap();
+    assert_eq!(compression.elided, vec!["src/orders.rs"]);
+    assert!(compression.original_tokens > 1000);
+    assert!(compression.compressed_tokens <= 100);
+}
+
+/// Panics when asked for context on `panic.rs`, standing in for a bug in
+/// one request's path
+struct PanickingProvider;
+
+impl maze::ContextProvider for PanickingProvider {
+    fn fetch(&self, context: &GenerationContext) -> Vec<maze::ContextSnippet> {
+        if context.current_file.as_deref() == Some("panic.rs") {
+            panic!("context index corrupted");
+        }
+        Vec::new()
+    }
+}
+
+#[tokio::test]
+async fn test_generate_many_isolates_a_panicking_request() {
+    let client = maze::MockInferenceClient::new("mock-model").with_default_response("fn ok() {}");
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            max_concurrent_requests: 2,
+            ..Default::default()
+        },
+    )
+    .with_context_provider(PanickingProvider);
+
+    let request = |file: &str| GenerationRequest {
+        prompt: format!("Implement {}", file),
+        constraints_ir: vec![],
+        max_tokens: 16,
+        temperature: 0.0,
+        context: Some(GenerationContext {
+            current_file: Some(file.to_string()),
+            language: None,
+            project_root: None,
+            metadata: HashMap::new(),
+        }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let results = orchestrator
+        .generate_many(vec![request("a.rs"), request("panic.rs"), request("b.rs")])
+        .await;
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().code, "fn ok() {}");
+    assert_eq!(results[2].as_ref().unwrap().code, "fn ok() {}");
+    let err = results[1].as_ref().unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<maze::GenerationPanicked>(),
+        Some(&maze::GenerationPanicked {
+            index: 1,
+            message: "context index corrupted".to_string(),
+        })
+    );
+    assert_eq!(client.requests().len(), 2);
+
+    // The panic released its concurrency slot
+    assert_eq!(orchestrator.load().in_flight, 0);
+    assert!(orchestrator.generate(request("c.rs")).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_provenance_records_every_sent_parameter() {
+    let client = maze::MockInferenceClient::new("mock-model");
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            strict_provenance: true,
+            ..maze::MazeConfig::default()
+        },
+    );
+
+    let response = orchestrator
+        .generate(GenerationRequest {
+            prompt: "fn add".to_string(),
+            constraints_ir: vec![],
+            max_tokens: 64,
+            temperature: 0.2,
+            context: None,
+            seed: Some(7),
+            timeout_ms: Some(900),
+            history: Vec::new(),
+        })
+        .await
+        .unwrap();
+
+    let mut sent = client.requests().remove(0);
+    let provenance = response.provenance;
+    assert!(provenance.unrecorded_parameters(&sent).unwrap().is_empty());
+    assert_eq!(provenance.parameters["timeout_ms"], serde_json::json!(900));
+    assert_eq!(provenance.parameters["seed"], serde_json::json!(7));
+
+    // A parameter the provenance was not built from is caught
+    sent.include_logprobs = true;
+    assert_eq!(
+        provenance.unrecorded_parameters(&sent).unwrap(),
+        vec!["include_logprobs"]
+    );
+
+    // As is the ad hoc recording provenance used to do
+    let mut ad_hoc = provenance.clone();
+    ad_hoc
+        .parameters
+        .retain(|name, _| name == "max_tokens" || name == "temperature");
+    assert_eq!(
+        ad_hoc.unrecorded_parameters(&sent).unwrap(),
+        vec![
+            "include_constraint_events",
+            "include_logprobs",
+            "seed",
+            "timeout_ms"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_conversation_history_reaches_prompt_and_provenance() {
+    let client = maze::MockInferenceClient::new("mock-model");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+
+    let first = orchestrator
+        .generate(GenerationRequest {
+            prompt: "Write a parser".to_string(),
+            constraints_ir: vec![],
+            max_tokens: 64,
+            temperature: 0.2,
+            context: None,
+            seed: None,
+            timeout_ms: None,
+            history: Vec::new(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(first.provenance.turn, None);
+
+    let follow_up = orchestrator
+        .generate(GenerationRequest {
+            prompt: "Also handle empty input".to_string(),
+            constraints_ir: vec![],
+            max_tokens: 64,
+            temperature: 0.2,
+            context: None,
+            seed: None,
+            timeout_ms: None,
+            history: vec![maze::ConversationTurn {
+                prompt: "Write a parser".to_string(),
+                response: first.code.clone(),
+            }],
+        })
+        .await
+        .unwrap();
+
+    let sent = &client.requests()[1];
+    assert!(sent
+        .prompt
+        .starts_with("Previous turns:\nRequest: Write a parser\n"));
+    assert!(sent.prompt.ends_with("\nAlso handle empty input"));
+    assert_eq!(follow_up.provenance.turn, Some(1));
+    assert_eq!(follow_up.provenance.context_included, vec!["history:1"]);
+    assert_eq!(
+        follow_up.provenance.original_intent,
+        "Also handle empty input"
+    );
+}
+
+#[tokio::test]
+async fn test_json_language_merges_json_grammar() {
+    let client = maze::MockInferenceClient::new("mock-model")
+        .with_default_response(r#"{"name": "ada", "langs": ["rust", "zig"]}"#);
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+    let request = |language: &str| GenerationRequest {
+        prompt: "Describe the user as JSON".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 128,
+        temperature: 0.0,
+        context: Some(GenerationContext {
+            current_file: None,
+            language: Some(language.to_string()),
+            project_root: None,
+            metadata: HashMap::new(),
+        }),
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    };
+
+    let response = orchestrator.generate(request("json")).await.unwrap();
+    let sent = &client.requests()[0];
+    let grammar = &sent.constraints["constraints"][0];
+    assert_eq!(grammar["type"], "grammar");
+    assert_eq!(grammar["name"], "language:json");
+    assert_eq!(grammar["start"], "json_text");
+    assert_eq!(
+        response.provenance.constraints_applied,
+        vec!["language:json"]
+    );
+    serde_json::from_str::<serde_json::Value>(&response.code).unwrap();
+
+    // Languages without a grammar are sent unconstrained
+    orchestrator.generate(request("cobol")).await.unwrap();
+    assert_eq!(client.requests()[1].constraints, serde_json::json!({}));
+
+    // And the merge can be turned off
+    let client = maze::MockInferenceClient::new("mock-model");
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            language_grammars: false,
+            ..maze::MazeConfig::default()
+        },
+    );
+    orchestrator.generate(request("json")).await.unwrap();
+    assert_eq!(client.requests()[0].constraints, serde_json::json!({}));
+}
+
+fn regex_constraint(pattern: &str) -> ConstraintIR {
+    ConstraintIR {
+        name: "shape".to_string(),
+        json_schema: None,
+        grammar: None,
+        regex_patterns: vec![RegexPattern {
+            pattern: pattern.to_string(),
+            flags: String::new(),
+        }],
+        token_masks: None,
+        type_inhabitation: None,
+        priority: 1,
+        rich_context: None,
+        feasibility_score: 0.0,
+        is_feasible: true,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
+    }
+}
+
+fn constrained_request() -> GenerationRequest {
+    GenerationRequest {
+        prompt: "fn add".to_string(),
+        constraints_ir: vec![regex_constraint("fn \\w+")],
+        max_tokens: 64,
+        temperature: 0.2,
+        context: None,
+        seed: None,
+        timeout_ms: None,
+        history: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn test_response_cache_expires_after_ttl() {
+    let client = maze::MockInferenceClient::new("mock-model");
+    let clock = maze::MockClock::new();
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            response_cache: maze::ResponseCacheConfig {
+                enabled: true,
+                ttl_secs: 60,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .with_clock(clock.clone());
+    let request = || GenerationRequest {
+        seed: Some(7),
+        temperature: 0.0,
+        ..constrained_request()
+    };
+
+    orchestrator.generate(request()).await.unwrap();
+    clock.advance(std::time::Duration::from_secs(59));
+    assert!(
+        orchestrator
+            .generate(request())
+            .await
+            .unwrap()
+            .metadata
+            .cache_hit
+    );
+    assert_eq!(client.requests().len(), 1);
+
+    clock.advance(std::time::Duration::from_secs(1));
+    assert!(
+        !orchestrator
+            .generate(request())
+            .await
+            .unwrap()
+            .metadata
+            .cache_hit
+    );
+    assert_eq!(client.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn test_wait_until_ready_backs_off_on_the_clock() {
+    let clock = maze::MockClock::new();
+    let orchestrator = MazeOrchestrator::with_client(
+        maze::MockInferenceClient::new("mock-model").with_health(false),
+        maze::MazeConfig::default(),
+    )
+    .with_clock(clock.clone());
+
+    let readiness = orchestrator
+        .wait_until_ready(std::time::Duration::from_secs(1))
+        .await;
+
+    assert!(!readiness.ready);
+    assert_eq!(readiness.probes, 5);
+    assert_eq!(readiness.elapsed_ms, 1000);
+    let millis: Vec<u128> = clock.sleeps().iter().map(|d| d.as_millis()).collect();
+    assert_eq!(millis, vec![100, 200, 400, 300]);
+}
+
+/// A mock model whose info says it cannot enforce constraints
+fn without_grammar_support(model: &str) -> maze::MockInferenceClient {
+    maze::MockInferenceClient::new(model).with_model_info(maze::ModelInfo {
+        supports_grammar: false,
+        ..maze::ModelInfo::from_name(model.to_string())
+    })
+}
+
+#[tokio::test]
+async fn test_unconstrained_model_routes_to_capable_fallback() {
+    let primary = without_grammar_support("plain-model");
+    let fallback = maze::MockInferenceClient::new("guided-model");
+    let orchestrator = MazeOrchestrator::with_client(primary.clone(), maze::MazeConfig::default())
+        .with_fallback_client(fallback.clone());
+
+    let response = orchestrator.generate(constrained_request()).await.unwrap();
+    assert!(primary.requests().is_empty());
+    assert_eq!(fallback.requests().len(), 1);
+    assert_ne!(fallback.requests()[0].constraints, serde_json::json!({}));
+    assert!(response.validation.all_satisfied);
+    let chain = &response.provenance.model_chain;
+    assert_eq!(chain[0].outcome, maze::ChainOutcome::Failed);
+    assert!(chain[0]
+        .error
+        .as_deref()
+        .unwrap()
+        .contains("does not support constrained decoding"));
+    assert!(chain[1].selected);
+}
+
+#[tokio::test]
+async fn test_unconstrained_output_is_never_reported_satisfied() {
+    // Known from model info: constraints are not sent at all
+    let client = without_grammar_support("plain-model");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+    let response = orchestrator.generate(constrained_request()).await.unwrap();
+    assert_eq!(client.requests()[0].constraints, serde_json::json!({}));
+    assert!(!response.validation.all_satisfied);
+    assert!(response.validation.satisfied.is_empty());
+    assert!(response.validation.violated[0].starts_with("constraints not enforced"));
+    assert_eq!(
+        response.validation.metadata["constraints_unenforced"]["model"],
+        "plain-model"
+    );
+
+    // Reported by the service: the request is resent without constraints
+    let client =
+        maze::MockInferenceClient::new("plain-model").then(maze::MockReply::ConstraintsUnsupported);
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+    let response = orchestrator.generate(constrained_request()).await.unwrap();
+    let requests = client.requests();
+    assert_eq!(requests.len(), 2);
+    assert_ne!(requests[0].constraints, serde_json::json!({}));
+    assert_eq!(requests[1].constraints, serde_json::json!({}));
+    assert!(!response.validation.all_satisfied);
+
+    // Streams degrade the same way
+    let client =
+        maze::MockInferenceClient::new("plain-model").then(maze::MockReply::ConstraintsUnsupported);
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+    let response = orchestrator
+        .generate_streamed(constrained_request())
+        .await
+        .unwrap();
+    assert!(!response.validation.all_satisfied);
+
+    // Without constraints there is nothing to enforce
+    let client = without_grammar_support("plain-model");
+    let orchestrator = MazeOrchestrator::with_client(client, maze::MazeConfig::default());
+    let response = orchestrator
+        .generate(GenerationRequest {
+            constraints_ir: vec![],
+            ..constrained_request()
+        })
+        .await
+        .unwrap();
+    assert!(response.validation.all_satisfied);
+}
+
+#[tokio::test]
+async fn test_overrides_apply_to_one_call_only() {
+    let client = maze::MockInferenceClient::new("mock-model");
+    let orchestrator = MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+    let overrides = maze::MazeConfigOverrides {
+        timeout_ms: Some(90_000),
+        max_tokens: Some(16),
+        temperature: Some(0.0),
+        ..Default::default()
+    };
+
+    orchestrator
+        .generate_with(constrained_request(), overrides)
+        .await
+        .unwrap();
+    orchestrator.generate(constrained_request()).await.unwrap();
+
+    let sent = client.requests();
+    assert_eq!(sent[0].timeout_ms, Some(90_000));
+    assert_eq!(sent[0].max_tokens, 16);
+    assert_eq!(sent[0].temperature, 0.0);
+    // The orchestrator's configuration is untouched
+    assert_eq!(sent[1].timeout_ms, None);
+    assert_eq!(sent[1].max_tokens, 64);
+    assert_eq!(sent[1].temperature, 0.2);
+
+    // A ceiling never raises the request's own limit
+    let roomy = maze::MazeConfigOverrides {
+        max_tokens: Some(4096),
+        ..Default::default()
+    };
+    orchestrator
+        .generate_with(constrained_request(), roomy)
+        .await
+        .unwrap();
+    assert_eq!(client.requests()[2].max_tokens, 64);
+}
+
+#[tokio::test]
+async fn test_cache_override_bypasses_and_enables_caches_per_call() {
+    let client = maze::MockInferenceClient::new("mock-model");
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            enable_cache: false,
+            response_cache: maze::ResponseCacheConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    );
+    let request = || GenerationRequest {
+        seed: Some(7),
+        temperature: 0.0,
+        ..constrained_request()
+    };
+    let bypass = || maze::MazeConfigOverrides {
+        enable_cache: Some(false),
+        ..Default::default()
+    };
+
+    // Caching the constraints for one call, though the config disables it
+    let cached = maze::MazeConfigOverrides {
+        enable_cache: Some(true),
+        ..Default::default()
+    };
+    let first = orchestrator.generate_with(request(), cached).await.unwrap();
+    assert!(!first.metadata.cache_hit);
+    assert_eq!(orchestrator.cache_stats().await.size, 1);
+
+    // A bypassing call neither reads nor writes the response cache
+    let fresh = orchestrator
+        .generate_with(request(), bypass())
+        .await
+        .unwrap();
+    assert!(!fresh.metadata.cache_hit);
+    assert_eq!(client.requests().len(), 2);
+
+    // Calls without overrides still see the response cached by the first
+    let hit = orchestrator.generate(request()).await.unwrap();
+    assert!(hit.metadata.cache_hit);
+    assert_eq!(client.requests().len(), 2);
+
+    orchestrator.clear_cache().await.unwrap();
+    orchestrator.clear_response_cache().await;
+    orchestrator
+        .generate_with(request(), bypass())
+        .await
+        .unwrap();
+    let after_bypass = orchestrator.generate(request()).await.unwrap();
+    assert!(!after_bypass.metadata.cache_hit);
+    assert_eq!(orchestrator.cache_stats().await.size, 0);
+}
+
+#[tokio::test]
+async fn test_streamed_constraint_events_are_counted_when_requested() {
+    use futures::StreamExt;
+
+    let event = |token: &str, position, constraint: &str| maze::ConstraintEvent {
+        token: token.to_string(),
+        position,
+        constraint: constraint.to_string(),
+    };
+    let client = maze::MockInferenceClient::new("mock-model")
+        .with_default_response("fn add(a: i32) {}")
+        .with_constraint_events(vec![
+            event("var", 0, "shape"),
+            event("(", 1, "shape"),
+            event("u8", 2, "types"),
+        ]);
+    let orchestrator = MazeOrchestrator::with_client(
+        client.clone(),
+        maze::MazeConfig {
+            include_constraint_events: true,
+            ..Default::default()
+        },
+    );
+
+    let chunks: Vec<_> = orchestrator
+        .generate_stream(constrained_request())
+        .await
+        .unwrap()
+        .map(|chunk| chunk.unwrap())
+        .collect()
+        .await;
+    let events: Vec<_> = chunks
+        .iter()
+        .filter_map(|c| c.constraint_event.as_ref())
+        .map(|e| e.token.as_str())
+        .collect();
+    assert_eq!(events, vec!["var", "(", "u8"]);
+    // Each event precedes the text at its position
+    assert_eq!(chunks[1].text, "fn ");
+    assert!(client.requests()[0].include_constraint_events);
+
+    let response = orchestrator
+        .generate_streamed(constrained_request())
+        .await
+        .unwrap();
+    assert_eq!(response.code, "fn add(a: i32) {}");
+    assert_eq!(
+        response.validation.metadata["constraint_events"],
+        serde_json::json!({"total": 3, "by_constraint": {"shape": 2, "types": 1}})
+    );
+
+    // Off by default: no events are asked for or counted
+    let quiet = MazeOrchestrator::with_client(client.clone(), maze::MazeConfig::default());
+    let response = quiet
+        .generate_streamed(constrained_request())
+        .await
+        .unwrap();
+    assert!(!response
+        .validation
+        .metadata
+        .contains_key("constraint_events"));
+    assert!(!client.requests().last().unwrap().include_constraint_events);
+}
+
+#[tokio::test]
+async fn test_template_instantiates_with_two_parameter_sets() {
+    use maze::constraint_builder::ConstraintBuilder;
+    use maze::ffi::GrammarRule;
+    use maze::ConstraintTemplate;
+
+    let rule = GrammarRule {
+        lhs: "query".to_string(),
+        rhs: vec!["SELECT * FROM".to_string(), "{{table}}".to_string()],
+    };
+    let template = ConstraintTemplate::new(
+        "select",
+        vec![
+            ConstraintBuilder::new("sql")
+                .with_grammar(vec![rule], "query")
+                .unwrap()
+                .build()
+                .unwrap(),
+            ConstraintBuilder::new("ident")
+                .with_regex("[a-z_]+", "")
+                .unwrap()
+                .build()
+                .unwrap(),
+        ],
+    )
+    .unwrap();
+    let params = |table: &str| HashMap::from([("table".to_string(), table.to_string())]);
+    let orchestrator = MazeOrchestrator::with_client(
+        maze::MockInferenceClient::new("mock-model"),
+        Default::default(),
+    );
+
+    let users = orchestrator
+        .compile_template(&template, &params("users"))
+        .await
+        .unwrap();
+    assert_eq!(template.last_update().await.diff.added.len(), 2);
+
+    // The second set recompiles only the constraint naming the table
+    let orders = orchestrator
+        .compile_template(&template, &params("orders"))
+        .await
+        .unwrap();
+    let update = template.last_update().await;
+    assert_eq!(update.diff.changed, vec!["sql"]);
+    assert_eq!(update.diff.unchanged, 1);
+
+    assert_ne!(users.hash, orders.hash);
+    assert!(users.llguidance_schema.to_string().contains("users"));
+    assert!(orders.llguidance_schema.to_string().contains("orders"));
+    assert!(!orders.llguidance_schema.to_string().contains("users"));
+
+    // Each instance is cached under the key of its instantiated IR
+    let instance = template.instantiate(&params("orders")).unwrap();
+    assert_eq!(
+        orders.hash,
+        orchestrator.generate_cache_key(&instance).unwrap()
+    );
+    assert!(matches!(
+        orchestrator
+            .compile_template(&template, &HashMap::new())
+            .await
+            .unwrap_err()
+            .downcast_ref::<maze::TemplateError>(),
+        Some(maze::TemplateError::MissingParameters { .. })
+    ));
+}
@@ -0,0 +1,84 @@
+//! Integration tests for provenance signing
+
+#![cfg(feature = "signing")]
+
+use maze::signing::{self, SignatureError, SigningKey};
+use maze::{GenerationRequest, MazeConfig, MazeOrchestrator, MockInferenceClient};
+
+fn request() -> GenerationRequest {
+    GenerationRequest {
+        prompt: "Add two numbers".to_string(),
+        constraints_ir: vec![],
+        max_tokens: 32,
+        temperature: 0.0,
+        context: None,
+        seed: Some(1),
+        timeout_ms: None,
+        history: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn test_signed_response_verifies_until_modified() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let orchestrator = MazeOrchestrator::with_client(
+        MockInferenceClient::new("mock-model")
+            .then_respond("fn add(a: i32, b: i32) -> i32 { a + b }"),
+        MazeConfig::default(),
+    )
+    .with_signing_key(key.clone());
+
+    let response = orchestrator.generate(request()).await.unwrap();
+    let signature = response.provenance.signature.as_ref().unwrap();
+    assert_eq!(signature.algorithm, signing::ALGORITHM);
+    assert_eq!(signing::verify(&response, &key.verifying_key()), Ok(()));
+
+    // Survives a JSON round trip, e.g. storage alongside the code
+    let stored: maze::GenerationResponse =
+        serde_json::from_str(&serde_json::to_string(&response).unwrap()).unwrap();
+    assert_eq!(signing::verify(&stored, &key.verifying_key()), Ok(()));
+
+    let mut tampered = response.clone();
+    tampered.code.push_str("\n// unreviewed");
+    assert_eq!(
+        signing::verify(&tampered, &key.verifying_key()),
+        Err(SignatureError::Invalid)
+    );
+
+    let mut tampered = response.clone();
+    tampered.validation.all_satisfied = !tampered.validation.all_satisfied;
+    assert_eq!(
+        signing::verify(&tampered, &key.verifying_key()),
+        Err(SignatureError::Invalid)
+    );
+
+    let mut tampered = response.clone();
+    tampered.provenance.model = "other-model".to_string();
+    assert_eq!(
+        signing::verify(&tampered, &key.verifying_key()),
+        Err(SignatureError::Invalid)
+    );
+
+    let other = SigningKey::from_bytes(&[8; 32]);
+    assert_eq!(
+        signing::verify(&response, &other.verifying_key()),
+        Err(SignatureError::KeyMismatch)
+    );
+}
+
+#[tokio::test]
+async fn test_unsigned_response_is_reported_missing() {
+    let orchestrator = MazeOrchestrator::with_client(
+        MockInferenceClient::new("mock-model").then_respond("fn f() {}"),
+        MazeConfig::default(),
+    );
+
+    let response = orchestrator.generate(request()).await.unwrap();
+    assert!(response.provenance.signature.is_none());
+
+    let key = SigningKey::from_bytes(&[7; 32]);
+    assert_eq!(
+        signing::verify(&response, &key.verifying_key()),
+        Err(SignatureError::Missing)
+    );
+}
@@ -62,6 +62,7 @@ fn test_zig_ffi_constraint_ir_conversion() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     // Convert to FFI representation and back
@@ -138,6 +139,7 @@ fn test_ffi_memory_ownership() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
         ConstraintIR {
             name: "constraint_2".to_string(),
@@ -153,6 +155,7 @@ fn test_ffi_memory_ownership() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
         ConstraintIR {
             name: "constraint_3".to_string(),
@@ -177,6 +180,7 @@ fn test_ffi_memory_ownership() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         },
     ];
 
@@ -217,7 +221,9 @@ fn test_ffi_error_handling() {
     unsafe {
         let result = ConstraintIR::from_ffi(std::ptr::null());
         assert!(result.is_err());
-        assert!(result.unwrap_err().contains("Null"));
+        let err = result.unwrap_err();
+        assert_eq!(err.code, maze::ffi::FfiErrorCode::NullPointer);
+        assert!(err.message.contains("Null"));
     }
 
     // Test malformed data handling would go here if we had access to
@@ -242,12 +248,26 @@ fn test_ffi_edge_cases() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
+    // An empty name is rejected at the boundary
+    let ffi = minimal.to_ffi();
+    unsafe {
+        let err = ConstraintIR::from_ffi(ffi).unwrap_err();
+        assert_eq!(err.code, maze::ffi::FfiErrorCode::InvalidInput);
+        assert!(err.message.contains("constraint name is empty"));
+        maze::ffi::free_constraint_ir_ffi(ffi);
+    }
+
+    let minimal = ConstraintIR {
+        name: "minimal".to_string(),
+        ..minimal
+    };
     let ffi = minimal.to_ffi();
     unsafe {
         let restored = ConstraintIR::from_ffi(ffi).expect("FFI conversion failed");
-        assert_eq!(restored.name, "");
+        assert_eq!(restored.name, "minimal");
         assert_eq!(restored.priority, 0);
         assert!(restored.json_schema.is_none());
         assert!(restored.grammar.is_none());
@@ -274,6 +294,7 @@ fn test_ffi_edge_cases() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = large.to_ffi();
@@ -334,6 +355,7 @@ fn test_ffi_complex_grammar() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = constraint.to_ffi();
@@ -377,6 +399,7 @@ fn test_ffi_token_masks_edge_cases() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = allowed_only.to_ffi();
@@ -403,6 +426,7 @@ fn test_ffi_token_masks_edge_cases() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = forbidden_only.to_ffi();
@@ -429,6 +453,7 @@ fn test_ffi_token_masks_edge_cases() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = empty_masks.to_ffi();
@@ -488,6 +513,7 @@ fn test_ffi_json_schema_complex() {
         feasibility_score: 0.0,
         is_feasible: true,
         type_inhabitation: None,
+        schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
     };
 
     let ffi = constraint.to_ffi();
@@ -540,6 +566,7 @@ fn test_ffi_roundtrip_stress() {
             feasibility_score: 0.0,
             is_feasible: true,
             type_inhabitation: None,
+            schema_version: maze::ffi::CONSTRAINT_SCHEMA_VERSION,
         };
 
         let ffi = constraint.to_ffi();
@@ -53,6 +53,7 @@ pub const AnankeError = enum(c_int) {
     DoubleFree = 6,
     InvalidPointer = 7,
     UseAfterFree = 8,
+    Panic = 9,
 };
 
 /// C-compatible TokenMaskRules structure matching Rust FFI definition
@@ -26,6 +26,9 @@ pub const Intent = struct {
     /// Metadata
     metadata: IntentMetadata,
 
+    /// Time allowed for generation in milliseconds (null = configured timeout)
+    timeout_ms: ?u64 = null,
+
     pub fn init(input: []const u8) Intent {
         return .{
             .raw_input = input,